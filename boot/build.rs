@@ -1,5 +1,11 @@
 fn main() {
     let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    // The linker script is only for the kernel itself, and not for the unit tests running on the
+    // host
+    if std::env::var("CARGO_CFG_TARGET_OS").unwrap() != "none" {
+        return;
+    }
+
     // Tell cargo to pass the linker script to the linker..
     println!("cargo:rustc-link-arg=-Tlinker-{arch}.ld");
     // ..and to re-run if it changes.
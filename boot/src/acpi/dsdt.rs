@@ -0,0 +1,59 @@
+//! Parsing of the `DSDT` and `SSDT` ACPI tables, which hold the platform's AML bytecode
+//!
+//! NOTE: We don't interpret the AML (yet), we only locate and validate it.
+
+use super::{AcpiError, AcpiTable, SdtHeader};
+use core::{ptr::from_ref, slice::from_raw_parts};
+
+/// The DSDT (Differentiated System Description Table)
+#[repr(C)]
+#[derive(Debug)]
+pub(super) struct Dsdt {
+    pub(super) header: SdtHeader,
+}
+
+/// An SSDT (Secondary System Description Table). Has the same layout as the DSDT
+#[repr(C)]
+#[derive(Debug)]
+pub(super) struct Ssdt {
+    header: SdtHeader,
+}
+
+impl Dsdt {
+    /// Validate the DSDT and get its AML bytecode.
+    ///
+    /// Unlike other tables, the DSDT is found through a pointer in the FADT and not by its
+    /// signature, so the signature is checked as well.
+    pub(super) fn aml(&self) -> Result<&[u8], AcpiError> {
+        if self.header.signature != *Self::SIGNATURE {
+            return Err(AcpiError::InvalidSignature);
+        }
+
+        aml_of(&self.header)
+    }
+}
+
+impl Ssdt {
+    /// Validate the SSDT and get its AML bytecode
+    pub(super) fn aml(&self) -> Result<&[u8], AcpiError> {
+        aml_of(&self.header)
+    }
+}
+
+/// Validate the table starting with `header` and get the AML bytecode that follows the header
+fn aml_of(header: &SdtHeader) -> Result<&[u8], AcpiError> {
+    let aml_len = (header.length as usize)
+        .checked_sub(size_of::<SdtHeader>())
+        .ok_or(AcpiError::InvalidLength)?;
+    header.validate_checksum()?;
+
+    Ok(unsafe { from_raw_parts(from_ref(header).add(1).cast::<u8>(), aml_len) })
+}
+
+impl AcpiTable for Dsdt {
+    const SIGNATURE: &'static [u8; 4] = b"DSDT";
+}
+
+impl AcpiTable for Ssdt {
+    const SIGNATURE: &'static [u8; 4] = b"SSDT";
+}
@@ -0,0 +1,445 @@
+//! Parser for the FADT (signature `FACP`)
+
+use super::{AcpiError, AcpiTable, Gas, MapTable, SdtHeader, dsdt::Dsdt, gas::Location};
+use core::{mem::offset_of, ptr::from_ref};
+use drivers::clock::pm_timer::{CounterWidth, PM_TIMER, PmTimerAccess};
+use kernel::{
+    arch::{BASIC_PAGE_SIZE, x86_64::X86_64},
+    mem::paging::{Flags, PageSize, PagingManager},
+};
+use utils::{mem::PhysAddr, sync::once::Once};
+
+/// `TMR_VAL_EXT` flag: the PM timer's counter is 32 bits wide, instead of 24
+const FLAG_TMR_VAL_EXT: u32 = 1 << 8;
+/// `RESET_REG_SUP` flag: the system can be reset by writing `RESET_VALUE` to `RESET_REG`
+const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+/// The reset register and the value to write to it, if the system supports resetting through it
+static RESET_REGISTER: Once<(Gas, u8)> = Once::new();
+
+/// Where the PM timer's counter register is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PmTimerBlock {
+    Port(u16),
+    Memory(PhysAddr),
+}
+
+/// The FADT (Fixed ACPI Description Table)
+#[repr(C, packed)]
+#[derive(Debug)]
+pub(super) struct Fadt {
+    header: SdtHeader,
+    firmware_ctrl: u32,
+    /// The 32 bit physical address of the DSDT. Superseded by `x_dsdt` if it's set
+    dsdt: u32,
+    _reserved0: u8,
+    preferred_pm_profile: u8,
+    sci_int: u16,
+    smi_cmd: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_cnt: u8,
+    pm1a_evt_blk: u32,
+    pm1b_evt_blk: u32,
+    pm1a_cnt_blk: u32,
+    pm1b_cnt_blk: u32,
+    pm2_cnt_blk: u32,
+    pm_tmr_blk: u32,
+    gpe0_blk: u32,
+    gpe1_blk: u32,
+    pm1_evt_len: u8,
+    pm1_cnt_len: u8,
+    pm2_cnt_len: u8,
+    pm_tmr_len: u8,
+    gpe0_blk_len: u8,
+    gpe1_blk_len: u8,
+    gpe1_base: u8,
+    cst_cnt: u8,
+    p_lvl2_lat: u16,
+    p_lvl3_lat: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alrm: u8,
+    mon_alrm: u8,
+    century: u8,
+    iapc_boot_arch: u16,
+    _reserved1: u8,
+    flags: u32,
+    reset_reg: Gas,
+    reset_value: u8,
+    arm_boot_arch: u16,
+    minor_version: u8,
+    x_firmware_ctrl: u64,
+    /// The 64 bit physical address of the DSDT. Only present since ACPI 2.0
+    x_dsdt: u64,
+    x_pm1a_evt_blk: Gas,
+    x_pm1b_evt_blk: Gas,
+    x_pm1a_cnt_blk: Gas,
+    x_pm1b_cnt_blk: Gas,
+    x_pm2_cnt_blk: Gas,
+    x_pm_tmr_blk: Gas,
+    x_gpe0_blk: Gas,
+    x_gpe1_blk: Gas,
+}
+
+impl Fadt {
+    /// Get the SDT header of the FADT
+    #[inline]
+    fn header(&self) -> &SdtHeader {
+        // we need to do this trick since Fadt is packed, so direct access of `Fadt.header` is not aligned
+        unsafe { from_ref(self).cast::<SdtHeader>().as_ref().unwrap() }
+    }
+
+    /// Get the physical address of the DSDT, or `None` if the FADT doesn't point to one.
+    ///
+    /// `X_DSDT` is preferred over the legacy 32 bit `DSDT` field, but only if the table is long
+    /// enough to contain it (older revisions of the FADT are shorter).
+    fn dsdt_addr(&self) -> Option<PhysAddr> {
+        let x_dsdt_end = offset_of!(Fadt, x_dsdt) + size_of::<u64>();
+        if self.header().length as usize >= x_dsdt_end {
+            let x_dsdt = self.x_dsdt;
+            if x_dsdt != 0 {
+                return Some(PhysAddr(x_dsdt as usize));
+            }
+        }
+
+        match self.dsdt {
+            0 => None,
+            dsdt => Some(PhysAddr(dsdt as usize)),
+        }
+    }
+
+    /// Get the location of the PM timer's counter register and the counter's width, or `None` if
+    /// the system has no PM timer.
+    ///
+    /// Like with the DSDT, `X_PM_TMR_BLK` is preferred over the legacy `PM_TMR_BLK` port.
+    fn pm_timer(&self) -> Option<(PmTimerBlock, CounterWidth)> {
+        let width = if self.flags & FLAG_TMR_VAL_EXT != 0 {
+            CounterWidth::Bits32
+        } else {
+            CounterWidth::Bits24
+        };
+
+        let x_pm_tmr_blk_end = offset_of!(Fadt, x_pm_tmr_blk) + size_of::<Gas>();
+        if self.header().length as usize >= x_pm_tmr_blk_end {
+            let gas = self.x_pm_tmr_blk;
+            let addr = gas.addr;
+            match gas.location() {
+                _ if addr == 0 => (),
+                Ok(Location::Port(port)) => return Some((PmTimerBlock::Port(port), width)),
+                Ok(Location::Memory(addr)) => return Some((PmTimerBlock::Memory(addr), width)),
+                Err(_) => (),
+            }
+        }
+
+        match (self.pm_tmr_blk, self.pm_tmr_len) {
+            (0, _) | (_, 0) => None,
+            (port, _) => Some((PmTimerBlock::Port(port as u16), width)),
+        }
+    }
+
+    /// Get the reset register and the value to write to it, or `None` if the system can't be reset
+    /// through it.
+    ///
+    /// The reset register was only added in revision 2 of the FADT, so older (shorter) ones don't
+    /// have it
+    fn reset_register(&self) -> Option<(Gas, u8)> {
+        let reset_value_end = offset_of!(Fadt, reset_value) + size_of::<u8>();
+        if (self.header().length as usize) < reset_value_end || self.flags & FLAG_RESET_REG_SUP == 0
+        {
+            return None;
+        }
+
+        let gas = self.reset_reg;
+        let addr = gas.addr;
+        (addr != 0).then_some((gas, self.reset_value))
+    }
+
+    /// Set up the PM timer, if the system has one
+    fn setup_pm_timer(&self) {
+        let Some((block, width)) = self.pm_timer() else {
+            logger::warn!("ACPI: FADT doesn't describe a PM timer");
+            return;
+        };
+
+        let access = match block {
+            PmTimerBlock::Port(port) => PmTimerAccess::Port(port),
+            PmTimerBlock::Memory(addr) => {
+                // SAFETY: The register is in reserved memory, so the kernel isn't tracking it
+                let base = addr.align_down(BASIC_PAGE_SIZE.size());
+                let diff = addr - base;
+                let ptr = unsafe {
+                    X86_64::map_pages(base, 1, Flags::new(), PageSize::size_4kb())
+                        .unwrap()
+                        .byte_add(diff)
+                };
+                PmTimerAccess::Mmio(ptr.cast::<u32>().cast_const())
+            }
+        };
+
+        unsafe { PM_TIMER.lock().init(access, width) };
+
+        logger::info!("ACPI: Found {:?} PM timer at {:?}", width, block);
+    }
+
+    /// Parse the FADT, and set up the PM timer
+    pub(super) fn parse(&self) -> Result<(), AcpiError> {
+        self.header().validate_checksum()?;
+
+        self.setup_pm_timer();
+
+        if let Some(reset_register) = self.reset_register() {
+            // The FADT is only parsed once
+            let _ = RESET_REGISTER.set(reset_register);
+        }
+
+        Ok(())
+    }
+
+    /// Locate the DSDT the FADT points to using `map`, and get its AML bytecode. Returns `None`
+    /// if the FADT doesn't point to a DSDT.
+    ///
+    /// # Errors
+    /// Fails if the DSDT is invalid
+    pub(super) fn dsdt_aml(&self, map: MapTable) -> Result<Option<&'static [u8]>, AcpiError> {
+        let Some(dsdt_addr) = self.dsdt_addr() else {
+            return Ok(None);
+        };
+
+        let dsdt = unsafe { map(dsdt_addr).cast::<Dsdt>().as_ref().unwrap() };
+
+        dsdt.aml().map(Some)
+    }
+}
+
+/// Reset the system by writing to the FADT's reset register.
+///
+/// The reset takes effect asynchronously, so this might return before the system resets.
+///
+/// # Safety
+/// Everything running is lost, so the system must be ready for it
+///
+/// # Errors
+/// Fails if the system can't be reset through the reset register (or the FADT wasn't parsed yet)
+pub(super) unsafe fn reset() -> Result<(), AcpiError> {
+    let &(gas, value) = RESET_REGISTER.get().ok_or(AcpiError::NoResetRegister)?;
+
+    // The register is in reserved memory, which isn't necessarily in the HHDM. `Gas::write`
+    // accesses it through the HHDM, so that's where it's mapped
+    if let Location::Memory(addr) = gas.location()? {
+        let base = addr.align_down(BASIC_PAGE_SIZE.size());
+        if X86_64::translate(base.add_hhdm_offset()).is_none() {
+            unsafe {
+                X86_64::map_pages_to(
+                    base,
+                    base.add_hhdm_offset(),
+                    1,
+                    Flags::new(),
+                    PageSize::size_4kb(),
+                )
+            }
+            .map_err(AcpiError::MappingFailed)?;
+        }
+    }
+
+    unsafe { gas.write(u64::from(value)) }
+}
+
+impl AcpiTable for Fadt {
+    const SIGNATURE: &'static [u8; 4] = b"FACP";
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::acpi::gas::{GAS_SYSTEM_IO, GAS_SYSTEM_MEMORY};
+    use core::ptr::{from_mut, from_ref};
+    use utils::checksum;
+
+    /// A synthetic DSDT with a few bytes of AML
+    #[repr(C)]
+    pub(in crate::acpi) struct TestDsdt {
+        header: SdtHeader,
+        pub(in crate::acpi) aml: [u8; 8],
+    }
+
+    /// Create a header for a table of type `T` with the given signature, and a zeroed checksum
+    pub(in crate::acpi) fn header<T>(signature: [u8; 4]) -> SdtHeader {
+        SdtHeader {
+            signature,
+            length: size_of::<T>() as u32,
+            revision: 2,
+            checksum: 0,
+            oem_id: *b"FNDRBK",
+            oem_table_id: *b"SYNTHTIC",
+            oem_revision: 1,
+            creator_id: 0,
+            creator_revision: 0,
+        }
+    }
+
+    /// Fix up the checksum of the table at `table`, so all its bytes sum up to 0
+    pub(in crate::acpi) fn fix_checksum<T>(table: &mut T) {
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(from_mut(table).cast::<u8>(), size_of::<T>())
+        };
+        bytes[offset_of!(SdtHeader, checksum)] = 0_u8.wrapping_sub(checksum::acpi_checksum(bytes));
+    }
+
+    pub(in crate::acpi) fn test_dsdt() -> TestDsdt {
+        let mut dsdt = TestDsdt {
+            header: header::<TestDsdt>(*Dsdt::SIGNATURE),
+            aml: [0x10, 0x4a, 0x04, 0x5c, 0x5f, 0x53, 0x42, 0x5f],
+        };
+        fix_checksum(&mut dsdt);
+
+        dsdt
+    }
+
+    /// The FADT is packed, but the header inside it still needs to be aligned
+    #[repr(C, align(8))]
+    pub(in crate::acpi) struct TestFadt(Fadt);
+
+    pub(in crate::acpi) fn test_fadt(dsdt: u32, x_dsdt: u64) -> TestFadt {
+        let mut fadt = TestFadt(unsafe { core::mem::zeroed() });
+        unsafe {
+            from_mut(&mut fadt)
+                .cast::<SdtHeader>()
+                .write(header::<Fadt>(*Fadt::SIGNATURE));
+        };
+        fadt.0.dsdt = dsdt;
+        fadt.0.x_dsdt = x_dsdt;
+        fix_checksum(&mut fadt.0);
+
+        fadt
+    }
+
+    #[test]
+    fn test_dsdt_from_fadt() {
+        let dsdt = test_dsdt();
+        let dsdt_addr = from_ref(&dsdt).expose_provenance();
+
+        let fadt = test_fadt(0, dsdt_addr as u64);
+        assert!(fadt.0.header().validate_checksum().is_ok());
+        assert_eq!(fadt.0.dsdt_addr(), Some(PhysAddr(dsdt_addr)));
+
+        // HHDM offset is 0 in tests, so the physical address is usable as is
+        let found = unsafe {
+            core::ptr::with_exposed_provenance::<Dsdt>(fadt.0.dsdt_addr().unwrap().0)
+                .as_ref()
+                .unwrap()
+        };
+        let aml = found.aml().unwrap();
+
+        assert_eq!(&found.header.signature, b"DSDT");
+        assert_eq!(found.header.length as usize, size_of::<TestDsdt>());
+        assert_eq!(aml, &[0x10, 0x4a, 0x04, 0x5c, 0x5f, 0x53, 0x42, 0x5f]);
+    }
+
+    #[test]
+    fn test_dsdt_addr_fallback() {
+        // No X_DSDT, so the legacy field should be used
+        let fadt = test_fadt(0x1234_5000, 0);
+        assert_eq!(fadt.0.dsdt_addr(), Some(PhysAddr(0x1234_5000)));
+
+        // X_DSDT should take precedence
+        let fadt = test_fadt(0x1234_5000, 0x8_0000_0000);
+        assert_eq!(fadt.0.dsdt_addr(), Some(PhysAddr(0x8_0000_0000)));
+
+        // A revision 1 FADT is too short to contain X_DSDT, so it should be ignored
+        let mut fadt = test_fadt(0x1234_5000, 0x8_0000_0000);
+        unsafe {
+            (*from_mut(&mut fadt).cast::<SdtHeader>()).length =
+                offset_of!(Fadt, x_firmware_ctrl) as u32;
+        }
+        assert_eq!(fadt.0.dsdt_addr(), Some(PhysAddr(0x1234_5000)));
+
+        let fadt = test_fadt(0, 0);
+        assert_eq!(fadt.0.dsdt_addr(), None);
+    }
+
+    #[test]
+    fn test_pm_timer_location() {
+        let mut fadt = test_fadt(0, 0);
+        assert_eq!(fadt.0.pm_timer(), None);
+
+        // The legacy port, with a 24 bit counter
+        fadt.0.pm_tmr_blk = 0x608;
+        fadt.0.pm_tmr_len = 4;
+        assert_eq!(
+            fadt.0.pm_timer(),
+            Some((PmTimerBlock::Port(0x608), CounterWidth::Bits24))
+        );
+
+        // X_PM_TMR_BLK takes precedence, and the flags decide the width
+        fadt.0.flags = FLAG_TMR_VAL_EXT;
+        fadt.0.x_pm_tmr_blk = Gas {
+            space_id: GAS_SYSTEM_MEMORY,
+            register_bit_width: 32,
+            register_bit_offset: 0,
+            access_size: 3,
+            addr: 0xfed0_0008,
+        };
+        assert_eq!(
+            fadt.0.pm_timer(),
+            Some((
+                PmTimerBlock::Memory(PhysAddr(0xfed0_0008)),
+                CounterWidth::Bits32
+            ))
+        );
+
+        // Unless the FADT is too short to contain it
+        unsafe {
+            (*from_mut(&mut fadt).cast::<SdtHeader>()).length =
+                offset_of!(Fadt, x_pm_tmr_blk) as u32;
+        }
+        assert_eq!(
+            fadt.0.pm_timer(),
+            Some((PmTimerBlock::Port(0x608), CounterWidth::Bits32))
+        );
+    }
+
+    #[test]
+    fn test_dsdt_validation() {
+        let mut dsdt = test_dsdt();
+        dsdt.aml[0] ^= 0xff;
+        let dsdt_ref = unsafe { from_ref(&dsdt).cast::<Dsdt>().as_ref().unwrap() };
+        assert!(matches!(dsdt_ref.aml(), Err(AcpiError::InvalidChecksum)));
+
+        let mut dsdt = test_dsdt();
+        dsdt.header.signature = *b"SSDT";
+        fix_checksum(&mut dsdt);
+        let dsdt_ref = unsafe { from_ref(&dsdt).cast::<Dsdt>().as_ref().unwrap() };
+        assert!(matches!(dsdt_ref.aml(), Err(AcpiError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_reset_register() {
+        let mut fadt = test_fadt(0, 0);
+        fadt.0.reset_reg = Gas {
+            space_id: GAS_SYSTEM_IO,
+            register_bit_width: 8,
+            register_bit_offset: 0,
+            access_size: 1,
+            addr: 0xcf9,
+        };
+        fadt.0.reset_value = 0x06;
+
+        // Not supported unless the flag says so
+        assert!(fadt.0.reset_register().is_none());
+
+        fadt.0.flags = FLAG_RESET_REG_SUP;
+        let (gas, value) = fadt.0.reset_register().unwrap();
+        assert_eq!(gas.location(), Ok(Location::Port(0xcf9)));
+        assert_eq!(value, 0x06);
+
+        // A revision 1 FADT doesn't have the reset register
+        unsafe {
+            (*from_mut(&mut fadt).cast::<SdtHeader>()).length = offset_of!(Fadt, reset_reg) as u32;
+        }
+        assert!(fadt.0.reset_register().is_none());
+    }
+}
@@ -0,0 +1,371 @@
+//! The ACPI GAS (Generic Address Structure), which describes where a register is and how it's
+//! accessed
+
+use super::AcpiError;
+use core::ptr::{with_exposed_provenance, with_exposed_provenance_mut};
+use kernel::arch::x86_64::cpu::{inb_8, inb_16, inb_32, outb_8, outb_16, outb_32};
+use utils::mem::PhysAddr;
+
+/// GAS address space ID of registers in the system memory space
+pub(super) const GAS_SYSTEM_MEMORY: u8 = 0;
+/// GAS address space ID of registers in the system I/O space
+pub(super) const GAS_SYSTEM_IO: u8 = 1;
+
+/// The ACPI GAS (Generic Address Structure)
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Gas {
+    pub(super) space_id: u8,
+    /// The width of the register in bits, or 0 if it takes up the whole access
+    pub(super) register_bit_width: u8,
+    /// The bit the register starts at, within the access
+    pub(super) register_bit_offset: u8,
+    /// The size of the accesses the register must be read and written with, or 0 for legacy
+    /// tables that don't specify it
+    pub(super) access_size: u8,
+    pub(super) addr: u64,
+}
+
+/// Where a GAS register is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Location {
+    /// In the system memory space, accessed through the HHDM
+    Memory(PhysAddr),
+    /// In the system I/O space
+    Port(u16),
+}
+
+/// The size of the accesses to a GAS register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum AccessSize {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl AccessSize {
+    /// Get the size in bits
+    #[inline]
+    #[must_use]
+    const fn bits(self) -> u32 {
+        match self {
+            Self::Byte => 8,
+            Self::Word => 16,
+            Self::Dword => 32,
+            Self::Qword => 64,
+        }
+    }
+
+    /// Get the smallest access size that spans `bits` bits, or `None` if none does
+    #[inline]
+    #[must_use]
+    const fn spanning(bits: u32) -> Option<Self> {
+        match bits {
+            0..=8 => Some(Self::Byte),
+            9..=16 => Some(Self::Word),
+            17..=32 => Some(Self::Dword),
+            33..=64 => Some(Self::Qword),
+            _ => None,
+        }
+    }
+}
+
+/// Get a mask of the `bits` low bits
+#[inline]
+#[must_use]
+const fn low_bits_mask(bits: u32) -> u64 {
+    match bits {
+        64.. => u64::MAX,
+        _ => (1 << bits) - 1,
+    }
+}
+
+impl Gas {
+    /// Get where the register is, dispatching on its address space
+    ///
+    /// # Errors
+    /// Fails if the register is in an address space other than system memory or system I/O (e.g.
+    /// PCI configuration space), or if its port doesn't fit in a `u16`
+    pub(super) fn location(&self) -> Result<Location, AcpiError> {
+        let addr = self.addr;
+        match self.space_id {
+            GAS_SYSTEM_MEMORY => Ok(Location::Memory(PhysAddr(addr as usize))),
+            GAS_SYSTEM_IO => u16::try_from(addr)
+                .map(Location::Port)
+                .map_err(|_| AcpiError::UnsupportedAddressSpace),
+            _ => Err(AcpiError::UnsupportedAddressSpace),
+        }
+    }
+
+    /// Get the size of the accesses to the register. Legacy tables don't specify it, in which case
+    /// it's the smallest access that spans the register
+    fn access_size(&self) -> Result<AccessSize, AcpiError> {
+        let size = match self.access_size {
+            0 => AccessSize::spanning(
+                u32::from(self.register_bit_offset) + u32::from(self.register_bit_width),
+            )
+            .ok_or(AcpiError::UnsupportedAddressSpace),
+            1 => Ok(AccessSize::Byte),
+            2 => Ok(AccessSize::Word),
+            3 => Ok(AccessSize::Dword),
+            4 => Ok(AccessSize::Qword),
+            _ => Err(AcpiError::UnsupportedAddressSpace),
+        }?;
+
+        // The register must fit in a single access
+        let end = u32::from(self.register_bit_offset) + u32::from(self.register_bit_width);
+        if end > size.bits() {
+            return Err(AcpiError::UnsupportedAddressSpace);
+        }
+
+        Ok(size)
+    }
+
+    /// Get the mask of the register's bits within an access of `size`, before it's shifted to the
+    /// register's offset
+    fn mask(&self, size: AccessSize) -> u64 {
+        match self.register_bit_width {
+            0 => low_bits_mask(size.bits()),
+            width => low_bits_mask(u32::from(width)),
+        }
+    }
+
+    /// Read the register
+    ///
+    /// # Safety
+    /// The GAS must describe a register that is safe to read. Registers in system memory must have
+    /// been mapped in the HHDM by the caller
+    ///
+    /// # Errors
+    /// Fails if the register can't be accessed (see `location`)
+    pub(super) unsafe fn read(&self) -> Result<u64, AcpiError> {
+        self.read_with(|location, size| unsafe { read_raw(location, size) })
+    }
+
+    /// Like `read`, but does the access itself with `read`
+    fn read_with(
+        &self,
+        read: impl FnOnce(Location, AccessSize) -> Result<u64, AcpiError>,
+    ) -> Result<u64, AcpiError> {
+        let location = self.location()?;
+        let size = self.access_size()?;
+        let raw = read(location, size)?;
+
+        Ok((raw >> self.register_bit_offset) & self.mask(size))
+    }
+
+    /// Write `value` to the register. Bits of `value` that don't fit in the register are dropped,
+    /// and the bits of the access outside the register are preserved
+    ///
+    /// # Safety
+    /// The GAS must describe a register that is safe to write `value` to. Registers in system
+    /// memory must have been mapped in the HHDM by the caller
+    ///
+    /// # Errors
+    /// Fails if the register can't be accessed (see `location`)
+    pub(super) unsafe fn write(&self, value: u64) -> Result<(), AcpiError> {
+        self.write_with(
+            value,
+            |location, size| unsafe { read_raw(location, size) },
+            |location, size, raw| unsafe { write_raw(location, size, raw) },
+        )
+    }
+
+    /// Like `write`, but does the accesses itself with `read` and `write`
+    fn write_with(
+        &self,
+        value: u64,
+        read: impl FnOnce(Location, AccessSize) -> Result<u64, AcpiError>,
+        write: impl FnOnce(Location, AccessSize, u64) -> Result<(), AcpiError>,
+    ) -> Result<(), AcpiError> {
+        let location = self.location()?;
+        let size = self.access_size()?;
+        let mask = self.mask(size) << self.register_bit_offset;
+        let value = (value << self.register_bit_offset) & mask;
+
+        // Only the register's bits are ours to change, so if it doesn't take up the whole access
+        // the rest are read back first
+        let raw = if mask == low_bits_mask(size.bits()) {
+            value
+        } else {
+            (read(location, size)? & !mask) | value
+        };
+
+        write(location, size, raw)
+    }
+}
+
+/// Read a `T` from `addr` in the system memory, through the HHDM
+///
+/// # Safety
+/// The caller must have mapped `addr` in the HHDM (reserved memory isn't necessarily there), and
+/// reading a `T` from it must be safe
+unsafe fn read_memory<T>(addr: PhysAddr) -> T {
+    unsafe { with_exposed_provenance::<T>(addr.add_hhdm_offset().0).read_volatile() }
+}
+
+/// Write `value` to `addr` in the system memory, through the HHDM
+///
+/// # Safety
+/// The caller must have mapped `addr` in the HHDM (reserved memory isn't necessarily there), and
+/// writing `value` to it must be safe
+unsafe fn write_memory<T>(addr: PhysAddr, value: T) {
+    unsafe { with_exposed_provenance_mut::<T>(addr.add_hhdm_offset().0).write_volatile(value) };
+}
+
+/// Read an access of `size` at `location` from the hardware
+unsafe fn read_raw(location: Location, size: AccessSize) -> Result<u64, AcpiError> {
+    unsafe {
+        match (location, size) {
+            (Location::Memory(addr), AccessSize::Byte) => Ok(u64::from(read_memory::<u8>(addr))),
+            (Location::Memory(addr), AccessSize::Word) => Ok(u64::from(read_memory::<u16>(addr))),
+            (Location::Memory(addr), AccessSize::Dword) => Ok(u64::from(read_memory::<u32>(addr))),
+            (Location::Memory(addr), AccessSize::Qword) => Ok(read_memory::<u64>(addr)),
+            (Location::Port(port), AccessSize::Byte) => Ok(u64::from(inb_8(port))),
+            (Location::Port(port), AccessSize::Word) => Ok(u64::from(inb_16(port))),
+            (Location::Port(port), AccessSize::Dword) => Ok(u64::from(inb_32(port))),
+            // There are no 64 bit port accesses
+            (Location::Port(_), AccessSize::Qword) => Err(AcpiError::UnsupportedAddressSpace),
+        }
+    }
+}
+
+/// Write an access of `size` at `location` to the hardware
+unsafe fn write_raw(location: Location, size: AccessSize, raw: u64) -> Result<(), AcpiError> {
+    // NOTE: The truncations are fine, since `raw` was masked to the access size
+    unsafe {
+        match (location, size) {
+            (Location::Memory(addr), AccessSize::Byte) => write_memory(addr, raw as u8),
+            (Location::Memory(addr), AccessSize::Word) => write_memory(addr, raw as u16),
+            (Location::Memory(addr), AccessSize::Dword) => write_memory(addr, raw as u32),
+            (Location::Memory(addr), AccessSize::Qword) => write_memory(addr, raw),
+            (Location::Port(port), AccessSize::Byte) => outb_8(port, raw as u8),
+            (Location::Port(port), AccessSize::Word) => outb_16(port, raw as u16),
+            (Location::Port(port), AccessSize::Dword) => outb_32(port, raw as u32),
+            (Location::Port(_), AccessSize::Qword) => {
+                return Err(AcpiError::UnsupportedAddressSpace);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr::from_mut;
+
+    fn gas(space_id: u8, width: u8, offset: u8, access_size: u8, addr: u64) -> Gas {
+        Gas {
+            space_id,
+            register_bit_width: width,
+            register_bit_offset: offset,
+            access_size,
+            addr,
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        assert_eq!(
+            gas(GAS_SYSTEM_MEMORY, 32, 0, 3, 0xfed0_00f0).location(),
+            Ok(Location::Memory(PhysAddr(0xfed0_00f0)))
+        );
+        assert_eq!(
+            gas(GAS_SYSTEM_IO, 8, 0, 1, 0xcf9).location(),
+            Ok(Location::Port(0xcf9))
+        );
+
+        // PCI configuration space, and ports that can't exist
+        assert_eq!(
+            gas(2, 8, 0, 1, 0x1234).location(),
+            Err(AcpiError::UnsupportedAddressSpace)
+        );
+        assert_eq!(
+            gas(GAS_SYSTEM_IO, 8, 0, 1, 0x1_0000).location(),
+            Err(AcpiError::UnsupportedAddressSpace)
+        );
+
+        // The port backend is called with the port and the access size
+        let mut accessed = None;
+        let port = gas(GAS_SYSTEM_IO, 16, 0, 2, 0x604);
+        let value = port.read_with(|location, size| {
+            accessed = Some((location, size));
+            Ok(0xabcd)
+        });
+        assert_eq!(value, Ok(0xabcd));
+        assert_eq!(accessed, Some((Location::Port(0x604), AccessSize::Word)));
+    }
+
+    #[test]
+    fn test_memory_access() {
+        // The HHDM offset is 0 in tests, so the register's address is its own "physical" one
+        let mut register = 0x1122_3344_u32;
+        let addr = from_mut(&mut register).expose_provenance() as u64;
+
+        let whole = gas(GAS_SYSTEM_MEMORY, 32, 0, 3, addr);
+        assert_eq!(unsafe { whole.read() }, Ok(0x1122_3344));
+
+        // Only the register's bits change
+        let field = gas(GAS_SYSTEM_MEMORY, 8, 8, 3, addr);
+        assert_eq!(unsafe { field.read() }, Ok(0x33));
+        unsafe { field.write(0x1ff).unwrap() };
+        assert_eq!(register, 0x1122_ff44);
+    }
+
+    #[test]
+    fn test_bit_width_masking() {
+        // A 24 bit register in a dword access: the top byte isn't part of it
+        let timer = gas(GAS_SYSTEM_IO, 24, 0, 3, 0x608);
+        assert_eq!(
+            timer.read_with(|_, size| {
+                assert_eq!(size, AccessSize::Dword);
+                Ok(0xff12_3456)
+            }),
+            Ok(0x12_3456)
+        );
+
+        // Legacy tables don't give the access size, so it's derived from the register's bits
+        let legacy = gas(GAS_SYSTEM_IO, 4, 4, 0, 0x64);
+        assert_eq!(legacy.access_size(), Ok(AccessSize::Byte));
+        assert_eq!(legacy.read_with(|_, _| Ok(0xa5)), Ok(0xa));
+        assert_eq!(
+            gas(GAS_SYSTEM_IO, 0, 0, 0, 0x64).mask(AccessSize::Byte),
+            0xff
+        );
+        assert_eq!(
+            gas(GAS_SYSTEM_IO, 8, 4, 1, 0x64).access_size(),
+            Err(AcpiError::UnsupportedAddressSpace)
+        );
+
+        // Writing part of the access reads the rest back first
+        let mut written = None;
+        legacy
+            .write_with(
+                0x13,
+                |_, _| Ok(0xa5),
+                |_, _, raw| {
+                    written = Some(raw);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(written, Some(0x35));
+
+        // Writing the whole access doesn't
+        gas(GAS_SYSTEM_IO, 8, 0, 1, 0xcf9)
+            .write_with(
+                0x106,
+                |_, _| panic!("Nothing should be read"),
+                |location, size, raw| {
+                    written = Some(raw);
+                    assert_eq!((location, size), (Location::Port(0xcf9), AccessSize::Byte));
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(written, Some(0x06));
+    }
+}
@@ -6,9 +6,10 @@ use kernel::{
         X86_64,
         apic::{
             DeliveryMode,
-            ioapic::{self, IoApic, init_irq_allocator},
+            ioapic::{self, init_irq_allocator},
             lapic,
         },
+        pic,
     },
     mem::paging::{Flags, PageSize, PagingManager},
 };
@@ -188,8 +189,8 @@ impl Madt {
     pub(super) fn parse(&self) -> Result<(), AcpiError> {
         self.header.validate_checksum()?;
 
-        // Mask off all of the old PIC interrupts
-        unsafe { IoApic::mask_off_pic() };
+        // Get the old PICs out of the way before setting up the APIC stack
+        unsafe { pic::remap_and_disable() };
 
         for entry in self.iter() {
             let entry_type = unsafe { entry.read().entry_type };
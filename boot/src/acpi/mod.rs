@@ -1,13 +1,25 @@
 //! ACPI table parser
 
-use core::{ptr::from_ref, slice::from_raw_parts};
+use core::{
+    ptr::{from_ref, with_exposed_provenance},
+    slice::from_raw_parts,
+};
+use gas::Gas;
 use kernel::{
     arch::{BASIC_PAGE_SIZE, x86_64::X86_64},
-    mem::paging::{Flags, PageSize, PagingManager},
+    mem::paging::{Flags, PageSize, PagingError, PagingManager},
 };
 use rsdp::Rsdp2;
-use utils::{mem::PhysAddr, sanity_assert};
+use utils::{
+    checksum,
+    mem::PhysAddr,
+    sanity_assert,
+    sync::spinlock::{SpinLock, SpinLockable},
+};
 
+mod dsdt;
+mod fadt;
+mod gas;
 mod hpet;
 mod madt;
 pub mod mcfg;
@@ -15,23 +27,70 @@ mod rsdp;
 mod xsdt;
 
 /// Errors that can occur while parsing ACPI tables
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum AcpiError {
     /// The checksum of the table is invalid
     InvalidChecksum,
+    /// The signature of the table isn't the one expected
+    InvalidSignature,
+    /// The length of the table is too short to even hold its header
+    InvalidLength,
+    /// A register is described by a GAS we can't access, e.g. one in PCI configuration space
+    UnsupportedAddressSpace,
+    /// The platform doesn't support resetting through the FADT's reset register
+    NoResetRegister,
+    /// A register in system memory couldn't be mapped
+    MappingFailed(PagingError),
 }
 
-/// The ACPI GAS (Generic Address Structure)
-#[repr(C, packed)]
-#[derive(Debug)]
-struct Gas {
-    space_id: u8,
-    register_bit_width: u8,
-    register_bit_offset: u8,
-    _reserved: u8,
-    addr: u64,
+/// The maximum amount of SSDTs we keep track of
+const MAX_SSDTS: usize = 16;
+
+/// The AML bytecode of the DSDT and SSDTs, found while parsing the tables
+#[derive(Debug, Clone, Copy)]
+struct AmlTables {
+    /// The AML of the DSDT. Empty if no DSDT was found
+    dsdt: &'static [u8],
+    /// The AML of each of the SSDTs
+    ssdts: [Option<&'static [u8]>; MAX_SSDTS],
+}
+
+impl AmlTables {
+    const EMPTY: Self = Self {
+        dsdt: &[],
+        ssdts: [None; MAX_SSDTS],
+    };
+
+    /// Keep track of the AML bytecode of an SSDT. Returns false if the SSDT was skipped.
+    ///
+    /// Some firmware lists the same SSDT more than once, so an SSDT identical to one that was
+    /// already added is skipped, since loading it again would redefine its objects. SSDTs past
+    /// the first `MAX_SSDTS` are skipped as well.
+    fn add_ssdt(&mut self, aml: &'static [u8]) -> bool {
+        if self.ssdts.iter().flatten().any(|&ssdt| ssdt == aml) {
+            return false;
+        }
+
+        let Some(slot) = self.ssdts.iter_mut().find(|slot| slot.is_none()) else {
+            return false;
+        };
+        *slot = Some(aml);
+
+        true
+    }
+
+    /// Get the AML bytecode of the DSDT (if there is one), followed by that of the SSDTs
+    fn blobs(self) -> impl Iterator<Item = &'static [u8]> {
+        core::iter::once(self.dsdt)
+            .filter(|dsdt| !dsdt.is_empty())
+            .chain(self.ssdts.into_iter().flatten())
+    }
 }
 
+impl SpinLockable for AmlTables {}
+
+static AML_TABLES: SpinLock<AmlTables> = SpinLock::new(AmlTables::EMPTY);
+
 /// The header that comes before (almost) all ACPI table
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -62,11 +121,8 @@ impl SdtHeader {
 
     /// Validate the checksum of the table
     fn validate_checksum(&self) -> Result<(), AcpiError> {
-        let sum = unsafe { from_raw_parts(from_ref(self).cast::<u8>(), self.length as usize) }
-            .iter()
-            .fold(0, |acc, &x| acc + x as usize);
-
-        if sum % 0x100 != 0 {
+        let bytes = unsafe { from_raw_parts(from_ref(self).cast::<u8>(), self.length as usize) };
+        if !checksum::verify(bytes) {
             return Err(AcpiError::InvalidChecksum);
         }
 
@@ -81,17 +137,72 @@ trait AcpiTable {
     const SIGNATURE: &'static [u8; 4];
 }
 
+/// A function that makes the ACPI table at a physical address accessible, returning a pointer to
+/// it
+type MapTable = unsafe fn(PhysAddr) -> *const SdtHeader;
+
+/// Map the entire ACPI table at `addr`, and not just its first page
+unsafe fn map_table(addr: PhysAddr) -> *const SdtHeader {
+    let base = addr.align_down(BASIC_PAGE_SIZE.size());
+    let diff = addr - base;
+
+    // The length is read through a temporary mapping of 2 pages, in case the header itself crosses
+    // a page boundary
+    let length = unsafe {
+        kernel::arch::with_temp_mapping(addr, 2, Flags::new(), |header| {
+            (*with_exposed_provenance::<SdtHeader>(header.0)).length
+        })
+    }
+    .expect("Failed to map ACPI table header") as usize;
+    let page_count = (diff + length).div_ceil(BASIC_PAGE_SIZE.size());
+
+    unsafe {
+        X86_64::map_pages(base, page_count, Flags::new(), PageSize::size_4kb())
+            .unwrap()
+            .byte_add(diff)
+            .cast::<SdtHeader>()
+    }
+}
+
+/// Get the AML bytecode of the DSDT.
+///
+/// Returns an empty slice if ACPI wasn't initialized yet, or if the platform has no DSDT.
+pub fn dsdt_aml() -> &'static [u8] {
+    AML_TABLES.lock().dsdt
+}
+
+/// Get the AML bytecode of all the definition blocks: the DSDT first, followed by all the SSDTs
+/// found in the XSDT. Together they make up the platform's whole ACPI namespace.
+///
+/// Returns an empty iterator if ACPI wasn't initialized yet.
+pub fn aml_blobs() -> impl Iterator<Item = &'static [u8]> {
+    AML_TABLES.lock().blobs()
+}
+
+/// Reset the system through the FADT's reset register. Returns once the reset was requested, but
+/// the system might take a moment to actually reset.
+///
+/// # Safety
+/// Everything running is lost, so the system must be ready for it
+///
+/// # Errors
+/// Fails if the system doesn't support resetting through the reset register, or if ACPI wasn't
+/// initialized yet
+pub unsafe fn reset() -> Result<(), AcpiError> {
+    unsafe { fadt::reset() }
+}
+
 /// Initialize the ACPI subsystem
 pub unsafe fn init(rsdp_addr: PhysAddr) -> Result<(), AcpiError> {
-    sanity_assert!(rsdp_addr.0 % align_of::<Rsdp2>() == 0);
+    sanity_assert!(rsdp_addr.is_aligned(align_of::<Rsdp2>()));
 
     let rsdp = unsafe {
-        let diff = rsdp_addr.0 % BASIC_PAGE_SIZE.size();
-        let ptr: *const Rsdp2 =
-            X86_64::map_pages(rsdp_addr - diff, 1, Flags::new(), PageSize::size_4kb())
-                .unwrap()
-                .byte_add(diff)
-                .cast();
+        let base = rsdp_addr.align_down(BASIC_PAGE_SIZE.size());
+        let diff = rsdp_addr - base;
+        let ptr: *const Rsdp2 = X86_64::map_pages(base, 1, Flags::new(), PageSize::size_4kb())
+            .unwrap()
+            .byte_add(diff)
+            .cast();
 
         ptr.as_ref().unwrap()
     };
@@ -100,7 +211,11 @@ pub unsafe fn init(rsdp_addr: PhysAddr) -> Result<(), AcpiError> {
     let xsdt = rsdp.get_xsdt();
     xsdt.parse_tables()?;
 
-    logger::info!("ACPI: All tables parsed successfully");
+    logger::info!(
+        "ACPI: All tables parsed successfully ({} bytes of DSDT AML, {} SSDTs)",
+        dsdt_aml().len(),
+        AML_TABLES.lock().ssdts.iter().flatten().count()
+    );
 
     Ok(())
 }
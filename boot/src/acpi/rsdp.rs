@@ -1,12 +1,12 @@
 //! Parser for the RSDP table
 
 use super::{AcpiError, SdtHeader, xsdt::Xsdt};
-use core::ptr;
+use core::{ptr, slice};
 use kernel::{
     arch::{BASIC_PAGE_SIZE, x86_64::X86_64},
     mem::paging::{Flags, PageSize, PagingManager},
 };
-use utils::mem::PhysAddr;
+use utils::{checksum, mem::PhysAddr};
 
 /// The RSDP (a pointer to the XSDT)
 #[repr(C, packed)]
@@ -42,32 +42,14 @@ pub(super) struct Rsdp2 {
 }
 
 impl Rsdp2 {
-    /// Validate the checksum of the RSDP2
+    /// Validate the checksums of the RSDP2. The original checksum covers the original fields, and
+    /// the extended checksum covers the whole structure (including the original fields)
     pub(super) fn validate_checksum(&self) -> Result<(), AcpiError> {
-        // RSDP2 checksum is calculated for the original fields, and the extended fields separately
-        // Calculate checksum for the original Rsdp
-        {
-            let mut sum: usize = 0;
-            let ptr = ptr::from_ref(self).cast::<u8>();
-            for i in 0..size_of::<Rsdp>() {
-                sum += unsafe { *(ptr.add(i)) } as usize;
-            }
-
-            if sum & 0xff != 0 {
-                return Err(AcpiError::InvalidChecksum);
-            }
-        }
-        // Calculate checksum for the new fields
-        {
-            let mut sum: usize = 0;
-            let ptr = unsafe { ptr::from_ref(self).cast::<u8>().byte_add(size_of::<Rsdp>()) };
-            for i in 0..(size_of::<Rsdp2>() - size_of::<Rsdp>()) {
-                sum += unsafe { *(ptr.add(i)) } as usize;
-            }
-
-            if sum & 0xff != 0 {
-                return Err(AcpiError::InvalidChecksum);
-            }
+        let bytes =
+            unsafe { slice::from_raw_parts(ptr::from_ref(self).cast::<u8>(), size_of::<Self>()) };
+
+        if !checksum::verify(&bytes[..size_of::<Rsdp>()]) || !checksum::verify(bytes) {
+            return Err(AcpiError::InvalidChecksum);
         }
 
         Ok(())
@@ -78,9 +60,10 @@ impl Rsdp2 {
     pub(super) fn get_xsdt(&self) -> &Xsdt {
         let ptr: *const SdtHeader = unsafe {
             let addr = PhysAddr(self.xsdt_address as usize);
-            let diff = addr.0 % BASIC_PAGE_SIZE.size();
+            let base = addr.align_down(BASIC_PAGE_SIZE.size());
+            let diff = addr - base;
 
-            X86_64::map_pages(addr - diff, 1, Flags::new(), PageSize::size_4kb())
+            X86_64::map_pages(base, 1, Flags::new(), PageSize::size_4kb())
                 .unwrap()
                 .byte_add(diff)
                 .cast()
@@ -91,3 +74,40 @@ impl Rsdp2 {
         unsafe { ptr.cast::<Xsdt>().as_ref().unwrap() }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RSDP2 as QEMU builds it
+    const RSDP2: [u8; 36] = [
+        0x52, 0x53, 0x44, 0x20, 0x50, 0x54, 0x52, 0x20, 0x8a, 0x42, 0x4f, 0x43, 0x48, 0x53, 0x20,
+        0x02, 0xad, 0x14, 0xfe, 0x07, 0x24, 0x00, 0x00, 0x00, 0xa1, 0x15, 0xfe, 0x07, 0x00, 0x00,
+        0x00, 0x00, 0x21, 0x00, 0x00, 0x00,
+    ];
+
+    fn parse(bytes: [u8; 36]) -> Rsdp2 {
+        unsafe { ptr::read_unaligned(bytes.as_ptr().cast::<Rsdp2>()) }
+    }
+
+    #[test]
+    fn test_validate_checksum() {
+        assert_eq!(parse(RSDP2).validate_checksum(), Ok(()));
+
+        // The original checksum is off
+        let mut rsdp = RSDP2;
+        rsdp[9] = b'X';
+        assert_eq!(
+            parse(rsdp).validate_checksum(),
+            Err(AcpiError::InvalidChecksum)
+        );
+
+        // Only the XSDT address is off, which only the extended checksum covers
+        let mut rsdp = RSDP2;
+        rsdp[24] = 0;
+        assert_eq!(
+            parse(rsdp).validate_checksum(),
+            Err(AcpiError::InvalidChecksum)
+        );
+    }
+}
@@ -1,11 +1,11 @@
 //! Parser for the XSDT table
 
-use super::{AcpiError, AcpiTable, SdtHeader, hpet::Hpet, madt::Madt, mcfg::Mcfg};
-use core::ptr::from_ref;
-use kernel::{
-    arch::{BASIC_PAGE_SIZE, x86_64::X86_64},
-    mem::paging::{Flags, PageSize, PagingManager},
+use super::{
+    AcpiError, AcpiTable, AmlTables, MapTable, SdtHeader, dsdt::Ssdt, fadt::Fadt, hpet::Hpet,
+    madt::Madt, mcfg::Mcfg,
 };
+use core::ptr::from_ref;
+use drivers::bus::pcie::PcieManager;
 use utils::mem::PhysAddr;
 
 /// The XSDT
@@ -17,20 +17,24 @@ pub(super) struct Xsdt {
 }
 
 impl Xsdt {
-    /// Get an iterator over the entries in the XSDT
+    /// Get an iterator over the entries in the XSDT, which are made accessible using `map`
     #[inline]
-    fn iter(&self) -> Iter {
+    fn iter(&self, map: MapTable) -> Iter {
         let count = self.header.entry_count::<PhysAddr>();
         let ptr: *const PhysAddr = unsafe { from_ref(self).add(1).cast::<PhysAddr>() };
 
-        Iter { ptr, count }
+        Iter { ptr, count, map }
     }
 
     /// Parse the ACPI tables in the XSDT
     pub(super) fn parse_tables(&self) -> Result<(), AcpiError> {
         self.header.validate_checksum()?;
 
-        for entry in self.iter() {
+        let mut found_mcfg = false;
+        let mut aml = AmlCollector::new();
+        for entry in self.iter(super::map_table) {
+            aml.collect(entry, super::map_table)?;
+
             let signature = &unsafe { (*entry).signature };
             match signature {
                 Madt::SIGNATURE => {
@@ -44,6 +48,11 @@ impl Xsdt {
                 Mcfg::SIGNATURE => {
                     let mcfg = unsafe { entry.cast::<Mcfg>().as_ref().unwrap() };
                     mcfg.parse()?;
+                    found_mcfg = true;
+                }
+                Fadt::SIGNATURE => {
+                    let fadt = unsafe { entry.cast::<Fadt>().as_ref().unwrap() };
+                    fadt.parse()?;
                 }
                 _ => continue,
                 // _ => {
@@ -58,6 +67,67 @@ impl Xsdt {
             logger::info!("ACPI: Parsed table: {:?}", core::str::from_utf8(signature));
         }
 
+        // Without an MCFG there is no ECAM, so the devices can only be reached the legacy way
+        if !found_mcfg {
+            PcieManager::init(&[]).unwrap();
+        }
+
+        if aml.tables.dsdt.is_empty() {
+            logger::warn!("ACPI: FADT doesn't point to a DSDT");
+        }
+        if aml.skipped > 0 {
+            logger::warn!(
+                "ACPI: Skipped {} malformed, duplicate or excess SSDTs",
+                aml.skipped
+            );
+        }
+        *super::AML_TABLES.lock() = aml.tables;
+
+        Ok(())
+    }
+}
+
+/// Collects the AML bytecode of the DSDT the FADT points to, and of all the SSDTs, while the
+/// tables in the XSDT are parsed
+struct AmlCollector {
+    tables: AmlTables,
+    /// The amount of SSDTs that were skipped
+    skipped: usize,
+}
+
+impl AmlCollector {
+    const fn new() -> Self {
+        Self {
+            tables: AmlTables::EMPTY,
+            skipped: 0,
+        }
+    }
+
+    /// Collect the AML bytecode of `entry` if it's an SSDT, or of the DSDT it points to if it's
+    /// the FADT. `map` makes the DSDT accessible.
+    ///
+    /// Malformed and duplicate SSDTs are skipped rather than failing, since the rest of the
+    /// namespace is still usable without them.
+    ///
+    /// # Errors
+    /// Fails if the DSDT is invalid
+    fn collect(&mut self, entry: *const SdtHeader, map: MapTable) -> Result<(), AcpiError> {
+        match unsafe { &(*entry).signature } {
+            Fadt::SIGNATURE => {
+                let fadt = unsafe { entry.cast::<Fadt>().as_ref().unwrap() };
+                if let Some(aml) = fadt.dsdt_aml(map)? {
+                    self.tables.dsdt = aml;
+                }
+            }
+            Ssdt::SIGNATURE => {
+                let ssdt = unsafe { entry.cast::<Ssdt>().as_ref().unwrap() };
+                if !ssdt.aml().is_ok_and(|aml| self.tables.add_ssdt(aml)) {
+                    self.skipped += 1;
+                }
+            }
+            _ => (),
+        }
+
         Ok(())
     }
 }
@@ -66,6 +136,7 @@ impl Xsdt {
 struct Iter {
     ptr: *const PhysAddr,
     count: usize,
+    map: MapTable,
 }
 
 impl Iterator for Iter {
@@ -76,14 +147,7 @@ impl Iterator for Iter {
             return None;
         }
 
-        let ptr = unsafe {
-            let addr = self.ptr.read_unaligned();
-            let diff = addr.0 % BASIC_PAGE_SIZE.size();
-            X86_64::map_pages(addr - diff, 1, Flags::new(), PageSize::size_4kb())
-                .unwrap()
-                .cast::<SdtHeader>()
-                .byte_add(diff)
-        };
+        let ptr = unsafe { (self.map)(self.ptr.read_unaligned()) };
 
         self.ptr = unsafe { self.ptr.add(1) };
         self.count -= 1;
@@ -91,3 +155,108 @@ impl Iterator for Iter {
         Some(ptr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::fadt::tests::{fix_checksum, header, test_dsdt, test_fadt};
+    use super::*;
+    use core::ptr::{from_ref, with_exposed_provenance};
+
+    /// HHDM offset is 0 in tests, so tables are accessible at their "physical" address
+    unsafe fn identity_map(addr: PhysAddr) -> *const SdtHeader {
+        with_exposed_provenance(addr.0)
+    }
+
+    /// Get the "physical" address of `table`
+    fn addr_of<T>(table: &T) -> u64 {
+        from_ref(table).expose_provenance() as u64
+    }
+
+    /// A synthetic SSDT with a few bytes of AML
+    #[repr(C)]
+    struct TestSsdt {
+        header: SdtHeader,
+        aml: [u8; 4],
+    }
+
+    fn test_ssdt(aml: [u8; 4]) -> TestSsdt {
+        let mut ssdt = TestSsdt {
+            header: header::<TestSsdt>(*Ssdt::SIGNATURE),
+            aml,
+        };
+        fix_checksum(&mut ssdt);
+
+        ssdt
+    }
+
+    /// A synthetic XSDT. The entries come right after the header, without padding
+    #[repr(C, packed(4))]
+    struct TestXsdt<const N: usize> {
+        header: SdtHeader,
+        entries: [u64; N],
+    }
+
+    /// Collect the AML bytecode of all the entries of `xsdt`, like `Xsdt::parse_tables` does
+    fn collect_aml<const N: usize>(xsdt: &TestXsdt<N>) -> Result<AmlCollector, AcpiError> {
+        let xsdt = unsafe { from_ref(xsdt).cast::<Xsdt>().as_ref().unwrap() };
+        assert!(xsdt.header.validate_checksum().is_ok());
+
+        let mut aml = AmlCollector::new();
+        for entry in xsdt.iter(identity_map) {
+            aml.collect(entry, identity_map)?;
+        }
+
+        Ok(aml)
+    }
+
+    fn test_xsdt<const N: usize>(entries: [u64; N]) -> TestXsdt<N> {
+        let mut xsdt = TestXsdt {
+            header: header::<TestXsdt<N>>(*b"XSDT"),
+            entries,
+        };
+        fix_checksum(&mut xsdt);
+
+        xsdt
+    }
+
+    #[test]
+    fn test_aml_from_dsdt_and_ssdts() {
+        let dsdt = test_dsdt();
+        let fadt = test_fadt(0, addr_of(&dsdt));
+        let first = test_ssdt([0x10, 0x01, 0x02, 0x03]);
+        let second = test_ssdt([0x14, 0x05, 0x06, 0x07]);
+        let mut malformed = test_ssdt([0x14, 0x08, 0x09, 0x0a]);
+        malformed.aml[0] ^= 0xff;
+        let duplicate = test_ssdt(first.aml);
+
+        let xsdt = test_xsdt([
+            addr_of(&first),
+            addr_of(&fadt),
+            addr_of(&malformed),
+            addr_of(&second),
+            addr_of(&duplicate),
+        ]);
+
+        // The DSDT comes first, no matter where the FADT is, and the bad SSDTs are skipped
+        let aml = collect_aml(&xsdt).unwrap();
+        let blobs: [&[u8]; 3] = [&dsdt.aml, &first.aml, &second.aml];
+        assert!(aml.tables.blobs().eq(blobs));
+        assert_eq!(aml.skipped, 2);
+
+        // An invalid DSDT fails the whole thing, since the namespace is unusable without it
+        let mut dsdt = test_dsdt();
+        dsdt.aml[0] ^= 0xff;
+        let fadt = test_fadt(0, addr_of(&dsdt));
+        let xsdt = test_xsdt([addr_of(&first), addr_of(&fadt)]);
+        assert!(matches!(
+            collect_aml(&xsdt),
+            Err(AcpiError::InvalidChecksum)
+        ));
+
+        // Without a DSDT, only the SSDTs are left
+        let xsdt = test_xsdt([addr_of(&second)]);
+        let aml = collect_aml(&xsdt).unwrap();
+        assert!(aml.tables.blobs().eq([&second.aml[..]]));
+        assert_eq!(aml.skipped, 0);
+    }
+}
@@ -1,19 +1,22 @@
 //! Everything needed to boot the kernel with Limine.
 
-use kernel::mem::{paging::PagingManager, vaa::init_vaa_from_limine};
-
-use crate::{acpi, funderberker_start};
-use kernel::arch::Arch;
-use kernel::arch::x86_64::X86_64;
-use utils::mem::{HHDM_OFFSET, PhysAddr, VirtAddr};
+use utils::boot_info::{self, BootInfo, BootModule, MemoryRegion, MemoryRegionKind};
+#[cfg(feature = "framebuffer")]
+use utils::boot_info::{ColorMask, FramebufferInfo};
+use utils::cmdline::KernelArgs;
+use utils::mem::{PhysAddr, VirtAddr};
 
 #[cfg(feature = "framebuffer")]
 use limine::request::FramebufferRequest;
 use limine::request::{
-    ExecutableAddressRequest, HhdmRequest, MemoryMapRequest, PagingModeRequest, RequestsEndMarker,
-    RequestsStartMarker, RsdpRequest,
+    ExecutableAddressRequest, ExecutableFileRequest, HhdmRequest, MemoryMapRequest, ModuleRequest,
+    PagingModeRequest, RequestsEndMarker, RequestsStartMarker, RsdpRequest,
+};
+use limine::{
+    BaseRevision,
+    memory_map::{Entry, EntryType},
+    paging,
 };
-use limine::{BaseRevision, paging};
 
 /// Sets the base revision to the latest revision supported by the crate.
 /// See specification for further info.
@@ -34,6 +37,13 @@ static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 #[used]
 #[unsafe(link_section = ".requests")]
 static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+#[used]
+#[unsafe(link_section = ".requests")]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+/// The kernel's own file, which carries the command line it was booted with
+#[used]
+#[unsafe(link_section = ".requests")]
+static EXECUTABLE_FILE_REQUEST: ExecutableFileRequest = ExecutableFileRequest::new();
 
 #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))] // x86_64 and AArch64 share the same modes
 #[used]
@@ -54,59 +64,119 @@ static _START_MARKER: RequestsStartMarker = RequestsStartMarker::new();
 #[unsafe(link_section = ".requests_end_marker")]
 static _END_MARKER: RequestsEndMarker = RequestsEndMarker::new();
 
-#[unsafe(no_mangle)]
-unsafe extern "C" fn kmain() -> ! {
-    // All limine requests must also be referenced in a called function, otherwise they may be
-    // removed by the linker.
-    assert!(BASE_REVISION.is_supported());
+/// Convert a Limine memory map entry to a bootloader agnostic memory region
+fn memory_region_from(entry: &Entry) -> MemoryRegion {
+    let kind = match entry.entry_type {
+        EntryType::USABLE => MemoryRegionKind::Usable,
+        EntryType::ACPI_RECLAIMABLE => MemoryRegionKind::AcpiReclaimable,
+        EntryType::ACPI_NVS => MemoryRegionKind::AcpiNvs,
+        EntryType::BAD_MEMORY => MemoryRegionKind::BadMemory,
+        EntryType::BOOTLOADER_RECLAIMABLE => MemoryRegionKind::BootloaderReclaimable,
+        EntryType::EXECUTABLE_AND_MODULES => MemoryRegionKind::ExecutableAndModules,
+        EntryType::FRAMEBUFFER => MemoryRegionKind::Framebuffer,
+        _ => MemoryRegionKind::Reserved,
+    };
 
-    #[cfg(feature = "framebuffer")]
-    logger::framebuffer::init_from_limine(
-        FRAMEBUFFER_REQUEST
-            .get_response()
-            .unwrap()
-            .framebuffers()
-            .next()
-            .unwrap(),
-    );
+    MemoryRegion {
+        base: PhysAddr(entry.base as usize),
+        length: entry.length as usize,
+        kind,
+    }
+}
 
+/// Gather everything Limine told us into a `BootInfo`, and store it
+fn init_boot_info() -> &'static BootInfo {
     let hhdm = HHDM_REQUEST
         .get_response()
-        .expect("Can't get Limine framebuffer feature response");
+        .expect("Can't get Limine HHDM feature response");
     let mem_map = MEMORY_MAP_REQUEST
         .get_response()
         .expect("Can't get Limine memory map feature");
     let kernel_addr = KERNEL_ADDRESS_REQUEST
         .get_response()
         .expect("Can't get Limine kernel address feature");
-    let rsdp = RSDP_REQUEST
-        .get_response()
-        .expect("Can't get Limine RSDP feature");
-
-    unsafe {
-        HHDM_OFFSET.set(hhdm.offset() as usize);
 
-        X86_64::early_boot_init();
+    let mut info = BootInfo::new(
+        hhdm.offset() as usize,
+        VirtAddr(kernel_addr.virtual_base() as usize),
+        PhysAddr(kernel_addr.physical_base() as usize),
+    );
 
-        init_vaa_from_limine(mem_map.entries());
+    for &entry in mem_map.entries() {
+        if info.push_memory_region(memory_region_from(entry)).is_err() {
+            logger::warn!("Too many memory map entries, ignoring the rest");
+            break;
+        }
+    }
+
+    if let Some(rsdp) = RSDP_REQUEST.get_response() {
+        info.set_rsdp(PhysAddr(rsdp.address()));
+    }
+
+    if let Some(executable) = EXECUTABLE_FILE_REQUEST.get_response() {
+        let cmdline = core::str::from_utf8(executable.file().string()).unwrap_or_default();
+        match KernelArgs::new(cmdline) {
+            Some(args) => info.set_args(args),
+            None => logger::warn!("Kernel command line is too long, ignoring it"),
+        }
+    }
+
+    if let Some(modules) = MODULE_REQUEST.get_response() {
+        for module in modules.modules() {
+            let module = BootModule {
+                addr: VirtAddr(module.addr().addr()),
+                size: module.size() as usize,
+                cmdline: core::str::from_utf8(module.string()).unwrap_or_default(),
+            };
+            if info.push_module(module).is_err() {
+                logger::warn!("Too many boot modules, ignoring the rest");
+                break;
+            }
+        }
+    }
 
-        let used_by_pmm = pmm::init_from_limine(mem_map.entries());
+    #[cfg(feature = "framebuffer")]
+    if let Some(fb) = FRAMEBUFFER_REQUEST
+        .get_response()
+        .and_then(|response| response.framebuffers().next())
+    {
+        info.set_framebuffer(FramebufferInfo {
+            addr: VirtAddr(fb.addr().addr()),
+            width: fb.width(),
+            height: fb.height(),
+            pitch: fb.pitch(),
+            bpp: fb.bpp(),
+            red_mask: ColorMask {
+                size: fb.red_mask_size(),
+                shift: fb.red_mask_shift(),
+            },
+            green_mask: ColorMask {
+                size: fb.green_mask_size(),
+                shift: fb.green_mask_shift(),
+            },
+            blue_mask: ColorMask {
+                size: fb.blue_mask_size(),
+                shift: fb.blue_mask_shift(),
+            },
+        });
+    }
+
+    boot_info::init(info)
+}
 
-        X86_64::init_paging_from_limine(
-            mem_map.entries(),
-            VirtAddr(kernel_addr.virtual_base() as usize),
-            PhysAddr(kernel_addr.physical_base() as usize),
-            used_by_pmm,
-        );
+#[unsafe(no_mangle)]
+unsafe extern "C" fn kmain() -> ! {
+    // All limine requests must also be referenced in a called function, otherwise they may be
+    // removed by the linker.
+    assert!(BASE_REVISION.is_supported());
 
-        acpi::init(PhysAddr(rsdp.address())).unwrap();
-    };
+    let boot_info = init_boot_info();
 
     // XXX: As I've stated in the comment in the function below, this is technically bad since
     // there is a period of time our stack is marked as free, but during that time period nothing
     // gets allocated, so these pages will stay intact and so it shouldn't be a problem.
     // unsafe { free_bootloader_reclaimable(mem_map.entries()) };
 
-    funderberker_start();
+    unsafe { super::start_kernel(boot_info) }
     // unsafe { kernel::archmigrate_to_new_stack() };
 }
@@ -2,3 +2,94 @@
 
 #[cfg(feature = "limine")]
 pub mod limine;
+#[cfg(any(feature = "multiboot2", test))]
+pub mod multiboot2;
+
+#[cfg(any(feature = "limine", feature = "multiboot2"))]
+use kernel::{
+    arch::{Arch, x86_64::X86_64},
+    mem::{paging::PagingManager, vaa::init_vaa},
+};
+#[cfg(any(feature = "limine", feature = "multiboot2"))]
+use utils::{boot_info::BootInfo, mem::HHDM_OFFSET};
+
+#[cfg(any(feature = "limine", feature = "multiboot2"))]
+use crate::{acpi, funderberker_start};
+
+/// Bring the kernel up using what the bootloader told us, whichever one it was, and start it.
+///
+/// # Safety
+/// Should only be called once, by the boot method's entry point, right after `boot_info` was
+/// gathered
+#[cfg(any(feature = "limine", feature = "multiboot2"))]
+unsafe fn start_kernel(boot_info: &'static BootInfo) -> ! {
+    // SAFETY: Nothing else is running yet
+    unsafe { logger::serial::init() };
+
+    #[cfg(feature = "framebuffer")]
+    logger::framebuffer::init(
+        boot_info
+            .framebuffer()
+            .expect("The bootloader didn't provide a framebuffer"),
+    );
+
+    // A PSF font can be passed as a module with the `font` command line, to replace the built-in
+    // one
+    #[cfg(feature = "framebuffer")]
+    if let Some(font) = boot_info.module("font") {
+        // SAFETY: Modules are loaded in executable and modules memory, which is never freed and
+        // stays mapped through the HHDM once the kernel's page tables are set up
+        if let Err(err) = logger::framebuffer::load_font(unsafe { font.data() }) {
+            logger::warn!("Failed to load the font module: {err:?}");
+        }
+    }
+
+    // `loglevel=` hides the less severe messages, e.g. `loglevel=warning` only leaves warnings
+    // and errors
+    if let Some(name) = boot_info.args().get("loglevel") {
+        match logger::Level::from_name(name) {
+            Some(level) => logger::sink::set_builtin_min_level(level),
+            None => {
+                logger::warn!("Unknown log level {name:?}, expected debug, info, warning or error");
+            }
+        }
+    }
+
+    unsafe {
+        HHDM_OFFSET.set(boot_info.hhdm_offset());
+
+        X86_64::early_boot_init();
+
+        init_vaa(boot_info);
+
+        let used_by_pmm = pmm::init(boot_info);
+
+        X86_64::init_paging(boot_info, &used_by_pmm);
+
+        // The PCIe devices are discovered and handed to their drivers while parsing the ACPI
+        // tables, so the drivers have to be registered by then
+        for driver in [
+            drivers::usb::xhci::PCI_DRIVER,
+            drivers::storage::nvme::PCI_DRIVER,
+        ] {
+            drivers::bus::pcie::register_driver(driver).unwrap();
+        }
+
+        acpi::init(
+            boot_info
+                .rsdp()
+                .expect("The bootloader didn't provide the RSDP"),
+        )
+        .unwrap();
+
+        drivers::clock::tsc::calibrate();
+
+        // `nowatchdog` turns the watchdog off without a rebuild, e.g. while sitting in a debugger
+        #[cfg(feature = "watchdog")]
+        if !boot_info.args().flag("nowatchdog") {
+            kernel::arch::x86_64::watchdog::start();
+        }
+    };
+
+    funderberker_start();
+}
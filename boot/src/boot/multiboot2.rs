@@ -0,0 +1,874 @@
+//! Everything needed to boot the kernel with a Multiboot2 bootloader (e.g. GRUB).
+//!
+//! The bootloader finds the kernel by its Multiboot2 header, and hands it the Multiboot2
+//! information structure: A list of tags, each describing something about the system (the memory
+//! map, the framebuffer, a copy of the RSDP...). The tags are parsed into the same `BootInfo` the
+//! Limine path produces, so the rest of the kernel doesn't care how it was booted.
+//!
+//! Unlike Limine, the bootloader leaves us in 32 bit protected mode without paging, so a small
+//! trampoline sets up long mode first: it maps the kernel's image where it's linked, and the first
+//! 4GB of physical memory both at the HHDM and at their own addresses, then jumps to
+//! `multiboot2_main`.
+
+use kernel::arch::BASIC_PAGE_SIZE;
+use utils::boot_info::{
+    BootInfo, BootModule, ColorMask, FramebufferInfo, MAX_MEMORY_REGIONS, MemoryRegion,
+    MemoryRegionKind,
+};
+use utils::cmdline::KernelArgs;
+use utils::collections::arrayvec::ArrayVec;
+use utils::mem::{PhysAddr, VirtAddr};
+
+/// The magic number the bootloader looks for in the header
+const HEADER_MAGIC: u32 = 0xe852_50d6;
+
+/// The magic number the bootloader passes the kernel in `eax`
+const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+/// The architecture field of the header for (32 bit protected mode) i386
+const ARCHITECTURE_I386: u32 = 0;
+
+/// Where the kernel's image is mapped. Must match the linker script
+const KERNEL_VIRT_BASE: usize = 0xffff_ffff_8000_0000;
+
+/// Where the bootloader loads the kernel's image. Must match the linker script, and be 2MB aligned
+/// since the trampoline maps the image with 2MB pages
+const KERNEL_PHYS_BASE: usize = 0x20_0000;
+
+/// Where the trampoline maps the first 4GB of physical memory
+const HHDM_BASE: usize = 0xffff_8000_0000_0000;
+
+/// The size of the stack the kernel starts on
+const BOOT_STACK_SIZE: usize = 64 * 1024;
+
+/// The alignment of every tag, both in the header and in the information structure
+const TAG_ALIGNMENT: usize = 8;
+
+/// The size of the fixed part of the information structure, and of each tag's header
+const TAG_HEADER_SIZE: usize = 8;
+
+/// The tags of the information structure the kernel uses
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD_RSDP: u32 = 14;
+const TAG_ACPI_NEW_RSDP: u32 = 15;
+
+/// The framebuffer type of direct RGB color framebuffers
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+// The Multiboot2 header, which has to be in the first 32KB of the kernel's image, followed by the
+// trampoline the bootloader jumps to.
+//
+// The header asks for a framebuffer of whatever mode the bootloader prefers (flagged optional, so
+// systems without one still boot), and for the bootloader to enter the kernel at the trampoline
+// rather than at the ELF's entry point, which is Limine's.
+//
+// The trampoline runs from the physical addresses the kernel was loaded at, so the addresses of
+// the symbols it uses are adjusted by `KERNEL_VIRT_BASE - KERNEL_PHYS_BASE` until it gets to the
+// higher half.
+#[cfg(feature = "multiboot2")]
+core::arch::global_asm!(
+    ".set PHYS_OFFSET, {phys_offset}",
+    ".set PAGE_PRESENT_WRITABLE, 0x3",
+    ".set PAGE_HUGE, 0x80",
+    //
+    ".section .multiboot2, \"a\"",
+    ".balign 8",
+    ".Lmultiboot2_header_start:",
+    ".long {header_magic}",
+    ".long {architecture}",
+    ".long .Lmultiboot2_header_end - .Lmultiboot2_header_start",
+    ".long -({header_magic} + {architecture} + (.Lmultiboot2_header_end - .Lmultiboot2_header_start))",
+    // The framebuffer tag: Type 5 with the optional flag set, then the size, and no preferred
+    // width, height or depth. The last word pads the tag to `TAG_ALIGNMENT`
+    ".long 5 | (1 << 16), 20, 0, 0, 0, 0",
+    // The entry address tag: Type 3, then the size and the trampoline's physical address
+    ".long 3, 12, multiboot2_entry - PHYS_OFFSET, 0",
+    // The end tag
+    ".long 0, 8",
+    ".Lmultiboot2_header_end:",
+    //
+    ".section .bss.multiboot2, \"aw\", @nobits",
+    ".balign 4096",
+    "multiboot2_pml4: .skip 4096",
+    // Maps the first 4GB with the 4 page directories that follow it, both at their own addresses
+    // and at the HHDM
+    "multiboot2_low_pdpt: .skip 4096",
+    "multiboot2_low_pds: .skip 4 * 4096",
+    // Maps the kernel's image at `KERNEL_VIRT_BASE`
+    "multiboot2_high_pdpt: .skip 4096",
+    "multiboot2_kernel_pd: .skip 4096",
+    "multiboot2_stack: .skip {stack_size}",
+    "multiboot2_stack_top:",
+    //
+    ".section .rodata.multiboot2, \"a\"",
+    ".balign 8",
+    // A null descriptor, a 64 bit code segment and a data segment. The kernel loads its own GDT
+    // later on
+    "multiboot2_gdt:",
+    ".quad 0, 0x00af9a000000ffff, 0x00cf92000000ffff",
+    "multiboot2_gdt_ptr:",
+    ".short 3 * 8 - 1",
+    ".long multiboot2_gdt - PHYS_OFFSET",
+    //
+    ".section .text.multiboot2, \"ax\"",
+    ".code32",
+    ".global multiboot2_entry",
+    "multiboot2_entry:",
+    "cli",
+    "cld",
+    // Hold on to the magic and the information structure's physical address
+    "mov ebp, eax",
+    "mov esi, ebx",
+    "mov esp, offset multiboot2_stack_top - PHYS_OFFSET",
+    // Map the first 4GB with 2MB pages
+    "xor ecx, ecx",
+    ".Lmultiboot2_map_low:",
+    "mov eax, ecx",
+    "shl eax, 21",
+    "or eax, PAGE_PRESENT_WRITABLE | PAGE_HUGE",
+    "mov [multiboot2_low_pds - PHYS_OFFSET + ecx * 8], eax",
+    "inc ecx",
+    "cmp ecx, 4 * 512",
+    "jb .Lmultiboot2_map_low",
+    "mov eax, offset multiboot2_low_pds - PHYS_OFFSET + PAGE_PRESENT_WRITABLE",
+    "xor ecx, ecx",
+    ".Lmultiboot2_link_low:",
+    "mov [multiboot2_low_pdpt - PHYS_OFFSET + ecx * 8], eax",
+    "add eax, 4096",
+    "inc ecx",
+    "cmp ecx, 4",
+    "jb .Lmultiboot2_link_low",
+    // Map the 1GB starting at the kernel's image at `KERNEL_VIRT_BASE`, which is the second to
+    // last entry of the last PDPT
+    "xor ecx, ecx",
+    ".Lmultiboot2_map_kernel:",
+    "mov eax, ecx",
+    "shl eax, 21",
+    "add eax, {phys_base}",
+    "or eax, PAGE_PRESENT_WRITABLE | PAGE_HUGE",
+    "mov [multiboot2_kernel_pd - PHYS_OFFSET + ecx * 8], eax",
+    "inc ecx",
+    "cmp ecx, 512",
+    "jb .Lmultiboot2_map_kernel",
+    "mov eax, offset multiboot2_kernel_pd - PHYS_OFFSET + PAGE_PRESENT_WRITABLE",
+    "mov [multiboot2_high_pdpt - PHYS_OFFSET + 510 * 8], eax",
+    // The first 4GB at their own addresses (so we survive enabling paging) and at the HHDM, and
+    // the kernel's image
+    "mov eax, offset multiboot2_low_pdpt - PHYS_OFFSET + PAGE_PRESENT_WRITABLE",
+    "mov [multiboot2_pml4 - PHYS_OFFSET], eax",
+    "mov [multiboot2_pml4 - PHYS_OFFSET + {hhdm_index} * 8], eax",
+    "mov eax, offset multiboot2_high_pdpt - PHYS_OFFSET + PAGE_PRESENT_WRITABLE",
+    "mov [multiboot2_pml4 - PHYS_OFFSET + 511 * 8], eax",
+    // Enable PAE, long mode and then paging, which activates long mode
+    "mov eax, offset multiboot2_pml4 - PHYS_OFFSET",
+    "mov cr3, eax",
+    "mov eax, cr4",
+    "or eax, 1 << 5",
+    "mov cr4, eax",
+    "mov ecx, 0xc0000080",
+    "rdmsr",
+    "or eax, 1 << 8",
+    "wrmsr",
+    "mov eax, cr0",
+    "or eax, (1 << 31) | 1",
+    "mov cr0, eax",
+    // Load a GDT with a 64 bit code segment, and jump to it
+    "lgdt [multiboot2_gdt_ptr - PHYS_OFFSET]",
+    "push 0x8",
+    "mov eax, offset .Lmultiboot2_long_mode - PHYS_OFFSET",
+    "push eax",
+    "retf",
+    ".code64",
+    ".Lmultiboot2_long_mode:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "xor eax, eax",
+    "mov fs, ax",
+    "mov gs, ax",
+    // Move on to the higher half, and call into Rust with the magic and the information structure
+    "movabs rax, offset .Lmultiboot2_higher_half",
+    "jmp rax",
+    ".Lmultiboot2_higher_half:",
+    "movabs rsp, offset multiboot2_stack_top",
+    "mov edi, ebp",
+    "mov esi, esi",
+    "xor ebp, ebp",
+    "call {main}",
+    "ud2",
+    // Negative, since the assembler's expressions are signed 64 bit ones
+    phys_offset = const KERNEL_VIRT_BASE.wrapping_sub(KERNEL_PHYS_BASE).cast_signed(),
+    phys_base = const KERNEL_PHYS_BASE,
+    hhdm_index = const (HHDM_BASE >> 39) & 511,
+    header_magic = const HEADER_MAGIC,
+    architecture = const ARCHITECTURE_I386,
+    stack_size = const BOOT_STACK_SIZE,
+    main = sym multiboot2_main,
+);
+
+/// Errors that can occur while parsing the Multiboot2 information structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiboot2Error {
+    /// The structure's size doesn't fit in the buffer it's in
+    Truncated,
+    /// A tag's size is invalid, or it runs past the end of the structure
+    BadTag,
+}
+
+/// Read the little endian `u32` at `offset`
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read the little endian `u64` at `offset`
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Convert a Multiboot2 memory map entry type to a bootloader agnostic region kind
+const fn memory_region_kind_from(typ: u32) -> MemoryRegionKind {
+    match typ {
+        1 => MemoryRegionKind::Usable,
+        3 => MemoryRegionKind::AcpiReclaimable,
+        4 => MemoryRegionKind::AcpiNvs,
+        5 => MemoryRegionKind::BadMemory,
+        _ => MemoryRegionKind::Reserved,
+    }
+}
+
+/// Parse a command line tag, whose body is a null terminated UTF-8 string.
+///
+/// A command line that is too long, isn't null terminated or isn't UTF-8 is dropped with a warning
+/// rather than failing the boot, since the kernel can run without one
+fn parse_cmdline(tag: &[u8]) -> KernelArgs {
+    let body = &tag[TAG_HEADER_SIZE..];
+    let Some(len) = body.iter().position(|&byte| byte == 0) else {
+        logger::warn!("Kernel command line isn't null terminated, ignoring it");
+        return KernelArgs::EMPTY;
+    };
+    let Ok(cmdline) = core::str::from_utf8(&body[..len]) else {
+        logger::warn!("Kernel command line isn't valid UTF-8, ignoring it");
+        return KernelArgs::EMPTY;
+    };
+
+    KernelArgs::new(cmdline).unwrap_or_else(|| {
+        logger::warn!("Kernel command line is too long, ignoring it");
+        KernelArgs::EMPTY
+    })
+}
+
+/// Parse the entries of a memory map tag into `regions`
+fn parse_memory_map(
+    tag: &[u8],
+    regions: &mut ArrayVec<MemoryRegion, MAX_MEMORY_REGIONS>,
+) -> Result<(), Multiboot2Error> {
+    const BASE_OFFSET: usize = 0;
+    const LENGTH_OFFSET: usize = 8;
+    const TYPE_OFFSET: usize = 16;
+    const MIN_ENTRY_SIZE: usize = 24;
+
+    if tag.len() < TAG_HEADER_SIZE + 8 {
+        return Err(Multiboot2Error::BadTag);
+    }
+    let entry_size = read_u32(tag, TAG_HEADER_SIZE) as usize;
+    if entry_size < MIN_ENTRY_SIZE {
+        return Err(Multiboot2Error::BadTag);
+    }
+
+    for entry in tag[TAG_HEADER_SIZE + 8..].chunks_exact(entry_size) {
+        let region = MemoryRegion {
+            base: PhysAddr(read_u64(entry, BASE_OFFSET) as usize),
+            length: read_u64(entry, LENGTH_OFFSET) as usize,
+            kind: memory_region_kind_from(read_u32(entry, TYPE_OFFSET)),
+        };
+
+        if regions.push(region).is_err() {
+            logger::warn!("Too many memory map entries, ignoring the rest");
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a module tag: The physical addresses the module starts and ends at, followed by its null
+/// terminated command line.
+///
+/// Modules are accessed through the HHDM, like on Limine
+fn parse_module(tag: &'static [u8], hhdm_offset: usize) -> Result<BootModule, Multiboot2Error> {
+    const START_OFFSET: usize = 8;
+    const END_OFFSET: usize = 12;
+    const CMDLINE_OFFSET: usize = 16;
+
+    if tag.len() <= CMDLINE_OFFSET {
+        return Err(Multiboot2Error::BadTag);
+    }
+    let start = read_u32(tag, START_OFFSET) as usize;
+    let end = read_u32(tag, END_OFFSET) as usize;
+    if end < start {
+        return Err(Multiboot2Error::BadTag);
+    }
+
+    let cmdline = &tag[CMDLINE_OFFSET..];
+    let len = cmdline
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or(Multiboot2Error::BadTag)?;
+
+    Ok(BootModule {
+        addr: VirtAddr(start + hhdm_offset),
+        size: end - start,
+        cmdline: core::str::from_utf8(&cmdline[..len]).unwrap_or_default(),
+    })
+}
+
+/// Mark the memory `size` bytes long starting at `base` (rounded out to whole pages) as `kind`,
+/// splitting it out of whatever regions it overlaps.
+///
+/// Multiboot2 memory maps report the memory the bootloader put things in (e.g. the kernel's image)
+/// as usable, so it has to be carved out before the PMM gets to hand it out
+fn carve(
+    regions: &mut ArrayVec<MemoryRegion, MAX_MEMORY_REGIONS>,
+    base: PhysAddr,
+    size: usize,
+    kind: MemoryRegionKind,
+) {
+    let page_size = BASIC_PAGE_SIZE.size();
+    let start = base.0 - base.0 % page_size;
+    let end = (base.0 + size).next_multiple_of(page_size);
+
+    let mut carved = ArrayVec::new();
+    let pieces = regions.iter().flat_map(|region| {
+        let (region_start, region_end) = (region.base.0, region.end().0);
+        let piece = |from: usize, to: usize| {
+            (from < to).then(|| MemoryRegion {
+                base: PhysAddr(from),
+                length: to - from,
+                kind: region.kind,
+            })
+        };
+
+        // The parts of the region before and after the carved memory
+        [
+            piece(region_start, region_end.min(start)),
+            piece(region_start.max(end), region_end),
+        ]
+        .into_iter()
+        .flatten()
+    });
+
+    for region in pieces.chain(core::iter::once(MemoryRegion {
+        base: PhysAddr(start),
+        length: end - start,
+        kind,
+    })) {
+        if carved.push(region).is_err() {
+            logger::warn!("Too many memory map entries, ignoring the rest");
+            break;
+        }
+    }
+
+    *regions = carved;
+}
+
+/// Parse a framebuffer tag, or return `None` if the framebuffer isn't a direct RGB one.
+///
+/// The framebuffer is reported by its physical address, so it's accessed through the HHDM
+fn parse_framebuffer(
+    tag: &[u8],
+    hhdm_offset: usize,
+) -> Result<Option<FramebufferInfo>, Multiboot2Error> {
+    const ADDR_OFFSET: usize = 8;
+    const PITCH_OFFSET: usize = 16;
+    const WIDTH_OFFSET: usize = 20;
+    const HEIGHT_OFFSET: usize = 24;
+    const BPP_OFFSET: usize = 28;
+    const TYPE_OFFSET: usize = 29;
+    const COLOR_INFO_OFFSET: usize = 32;
+    const RGB_TAG_SIZE: usize = COLOR_INFO_OFFSET + 6;
+
+    if tag.len() <= TYPE_OFFSET {
+        return Err(Multiboot2Error::BadTag);
+    }
+    if tag[TYPE_OFFSET] != FRAMEBUFFER_TYPE_RGB {
+        return Ok(None);
+    }
+    if tag.len() < RGB_TAG_SIZE {
+        return Err(Multiboot2Error::BadTag);
+    }
+
+    // Each channel is described by the position of its lowest bit, followed by its size
+    let color_mask = |i: usize| ColorMask {
+        shift: tag[COLOR_INFO_OFFSET + 2 * i],
+        size: tag[COLOR_INFO_OFFSET + 2 * i + 1],
+    };
+
+    Ok(Some(FramebufferInfo {
+        addr: VirtAddr(read_u64(tag, ADDR_OFFSET) as usize + hhdm_offset),
+        width: u64::from(read_u32(tag, WIDTH_OFFSET)),
+        height: u64::from(read_u32(tag, HEIGHT_OFFSET)),
+        pitch: u64::from(read_u32(tag, PITCH_OFFSET)),
+        bpp: u16::from(tag[BPP_OFFSET]),
+        red_mask: color_mask(0),
+        green_mask: color_mask(1),
+        blue_mask: color_mask(2),
+    }))
+}
+
+/// Parse the Multiboot2 information structure `info`, located at `info_phys`, into `boot_info`.
+///
+/// Tags the kernel doesn't use are skipped. The memory map is sorted by address, since unlike
+/// Limine's, Multiboot2's isn't necessarily.
+///
+/// Multiboot2 memory maps don't tell apart the memory the kernel's image (`kernel_size` bytes at
+/// `boot_info.kernel_phys()`), the modules and the information structure itself are in, so they are
+/// carved out of it. So is the framebuffer, which is often missing from it.
+///
+/// # Errors
+/// Fails if the structure is malformed, in which case `boot_info` might be partly filled in
+pub fn parse(
+    info: &'static [u8],
+    info_phys: PhysAddr,
+    kernel_size: usize,
+    boot_info: &mut BootInfo,
+) -> Result<(), Multiboot2Error> {
+    if info.len() < TAG_HEADER_SIZE {
+        return Err(Multiboot2Error::Truncated);
+    }
+    let total_size = read_u32(info, 0) as usize;
+    if total_size < TAG_HEADER_SIZE || total_size > info.len() {
+        return Err(Multiboot2Error::Truncated);
+    }
+
+    let mut regions = ArrayVec::new();
+    let mut offset = TAG_HEADER_SIZE;
+    loop {
+        if offset + TAG_HEADER_SIZE > total_size {
+            return Err(Multiboot2Error::BadTag);
+        }
+        let typ = read_u32(info, offset);
+        let size = read_u32(info, offset + 4) as usize;
+        if size < TAG_HEADER_SIZE || offset + size > total_size {
+            return Err(Multiboot2Error::BadTag);
+        }
+        let tag = &info[offset..offset + size];
+
+        match typ {
+            TAG_END => break,
+            TAG_CMDLINE => boot_info.set_args(parse_cmdline(tag)),
+            TAG_MODULE => {
+                if boot_info
+                    .push_module(parse_module(tag, boot_info.hhdm_offset())?)
+                    .is_err()
+                {
+                    logger::warn!("Too many boot modules, ignoring the rest");
+                }
+            }
+            TAG_MEMORY_MAP => parse_memory_map(tag, &mut regions)?,
+            TAG_FRAMEBUFFER => {
+                if let Some(framebuffer) = parse_framebuffer(tag, boot_info.hhdm_offset())? {
+                    boot_info.set_framebuffer(framebuffer);
+                }
+            }
+            // The tags hold a copy of the RSDP, right after the tag's header. The new one points
+            // to the XSDT as well, so it's preferred
+            TAG_ACPI_NEW_RSDP => boot_info.set_rsdp(info_phys + offset + TAG_HEADER_SIZE),
+            TAG_ACPI_OLD_RSDP if boot_info.rsdp().is_none() => {
+                boot_info.set_rsdp(info_phys + offset + TAG_HEADER_SIZE);
+            }
+            _ => (),
+        }
+
+        offset += size.next_multiple_of(TAG_ALIGNMENT);
+    }
+
+    let hhdm_offset = boot_info.hhdm_offset();
+    carve(
+        &mut regions,
+        boot_info.kernel_phys(),
+        kernel_size,
+        MemoryRegionKind::ExecutableAndModules,
+    );
+    carve(
+        &mut regions,
+        info_phys,
+        total_size,
+        MemoryRegionKind::BootloaderReclaimable,
+    );
+    for module in boot_info.modules() {
+        carve(
+            &mut regions,
+            PhysAddr(module.addr.0 - hhdm_offset),
+            module.size,
+            MemoryRegionKind::ExecutableAndModules,
+        );
+    }
+    if let Some(framebuffer) = boot_info.framebuffer() {
+        carve(
+            &mut regions,
+            PhysAddr(framebuffer.addr.0 - hhdm_offset),
+            (framebuffer.pitch * framebuffer.height) as usize,
+            MemoryRegionKind::Framebuffer,
+        );
+    }
+
+    regions.sort_unstable_by_key(|region| region.base);
+    for region in &regions {
+        // Both memory maps have the same capacity, so this never fails
+        let _ = boot_info.push_memory_region(*region);
+    }
+
+    Ok(())
+}
+
+/// Where the trampoline leaves off: Called in long mode, on the boot stack, with the magic the
+/// bootloader passed and the physical address of the information structure
+#[cfg(feature = "multiboot2")]
+unsafe extern "C" fn multiboot2_main(magic: u32, info_phys: u32) -> ! {
+    unsafe extern "C" {
+        /// The end of the kernel's image, defined by the linker script
+        static __kernel_end: u8;
+    }
+
+    assert_eq!(
+        magic, BOOTLOADER_MAGIC,
+        "Not booted by a Multiboot2 bootloader"
+    );
+
+    let info_phys = PhysAddr(info_phys as usize);
+    // SAFETY: The trampoline mapped the first 4GB at the HHDM, and the structure is below 4GB. It's
+    // carved out of the memory map, so it's never reused
+    let info = unsafe {
+        let info = core::ptr::with_exposed_provenance::<u8>(info_phys.0 + HHDM_BASE);
+        let total_size = info.cast::<u32>().read_unaligned() as usize;
+        core::slice::from_raw_parts(info, total_size)
+    };
+
+    let mut boot_info = BootInfo::new(
+        HHDM_BASE,
+        VirtAddr(KERNEL_VIRT_BASE),
+        PhysAddr(KERNEL_PHYS_BASE),
+    );
+    let kernel_size = (&raw const __kernel_end).addr() - KERNEL_VIRT_BASE;
+    if let Err(err) = parse(info, info_phys, kernel_size, &mut boot_info) {
+        panic!("Failed to parse the Multiboot2 information structure: {err:?}");
+    }
+
+    unsafe { super::start_kernel(utils::boot_info::init(boot_info)) }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    const HHDM_OFFSET: usize = 0xffff_8000_0000_0000;
+    const INFO_PHYS: PhysAddr = PhysAddr(0x10_0000);
+    const KERNEL_SIZE: usize = 0x4_0000;
+
+    /// Builds a Multiboot2 information structure tag by tag
+    struct InfoBuilder {
+        buffer: [u8; 256],
+        len: usize,
+    }
+
+    impl InfoBuilder {
+        fn new() -> Self {
+            Self {
+                buffer: [0; 256],
+                len: TAG_HEADER_SIZE,
+            }
+        }
+
+        fn push(&mut self, bytes: &[u8]) {
+            self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+
+        /// Add a tag whose body is made of `parts`
+        fn tag(mut self, typ: u32, parts: &[&[u8]]) -> Self {
+            let size = TAG_HEADER_SIZE + parts.iter().map(|part| part.len()).sum::<usize>();
+
+            self.push(&typ.to_le_bytes());
+            self.push(&(size as u32).to_le_bytes());
+            for part in parts {
+                self.push(part);
+            }
+            self.len = self.len.next_multiple_of(TAG_ALIGNMENT);
+
+            self
+        }
+
+        fn memory_map(self, entries: &[(u64, u64, u32)]) -> Self {
+            let mut body = [0; 4 * 24];
+            for (i, &(base, length, typ)) in entries.iter().enumerate() {
+                body[i * 24..i * 24 + 8].copy_from_slice(&base.to_le_bytes());
+                body[i * 24 + 8..i * 24 + 16].copy_from_slice(&length.to_le_bytes());
+                body[i * 24 + 16..i * 24 + 20].copy_from_slice(&typ.to_le_bytes());
+            }
+
+            self.tag(
+                TAG_MEMORY_MAP,
+                &[&24_u32.to_le_bytes(), &[0; 4], &body[..entries.len() * 24]],
+            )
+        }
+
+        fn framebuffer(self, typ: u8) -> Self {
+            self.tag(
+                TAG_FRAMEBUFFER,
+                &[
+                    &0xfd00_0000_u64.to_le_bytes(),
+                    &(1024_u32 * 4).to_le_bytes(),
+                    &1024_u32.to_le_bytes(),
+                    &768_u32.to_le_bytes(),
+                    &[32, typ, 0, 0],
+                    // Red, green and blue positions and sizes
+                    &[16, 8, 8, 8, 0, 8],
+                ],
+            )
+        }
+
+        /// Finish the structure, which is leaked since `parse` keeps references into it
+        fn build(self) -> &'static mut [u8] {
+            let mut info = self.tag(TAG_END, &[]);
+            let total_size = (info.len as u32).to_le_bytes();
+            info.buffer[..4].copy_from_slice(&total_size);
+
+            Box::leak(Box::new(info.buffer)).split_at_mut(info.len).0
+        }
+    }
+
+    fn new_boot_info() -> BootInfo {
+        BootInfo::new(
+            HHDM_OFFSET,
+            VirtAddr(0xffff_ffff_8000_0000),
+            PhysAddr(0x20_0000),
+        )
+    }
+
+    #[test]
+    fn test_parse_memory_map_and_framebuffer() {
+        let info = InfoBuilder::new()
+            // Some tag the kernel doesn't care about (the bootloader's name)
+            .tag(2, &[b"GRUB 2.12\0"])
+            .memory_map(&[
+                (0x10_0000, 0x7fe0_0000, 1),
+                (0x0, 0x9_fc00, 1),
+                (0x7ff0_0000, 0x10_0000, 3),
+                (0xfee0_0000, 0x1000, 2),
+            ])
+            .framebuffer(FRAMEBUFFER_TYPE_RGB)
+            .build();
+
+        let mut boot_info = new_boot_info();
+        assert_eq!(parse(info, INFO_PHYS, KERNEL_SIZE, &mut boot_info), Ok(()));
+
+        let region = |base, length, kind| MemoryRegion {
+            base: PhysAddr(base),
+            length,
+            kind,
+        };
+        // The information structure and the kernel's image are carved out of the usable memory
+        // they're in, and the framebuffer is added
+        assert_eq!(
+            boot_info.memory_map(),
+            &[
+                region(0x0, 0x9_fc00, MemoryRegionKind::Usable),
+                region(0x10_0000, 0x1000, MemoryRegionKind::BootloaderReclaimable),
+                region(0x10_1000, 0xf_f000, MemoryRegionKind::Usable),
+                region(
+                    0x20_0000,
+                    KERNEL_SIZE,
+                    MemoryRegionKind::ExecutableAndModules
+                ),
+                region(0x24_0000, 0x7fcc_0000, MemoryRegionKind::Usable),
+                region(0x7ff0_0000, 0x10_0000, MemoryRegionKind::AcpiReclaimable),
+                region(0xfd00_0000, 0x30_0000, MemoryRegionKind::Framebuffer),
+                region(0xfee0_0000, 0x1000, MemoryRegionKind::Reserved),
+            ]
+        );
+
+        assert_eq!(
+            boot_info.framebuffer(),
+            Some(&FramebufferInfo {
+                addr: VirtAddr(HHDM_OFFSET + 0xfd00_0000),
+                width: 1024,
+                height: 768,
+                pitch: 4096,
+                bpp: 32,
+                red_mask: ColorMask { size: 8, shift: 16 },
+                green_mask: ColorMask { size: 8, shift: 8 },
+                blue_mask: ColorMask { size: 8, shift: 0 },
+            })
+        );
+        assert_eq!(boot_info.rsdp(), None);
+        assert_eq!(boot_info.args(), &KernelArgs::EMPTY);
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        let info = InfoBuilder::new()
+            .tag(
+                TAG_CMDLINE,
+                &[b"loglevel=debug label=\"test machine\" noapic\0"],
+            )
+            .build();
+
+        let mut boot_info = new_boot_info();
+        assert_eq!(parse(info, INFO_PHYS, KERNEL_SIZE, &mut boot_info), Ok(()));
+
+        let args = boot_info.args();
+        assert_eq!(
+            args.as_str(),
+            "loglevel=debug label=\"test machine\" noapic"
+        );
+        assert_eq!(args.get("loglevel"), Some("debug"));
+        assert_eq!(args.get("label"), Some("test machine"));
+        assert!(args.flag("noapic"));
+
+        // A command line that isn't null terminated or isn't UTF-8 is ignored, without failing
+        // the boot
+        for body in [&b"noapic"[..], b"label=\xff\xfe\0"] {
+            let info = InfoBuilder::new().tag(TAG_CMDLINE, &[body]).build();
+            let mut boot_info = new_boot_info();
+            assert_eq!(parse(info, INFO_PHYS, KERNEL_SIZE, &mut boot_info), Ok(()));
+            assert_eq!(boot_info.args(), &KernelArgs::EMPTY);
+        }
+    }
+
+    #[test]
+    fn test_parse_rsdp_and_skipped_framebuffer() {
+        // A text mode "framebuffer", and both RSDP tags
+        let info = InfoBuilder::new()
+            .framebuffer(2)
+            .tag(TAG_ACPI_NEW_RSDP, &[&[0; 36]])
+            .tag(TAG_ACPI_OLD_RSDP, &[&[0; 20]])
+            .build();
+
+        let mut boot_info = new_boot_info();
+        assert_eq!(parse(info, INFO_PHYS, KERNEL_SIZE, &mut boot_info), Ok(()));
+
+        assert_eq!(boot_info.framebuffer(), None);
+        // The new RSDP's tag follows the framebuffer tag (40 bytes with its padding), and its copy
+        // of the RSDP follows the tag's header
+        assert_eq!(
+            boot_info.rsdp(),
+            Some(INFO_PHYS + TAG_HEADER_SIZE + 40 + TAG_HEADER_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let rsdp_info = || {
+            InfoBuilder::new()
+                .tag(TAG_ACPI_OLD_RSDP, &[&[0; 20]])
+                .build()
+        };
+        let mut boot_info = new_boot_info();
+
+        let info = rsdp_info();
+        let truncated = info.len() - 1;
+        assert_eq!(
+            parse(&info[..truncated], INFO_PHYS, KERNEL_SIZE, &mut boot_info),
+            Err(Multiboot2Error::Truncated)
+        );
+
+        // The RSDP tag's size runs past the end of the structure
+        let info = rsdp_info();
+        info[12] = 0xff;
+        assert_eq!(
+            parse(info, INFO_PHYS, KERNEL_SIZE, &mut boot_info),
+            Err(Multiboot2Error::BadTag)
+        );
+    }
+
+    #[test]
+    fn test_parse_modules() {
+        let info = InfoBuilder::new()
+            .memory_map(&[(0x10_0000, 0x7fe0_0000, 1)])
+            .tag(
+                TAG_MODULE,
+                &[
+                    &0x30_0000_u32.to_le_bytes(),
+                    &0x30_1800_u32.to_le_bytes(),
+                    b"font\0",
+                ],
+            )
+            .tag(
+                TAG_MODULE,
+                &[
+                    &0x40_0000_u32.to_le_bytes(),
+                    &0x40_0200_u32.to_le_bytes(),
+                    b"\0",
+                ],
+            )
+            .build();
+
+        let mut boot_info = new_boot_info();
+        assert_eq!(parse(info, INFO_PHYS, KERNEL_SIZE, &mut boot_info), Ok(()));
+
+        assert_eq!(
+            boot_info.modules(),
+            &[
+                BootModule {
+                    addr: VirtAddr(HHDM_OFFSET + 0x30_0000),
+                    size: 0x1800,
+                    cmdline: "font",
+                },
+                BootModule {
+                    addr: VirtAddr(HHDM_OFFSET + 0x40_0000),
+                    size: 0x200,
+                    cmdline: "",
+                },
+            ]
+        );
+        assert!(boot_info.module("font").is_some());
+
+        // The modules are carved out of the usable memory, rounded out to whole pages
+        let region = |base, length, kind| MemoryRegion {
+            base: PhysAddr(base),
+            length,
+            kind,
+        };
+        assert_eq!(
+            boot_info.memory_map(),
+            &[
+                region(0x10_0000, 0x1000, MemoryRegionKind::BootloaderReclaimable),
+                region(0x10_1000, 0xf_f000, MemoryRegionKind::Usable),
+                region(
+                    0x20_0000,
+                    KERNEL_SIZE,
+                    MemoryRegionKind::ExecutableAndModules
+                ),
+                region(0x24_0000, 0xc_0000, MemoryRegionKind::Usable),
+                region(0x30_0000, 0x2000, MemoryRegionKind::ExecutableAndModules),
+                region(0x30_2000, 0xf_e000, MemoryRegionKind::Usable),
+                region(0x40_0000, 0x1000, MemoryRegionKind::ExecutableAndModules),
+                region(0x40_1000, 0x7faf_f000, MemoryRegionKind::Usable),
+            ]
+        );
+
+        // The module's command line has to be null terminated
+        let info = InfoBuilder::new()
+            .tag(
+                TAG_MODULE,
+                &[
+                    &0x30_0000_u32.to_le_bytes(),
+                    &0x30_1000_u32.to_le_bytes(),
+                    b"font",
+                ],
+            )
+            .build();
+        assert_eq!(
+            parse(info, INFO_PHYS, KERNEL_SIZE, &mut new_boot_info()),
+            Err(Multiboot2Error::BadTag)
+        );
+    }
+}
@@ -1,14 +1,12 @@
-#![no_std]
-#![no_main]
+// The parsers are unit tested on the host, so tests are built as a regular hosted binary
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(pointer_is_aligned_to)]
 // TODO: Remove this once the modular_bitfield errors are taken care of
 #![allow(dead_code)]
 // TODO: Remove this once you fix the `as` conversion warnings
 #![allow(clippy::cast_possible_truncation)]
 
-// TODO: Some boot sanity checks to make sure basic features that are expected are available on
-// this CPU.
-
 use core::arch::asm;
 use slab::heap::Heap;
 
@@ -16,7 +14,7 @@ mod acpi;
 mod boot;
 
 /// The global instance of the kernel heap allocator
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static HEAP: Heap = Heap::new();
 
 fn funderberker_start() -> ! {
@@ -36,9 +34,19 @@ fn hcf() -> ! {
     }
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 pub fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    // The panic might have been caused by running out of memory, and logging it might allocate
+    HEAP.enter_panic_mode();
+
+    // Paint the screen red, so a panic can't be missed among the rest of the log
+    #[cfg(feature = "framebuffer")]
+    logger::framebuffer::clear(logger::framebuffer::Color::RED);
     logger::err!("{}", info);
+    // Doesn't wait for a flush the panic interrupted (which would deadlock), but writes the lines
+    // out itself
+    logger::flush_on_panic();
 
     hcf();
 }
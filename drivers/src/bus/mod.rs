@@ -1 +1,2 @@
+pub mod pci_legacy;
 pub mod pcie;
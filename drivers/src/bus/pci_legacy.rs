@@ -0,0 +1,89 @@
+//! Legacy PCI configuration space access, through the `0xCF8` address port and the `0xCFC` data
+//! port (configuration mechanism #1).
+//!
+//! This is only used when there is no MCFG (and so no ECAM). It can only reach the first 256
+//! bytes of each function's configuration space.
+
+use kernel::arch::x86_64::cpu::{inb_32, outb_32};
+use utils::sync::spinlock::SpinLock;
+
+/// The port the address of the configuration register to access is written to
+const CONFIG_ADDRESS_PORT: u16 = 0xcf8;
+/// The port the configuration register selected by `CONFIG_ADDRESS_PORT` is accessed through
+const CONFIG_DATA_PORT: u16 = 0xcfc;
+
+/// The bit in the configuration address that enables the access
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+/// The size of the configuration space reachable through this mechanism
+pub const CONFIG_SPACE_SIZE: usize = 256;
+
+/// Selecting a register and accessing it are 2 separate port accesses, so they must not be
+/// interleaved with another access
+static CONFIG_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// Encode the configuration address of the register at `offset` in the configuration space of
+/// `bus`:`device`.`function`.
+///
+/// The register is 32 bits wide, so the low 2 bits of `offset` are ignored.
+#[inline]
+const fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    CONFIG_ENABLE
+        | ((bus as u32) << 16)
+        | (((device & 0x1f) as u32) << 11)
+        | (((function & 0x7) as u32) << 8)
+        | ((offset & 0xfc) as u32)
+}
+
+/// Read the 32 bit register at `offset` in the configuration space of `bus`:`device`.`function`
+///
+/// # Safety
+/// Reading some registers might have side effects on the device
+pub unsafe fn read(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let _guard = CONFIG_LOCK.lock();
+
+    unsafe {
+        outb_32(
+            CONFIG_ADDRESS_PORT,
+            config_address(bus, device, function, offset),
+        );
+        inb_32(CONFIG_DATA_PORT)
+    }
+}
+
+/// Write `value` to the 32 bit register at `offset` in the configuration space of
+/// `bus`:`device`.`function`
+///
+/// # Safety
+/// The caller must make sure the write doesn't break the device (or whatever is using it)
+pub unsafe fn write(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    let _guard = CONFIG_LOCK.lock();
+
+    unsafe {
+        outb_32(
+            CONFIG_ADDRESS_PORT,
+            config_address(bus, device, function, offset),
+        );
+        outb_32(CONFIG_DATA_PORT, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_address() {
+        assert_eq!(config_address(0, 0, 0, 0), 0x8000_0000);
+        assert_eq!(config_address(0, 0x1f, 0, 0x10), 0x8000_f810);
+        assert_eq!(config_address(0xff, 0x1f, 0x7, 0xfc), 0x80ff_fffc);
+        assert_eq!(config_address(0x3, 0x2, 0x1, 0x3c), 0x8003_113c);
+    }
+
+    #[test]
+    fn test_config_address_alignment() {
+        // The register is selected by its dword, so unaligned offsets select the containing one
+        assert_eq!(config_address(1, 2, 3, 0x0e), config_address(1, 2, 3, 0x0c));
+        assert_eq!(config_address(1, 2, 3, 0x0f), config_address(1, 2, 3, 0x0c));
+    }
+}
@@ -6,12 +6,14 @@ use kernel::{
 };
 
 // use crate::acpi::mcfg::ConfigSpace;
+use super::pci_legacy;
 use alloc::vec::Vec;
 use utils::{
     mem::{
         PhysAddr,
         mmio::{MmioArea, Offsetable},
     },
+    sanity_assert,
     sync::spinlock::{SpinLock, SpinLockable},
 };
 
@@ -148,23 +150,90 @@ pub struct ConfigSpace {
     _reserved: u32,
 }
 
+/// The mechanism used to reach the configuration spaces of the device functions
+#[derive(Debug, Clone, Copy)]
+enum ConfigMechanism {
+    /// Memory mapped (ECAM), with the configuration spaces of a segment group starting at `base`
+    Ecam { base: u64 },
+    /// The legacy `0xCF8`/`0xCFC` IO ports, used when there is no MCFG
+    Legacy,
+}
+
+/// How the configuration space of a specific device function is accessed
+enum ConfigSpaceAccess {
+    /// Through the mapped ECAM configuration space
+    Ecam(MmioArea<usize, usize, u32>),
+    /// Through the legacy IO ports. Only the first 256 bytes are reachable this way
+    Legacy { bus: u8, device: u8, function: u8 },
+}
+
+/// The identity of a device function, which drivers are matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcieDeviceInfo {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class_code: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+/// Errors a driver might encounter while probing a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeError {
+    /// The driver matched the device, but can't drive it
+    Unsupported,
+    /// The device couldn't be initialized
+    InitFailed,
+}
+
+/// A driver for `PCIe` device functions, which `PcieManager` hands the devices it matches to
+#[derive(Debug, Clone, Copy)]
+pub struct PciDriver {
+    /// The name of the driver, for logging
+    pub name: &'static str,
+    /// Returns true if the driver can drive the device
+    pub matches: fn(&PcieDeviceInfo) -> bool,
+    /// Initialize the device. Only called for devices `matches` returned true for
+    pub probe: fn(&PcieDevice) -> Result<(), ProbeError>,
+}
+
+/// Errors registering a driver might encounter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    /// A driver with the same name was already registered
+    AlreadyRegistered,
+}
+
 /// Represents a specific `PCIe` device + function.
 ///
 /// NOTE: This does not represent a `PCIe` device in the sense of a physical device, but rather in
 /// the sense of a "device function"
 pub struct PcieDevice {
-    config_space: MmioArea<usize, usize, u32>,
+    config_space: ConfigSpaceAccess,
 }
 
 /// A manager for all the `PCIe` devices in the system.
 pub struct PcieManager {
     devices: Vec<PcieDevice>,
+    /// The registered drivers, in the order they were registered
+    drivers: Vec<PciDriver>,
 }
 
 impl PcieManager {
+    /// Discover the devices and load their drivers.
+    ///
+    /// If `segment_groups` is empty (i.e. there is no MCFG), the legacy configuration mechanism is
+    /// used instead of ECAM.
     pub fn init(segment_groups: &[ConfigSpace]) -> Result<(), ()> {
         let mut manager = PCIE_MANAGER.lock();
-        manager.brute_force_discover(segment_groups);
+        if segment_groups.is_empty() {
+            logger::warn!(
+                "No ECAM segment groups, falling back to legacy PCI configuration access"
+            );
+            manager.brute_force_discover_legacy();
+        } else {
+            manager.brute_force_discover(segment_groups);
+        }
         manager.load_device_drivers();
 
         Ok(())
@@ -173,30 +242,38 @@ impl PcieManager {
     const fn new() -> Self {
         Self {
             devices: Vec::new(),
+            drivers: Vec::new(),
         }
     }
 
-    /// Discover all device functions under the given `bus`, `device`, and `segment_group`.
-    fn discover_device_functions(&mut self, bus: u8, device: u8, segment_group: &ConfigSpace) {
-        if let Some(config_space) = self.check_device(bus, device, 0, segment_group.base_address) {
-            self.devices.push(PcieDevice::new(config_space));
+    /// Register `driver`, so it's matched against the devices once they are discovered. Drivers
+    /// registered first take precedence.
+    ///
+    /// # Errors
+    /// Returns `RegistryError::AlreadyRegistered` if a driver with the same name was already
+    /// registered
+    pub fn register_driver(&mut self, driver: PciDriver) -> Result<(), RegistryError> {
+        if self.drivers.iter().any(|other| other.name == driver.name) {
+            return Err(RegistryError::AlreadyRegistered);
+        }
+        self.drivers.push(driver);
 
-            let header_type = unsafe {
-                self.devices
-                    .last()
-                    .unwrap()
-                    .config_space
-                    .read(StandardHeader::BistHeaderLatencyCache as usize)
-                    >> 16
-                    & 0xff
-            };
+        Ok(())
+    }
+
+    /// Discover all device functions under the given `bus` and `device`, reached through
+    /// `mechanism`.
+    fn discover_device_functions(&mut self, bus: u8, device: u8, mechanism: ConfigMechanism) {
+        if let Some(pcie_device) = self.check_device(bus, device, 0, mechanism) {
+            let header_type =
+                pcie_device.read_config(StandardHeader::BistHeaderLatencyCache as usize) >> 16
+                    & 0xff;
+            self.devices.push(pcie_device);
 
             if header_type & 0x80 != 0 {
                 for function in 1..=7 {
-                    if let Some(config_space) =
-                        self.check_device(bus, device, function, segment_group.base_address)
-                    {
-                        self.devices.push(PcieDevice::new(config_space));
+                    if let Some(pcie_device) = self.check_device(bus, device, function, mechanism) {
+                        self.devices.push(pcie_device);
                     } else {
                         continue;
                     }
@@ -211,37 +288,42 @@ impl PcieManager {
         bus: u8,
         device: u8,
         function: u8,
-        segment_group_base: u64,
-    ) -> Option<MmioArea<usize, usize, u32>> {
-        let config_space: MmioArea<usize, usize, u32> = {
-            let phys_addr = PcieDevice::get_base_address(bus, device, function, segment_group_base);
-            let ptr = unsafe {
-                X86_64::map_pages(
-                    phys_addr,
-                    1,
-                    Flags::new()
-                        .set_read_write(true)
-                        .set_pat(PatType::WriteThrough, PageSize::size_4kb()),
-                    PageSize::size_4kb(),
-                )
-                .unwrap()
-            };
+        mechanism: ConfigMechanism,
+    ) -> Option<PcieDevice> {
+        let config_space = match mechanism {
+            ConfigMechanism::Ecam { base } => {
+                let phys_addr = PcieDevice::get_base_address(bus, device, function, base);
+                // XXX: Set the flags to the correct ones
+                let ptr = unsafe {
+                    X86_64::map_pages(
+                        phys_addr,
+                        1,
+                        Flags::new()
+                            .set_read_write(true)
+                            .set_pat(PatType::WriteThrough, PageSize::size_4kb()),
+                        PageSize::size_4kb(),
+                    )
+                    .unwrap()
+                };
 
-            MmioArea::new(ptr.cast())
+                ConfigSpaceAccess::Ecam(MmioArea::new(ptr.cast()))
+            }
+            ConfigMechanism::Legacy => ConfigSpaceAccess::Legacy {
+                bus,
+                device,
+                function,
+            },
         };
+        // If the device isn't present, dropping it unmaps the config space
+        let pcie_device = PcieDevice::new(config_space);
 
         // Check if the device is present
-        let vendor_id =
-            unsafe { config_space.read(StandardHeader::DeviceVendorId as usize) & 0xffff };
+        let vendor_id = pcie_device.read_config(StandardHeader::DeviceVendorId as usize) & 0xffff;
         if vendor_id == VENDOR_ID_INVALID as u32 {
-            // XXX: Set the flags to the correct ones
-            unsafe {
-                X86_64::unmap_pages(config_space.base().into(), 1, PageSize::size_4kb()).unwrap()
-            };
             return None;
         }
 
-        Some(config_space)
+        Some(pcie_device)
     }
 
     /// Method 1 of discovering PCIe devices: brute force scan the entire `PCIe` space for each segment group
@@ -249,48 +331,190 @@ impl PcieManager {
         for segment_group in segment_groups.iter() {
             for bus in segment_group.start_bus_number..=segment_group.end_bus_number {
                 for device in 0..=31 {
-                    self.discover_device_functions(bus, device, segment_group);
+                    self.discover_device_functions(
+                        bus,
+                        device,
+                        ConfigMechanism::Ecam {
+                            base: segment_group.base_address,
+                        },
+                    );
                 }
             }
         }
     }
 
+    /// Brute force scan all of the buses using the legacy configuration mechanism
+    fn brute_force_discover_legacy(&mut self) {
+        for bus in 0..=u8::MAX {
+            for device in 0..=31 {
+                self.discover_device_functions(bus, device, ConfigMechanism::Legacy);
+            }
+        }
+    }
+
+    /// Hand each discovered device to the first registered driver that matches it
     pub fn load_device_drivers(&self) {
-        for device in self.devices.iter() {
-            let (class_code, subclass, prog_if) = unsafe {
-                (
-                    device
-                        .config_space
-                        .read(StandardHeader::ClassRevision as usize)
-                        >> 24,
-                    (device
-                        .config_space
-                        .read(StandardHeader::ClassRevision as usize)
-                        >> 16)
-                        & 0xff,
-                    (device
-                        .config_space
-                        .read(StandardHeader::ClassRevision as usize)
-                        >> 8)
-                        & 0xff,
-                )
+        for device in &self.devices {
+            let info = device.info();
+            let Some(driver) = self.drivers.iter().find(|driver| (driver.matches)(&info)) else {
+                continue;
             };
 
-            match (class_code, subclass, prog_if) {
-                (0x1, 0x8, 0x2) => {
-                    logger::info!("Found NVMe");
-                }
-                _ => (),
+            if let Err(err) = (driver.probe)(device) {
+                logger::warn!("PCIe: {} failed to probe {info:x?}: {err:?}", driver.name);
             }
         }
     }
 }
 
+/// Register `driver` with the system's `PcieManager`, so it's handed the devices it matches once
+/// `PcieManager::init` discovers them
+///
+/// # Errors
+/// Returns `RegistryError::AlreadyRegistered` if a driver with the same name was already registered
+pub fn register_driver(driver: PciDriver) -> Result<(), RegistryError> {
+    PCIE_MANAGER.lock().register_driver(driver)
+}
+
 impl PcieDevice {
-    fn new(config_space: MmioArea<usize, usize, u32>) -> Self {
+    /// Command register bit enabling responses to memory space accesses
+    const COMMAND_MEMORY_SPACE: u32 = 1 << 1;
+    /// Command register bit allowing the device to act as a bus master (i.e. do DMA)
+    const COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+    fn new(config_space: ConfigSpaceAccess) -> Self {
         Self { config_space }
     }
 
+    /// Read the IDs and class of the device
+    #[must_use]
+    pub fn info(&self) -> PcieDeviceInfo {
+        let ids = self.read_config(StandardHeader::DeviceVendorId as usize);
+        let class_revision = self.read_config(StandardHeader::ClassRevision as usize);
+
+        PcieDeviceInfo {
+            vendor_id: ids as u16,
+            device_id: (ids >> 16) as u16,
+            class_code: (class_revision >> 24) as u8,
+            subclass: (class_revision >> 16) as u8,
+            prog_if: (class_revision >> 8) as u8,
+        }
+    }
+
+    /// Read the 32 bit register at `offset` in the device's configuration space.
+    ///
+    /// When accessed through the legacy mechanism, registers past the first 256 bytes read as all
+    /// 1s, like registers of a function that isn't there.
+    #[must_use]
+    pub fn read_config(&self, offset: usize) -> u32 {
+        sanity_assert!(offset.is_multiple_of(size_of::<u32>()));
+
+        match self.config_space {
+            ConfigSpaceAccess::Ecam(ref config_space) => unsafe { config_space.read(offset) },
+            ConfigSpaceAccess::Legacy {
+                bus,
+                device,
+                function,
+            } => {
+                if offset >= pci_legacy::CONFIG_SPACE_SIZE {
+                    return u32::MAX;
+                }
+
+                unsafe { pci_legacy::read(bus, device, function, offset as u8) }
+            }
+        }
+    }
+
+    /// Write `value` to the 32 bit register at `offset` in the device's configuration space.
+    ///
+    /// When accessed through the legacy mechanism, writes past the first 256 bytes are dropped.
+    ///
+    /// # Safety
+    /// The caller must make sure the write doesn't break the device (or whatever is using it)
+    pub unsafe fn write_config(&self, offset: usize, value: u32) {
+        sanity_assert!(offset.is_multiple_of(size_of::<u32>()));
+
+        match self.config_space {
+            ConfigSpaceAccess::Ecam(ref config_space) => unsafe {
+                config_space.write(offset, value);
+            },
+            ConfigSpaceAccess::Legacy {
+                bus,
+                device,
+                function,
+            } => {
+                if offset >= pci_legacy::CONFIG_SPACE_SIZE {
+                    return;
+                }
+
+                unsafe { pci_legacy::write(bus, device, function, offset as u8, value) };
+            }
+        }
+    }
+
+    /// Get the physical address and size of the memory BAR at `index` (0 to 5).
+    ///
+    /// Returns `None` if the BAR is an I/O BAR, or isn't implemented.
+    #[must_use]
+    pub fn memory_bar(&self, index: usize) -> Option<(PhysAddr, usize)> {
+        sanity_assert!(index < 6);
+
+        let offset = StandardHeader::Bar0 as usize + index * size_of::<u32>();
+        let low = self.read_config(offset);
+        // I/O BARs are marked by bit 0
+        if low & 0x1 != 0 {
+            return None;
+        }
+        let is_64_bit = (low >> 1) & 0b11 == 0b10;
+
+        // The size is found by writing all 1s, and seeing which bits stick
+        let size_mask = unsafe {
+            self.write_config(offset, u32::MAX);
+            let size_low = self.read_config(offset);
+            self.write_config(offset, low);
+
+            let size_high = if is_64_bit {
+                let high = self.read_config(offset + size_of::<u32>());
+                self.write_config(offset + size_of::<u32>(), u32::MAX);
+                let size_high = self.read_config(offset + size_of::<u32>());
+                self.write_config(offset + size_of::<u32>(), high);
+
+                size_high
+            } else {
+                u32::MAX
+            };
+
+            (u64::from(size_high) << 32) | u64::from(size_low & !0xf)
+        };
+        if size_mask == u64::MAX << 32 {
+            return None;
+        }
+
+        let high = if is_64_bit {
+            self.read_config(offset + size_of::<u32>())
+        } else {
+            0
+        };
+        let addr = (u64::from(high) << 32) | u64::from(low & !0xf);
+
+        Some((
+            PhysAddr(addr as usize),
+            (!size_mask).wrapping_add(1) as usize,
+        ))
+    }
+
+    /// Enable memory space accesses and bus mastering (DMA) for the device
+    pub fn enable_bus_mastering(&self) {
+        let command = self.read_config(StandardHeader::StatusCommand as usize);
+        // NOTE: The upper half is the status register, whose bits are cleared by writing 1
+        unsafe {
+            self.write_config(
+                StandardHeader::StatusCommand as usize,
+                (command & 0xffff) | Self::COMMAND_MEMORY_SPACE | Self::COMMAND_BUS_MASTER,
+            );
+        }
+    }
+
     #[inline]
     const fn get_base_address(
         bus: u8,
@@ -309,10 +533,12 @@ impl PcieDevice {
 
 impl Drop for PcieDevice {
     fn drop(&mut self) {
-        unsafe {
-            X86_64::unmap_pages(self.config_space.base().into(), 1, PageSize::size_4kb())
-                .expect("Failed to unmap PCIe device config space");
-        };
+        if let ConfigSpaceAccess::Ecam(ref config_space) = self.config_space {
+            unsafe {
+                X86_64::unmap_pages(config_space.base().into(), 1, PageSize::size_4kb())
+                    .expect("Failed to unmap PCIe device config space");
+            };
+        }
     }
 }
 
@@ -338,3 +564,83 @@ impl SpinLockable for PcieManager {}
 
 // XXX: This might not actually be safe
 unsafe impl Sync for PcieDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+
+    /// The amount of times the test driver probed a device
+    static PROBES: AtomicUsize = AtomicUsize::new(0);
+    /// The vendor ID of the last device the test driver probed
+    static PROBED_VENDOR: AtomicU16 = AtomicU16::new(0);
+
+    fn never_probed(_device: &PcieDevice) -> Result<(), ProbeError> {
+        panic!("Only the first matching driver should be probed");
+    }
+
+    /// A device function whose configuration space is `config_space`, instead of a mapped one
+    fn synthetic_device(config_space: &mut [u32], ids: u32, class_revision: u32) -> PcieDevice {
+        config_space[StandardHeader::DeviceVendorId as usize / size_of::<u32>()] = ids;
+        config_space[StandardHeader::ClassRevision as usize / size_of::<u32>()] = class_revision;
+
+        PcieDevice::new(ConfigSpaceAccess::Ecam(MmioArea::new(
+            config_space.as_mut_ptr(),
+        )))
+    }
+
+    #[test]
+    fn test_driver_matching() {
+        let mut matching = vec![0; 1024];
+        let mut other = vec![0; 1024];
+
+        let mut manager = PcieManager::new();
+        manager
+            .devices
+            .push(synthetic_device(&mut other, 0x1111_8086, 0x0c03_3000));
+        manager
+            .devices
+            .push(synthetic_device(&mut matching, 0xbeef_1234, 0x0108_0200));
+        assert_eq!(
+            manager.devices[1].info(),
+            PcieDeviceInfo {
+                vendor_id: 0x1234,
+                device_id: 0xbeef,
+                class_code: 0x1,
+                subclass: 0x8,
+                prog_if: 0x2,
+            }
+        );
+
+        manager
+            .register_driver(PciDriver {
+                name: "test",
+                matches: |info| info.vendor_id == 0x1234,
+                probe: |device| {
+                    PROBES.fetch_add(1, Ordering::Relaxed);
+                    PROBED_VENDOR.store(device.info().vendor_id, Ordering::Relaxed);
+                    Ok(())
+                },
+            })
+            .unwrap();
+        let shadowed = PciDriver {
+            name: "shadowed",
+            matches: |info| info.class_code == 0x1,
+            probe: never_probed,
+        };
+        manager.register_driver(shadowed).unwrap();
+        // Every driver is only registered once
+        assert_eq!(
+            manager.register_driver(shadowed),
+            Err(RegistryError::AlreadyRegistered)
+        );
+        manager.load_device_drivers();
+
+        assert_eq!(PROBES.load(Ordering::Relaxed), 1);
+        assert_eq!(PROBED_VENDOR.load(Ordering::Relaxed), 0x1234);
+
+        // The configuration spaces aren't mapped, so they mustn't be unmapped
+        core::mem::forget(manager);
+    }
+}
@@ -0,0 +1,164 @@
+//! Calibrated busy-waits, for the short delays device init sequences need (e.g. holding a
+//! controller in reset for a few microseconds).
+//!
+//! The delays poll the best monotonic clock there is, so they work with interrupts disabled, and
+//! never return early. They aren't meant for long waits, since the core does nothing else in the
+//! meantime.
+
+use core::{arch::x86_64::__cpuid, hint, time::Duration};
+
+use utils::{
+    sync::spinlock::{SpinLock, SpinLockable},
+    time,
+};
+
+use super::{MonotonicClock, pm_timer::PM_TIMER, tsc::Tsc};
+use crate::timer::hpet::HPET;
+
+/// A lower bound on how long a `cpuid` takes, in nanoseconds. It's serializing, so it takes at
+/// least a few dozen cycles even on the fastest cores, and far more under a hypervisor
+const MIN_CPUID_NS: u64 = 10;
+
+/// Busy-wait for at least `ns` nanoseconds
+pub fn delay_ns(ns: u64) {
+    delay(Duration::from_nanos(ns));
+}
+
+/// Busy-wait for at least `us` microseconds
+pub fn delay_us(us: u64) {
+    delay(Duration::from_micros(us));
+}
+
+/// Busy-wait for at least `time`, on the TSC if it's calibrated, otherwise on the HPET or the PM
+/// timer, and with a `cpuid` loop if there is no clock to use yet
+fn delay(time: Duration) {
+    if let Some(mut tsc) = Tsc::get() {
+        return delay_on(&mut tsc, time);
+    }
+
+    if HPET.lock().is_available() {
+        return delay_on(&mut Shared(&HPET), time);
+    }
+
+    if PM_TIMER.lock().is_available() {
+        return delay_on(&mut Shared(&PM_TIMER), time);
+    }
+
+    delay_uncalibrated(time);
+}
+
+/// A clock other cores might be using as well, which is only locked for each read, so they can
+/// keep using it while we spin.
+///
+/// The clocks extend their counters to 64 bits themselves (e.g. 32 bit HPETs), so the tick count
+/// never wraps around mid-delay, whoever else reads it in the meantime
+struct Shared<'a, C: SpinLockable>(&'a SpinLock<C>);
+
+impl<C: MonotonicClock + SpinLockable> MonotonicClock for Shared<'_, C> {
+    fn frequency(&self) -> u64 {
+        self.0.lock().frequency()
+    }
+
+    fn ticks(&mut self) -> u64 {
+        self.0.lock().ticks()
+    }
+}
+
+/// Busy-wait on `clock` until at least `time` passed
+fn delay_on(clock: &mut impl MonotonicClock, time: Duration) {
+    if time.is_zero() {
+        return;
+    }
+
+    let hz = clock.frequency();
+    let mut target = time::duration_to_ticks(time, hz);
+    if time::ticks_to_duration(target, hz) < time {
+        target += 1;
+    }
+    // The clock might tick right after we read where it started, so that tick doesn't count
+    let target = target.saturating_add(1);
+
+    let start = clock.ticks();
+    while clock.ticks().wrapping_sub(start) < target {
+        hint::spin_loop();
+    }
+}
+
+/// Busy-wait for at least `time` without any clock, by running enough `cpuid`s
+fn delay_uncalibrated(time: Duration) {
+    let iterations = time.as_nanos().div_ceil(u128::from(MIN_CPUID_NS));
+    for _ in 0..iterations {
+        hint::black_box(__cpuid(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1MHz clock that moves `step` ticks per read
+    struct FakeClock {
+        now: u64,
+        step: u64,
+    }
+
+    impl SpinLockable for FakeClock {}
+
+    impl MonotonicClock for FakeClock {
+        fn frequency(&self) -> u64 {
+            1_000_000
+        }
+
+        fn ticks(&mut self) -> u64 {
+            self.now = self.now.wrapping_add(self.step);
+            self.now
+        }
+    }
+
+    #[test]
+    fn test_delay_waits_for_the_interval() {
+        let mut clock = FakeClock { now: 0, step: 3 };
+        delay_on(&mut clock, Duration::from_micros(100));
+
+        // 100 ticks, plus the one that might have been partially over when we started. The last
+        // read overshoots by less than a step
+        assert!(clock.now >= 3 + 101);
+        assert!(clock.now < 3 + 101 + 3);
+
+        // Across the wraparound of the counter
+        let mut clock = FakeClock {
+            now: u64::MAX - 10,
+            step: 1,
+        };
+        delay_on(&mut clock, Duration::from_micros(20));
+        assert_eq!(clock.now, 11);
+    }
+
+    #[test]
+    fn test_shared_clock_is_locked_per_read() {
+        static CLOCK: SpinLock<FakeClock> = SpinLock::new(FakeClock { now: 0, step: 1 });
+
+        delay_on(&mut Shared(&CLOCK), Duration::from_micros(10));
+
+        // Left unlocked, and read until the whole interval passed
+        let clock = CLOCK.try_lock().expect("The clock was left locked");
+        assert_eq!(clock.now, 1 + 11);
+    }
+
+    #[test]
+    fn test_delay_rounds_up() {
+        // Less than a tick still waits for a whole one
+        let mut clock = FakeClock { now: 0, step: 1 };
+        delay_on(&mut clock, Duration::from_nanos(1));
+        assert_eq!(clock.now, 1 + 2);
+
+        let mut clock = FakeClock { now: 0, step: 1 };
+        delay_on(&mut clock, Duration::from_nanos(1_500));
+        assert_eq!(clock.now, 1 + 3);
+
+        // Nothing to wait for
+        let mut clock = FakeClock { now: 0, step: 1 };
+        delay_on(&mut clock, Duration::ZERO);
+        assert_eq!(clock.now, 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! Points in time read off a clock, for measuring how much time passed between them.
+
+use core::{ops::Sub, time::Duration};
+
+use utils::{sanity_assert, time};
+
+use super::{MonotonicClock, pm_timer::PM_TIMER, tsc::Tsc};
+
+/// A point in time, as read off some clock.
+///
+/// Instants are only comparable with instants read off the same clock. The counter is allowed to
+/// wrap around (e.g. the 24 bit PM timer counter, or the 32 bit APIC timer count), as long as it
+/// doesn't wrap around more than once between the two instants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instant {
+    /// The value of the clock's counter
+    counter: u64,
+    /// The mask of the counter's valid bits
+    mask: u64,
+    /// The frequency the counter ticks at, in Hz
+    frequency: u64,
+}
+
+impl Instant {
+    /// Create an instant from the raw value of a counter ticking at `frequency` Hz, whose valid
+    /// bits are `mask`.
+    ///
+    /// NOTE: The counter must count up. For counters that count down (e.g. the APIC timer's),
+    /// pass the complement of the count instead.
+    #[inline]
+    #[must_use]
+    pub const fn from_counter(counter: u64, mask: u64, frequency: u64) -> Self {
+        Self {
+            counter: counter & mask,
+            mask,
+            frequency,
+        }
+    }
+
+    /// Get the current instant according to the system's monotonic clock, which is the TSC once
+    /// it's calibrated, and the PM timer otherwise.
+    ///
+    /// NOTE: Instants taken before the TSC was calibrated can't be compared with ones taken after
+    #[must_use]
+    pub fn now() -> Self {
+        if let Some(mut tsc) = Tsc::get() {
+            Self::now_on(&mut tsc)
+        } else {
+            Self::now_on(&mut *PM_TIMER.lock())
+        }
+    }
+
+    /// Get the current instant according to `clock`
+    #[must_use]
+    pub fn now_on(clock: &mut impl MonotonicClock) -> Self {
+        Self::from_counter(clock.ticks(), u64::MAX, clock.frequency())
+    }
+
+    /// Get the time that passed since this instant, according to the system's monotonic clock
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        Self::now() - *self
+    }
+
+    /// Get the time that passed from `earlier` to this instant.
+    ///
+    /// # Panics
+    /// On debug builds, panics if the instants weren't read off the same clock
+    #[must_use]
+    pub fn duration_since(&self, earlier: Self) -> Duration {
+        sanity_assert!(
+            self.mask == earlier.mask && self.frequency == earlier.frequency,
+            "Instants read off different clocks"
+        );
+
+        let ticks = self.counter.wrapping_sub(earlier.counter) & self.mask;
+
+        time::ticks_to_duration(ticks, self.frequency)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    #[inline]
+    fn sub(self, earlier: Self) -> Self::Output {
+        self.duration_since(earlier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::pm_timer::{CounterWidth, FREQUENCY};
+
+    #[test]
+    fn test_sub_across_wraparound() {
+        let pm_timer = |counter| {
+            Instant::from_counter(counter, u64::from(CounterWidth::Bits24.mask()), FREQUENCY)
+        };
+
+        // 0x20 ticks, half of them before the 24 bit counter wrapped
+        assert_eq!(
+            pm_timer(0x10) - pm_timer(0x00ff_fff0),
+            time::ticks_to_duration(0x20, FREQUENCY)
+        );
+        // Exactly one full wraparound of the counter is indistinguishable from no time passing
+        assert_eq!(pm_timer(0x0100_0010) - pm_timer(0x10), Duration::ZERO);
+
+        // The APIC timer's 32 bit count at 1MHz, counting down from 5 and wrapping around past 0
+        let apic_timer =
+            |count: u32| Instant::from_counter(u64::from(!count), u64::from(u32::MAX), 1_000_000);
+        assert_eq!(
+            apic_timer(0xffff_fffb) - apic_timer(5),
+            Duration::from_micros(10)
+        );
+    }
+
+    #[test]
+    fn test_now_on_clock() {
+        struct FakeClock(u64);
+        impl MonotonicClock for FakeClock {
+            fn frequency(&self) -> u64 {
+                1_000
+            }
+
+            fn ticks(&mut self) -> u64 {
+                self.0
+            }
+        }
+
+        let mut clock = FakeClock(1_000);
+        let start = Instant::now_on(&mut clock);
+        clock.0 += 1_500;
+
+        assert_eq!(
+            Instant::now_on(&mut clock) - start,
+            Duration::from_millis(1_500)
+        );
+    }
+}
@@ -1,4 +1,30 @@
 //! This module contains implementations of drivers for various hardware clocks.
 
+use core::time::Duration;
+
+use utils::time;
+
+pub use delay::{delay_ns, delay_us};
+pub use instant::Instant;
+pub use tsc::tsc_hz;
+
+pub mod delay;
+pub mod instant;
+pub mod pm_timer;
+pub mod tsc;
 // #[cfg(all(target_arch = "x86_64", feature = "legacy_timers"))]
 // pub mod rtc;
+
+/// A clock whose tick count only ever moves forward, used for measuring elapsed time
+pub trait MonotonicClock {
+    /// The frequency of the clock, in Hz
+    fn frequency(&self) -> u64;
+
+    /// Get the amount of ticks since some fixed point in the past
+    fn ticks(&mut self) -> u64;
+
+    /// Get the time since the same fixed point `ticks` counts from
+    fn now(&mut self) -> Duration {
+        time::ticks_to_duration(self.ticks(), self.frequency())
+    }
+}
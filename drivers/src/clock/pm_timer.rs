@@ -0,0 +1,214 @@
+//! Driver for the ACPI PM timer.
+//!
+//! The PM timer is a free running counter ticking at a fixed 3.579545 MHz, which every ACPI
+//! compliant system has. It can't generate interrupts, but it's a reliable reference for
+//! calibrating the other timers when there is no HPET, and a fallback `MonotonicClock`.
+
+use core::time::Duration;
+
+use kernel::arch::x86_64::cpu::inb_32;
+use utils::{
+    sync::spinlock::{SpinLock, SpinLockable},
+    time,
+};
+
+use super::MonotonicClock;
+
+/// The frequency of the PM timer, in Hz
+pub const FREQUENCY: u64 = 3_579_545;
+
+/// How the PM timer's counter register is accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmTimerAccess {
+    /// Through an I/O port
+    Port(u16),
+    /// Through a mapped MMIO register
+    Mmio(*const u32),
+}
+
+/// The width of the PM timer's counter, as reported by the FADT's `TMR_VAL_EXT` flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterWidth {
+    Bits24,
+    Bits32,
+}
+
+impl CounterWidth {
+    /// Get the mask of the counter's valid bits
+    #[inline]
+    #[must_use]
+    pub const fn mask(self) -> u32 {
+        match self {
+            Self::Bits24 => 0x00ff_ffff,
+            Self::Bits32 => u32::MAX,
+        }
+    }
+}
+
+/// The system's PM timer
+pub struct PmTimer {
+    /// How to read the counter, or `None` if the system has no PM timer (or it wasn't found yet)
+    access: Option<PmTimerAccess>,
+    width: CounterWidth,
+    /// The counter's value when it was last read
+    last: u32,
+    /// The amount of ticks since the timer was initialized, which doesn't wrap around like the
+    /// counter does
+    ticks: u64,
+}
+
+/// The global PM timer instance
+pub static PM_TIMER: SpinLock<PmTimer> = SpinLock::new(PmTimer {
+    access: None,
+    width: CounterWidth::Bits24,
+    last: 0,
+    ticks: 0,
+});
+
+unsafe impl Send for PmTimer {}
+unsafe impl Sync for PmTimer {}
+
+impl SpinLockable for PmTimer {}
+
+/// Get the amount of ticks from counter value `start` to counter value `end`, for a counter of
+/// `width` bits. The counter must not have wrapped around more than once in between.
+#[inline]
+#[must_use]
+pub const fn elapsed_ticks(start: u32, end: u32, width: CounterWidth) -> u32 {
+    end.wrapping_sub(start) & width.mask()
+}
+
+impl PmTimer {
+    /// Initialize the PM timer, reading its counter using `access`.
+    ///
+    /// # Safety
+    /// `access` must point to the PM timer's counter register, as reported by the FADT
+    pub unsafe fn init(&mut self, access: PmTimerAccess, width: CounterWidth) {
+        self.access = Some(access);
+        self.width = width;
+        self.last = self.read_counter().unwrap_or(0);
+        self.ticks = 0;
+    }
+
+    /// Returns true if the system has a PM timer, and it was initialized
+    #[inline]
+    #[must_use]
+    pub const fn is_available(&self) -> bool {
+        self.access.is_some()
+    }
+
+    /// Read the raw value of the counter, or `None` if there is no PM timer
+    #[must_use]
+    pub fn read_counter(&self) -> Option<u32> {
+        let raw = match self.access? {
+            PmTimerAccess::Port(port) => unsafe { inb_32(port) },
+            PmTimerAccess::Mmio(ptr) => unsafe { ptr.read_volatile() },
+        };
+
+        Some(raw & self.width.mask())
+    }
+
+    /// Spin for `time`, or return false right away if there is no PM timer.
+    ///
+    /// Used as a reference for calibrating other timers.
+    #[must_use]
+    pub fn busy_wait(&self, time: Duration) -> bool {
+        let Some(start) = self.read_counter() else {
+            return false;
+        };
+
+        // The elapsed ticks are summed up read by read, so waits longer than a wraparound of the
+        // counter (~4.7s for 24 bit counters) work too
+        let mut remaining = time::duration_to_ticks(time, FREQUENCY);
+        let mut last = start;
+        while remaining > 0 {
+            core::hint::spin_loop();
+
+            // The timer was available above, so this never falls back
+            let now = self.read_counter().unwrap_or(last);
+            remaining = remaining.saturating_sub(u64::from(elapsed_ticks(last, now, self.width)));
+            last = now;
+        }
+
+        true
+    }
+}
+
+impl MonotonicClock for PmTimer {
+    #[inline]
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    /// Get the amount of ticks since the timer was initialized.
+    ///
+    /// NOTE: This must be called at least once per counter wraparound (~4.7s for 24 bit counters,
+    /// ~20m for 32 bit ones), or the wraparounds in between are missed.
+    fn ticks(&mut self) -> u64 {
+        let Some(now) = self.read_counter() else {
+            return 0;
+        };
+
+        self.ticks += u64::from(elapsed_ticks(self.last, now, self.width));
+        self.last = now;
+
+        self.ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_elapsed_ticks_wraparound() {
+        // No wraparound
+        assert_eq!(elapsed_ticks(100, 250, CounterWidth::Bits24), 150);
+        assert_eq!(elapsed_ticks(100, 250, CounterWidth::Bits32), 150);
+
+        // A 24 bit counter wraps at 2^24, so the bits above it don't count
+        assert_eq!(elapsed_ticks(0x00ff_fff0, 0x10, CounterWidth::Bits24), 0x20);
+        assert_eq!(
+            elapsed_ticks(0x00ff_fff0, 0x00ff_ffff, CounterWidth::Bits24),
+            0xf
+        );
+        assert_eq!(elapsed_ticks(0x00ff_ffff, 0, CounterWidth::Bits24), 1);
+
+        // While a 32 bit counter keeps going past it
+        assert_eq!(
+            elapsed_ticks(0x00ff_fff0, 0x0100_0010, CounterWidth::Bits32),
+            0x20
+        );
+        assert_eq!(elapsed_ticks(0xffff_fff0, 0x10, CounterWidth::Bits32), 0x20);
+    }
+
+    #[test]
+    fn test_ticks_extend_past_the_counter() {
+        // A fake counter register, read through "MMIO"
+        let counter = Cell::new(0x00ff_ff00_u32);
+        let mut timer = PmTimer {
+            access: None,
+            width: CounterWidth::Bits24,
+            last: 0,
+            ticks: 0,
+        };
+        assert_eq!(timer.ticks(), 0);
+        assert!(!timer.busy_wait(Duration::from_secs(1)));
+
+        unsafe {
+            timer.init(PmTimerAccess::Mmio(counter.as_ptr()), CounterWidth::Bits24);
+        };
+        assert!(timer.is_available());
+
+        // Wrap the counter around a few times, reading it once per lap
+        for lap in 1..=3_u64 {
+            counter.set((counter.get() + 0x0080_0000) & 0x00ff_ffff);
+            assert_eq!(timer.ticks(), lap * 0x0080_0000);
+        }
+        assert_eq!(
+            timer.now(),
+            time::ticks_to_duration(3 * 0x0080_0000, FREQUENCY)
+        );
+    }
+}
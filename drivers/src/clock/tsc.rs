@@ -0,0 +1,187 @@
+//! The time stamp counter as a clock.
+//!
+//! The TSC is by far the cheapest clock to read (a single instruction, no MMIO or port I/O), but
+//! its frequency isn't reported anywhere reliable, so it's measured against the HPET or the PM
+//! timer at boot. It's only used on CPUs with an invariant TSC, since otherwise it speeds up and
+//! slows down with the P-state of the core, and stops in deep C-states.
+
+use core::{hint, time::Duration};
+
+use kernel::arch::x86_64::cpu::{features::cpu_features, read_tsc_ordered};
+use utils::{sync::once::Once, time};
+
+use super::{MonotonicClock, pm_timer::PM_TIMER};
+use crate::timer::hpet::HPET;
+
+/// How long to measure the TSC against the reference clock for. Longer is more accurate, but
+/// delays the boot
+const CALIBRATION_TIME: Duration = Duration::from_millis(50);
+
+/// The frequency of the TSC in Hz, once it's calibrated
+static FREQUENCY: Once<u64> = Once::new();
+
+/// The calibrated TSC
+#[derive(Debug, Clone, Copy)]
+pub struct Tsc {
+    frequency: u64,
+}
+
+impl Tsc {
+    /// Get the TSC, or `None` if it wasn't calibrated (or can't be used as a clock)
+    #[inline]
+    #[must_use]
+    pub fn get() -> Option<Self> {
+        FREQUENCY.get().map(|&frequency| Self { frequency })
+    }
+}
+
+impl MonotonicClock for Tsc {
+    #[inline]
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    #[inline]
+    fn ticks(&mut self) -> u64 {
+        read_tsc_ordered()
+    }
+}
+
+/// Get the frequency of a counter that moved `delta` ticks while a reference clock running at
+/// `reference_hz` moved `reference_ticks` ticks
+#[must_use]
+pub const fn frequency_from(delta: u64, reference_ticks: u64, reference_hz: u64) -> u64 {
+    if reference_ticks == 0 {
+        return 0;
+    }
+
+    // NOTE: The product of two `u64`s always fits in a `u128`
+    let hz = (delta as u128 * reference_hz as u128) / reference_ticks as u128;
+    if hz > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        hz as u64
+    }
+}
+
+/// Measure the frequency of the counter read by `read_counter` against `reference`, for about
+/// `time`.
+///
+/// The reference is read right before and right after the counter on both ends, so the measured
+/// interval is the one that actually passed, no matter how long the reads take.
+fn measure(
+    reference: &mut impl MonotonicClock,
+    mut read_counter: impl FnMut() -> u64,
+    time: Duration,
+) -> u64 {
+    let target = time::duration_to_ticks(time, reference.frequency());
+
+    let reference_start = reference.ticks();
+    let counter_start = read_counter();
+
+    let mut reference_end = reference.ticks();
+    while reference_end.wrapping_sub(reference_start) < target {
+        hint::spin_loop();
+        reference_end = reference.ticks();
+    }
+    let counter_end = read_counter();
+
+    frequency_from(
+        counter_end.wrapping_sub(counter_start),
+        reference_end.wrapping_sub(reference_start),
+        reference.frequency(),
+    )
+}
+
+/// Measure the TSC's frequency against the HPET (or the PM timer if there is no HPET), and use it
+/// as a clock from then on. Returns the frequency in Hz, or `None` if the TSC can't be used as a
+/// clock, either because it isn't invariant or because there is no reference to measure it with.
+///
+/// NOTE: This should be called once, after the ACPI tables were parsed
+pub fn calibrate() -> Option<u64> {
+    if let Some(&frequency) = FREQUENCY.get() {
+        return Some(frequency);
+    }
+
+    if !cpu_features().invariant_tsc {
+        logger::warn!("The TSC isn't invariant, not using it as a clock");
+        return None;
+    }
+
+    let frequency = {
+        let mut hpet = HPET.lock();
+        if hpet.is_available() {
+            measure(&mut *hpet, read_tsc_ordered, CALIBRATION_TIME)
+        } else {
+            drop(hpet);
+
+            let mut pm_timer = PM_TIMER.lock();
+            if !pm_timer.is_available() {
+                logger::warn!("No HPET or PM timer to calibrate the TSC with");
+                return None;
+            }
+            measure(&mut *pm_timer, read_tsc_ordered, CALIBRATION_TIME)
+        }
+    };
+
+    logger::info!("TSC frequency: {} MHz", frequency / 1_000_000);
+
+    Some(*FREQUENCY.set(frequency).unwrap_or(&frequency))
+}
+
+/// Get the frequency of the TSC in Hz, or `None` if it isn't used as a clock
+#[inline]
+#[must_use]
+pub fn tsc_hz() -> Option<u64> {
+    FREQUENCY.get().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::pm_timer::FREQUENCY as PM_TIMER_FREQUENCY;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_frequency_from() {
+        // 100ms of the PM timer (rounded down to a whole tick), during which a 3GHz counter
+        // ticked 300M times
+        assert_eq!(
+            frequency_from(300_000_000, PM_TIMER_FREQUENCY / 10, PM_TIMER_FREQUENCY),
+            3_000_004_190
+        );
+        // Round numbers come out exact
+        assert_eq!(frequency_from(2_500, 1_000, 1_000_000), 2_500_000);
+        // A reference that didn't move can't tell anything
+        assert_eq!(frequency_from(1_000, 0, 1_000_000), 0);
+        assert_eq!(frequency_from(u64::MAX, 1, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_measure_against_reference() {
+        // A 1MHz reference clock that moves 7 ticks per read, while the counter moves 2400 ticks
+        // for every reference tick (so it's a 2.4GHz counter)
+        struct FakeClock<'a>(&'a Cell<u64>);
+        impl MonotonicClock for FakeClock<'_> {
+            fn frequency(&self) -> u64 {
+                1_000_000
+            }
+
+            fn ticks(&mut self) -> u64 {
+                self.0.set(self.0.get().wrapping_add(7));
+                self.0.get()
+            }
+        }
+
+        let reference = Cell::new(u64::MAX - 100);
+        let frequency = measure(
+            &mut FakeClock(&reference),
+            || reference.get().wrapping_mul(2_400),
+            Duration::from_millis(1),
+        );
+        assert_eq!(frequency, 2_400_000_000);
+
+        // The measurement ran for (at least) the requested time, even across the wraparound
+        assert!(reference.get() >= 1_000 - 100);
+    }
+}
@@ -1,7 +1,7 @@
 //! Handling of CMOS data
 
-use crate::arch::x86_64::{
-    cpu::{inb_8, outb_8},
+use kernel::arch::x86_64::{
+    cpu::{inb_8, io_wait, outb_8},
     interrupts,
 };
 
@@ -59,9 +59,11 @@ enum CmosPort {
 }
 
 /// Read a byte from the CMOS
+#[must_use]
 pub fn read_cmos(index: CmosIndex, nmi_status: NmiStatus) -> u8 {
-    interrupts::do_inside_interrupts_disabled_window(|| unsafe {
+    interrupts::without_interrupts(|| unsafe {
         outb_8(CmosPort::Read as u16, index as u8 | nmi_status as u8);
+        io_wait();
 
         inb_8(CmosPort::Write as u16)
     })
@@ -69,9 +71,10 @@ pub fn read_cmos(index: CmosIndex, nmi_status: NmiStatus) -> u8 {
 
 /// Write a byte to the CMOS
 pub fn write_cmos(index: CmosIndex, value: u8, nmi_status: NmiStatus) {
-    interrupts::do_inside_interrupts_disabled_window(|| unsafe {
+    interrupts::without_interrupts(|| unsafe {
         outb_8(CmosPort::Read as u16, index as u8 | nmi_status as u8);
+        io_wait();
 
         outb_8(CmosPort::Write as u16, value);
-    })
+    });
 }
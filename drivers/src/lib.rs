@@ -9,5 +9,8 @@ extern crate alloc;
 
 pub mod bus;
 pub mod clock;
+#[cfg(target_arch = "x86_64")]
+pub mod cmos;
 pub mod storage;
 pub mod timer;
+pub mod usb;
@@ -0,0 +1,213 @@
+//! Byte granular access to a `BlockDevice`, for code that doesn't think in blocks (e.g. partition
+//! tables and filesystems)
+
+use alloc::{boxed::Box, vec};
+
+use super::{BlockDevice, StorageError};
+
+/// Wraps a `BlockDevice`, allowing reads and writes at any byte offset and of any length.
+///
+/// The blocks that are only partly covered by an access go through a scratch block (and writes to
+/// them are read-modify-write), while the whole blocks in between are read or written in one
+/// request, straight from the caller's buffer.
+pub struct ByteAccess<D: BlockDevice> {
+    device: D,
+    /// Holds the partly accessed blocks
+    scratch: Box<[u8]>,
+}
+
+impl<D: BlockDevice> ByteAccess<D> {
+    /// Wrap `device`
+    #[must_use]
+    pub fn new(device: D) -> Self {
+        let scratch = vec![0; device.block_size()].into_boxed_slice();
+
+        Self { device, scratch }
+    }
+
+    /// Get the underlying device
+    #[must_use]
+    pub const fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Get the underlying device back
+    #[must_use]
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    /// The size of the device in bytes
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.device.block_count() * self.device.block_size() as u64
+    }
+
+    /// Returns true if the device has no blocks
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read `buffer.len()` bytes starting from byte `offset` into `buffer`.
+    ///
+    /// # Errors
+    /// Fails if the range reaches past the end of the device, or if the device fails
+    pub fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.check_range(offset, buffer.len())?;
+
+        let block_size = self.device.block_size();
+        let mut done = 0;
+        while done < buffer.len() {
+            let (lba, in_block) = self.locate(offset + done as u64);
+            let remaining = buffer.len() - done;
+
+            if in_block == 0 && remaining >= block_size {
+                let whole = remaining - remaining % block_size;
+                self.device
+                    .read_blocks(lba, &mut buffer[done..done + whole])?;
+                done += whole;
+            } else {
+                let len = remaining.min(block_size - in_block);
+                self.device.read_blocks(lba, &mut self.scratch)?;
+                buffer[done..done + len].copy_from_slice(&self.scratch[in_block..in_block + len]);
+                done += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write `buffer` to the device starting from byte `offset`, leaving the rest of the blocks
+    /// it partly covers as they were.
+    ///
+    /// # Errors
+    /// Fails if the range reaches past the end of the device, or if the device fails. In the
+    /// latter case, the blocks before the failing one were already written
+    pub fn write_at(&mut self, offset: u64, buffer: &[u8]) -> Result<(), StorageError> {
+        self.check_range(offset, buffer.len())?;
+
+        let block_size = self.device.block_size();
+        let mut done = 0;
+        while done < buffer.len() {
+            let (lba, in_block) = self.locate(offset + done as u64);
+            let remaining = buffer.len() - done;
+
+            if in_block == 0 && remaining >= block_size {
+                let whole = remaining - remaining % block_size;
+                self.device.write_blocks(lba, &buffer[done..done + whole])?;
+                done += whole;
+            } else {
+                let len = remaining.min(block_size - in_block);
+                self.device.read_blocks(lba, &mut self.scratch)?;
+                self.scratch[in_block..in_block + len].copy_from_slice(&buffer[done..done + len]);
+                self.device.write_blocks(lba, &self.scratch)?;
+                done += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the block byte `offset` is in, and where in the block it is
+    fn locate(&self, offset: u64) -> (u64, usize) {
+        let block_size = self.device.block_size() as u64;
+
+        (offset / block_size, (offset % block_size) as usize)
+    }
+
+    /// Make sure the `len` bytes starting from `offset` are all on the device
+    fn check_range(&self, offset: u64, len: usize) -> Result<(), StorageError> {
+        offset
+            .checked_add(len as u64)
+            .filter(|&end| end <= self.len())
+            .map(|_| ())
+            .ok_or(StorageError::OutOfRange)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ram_disk::RamDisk, tests::BLOCK_SIZE};
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A disk of `blocks` blocks, with each byte set to its offset (modulo a prime, so the blocks
+    /// differ from each other)
+    fn patterned_disk(blocks: usize) -> (RamDisk, Vec<u8>) {
+        let data: Vec<u8> = (0..blocks * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        let disk = RamDisk::from_image(&data, BLOCK_SIZE);
+
+        (disk, data)
+    }
+
+    #[test]
+    fn test_unaligned_read_spanning_blocks() {
+        let (disk, data) = patterned_disk(4);
+        let mut bytes = ByteAccess::new(disk);
+
+        // The tail of block 0, all of block 1, and the head of block 2
+        let offset = BLOCK_SIZE - 10;
+        let mut buffer = vec![0; BLOCK_SIZE + 30];
+        bytes.read_at(offset as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, data[offset..offset + buffer.len()]);
+        assert_eq!(bytes.device().reads(), 3);
+
+        // Within a single block
+        let mut buffer = [0; 7];
+        bytes
+            .read_at(3 * BLOCK_SIZE as u64 + 5, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, data[3 * BLOCK_SIZE + 5..3 * BLOCK_SIZE + 12]);
+
+        // Whole blocks are read right into the buffer, all at once
+        let mut buffer = vec![0; 2 * BLOCK_SIZE];
+        bytes.read_at(BLOCK_SIZE as u64, &mut buffer).unwrap();
+        assert_eq!(buffer, data[BLOCK_SIZE..3 * BLOCK_SIZE]);
+        assert_eq!(bytes.device().reads(), 5);
+
+        bytes.read_at(0, &mut []).unwrap();
+        assert_eq!(
+            bytes.read_at(4 * BLOCK_SIZE as u64 - 1, &mut [0; 2]),
+            Err(StorageError::OutOfRange)
+        );
+        assert_eq!(
+            bytes.read_at(u64::MAX, &mut [0; 2]),
+            Err(StorageError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_partial_write_preserves_surroundings() {
+        let (disk, mut expected) = patterned_disk(4);
+        let mut bytes = ByteAccess::new(disk);
+
+        // Part of block 1 only
+        let offset = BLOCK_SIZE + 100;
+        bytes.write_at(offset as u64, &[0xaa; 20]).unwrap();
+        expected[offset..offset + 20].fill(0xaa);
+        assert_eq!(bytes.device().writes(), 1);
+
+        // The tail of block 1, all of block 2, and the head of block 3
+        let offset = 2 * BLOCK_SIZE - 1;
+        bytes
+            .write_at(offset as u64, &[0xbb; BLOCK_SIZE + 2])
+            .unwrap();
+        expected[offset..offset + BLOCK_SIZE + 2].fill(0xbb);
+        assert_eq!(bytes.device().writes(), 4);
+
+        let mut disk = bytes.into_device();
+        let mut written = vec![0; 4 * BLOCK_SIZE];
+        disk.read_blocks(0, &mut written).unwrap();
+        assert_eq!(written, expected);
+
+        // Nothing is written if the write doesn't fit
+        let writes = disk.writes();
+        let mut bytes = ByteAccess::new(disk);
+        assert_eq!(
+            bytes.write_at(3 * BLOCK_SIZE as u64, &[0; BLOCK_SIZE + 1]),
+            Err(StorageError::OutOfRange)
+        );
+        assert_eq!(bytes.device().writes(), writes);
+    }
+}
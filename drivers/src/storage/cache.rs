@@ -0,0 +1,380 @@
+//! A block cache in front of a `BlockDevice`, keeping recently used blocks in memory
+
+use alloc::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+
+use super::{BlockDevice, Completions, Operation, Request, RequestId, StorageError};
+
+/// When writes reach the underlying device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Writes go straight to the device, and update the blocks that are cached
+    WriteThrough,
+    /// Writes only update the cache. Written blocks reach the device when they are evicted, or on
+    /// `CachedBlockDevice::flush`
+    WriteBack,
+}
+
+/// A cached copy of a block
+struct CachedBlock {
+    /// The block's address
+    lba: u64,
+    /// The block's data
+    data: Box<[u8]>,
+    /// Set if `data` was written to, but not to the device yet
+    dirty: bool,
+    /// When the block was last used, for finding the least recently used one
+    last_used: u64,
+}
+
+/// Wraps a `BlockDevice`, caching up to a fixed amount of recently used blocks. When the cache is
+/// full, the least recently used block is evicted.
+///
+/// It's a `BlockDevice` itself, so it can be used anywhere the underlying device can. Requests are
+/// carried out as soon as they are submitted, though their completions are still only reported by
+/// `poll_completions`.
+///
+/// NOTE: With `WritePolicy::WriteBack`, dirty blocks are lost unless `flush` is called before the
+/// cache is dropped.
+pub struct CachedBlockDevice<D: BlockDevice> {
+    device: D,
+    policy: WritePolicy,
+    /// The maximum amount of cached blocks
+    capacity: usize,
+    blocks: Vec<CachedBlock>,
+    /// Incremented on every access, to order the accesses
+    clock: u64,
+    /// The completions of the submitted requests, until they are polled
+    completed: VecDeque<(RequestId, Result<(), StorageError>)>,
+    next_id: u64,
+}
+
+impl<D: BlockDevice> CachedBlockDevice<D> {
+    /// Wrap `device` with a cache of up to `capacity` blocks, using `policy` for writes
+    ///
+    /// # Panics
+    /// Panics if `capacity` is 0
+    #[must_use]
+    pub fn new(device: D, capacity: usize, policy: WritePolicy) -> Self {
+        assert!(
+            capacity > 0,
+            "A block cache must have room for at least one block"
+        );
+
+        Self {
+            device,
+            policy,
+            capacity,
+            blocks: Vec::with_capacity(capacity),
+            clock: 0,
+            completed: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Get the underlying device
+    #[must_use]
+    pub const fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Write all the dirty blocks to the device.
+    ///
+    /// # Errors
+    /// Fails if the device fails. The blocks that weren't written stay dirty
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        for block in self.blocks.iter_mut().filter(|block| block.dirty) {
+            self.device.write_blocks(block.lba, &block.data)?;
+            block.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Flush the cache, and get the underlying device back.
+    ///
+    /// # Errors
+    /// Fails if flushing fails, in which case the cache is handed back as well
+    pub fn into_device(mut self) -> Result<D, (Self, StorageError)> {
+        match self.flush() {
+            Ok(()) => Ok(self.device),
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    /// Drop all the cached blocks without writing them out, so the next reads go to the device
+    pub fn invalidate(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Find the index of the cached copy of block `lba`, marking it as used
+    fn lookup(&mut self, lba: u64) -> Option<usize> {
+        let index = self.blocks.iter().position(|block| block.lba == lba)?;
+
+        self.clock += 1;
+        self.blocks[index].last_used = self.clock;
+
+        Some(index)
+    }
+
+    /// Cache a copy of block `lba`, evicting the least recently used block if the cache is full.
+    ///
+    /// # Errors
+    /// Fails if a dirty block had to be evicted, and writing it to the device failed
+    fn insert(&mut self, lba: u64, data: &[u8], dirty: bool) -> Result<(), StorageError> {
+        if let Some(index) = self.lookup(lba) {
+            let block = &mut self.blocks[index];
+            block.data.copy_from_slice(data);
+            block.dirty |= dirty;
+            return Ok(());
+        }
+
+        self.clock += 1;
+        let block = CachedBlock {
+            lba,
+            data: data.into(),
+            dirty,
+            last_used: self.clock,
+        };
+
+        if self.blocks.len() < self.capacity {
+            self.blocks.push(block);
+            return Ok(());
+        }
+
+        // The cache is full, and its capacity isn't 0, so there is always a victim
+        let victim = self
+            .blocks
+            .iter_mut()
+            .min_by_key(|block| block.last_used)
+            .unwrap();
+        if victim.dirty {
+            self.device.write_blocks(victim.lba, &victim.data)?;
+        }
+        *victim = block;
+
+        Ok(())
+    }
+
+    /// Read the blocks starting from `lba` into `buffer`, reading only the ones that aren't
+    /// cached from the device
+    fn read_cached(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), StorageError> {
+        let block_size = self.device.block_size();
+        let mut blocks = buffer.chunks_exact_mut(block_size).zip(lba..).peekable();
+
+        while let Some((chunk, block_lba)) = blocks.next() {
+            if let Some(index) = self.lookup(block_lba) {
+                chunk.copy_from_slice(&self.blocks[index].data);
+                continue;
+            }
+
+            // Read the whole run of missing blocks at once
+            let mut missing = vec![(chunk, block_lba)];
+            while let Some((_, next_lba)) = blocks.peek()
+                && !self.blocks.iter().any(|block| block.lba == *next_lba)
+            {
+                missing.extend(blocks.next());
+            }
+
+            let mut run = vec![0; missing.len() * block_size];
+            self.device.read_blocks(block_lba, &mut run)?;
+
+            for ((chunk, missing_lba), data) in
+                missing.into_iter().zip(run.chunks_exact(block_size))
+            {
+                chunk.copy_from_slice(data);
+                self.insert(missing_lba, data, false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write `buffer` to the blocks starting from `lba`, according to the write policy
+    fn write_cached(&mut self, lba: u64, buffer: &[u8]) -> Result<(), StorageError> {
+        let block_size = self.device.block_size();
+
+        match self.policy {
+            WritePolicy::WriteThrough => {
+                self.device.write_blocks(lba, buffer)?;
+
+                // Keep the cached copies up to date. Blocks that aren't cached aren't worth
+                // evicting anything for
+                for (data, block_lba) in buffer.chunks_exact(block_size).zip(lba..) {
+                    if let Some(index) = self.lookup(block_lba) {
+                        let block = &mut self.blocks[index];
+                        block.data.copy_from_slice(data);
+                        block.dirty = false;
+                    }
+                }
+            }
+            WritePolicy::WriteBack => {
+                for (data, block_lba) in buffer.chunks_exact(block_size).zip(lba..) {
+                    self.insert(block_lba, data, true)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for CachedBlockDevice<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn in_flight(&self) -> usize {
+        self.completed.len()
+    }
+
+    unsafe fn submit(&mut self, mut request: Request) -> Result<RequestId, StorageError> {
+        request.blocks(self.block_size(), self.block_count())?;
+
+        // SAFETY: The submitter gave us the buffer until the completion is polled
+        let result = match request.operation {
+            Operation::Read => self.read_cached(request.lba, unsafe { request.buffer.as_mut() }),
+            Operation::Write => self.write_cached(request.lba, unsafe { request.buffer.as_ref() }),
+        };
+
+        let id = RequestId(self.next_id);
+        self.next_id += 1;
+        self.completed.push_back((id, result));
+
+        Ok(id)
+    }
+
+    fn poll_completions(&mut self) -> Completions<'_> {
+        Box::new(self.completed.drain(..))
+    }
+
+    fn discard(&mut self, lba: u64, count: u64) -> Result<(), StorageError> {
+        self.device.discard(lba, count)?;
+
+        // The cached copies would bring the discarded data back, and the dirty ones would even
+        // write it back to the device
+        let range = lba..lba.saturating_add(count);
+        self.blocks.retain(|block| !range.contains(&block.lba));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ram_disk::RamDisk, tests::BLOCK_SIZE};
+    use super::*;
+
+    /// Fill a buffer of `blocks` blocks, with each block filled with its LBA
+    fn pattern(lba: u64, blocks: usize) -> Vec<u8> {
+        (lba..)
+            .take(blocks)
+            .flat_map(|lba| [lba as u8; BLOCK_SIZE])
+            .collect()
+    }
+
+    #[test]
+    fn test_repeated_reads_hit_the_cache() {
+        let disk = RamDisk::from_image(&pattern(0, 16), BLOCK_SIZE);
+        let mut cache = CachedBlockDevice::new(disk, 4, WritePolicy::WriteThrough);
+        let mut buffer = vec![0; 3 * BLOCK_SIZE];
+
+        cache.read_blocks(5, &mut buffer[..BLOCK_SIZE]).unwrap();
+        assert_eq!(cache.device().reads(), 1);
+        cache.read_blocks(5, &mut buffer[..BLOCK_SIZE]).unwrap();
+        assert_eq!(cache.device().reads(), 1);
+        assert_eq!(buffer[..BLOCK_SIZE], pattern(5, 1));
+
+        // Only the missing blocks around the cached one are read, and each run is read at once
+        cache.read_blocks(4, &mut buffer).unwrap();
+        assert_eq!(buffer, pattern(4, 3));
+        assert_eq!(cache.device().reads(), 3);
+        cache.read_blocks(4, &mut buffer).unwrap();
+        assert_eq!(cache.device().reads(), 3);
+
+        // Reading 2 more blocks evicts the least recently used one, 4
+        let mut other = vec![0; 2 * BLOCK_SIZE];
+        cache.read_blocks(6, &mut buffer[..BLOCK_SIZE]).unwrap();
+        cache.read_blocks(10, &mut other).unwrap();
+        assert_eq!(cache.device().reads(), 4);
+        cache.read_blocks(6, &mut buffer[..BLOCK_SIZE]).unwrap();
+        assert_eq!(cache.device().reads(), 4);
+        cache.read_blocks(4, &mut buffer[..BLOCK_SIZE]).unwrap();
+        assert_eq!(cache.device().reads(), 5);
+
+        assert_eq!(
+            cache.read_blocks(15, &mut buffer),
+            Err(StorageError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_writes_update_the_cache() {
+        let mut cache =
+            CachedBlockDevice::new(RamDisk::new(BLOCK_SIZE, 8), 4, WritePolicy::WriteThrough);
+        let mut buffer = vec![0; 2 * BLOCK_SIZE];
+
+        cache.read_blocks(2, &mut buffer).unwrap();
+        cache.write_blocks(3, &pattern(3, 2)).unwrap();
+        assert_eq!(cache.device().writes(), 1);
+
+        // The cached block was updated rather than read again
+        cache.read_blocks(2, &mut buffer).unwrap();
+        assert_eq!(cache.device().reads(), 1);
+        assert_eq!(buffer[BLOCK_SIZE..], pattern(3, 1));
+
+        // And the uncached one went to the device
+        cache.invalidate();
+        cache.read_blocks(3, &mut buffer).unwrap();
+        assert_eq!(buffer, pattern(3, 2));
+    }
+
+    #[test]
+    fn test_write_back() {
+        let mut cache =
+            CachedBlockDevice::new(RamDisk::new(BLOCK_SIZE, 8), 2, WritePolicy::WriteBack);
+        let mut buffer = vec![0; BLOCK_SIZE];
+
+        cache.write_blocks(0, &pattern(0, 2)).unwrap();
+        assert_eq!(cache.device().writes(), 0);
+        cache.read_blocks(1, &mut buffer).unwrap();
+        assert_eq!(buffer, pattern(1, 1));
+        assert_eq!(cache.device().reads(), 0);
+
+        // Evicting the dirty block 0 writes it out
+        cache.read_blocks(5, &mut buffer).unwrap();
+        assert_eq!(cache.device().writes(), 1);
+
+        // The rest is written out when the cache is done with
+        let mut disk = cache.into_device().ok().unwrap();
+        assert_eq!(disk.writes(), 2);
+        let mut written = vec![0; 2 * BLOCK_SIZE];
+        disk.read_blocks(0, &mut written).unwrap();
+        assert_eq!(written, pattern(0, 2));
+    }
+
+    #[test]
+    fn test_discard_drops_cached_blocks() {
+        let mut cache =
+            CachedBlockDevice::new(RamDisk::new(BLOCK_SIZE, 8), 4, WritePolicy::WriteBack);
+        let mut buffer = vec![0; 3 * BLOCK_SIZE];
+
+        cache.write_blocks(1, &pattern(1, 3)).unwrap();
+        cache.discard(2, 2).unwrap();
+        assert_eq!(cache.device().discarded(), 2);
+
+        // The discarded blocks aren't written back over the discarded range, and read back from
+        // the device
+        let mut disk = cache.into_device().ok().unwrap();
+        assert_eq!(disk.writes(), 1);
+        disk.read_blocks(1, &mut buffer).unwrap();
+        assert_eq!(buffer[..BLOCK_SIZE], pattern(1, 1));
+        assert!(buffer[BLOCK_SIZE..].iter().all(|&byte| byte == 0));
+
+        let mut cache = CachedBlockDevice::new(disk, 4, WritePolicy::WriteBack);
+        assert_eq!(cache.discard(7, 2), Err(StorageError::OutOfRange));
+    }
+}
@@ -0,0 +1,753 @@
+//! A read-only FAT32 filesystem driver, for loading files off a boot partition.
+//!
+//! The filesystem is read through a `ByteAccess` over the device, starting from the byte offset
+//! its volume begins at (e.g. the start of the partition holding it). Long file names (VFAT) are
+//! supported for reading, and names are matched case insensitively like FAT does, though only for
+//! ASCII.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use utils::endian::{read_u16_le, read_u32_le};
+
+use super::{BlockDevice, StorageError, byte_access::ByteAccess};
+
+/// The size of a directory entry
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// The attribute bit of read-only entries
+const ATTR_READ_ONLY: u8 = 0x01;
+/// The attribute bit of hidden entries
+const ATTR_HIDDEN: u8 = 0x02;
+/// The attribute bit of system entries
+const ATTR_SYSTEM: u8 = 0x04;
+/// The attribute bit of the volume label entry
+const ATTR_VOLUME_ID: u8 = 0x08;
+/// The attribute bit of directories
+const ATTR_DIRECTORY: u8 = 0x10;
+/// The attribute bit set on files modified since the last backup
+const ATTR_ARCHIVE: u8 = 0x20;
+/// The attributes a long name entry has. No regular entry has all of them set
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+/// The attribute bits checked when looking for long name entries
+const ATTR_LONG_NAME_MASK: u8 = ATTR_LONG_NAME | ATTR_DIRECTORY | ATTR_ARCHIVE;
+
+/// The first byte of the entry marking the end of a directory
+const END_OF_ENTRIES: u8 = 0x00;
+/// The first byte of deleted entries
+const DELETED: u8 = 0xe5;
+/// The first byte of entries whose name really starts with `DELETED`
+const ESCAPED_DELETED: u8 = 0x05;
+
+/// Set in the case flags when the base of a short name is displayed in lowercase
+const LOWERCASE_BASE: u8 = 0x08;
+/// Set in the case flags when the extension of a short name is displayed in lowercase
+const LOWERCASE_EXTENSION: u8 = 0x10;
+
+/// Set in the sequence number of the long name entry holding the last part of the name
+const LFN_LAST_ENTRY: u8 = 0x40;
+/// The bits of a long name entry's sequence number holding its position in the name
+const LFN_SEQUENCE_MASK: u8 = 0x1f;
+/// The offsets of the UCS-2 units of the name in a long name entry
+const LFN_UNIT_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The offset of the short name checksum in a long name entry
+const LFN_CHECKSUM: usize = 13;
+
+/// The bits of a FAT entry holding the next cluster (the top 4 are reserved)
+const CLUSTER_MASK: u32 = 0x0fff_ffff;
+/// The FAT entry of bad clusters. Entries above it mark the end of a chain
+const BAD_CLUSTER: u32 = 0x0fff_fff7;
+
+/// Errors reading a FAT32 filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    /// The device failed
+    Storage(StorageError),
+    /// The volume's boot sector doesn't describe a FAT32 filesystem
+    NotFat32,
+    /// A cluster chain leads outside the volume, ends before its file does, or loops
+    CorruptedChain,
+    /// There is no entry at the given path
+    NotFound,
+    /// A component of the path which isn't the last one is a file
+    NotADirectory,
+    /// The path leads to a directory where a file was expected
+    IsADirectory,
+}
+
+impl From<StorageError> for FatError {
+    fn from(err: StorageError) -> Self {
+        Self::Storage(err)
+    }
+}
+
+/// An entry of a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The entry's long name, or its short name if it has none
+    pub name: String,
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+    /// The size of the file in bytes, or 0 for directories
+    pub size: u32,
+    /// The first cluster of the entry's data. 0 for empty files, and for the root directory when
+    /// it's referred to by `..`
+    cluster: u32,
+}
+
+/// A FAT32 volume
+pub struct Fat32<D: BlockDevice> {
+    bytes: ByteAccess<D>,
+    /// The byte offset of the first FAT on the device. The other copies of it are ignored
+    fat_offset: u64,
+    /// The byte offset of the first data cluster (cluster 2) on the device
+    data_offset: u64,
+    /// The size of a cluster in bytes
+    cluster_size: u32,
+    /// The amount of data clusters, so the valid clusters are `2..cluster_count + 2`
+    cluster_count: u32,
+    /// The first cluster of the root directory
+    root_cluster: u32,
+}
+
+impl<D: BlockDevice> Fat32<D> {
+    /// Mount the FAT32 volume starting at byte `offset` on `device`.
+    ///
+    /// # Errors
+    /// Fails if the boot sector doesn't describe a FAT32 volume that fits on the device, or if the
+    /// device fails
+    pub fn new(device: D, offset: u64) -> Result<Self, FatError> {
+        let mut bytes = ByteAccess::new(device);
+        let mut boot = [0; 512];
+        bytes.read_at(offset, &mut boot)?;
+
+        let bytes_per_sector = u32::from(read_u16_le(&boot, 11));
+        let sectors_per_cluster = u32::from(boot[13]);
+        let reserved_sectors = u64::from(read_u16_le(&boot, 14));
+        let fat_count = u64::from(boot[16]);
+        let root_entry_count = read_u16_le(&boot, 17);
+        let fat_size_16 = read_u16_le(&boot, 22);
+        let total_sectors = match read_u16_le(&boot, 19) {
+            0 => u64::from(read_u32_le(&boot, 32)),
+            sectors => u64::from(sectors),
+        };
+        let fat_size = u64::from(read_u32_le(&boot, 36));
+        let root_cluster = read_u32_le(&boot, 44);
+
+        // FAT12/16 volumes have a fixed size root directory and a 16 bit FAT size instead. The
+        // spec tells the types apart by cluster count, but that would reject the small volumes
+        // tools happily format as FAT32
+        if boot[510..] != [0x55, 0xaa]
+            || !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || fat_count == 0
+            || fat_size == 0
+            || root_entry_count != 0
+            || fat_size_16 != 0
+        {
+            return Err(FatError::NotFat32);
+        }
+
+        let sector_size = u64::from(bytes_per_sector);
+        if offset + total_sectors * sector_size > bytes.len() {
+            return Err(FatError::NotFat32);
+        }
+
+        let data_sector = reserved_sectors + fat_count * fat_size;
+        let cluster_count = total_sectors
+            .checked_sub(data_sector)
+            .ok_or(FatError::NotFat32)?
+            / u64::from(sectors_per_cluster);
+        // Clusters that have no FAT entry can't be used
+        let cluster_count = cluster_count
+            .min((fat_size * sector_size / 4).saturating_sub(2))
+            .min(u64::from(BAD_CLUSTER - 2)) as u32;
+
+        let fs = Self {
+            bytes,
+            fat_offset: offset + reserved_sectors * sector_size,
+            data_offset: offset + data_sector * sector_size,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            cluster_count,
+            root_cluster,
+        };
+        if !fs.is_valid_cluster(root_cluster) {
+            return Err(FatError::NotFat32);
+        }
+
+        Ok(fs)
+    }
+
+    /// Get the underlying device back
+    #[must_use]
+    pub fn into_device(self) -> D {
+        self.bytes.into_device()
+    }
+
+    /// Open the file at `path`, which is relative to the root directory.
+    ///
+    /// # Errors
+    /// Fails if there is no file at `path`, or if the device fails
+    pub fn open(&mut self, path: &str) -> Result<File<'_, D>, FatError> {
+        let entry = self.lookup(path)?;
+        if entry.is_dir {
+            return Err(FatError::IsADirectory);
+        }
+
+        Ok(File {
+            fs: self,
+            size: entry.size,
+            position: 0,
+            cluster: entry.cluster,
+        })
+    }
+
+    /// List the entries of the directory at `path`, which is relative to the root directory.
+    ///
+    /// # Errors
+    /// Fails if there is no directory at `path`, or if the device fails
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, FatError> {
+        let entry = self.lookup(path)?;
+        if !entry.is_dir {
+            return Err(FatError::NotADirectory);
+        }
+
+        self.list(entry.cluster)
+    }
+
+    /// Find the entry at `path`, walking down the directories from the root
+    fn lookup(&mut self, path: &str) -> Result<DirEntry, FatError> {
+        let mut entry = DirEntry {
+            name: String::new(),
+            is_dir: true,
+            size: 0,
+            cluster: self.root_cluster,
+        };
+
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if !entry.is_dir {
+                return Err(FatError::NotADirectory);
+            }
+
+            entry = self
+                .list(entry.cluster)?
+                .into_iter()
+                .find(|child| child.name.eq_ignore_ascii_case(component))
+                .ok_or(FatError::NotFound)?;
+        }
+
+        Ok(entry)
+    }
+
+    /// List the entries of the directory starting at `cluster`
+    fn list(&mut self, cluster: u32) -> Result<Vec<DirEntry>, FatError> {
+        // The `..` entries of the root's children point to cluster 0
+        let cluster = if cluster == 0 {
+            self.root_cluster
+        } else {
+            cluster
+        };
+
+        Ok(parse_entries(&self.read_chain(cluster)?))
+    }
+
+    /// Read the whole cluster chain starting at `first`
+    fn read_chain(&mut self, first: u32) -> Result<Vec<u8>, FatError> {
+        let cluster_size = self.cluster_size as usize;
+        let mut data = Vec::new();
+        let mut cluster = Some(first);
+
+        // A chain can't be longer than the volume, so a longer one must loop
+        for _ in 0..self.cluster_count {
+            let Some(current) = cluster else {
+                return Ok(data);
+            };
+            if !self.is_valid_cluster(current) {
+                return Err(FatError::CorruptedChain);
+            }
+
+            let start = data.len();
+            data.resize(start + cluster_size, 0);
+            self.bytes
+                .read_at(self.cluster_offset(current), &mut data[start..])?;
+            cluster = self.next_cluster(current)?;
+        }
+
+        match cluster {
+            Some(_) => Err(FatError::CorruptedChain),
+            None => Ok(data),
+        }
+    }
+
+    /// Get the cluster following `cluster` in its chain, or `None` if it's the last one
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FatError> {
+        let mut entry = [0; 4];
+        self.bytes
+            .read_at(self.fat_offset + u64::from(cluster) * 4, &mut entry)?;
+
+        match u32::from_le_bytes(entry) & CLUSTER_MASK {
+            next if next > BAD_CLUSTER => Ok(None),
+            next if self.is_valid_cluster(next) => Ok(Some(next)),
+            // Free, reserved and bad clusters aren't part of any chain
+            _ => Err(FatError::CorruptedChain),
+        }
+    }
+
+    /// Returns true if `cluster` is a data cluster of the volume
+    const fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    /// Get the byte offset of `cluster` on the device
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + u64::from(cluster - 2) * u64::from(self.cluster_size)
+    }
+}
+
+/// A file opened for reading
+pub struct File<'a, D: BlockDevice> {
+    fs: &'a mut Fat32<D>,
+    /// The size of the file in bytes
+    size: u32,
+    /// The offset the next read starts from
+    position: u32,
+    /// The cluster holding `position`, so reads don't have to walk the chain from its start.
+    ///
+    /// When `position` is right past the end of a cluster, this is still that cluster: the chain
+    /// is only followed once there is more to read, since it might end there.
+    cluster: u32,
+}
+
+impl<D: BlockDevice> File<'_, D> {
+    /// The size of the file in bytes
+    #[must_use]
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The offset the next read starts from
+    #[must_use]
+    pub const fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Read from the current position into `buffer`, returning the amount of bytes read. It's
+    /// less than `buffer.len()` only if the end of the file was reached.
+    ///
+    /// # Errors
+    /// Fails if the file's cluster chain is corrupted, or if the device fails
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FatError> {
+        let cluster_size = self.fs.cluster_size;
+        let len = buffer.len().min((self.size - self.position) as usize);
+
+        let mut done = 0;
+        while done < len {
+            let in_cluster = self.position % cluster_size;
+            if in_cluster == 0 && self.position != 0 {
+                self.cluster = self
+                    .fs
+                    .next_cluster(self.cluster)?
+                    .ok_or(FatError::CorruptedChain)?;
+            }
+            if !self.fs.is_valid_cluster(self.cluster) {
+                return Err(FatError::CorruptedChain);
+            }
+
+            let chunk = (len - done).min((cluster_size - in_cluster) as usize);
+            let offset = self.fs.cluster_offset(self.cluster) + u64::from(in_cluster);
+            self.fs
+                .bytes
+                .read_at(offset, &mut buffer[done..done + chunk])?;
+
+            done += chunk;
+            self.position += chunk as u32;
+        }
+
+        Ok(len)
+    }
+}
+
+/// Parse the entries of a directory out of its `data`, skipping deleted entries and the volume
+/// label
+fn parse_entries(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut long_name = LongName::default();
+
+    for raw in data.as_chunks::<DIR_ENTRY_SIZE>().0 {
+        match raw[0] {
+            END_OF_ENTRIES => break,
+            DELETED => {
+                long_name.units.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        let attributes = raw[11];
+        if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+            long_name.push(raw);
+            continue;
+        }
+        if attributes & ATTR_VOLUME_ID != 0 {
+            long_name.units.clear();
+            continue;
+        }
+
+        let short = raw[..11].try_into().unwrap();
+        let name = long_name
+            .take(short)
+            .unwrap_or_else(|| short_name(short, raw[12]));
+        entries.push(DirEntry {
+            name,
+            is_dir: attributes & ATTR_DIRECTORY != 0,
+            size: read_u32_le(raw, 28),
+            cluster: (u32::from(read_u16_le(raw, 20)) << 16) | u32::from(read_u16_le(raw, 26)),
+        });
+    }
+
+    entries
+}
+
+/// A long name being collected from the long name entries preceding a short entry.
+///
+/// The entries are stored in reverse, from the one holding the last part of the name down to the
+/// one holding the first.
+#[derive(Default)]
+struct LongName {
+    /// The UCS-2 units of the name, or empty if no valid name is being collected
+    units: Vec<u16>,
+    /// The checksum of the short name the entries belong to
+    checksum: u8,
+    /// The sequence number of the entry expected next, which is 0 once the name is complete
+    next: u8,
+}
+
+impl LongName {
+    /// Add the part of the name in long name entry `raw`
+    fn push(&mut self, raw: &[u8; DIR_ENTRY_SIZE]) {
+        let sequence = raw[0] & LFN_SEQUENCE_MASK;
+        if raw[0] & LFN_LAST_ENTRY != 0 && sequence != 0 {
+            self.units = vec![0; usize::from(sequence) * LFN_UNIT_OFFSETS.len()];
+            self.checksum = raw[LFN_CHECKSUM];
+        } else if self.units.is_empty()
+            || sequence == 0
+            || sequence != self.next
+            || raw[LFN_CHECKSUM] != self.checksum
+        {
+            // A leftover part of a name, or one that's out of order
+            self.units.clear();
+            return;
+        }
+
+        let start = usize::from(sequence - 1) * LFN_UNIT_OFFSETS.len();
+        for (unit, &offset) in self.units[start..].iter_mut().zip(&LFN_UNIT_OFFSETS) {
+            *unit = read_u16_le(raw, offset);
+        }
+        self.next = sequence - 1;
+    }
+
+    /// Take the collected name, if it's complete and belongs to `short`
+    fn take(&mut self, short: &[u8; 11]) -> Option<String> {
+        let units = core::mem::take(&mut self.units);
+        if units.is_empty() || self.next != 0 || self.checksum != lfn_checksum(short) {
+            return None;
+        }
+
+        // Names that don't fill their last entry are terminated by a null, and padded with 0xffff
+        let len = units
+            .iter()
+            .position(|&unit| unit == 0 || unit == 0xffff)
+            .unwrap_or(units.len());
+
+        Some(
+            char::decode_utf16(units[..len].iter().copied())
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        )
+    }
+}
+
+/// Calculate the checksum of `short` stored in the long name entries belonging to it
+fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0_u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Turn the space padded `short` name into its displayed form, applying the lowercase `case`
+/// flags
+fn short_name(short: &[u8; 11], case: u8) -> String {
+    let mut short = *short;
+    if short[0] == ESCAPED_DELETED {
+        short[0] = DELETED;
+    }
+
+    let (base, extension) = short.split_at(8);
+    let mut name = String::new();
+    push_name_part(&mut name, base, case & LOWERCASE_BASE != 0);
+    if extension != b"   " {
+        name.push('.');
+        push_name_part(&mut name, extension, case & LOWERCASE_EXTENSION != 0);
+    }
+
+    name
+}
+
+/// Append a part of a short name to `name`, without the padding
+fn push_name_part(name: &mut String, part: &[u8], lowercase: bool) {
+    let len = part
+        .iter()
+        .rposition(|&b| b != b' ')
+        .map_or(0, |last| last + 1);
+
+    name.extend(
+        part[..len]
+            .iter()
+            .map(|&b| char::from(if lowercase { b.to_ascii_lowercase() } else { b })),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ram_disk::RamDisk, tests::BLOCK_SIZE};
+    use super::*;
+
+    /// The offset of the volume on the test disk, as if it was in a partition
+    const VOLUME_OFFSET: u64 = 8 * BLOCK_SIZE as u64;
+    /// The amount of sectors in the test volume
+    const VOLUME_SECTORS: usize = 40;
+    const RESERVED_SECTORS: usize = 2;
+    const FAT_COUNT: usize = 2;
+
+    /// The contents of the file spread over clusters 5, 7 and 6
+    fn module() -> Vec<u8> {
+        (0..2 * BLOCK_SIZE + 100).map(|i| (i * 7) as u8).collect()
+    }
+
+    /// A FAT32 volume built in memory, with one sector per cluster and single sector FATs
+    struct Image {
+        data: Vec<u8>,
+    }
+
+    impl Image {
+        fn new() -> Self {
+            let mut data = vec![0; VOLUME_SECTORS * BLOCK_SIZE];
+            data[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+            data[13] = 1;
+            data[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+            data[16] = FAT_COUNT as u8;
+            data[32..36].copy_from_slice(&(VOLUME_SECTORS as u32).to_le_bytes());
+            data[36..40].copy_from_slice(&1_u32.to_le_bytes());
+            data[44..48].copy_from_slice(&2_u32.to_le_bytes());
+            data[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+            let mut image = Self { data };
+            image.set_fat(0, 0x0fff_fff8);
+            image.set_fat(1, 0x0fff_ffff);
+
+            image
+        }
+
+        fn set_fat(&mut self, cluster: u32, value: u32) {
+            for fat in 0..FAT_COUNT {
+                let offset = (RESERVED_SECTORS + fat) * BLOCK_SIZE + cluster as usize * 4;
+                self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        /// Write `data` over the clusters in `chain`, and link them up in the FATs
+        fn write(&mut self, chain: &[u32], data: &[u8]) {
+            for (i, &cluster) in chain.iter().enumerate() {
+                self.set_fat(cluster, chain.get(i + 1).copied().unwrap_or(0x0fff_ffff));
+            }
+
+            let data_start = (RESERVED_SECTORS + FAT_COUNT) * BLOCK_SIZE;
+            for (&cluster, part) in chain.iter().zip(data.chunks(BLOCK_SIZE)) {
+                let offset = data_start + (cluster as usize - 2) * BLOCK_SIZE;
+                self.data[offset..offset + part.len()].copy_from_slice(part);
+            }
+        }
+
+        fn into_disk(self) -> RamDisk {
+            RamDisk::from_image(&self.into_disk_image(), BLOCK_SIZE)
+        }
+
+        /// Get an image of the whole disk holding the volume, like one loaded as a boot module
+        fn into_disk_image(self) -> Vec<u8> {
+            let mut image = vec![0; VOLUME_OFFSET as usize];
+            image.extend(self.data);
+
+            image
+        }
+    }
+
+    fn short_entry(short: &[u8; 11], attributes: u8, cluster: u32, size: u32) -> Vec<u8> {
+        let mut entry = vec![0; DIR_ENTRY_SIZE];
+        entry[..11].copy_from_slice(short);
+        entry[11] = attributes;
+        entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+
+        entry
+    }
+
+    /// Build the long name entries storing `name` for `short`, last part first
+    fn long_entries(name: &str, short: &[u8; 11]) -> Vec<u8> {
+        let mut units: Vec<u16> = name.encode_utf16().collect();
+        let count = units.len().div_ceil(13);
+        if units.len() < count * 13 {
+            units.push(0);
+            units.resize(count * 13, 0xffff);
+        }
+
+        let mut entries = Vec::new();
+        for (i, part) in units.chunks(13).enumerate().rev() {
+            let mut entry = [0; DIR_ENTRY_SIZE];
+            entry[0] = (i + 1) as u8 | if i + 1 == count { LFN_LAST_ENTRY } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[LFN_CHECKSUM] = lfn_checksum(short);
+            for (&unit, &offset) in part.iter().zip(&LFN_UNIT_OFFSETS) {
+                entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entries.extend(entry);
+        }
+
+        entries
+    }
+
+    /// Build a volume holding:
+    /// - /Boot Modules/kernel-module.bin: `module()`, over clusters 5, 7 and 6
+    /// - /readme.txt: "hello, fat!"
+    /// - /NOTES.TXT: empty, with an orphaned long name entry before it
+    /// - /BROKEN.BIN: a file whose chain ends too early
+    fn disk() -> RamDisk {
+        image().into_disk()
+    }
+
+    /// Build the volume `disk` holds
+    fn image() -> Image {
+        let mut image = Image::new();
+
+        let mut root = short_entry(b"FUNDERBERK ", ATTR_VOLUME_ID, 0, 0);
+        root.extend(long_entries("Boot Modules", b"BOOTMO~1   "));
+        root.extend(short_entry(b"BOOTMO~1   ", ATTR_DIRECTORY, 3, 0));
+        let mut readme = short_entry(b"README  TXT", ATTR_ARCHIVE, 4, 11);
+        readme[12] = LOWERCASE_BASE | LOWERCASE_EXTENSION;
+        root.extend(readme);
+        let mut deleted = long_entries("Deleted file", b"DELETE~1   ");
+        deleted.extend(short_entry(b"DELETE~1   ", 0, 8, 1));
+        deleted[0] = DELETED;
+        deleted[DIR_ENTRY_SIZE] = DELETED;
+        root.extend(deleted);
+        root.extend(long_entries("Not notes", b"NOT     TXT"));
+        root.extend(short_entry(b"NOTES   TXT", 0, 0, 0));
+        root.extend(short_entry(b"BROKEN  BIN", 0, 9, 2 * BLOCK_SIZE as u32));
+        image.write(&[2], &root);
+
+        let mut modules = short_entry(b".          ", ATTR_DIRECTORY, 3, 0);
+        modules.extend(short_entry(b"..         ", ATTR_DIRECTORY, 0, 0));
+        modules.extend(long_entries("kernel-module.bin", b"KERNEL~1BIN"));
+        modules.extend(short_entry(
+            b"KERNEL~1BIN",
+            ATTR_ARCHIVE,
+            5,
+            module().len() as u32,
+        ));
+        image.write(&[3], &modules);
+
+        image.write(&[4], b"hello, fat!");
+        image.write(&[5, 7, 6], &module());
+        image.write(&[9], &[0xff; BLOCK_SIZE]);
+
+        image
+    }
+
+    #[test]
+    fn test_read_file() {
+        let mut fs = Fat32::new(disk(), VOLUME_OFFSET).unwrap();
+
+        // Read in odd sized pieces, so reads straddle the clusters
+        let mut file = fs.open("/Boot Modules/kernel-module.bin").unwrap();
+        assert_eq!(file.size() as usize, module().len());
+        let mut contents: Vec<u8> = Vec::new();
+        let mut buffer = [0; 300];
+        loop {
+            let read = file.read(&mut buffer).unwrap();
+            contents.extend(&buffer[..read]);
+            if read < buffer.len() {
+                break;
+            }
+        }
+        assert_eq!(contents, module());
+        assert_eq!(file.read(&mut buffer), Ok(0));
+
+        // Names are case insensitive, and `..` leads back to the root
+        let mut file = fs.open("boot modules/../README.TXT").unwrap();
+        let mut buffer = [0; 32];
+        assert_eq!(file.read(&mut buffer), Ok(11));
+        assert_eq!(&buffer[..11], b"hello, fat!");
+
+        let mut file = fs.open("/NOTES.TXT").unwrap();
+        assert_eq!(file.read(&mut buffer), Ok(0));
+
+        let mut file = fs.open("/BROKEN.BIN").unwrap();
+        assert_eq!(file.read(&mut buffer), Ok(32));
+        assert_eq!(
+            file.read(&mut [0; BLOCK_SIZE]),
+            Err(FatError::CorruptedChain)
+        );
+    }
+
+    #[test]
+    fn test_mount_ram_disk() {
+        let image = image().into_disk_image();
+        let disk = RamDisk::from_image(&image, BLOCK_SIZE);
+        let mut fs = Fat32::new(disk, VOLUME_OFFSET).unwrap();
+
+        let mut file = fs.open("/Boot Modules/kernel-module.bin").unwrap();
+        let mut contents = vec![0; module().len() + 1];
+        assert_eq!(file.read(&mut contents), Ok(module().len()));
+        assert_eq!(contents[..module().len()], module());
+
+        // The disk is handed back as it was
+        assert_eq!(fs.into_device().data(), image);
+    }
+
+    #[test]
+    fn test_read_dir() {
+        let mut fs = Fat32::new(disk(), VOLUME_OFFSET).unwrap();
+
+        let names = |entries: Vec<DirEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.name).collect()
+        };
+        assert_eq!(
+            names(fs.read_dir("/").unwrap()),
+            ["Boot Modules", "readme.txt", "NOTES.TXT", "BROKEN.BIN"]
+        );
+        assert_eq!(
+            names(fs.read_dir("/Boot Modules/.").unwrap()),
+            [".", "..", "kernel-module.bin"]
+        );
+
+        assert_eq!(fs.open("/Boot Modules").err(), Some(FatError::IsADirectory));
+        assert_eq!(fs.read_dir("/readme.txt"), Err(FatError::NotADirectory));
+        assert_eq!(
+            fs.open("/readme.txt/x").err(),
+            Some(FatError::NotADirectory)
+        );
+        assert_eq!(
+            fs.open("/Boot Modules/KERNEL").err(),
+            Some(FatError::NotFound)
+        );
+        assert_eq!(fs.open("/Deleted file").err(), Some(FatError::NotFound));
+    }
+
+    #[test]
+    fn test_not_fat32() {
+        assert!(matches!(
+            Fat32::new(RamDisk::new(BLOCK_SIZE, 8), 0),
+            Err(FatError::NotFat32)
+        ));
+        assert!(matches!(Fat32::new(disk(), 0), Err(FatError::NotFat32)));
+    }
+}
@@ -1 +1,270 @@
-// mod nvme;
+//! Block storage drivers, and the interface they expose
+//!
+//! Devices are driven through a queue: requests are submitted with `BlockDevice::submit`, and
+//! their results are collected later with `BlockDevice::poll_completions`, so multiple requests
+//! can be in flight at once. `read_blocks` and `write_blocks` are synchronous wrappers around
+//! that, for when throughput doesn't matter (e.g. during setup).
+
+use alloc::boxed::Box;
+use core::{ops::Range, ptr::NonNull};
+
+pub mod byte_access;
+pub mod cache;
+pub mod fat32;
+pub mod nvme;
+pub mod ram_disk;
+
+/// Errors a storage device might encounter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError {
+    /// The request reaches past the end of the device
+    OutOfRange,
+    /// The buffer's size isn't a multiple of the device's block size
+    InvalidBufferSize,
+    /// The device can't take any more requests until some complete
+    QueueFull,
+    /// A synchronous request was made while queued requests are in flight
+    RequestsInFlight,
+    /// The device failed to carry out the request
+    DeviceError,
+    /// The device doesn't support the operation
+    Unsupported,
+}
+
+/// Identifies a request submitted to a device, until its completion is polled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestId(pub u64);
+
+/// The operation a request performs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Read blocks from the device into the buffer
+    Read,
+    /// Write the buffer to the device
+    Write,
+}
+
+/// A request to read or write a contiguous range of blocks.
+///
+/// The request doesn't borrow its buffer, since it outlives the call to `BlockDevice::submit`.
+/// Instead, the device owns the buffer from the moment the request is submitted until its
+/// completion is returned from `BlockDevice::poll_completions`, and the caller must not touch it
+/// in the meantime.
+#[derive(Debug, Clone, Copy)]
+pub struct Request {
+    /// The operation to perform
+    pub operation: Operation,
+    /// The first block the request operates on
+    pub lba: u64,
+    /// The buffer to read into or write from. Its size decides the amount of blocks
+    pub buffer: NonNull<[u8]>,
+}
+
+impl Request {
+    /// Create a request reading blocks starting from `lba` into `buffer`
+    #[must_use]
+    pub fn read(lba: u64, buffer: &mut [u8]) -> Self {
+        Self {
+            operation: Operation::Read,
+            lba,
+            buffer: NonNull::from(buffer),
+        }
+    }
+
+    /// Create a request writing `buffer` to the blocks starting from `lba`
+    #[must_use]
+    pub fn write(lba: u64, buffer: &[u8]) -> Self {
+        Self {
+            operation: Operation::Write,
+            lba,
+            buffer: NonNull::from(buffer),
+        }
+    }
+
+    /// Get the range of blocks the request operates on, on a device with `block_count` blocks of
+    /// `block_size` bytes.
+    ///
+    /// # Errors
+    /// Fails if the buffer's size isn't a multiple of `block_size`, or if the range reaches past
+    /// the end of the device
+    pub fn blocks(&self, block_size: usize, block_count: u64) -> Result<Range<u64>, StorageError> {
+        if !self.buffer.len().is_multiple_of(block_size) {
+            return Err(StorageError::InvalidBufferSize);
+        }
+
+        let end = self
+            .lba
+            .checked_add((self.buffer.len() / block_size) as u64)
+            .filter(|&end| end <= block_count)
+            .ok_or(StorageError::OutOfRange)?;
+
+        Ok(self.lba..end)
+    }
+}
+
+/// The completions `BlockDevice::poll_completions` returns, along with the result of each request.
+///
+/// Boxed, so that `BlockDevice` can be used as a trait object
+pub type Completions<'a> = Box<dyn Iterator<Item = (RequestId, Result<(), StorageError>)> + 'a>;
+
+/// A device storing data in fixed size blocks
+pub trait BlockDevice {
+    /// The size of a block in bytes
+    fn block_size(&self) -> usize;
+
+    /// The amount of blocks on the device
+    fn block_count(&self) -> u64;
+
+    /// The amount of submitted requests whose completion wasn't polled yet
+    fn in_flight(&self) -> usize;
+
+    /// Submit `request` to the device, returning the ID its completion will be reported with.
+    ///
+    /// # Safety
+    /// The request's buffer must stay valid, and must not be accessed, until the request's
+    /// completion is returned from `poll_completions`
+    ///
+    /// # Errors
+    /// Fails if the request is invalid for this device, or if the device's queue is full
+    unsafe fn submit(&mut self, request: Request) -> Result<RequestId, StorageError>;
+
+    /// Collect the completions of the requests that finished since the last poll.
+    ///
+    /// Once a request's completion is returned, its buffer belongs to the caller again.
+    fn poll_completions(&mut self) -> Completions<'_>;
+
+    /// Read the blocks starting from `lba` into `buffer`, and wait for the read to complete
+    ///
+    /// # Errors
+    /// Fails if the request is invalid, if the device fails, or if queued requests are in flight
+    fn read_blocks(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), StorageError> {
+        submit_and_wait(self, Request::read(lba, buffer))
+    }
+
+    /// Write `buffer` to the blocks starting from `lba`, and wait for the write to complete
+    ///
+    /// # Errors
+    /// Fails if the request is invalid, if the device fails, or if queued requests are in flight
+    fn write_blocks(&mut self, lba: u64, buffer: &[u8]) -> Result<(), StorageError> {
+        submit_and_wait(self, Request::write(lba, buffer))
+    }
+
+    /// Tell the device the `count` blocks starting from `lba` don't hold any useful data anymore
+    /// (TRIM), so it can reclaim them. Reading them afterwards returns unspecified data.
+    ///
+    /// Filesystems should call this when they free blocks. Devices that can't make use of it
+    /// don't have to implement it.
+    ///
+    /// # Errors
+    /// Fails with `StorageError::Unsupported` if the device can't discard blocks, and otherwise
+    /// like `write_blocks`
+    fn discard(&mut self, lba: u64, count: u64) -> Result<(), StorageError> {
+        let _ = (lba, count);
+        Err(StorageError::Unsupported)
+    }
+}
+
+/// Submit `request` to `device`, and spin until it completes.
+///
+/// Completions are polled in bulk, so waiting while other requests are in flight would swallow
+/// their completions. In that case `StorageError::RequestsInFlight` is returned instead.
+fn submit_and_wait<D: BlockDevice + ?Sized>(
+    device: &mut D,
+    request: Request,
+) -> Result<(), StorageError> {
+    if device.in_flight() != 0 {
+        return Err(StorageError::RequestsInFlight);
+    }
+
+    // SAFETY: The caller's borrow of the buffer lasts until we return, and we only return once
+    // the request has completed
+    let id = unsafe { device.submit(request)? };
+
+    loop {
+        if let Some((_, result)) = device
+            .poll_completions()
+            .find(|(completed, _)| *completed == id)
+        {
+            return result;
+        }
+
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ram_disk::RamDisk;
+    use super::*;
+    use alloc::vec::Vec;
+
+    pub(super) const BLOCK_SIZE: usize = 512;
+
+    #[test]
+    fn test_multiple_requests_in_flight() {
+        let mut disk = RamDisk::new(BLOCK_SIZE, 8);
+        let first = [0x11; BLOCK_SIZE];
+        let second = [0x22; 2 * BLOCK_SIZE];
+        let mut read_back = [0; 3 * BLOCK_SIZE];
+
+        let ids = unsafe {
+            [
+                disk.submit(Request::write(1, &first)).unwrap(),
+                disk.submit(Request::write(2, &second)).unwrap(),
+                disk.submit(Request::read(1, &mut read_back)).unwrap(),
+            ]
+        };
+        assert_eq!(disk.in_flight(), 3);
+
+        let completions: Vec<_> = disk.poll_completions().collect();
+        assert_eq!(
+            completions,
+            [(ids[0], Ok(())), (ids[1], Ok(())), (ids[2], Ok(()))]
+        );
+        assert_eq!(disk.in_flight(), 0);
+        assert_eq!(disk.poll_completions().count(), 0);
+
+        // The completions were polled, so the buffer is ours again
+        assert_eq!(&read_back[..BLOCK_SIZE], &first);
+        assert_eq!(&read_back[BLOCK_SIZE..], &second);
+    }
+
+    #[test]
+    fn test_sync_wrappers() {
+        let mut disk = RamDisk::new(BLOCK_SIZE, 4);
+        let mut buffer = [0; BLOCK_SIZE];
+
+        disk.write_blocks(3, &[0xab; BLOCK_SIZE]).unwrap();
+        disk.read_blocks(3, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xab; BLOCK_SIZE]);
+
+        assert_eq!(
+            disk.read_blocks(4, &mut buffer),
+            Err(StorageError::OutOfRange)
+        );
+        assert_eq!(
+            disk.read_blocks(0, &mut buffer[1..]),
+            Err(StorageError::InvalidBufferSize)
+        );
+
+        // Waiting would swallow the completion of the queued request
+        let queued = [0; BLOCK_SIZE];
+        unsafe { disk.submit(Request::write(0, &queued)).unwrap() };
+        assert_eq!(
+            disk.read_blocks(3, &mut buffer),
+            Err(StorageError::RequestsInFlight)
+        );
+    }
+
+    #[test]
+    fn test_trait_object() {
+        let mut disk = RamDisk::new(BLOCK_SIZE, 2);
+        let device: &mut dyn BlockDevice = &mut disk;
+        let mut buffer = [0; BLOCK_SIZE];
+
+        device.write_blocks(1, &[0x5a; BLOCK_SIZE]).unwrap();
+        device.read_blocks(1, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x5a; BLOCK_SIZE]);
+        assert_eq!(device.poll_completions().count(), 0);
+    }
+}
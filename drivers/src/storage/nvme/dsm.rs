@@ -0,0 +1,115 @@
+//! The Dataset Management command, which discards (deallocates) ranges of blocks.
+//!
+//! The command points to a list of up to `MAX_RANGES` range descriptors, which must fit in a
+//! single page.
+
+use core::iter;
+
+/// The opcode of the Dataset Management command in the NVM command set
+pub const OPCODE: u8 = 0x09;
+
+/// The most ranges a single command can carry
+pub const MAX_RANGES: usize = 256;
+
+/// The most blocks a single range can cover
+const MAX_RANGE_LENGTH: u64 = u32::MAX as u64;
+
+/// The Attribute - Deallocate bit of command dword 11
+const ATTRIBUTE_DEALLOCATE: u32 = 1 << 2;
+
+/// A range of blocks the command applies to
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RangeDescriptor {
+    /// Hints about how the range is accessed. Meaningless when deallocating
+    pub context_attributes: u32,
+    /// The amount of blocks in the range
+    pub length: u32,
+    /// The first block of the range
+    pub starting_lba: u64,
+}
+
+const _: () = assert!(size_of::<RangeDescriptor>() == 16);
+const _: () = assert!(size_of::<[RangeDescriptor; MAX_RANGES]>() == 0x1000);
+
+/// Split the `count` blocks starting from `lba` into range descriptors, as few as possible.
+///
+/// NOTE: Discarding more than `MAX_RANGES` ranges (about a trillion blocks) takes multiple
+/// commands
+pub fn ranges(lba: u64, count: u64) -> impl Iterator<Item = RangeDescriptor> {
+    let mut next = lba;
+    let end = lba.saturating_add(count);
+
+    iter::from_fn(move || {
+        let length = (end - next).min(MAX_RANGE_LENGTH);
+        if length == 0 {
+            return None;
+        }
+
+        let range = RangeDescriptor {
+            context_attributes: 0,
+            // NOTE: Fits, since it's at most `MAX_RANGE_LENGTH`
+            length: length as u32,
+            starting_lba: next,
+        };
+        next += length;
+
+        Some(range)
+    })
+}
+
+/// Get command dwords 10 and 11 of a command deallocating `range_count` ranges.
+///
+/// # Panics
+/// Panics if `range_count` isn't between 1 and `MAX_RANGES`
+#[must_use]
+pub const fn deallocate_dwords(range_count: usize) -> (u32, u32) {
+    assert!(
+        range_count != 0 && range_count <= MAX_RANGES,
+        "Invalid amount of DSM ranges"
+    );
+
+    // The number of ranges is 0 based
+    ((range_count - 1) as u32, ATTRIBUTE_DEALLOCATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_ranges() {
+        let descriptors: Vec<_> = ranges(0x1000, 64).collect();
+        assert_eq!(
+            descriptors,
+            [RangeDescriptor {
+                context_attributes: 0,
+                length: 64,
+                starting_lba: 0x1000,
+            }]
+        );
+
+        // Ranges longer than a descriptor can hold are split
+        let descriptors: Vec<_> = ranges(10, MAX_RANGE_LENGTH * 2 + 5).collect();
+        assert_eq!(
+            descriptors
+                .iter()
+                .map(|range| (range.starting_lba, range.length))
+                .collect::<Vec<_>>(),
+            [
+                (10, u32::MAX),
+                (10 + MAX_RANGE_LENGTH, u32::MAX),
+                (10 + MAX_RANGE_LENGTH * 2, 5),
+            ]
+        );
+
+        assert_eq!(ranges(5, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_deallocate_dwords() {
+        assert_eq!(deallocate_dwords(1), (0, 0b100));
+        assert_eq!(deallocate_dwords(MAX_RANGES), (255, 0b100));
+    }
+}
@@ -1 +1,20 @@
-mod pcie;
+//! `NVMe` storage devices
+
+use crate::bus::pcie::PciDriver;
+
+pub mod dsm;
+// TODO: The controller isn't finished yet
+// mod pcie;
+
+/// Matches `NVMe` controllers (mass storage controller, non-volatile memory controller, `NVMe`
+/// interface).
+///
+/// NOTE: The controller isn't driven yet, so probing one only reports it
+pub const PCI_DRIVER: PciDriver = PciDriver {
+    name: "NVMe",
+    matches: |info| (info.class_code, info.subclass, info.prog_if) == (0x1, 0x8, 0x2),
+    probe: |_device| {
+        logger::info!("Found NVMe");
+        Ok(())
+    },
+};
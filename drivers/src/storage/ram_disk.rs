@@ -0,0 +1,283 @@
+//! A block device backed by memory, for mounting disk images (e.g. one loaded as a boot module)
+//! and running the partition and filesystem code against them without any real hardware
+
+use alloc::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+
+use utils::boot_info::BootModule;
+
+use super::{BlockDevice, Completions, Operation, Request, RequestId, StorageError};
+
+/// The memory holding a `RamDisk`'s blocks
+enum Backing {
+    /// A buffer on the heap
+    Heap(Box<[u8]>),
+    /// Memory the disk was handed (e.g. reserved physical memory), which it uses in place
+    Borrowed(&'static mut [u8]),
+}
+
+impl Backing {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Heap(data) => data,
+            Self::Borrowed(data) => data,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            Self::Heap(data) => data,
+            Self::Borrowed(data) => data,
+        }
+    }
+}
+
+/// A block device whose blocks live in memory.
+///
+/// Requests are only carried out once their completions are polled, like on a real device
+/// completing them asynchronously, so submitters that touch a buffer too early are caught.
+pub struct RamDisk {
+    backing: Backing,
+    block_size: usize,
+    /// The submitted requests, until their completions are polled
+    pending: VecDeque<(RequestId, Request)>,
+    next_id: u64,
+    /// The amount of read requests submitted
+    reads: usize,
+    /// The amount of write requests submitted
+    writes: usize,
+    /// The amount of blocks discarded
+    discarded: u64,
+}
+
+impl RamDisk {
+    /// Create a disk of `block_count` zeroed blocks of `block_size` bytes, on the heap
+    ///
+    /// # Panics
+    /// Panics if `block_size` is 0
+    #[must_use]
+    pub fn new(block_size: usize, block_count: usize) -> Self {
+        Self::with_backing(
+            Backing::Heap(vec![0; block_size * block_count].into_boxed_slice()),
+            block_size,
+        )
+    }
+
+    /// Create a disk on the heap holding a copy of `image`. If the image doesn't end on a block
+    /// boundary, its last block is padded with zeroes.
+    ///
+    /// # Panics
+    /// Panics if `block_size` is 0
+    #[must_use]
+    pub fn from_image(image: &[u8], block_size: usize) -> Self {
+        let mut disk = Self::new(block_size, image.len().div_ceil(block_size));
+        disk.backing.as_mut_slice()[..image.len()].copy_from_slice(image);
+
+        disk
+    }
+
+    /// Create a disk holding a copy of the disk image the bootloader loaded as `module`
+    ///
+    /// # Safety
+    /// The module must still be mapped (see `BootModule::data`)
+    ///
+    /// # Panics
+    /// Panics if `block_size` is 0
+    #[must_use]
+    pub unsafe fn from_module(module: &BootModule, block_size: usize) -> Self {
+        Self::from_image(unsafe { module.data() }, block_size)
+    }
+
+    /// Create a disk using `memory` for its blocks, as is. Bytes past the last whole block are
+    /// left unused.
+    ///
+    /// # Panics
+    /// Panics if `block_size` is 0
+    #[must_use]
+    pub fn from_memory(memory: &'static mut [u8], block_size: usize) -> Self {
+        let len = memory.len() - memory.len() % block_size;
+        Self::with_backing(Backing::Borrowed(&mut memory[..len]), block_size)
+    }
+
+    fn with_backing(backing: Backing, block_size: usize) -> Self {
+        assert!(block_size > 0, "A block must be at least one byte long");
+
+        Self {
+            backing,
+            block_size,
+            pending: VecDeque::new(),
+            next_id: 0,
+            reads: 0,
+            writes: 0,
+            discarded: 0,
+        }
+    }
+
+    /// Get the contents of the whole disk
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        self.backing.as_slice()
+    }
+
+    /// Get the amount of read requests submitted so far
+    #[must_use]
+    pub const fn reads(&self) -> usize {
+        self.reads
+    }
+
+    /// Get the amount of write requests submitted so far
+    #[must_use]
+    pub const fn writes(&self) -> usize {
+        self.writes
+    }
+
+    /// Get the amount of blocks discarded so far
+    #[must_use]
+    pub const fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Carry out `request`, whose blocks were already checked to be on the disk
+    fn complete(&mut self, mut request: Request) -> Result<(), StorageError> {
+        let blocks = request.blocks(self.block_size, self.block_count())?;
+        let range = blocks.start as usize * self.block_size..blocks.end as usize * self.block_size;
+
+        let data = self.backing.as_mut_slice();
+        // SAFETY: The submitter gave us the buffer until the completion is polled. Write buffers
+        // are only borrowed immutably by the submitter, so they're only read
+        match request.operation {
+            Operation::Read => unsafe { request.buffer.as_mut() }.copy_from_slice(&data[range]),
+            Operation::Write => data[range].copy_from_slice(unsafe { request.buffer.as_ref() }),
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.backing.as_slice().len() / self.block_size) as u64
+    }
+
+    fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    unsafe fn submit(&mut self, request: Request) -> Result<RequestId, StorageError> {
+        request.blocks(self.block_size, self.block_count())?;
+        match request.operation {
+            Operation::Read => self.reads += 1,
+            Operation::Write => self.writes += 1,
+        }
+
+        let id = RequestId(self.next_id);
+        self.next_id += 1;
+        self.pending.push_back((id, request));
+
+        Ok(id)
+    }
+
+    fn poll_completions(&mut self) -> Completions<'_> {
+        let pending = core::mem::take(&mut self.pending);
+        let completions: Vec<_> = pending
+            .into_iter()
+            .map(|(id, request)| (id, self.complete(request)))
+            .collect();
+
+        Box::new(completions.into_iter())
+    }
+
+    fn discard(&mut self, lba: u64, count: u64) -> Result<(), StorageError> {
+        let end = lba
+            .checked_add(count)
+            .filter(|&end| end <= self.block_count())
+            .ok_or(StorageError::OutOfRange)?;
+
+        // Discarded blocks read back as zeroes
+        self.backing.as_mut_slice()[lba as usize * self.block_size..end as usize * self.block_size]
+            .fill(0);
+        self.discarded += count;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 512;
+
+    #[test]
+    fn test_read_write() {
+        let mut disk = RamDisk::new(BLOCK_SIZE, 4);
+        assert_eq!(disk.block_count(), 4);
+
+        let mut buffer = [0; 2 * BLOCK_SIZE];
+        disk.write_blocks(2, &[0x5a; 2 * BLOCK_SIZE]).unwrap();
+        disk.read_blocks(1, &mut buffer).unwrap();
+        assert_eq!(buffer[..BLOCK_SIZE], [0; BLOCK_SIZE]);
+        assert_eq!(buffer[BLOCK_SIZE..], [0x5a; BLOCK_SIZE]);
+
+        assert_eq!(disk.write_blocks(3, &buffer), Err(StorageError::OutOfRange));
+        assert_eq!(
+            disk.read_blocks(0, &mut buffer[1..]),
+            Err(StorageError::InvalidBufferSize)
+        );
+
+        disk.discard(3, 1).unwrap();
+        assert_eq!(disk.discarded(), 1);
+        disk.read_blocks(2, &mut buffer).unwrap();
+        assert_eq!(buffer[..BLOCK_SIZE], [0x5a; BLOCK_SIZE]);
+        assert_eq!(buffer[BLOCK_SIZE..], [0; BLOCK_SIZE]);
+        assert_eq!(disk.discard(3, 2), Err(StorageError::OutOfRange));
+    }
+
+    #[test]
+    fn test_completions_are_polled() {
+        let mut disk = RamDisk::new(BLOCK_SIZE, 2);
+        let data = [0x11; BLOCK_SIZE];
+        let mut read_back = [0; BLOCK_SIZE];
+
+        let ids = unsafe {
+            [
+                disk.submit(Request::write(1, &data)).unwrap(),
+                disk.submit(Request::read(1, &mut read_back)).unwrap(),
+            ]
+        };
+        assert_eq!(disk.in_flight(), 2);
+        assert_eq!((disk.reads(), disk.writes()), (1, 1));
+        // Nothing is carried out until the completions are polled
+        assert_eq!(disk.data()[BLOCK_SIZE..], [0; BLOCK_SIZE]);
+
+        let completions: Vec<_> = disk.poll_completions().collect();
+        assert_eq!(completions, [(ids[0], Ok(())), (ids[1], Ok(()))]);
+        assert_eq!(disk.in_flight(), 0);
+        assert_eq!(disk.poll_completions().count(), 0);
+        assert_eq!(read_back, data);
+    }
+
+    #[test]
+    fn test_seeded_from_image() {
+        // The last block of the image is only partially there
+        let image: Vec<u8> = (0..BLOCK_SIZE + 10).map(|i| i as u8).collect();
+        let mut disk = RamDisk::from_image(&image, BLOCK_SIZE);
+        assert_eq!(disk.block_count(), 2);
+        assert_eq!(disk.data()[..image.len()], image);
+
+        let mut buffer = [0xff; BLOCK_SIZE];
+        disk.read_blocks(1, &mut buffer).unwrap();
+        assert_eq!(buffer[..10], image[BLOCK_SIZE..]);
+        assert_eq!(buffer[10..], [0; BLOCK_SIZE - 10]);
+
+        // Memory that's used in place, with a partial block at the end that's left unused
+        let memory = Box::leak(vec![0x33; 3 * BLOCK_SIZE + 1].into_boxed_slice());
+        let mut disk = RamDisk::from_memory(memory, BLOCK_SIZE);
+        assert_eq!(disk.block_count(), 3);
+        disk.read_blocks(2, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x33; BLOCK_SIZE]);
+    }
+}
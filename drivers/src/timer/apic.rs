@@ -6,9 +6,13 @@ use super::{
     Timer, TimerError,
     hpet::{self, AdditionalConfig, DeliveryMode, HPET, HpetTimer, TriggerMode},
 };
+use crate::clock::pm_timer::PM_TIMER;
 use core::{arch::x86_64::__cpuid_count, hint, time::Duration};
-use kernel::arch::x86_64::apic::lapic::{LocalApic, TimerDivisor, TimerMode};
-use kernel::arch::x86_64::event::__isr_stub_generic_irq_isr;
+use kernel::arch::x86_64::{
+    apic::lapic::{LocalApic, TimerDivisor, TimerMode},
+    cpu::features::cpu_features,
+};
+use utils::time;
 
 // TODO: Remove having a APIC field, we should just have a global static
 
@@ -22,6 +26,8 @@ pub struct ApicTimer {
     tsc_deadline_supported: bool,
     /// The local APIC this timer belongs to
     apic_id: u32,
+    /// The mode the timer was last configured with, or `None` if it wasn't configured yet
+    mode: Option<TimerMode>,
 }
 
 impl ApicTimer {
@@ -36,15 +42,13 @@ impl ApicTimer {
         logger::info!("APIC timer frequency: {} Mhz", base_frequency);
 
         // Cache the TSC deadline mode support
-        let tsc_deadline_supported = {
-            let res = unsafe { __cpuid_count(0x1, 0x0) };
-            (res.ecx >> 24) & 0b1 == 1
-        };
+        let tsc_deadline_supported = cpu_features().tsc_deadline;
 
         Self {
             base_frequency,
             tsc_deadline_supported,
             apic_id,
+            mode: None,
         }
     }
 
@@ -55,70 +59,103 @@ impl ApicTimer {
         // If these 2 aren't 0, then we can use CPUID result to read the frequency
         if res.ecx != 0 && res.ebx != 0 {
             res.ecx
+        } else if let Ok(hpet_timer) = HpetTimer::try_new() {
+            // If we can't read it from the CPUID, we need to calculate it using HPET. Don't wait
+            // for a timer if they're all taken, since the PM timer will do just as well:
+            Self::calibrate_with_hpet(apic_id, hpet_timer)
         } else {
-            // If we can't read it from the CPUID, we need to calculate it using HPET:
-            let mut hpet_timer = HpetTimer::new().unwrap();
-
-            // Translate the 100ms to ticks
-            let ticks = {
-                let hpet = HPET.lock();
-                hpet.time_to_cycles(Duration::from_millis(1000))
-            };
-
-            let apic = LocalApic::get_apic(apic_id);
-
-            // Set the divisor to 1, we want the timer to tick as fast as possible
-            apic.set_timer_divider_config(TimerDivisor::Div1);
-            // Configure the 2 timers to tick for a period longer than 100ms
-            apic.config_timer(u32::MAX, TimerMode::OneShot);
-
-            // We configure the HPET timer.
-            //
-            // We make sure we don't recieve the interrupts since we want to just poll, and so we
-            // use `EdgeTriggered` as well
-            //
-            // We configure it to run for 5000 just to make sure it doesn't interfere with out
-            // measurement
-            hpet_timer
-                .configure(
-                    Duration::from_secs(5000),
-                    hpet::TimerMode::OneShot,
-                    AdditionalConfig {
-                        receive_interrupts: false,
-                        delivery_mode: DeliveryMode::Interrupt(
-                            __isr_stub_generic_irq_isr,
-                            TriggerMode::EdgeTriggered,
-                        ),
-                    },
-                )
-                .unwrap();
-
-            // Enable both timers
-            apic.set_timer_disabled(false);
-
-            // Poll until we reached 100ms mark, then disable the timers
-            loop {
-                hint::spin_loop();
-                let hpet_timer_count = hpet_timer.read_main_counter();
-                if hpet_timer_count >= ticks {
-                    apic.set_timer_disabled(true);
-                    hpet_timer.set_disabled(true);
-                    break;
-                }
-            }
+            // And if there is no HPET either, the ACPI PM timer is always there
+            Self::calibrate_with_pm_timer(apic_id)
+        }
+    }
+
+    /// Measure the base frequency of the timer against the HPET
+    fn calibrate_with_hpet(apic_id: u32, mut hpet_timer: HpetTimer) -> u32 {
+        // Translate the 100ms to ticks
+        let ticks = {
+            let hpet = HPET.lock();
+            hpet.time_to_cycles(Duration::from_millis(1000))
+        };
 
-            // Find the delta (intial tick count - current tick count)
-            let ticks_delta = u32::MAX - apic.read_current_timer_count();
+        let apic = LocalApic::get_apic(apic_id);
+
+        // Set the divisor to 1, we want the timer to tick as fast as possible
+        apic.set_timer_divider_config(TimerDivisor::Div1);
+        // Configure the 2 timers to tick for a period longer than 100ms
+        apic.config_timer(u32::MAX, TimerMode::OneShot);
+
+        // We configure the HPET timer.
+        //
+        // We make sure we don't recieve the interrupts since we want to just poll, and so we
+        // use `EdgeTriggered` as well
+        //
+        // We configure it to run for 5000 just to make sure it doesn't interfere with out
+        // measurement
+        hpet_timer
+            .configure(
+                Duration::from_secs(5000),
+                hpet::TimerMode::OneShot,
+                AdditionalConfig {
+                    receive_interrupts: false,
+                    delivery_mode: DeliveryMode::Interrupt(|_| {}, TriggerMode::EdgeTriggered),
+                },
+            )
+            .unwrap();
+
+        // Enable both timers
+        apic.set_timer_disabled(false);
 
-            // NOTE: We technically need to mult `ticks_delta` by the TimerDivisor, but we set it
-            // to 1 so we don't need to worry about it
-            ticks_delta / 1_000_000 // Convert to MHz
+        // Poll until we reached 100ms mark, then disable the timers
+        loop {
+            hint::spin_loop();
+            let hpet_timer_count = hpet_timer.read_main_counter();
+            if hpet_timer_count >= ticks {
+                apic.set_timer_disabled(true);
+                hpet_timer.set_disabled(true);
+                break;
+            }
         }
+
+        // Find the delta (intial tick count - current tick count)
+        let ticks_delta = u32::MAX - apic.read_current_timer_count();
+
+        // NOTE: We technically need to mult `ticks_delta` by the TimerDivisor, but we set it
+        // to 1 so we don't need to worry about it
+        ticks_delta / 1_000_000 // Convert to MHz
+    }
+
+    /// Measure the base frequency of the timer against the ACPI PM timer
+    fn calibrate_with_pm_timer(apic_id: u32) -> u32 {
+        let apic = LocalApic::get_apic(apic_id);
+
+        apic.set_timer_divider_config(TimerDivisor::Div1);
+        apic.config_timer(u32::MAX, TimerMode::OneShot);
+        apic.set_timer_disabled(false);
+
+        let waited = PM_TIMER.lock().busy_wait(Duration::from_millis(100));
+        apic.set_timer_disabled(true);
+        assert!(
+            waited,
+            "No HPET or PM timer to calibrate the APIC timer with"
+        );
+
+        // The timer ran for 100ms, so 10 times that is a second's worth of ticks
+        let ticks_delta = u32::MAX - apic.read_current_timer_count();
+        ticks_delta / 100_000 // Convert to MHz
     }
 
     /// Convert a `Duration` into APIC timer clock ticks
-    const fn time_to_ticks(&self, time: Duration) -> u32 {
-        (self.base_frequency * 1_000_000) / time.as_micros() as u32
+    ///
+    /// The APIC timer's count register is only 32 bits, so the result saturates to `u32::MAX`
+    fn time_to_ticks(&self, time: Duration) -> u32 {
+        let ticks = time::duration_to_ticks(time, u64::from(self.base_frequency) * 1_000_000);
+
+        u32::try_from(ticks).unwrap_or(u32::MAX)
+    }
+
+    /// Convert APIC timer clock ticks into a `Duration`
+    fn ticks_to_time(&self, ticks: u32) -> Duration {
+        time::ticks_to_duration(u64::from(ticks), u64::from(self.base_frequency) * 1_000_000)
     }
 }
 
@@ -145,6 +182,7 @@ impl Timer for ApicTimer {
         let apic = LocalApic::get_apic(self.apic_id);
         apic.config_timer(ticks, timer_mode);
         apic.set_timer_disabled(false);
+        self.mode = Some(timer_mode);
 
         Ok(ticks as u64)
     }
@@ -154,4 +192,30 @@ impl Timer for ApicTimer {
 
         apic.set_timer_disabled(disable);
     }
+
+    /// NOTE: The count isn't used in TSC deadline mode, so `None` is returned for it
+    fn remaining(&self) -> Option<Duration> {
+        if !matches!(self.mode?, TimerMode::OneShot | TimerMode::Periodic) {
+            return None;
+        }
+
+        // The current count counts down to 0, where a one shot timer stays once it fires
+        match LocalApic::get_apic(self.apic_id).read_current_timer_count() {
+            0 => None,
+            ticks => Some(self.ticks_to_time(ticks)),
+        }
+    }
+
+    fn rearm(&mut self, time: Duration) -> Result<u64, TimerError> {
+        match self.mode.ok_or(TimerError::NotConfigured)? {
+            TimerMode::OneShot | TimerMode::Periodic => {
+                let ticks = self.time_to_ticks(time);
+                LocalApic::get_apic(self.apic_id).set_initial_timer_count(ticks);
+
+                Ok(u64::from(ticks))
+            }
+            // The deadline is written to an MSR, rather than counted down
+            _ => Err(TimerError::UnsupportedTimerMode),
+        }
+    }
 }
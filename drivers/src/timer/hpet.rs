@@ -1,17 +1,22 @@
 //! HPET driver implementation
 
-use super::{PIT_IRQ, RTC_IRQ, Timer, TimerError};
+use super::{PIT_IRQ, RTC_IRQ, Timer, TimerError, ticks_until, ticks_until_32};
+use crate::clock::MonotonicClock;
 use core::{ptr, time::Duration};
 use kernel::arch::x86_64::{
     apic::ioapic::{allocate_irq_at, gsi_to_irq},
-    interrupts::{IsrStub, register_irq},
+    event::IrqHandler,
+    interrupts::register_irq,
 };
 use modular_bitfield::prelude::*;
 use utils::{
     collections::id::{Id, tracker::IdTracker},
     mem::mmio::MmioArea,
     sanity_assert,
-    sync::spinlock::{SpinLock, SpinLockable},
+    sync::{
+        semaphore::Semaphore,
+        spinlock::{SpinLock, SpinLockable},
+    },
 };
 
 /// The different interrupt routing modes the HPET timer supports
@@ -37,7 +42,7 @@ pub enum DeliveryMode {
     /// The timer will issue interrupts as specified by `InterruptRoutingMode`.
     ///
     /// The interrupt type will be determined by the `TriggerMode`
-    Interrupt(IsrStub, TriggerMode),
+    Interrupt(IrqHandler, TriggerMode),
     /// The timer will deliver a FSB message instead of issuing an interrupt
     ///
     /// NOTE: When using this delivery mode, only `EdgeTriggered` interrupts can be used.
@@ -163,6 +168,8 @@ pub struct HpetTimer {
     id: Id,
     /// The bit width of the timer's comparator
     size_64_bits: bool,
+    /// The mode the timer was last configured with, or `None` if it wasn't configured yet
+    mode: Option<TimerMode>,
 }
 
 /// The HPET
@@ -183,10 +190,16 @@ pub struct Hpet {
     ///
     /// NOTE: This is not the same as the bit width of a specific timer's comparator.
     size_64_bits: bool,
+    /// The value of a 32 bit main counter when it was last read
+    last_counter: u32,
+    /// The amount of ticks of a 32 bit main counter since the HPET was initialized, which doesn't
+    /// wrap around like the counter does
+    ticks: u64,
 }
 
 // TODO: Move this out of here
 const NANO_TO_FEMTOSEC: u128 = 1_000_000;
+const FEMTOSEC_PER_SEC: u128 = 1_000_000_000_000_000;
 
 /// The global static HPET instance
 pub static HPET: SpinLock<Hpet> = SpinLock::new(Hpet {
@@ -196,8 +209,17 @@ pub static HPET: SpinLock<Hpet> = SpinLock::new(Hpet {
     timer_ids: IdTracker::uninit(),
     int_routing_mode: InterruptRoutingMode::Normal,
     size_64_bits: false,
+    last_counter: 0,
+    ticks: 0,
 });
 
+/// One permit for every HPET timer that isn't handed out, so `HpetTimer::new` can wait for a
+/// timer to be freed.
+///
+/// NOTE: Taken before the HPET's lock, since waiting for it while holding the lock would keep the
+/// timers from ever being freed
+static FREE_TIMERS: Semaphore = Semaphore::new(0);
+
 impl ReadableRegs {
     /// Offset to the HPET's `GeneralCapabilities` register
     const GENERAL_CAPABILITIES: usize = 0x0;
@@ -228,6 +250,9 @@ impl Hpet {
     ///
     /// IMPORTANT NOTE: If the time is not a multiple of the main clock period, it will be rounded
     /// up to the next multiple of the main clock period.
+    ///
+    /// NOTE: The HPET reports its period in femtoseconds rather than its frequency, so this doesn't
+    /// use `utils::time`: going through the frequency in whole Hz would truncate it, and drift.
     #[inline]
     pub const fn time_to_cycles(&self, time: Duration) -> u64 {
         let time_femtosec = time.as_nanos() * NANO_TO_FEMTOSEC;
@@ -243,6 +268,21 @@ impl Hpet {
         (adjusted_time / (self.main_clock_period as u128)) as u64
     }
 
+    /// Converts cycles of the main counter to time, rounded down to the nearest nanosecond
+    #[inline]
+    pub const fn cycles_to_time(&self, cycles: u64) -> Duration {
+        let time_femtosec = cycles as u128 * self.main_clock_period as u128;
+
+        Duration::from_nanos((time_femtosec / NANO_TO_FEMTOSEC) as u64)
+    }
+
+    /// Returns true if the HPET was initialized
+    #[inline]
+    #[must_use]
+    pub const fn is_available(&self) -> bool {
+        self.main_clock_period != 0
+    }
+
     /// Set the HPETs interrupt routing mode
     ///
     /// SAFETY: This function is unsafe because calling it not during initialization can cause UB.
@@ -302,6 +342,8 @@ impl Hpet {
             timer_ids: IdTracker::uninit(),
             int_routing_mode,
             size_64_bits: false,
+            last_counter: 0,
+            ticks: 0,
         };
 
         let capabilities: GeneralCapabilities =
@@ -328,6 +370,7 @@ impl Hpet {
 
         // Construct the range
         hpet.timer_ids = IdTracker::new(Id(0), Id(max_timer_index));
+        FREE_TIMERS.add_permits(max_timer_index + 1);
 
         hpet
     }
@@ -347,6 +390,32 @@ impl Hpet {
     }
 }
 
+impl MonotonicClock for Hpet {
+    #[inline]
+    fn frequency(&self) -> u64 {
+        (FEMTOSEC_PER_SEC / u128::from(self.main_clock_period)) as u64
+    }
+
+    /// Get the value of the main counter.
+    ///
+    /// 32 bit main counters are extended to 64 bits like the PM timer's, so this must be called at
+    /// least once per wraparound of theirs (every few minutes), or the wraparounds in between are
+    /// missed
+    fn ticks(&mut self) -> u64 {
+        let counter = unsafe { self.area.read(ReadableRegs::MAIN_COUNTER_VALUE) };
+        if self.size_64_bits {
+            return counter;
+        }
+
+        // The upper half of a 32 bit counter isn't part of it
+        let now = counter as u32;
+        self.ticks += u64::from(now.wrapping_sub(self.last_counter));
+        self.last_counter = now;
+
+        self.ticks
+    }
+}
+
 impl HpetTimer {
     /// Configure and initialize the delivery mode of the timer
     fn config_delivery_mode(
@@ -370,7 +439,7 @@ impl HpetTimer {
                         .write(self.fsb_interrupt_route_reg_offset(), fsb_info.into());
                 };
             }
-            DeliveryMode::Interrupt(isr_stub, int_type) => {
+            DeliveryMode::Interrupt(handler, int_type) => {
                 let int_routing_mode = {
                     let hpet = HPET.lock();
                     hpet.int_routing_mode
@@ -402,7 +471,7 @@ impl HpetTimer {
                 };
 
                 // Register the IRQ
-                unsafe { register_irq(irq, isr_stub) };
+                unsafe { register_irq(irq, handler) }.map_err(|_| TimerError::IrqError)?;
 
                 // IMPORTANT! Having FSB enabled overrides interrupts
                 config.set_fsb_int_enable(false.into());
@@ -413,15 +482,41 @@ impl HpetTimer {
         Ok(())
     }
 
-    /// Initialize the timer with the given `time `and `timer_mode`
+    /// Get a free timer, waiting for one to be freed if they are all taken.
+    ///
+    /// # Errors
+    /// Fails with `TimerError::NoTimerAvailable` only if there is no HPET at all
     pub fn new() -> Result<Self, TimerError> {
+        if !HPET.lock().is_available() {
+            return Err(TimerError::NoTimerAvailable);
+        }
+
+        FREE_TIMERS.acquire();
+        Ok(Self::with_permit())
+    }
+
+    /// Get a free timer, without waiting if they are all taken.
+    ///
+    /// # Errors
+    /// Fails with `TimerError::NoTimerAvailable` if all the timers are taken, or if there is no
+    /// HPET at all
+    pub fn try_new() -> Result<Self, TimerError> {
+        if !FREE_TIMERS.try_acquire() {
+            return Err(TimerError::NoTimerAvailable);
+        }
+
+        Ok(Self::with_permit())
+    }
+
+    /// Hand out a free timer, after a permit was taken from `FREE_TIMERS` for it
+    fn with_permit() -> Self {
         let mut hpet = HPET.lock();
 
         let base = hpet.area.base();
         let id = hpet
             .timer_ids
             .allocate()
-            .map_err(|_| TimerError::NoTimerAvailable)?;
+            .expect("No free HPET timer even though a permit was taken");
 
         // We don't need HPET anymore, so release the lock
         drop(hpet);
@@ -430,6 +525,7 @@ impl HpetTimer {
             area: MmioArea::new(base),
             id,
             size_64_bits: false,
+            mode: None,
         };
 
         // Checking if the timer's comparator is 64/32 bits
@@ -440,7 +536,7 @@ impl HpetTimer {
             config.size_capable() == 1
         };
 
-        Ok(ret)
+        ret
     }
 
     /// Check if the timer has fired
@@ -495,6 +591,40 @@ impl HpetTimer {
     pub const fn id(&self) -> Id {
         self.id
     }
+
+    /// Write the timer's configuration, and arm it to fire when the main counter reaches
+    /// `target_cycles`, and every `cycles_delta` cycles after that if it's periodic
+    fn program(
+        &mut self,
+        mut config: TimerConfiguration,
+        timer_mode: TimerMode,
+        target_cycles: TimerComparator,
+        cycles_delta: TimerComparator,
+    ) {
+        match timer_mode {
+            TimerMode::Periodic => {
+                // If we're operating in `Periodic` mode, we need to also enable the `set value` flag
+                // to tell the HPET this write isn't an ordinary write (make it write to the
+                // comparator) and then the second write will only modify the accumulator, so when
+                // the interrupt is triggered the comparator is incremented by `cycles_delta` count
+
+                config.set_value_set(1);
+                unsafe {
+                    self.area.write(self.config_reg_offset(), config.into());
+                    self.area.write(self.comparator_reg_offset(), target_cycles);
+                    self.area.write(self.comparator_reg_offset(), cycles_delta);
+                };
+            }
+            TimerMode::OneShot => {
+                // On `OneShot`, it's simply write "delta + main timer" and write the config as
+                // usual
+                unsafe {
+                    self.area.write(self.config_reg_offset(), config.into());
+                    self.area.write(self.comparator_reg_offset(), target_cycles);
+                };
+            }
+        }
+    }
 }
 
 impl Timer for HpetTimer {
@@ -534,29 +664,8 @@ impl Timer for HpetTimer {
         config.set_timer_type(timer_mode as u8);
         config.set_int_enable(additional_config.receive_interrupts.into());
 
-        match timer_mode {
-            TimerMode::Periodic => {
-                // If we're operating in `Periodic` mode, we need to also enable the `set value` flag
-                // to tell the HPET this write isn't an ordinary write (make it write to the
-                // comparator) and then the second write will only modify the accumulator, so when
-                // the interrupt is triggered the comparator is incremented by `cycles_delta` count
-
-                config.set_value_set(1);
-                unsafe {
-                    self.area.write(self.config_reg_offset(), config.into());
-                    self.area.write(self.comparator_reg_offset(), target_cycles);
-                    self.area.write(self.comparator_reg_offset(), cycles_delta);
-                };
-            }
-            TimerMode::OneShot => {
-                // On `OneShot`, it's simply write "delta + main timer" and write the config as
-                // usual
-                unsafe {
-                    self.area.write(self.config_reg_offset(), config.into());
-                    self.area.write(self.comparator_reg_offset(), target_cycles);
-                };
-            }
-        }
+        self.program(config, timer_mode, target_cycles, cycles_delta);
+        self.mode = Some(timer_mode);
 
         Ok(cycles_delta)
     }
@@ -579,6 +688,44 @@ impl Timer for HpetTimer {
             self.area.write(self.config_reg_offset(), config.into());
         };
     }
+
+    fn remaining(&self) -> Option<Duration> {
+        // Bail out if the timer was never armed
+        self.mode?;
+
+        let (now, deadline) = unsafe {
+            (
+                self.read_main_counter(),
+                self.area.read(self.comparator_reg_offset()),
+            )
+        };
+        // The comparator of a periodic timer moves forward whenever it fires, so it always holds
+        // the next deadline. A 32 bit comparator is only matched against the low half of the main
+        // counter, so both wrap around at 32 bits
+        let cycles = if self.size_64_bits {
+            ticks_until(now, deadline)?
+        } else {
+            ticks_until_32(now as u32, deadline as u32)?
+        };
+
+        Some(HPET.lock().cycles_to_time(cycles))
+    }
+
+    fn rearm(&mut self, time: Duration) -> Result<u64, TimerError> {
+        let timer_mode = self.mode.ok_or(TimerError::NotConfigured)?;
+
+        let hpet = HPET.lock();
+        let cycles_delta: TimerComparator = hpet.time_to_cycles(time);
+        let target_cycles: TimerComparator =
+            unsafe { hpet.area.read(ReadableRegs::MAIN_COUNTER_VALUE) + cycles_delta };
+        drop(hpet);
+
+        // Everything but the comparator stays as it was configured
+        let config: TimerConfiguration = unsafe { self.area.read(self.config_reg_offset()).into() };
+        self.program(config, timer_mode, target_cycles, cycles_delta);
+
+        Ok(cycles_delta)
+    }
 }
 
 impl SpinLockable for HpetTimer {
@@ -588,6 +735,7 @@ impl SpinLockable for HpetTimer {
             let mut hpet = HPET.lock();
             hpet.timer_ids.free(self.id).unwrap();
         };
+        FREE_TIMERS.release();
     }
 }
 
@@ -598,3 +746,40 @@ unsafe impl Send for HpetTimer {}
 unsafe impl Sync for HpetTimer {}
 
 impl SpinLockable for Hpet {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_32_bit_ticks_extend_past_the_counter() {
+        // Fake registers, with the main counter about to wrap around. The upper half of a 32 bit
+        // counter is garbage as far as we're concerned
+        let regs = [const { Cell::new(0_u64) }; 32];
+        let counter = &regs[ReadableRegs::MAIN_COUNTER_VALUE / size_of::<u64>()];
+        counter.set(0xdead_0000_ffff_ff00);
+
+        let mut hpet = Hpet {
+            area: MmioArea::new(regs.as_ptr().cast::<u64>().cast_mut()),
+            main_clock_period: 10,
+            minimum_tick: 0,
+            timer_ids: IdTracker::uninit(),
+            int_routing_mode: InterruptRoutingMode::Normal,
+            size_64_bits: false,
+            last_counter: 0xffff_ff00,
+            ticks: 0,
+        };
+        assert_eq!(hpet.ticks(), 0);
+
+        counter.set(0xdead_0000_ffff_fff0);
+        assert_eq!(hpet.ticks(), 0xf0);
+        counter.set(0xdead_0000_0000_0010);
+        assert_eq!(hpet.ticks(), 0x110);
+
+        // 64 bit counters are taken as they are
+        hpet.size_64_bits = true;
+        counter.set(0x1_0000_0010);
+        assert_eq!(hpet.ticks(), 0x1_0000_0010);
+    }
+}
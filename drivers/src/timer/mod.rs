@@ -5,7 +5,8 @@ use core::time::Duration;
 #[cfg(target_arch = "x86_64")]
 pub mod apic;
 pub mod hpet;
-// pub mod pit;
+#[cfg(target_arch = "x86_64")]
+pub mod pit;
 
 const PIT_IRQ: u8 = 0;
 const RTC_IRQ: u8 = 8;
@@ -25,6 +26,8 @@ pub enum TimerError {
     IdtError,
     /// IRQ mapping error (eg not IRQ lines available, IRQ line already taken)
     IrqError,
+    /// The timer has to be configured before doing this
+    NotConfigured,
 }
 
 /// A trait that represents a timer. This trait is implemented by all timers in the system.
@@ -55,6 +58,38 @@ pub trait Timer: Sized {
 
     /// Disable/enable the timer
     fn set_disabled(&mut self, disable: bool);
+
+    /// Get the time left until the timer fires next, or `None` if it isn't armed (it was never
+    /// configured, or it's a one shot timer that already fired)
+    fn remaining(&self) -> Option<Duration>;
+
+    /// Rearm the timer to fire after `time`, keeping the mode and configuration it was last
+    /// configured with, and return the amount of clock ticks that the timer will tick for
+    ///
+    /// # Errors
+    /// Returns `TimerError::NotConfigured` if the timer wasn't configured yet, or another error if
+    /// the timer cannot be rearmed
+    fn rearm(&mut self, time: Duration) -> Result<u64, TimerError>;
+}
+
+/// Get the amount of ticks left until a counter that's currently at `now` reaches `deadline`, or
+/// `None` if it already did
+#[inline]
+const fn ticks_until(now: u64, deadline: u64) -> Option<u64> {
+    match deadline.checked_sub(now) {
+        Some(0) | None => None,
+        ticks => ticks,
+    }
+}
+
+/// Like `ticks_until`, for a 32 bit counter and deadline, which both wrap around. Deadlines more
+/// than half the counter's range ahead are taken to have already passed
+#[inline]
+const fn ticks_until_32(now: u32, deadline: u32) -> Option<u64> {
+    match deadline.wrapping_sub(now).cast_signed() {
+        ..=0 => None,
+        ticks => Some(ticks.unsigned_abs() as u64),
+    }
 }
 
 // /// Initializes either HPET or the PIT
@@ -63,3 +98,131 @@ pub trait Timer: Sized {
 //         ioapic::set_disabled(interrupts::PIT_IRQ, false).expect("Failed to set PIT IRQ disabled");
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::time;
+
+    /// The frequency of the mock timer's counter, in Hz
+    const MOCK_FREQUENCY: u64 = 1_000_000;
+
+    /// A timer whose counter only moves when it's told to
+    struct MockTimer {
+        /// The counter's current value
+        now: u64,
+        /// The value the counter fires at, if the timer is armed
+        deadline: Option<u64>,
+        /// The interval between the timer's firings, if it's periodic
+        period: Option<u64>,
+    }
+
+    impl MockTimer {
+        /// Move the counter forward by `ticks`, handling the periodic timer's refiring
+        fn advance(&mut self, ticks: u64) {
+            self.now += ticks;
+            if let (Some(deadline), Some(period)) = (self.deadline, self.period)
+                && self.now >= deadline
+            {
+                let fired = (self.now - deadline) / period + 1;
+                self.deadline = Some(deadline + fired * period);
+            }
+        }
+    }
+
+    impl Timer for MockTimer {
+        /// Whether the timer is periodic
+        type TimerMode = bool;
+        type AdditionalConfig = ();
+
+        fn configure(
+            &mut self,
+            time: Duration,
+            periodic: bool,
+            _additional_config: (),
+        ) -> Result<u64, TimerError> {
+            let ticks = time::duration_to_ticks(time, MOCK_FREQUENCY);
+            if ticks == 0 {
+                return Err(TimerError::InvalidTimePeriod);
+            }
+
+            self.period = periodic.then_some(ticks);
+            self.deadline = Some(self.now + ticks);
+
+            Ok(ticks)
+        }
+
+        fn set_disabled(&mut self, _disable: bool) {}
+
+        fn remaining(&self) -> Option<Duration> {
+            let ticks = ticks_until(self.now, self.deadline?)?;
+
+            Some(time::ticks_to_duration(ticks, MOCK_FREQUENCY))
+        }
+
+        fn rearm(&mut self, time: Duration) -> Result<u64, TimerError> {
+            if self.deadline.is_none() {
+                return Err(TimerError::NotConfigured);
+            }
+
+            self.configure(time, self.period.is_some(), ())
+        }
+    }
+
+    #[test]
+    fn test_remaining() {
+        let mut timer = MockTimer {
+            now: 500,
+            deadline: None,
+            period: None,
+        };
+        assert_eq!(timer.remaining(), None);
+        assert!(matches!(
+            timer.rearm(Duration::from_millis(1)),
+            Err(TimerError::NotConfigured)
+        ));
+
+        // A one shot timer counts down, and isn't armed anymore once it fires
+        assert_eq!(
+            timer
+                .configure(Duration::from_millis(10), false, ())
+                .unwrap(),
+            10_000
+        );
+        assert_eq!(timer.remaining(), Some(Duration::from_millis(10)));
+        timer.advance(2_500);
+        assert_eq!(timer.remaining(), Some(Duration::from_micros(7_500)));
+        timer.advance(7_500);
+        assert_eq!(timer.remaining(), None);
+
+        // Until it's rearmed
+        assert_eq!(timer.rearm(Duration::from_millis(3)).unwrap(), 3_000);
+        assert_eq!(timer.remaining(), Some(Duration::from_millis(3)));
+
+        // A periodic timer is always armed, and keeps its mode when rearmed
+        timer.configure(Duration::from_millis(4), true, ()).unwrap();
+        timer.advance(9_000);
+        assert_eq!(timer.remaining(), Some(Duration::from_millis(3)));
+        timer.rearm(Duration::from_millis(1)).unwrap();
+        timer.advance(1_500);
+        assert_eq!(timer.remaining(), Some(Duration::from_micros(500)));
+    }
+
+    #[test]
+    fn test_ticks_until() {
+        assert_eq!(ticks_until(10, 25), Some(15));
+        assert_eq!(ticks_until(25, 25), None);
+        assert_eq!(ticks_until(30, 25), None);
+    }
+
+    #[test]
+    fn test_ticks_until_32() {
+        assert_eq!(ticks_until_32(10, 25), Some(15));
+        assert_eq!(ticks_until_32(25, 25), None);
+        assert_eq!(ticks_until_32(30, 25), None);
+
+        // The deadline is past the point the counter wraps around
+        assert_eq!(ticks_until_32(0xffff_fff0, 0x10), Some(0x20));
+        assert_eq!(ticks_until_32(0x10, 0xffff_fff0), None);
+    }
+}
@@ -2,29 +2,30 @@
 
 use core::time::Duration;
 
-use macros::isr;
+use kernel::arch::x86_64::{
+    apic::ioapic::{self, allocate_irq_at},
+    cpu::{io_wait, outb_8},
+    interrupts::register_irq,
+};
 use modular_bitfield::prelude::*;
-
-use crate::{
-    kernel::archx86_64::{
-        apic::{ioapic, lapic::LocalApic},
-        cpu::outb_8,
-        interrupts,
-    },
-    dev::register_irq,
+use utils::{
     sync::spinlock::{SpinLock, SpinLockable},
+    time,
 };
 
 use super::{PIT_IRQ, Timer, TimerError};
 
-#[derive(Clone, Copy)]
+/// The frequency the PIT's channels count at, in Hz
+const BASE_FREQUENCY: u64 = 1_193_182;
+
+/// The command register. The fields are laid out from the lowest bit up
 #[bitfield(bits = 8)]
-#[repr(u8)]
+#[derive(Clone, Copy)]
 struct Command {
-    channel: B2,
-    access_mode: B2,
-    operating_mode: B3,
     bcd: B1,
+    operating_mode: B3,
+    access_mode: B2,
+    channel: B2,
 }
 
 /// The port of each channel
@@ -62,7 +63,7 @@ enum AccessMode {
 }
 
 /// The different operating modes of the PIT.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum OperatingMode {
     /// On a `ChannelPort::Command` write the output signal turns 0, until the channel's reload
@@ -92,10 +93,13 @@ pub enum OperatingMode {
 
 // TODO: Overcome the limitation of having this be a non ZST?
 
-pub static PIT: SpinLock<Pit> = SpinLock::new(Pit(0));
+pub static PIT: SpinLock<Pit> = SpinLock::new(Pit { mode: None });
 
 #[derive(Debug)]
-pub struct Pit(u8);
+pub struct Pit {
+    /// The mode channel 0 was last configured with, or `None` if it wasn't configured yet
+    mode: Option<OperatingMode>,
+}
 
 impl Timer for Pit {
     type TimerMode = OperatingMode;
@@ -107,27 +111,23 @@ impl Timer for Pit {
         operating_mode: Self::TimerMode,
         _additional_config: Self::AdditionalConfig,
     ) -> Result<u64, TimerError> {
-        // Register the PITs IRQ
-        unsafe {
-            register_irq(PIT_IRQ, __isr_stub_pit_isr);
-        };
-
-        // Setup the command to write
-        let command = Command::new()
-            .with_channel(Channel::Channel0 as u8)
-            .with_access_mode(AccessMode::LowAndHighByte as u8)
-            .with_operating_mode(operating_mode as u8)
-            .with_bcd(false.into());
-
         // Find out which divisor we need to use
         let cycles = Pit::time_to_cycles(period, operating_mode)?;
 
+        // Register the PITs IRQ, the first time it's configured
+        if self.mode.is_none() {
+            unsafe {
+                allocate_irq_at(PIT_IRQ).map_err(|_| TimerError::IrqError)?;
+                register_irq(PIT_IRQ, pit_irq).map_err(|_| TimerError::IrqError)?;
+            };
+        }
+
         unsafe {
-            self.write(command, cycles);
+            Pit::write(Pit::command(operating_mode), cycles);
         };
+        self.mode = Some(operating_mode);
 
-        println!("here!");
-        Ok(cycles as u64)
+        Ok(Pit::reload_ticks(cycles))
     }
 
     #[inline]
@@ -136,49 +136,100 @@ impl Timer for Pit {
             ioapic::set_disabled(PIT_IRQ, status).expect("Failed to set PIT IRQ disabled");
         }
     }
+
+    /// NOTE: The PIT's count can't be read back without racing its output, so this is never
+    /// known
+    fn remaining(&self) -> Option<Duration> {
+        None
+    }
+
+    fn rearm(&mut self, time: Duration) -> Result<u64, TimerError> {
+        let operating_mode = self.mode.ok_or(TimerError::NotConfigured)?;
+        let cycles = Pit::time_to_cycles(time, operating_mode)?;
+
+        unsafe {
+            Pit::write(Pit::command(operating_mode), cycles);
+        };
+
+        Ok(Pit::reload_ticks(cycles))
+    }
 }
 
 impl Pit {
+    /// The command setting channel 0 up to count in `operating_mode`, with its whole reload value
+    /// written next
+    fn command(operating_mode: OperatingMode) -> Command {
+        Command::new()
+            .with_channel(Channel::Channel0 as u8)
+            .with_access_mode(AccessMode::LowAndHighByte as u8)
+            .with_operating_mode(operating_mode as u8)
+            .with_bcd(false.into())
+    }
+
+    /// Get the amount of ticks a reload value of `cycles` counts for
+    const fn reload_ticks(cycles: u16) -> u64 {
+        if cycles == 0 { 0x1_0000 } else { cycles as u64 }
+    }
+
     const fn time_to_cycles(
         period: Duration,
         operating_mode: OperatingMode,
     ) -> Result<u16, TimerError> {
-        const BASE_FREQUENCY: u32 = 1193182; // Hz
-
+        let cycles = time::duration_to_ticks(period, BASE_FREQUENCY);
         match operating_mode {
             OperatingMode::RateGenerator
             | OperatingMode::SquareWaveGenerator
             | OperatingMode::_RateGenerator2
             | OperatingMode::_SquareWaveGenerator2
-                if period.as_micros() == 0 =>
+                if cycles == 0 =>
             {
                 return Err(TimerError::InvalidTimePeriod);
             }
             _ => (),
-        };
-
-        let cycles = (BASE_FREQUENCY / period.as_micros() as u32) as u16;
+        }
 
-        Ok(cycles)
+        // The reload register is 16 bits wide, and 0 stands for the longest period, 0x10000. A
+        // one shot shorter than a tick is rounded up to a single tick instead of wrapping to it
+        match cycles {
+            0 => Ok(1),
+            0x1_0000 => Ok(0),
+            cycles if cycles > 0x1_0000 => Err(TimerError::InvalidTimePeriod),
+            cycles => Ok(cycles as u16),
+        }
     }
 
-    unsafe fn write(&mut self, command: Command, divisor: u16) {
-        unsafe {
-            let val: u8 = command.into();
-            outb_8(ChannelPort::Command as u16, val);
+    unsafe fn write(command: Command, divisor: u16) {
+        Self::write_with(
+            command,
+            divisor,
+            |port, value| unsafe { outb_8(port, value) },
+            io_wait,
+        );
+    }
 
-            outb_8(ChannelPort::Channel0 as u16, (divisor & 0xff) as u8);
-            outb_8(ChannelPort::Channel0 as u16, ((divisor >> 8) & 0xff) as u8);
-        };
+    /// Same as `write`, with `out` writing a byte to a port, and `wait` waiting for the PIT to
+    /// take it in before the next one
+    fn write_with(
+        command: Command,
+        divisor: u16,
+        mut out: impl FnMut(u16, u8),
+        mut wait: impl FnMut(),
+    ) {
+        let [command] = command.into_bytes();
+        let [low, high] = divisor.to_le_bytes();
+        for (port, value) in [
+            (ChannelPort::Command, command),
+            (ChannelPort::Channel0, low),
+            (ChannelPort::Channel0, high),
+        ] {
+            out(port as u16, value);
+            wait();
+        }
     }
 }
 
-#[isr]
-fn pit_isr() {
-    println!("recieved PIT interrupt!");
-    let this_lapic_id = LocalApic::get_this_apic_id();
-    let lapic = LocalApic::get_apic(this_lapic_id);
-    lapic.signal_eoi();
+fn pit_irq(_vector: u8) {
+    logger::println!("recieved PIT interrupt!");
 }
 
 impl SpinLockable for Pit {
@@ -186,3 +237,65 @@ impl SpinLockable for Pit {
         self.set_disabled(true);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{vec, vec::Vec};
+
+    /// A port access the PIT was programmed with
+    #[derive(Debug, PartialEq, Eq)]
+    enum Access {
+        Out(u16, u8),
+        Wait,
+    }
+
+    #[test]
+    fn test_time_to_cycles() {
+        // `ticks_to_duration` truncates, so round up by a nanosecond to land on the exact tick
+        let ticks =
+            |ticks| time::ticks_to_duration(ticks, BASE_FREQUENCY) + Duration::from_nanos(1);
+
+        // A sub tick one shot fires after a single tick, not after the longest period
+        assert!(matches!(
+            Pit::time_to_cycles(Duration::ZERO, OperatingMode::InterruptOnTerminalCount),
+            Ok(1)
+        ));
+        assert!(matches!(
+            Pit::time_to_cycles(ticks(0x1_0000), OperatingMode::InterruptOnTerminalCount),
+            Ok(0)
+        ));
+        assert!(matches!(
+            Pit::time_to_cycles(ticks(0x1_0001), OperatingMode::RateGenerator),
+            Err(TimerError::InvalidTimePeriod)
+        ));
+        assert!(matches!(
+            Pit::time_to_cycles(Duration::ZERO, OperatingMode::RateGenerator),
+            Err(TimerError::InvalidTimePeriod)
+        ));
+    }
+
+    #[test]
+    fn test_write_waits_between_port_writes() {
+        let accesses = core::cell::RefCell::new(Vec::new());
+        let command = Pit::command(OperatingMode::RateGenerator);
+
+        Pit::write_with(
+            command,
+            0x1234,
+            |port, value| accesses.borrow_mut().push(Access::Out(port, value)),
+            || accesses.borrow_mut().push(Access::Wait),
+        );
+        assert_eq!(
+            accesses.into_inner(),
+            vec![
+                Access::Out(0x43, 0b0011_0100),
+                Access::Wait,
+                Access::Out(0x40, 0x34),
+                Access::Wait,
+                Access::Out(0x40, 0x12),
+                Access::Wait,
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Support for USB host controllers, and the USB devices connected to them
+
+pub mod xhci;
+
+/// The speed a USB device operates at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbSpeed {
+    /// USB 1.x full speed (12 Mb/s)
+    Full,
+    /// USB 1.x low speed (1.5 Mb/s)
+    Low,
+    /// USB 2.0 high speed (480 Mb/s)
+    High,
+    /// USB 3.x super speed (5 Gb/s)
+    Super,
+    /// USB 3.1+ super speed plus (10 Gb/s and up)
+    SuperPlus,
+}
+
+impl UsbSpeed {
+    /// The max packet size of the default control endpoint, before reading the device descriptor
+    #[inline]
+    #[must_use]
+    pub const fn default_max_packet_size(self) -> u16 {
+        match self {
+            Self::Low | Self::Full => 8,
+            Self::High => 64,
+            Self::Super | Self::SuperPlus => 512,
+        }
+    }
+}
+
+/// The standard USB device descriptor
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceDescriptor {
+    /// The size of the descriptor (18)
+    pub length: u8,
+    /// The type of the descriptor (`DescriptorType::Device`)
+    pub descriptor_type: u8,
+    /// The USB version the device complies with, in BCD
+    pub usb_version: u16,
+    /// The class code of the device
+    pub class: u8,
+    /// The subclass code of the device
+    pub subclass: u8,
+    /// The protocol code of the device
+    pub protocol: u8,
+    /// The max packet size of the default control endpoint
+    pub max_packet_size0: u8,
+    /// The vendor ID of the device
+    pub vendor_id: u16,
+    /// The product ID of the device
+    pub product_id: u16,
+    /// The release number of the device, in BCD
+    pub device_version: u16,
+    /// The index of the manufacturer string descriptor
+    pub manufacturer_index: u8,
+    /// The index of the product string descriptor
+    pub product_index: u8,
+    /// The index of the serial number string descriptor
+    pub serial_number_index: u8,
+    /// The amount of configurations the device has
+    pub configuration_count: u8,
+}
+
+/// The standard descriptor types
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorType {
+    /// A `DeviceDescriptor`
+    Device = 1,
+    /// A configuration descriptor
+    Configuration = 2,
+    /// A string descriptor
+    String = 3,
+    /// An interface descriptor
+    Interface = 4,
+    /// An endpoint descriptor
+    Endpoint = 5,
+}
+
+/// A setup packet, sent at the start of every control transfer
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupPacket {
+    /// The direction, type and recipient of the request
+    pub request_type: u8,
+    /// The request itself
+    pub request: u8,
+    /// Request specific
+    pub value: u16,
+    /// Request specific
+    pub index: u16,
+    /// The amount of bytes to transfer in the data stage
+    pub length: u16,
+}
+
+impl SetupPacket {
+    /// The `GET_DESCRIPTOR` standard request
+    const GET_DESCRIPTOR: u8 = 6;
+    /// Device to host, standard request, device recipient
+    const DEVICE_TO_HOST: u8 = 0x80;
+
+    /// Create a `GET_DESCRIPTOR` request for the descriptor of the given type and index
+    #[inline]
+    #[must_use]
+    pub const fn get_descriptor(descriptor_type: DescriptorType, index: u8, length: u16) -> Self {
+        Self {
+            request_type: Self::DEVICE_TO_HOST,
+            request: Self::GET_DESCRIPTOR,
+            value: ((descriptor_type as u16) << 8) | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    /// Returns true if the data stage (if there is one) moves data from the device to the host
+    #[inline]
+    #[must_use]
+    pub const fn is_device_to_host(&self) -> bool {
+        self.request_type & Self::DEVICE_TO_HOST != 0
+    }
+
+    /// Get the setup packet as the raw `u64` it's sent as
+    #[inline]
+    #[must_use]
+    pub const fn as_u64(&self) -> u64 {
+        self.request_type as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
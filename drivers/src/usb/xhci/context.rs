@@ -0,0 +1,243 @@
+//! The device, slot and endpoint contexts the controller uses to keep track of devices
+
+use super::super::UsbSpeed;
+use utils::mem::PhysAddr;
+
+/// Set `width` bits starting at bit `shift` of `dword` to `value`
+#[inline]
+const fn set_bits(dword: &mut u32, shift: u32, width: u32, value: u32) {
+    let mask = ((1 << width) - 1) << shift;
+    *dword = (*dword & !mask) | ((value << shift) & mask);
+}
+
+/// The Input Control Context, telling the controller which contexts to evaluate in a command
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct InputControlContext([u32; 8]);
+
+/// The Slot Context, describing the device as a whole
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct SlotContext([u32; 8]);
+
+/// An Endpoint Context, describing a single endpoint of a device
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct EndpointContext([u32; 8]);
+
+/// The endpoint types an `EndpointContext` can describe
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum EndpointType {
+    /// A bidirectional control endpoint
+    Control = 4,
+}
+
+impl InputControlContext {
+    /// Mark the context at device context index `index` to be added (i.e. evaluated)
+    #[inline]
+    pub(super) const fn add(&mut self, index: usize) {
+        self.0[1] |= 1 << index;
+    }
+}
+
+impl SlotContext {
+    /// Set the speed of the device
+    #[inline]
+    pub(super) const fn set_speed(&mut self, speed: UsbSpeed) {
+        set_bits(&mut self.0[0], 20, 4, speed_id(speed));
+    }
+
+    /// Set the index of the last valid endpoint context
+    #[inline]
+    pub(super) const fn set_context_entries(&mut self, entries: u32) {
+        set_bits(&mut self.0[0], 27, 5, entries);
+    }
+
+    /// Set the (1 based) number of the root hub port the device is connected to
+    #[inline]
+    pub(super) const fn set_root_hub_port(&mut self, port: u8) {
+        set_bits(&mut self.0[1], 16, 8, port as u32);
+    }
+
+    /// Get the USB address the controller assigned to the device
+    #[inline]
+    pub(super) const fn usb_device_address(&self) -> u8 {
+        self.0[3] as u8
+    }
+}
+
+impl EndpointContext {
+    /// Set the type of the endpoint
+    #[inline]
+    pub(super) const fn set_endpoint_type(&mut self, endpoint_type: EndpointType) {
+        set_bits(&mut self.0[1], 3, 3, endpoint_type as u32);
+    }
+
+    /// Set the amount of consecutive errors the controller allows before halting the endpoint
+    #[inline]
+    pub(super) const fn set_error_count(&mut self, count: u32) {
+        set_bits(&mut self.0[1], 1, 2, count);
+    }
+
+    /// Set the max packet size of the endpoint
+    #[inline]
+    pub(super) const fn set_max_packet_size(&mut self, size: u16) {
+        set_bits(&mut self.0[1], 16, 16, size as u32);
+    }
+
+    /// Set the transfer ring the endpoint uses, and the cycle state of its first TRB
+    #[inline]
+    pub(super) const fn set_dequeue_pointer(&mut self, ring: PhysAddr, cycle: bool) {
+        let addr = ring.0 as u64 | cycle as u64;
+        self.0[2] = addr as u32;
+        self.0[3] = (addr >> 32) as u32;
+    }
+
+    /// Set the average length of the TRBs on the endpoint's transfer ring
+    #[inline]
+    pub(super) const fn set_average_trb_length(&mut self, length: u16) {
+        set_bits(&mut self.0[4], 0, 16, length as u32);
+    }
+}
+
+/// Get the default protocol speed ID of `speed`, as used in the slot context and `PORTSC`
+#[inline]
+pub(super) const fn speed_id(speed: UsbSpeed) -> u32 {
+    match speed {
+        UsbSpeed::Full => 1,
+        UsbSpeed::Low => 2,
+        UsbSpeed::High => 3,
+        UsbSpeed::Super => 4,
+        UsbSpeed::SuperPlus => 5,
+    }
+}
+
+/// Get the speed matching the default protocol speed ID `id`
+#[inline]
+pub(super) const fn speed_from_id(id: u32) -> Option<UsbSpeed> {
+    match id {
+        1 => Some(UsbSpeed::Full),
+        2 => Some(UsbSpeed::Low),
+        3 => Some(UsbSpeed::High),
+        4 => Some(UsbSpeed::Super),
+        5 => Some(UsbSpeed::SuperPlus),
+        _ => None,
+    }
+}
+
+/// The layout of the input and output device contexts, which depends on the context size the
+/// controller uses (32 or 64 bytes, see `HCCPARAMS1.CSZ`).
+///
+/// The output device context is the slot context followed by the endpoint contexts. The input
+/// context is the same, but with the input control context before them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ContextLayout {
+    /// The size of each context
+    context_size: usize,
+}
+
+impl ContextLayout {
+    /// The offset of the input control context in the input context
+    pub(super) const INPUT_CONTROL: usize = 0;
+    /// The offset of the slot context in the output device context
+    pub(super) const OUTPUT_SLOT: usize = 0;
+
+    /// Create a layout for contexts of `context_size` bytes
+    #[inline]
+    pub(super) const fn new(context_size: usize) -> Self {
+        assert!(context_size == 32 || context_size == 64);
+
+        Self { context_size }
+    }
+
+    /// The offset of the slot context in the input context
+    #[inline]
+    pub(super) const fn input_slot(self) -> usize {
+        self.context_size
+    }
+
+    /// The offset of the endpoint context with device context index `dci` in the input context
+    #[inline]
+    pub(super) const fn input_endpoint(self, dci: usize) -> usize {
+        (dci + 1) * self.context_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_sizes() {
+        assert_eq!(size_of::<InputControlContext>(), 32);
+        assert_eq!(size_of::<SlotContext>(), 32);
+        assert_eq!(size_of::<EndpointContext>(), 32);
+    }
+
+    #[test]
+    fn test_slot_context() {
+        let mut slot = SlotContext::default();
+        slot.set_speed(UsbSpeed::High);
+        slot.set_context_entries(1);
+        slot.set_root_hub_port(5);
+
+        assert_eq!(slot.0[0], (3 << 20) | (1 << 27));
+        assert_eq!(slot.0[1], 5 << 16);
+
+        slot.0[3] = 0x0800_0012;
+        assert_eq!(slot.usb_device_address(), 0x12);
+    }
+
+    #[test]
+    fn test_endpoint_context() {
+        let mut endpoint = EndpointContext::default();
+        endpoint.set_endpoint_type(EndpointType::Control);
+        endpoint.set_error_count(3);
+        endpoint.set_max_packet_size(512);
+        endpoint.set_dequeue_pointer(PhysAddr(0x1_2345_6000), true);
+        endpoint.set_average_trb_length(8);
+
+        assert_eq!(endpoint.0[1], (512 << 16) | (4 << 3) | (3 << 1));
+        assert_eq!(endpoint.0[2], 0x2345_6001);
+        assert_eq!(endpoint.0[3], 0x1);
+        assert_eq!(endpoint.0[4], 8);
+
+        // Setting a field again should override it, and not OR into it
+        endpoint.set_max_packet_size(8);
+        assert_eq!(endpoint.0[1] >> 16, 8);
+    }
+
+    #[test]
+    fn test_input_control_context() {
+        let mut control = InputControlContext::default();
+        control.add(0);
+        control.add(1);
+
+        assert_eq!(control.0, [0, 0b11, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_context_layout() {
+        let layout = ContextLayout::new(64);
+        assert_eq!(layout.input_slot(), 64);
+        assert_eq!(layout.input_endpoint(1), 128);
+
+        let layout = ContextLayout::new(32);
+        assert_eq!(layout.input_endpoint(1), 64);
+    }
+
+    #[test]
+    fn test_speed_ids() {
+        for speed in [
+            UsbSpeed::Full,
+            UsbSpeed::Low,
+            UsbSpeed::High,
+            UsbSpeed::Super,
+            UsbSpeed::SuperPlus,
+        ] {
+            assert_eq!(speed_from_id(speed_id(speed)), Some(speed));
+        }
+        assert_eq!(speed_from_id(0), None);
+    }
+}
@@ -0,0 +1,804 @@
+//! xHCI (eXtensible Host Controller Interface) USB host controller driver
+//!
+//! NOTE: For now the controller is polled rather than interrupt driven, and devices are only
+//! brought up to the "addressed" state, after which their device descriptor is read. Class
+//! drivers (HID, mass storage, etc) and hubs can be built on top of this later.
+
+use super::{DescriptorType, DeviceDescriptor, SetupPacket, UsbSpeed};
+use crate::bus::pcie::{PciDriver, PcieDevice, ProbeError};
+use alloc::vec::Vec;
+use context::{
+    ContextLayout, EndpointContext, EndpointType, InputControlContext, SlotContext, speed_from_id,
+};
+use core::ptr::{NonNull, read_volatile, write_volatile};
+use kernel::{
+    arch::{
+        BASIC_PAGE_SIZE,
+        x86_64::{X86_64, paging::pat::PatType},
+    },
+    mem::paging::{Flags, PageSize, PagingManager},
+};
+use ring::{ErstEntry, EventRing, Ring, TRBS_PER_RING, Trb, TrbType, completion_code};
+use utils::{
+    mem::{PhysAddr, memset, mmio::MmioArea},
+    sanity_assert,
+    sync::{
+        barrier::dma_wmb,
+        spinlock::{SpinLock, SpinLockable},
+    },
+};
+
+mod context;
+mod ring;
+
+/// The amount of times to poll the controller before giving up on it
+const TIMEOUT_SPINS: usize = 10_000_000;
+
+/// All the xHCI controllers that were initialized successfully
+static CONTROLLERS: SpinLock<Controllers> = SpinLock::new(Controllers(Vec::new()));
+
+/// Possible errors the xHCI driver might encounter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XhciError {
+    /// The controller's registers couldn't be found or mapped
+    InvalidBar,
+    /// Couldn't allocate memory for the controller's data structures
+    OutOfMemory,
+    /// The controller requires something the driver doesn't support (yet)
+    Unsupported,
+    /// The controller didn't respond in time
+    Timeout,
+    /// A command or transfer completed with the given (unsuccessful) completion code
+    CompletionError(u8),
+    /// There's no (usable) device connected to the port
+    NoDevice,
+}
+
+/// A USB device connected to a controller, that has been assigned an address
+#[derive(Debug, Clone, Copy)]
+pub struct UsbDevice {
+    /// The slot the controller assigned to the device
+    pub slot_id: u8,
+    /// The (1 based) root hub port the device is connected to
+    pub port: u8,
+    /// The speed of the device
+    pub speed: UsbSpeed,
+    /// The USB address of the device
+    pub address: u8,
+    /// The device's device descriptor
+    pub descriptor: DeviceDescriptor,
+}
+
+/// A ZST for the capability registers
+struct CapabilityRegs;
+
+/// A ZST for the operational registers
+struct OperationalRegs;
+
+/// A ZST for the runtime registers
+struct RuntimeRegs;
+
+impl CapabilityRegs {
+    /// Offset to the `CAPLENGTH` (low byte) and `HCIVERSION` (high word) registers
+    const CAPLENGTH: usize = 0x0;
+    /// Offset to the `HCSPARAMS1` register
+    const HCSPARAMS1: usize = 0x4;
+    /// Offset to the `HCSPARAMS2` register
+    const HCSPARAMS2: usize = 0x8;
+    /// Offset to the `HCCPARAMS1` register
+    const HCCPARAMS1: usize = 0x10;
+    /// Offset to the `DBOFF` register
+    const DBOFF: usize = 0x14;
+    /// Offset to the `RTSOFF` register
+    const RTSOFF: usize = 0x18;
+}
+
+impl OperationalRegs {
+    /// Offset to the `USBCMD` register
+    const USBCMD: usize = 0x0;
+    /// Offset to the `USBSTS` register
+    const USBSTS: usize = 0x4;
+    /// Offset to the `PAGESIZE` register
+    const PAGESIZE: usize = 0x8;
+    /// Offset to the `CRCR` register
+    const CRCR: usize = 0x18;
+    /// Offset to the `DCBAAP` register
+    const DCBAAP: usize = 0x30;
+    /// Offset to the `CONFIG` register
+    const CONFIG: usize = 0x38;
+    /// Offset to the first port register set
+    const PORT_REGS: usize = 0x400;
+    /// The size of each port register set
+    const PORT_REGS_SIZE: usize = 0x10;
+
+    /// Offset to the `PORTSC` register of `port` (1 based)
+    #[inline]
+    const fn portsc(port: u8) -> usize {
+        Self::PORT_REGS + (port as usize - 1) * Self::PORT_REGS_SIZE
+    }
+}
+
+impl RuntimeRegs {
+    /// Offset to the `IMAN` register of interrupter 0
+    const IMAN: usize = 0x20;
+    /// Offset to the `ERSTSZ` register of interrupter 0
+    const ERSTSZ: usize = 0x28;
+    /// Offset to the `ERSTBA` register of interrupter 0
+    const ERSTBA: usize = 0x30;
+    /// Offset to the `ERDP` register of interrupter 0
+    const ERDP: usize = 0x38;
+}
+
+/// `USBCMD` Run/Stop bit
+const USBCMD_RUN: u32 = 1 << 0;
+/// `USBCMD` Host Controller Reset bit
+const USBCMD_HCRST: u32 = 1 << 1;
+/// `USBSTS` Host Controller Halted bit
+const USBSTS_HCH: u32 = 1 << 0;
+/// `USBSTS` Controller Not Ready bit
+const USBSTS_CNR: u32 = 1 << 11;
+/// `HCCPARAMS1` Context Size bit (64 byte contexts if set)
+const HCCPARAMS1_CSZ: u32 = 1 << 2;
+/// `CRCR` Ring Cycle State bit
+const CRCR_RCS: u64 = 1 << 0;
+/// `ERDP` Event Handler Busy bit (write 1 to clear)
+const ERDP_EHB: u64 = 1 << 3;
+/// `PORTSC` Current Connect Status bit
+const PORTSC_CCS: u32 = 1 << 0;
+/// `PORTSC` Port Enabled bit. Writing 1 disables the port!
+const PORTSC_PED: u32 = 1 << 1;
+/// `PORTSC` Port Reset bit
+const PORTSC_PR: u32 = 1 << 4;
+/// `PORTSC` Link Write Strobe bit
+const PORTSC_LWS: u32 = 1 << 16;
+/// `PORTSC` Port Reset Change bit
+const PORTSC_PRC: u32 = 1 << 21;
+/// All the `PORTSC` change bits (write 1 to clear)
+const PORTSC_CHANGE_BITS: u32 = 0x7f << 17;
+
+/// A zeroed page the controller can access through DMA
+#[derive(Debug)]
+struct DmaPage {
+    /// The virtual address of the page
+    virt: NonNull<u8>,
+    /// The physical address of the page
+    phys: PhysAddr,
+}
+
+impl DmaPage {
+    /// Allocate a new zeroed page
+    fn new() -> Result<Self, XhciError> {
+        let virt =
+            X86_64::allocate_pages(1, Flags::new().set_read_write(true), PageSize::size_4kb())
+                .map_err(|_| XhciError::OutOfMemory)?;
+        let phys = X86_64::translate(virt.into()).ok_or(XhciError::OutOfMemory)?;
+
+        unsafe { memset(virt.as_ptr().cast::<u8>(), 0, BASIC_PAGE_SIZE.size()) };
+
+        Ok(Self {
+            virt: virt.cast(),
+            phys,
+        })
+    }
+
+    /// Get a pointer to a `T` at `offset` bytes into the page
+    #[inline]
+    fn ptr<T>(&self, offset: usize) -> NonNull<T> {
+        sanity_assert!(offset + size_of::<T>() <= BASIC_PAGE_SIZE.size());
+        unsafe { self.virt.byte_add(offset).cast() }
+    }
+}
+
+impl Drop for DmaPage {
+    fn drop(&mut self) {
+        unsafe {
+            X86_64::free_pages(self.virt.cast(), 1, PageSize::size_4kb())
+                .expect("xHCI: Failed to free DMA page");
+        }
+    }
+}
+
+/// A device slot, and the memory the controller uses for it
+struct Slot {
+    /// The device in the slot
+    device: UsbDevice,
+    /// The output device context, which the controller writes the device's state to
+    output_context: DmaPage,
+    /// The input context, used to pass contexts to commands
+    input_context: DmaPage,
+    /// The page the transfer ring of the default control endpoint lives in
+    transfer_ring_page: DmaPage,
+    /// The transfer ring of the default control endpoint
+    transfer_ring: Ring,
+    /// A buffer for control transfers' data stages
+    buffer: DmaPage,
+}
+
+impl Slot {
+    /// Allocate the memory for `slot_id`, which the device connected to `port` will use
+    fn new(slot_id: u8, port: u8, speed: UsbSpeed) -> Result<Self, XhciError> {
+        let transfer_ring_page = DmaPage::new()?;
+        let transfer_ring = unsafe {
+            Ring::new(
+                transfer_ring_page.ptr(0),
+                transfer_ring_page.phys,
+                TRBS_PER_RING,
+            )
+        };
+
+        Ok(Self {
+            device: UsbDevice {
+                slot_id,
+                port,
+                speed,
+                address: 0,
+                descriptor: DeviceDescriptor::default(),
+            },
+            output_context: DmaPage::new()?,
+            input_context: DmaPage::new()?,
+            transfer_ring_page,
+            transfer_ring,
+            buffer: DmaPage::new()?,
+        })
+    }
+}
+
+/// An xHCI controller
+pub struct Xhci {
+    /// The capability registers
+    capability: MmioArea<usize, usize, u32>,
+    /// The operational registers
+    operational: MmioArea<usize, usize, u32>,
+    /// The runtime registers
+    runtime: MmioArea<usize, usize, u32>,
+    /// The doorbell registers
+    doorbells: MmioArea<usize, usize, u32>,
+    /// The amount of device slots enabled
+    max_slots: u8,
+    /// The amount of root hub ports
+    max_ports: u8,
+    /// The layout of the device contexts
+    context_layout: ContextLayout,
+    /// The Device Context Base Address Array
+    dcbaa: DmaPage,
+    /// The page the command ring lives in
+    command_ring_page: DmaPage,
+    /// The command ring
+    command_ring: Ring,
+    /// The page the (single segment) event ring lives in
+    event_ring_page: DmaPage,
+    /// The event ring of interrupter 0
+    event_ring: EventRing,
+    /// The Event Ring Segment Table of interrupter 0
+    erst: DmaPage,
+    /// The scratchpad buffer array, and the buffers themselves
+    scratchpad: Vec<DmaPage>,
+    /// The slots of the devices that were addressed
+    slots: Vec<Slot>,
+}
+
+/// A wrapper so the controllers can be put behind a `SpinLock`
+struct Controllers(Vec<Xhci>);
+
+impl Xhci {
+    /// Create a new controller instance over the registers mapped at `base`, and allocate the
+    /// memory it needs. The controller isn't touched at this point.
+    ///
+    /// SAFETY: `base` should point to the controller's mapped MMIO registers
+    unsafe fn new(base: *mut u32) -> Result<Self, XhciError> {
+        let capability: MmioArea<usize, usize, u32> = MmioArea::new(base);
+
+        let (cap_length, structural_params, capability_params, dboff, rtsoff) = unsafe {
+            (
+                capability.read(CapabilityRegs::CAPLENGTH) & 0xff,
+                capability.read(CapabilityRegs::HCSPARAMS1),
+                capability.read(CapabilityRegs::HCCPARAMS1),
+                capability.read(CapabilityRegs::DBOFF) & !0x3,
+                capability.read(CapabilityRegs::RTSOFF) & !0x1f,
+            )
+        };
+
+        let context_size = if capability_params & HCCPARAMS1_CSZ != 0 {
+            64
+        } else {
+            32
+        };
+
+        let command_ring_page = DmaPage::new()?;
+        let command_ring = unsafe {
+            Ring::new(
+                command_ring_page.ptr(0),
+                command_ring_page.phys,
+                TRBS_PER_RING,
+            )
+        };
+        let event_ring_page = DmaPage::new()?;
+        let event_ring =
+            unsafe { EventRing::new(event_ring_page.ptr(0), event_ring_page.phys, TRBS_PER_RING) };
+
+        Ok(Self {
+            capability,
+            operational: MmioArea::new(unsafe { base.byte_add(cap_length as usize) }),
+            runtime: MmioArea::new(unsafe { base.byte_add(rtsoff as usize) }),
+            doorbells: MmioArea::new(unsafe { base.byte_add(dboff as usize) }),
+            max_slots: structural_params as u8,
+            max_ports: (structural_params >> 24) as u8,
+            context_layout: ContextLayout::new(context_size),
+            dcbaa: DmaPage::new()?,
+            command_ring_page,
+            command_ring,
+            event_ring_page,
+            event_ring,
+            erst: DmaPage::new()?,
+            scratchpad: Vec::new(),
+            slots: Vec::new(),
+        })
+    }
+
+    /// Write a 64 bit register as 2 32 bit halves, low half first
+    #[inline]
+    unsafe fn write_u64(area: &MmioArea<usize, usize, u32>, offset: usize, value: u64) {
+        unsafe {
+            area.write(offset, value as u32);
+            area.write(offset + 4, (value >> 32) as u32);
+        }
+    }
+
+    /// Poll until `condition` is true, or until we give up
+    fn wait_until(&self, condition: impl Fn(&Self) -> bool) -> Result<(), XhciError> {
+        for _ in 0..TIMEOUT_SPINS {
+            if condition(self) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+
+        Err(XhciError::Timeout)
+    }
+
+    /// Halt and reset the controller
+    fn reset(&mut self) -> Result<(), XhciError> {
+        unsafe {
+            let usbcmd = self.operational.read(OperationalRegs::USBCMD);
+            self.operational
+                .write(OperationalRegs::USBCMD, usbcmd & !USBCMD_RUN);
+        }
+        self.wait_until(|xhci| unsafe {
+            xhci.operational.read(OperationalRegs::USBSTS) & USBSTS_HCH != 0
+        })?;
+
+        unsafe {
+            let usbcmd = self.operational.read(OperationalRegs::USBCMD);
+            self.operational
+                .write(OperationalRegs::USBCMD, usbcmd | USBCMD_HCRST);
+        }
+        self.wait_until(|xhci| unsafe {
+            xhci.operational.read(OperationalRegs::USBCMD) & USBCMD_HCRST == 0
+                && xhci.operational.read(OperationalRegs::USBSTS) & USBSTS_CNR == 0
+        })
+    }
+
+    /// Allocate the scratchpad buffers the controller asked for (if any), and point DCBAA entry 0
+    /// to them
+    fn setup_scratchpad(&mut self) -> Result<(), XhciError> {
+        let hcsparams2 = unsafe { self.capability.read(CapabilityRegs::HCSPARAMS2) };
+        let count = ((((hcsparams2 >> 21) & 0x1f) << 5) | ((hcsparams2 >> 27) & 0x1f)) as usize;
+        if count == 0 {
+            return Ok(());
+        }
+
+        // The buffers are of the controller's page size, which we only support being 4KB
+        let page_size = unsafe { self.operational.read(OperationalRegs::PAGESIZE) };
+        if page_size & 0x1 == 0 || count > BASIC_PAGE_SIZE.size() / size_of::<u64>() {
+            return Err(XhciError::Unsupported);
+        }
+
+        let array = DmaPage::new()?;
+        for i in 0..count {
+            let buffer = DmaPage::new()?;
+            unsafe {
+                write_volatile(
+                    array.ptr::<u64>(i * size_of::<u64>()).as_ptr(),
+                    buffer.phys.0 as u64,
+                );
+            }
+            self.scratchpad.push(buffer);
+        }
+
+        unsafe { write_volatile(self.dcbaa.ptr::<u64>(0).as_ptr(), array.phys.0 as u64) };
+        self.scratchpad.push(array);
+
+        Ok(())
+    }
+
+    /// Reset the controller, set up its data structures and start it
+    fn start(&mut self) -> Result<(), XhciError> {
+        self.reset()?;
+
+        // NOTE: The DCBAA has an entry per slot + 1 for the scratchpad array. There are at most 255
+        // slots, so it always fits in a single page
+        unsafe {
+            self.operational
+                .write(OperationalRegs::CONFIG, u32::from(self.max_slots));
+        }
+        self.setup_scratchpad()?;
+
+        unsafe {
+            Self::write_u64(
+                &self.operational,
+                OperationalRegs::DCBAAP,
+                self.dcbaa.phys.0 as u64,
+            );
+            Self::write_u64(
+                &self.operational,
+                OperationalRegs::CRCR,
+                self.command_ring.phys().0 as u64 | CRCR_RCS,
+            );
+        }
+
+        // Set up the event ring of interrupter 0. We poll it, so interrupts stay disabled
+        unsafe {
+            write_volatile(
+                self.erst.ptr::<ErstEntry>(0).as_ptr(),
+                ErstEntry::new(&self.event_ring),
+            );
+
+            self.runtime.write(RuntimeRegs::IMAN, 0);
+            self.runtime.write(RuntimeRegs::ERSTSZ, 1);
+            Self::write_u64(
+                &self.runtime,
+                RuntimeRegs::ERDP,
+                self.event_ring.dequeue_pointer().0 as u64,
+            );
+            // NOTE: ERSTBA has to be written last, since writing it is what enables the event ring
+            Self::write_u64(&self.runtime, RuntimeRegs::ERSTBA, self.erst.phys.0 as u64);
+        }
+
+        unsafe {
+            let usbcmd = self.operational.read(OperationalRegs::USBCMD);
+            self.operational
+                .write(OperationalRegs::USBCMD, usbcmd | USBCMD_RUN);
+        }
+        self.wait_until(|xhci| unsafe {
+            xhci.operational.read(OperationalRegs::USBSTS) & USBSTS_HCH == 0
+        })?;
+
+        // Making sure the command ring works before relying on it
+        self.send_command(Trb::no_op_command())?;
+
+        Ok(())
+    }
+
+    /// Wait for an event matching `predicate`. Events that don't match it are dropped
+    fn wait_for_event(&mut self, predicate: impl Fn(&Trb) -> bool) -> Result<Trb, XhciError> {
+        for _ in 0..TIMEOUT_SPINS {
+            let Some(event) = self.event_ring.pop() else {
+                core::hint::spin_loop();
+                continue;
+            };
+
+            // Letting the controller know we've consumed the event
+            unsafe {
+                Self::write_u64(
+                    &self.runtime,
+                    RuntimeRegs::ERDP,
+                    self.event_ring.dequeue_pointer().0 as u64 | ERDP_EHB,
+                );
+            }
+
+            if predicate(&event) {
+                return Ok(event);
+            }
+        }
+
+        Err(XhciError::Timeout)
+    }
+
+    /// Place a command on the command ring, and wait for it to complete
+    fn send_command(&mut self, command: Trb) -> Result<Trb, XhciError> {
+        let addr = self.command_ring.push(command);
+
+        // The command TRB must be visible before the doorbell is rung
+        dma_wmb();
+        unsafe { self.doorbells.write(0, 0) };
+
+        let event = self.wait_for_event(|event| {
+            event.trb_type() == TrbType::CommandCompletionEvent as u8
+                && event.parameter == addr.0 as u64
+        })?;
+
+        match event.completion_code() {
+            completion_code::SUCCESS => Ok(event),
+            code => Err(XhciError::CompletionError(code)),
+        }
+    }
+
+    /// Do a control transfer on the default control endpoint of the device in `slot`. If the
+    /// request has a data stage, the slot's buffer is used for it.
+    ///
+    /// Returns the address of the status stage TRB, whose completion marks the end of the transfer.
+    fn control_transfer(slot: &mut Slot, setup: SetupPacket) -> PhysAddr {
+        let has_data = setup.length != 0;
+        let device_to_host = setup.is_device_to_host();
+
+        slot.transfer_ring.push(Trb::setup_stage(setup));
+        if has_data {
+            slot.transfer_ring.push(Trb::data_stage(
+                slot.buffer.phys,
+                setup.length,
+                device_to_host,
+            ));
+        }
+        slot.transfer_ring
+            .push(Trb::status_stage(has_data, device_to_host))
+    }
+
+    /// Ring the doorbell of `slot`'s default control endpoint, and wait for the transfer placed on
+    /// it to complete
+    fn finish_control_transfer(
+        &mut self,
+        slot_id: u8,
+        status_addr: PhysAddr,
+    ) -> Result<(), XhciError> {
+        // The transfer TRBs must be visible before the doorbell is rung
+        dma_wmb();
+        // Device context index 1 is the default control endpoint
+        unsafe { self.doorbells.write(slot_id as usize * size_of::<u32>(), 1) };
+
+        let event = self.wait_for_event(|event| {
+            event.trb_type() == TrbType::TransferEvent as u8
+                && event.slot_id() == slot_id
+                && event.parameter == status_addr.0 as u64
+        })?;
+
+        match event.completion_code() {
+            completion_code::SUCCESS | completion_code::SHORT_PACKET => Ok(()),
+            code => Err(XhciError::CompletionError(code)),
+        }
+    }
+
+    /// Reset `port`, and return the speed of the device connected to it
+    fn reset_port(&self, port: u8) -> Result<UsbSpeed, XhciError> {
+        let offset = OperationalRegs::portsc(port);
+        let portsc = unsafe { self.operational.read(offset) };
+        if portsc & PORTSC_CCS == 0 {
+            return Err(XhciError::NoDevice);
+        }
+
+        // NOTE: We must not write 1 to any of the RW1C bits we don't mean to clear (and to `PED`,
+        // which would disable the port), or to `LWS` which would change the link state
+        let preserved = portsc & !(PORTSC_PED | PORTSC_LWS | PORTSC_CHANGE_BITS);
+        unsafe { self.operational.write(offset, preserved | PORTSC_PR) };
+        self.wait_until(|xhci| unsafe { xhci.operational.read(offset) & PORTSC_PRC != 0 })?;
+
+        let portsc = unsafe { self.operational.read(offset) };
+        // Acknowledging the changes
+        unsafe {
+            self.operational.write(
+                offset,
+                (portsc & !(PORTSC_PED | PORTSC_LWS)) | PORTSC_CHANGE_BITS,
+            );
+        };
+
+        if portsc & PORTSC_PED == 0 {
+            return Err(XhciError::NoDevice);
+        }
+
+        speed_from_id((portsc >> 10) & 0xf).ok_or(XhciError::NoDevice)
+    }
+
+    /// Get a slot for the device connected to `port`, assign it an address and read its device
+    /// descriptor
+    fn address_device(&mut self, port: u8, speed: UsbSpeed) -> Result<UsbDevice, XhciError> {
+        let slot_id = self.send_command(Trb::enable_slot_command())?.slot_id();
+
+        let mut slot = match Slot::new(slot_id, port, speed) {
+            Ok(slot) => slot,
+            Err(err) => {
+                self.disable_slot(slot_id);
+                return Err(err);
+            }
+        };
+
+        if let Err(err) = self.setup_slot(&mut slot) {
+            // The controller might still use the slot's memory, so it has to let go of it before
+            // the memory is freed along with `slot`
+            self.disable_slot(slot_id);
+            return Err(err);
+        }
+
+        let device = slot.device;
+        self.slots.push(slot);
+
+        Ok(device)
+    }
+
+    /// Point the DCBAA entry of `slot_id` at `output_context` (or 0 to clear it)
+    fn set_dcbaa_entry(&mut self, slot_id: u8, output_context: PhysAddr) {
+        unsafe {
+            write_volatile(
+                self.dcbaa
+                    .ptr::<u64>(slot_id as usize * size_of::<u64>())
+                    .as_ptr(),
+                output_context.0 as u64,
+            );
+        }
+    }
+
+    /// Disable `slot_id` and clear its DCBAA entry, so the controller stops using the slot's memory
+    fn disable_slot(&mut self, slot_id: u8) {
+        if let Err(err) = self.send_command(Trb::disable_slot_command(slot_id)) {
+            logger::warn!("xHCI: Failed to disable slot {slot_id}: {err:?}");
+        }
+
+        self.set_dcbaa_entry(slot_id, PhysAddr(0));
+    }
+
+    /// Hand `slot` to the controller, address its device and read the device's descriptor
+    fn setup_slot(&mut self, slot: &mut Slot) -> Result<(), XhciError> {
+        let slot_id = slot.device.slot_id;
+        self.set_dcbaa_entry(slot_id, slot.output_context.phys);
+
+        // Evaluate the slot context and the default control endpoint's context
+        let layout = self.context_layout;
+        let mut control = InputControlContext::default();
+        control.add(0);
+        control.add(1);
+
+        let mut slot_context = SlotContext::default();
+        slot_context.set_root_hub_port(slot.device.port);
+        slot_context.set_speed(slot.device.speed);
+        slot_context.set_context_entries(1);
+
+        let mut endpoint = EndpointContext::default();
+        endpoint.set_endpoint_type(EndpointType::Control);
+        endpoint.set_error_count(3);
+        endpoint.set_max_packet_size(slot.device.speed.default_max_packet_size());
+        endpoint.set_dequeue_pointer(slot.transfer_ring.phys(), slot.transfer_ring.cycle());
+        endpoint.set_average_trb_length(8);
+
+        let input_context = &slot.input_context;
+        unsafe {
+            write_volatile(
+                input_context.ptr(ContextLayout::INPUT_CONTROL).as_ptr(),
+                control,
+            );
+            write_volatile(
+                input_context.ptr(layout.input_slot()).as_ptr(),
+                slot_context,
+            );
+            write_volatile(
+                input_context.ptr(layout.input_endpoint(1)).as_ptr(),
+                endpoint,
+            );
+        }
+
+        self.send_command(Trb::address_device_command(input_context.phys, slot_id))?;
+
+        slot.device.address = unsafe {
+            read_volatile(
+                slot.output_context
+                    .ptr::<SlotContext>(ContextLayout::OUTPUT_SLOT)
+                    .as_ptr(),
+            )
+        }
+        .usb_device_address();
+
+        let setup = SetupPacket::get_descriptor(
+            DescriptorType::Device,
+            0,
+            size_of::<DeviceDescriptor>() as u16,
+        );
+        let status_addr = Self::control_transfer(slot, setup);
+        self.finish_control_transfer(slot_id, status_addr)?;
+
+        slot.device.descriptor =
+            unsafe { read_volatile(slot.buffer.ptr::<DeviceDescriptor>(0).as_ptr()) };
+
+        Ok(())
+    }
+
+    /// Go over all the root hub ports, and address every device connected to them
+    fn enumerate_ports(&mut self) {
+        for port in 1..=self.max_ports {
+            let speed = match self.reset_port(port) {
+                Ok(speed) => speed,
+                Err(XhciError::NoDevice) => continue,
+                Err(err) => {
+                    logger::warn!("xHCI: Failed to reset port {port}: {err:?}");
+                    continue;
+                }
+            };
+
+            match self.address_device(port, speed) {
+                Ok(device) => {
+                    let (vendor_id, product_id) =
+                        (device.descriptor.vendor_id, device.descriptor.product_id);
+                    logger::info!(
+                        "xHCI: Port {port}: {speed:?} speed device {vendor_id:04x}:{product_id:04x} at address {}",
+                        device.address
+                    );
+                }
+                Err(err) => {
+                    logger::warn!("xHCI: Failed to address device on port {port}: {err:?}");
+                }
+            }
+        }
+    }
+
+    /// Get the devices that were addressed on this controller
+    pub fn devices(&self) -> impl Iterator<Item = &UsbDevice> {
+        self.slots.iter().map(|slot| &slot.device)
+    }
+}
+
+/// Matches xHCI controllers (serial bus controller, USB, xHCI interface)
+pub const PCI_DRIVER: PciDriver = PciDriver {
+    name: "xHCI",
+    matches: |info| (info.class_code, info.subclass, info.prog_if) == (0xc, 0x3, 0x30),
+    probe: |device| {
+        // SAFETY: `matches` made sure it's an xHCI controller, and each device is only probed once
+        unsafe { init(device) }.map_err(|err| {
+            logger::warn!("Failed to initialize xHCI: {err:?}");
+            match err {
+                XhciError::Unsupported => ProbeError::Unsupported,
+                _ => ProbeError::InitFailed,
+            }
+        })
+    },
+};
+
+/// Initialize the xHCI controller behind `device`, and address the devices connected to it
+///
+/// # Safety
+/// `device` must be an xHCI controller, that isn't used by anything else.
+///
+/// # Errors
+/// Returns an error if the controller couldn't be initialized. Failing to address a single device
+/// isn't an error.
+pub unsafe fn init(device: &PcieDevice) -> Result<(), XhciError> {
+    let (phys_addr, size) = device.memory_bar(0).ok_or(XhciError::InvalidBar)?;
+    device.enable_bus_mastering();
+
+    let base = unsafe {
+        X86_64::map_pages(
+            phys_addr,
+            size.div_ceil(BASIC_PAGE_SIZE.size()),
+            Flags::new()
+                .set_read_write(true)
+                .set_pat(PatType::Uncacheable, PageSize::size_4kb()),
+            PageSize::size_4kb(),
+        )
+        .map_err(|_| XhciError::InvalidBar)?
+    };
+
+    let mut xhci = unsafe { Xhci::new(base.cast())? };
+    xhci.start()?;
+    logger::info!(
+        "xHCI: Controller started with {} slots and {} ports",
+        xhci.max_slots,
+        xhci.max_ports
+    );
+
+    xhci.enumerate_ports();
+    CONTROLLERS.lock().0.push(xhci);
+
+    Ok(())
+}
+
+/// Get all the USB devices that were addressed, on all the controllers
+pub fn devices() -> Vec<UsbDevice> {
+    CONTROLLERS
+        .lock()
+        .0
+        .iter()
+        .flat_map(Xhci::devices)
+        .copied()
+        .collect()
+}
+
+impl SpinLockable for Controllers {}
+
+// XXX: This might not actually be safe
+unsafe impl Send for Xhci {}
+unsafe impl Sync for Xhci {}
@@ -0,0 +1,520 @@
+//! TRBs (Transfer Request Blocks), and the rings they are placed on
+
+use super::super::SetupPacket;
+use core::ptr::{NonNull, read_volatile, write_volatile};
+use utils::mem::PhysAddr;
+
+/// The amount of TRBs in each of the rings we create, so each ring fills exactly a single page
+pub(super) const TRBS_PER_RING: usize = 256;
+
+/// A TRB, the basic unit the controller and software communicate with
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct Trb {
+    /// TRB type specific parameter (usually a pointer)
+    pub(super) parameter: u64,
+    /// TRB type specific status (usually a length, or a completion code)
+    pub(super) status: u32,
+    /// The cycle bit, flags and the type of the TRB
+    pub(super) control: u32,
+}
+
+/// The types of TRBs we use
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TrbType {
+    /// First TRB of a control transfer
+    SetupStage = 2,
+    /// Optional data TRB of a control transfer
+    DataStage = 3,
+    /// Last TRB of a control transfer
+    StatusStage = 4,
+    /// Points the controller to the next segment of the ring (in our case back to the start)
+    Link = 6,
+    /// Command to get a slot for a new device
+    EnableSlotCommand = 9,
+    /// Command to free a device slot
+    DisableSlotCommand = 10,
+    /// Command to assign an address to a device
+    AddressDeviceCommand = 11,
+    /// Command that does nothing, used for testing the command ring
+    NoOpCommand = 23,
+    /// Event generated when a transfer TRB completes
+    TransferEvent = 32,
+    /// Event generated when a command TRB completes
+    CommandCompletionEvent = 33,
+    /// Event generated when a port's status changes
+    PortStatusChangeEvent = 34,
+}
+
+/// Completion codes found in event TRBs
+pub(super) mod completion_code {
+    /// The TRB completed successfully
+    pub(in super::super) const SUCCESS: u8 = 1;
+    /// The transfer completed with less bytes than requested
+    pub(in super::super) const SHORT_PACKET: u8 = 13;
+}
+
+impl Trb {
+    /// Cycle bit. Marks who owns the TRB
+    const CYCLE: u32 = 1 << 0;
+    /// Toggle cycle bit, in Link TRBs
+    const TOGGLE_CYCLE: u32 = 1 << 1;
+    /// Interrupt (generate an event) on completion
+    const IOC: u32 = 1 << 5;
+    /// The parameter holds the data itself and not a pointer to it
+    const IDT: u32 = 1 << 6;
+    /// The direction bit of data and status stage TRBs. Set for IN (device to host)
+    const DIR_IN: u32 = 1 << 16;
+    /// The bit offset of the TRB type in `control`
+    const TYPE_SHIFT: u32 = 10;
+    /// The bit offset of the transfer type in setup stage TRBs
+    const TRANSFER_TYPE_SHIFT: u32 = 16;
+    /// The bit offset of the slot ID in command TRBs and their events
+    const SLOT_ID_SHIFT: u32 = 24;
+    /// The bit offset of the completion code in the `status` of event TRBs
+    const COMPLETION_CODE_SHIFT: u32 = 24;
+
+    /// Create a TRB of the given type, with the cycle bit cleared (it's set when placed on a ring)
+    #[inline]
+    const fn new(trb_type: TrbType, parameter: u64, status: u32, flags: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: flags | ((trb_type as u32) << Self::TYPE_SHIFT),
+        }
+    }
+
+    /// A Link TRB pointing to `target`, that toggles the cycle state of the ring
+    #[inline]
+    pub(super) const fn link(target: PhysAddr) -> Self {
+        Self::new(TrbType::Link, target.0 as u64, 0, Self::TOGGLE_CYCLE)
+    }
+
+    /// A No Op command
+    #[inline]
+    pub(super) const fn no_op_command() -> Self {
+        Self::new(TrbType::NoOpCommand, 0, 0, 0)
+    }
+
+    /// An Enable Slot command
+    #[inline]
+    pub(super) const fn enable_slot_command() -> Self {
+        Self::new(TrbType::EnableSlotCommand, 0, 0, 0)
+    }
+
+    /// A Disable Slot command, for the slot `slot_id`
+    #[inline]
+    pub(super) const fn disable_slot_command(slot_id: u8) -> Self {
+        Self::new(
+            TrbType::DisableSlotCommand,
+            0,
+            0,
+            (slot_id as u32) << Self::SLOT_ID_SHIFT,
+        )
+    }
+
+    /// An Address Device command for slot `slot_id`, using the input context at `input_context`
+    #[inline]
+    pub(super) const fn address_device_command(input_context: PhysAddr, slot_id: u8) -> Self {
+        Self::new(
+            TrbType::AddressDeviceCommand,
+            input_context.0 as u64,
+            0,
+            (slot_id as u32) << Self::SLOT_ID_SHIFT,
+        )
+    }
+
+    /// The Setup Stage TRB of a control transfer. The setup packet is stored in the TRB itself
+    #[inline]
+    pub(super) const fn setup_stage(setup: SetupPacket) -> Self {
+        // No data stage, OUT data stage, IN data stage
+        let transfer_type = match (setup.length, setup.is_device_to_host()) {
+            (0, _) => 0,
+            (_, false) => 2,
+            (_, true) => 3,
+        };
+
+        Self::new(
+            TrbType::SetupStage,
+            setup.as_u64(),
+            size_of::<SetupPacket>() as u32,
+            Self::IDT | (transfer_type << Self::TRANSFER_TYPE_SHIFT),
+        )
+    }
+
+    /// The Data Stage TRB of a control transfer, transferring `length` bytes to/from `buffer`
+    #[inline]
+    pub(super) const fn data_stage(buffer: PhysAddr, length: u16, device_to_host: bool) -> Self {
+        let flags = if device_to_host { Self::DIR_IN } else { 0 };
+
+        Self::new(TrbType::DataStage, buffer.0 as u64, length as u32, flags)
+    }
+
+    /// The Status Stage TRB of a control transfer. An event is generated once it completes.
+    ///
+    /// `has_data` and `device_to_host` describe the data stage. The status stage goes the opposite
+    /// way, or IN if there was no data stage.
+    #[inline]
+    pub(super) const fn status_stage(has_data: bool, device_to_host: bool) -> Self {
+        let flags = if has_data && device_to_host {
+            Self::IOC
+        } else {
+            Self::IOC | Self::DIR_IN
+        };
+
+        Self::new(TrbType::StatusStage, 0, 0, flags)
+    }
+
+    /// Get the raw type of the TRB
+    #[inline]
+    pub(super) const fn trb_type(&self) -> u8 {
+        ((self.control >> Self::TYPE_SHIFT) & 0x3f) as u8
+    }
+
+    /// Get the cycle bit of the TRB
+    #[inline]
+    pub(super) const fn cycle(&self) -> bool {
+        self.control & Self::CYCLE != 0
+    }
+
+    /// Set the cycle bit of the TRB
+    #[inline]
+    const fn set_cycle(&mut self, cycle: bool) {
+        self.control = (self.control & !Self::CYCLE) | cycle as u32;
+    }
+
+    /// Get the completion code of an event TRB
+    #[inline]
+    pub(super) const fn completion_code(&self) -> u8 {
+        (self.status >> Self::COMPLETION_CODE_SHIFT) as u8
+    }
+
+    /// Get the slot ID of a command completion or transfer event TRB
+    #[inline]
+    pub(super) const fn slot_id(&self) -> u8 {
+        (self.control >> Self::SLOT_ID_SHIFT) as u8
+    }
+}
+
+/// A ring software places TRBs on, and the controller consumes (i.e. the command ring and the
+/// transfer rings).
+///
+/// The last TRB is always a Link TRB back to the start of the ring.
+#[derive(Debug)]
+pub(super) struct Ring {
+    /// The TRBs of the ring
+    trbs: NonNull<Trb>,
+    /// The physical address of the ring
+    phys: PhysAddr,
+    /// The amount of TRBs in the ring, including the Link TRB
+    size: usize,
+    /// The index the next TRB will be placed at
+    enqueue: usize,
+    /// The producer cycle state
+    cycle: bool,
+}
+
+impl Ring {
+    /// Create a new ring over `size` TRBs at `trbs`, which is at physical address `phys`.
+    ///
+    /// # Safety
+    /// `trbs` must be valid for `size` zeroed TRBs, and outlive the ring.
+    pub(super) unsafe fn new(trbs: NonNull<Trb>, phys: PhysAddr, size: usize) -> Self {
+        assert!(
+            size >= 2,
+            "A ring needs room for at least a TRB and a Link TRB"
+        );
+
+        // The Link TRB's cycle bit is clear, so the controller doesn't follow it until we wrap
+        unsafe { write_volatile(trbs.add(size - 1).as_ptr(), Trb::link(phys)) };
+
+        Self {
+            trbs,
+            phys,
+            size,
+            enqueue: 0,
+            cycle: true,
+        }
+    }
+
+    /// Get the physical address of the start of the ring
+    #[inline]
+    pub(super) const fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Get the current producer cycle state
+    #[inline]
+    pub(super) const fn cycle(&self) -> bool {
+        self.cycle
+    }
+
+    /// Place `trb` on the ring, and return the physical address it was placed at.
+    ///
+    /// NOTE: This only places the TRB, the controller should then be notified through its doorbell.
+    pub(super) fn push(&mut self, mut trb: Trb) -> PhysAddr {
+        trb.set_cycle(self.cycle);
+        unsafe { write_volatile(self.trbs.add(self.enqueue).as_ptr(), trb) };
+        let addr = self.phys + self.enqueue * size_of::<Trb>();
+
+        self.enqueue += 1;
+        if self.enqueue == self.size - 1 {
+            // Handing the Link TRB over to the controller, and wrapping around
+            let mut link = Trb::link(self.phys);
+            link.set_cycle(self.cycle);
+            unsafe { write_volatile(self.trbs.add(self.enqueue).as_ptr(), link) };
+
+            self.cycle = !self.cycle;
+            self.enqueue = 0;
+        }
+
+        addr
+    }
+}
+
+/// A ring the controller places event TRBs on, and software consumes
+#[derive(Debug)]
+pub(super) struct EventRing {
+    /// The TRBs of the ring
+    trbs: NonNull<Trb>,
+    /// The physical address of the ring
+    phys: PhysAddr,
+    /// The amount of TRBs in the ring
+    size: usize,
+    /// The index of the next TRB to read
+    dequeue: usize,
+    /// The consumer cycle state
+    cycle: bool,
+}
+
+impl EventRing {
+    /// Create a new event ring (of a single segment) over `size` TRBs at `trbs`, which is at
+    /// physical address `phys`.
+    ///
+    /// # Safety
+    /// `trbs` must be valid for `size` zeroed TRBs, and outlive the ring.
+    pub(super) unsafe fn new(trbs: NonNull<Trb>, phys: PhysAddr, size: usize) -> Self {
+        assert!(
+            (16..=4096).contains(&size),
+            "An event ring segment must hold between 16 and 4096 TRBs"
+        );
+
+        Self {
+            trbs,
+            phys,
+            size,
+            dequeue: 0,
+            cycle: true,
+        }
+    }
+
+    /// Take the next event off the ring, or `None` if the controller hasn't placed one yet
+    pub(super) fn pop(&mut self) -> Option<Trb> {
+        let trb = unsafe { read_volatile(self.trbs.add(self.dequeue).as_ptr()) };
+        if trb.cycle() != self.cycle {
+            return None;
+        }
+
+        self.dequeue += 1;
+        if self.dequeue == self.size {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        Some(trb)
+    }
+
+    /// Get the physical address of the next TRB to be read, which is what `ERDP` should be set to
+    #[inline]
+    pub(super) fn dequeue_pointer(&self) -> PhysAddr {
+        self.phys + self.dequeue * size_of::<Trb>()
+    }
+}
+
+/// An entry in the ERST (Event Ring Segment Table), describing a single event ring segment
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct ErstEntry {
+    /// The physical address of the segment
+    base: u64,
+    /// The amount of TRBs in the segment
+    size: u32,
+    /// Reserved
+    _reserved: u32,
+}
+
+impl ErstEntry {
+    /// Create an entry describing the (single) segment of `ring`
+    #[inline]
+    pub(super) const fn new(ring: &EventRing) -> Self {
+        Self {
+            base: ring.phys.0 as u64,
+            size: ring.size as u32,
+            _reserved: 0,
+        }
+    }
+}
+
+// The rings are only ever accessed with the controller locked
+unsafe impl Send for Ring {}
+unsafe impl Send for EventRing {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::DescriptorType;
+    use super::*;
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    /// Allocate `size` zeroed TRBs, and get their pointer and "physical" address
+    fn trbs(size: usize) -> (Box<[Trb]>, NonNull<Trb>, PhysAddr) {
+        let mut trbs = vec![Trb::default(); size].into_boxed_slice();
+        let ptr = NonNull::new(trbs.as_mut_ptr()).unwrap();
+
+        // NOTE: The HHDM offset is 0 in tests
+        (trbs, ptr, PhysAddr(ptr.addr().get()))
+    }
+
+    #[test]
+    fn test_ring_link_trb() {
+        let (trbs, ptr, phys) = trbs(4);
+        let ring = unsafe { Ring::new(ptr, phys, 4) };
+
+        assert_eq!(ring.phys(), phys);
+        assert!(ring.cycle());
+        assert_eq!(trbs[3].trb_type(), TrbType::Link as u8);
+        assert_eq!(trbs[3].parameter, phys.0 as u64);
+        assert_ne!(trbs[3].control & Trb::TOGGLE_CYCLE, 0);
+        // The controller shouldn't follow the link until we wrap
+        assert!(!trbs[3].cycle());
+    }
+
+    #[test]
+    fn test_ring_push_and_wrap() {
+        let (trbs, ptr, phys) = trbs(4);
+        let mut ring = unsafe { Ring::new(ptr, phys, 4) };
+
+        let addrs: Vec<_> = (0..3).map(|_| ring.push(Trb::no_op_command())).collect();
+        assert_eq!(
+            addrs,
+            [phys, phys + size_of::<Trb>(), phys + 2 * size_of::<Trb>()]
+        );
+        for trb in &trbs[..3] {
+            assert_eq!(trb.trb_type(), TrbType::NoOpCommand as u8);
+            assert!(trb.cycle());
+        }
+
+        // Filling the last usable TRB should hand over the Link TRB and flip the cycle state
+        assert!(trbs[3].cycle());
+        assert!(!ring.cycle());
+
+        // The next TRB goes at the start again, with the new cycle state
+        assert_eq!(ring.push(Trb::enable_slot_command()), phys);
+        assert_eq!(trbs[0].trb_type(), TrbType::EnableSlotCommand as u8);
+        assert!(!trbs[0].cycle());
+        // The rest of the old TRBs are untouched
+        assert!(trbs[1].cycle());
+    }
+
+    #[test]
+    fn test_event_ring() {
+        let (mut trbs, ptr, phys) = trbs(16);
+        let mut ring = unsafe { EventRing::new(ptr, phys, 16) };
+
+        // Nothing was placed yet
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.dequeue_pointer(), phys);
+
+        // "Controller" places 2 events
+        for (i, trb) in trbs.iter_mut().take(2).enumerate() {
+            *trb = Trb::new(
+                TrbType::CommandCompletionEvent,
+                i as u64,
+                1 << 24,
+                Trb::CYCLE,
+            );
+        }
+
+        let event = ring.pop().unwrap();
+        assert_eq!(event.trb_type(), TrbType::CommandCompletionEvent as u8);
+        assert_eq!(event.completion_code(), completion_code::SUCCESS);
+        assert_eq!(event.parameter, 0);
+        assert_eq!(ring.pop().unwrap().parameter, 1);
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.dequeue_pointer(), phys + 2 * size_of::<Trb>());
+    }
+
+    #[test]
+    fn test_event_ring_wrap() {
+        let (mut trbs, ptr, phys) = trbs(16);
+        let mut ring = unsafe { EventRing::new(ptr, phys, 16) };
+
+        for trb in &mut trbs {
+            *trb = Trb::new(TrbType::PortStatusChangeEvent, 0, 0, Trb::CYCLE);
+        }
+        for _ in 0..16 {
+            assert!(ring.pop().is_some());
+        }
+
+        // The consumer cycle state flipped, so the stale events shouldn't be read again
+        assert_eq!(ring.dequeue_pointer(), phys);
+        assert_eq!(ring.pop(), None);
+
+        trbs[0] = Trb::new(TrbType::TransferEvent, 0, 0, 0);
+        assert_eq!(ring.pop().unwrap().trb_type(), TrbType::TransferEvent as u8);
+    }
+
+    #[test]
+    fn test_erst_entry() {
+        let (_trbs, ptr, phys) = trbs(TRBS_PER_RING);
+        let ring = unsafe { EventRing::new(ptr, phys, TRBS_PER_RING) };
+
+        let entry = ErstEntry::new(&ring);
+        assert_eq!(size_of::<ErstEntry>(), 16);
+        assert_eq!(entry.base, phys.0 as u64);
+        assert_eq!(entry.size, 256);
+    }
+
+    #[test]
+    #[should_panic(expected = "between 16 and 4096")]
+    fn test_event_ring_too_small() {
+        let (_trbs, ptr, phys) = trbs(8);
+        let _ = unsafe { EventRing::new(ptr, phys, 8) };
+    }
+
+    #[test]
+    fn test_control_transfer_trbs() {
+        let setup = SetupPacket::get_descriptor(DescriptorType::Device, 0, 18);
+
+        let trb = Trb::setup_stage(setup);
+        assert_eq!(trb.trb_type(), TrbType::SetupStage as u8);
+        assert_eq!(trb.parameter, 0x0012_0000_0100_0680);
+        assert_eq!(trb.status, 8);
+        assert_ne!(trb.control & Trb::IDT, 0);
+        // IN data stage
+        assert_eq!((trb.control >> Trb::TRANSFER_TYPE_SHIFT) & 0b11, 3);
+
+        let trb = Trb::data_stage(PhysAddr(0x1000), 18, true);
+        assert_eq!(trb.trb_type(), TrbType::DataStage as u8);
+        assert_eq!(trb.parameter, 0x1000);
+        assert_eq!(trb.status, 18);
+        assert_ne!(trb.control & Trb::DIR_IN, 0);
+
+        // The status stage goes the opposite direction of the data stage
+        let trb = Trb::status_stage(true, true);
+        assert_eq!(trb.control & Trb::DIR_IN, 0);
+        assert_ne!(trb.control & Trb::IOC, 0);
+        let trb = Trb::status_stage(false, false);
+        assert_ne!(trb.control & Trb::DIR_IN, 0);
+    }
+
+    #[test]
+    fn test_command_trbs() {
+        let trb = Trb::address_device_command(PhysAddr(0x1234_5000), 7);
+        assert_eq!(trb.trb_type(), TrbType::AddressDeviceCommand as u8);
+        assert_eq!(trb.parameter, 0x1234_5000);
+        assert_eq!(trb.slot_id(), 7);
+
+        assert_eq!(Trb::disable_slot_command(3).slot_id(), 3);
+    }
+}
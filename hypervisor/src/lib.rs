@@ -7,34 +7,77 @@
 
 extern crate alloc;
 
-use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::marker::PhantomData;
+use kernel::arch::x86_64::{CPU_VENDOR, CpuVendor};
 use scheduler::{Schedulable, constant::Constant};
-use slab::{SlabAllocatable, SlabAllocator};
+use slab::SlabAllocated;
+#[cfg(not(test))]
+use slab::SlabBox;
 use svm::Svm;
 use utils::collections::id::{Id, hander::IdHander};
+use utils::mem::PhysAddr;
 use utils::sync::spinlock::SpinLock;
+use vmx::Vmx;
 
 mod svm;
+mod vmx;
 
 static SCHEDULER: SpinLock<Constant<Vessel<Svm>>> = SpinLock::new(Constant::new_const());
 
 static VID_ALLOCATOR: SpinLock<IdHander> = SpinLock::new(IdHander::new(Id(0xffff_ffff)));
 
+/// The box a vCPU lives in
+#[cfg(not(test))]
+type VcpuBox<T> = SlabBox<T>;
+
+/// Tests can't grow the slab allocators (their pages are mapped by the kernel), so the vCPUs are
+/// allocated using the global allocator instead
+#[cfg(test)]
+type VcpuBox<T> = alloc::boxed::Box<T>;
+
 trait VirtTech {
     type VesselControlBlock: Vesselable + 'static;
 
     fn start();
+
+    /// Stop the virtualization operation on the calling core, tearing down its host state.
+    ///
+    /// The virtualization extensions stay enabled if any guests are still alive.
+    fn stop();
+
+    /// Create a new, empty nested page table, and return the physical address of its root
+    fn new_nested_page_table() -> PhysAddr;
+
+    /// Free a nested page table created with `new_nested_page_table`
+    ///
+    /// # Safety
+    /// No vCPU may use the nested page table anymore
+    unsafe fn free_nested_page_table(nested_page_table: PhysAddr);
+
+    /// Create a new vCPU starting at `rip`, which translates guest physical addresses using the
+    /// nested page table rooted at `nested_page_table`
+    fn new_vcpu(rip: usize, nested_page_table: PhysAddr) -> VcpuBox<Self::VesselControlBlock> {
+        Self::VesselControlBlock::new(rip, nested_page_table)
+    }
+}
+
+/// Why a vCPU couldn't be run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VcpuError {
+    /// The vCPU is bound to another core, and can't be moved to the calling one
+    WrongCore,
 }
 
-trait Vesselable: SlabAllocatable + Sized {
-    fn new(rip: usize) -> Box<Self, &'static SlabAllocator<Self>>;
+trait Vesselable: SlabAllocated {
+    /// Create a new vCPU starting at `rip`, which translates guest physical addresses using the
+    /// nested page table rooted at `nested_page_table`
+    fn new(rip: usize, nested_page_table: PhysAddr) -> VcpuBox<Self>;
 
-    fn run(&mut self);
+    /// Run the vCPU on the calling core until its next exit, and handle the exit
+    fn run(&mut self) -> Result<(), VcpuError>;
 }
 
-// TODO: Implement the type specific slab allocator, and then use a Box with that custom allocator instead
-// of reference to VMCB
 /// Represents a general guest execution context
 struct Vessel<T>
 where
@@ -42,24 +85,62 @@ where
 {
     id: Id,
     phantom: PhantomData<T>,
-    control: Box<T::VesselControlBlock, &'static SlabAllocator<T::VesselControlBlock>>,
+    /// The root of the nested page table all of the vCPUs share
+    nested_page_table: PhysAddr,
+    /// The virtual CPUs of the guest
+    vcpus: Vec<VcpuBox<T::VesselControlBlock>>,
+    /// The index of the vCPU that should run next
+    next_vcpu: usize,
 }
 
 impl<T> Vessel<T>
 where
     T: VirtTech,
 {
+    /// Create a new vessel with a single vCPU starting at `rip`
     fn new(rip: usize) -> Self {
-        Self {
+        let mut vessel = Self {
             id: VID_ALLOCATOR.lock().handout().unwrap(),
             phantom: PhantomData,
-            control: T::VesselControlBlock::new(rip),
-        }
+            nested_page_table: T::new_nested_page_table(),
+            vcpus: Vec::new(),
+            next_vcpu: 0,
+        };
+        vessel.add_vcpu(rip);
+
+        vessel
+    }
+
+    /// Add another vCPU starting at `rip`, sharing the guest's nested page table
+    fn add_vcpu(&mut self, rip: usize) {
+        self.vcpus.push(T::new_vcpu(rip, self.nested_page_table));
     }
 }
 
+impl<T> Drop for Vessel<T>
+where
+    T: VirtTech,
+{
+    fn drop(&mut self) {
+        // Dropping the vCPUs returns their ASIDs, and they must be gone before the nested page
+        // table they use is freed
+        self.vcpus.clear();
+
+        unsafe { T::free_nested_page_table(self.nested_page_table) };
+    }
+}
+
+/// Start the virtualization operation on the calling core, using the extensions of the CPU's
+/// vendor
+///
+/// # Panics
+/// Panics if the CPU vendor wasn't found yet
 pub fn start() {
-    Svm::start();
+    match CPU_VENDOR.get() {
+        CpuVendor::Amd => Svm::start(),
+        CpuVendor::Intel => Vmx::start(),
+        CpuVendor::Invalid => panic!("The CPU vendor wasn't found yet"),
+    }
     // let vessel: Box<Vessel<Svm>> = Box::new(Vessel::new(rip));
     // let mut scheduler = SCHEDULER.lock();
     // scheduler.add(vessel);
@@ -76,6 +157,10 @@ where
     }
 
     fn run(&mut self) {
-        self.control.run();
+        // Round robin between the vCPUs
+        if let Err(err) = self.vcpus[self.next_vcpu].run() {
+            logger::warn!("Skipping vCPU {}: {err:?}", self.next_vcpu);
+        }
+        self.next_vcpu = (self.next_vcpu + 1) % self.vcpus.len();
     }
 }
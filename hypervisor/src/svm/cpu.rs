@@ -7,8 +7,9 @@ use core::arch::asm;
 
 /// Execute a VMRUN instruction.
 #[inline]
+#[cfg(not(test))]
 pub(super) unsafe fn vmrun(vmcb: PhysAddr) {
-    sanity_assert!(vmcb.0 % BASIC_PAGE_SIZE.size() == 0);
+    sanity_assert!(vmcb.is_aligned(BASIC_PAGE_SIZE.size()));
 
     unsafe {
         asm!(
@@ -17,3 +18,20 @@ pub(super) unsafe fn vmrun(vmcb: PhysAddr) {
         );
     };
 }
+
+/// Tests can't enter a guest, so this acts as if the guest exited right away
+#[cfg(test)]
+pub(super) unsafe fn vmrun(vmcb: PhysAddr) {
+    sanity_assert!(vmcb.is_aligned(BASIC_PAGE_SIZE.size()));
+}
+
+/// Execute a STGI instruction, setting the global interrupt flag.
+///
+/// `VMEXIT` clears GIF, which holds off any interrupts (including NMIs) on the host until it's set
+/// again.
+#[inline]
+pub(super) unsafe fn stgi() {
+    unsafe {
+        asm!("stgi", options(nomem, nostack));
+    };
+}
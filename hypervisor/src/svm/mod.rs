@@ -1,4 +1,4 @@
-use super::{Vesselable, VirtTech};
+use super::{VcpuBox, VcpuError, Vesselable, VirtTech};
 
 use kernel::{
     arch::{
@@ -7,6 +7,8 @@ use kernel::{
             X86_64,
             cpu::{
                 AmdDr6, AmdDr7, Cr0, Cr2, Cr3, Cr4, Register, Rflags,
+                features::{cpu_features, svm_asid_count},
+                fpu::FpuState,
                 msr::{AmdMsr, Efer, MsrData, rdmsr, wrmsr},
                 read_rsp,
             },
@@ -14,25 +16,28 @@ use kernel::{
             interrupts::Idt,
         },
     },
-    mem::paging::{Flags, PageSize, PagingManager},
+    mem::paging::PagingManager,
 };
-use slab::{SlabAllocatable, SlabAllocator};
+use pmm::PmmAllocator;
+use slab::{SlabAllocatable, SlabAllocated, SlabAllocator};
 use utils::sync::spinlock::SpinLock;
 
-use alloc::boxed::Box;
 use core::{
-    arch::x86_64::__cpuid,
     ops::{Deref, DerefMut},
-    ptr, todo,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    todo,
 };
 use modular_bitfield::prelude::*;
+use npf::NestedPageFault;
 use utils::{
     collections::id::{Id, tracker::IdTracker},
-    mem::memset,
+    mem::{PhysAddr, memset},
     sanity_assert,
 };
 
 mod cpu;
+mod npf;
 
 // TODO: Make sure the pages are writeback WB and not writethough WT
 // TODO: Make this a box to a dyn or something since we might use VMX or something isntead
@@ -41,6 +46,15 @@ static VMCB_ALLOCATOR: SlabAllocator<Vmcb> = SlabAllocator::new();
 /// The ASID allocator for the guests.
 static ASID_ALLOCATOR: SpinLock<IdTracker> = SpinLock::new(IdTracker::uninit());
 
+/// The amount of vCPUs that hold an ASID, across all guests
+static LIVE_VCPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// `TLB_CONTROL` value leaving the TLB as is on the next `VMRUN`
+const TLB_CONTROL_DO_NOTHING: u32 = 0;
+
+/// `TLB_CONTROL` value flushing the TLB entries of the guest's ASID on the next `VMRUN`
+const TLB_CONTROL_FLUSH_GUEST: u32 = 3;
+
 /// A ZST to implement the `VirtTech` trait on
 pub struct Svm;
 
@@ -275,11 +289,24 @@ pub struct VmcbInner {
     control: ControlArea,
     state_save: StateSaveArea,
 }
-/// The VMCB structure.
+/// The size of the VMCB's page
+const VMCB_SIZE: usize = 0x1000;
+
+/// The VMCB structure, followed by the guest state `VMRUN` doesn't switch.
 ///
-/// Each VM has one, so HAV could be used
+/// Each vCPU has one, so HAV could be used
 #[repr(C, align(0x1000))]
-pub struct Vmcb(VmcbInner);
+pub struct Vmcb {
+    inner: VmcbInner,
+    /// The rest of the VMCB's page is reserved, so the state that follows starts on the next page
+    _reserved: [u8; VMCB_SIZE - size_of::<VmcbInner>()],
+    /// The guest's x87/SSE/AVX state while it isn't running
+    guest_fpu: FpuState,
+    /// The host's x87/SSE/AVX state while the guest is running
+    host_fpu: FpuState,
+    /// The allocator the vCPU's ASID is from, if it got one yet
+    asid_allocator: Option<&'static SpinLock<IdTracker>>,
+}
 
 /// The possible valid intercept codes that can be found in the `exitcode` field in the VMCB.
 ///
@@ -502,34 +529,65 @@ impl Svm {
     /// before VMRUN).
     /// Each core should have one of these of it's own.
     fn init_host_state() {
-        // NOTE: Because we've allocated the page manually, it's also up to us to free it in addition
-        // to unmapping it!
-        //
-        // NOTE: IIRC this should be called on each processor!
+        // NOTE: Because we've allocated the page manually, it's also up to us to free it (see
+        // `free_host_state`)
+        let phys_addr = Self::allocate_zeroed_page();
 
-        // Getting rid of stale data
-        let host_state_page =
-            X86_64::allocate_pages(1, Flags::new().set_read_write(true), PageSize::size_4kb())
-                .expect("Failed to allocate host state page");
+        unsafe {
+            // Breaking the physical address of the page into parts, so we can write it to the MSR
+            let low = (phys_addr.0 & 0xffff_ffff) as u32;
+            let high = ((phys_addr.0 >> 32) & 0xffff_ffff) as u32;
 
+            // Set the host state save area
+            wrmsr(AmdMsr::VmHsavePa, MsrData { low, high });
+        };
+    }
+
+    /// Make sure this core has a host state area, initializing it if it doesn't.
+    ///
+    /// Every core that runs a vCPU needs one, not just the one that called `start`
+    fn ensure_host_state() {
+        let host_state: u64 = unsafe { rdmsr(AmdMsr::VmHsavePa) }.into();
+        if host_state == 0 {
+            Self::init_host_state();
+        }
+    }
+
+    /// Free this core's host state area, if it has one
+    fn free_host_state() {
+        let host_state: u64 = unsafe { rdmsr(AmdMsr::VmHsavePa) }.into();
+        if host_state == 0 {
+            return;
+        }
+
+        unsafe {
+            wrmsr(AmdMsr::VmHsavePa, 0_u64.into());
+            Self::free_page(PhysAddr(host_state as usize));
+        };
+    }
+
+    /// Allocate a zeroed out page, and return its physical address
+    fn allocate_zeroed_page() -> PhysAddr {
+        let phys_addr = pmm::get().allocate(1, 1).expect("Failed to allocate page");
+
+        // Getting rid of stale data
         unsafe {
-            // Map the physical page so we can write to it
             memset(
-                host_state_page.as_ptr().cast::<u8>(),
+                ptr::without_provenance_mut(phys_addr.add_hhdm_offset().0),
                 0x0,
                 BASIC_PAGE_SIZE.size(),
             );
         };
 
-        unsafe {
-            let phys_addr = X86_64::translate(host_state_page.into()).unwrap();
-            // Breaking the physical address of the page into parts, so we can write it to the MSR
-            let low = (phys_addr.0 & 0xffff_ffff) as u32;
-            let high = ((phys_addr.0 >> 32) & 0xffff_ffff) as u32;
+        phys_addr
+    }
 
-            // Set the host state save area
-            wrmsr(AmdMsr::VmHsavePa, MsrData { low, high });
-        };
+    /// Free a page allocated with `allocate_zeroed_page`
+    ///
+    /// # Safety
+    /// The page must not be used anymore (by us or by the CPU)
+    unsafe fn free_page(phys_addr: PhysAddr) {
+        unsafe { pmm::get().free(phys_addr, 1).expect("Failed to free page") };
     }
 
     /// Enables the option to enter SVM operation.
@@ -546,33 +604,33 @@ impl Svm {
         logger::info!("Enabled SVM sucessfully");
     }
 
+    /// Disables SVM operation on this core
+    fn disable() {
+        unsafe {
+            let mut data: u64 = rdmsr(AmdMsr::Efer).into();
+            data &= !Efer::SVM;
+            wrmsr(AmdMsr::Efer, data.into());
+        }
+
+        logger::info!("Disabled SVM sucessfully");
+    }
+
     /// Make sure SVM is supported on this CPU
     fn check_support() {
-        const SVM_SUPPORT_ECX_BIT: u32 = 1 << 2;
-
-        unsafe {
-            assert!(
-                __cpuid(0x8000_0001).ecx & SVM_SUPPORT_ECX_BIT != 0,
-                "SVM isn't supported on this processor"
-            );
-        };
+        assert!(cpu_features().svm, "SVM isn't supported on this processor");
     }
 
     /// Perform a check to see if virtualization is disabled by the firmware.
     fn check_firmware_disabled() {
         const SVM_DISABLE: u32 = 1 << 4;
-        const CPUID_SVM_BRANCH: u32 = 0x8000_000a;
-        const SVML: u32 = 1 << 2;
 
         let vmcr = unsafe { rdmsr(AmdMsr::VmCr) };
 
         if vmcr.low & SVM_DISABLE != 0 {
-            unsafe {
-                assert!(
-                    __cpuid(CPUID_SVM_BRANCH).edx & SVML != 0,
-                    "SVM is disabled by firmware. Change your BIOS/UEFI settings to enable it."
-                );
-            };
+            assert!(
+                cpu_features().svm_lock,
+                "SVM is disabled by firmware. Change your BIOS/UEFI settings to enable it."
+            );
 
             panic!(
                 "SVM is disabled by firmware but unlockable with key. Sadly Funderberker doesn't support this yet"
@@ -587,7 +645,7 @@ impl Svm {
         let start_id = Id(1); // ASID 0 is reserved for the host, so we start from 1
         // NOTE: Adding 1 here since the max ASID is inclusive, so we need to add 1 to the end of
         // the range
-        let end_id = unsafe { Id(__cpuid(0x8000_000a).ebx as usize) };
+        let end_id = Id(svm_asid_count() as usize);
 
         *allocator = IdTracker::new(start_id, end_id)
     }
@@ -600,7 +658,28 @@ impl Vmcb {
     /// wish.
     #[inline]
     const fn uninit() -> Self {
-        unsafe { core::mem::zeroed() }
+        let mut vmcb: Self = unsafe { core::mem::zeroed() };
+        // That isn't part of the VMCB, and zeroes aren't a valid state for it
+        vmcb.guest_fpu = FpuState::new();
+
+        vmcb
+    }
+
+    /// Switch the x87/SSE/AVX state to the guest's, right before `VMRUN`.
+    ///
+    /// `VMRUN` only switches the state that is in the VMCB, which doesn't include these (or XCR0),
+    /// so otherwise the guest would run with (and clobber) the host's registers.
+    #[inline]
+    fn load_guest_fpu(&mut self) {
+        self.host_fpu.save();
+        self.guest_fpu.restore();
+    }
+
+    /// Switch the x87/SSE/AVX state back to the host's, right after `VMEXIT`
+    #[inline]
+    fn load_host_fpu(&mut self) {
+        self.guest_fpu.save();
+        self.host_fpu.restore();
     }
 
     /// Sanity checking the guest state of the VMCB after it has been initialized.
@@ -679,14 +758,39 @@ impl Vmcb {
     /// Makes sure the processor supports nested paging before we try to set it up.
     #[inline]
     fn check_nested_paging_support() {
-        const NESTED_PAGING_BIT: u32 = 1 << 0;
+        assert!(
+            cpu_features().nested_paging,
+            "Nested paging is not supported on this processor"
+        );
+    }
 
-        unsafe {
-            assert!(
-                __cpuid(0x8000_000a).edx & NESTED_PAGING_BIT != 0,
-                "Nested paging is not supported on this processor"
-            );
-        }
+    /// Create a new vCPU starting at `rip`, which translates guest physical addresses using the
+    /// nested page table rooted at `nested_page_table`, and gets its ASID from `asid_allocator`
+    fn new_with(
+        rip: usize,
+        nested_page_table: PhysAddr,
+        asid_allocator: &'static SpinLock<IdTracker>,
+    ) -> VcpuBox<Self> {
+        let mut vmcb = VcpuBox::new(Self::uninit());
+
+        vmcb.set_nested_page_table(nested_page_table);
+        vmcb.init_guest_state(rip, asid_allocator);
+
+        vmcb
+    }
+
+    /// Set the nested page table the vCPU uses to translate guest physical addresses.
+    ///
+    /// All of the vCPUs of a guest share the same one.
+    #[inline]
+    fn set_nested_page_table(&mut self, nested_page_table: PhysAddr) {
+        self.control.n_cr3 = nested_page_table.0 as u64;
+    }
+
+    /// Get the nested page table the vCPU uses
+    #[inline]
+    fn nested_page_table(&self) -> PhysAddr {
+        PhysAddr(self.control.n_cr3 as usize)
     }
 
     unsafe fn setup_nested_paging(&mut self) {
@@ -720,29 +824,53 @@ impl Vmcb {
     ///
     /// NOTE: Not every combination of fields is valid. See the AMD APM Vol 2, `Canonicalization
     /// and Consistency Checks`
-    fn init_guest_state(&mut self, rip: usize) {
+    fn init_guest_state(&mut self, rip: usize, asid_allocator: &'static SpinLock<IdTracker>) {
+        // let phys_page = pmm::get()
+        //     .allocate(NonZero::new(1).unwrap(), NonZero::new(1).unwrap())
+        //     .unwrap();
+        // let virt_addr = VirtAddr(0x40_000);
+        //
+        // map_page_to(phys_page, virt_addr, Entry::FLAG_RW);
+        //
+        // memcpy(virt_addr.into(), GUEST_CODE.as_ptr(), GUEST_CODE.len());
+
+        // Tests can't read the host's privileged registers, so the state is left zeroed there
+        #[cfg(not(test))]
+        self.copy_host_state();
+        self.state_save.rip = rip;
+        self.state_save.rflags = Rflags::new().with_rf(1);
+        self.state_save.rax = 0; // TODO: Not sure about RAX
+        self.state_save.cr0 = Cr0::new().with_cd(1).with_nw(1);
+        self.state_save.cpl = 0; // We start in ring 0
+
+        self.control.intercepts.set_vmrun(1);
+        self.control.intercepts.set_cpuid(1);
+        self.control.intercepts.set_hlt(1);
+        // Host NMIs that arrive while the guest is running should be handled by the host
+        self.control.intercepts.set_nmi(1);
+        self.control
+            .intercepts
+            .set_exceptions(Intercepts::ALL_EXCEPTIONS);
+        self.allocate_asid(asid_allocator);
+
+        // self.setup_nested_paging();
+
+        // The host's state isn't copied in tests, so it wouldn't pass
+        #[cfg(not(test))]
+        self.sanity_check_guest_state();
+    }
+
+    /// Start the guest off with the host's segments, control registers and stack
+    fn copy_host_state(&mut self) {
         let gdt = {
             let ptr: *mut Gdt = Gdt::read_gdtr().into();
             unsafe { ptr.as_mut().unwrap() }
         };
 
         unsafe {
-            // let phys_page = pmm::get()
-            //     .allocate(NonZero::new(1).unwrap(), NonZero::new(1).unwrap())
-            //     .unwrap();
-            // let virt_addr = VirtAddr(0x40_000);
-            //
-            // map_page_to(phys_page, virt_addr, Entry::FLAG_RW);
-            //
-            // memcpy(virt_addr.into(), GUEST_CODE.as_ptr(), GUEST_CODE.len());
-
             self.state_save.cs = gdt.read_full_selector(Cs::read().0);
-            self.state_save.rip = rip;
-            self.state_save.rflags = Rflags::new().with_rf(1);
-            self.state_save.rax = 0; // TODO: Not sure about RAX
             self.state_save.ss = gdt.read_full_selector(Ss::read().0);
             self.state_save.rsp = read_rsp() as u64;
-            self.state_save.cr0 = Cr0::new().with_cd(1).with_nw(1);
             self.state_save.cr3 = Cr3::read();
             self.state_save.cr4 = Cr4::read();
             self.state_save.efer = rdmsr(AmdMsr::Efer).into();
@@ -752,20 +880,22 @@ impl Vmcb {
             self.state_save.ds = gdt.read_full_selector(Ds::read().0);
             self.state_save.dr6 = AmdDr6::read();
             self.state_save.dr7 = AmdDr7::read();
-            self.state_save.cpl = 0; // We start in ring 0
+        }
+    }
 
-            self.control.intercepts.set_vmrun(1);
-            self.control.intercepts.set_cpuid(1);
-            self.control.intercepts.set_hlt(1);
-            self.control
-                .intercepts
-                .set_exceptions(Intercepts::ALL_EXCEPTIONS);
-            self.control.guest_asid = ASID_ALLOCATOR.lock().allocate().unwrap().0 as u32;
+    /// Assign the vCPU a free ASID from `allocator`, which it's returned to when the VMCB is
+    /// dropped
+    fn allocate_asid(&mut self, allocator: &'static SpinLock<IdTracker>) {
+        sanity_assert!(self.control.guest_asid == 0, "vCPU already has an ASID");
 
-            // self.setup_nested_paging();
-        }
+        let asid = allocator.lock().allocate().expect("Out of ASIDs");
+        self.control.guest_asid = asid.0 as u32;
+        self.asid_allocator = Some(allocator);
+        // The ASID might've been used by a guest that is gone now, so its stale translations
+        // must not be used
+        self.control.tlb_control = TLB_CONTROL_FLUSH_GUEST;
 
-        self.sanity_check_guest_state();
+        LIVE_VCPUS.fetch_add(1, Ordering::Relaxed);
     }
 
     /// Handles the intercept if the VM was in the middle of an interrupt delivery
@@ -781,6 +911,18 @@ impl Vmcb {
         );
     }
 
+    /// Handles a nested page fault.
+    ///
+    /// NOTE: Guest memory isn't mapped into the nested page table yet, so there is nothing to
+    /// map the page from, and the fault is fatal. The diagnostic at least tells where the guest
+    /// went wrong (e.g. a length that was used as an address)
+    fn handle_npf(&mut self) {
+        let fault = NestedPageFault::decode(self.control.exitinfo1, self.control.exitinfo2);
+        let rip = self.state_save.rip;
+
+        panic!("Unhandled nested page fault at guest RIP {rip:#x}: {fault}");
+    }
+
     /// Handles the VMEXIT when testing the intercepts.
     #[cfg(test)]
     fn test_intercepts_handle_vmexit(&mut self, expceted_exit_code: InterceptCode) {
@@ -794,6 +936,18 @@ impl Vmcb {
         );
     }
 
+    /// Run the guest until the next `VMEXIT`. `phys_addr` is the physical address of this VMCB
+    fn enter_guest(&mut self, phys_addr: PhysAddr) {
+        self.load_guest_fpu();
+        unsafe {
+            cpu::vmrun(phys_addr);
+        };
+        self.load_host_fpu();
+
+        // A requested flush was carried out by this VMRUN, so the next ones shouldn't repeat it
+        self.control.tlb_control = TLB_CONTROL_DO_NOTHING;
+    }
+
     // TODO: get rid of this function
     /// Same as run, but with test VMEXIT handling
     #[cfg(test)]
@@ -801,9 +955,7 @@ impl Vmcb {
         let ptr = ptr::from_mut(self);
         let phys_addr = X86_64::translate(ptr.into()).unwrap();
 
-        unsafe {
-            cpu::vmrun(phys_addr);
-        };
+        self.enter_guest(phys_addr);
 
         self.test_intercepts_handle_vmexit(expected_exit_code);
     }
@@ -836,9 +988,15 @@ impl Vmcb {
             InterceptCode::Hlt => {
                 logger::info!("HLT intercept triggered");
             }
+            InterceptCode::Nmi => {
+                // The NMI is still pending on the host, and is delivered to the host's NMI
+                // handler once GIF is set
+                unsafe { cpu::stgi() };
+            }
             InterceptCode::Vmrun | InterceptCode::Vmload | InterceptCode::Vmsave => {
                 logger::err!("Nested virtualization is not supported yet.");
             }
+            InterceptCode::Npf => self.handle_npf(),
             InterceptCode::Invalid | InterceptCode::QemuInvalid => {
                 panic!("Unknown invalid VMCB state. Fatal error");
             }
@@ -857,26 +1015,47 @@ impl VirtTech for Svm {
 
         logger::info!("Started SVM operation successfully");
     }
-}
 
-impl Vesselable for Vmcb {
-    fn new(rip: usize) -> Box<Self, &'static SlabAllocator<Self>> {
-        let mut vmcb = Box::new_in(Self::uninit(), &VMCB_ALLOCATOR);
+    fn stop() {
+        // Both the host state area and EFER are per core, so this only affects the calling core.
+        // If a vCPU runs on it again, `ensure_host_state` sets up a new host state area
+        Self::free_host_state();
+
+        let live_vcpus = LIVE_VCPUS.load(Ordering::Relaxed);
+        if live_vcpus == 0 {
+            Self::disable();
+        } else {
+            logger::warn!("Not disabling SVM, since {live_vcpus} vCPUs are still alive");
+        }
+    }
 
-        vmcb.init_guest_state(rip);
+    fn new_nested_page_table() -> PhysAddr {
+        Self::allocate_zeroed_page()
+    }
 
-        vmcb
+    unsafe fn free_nested_page_table(nested_page_table: PhysAddr) {
+        // TODO: Free the lower levels too once guest memory is actually mapped in
+        unsafe { Self::free_page(nested_page_table) };
+    }
+}
+
+impl Vesselable for Vmcb {
+    fn new(rip: usize, nested_page_table: PhysAddr) -> VcpuBox<Self> {
+        Self::new_with(rip, nested_page_table, &ASID_ALLOCATOR)
     }
 
-    fn run(&mut self) {
+    fn run(&mut self) -> Result<(), VcpuError> {
+        // The vCPU might be scheduled on a core other than the one SVM was started on
+        Svm::ensure_host_state();
+
         let ptr = ptr::from_mut(self);
         let phys_addr = X86_64::translate(ptr.into()).unwrap();
 
-        unsafe {
-            cpu::vmrun(phys_addr);
-        };
+        self.enter_guest(phys_addr);
 
         self.handle_vmexit();
+
+        Ok(())
     }
 }
 
@@ -884,22 +1063,97 @@ impl Deref for Vmcb {
     type Target = VmcbInner;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
 impl DerefMut for Vmcb {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.inner
+    }
+}
+
+impl Drop for Vmcb {
+    fn drop(&mut self) {
+        let Some(allocator) = self.asid_allocator else {
+            // Never got an ASID, so it never ran either
+            return;
+        };
+
+        unsafe {
+            allocator
+                .lock()
+                .free(Id(self.control.guest_asid as usize))
+                .expect("Failed to free the guest's ASID");
+        };
+        LIVE_VCPUS.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
 impl SlabAllocatable for Vmcb {}
 
+impl SlabAllocated for Vmcb {
+    fn allocator() -> &'static SlabAllocator<Self> {
+        &VMCB_ALLOCATOR
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Vessel;
+    use alloc::boxed::Box;
+    use alloc::{
+        alloc::{alloc_zeroed, dealloc},
+        vec::Vec,
+    };
+    use core::alloc::Layout;
     use core::mem::{offset_of, size_of};
+    use kernel::arch::x86_64::cpu::fpu::{INIT_MXCSR, read_mxcsr, write_mxcsr};
+
+    /// The ASID allocators of the tests' guests. Each test uses a pool of its own, so tests running
+    /// in parallel don't take each other's ASIDs
+    static TEST_ASID_ALLOCATORS: [SpinLock<IdTracker>; 2] =
+        [const { SpinLock::new(IdTracker::uninit()) }; 2];
+
+    /// SVM, with the vCPUs getting their ASIDs from `TEST_ASID_ALLOCATORS[POOL]`. It can't actually
+    /// be started, and its nested page tables are allocated on the heap
+    struct TestSvm<const POOL: usize>;
+
+    /// The layout of the tests' nested page tables
+    fn nested_page_table_layout() -> Layout {
+        Layout::from_size_align(BASIC_PAGE_SIZE.size(), BASIC_PAGE_SIZE.size()).unwrap()
+    }
+
+    impl<const POOL: usize> VirtTech for TestSvm<POOL> {
+        type VesselControlBlock = Vmcb;
+
+        fn start() {
+            unimplemented!("Tests can't enter SVM operation");
+        }
+
+        fn stop() {
+            unimplemented!("Tests can't enter SVM operation");
+        }
+
+        fn new_nested_page_table() -> PhysAddr {
+            // NOTE: The HHDM offset is 0 in tests, so the address is its own "physical" address
+            PhysAddr(unsafe { alloc_zeroed(nested_page_table_layout()) }.expose_provenance())
+        }
+
+        unsafe fn free_nested_page_table(nested_page_table: PhysAddr) {
+            unsafe {
+                dealloc(
+                    ptr::with_exposed_provenance_mut(nested_page_table.0),
+                    nested_page_table_layout(),
+                );
+            };
+        }
+
+        fn new_vcpu(rip: usize, nested_page_table: PhysAddr) -> VcpuBox<Vmcb> {
+            Vmcb::new_with(rip, nested_page_table, &TEST_ASID_ALLOCATORS[POOL])
+        }
+    }
 
     #[test]
     fn test_vmcb_layout() {
@@ -921,6 +1175,9 @@ mod tests {
         assert_eq!(offset_of!(StateSaveArea, spec_ctrl), 0x2e0);
         assert_eq!(offset_of!(StateSaveArea, ic_ibs_extd_ctl), 0x7c0);
 
+        // The state that isn't part of the VMCB is past its page
+        assert_eq!(offset_of!(Vmcb, guest_fpu), VMCB_SIZE);
+
         // FullSegmentSelector size and offset checks
         assert_eq!(size_of::<FullSegmentSelector>(), 16);
         assert_eq!(size_of::<u16>(), 2); // sel
@@ -928,4 +1185,81 @@ mod tests {
         assert_eq!(size_of::<u32>(), 4); // limit
         assert_eq!(size_of::<u64>(), 8); // base
     }
+
+    #[test]
+    fn test_vcpus_share_nested_page_table() {
+        const POOL: usize = 1;
+        *TEST_ASID_ALLOCATORS[POOL].lock() = IdTracker::new(Id(1), Id(2));
+
+        let mut vessel = Vessel::<TestSvm<POOL>>::new(0x1000);
+        vessel.add_vcpu(0x2000);
+        let [first, second] = vessel.vcpus.as_slice() else {
+            panic!("The guest should have 2 vCPUs");
+        };
+
+        // Same guest physical address space, but separate VMCBs and state
+        assert_eq!(first.nested_page_table(), vessel.nested_page_table);
+        assert_eq!(second.nested_page_table(), vessel.nested_page_table);
+        assert_ne!(ptr::from_ref(&**first), ptr::from_ref(&**second));
+
+        let (first_rip, second_rip) = (first.state_save.rip, second.state_save.rip);
+        assert_eq!((first_rip, second_rip), (0x1000, 0x2000));
+    }
+
+    #[test]
+    fn test_dropping_vcpu_frees_asid() {
+        const POOL: usize = 0;
+        *TEST_ASID_ALLOCATORS[POOL].lock() = IdTracker::new(Id(1), Id(2));
+
+        let mut vessel = Vessel::<TestSvm<POOL>>::new(0x1000);
+        vessel.add_vcpu(0x2000);
+        let asids: Vec<u32> = vessel
+            .vcpus
+            .iter()
+            .map(|vcpu| vcpu.control.guest_asid)
+            .collect();
+        assert_eq!(asids, [1, 2]);
+
+        // The pool is empty until the guest is dropped
+        assert!(TEST_ASID_ALLOCATORS[POOL].lock().allocate().is_err());
+        drop(vessel);
+
+        let mut vessel = Vessel::<TestSvm<POOL>>::new(0x1000);
+        let vcpu = &mut vessel.vcpus[0];
+        let (asid, tlb_control) = (vcpu.control.guest_asid, vcpu.control.tlb_control);
+        assert_eq!(asid, 1);
+        assert_eq!(tlb_control, TLB_CONTROL_FLUSH_GUEST);
+
+        // The flush only happens on the first run with the reused ASID
+        vcpu.enter_guest(PhysAddr(0x1000));
+        let tlb_control = vcpu.control.tlb_control;
+        assert_eq!(tlb_control, TLB_CONTROL_DO_NOTHING);
+    }
+
+    #[test]
+    fn test_guest_run_keeps_host_simd_state() {
+        /// MXCSR rounding control bits, for rounding down and towards zero
+        const ROUND_DOWN: u32 = 0x2000;
+        const ROUND_TOWARDS_ZERO: u32 = 0x6000;
+
+        let original = read_mxcsr();
+        let host_mxcsr = original | ROUND_TOWARDS_ZERO;
+        let mut vmcb = Box::new(Vmcb::uninit());
+        unsafe { write_mxcsr(host_mxcsr) };
+
+        // The guest starts from the reset state, and changes its rounding mode while it runs
+        vmcb.load_guest_fpu();
+        assert_eq!(read_mxcsr(), INIT_MXCSR);
+        unsafe { write_mxcsr(INIT_MXCSR | ROUND_DOWN) };
+        vmcb.load_host_fpu();
+        assert_eq!(read_mxcsr(), host_mxcsr);
+
+        // And finds it the way it left it on its next run
+        vmcb.load_guest_fpu();
+        assert_eq!(read_mxcsr(), INIT_MXCSR | ROUND_DOWN);
+        vmcb.load_host_fpu();
+        assert_eq!(read_mxcsr(), host_mxcsr);
+
+        unsafe { write_mxcsr(original) };
+    }
 }
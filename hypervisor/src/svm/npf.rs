@@ -0,0 +1,128 @@
+//! Decoding nested page faults (`#NPF` VMEXITs).
+//!
+//! On an `#NPF` VMEXIT, `exitinfo1` holds a page fault style error code describing the access,
+//! and `exitinfo2` the guest physical address that faulted.
+
+use core::fmt;
+
+use modular_bitfield::prelude::*;
+use utils::mem::PhysAddr;
+
+/// The error code of a nested page fault, found in `exitinfo1`
+#[bitfield(bits = 64)]
+#[derive(Clone, Copy)]
+pub(super) struct NpfErrorCode {
+    /// The access was to a present page (i.e. it was a protection violation)
+    present: B1,
+    /// The access was a write
+    write: B1,
+    /// The access was done in CPL 3
+    user: B1,
+    /// A reserved bit was set in one of the nested page table entries
+    reserved_bit_set: B1,
+    /// The access was an instruction fetch
+    instruction_fetch: B1,
+    #[skip]
+    reserved_1: B27,
+    /// The fault happened while translating the final guest physical address of the access
+    final_translation: B1,
+    /// The fault happened while translating the address of one of the guest's page tables
+    page_table_walk: B1,
+    #[skip]
+    reserved_2: B30,
+}
+
+/// A decoded nested page fault
+#[derive(Clone, Copy)]
+pub(super) struct NestedPageFault {
+    /// The guest physical address that faulted
+    pub guest_phys_addr: PhysAddr,
+    pub error_code: NpfErrorCode,
+}
+
+impl NestedPageFault {
+    /// Decode the nested page fault from the `exitinfo1` and `exitinfo2` of its VMEXIT
+    pub(super) const fn decode(exitinfo1: u64, exitinfo2: u64) -> Self {
+        Self {
+            guest_phys_addr: PhysAddr(exitinfo2 as usize),
+            error_code: NpfErrorCode::from_bytes(exitinfo1.to_le_bytes()),
+        }
+    }
+
+    /// Get the kind of access that faulted
+    fn access(&self) -> &'static str {
+        if self.error_code.instruction_fetch() != 0 {
+            "instruction fetch"
+        } else if self.error_code.write() != 0 {
+            "write"
+        } else {
+            "read"
+        }
+    }
+
+    /// Get the reason the access faulted
+    fn reason(&self) -> &'static str {
+        if self.error_code.reserved_bit_set() != 0 {
+            "reserved bit set in a nested page table entry"
+        } else if self.error_code.present() != 0 {
+            "protection violation"
+        } else {
+            "not mapped"
+        }
+    }
+}
+
+impl fmt::Display for NestedPageFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} at guest physical address {:#x} ({})",
+            if self.error_code.user() != 0 {
+                "user"
+            } else {
+                "supervisor"
+            },
+            self.access(),
+            self.guest_phys_addr.0,
+            self.reason(),
+        )?;
+
+        if self.error_code.page_table_walk() != 0 {
+            write!(f, ", while walking the guest's page tables")?;
+        } else if self.error_code.final_translation() != 0 {
+            write!(f, ", while translating the final address")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_decode() {
+        // A write by the guest's kernel to an unmapped page
+        let fault = NestedPageFault::decode(0x1_0000_0002, 0xdead_b000);
+
+        assert_eq!(fault.guest_phys_addr, PhysAddr(0xdead_b000));
+        assert_eq!(fault.error_code.present(), 0);
+        assert_eq!(fault.error_code.write(), 1);
+        assert_eq!(fault.error_code.user(), 0);
+        assert_eq!(fault.error_code.final_translation(), 1);
+        assert_eq!(fault.error_code.page_table_walk(), 0);
+        assert_eq!(
+            fault.to_string(),
+            "supervisor write at guest physical address 0xdeadb000 (not mapped), while translating the final address"
+        );
+
+        // An instruction fetch by user code, from a page the guest's page tables are in
+        let fault = NestedPageFault::decode(0x2_0000_0015, 0x1000);
+        assert_eq!(
+            fault.to_string(),
+            "user instruction fetch at guest physical address 0x1000 (protection violation), while walking the guest's page tables"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use kernel::arch::BASIC_PAGE_SIZE;
+use utils::mem::PhysAddr;
+use utils::sanity_assert;
+
+use core::arch::asm;
+
+use super::vmcs::VmcsField;
+
+/// How a VMX instruction failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum VmFail {
+    /// There is no current VMCS to hold the error number (`CF` was set)
+    Invalid,
+    /// The error number is in the current VMCS's `VmInstructionError` field (`ZF` was set)
+    Valid,
+}
+
+/// Turn the flags a VMX instruction left into its result
+#[inline]
+fn vm_result(cf: u8, zf: u8) -> Result<(), VmFail> {
+    if cf != 0 {
+        Err(VmFail::Invalid)
+    } else if zf != 0 {
+        Err(VmFail::Valid)
+    } else {
+        Ok(())
+    }
+}
+
+/// Execute an instruction that takes the physical address of a page as a memory operand, and
+/// return its result
+macro_rules! vmx_page_instruction {
+    ($instruction:literal, $addr:expr) => {{
+        let addr: PhysAddr = $addr;
+        sanity_assert!(addr.is_aligned(BASIC_PAGE_SIZE.size()));
+
+        let operand = addr.0 as u64;
+        let (cf, zf): (u8, u8);
+        unsafe {
+            asm!(
+                concat!($instruction, " qword ptr [{}]"),
+                "setc {}",
+                "setz {}",
+                in(reg) &raw const operand,
+                out(reg_byte) cf,
+                out(reg_byte) zf,
+                options(nostack),
+            );
+        };
+
+        vm_result(cf, zf)
+    }};
+}
+
+/// Execute a VMXON instruction, entering VMX operation with the VMXON region at `region`
+#[inline]
+pub(super) unsafe fn vmxon(region: PhysAddr) -> Result<(), VmFail> {
+    vmx_page_instruction!("vmxon", region)
+}
+
+/// Execute a VMXOFF instruction, leaving VMX operation
+#[inline]
+pub(super) unsafe fn vmxoff() -> Result<(), VmFail> {
+    let (cf, zf): (u8, u8);
+    unsafe {
+        asm!(
+            "vmxoff",
+            "setc {}",
+            "setz {}",
+            out(reg_byte) cf,
+            out(reg_byte) zf,
+            options(nomem, nostack),
+        );
+    };
+
+    vm_result(cf, zf)
+}
+
+/// Execute a VMCLEAR instruction, writing the VMCS at `vmcs` back to memory and marking it clear
+/// (i.e. not launched)
+#[inline]
+pub(super) unsafe fn vmclear(vmcs: PhysAddr) -> Result<(), VmFail> {
+    vmx_page_instruction!("vmclear", vmcs)
+}
+
+/// Execute a VMPTRLD instruction, making the VMCS at `vmcs` the current one
+#[inline]
+pub(super) unsafe fn vmptrld(vmcs: PhysAddr) -> Result<(), VmFail> {
+    vmx_page_instruction!("vmptrld", vmcs)
+}
+
+/// Read `field` of the current VMCS
+#[inline]
+pub(super) unsafe fn vmread(field: VmcsField) -> Result<u64, VmFail> {
+    let value: u64;
+    let (cf, zf): (u8, u8);
+    unsafe {
+        asm!(
+            "vmread {}, {}",
+            "setc {}",
+            "setz {}",
+            out(reg) value,
+            in(reg) u64::from(field.encoding()),
+            out(reg_byte) cf,
+            out(reg_byte) zf,
+            options(nostack),
+        );
+    };
+
+    vm_result(cf, zf).map(|()| value)
+}
+
+/// Write `value` to `field` of the current VMCS
+#[inline]
+pub(super) unsafe fn vmwrite(field: VmcsField, value: u64) -> Result<(), VmFail> {
+    let (cf, zf): (u8, u8);
+    unsafe {
+        asm!(
+            "vmwrite {}, {}",
+            "setc {}",
+            "setz {}",
+            in(reg) u64::from(field.encoding()),
+            in(reg) value,
+            out(reg_byte) cf,
+            out(reg_byte) zf,
+            options(nostack),
+        );
+    };
+
+    vm_result(cf, zf)
+}
+
+/// Enter the guest of the current VMCS, with VMRESUME if it was launched already and VMLAUNCH
+/// otherwise. Returns once the guest exits, or right away if the entry failed.
+///
+/// The host's RSP and RIP are written to the VMCS right before entering, so the VM exit lands
+/// back here. Nothing else of the host's state is, so the guest is free to clobber all of the
+/// other general purpose registers. RFLAGS is restored too, since a VM exit clears it (along with
+/// the interrupt flag).
+#[inline(never)]
+pub(super) unsafe fn vmenter(launched: bool) -> Result<(), VmFail> {
+    /// The value left in RAX by each outcome
+    const EXITED: u64 = 0;
+    const FAILED_VALID: u64 = 1;
+    const FAILED_INVALID: u64 = 2;
+
+    let outcome: u64;
+    unsafe {
+        asm!(
+            // RBX and RBP can't be marked as clobbered
+            "pushfq",
+            "push rbx",
+            "push rbp",
+            "vmwrite rcx, rsp",
+            "lea rax, [rip + 2f]",
+            "vmwrite rdx, rax",
+            "test rsi, rsi",
+            "jnz 3f",
+            "vmlaunch",
+            "jmp 4f",
+            "3:",
+            "vmresume",
+            // Only reached if the entry failed, with the flags telling how
+            "4:",
+            "mov eax, {failed_valid}",
+            "jnc 5f",
+            "mov eax, {failed_invalid}",
+            "jmp 5f",
+            // The VM exit lands here, with the stack as it was right before entering
+            "2:",
+            "mov eax, {exited}",
+            "5:",
+            "pop rbp",
+            "pop rbx",
+            "popfq",
+            exited = const EXITED,
+            failed_valid = const FAILED_VALID,
+            failed_invalid = const FAILED_INVALID,
+            inout("rcx") u64::from(VmcsField::HostRsp.encoding()) => _,
+            inout("rdx") u64::from(VmcsField::HostRip.encoding()) => _,
+            inout("rsi") u64::from(launched) => _,
+            lateout("rax") outcome,
+            lateout("r12") _,
+            lateout("r13") _,
+            lateout("r14") _,
+            lateout("r15") _,
+            clobber_abi("C"),
+        );
+    };
+
+    match outcome {
+        EXITED => Ok(()),
+        FAILED_VALID => Err(VmFail::Valid),
+        _ => Err(VmFail::Invalid),
+    }
+}
+
+/// Execute an INVEPT instruction, invalidating the cached translations of all EPTs
+#[inline]
+pub(super) unsafe fn invept_all() {
+    /// The INVEPT type invalidating the translations of all EPTs
+    const ALL_CONTEXTS: u64 = 2;
+
+    // The descriptor holds an EPT pointer, which is ignored when invalidating all contexts
+    let descriptor = [0_u64; 2];
+    unsafe {
+        asm!(
+            "invept {}, [{}]",
+            in(reg) ALL_CONTEXTS,
+            in(reg) &raw const descriptor,
+            options(nostack),
+        );
+    };
+}
+
+/// Invoke the host's NMI handler, for an NMI that caused a VM exit instead of being delivered
+#[inline]
+pub(super) unsafe fn deliver_host_nmi() {
+    unsafe {
+        asm!("int 2", options(nomem));
+    };
+}
@@ -0,0 +1,150 @@
+//! Extended page tables (EPT), Intel's nested paging, and decoding EPT violations.
+//!
+//! On an EPT violation VM exit, the exit qualification describes the access, and the guest
+//! physical address field holds the address that faulted.
+
+use core::fmt;
+
+use modular_bitfield::prelude::*;
+use utils::mem::PhysAddr;
+
+/// The memory type the processor uses to access the EPT paging structures (write back)
+const EPT_MEMORY_TYPE_WB: u64 = 6;
+
+/// The amount of levels of the EPT
+const EPT_PAGE_WALK_LENGTH: u64 = 4;
+
+/// Get the EPT pointer (the VMCS field) of the EPT rooted at `pml4`
+pub(super) const fn ept_pointer(pml4: PhysAddr) -> u64 {
+    pml4.0 as u64 | EPT_MEMORY_TYPE_WB | ((EPT_PAGE_WALK_LENGTH - 1) << 3)
+}
+
+/// Get the root of the EPT `ept_pointer` points to
+pub(super) const fn ept_root(ept_pointer: u64) -> PhysAddr {
+    PhysAddr((ept_pointer & !0xfff) as usize)
+}
+
+/// The exit qualification of an EPT violation
+#[bitfield(bits = 64)]
+#[derive(Clone, Copy)]
+pub(super) struct EptViolationQualification {
+    /// The access was a read
+    read: B1,
+    /// The access was a write
+    write: B1,
+    /// The access was an instruction fetch
+    instruction_fetch: B1,
+    /// The page was readable
+    readable: B1,
+    /// The page was writable
+    writable: B1,
+    /// The page was executable
+    executable: B1,
+    #[skip]
+    reserved_0: B1,
+    /// The guest linear address field is valid
+    linear_address_valid: B1,
+    /// If the linear address is valid, set if the fault happened while translating the final
+    /// address, and clear if it happened while walking the guest's page tables
+    final_translation: B1,
+    #[skip]
+    reserved_1: B55,
+}
+
+/// A decoded EPT violation
+#[derive(Clone, Copy)]
+pub(super) struct EptViolation {
+    /// The guest physical address that faulted
+    pub guest_phys_addr: PhysAddr,
+    pub qualification: EptViolationQualification,
+}
+
+impl EptViolation {
+    /// Decode the EPT violation from the exit qualification and guest physical address of its VM
+    /// exit
+    pub(super) const fn decode(qualification: u64, guest_phys_addr: u64) -> Self {
+        Self {
+            guest_phys_addr: PhysAddr(guest_phys_addr as usize),
+            qualification: EptViolationQualification::from_bytes(qualification.to_le_bytes()),
+        }
+    }
+
+    /// Get the kind of access that faulted
+    fn access(&self) -> &'static str {
+        if self.qualification.instruction_fetch() != 0 {
+            "instruction fetch"
+        } else if self.qualification.write() != 0 {
+            "write"
+        } else {
+            "read"
+        }
+    }
+
+    /// Get the reason the access faulted
+    fn reason(&self) -> &'static str {
+        let q = &self.qualification;
+        if q.readable() == 0 && q.writable() == 0 && q.executable() == 0 {
+            "not mapped"
+        } else {
+            "protection violation"
+        }
+    }
+}
+
+impl fmt::Display for EptViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at guest physical address {:#x} ({})",
+            self.access(),
+            self.guest_phys_addr.0,
+            self.reason(),
+        )?;
+
+        if self.qualification.linear_address_valid() != 0 {
+            if self.qualification.final_translation() != 0 {
+                write!(f, ", while translating the final address")?;
+            } else {
+                write!(f, ", while walking the guest's page tables")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_ept_pointer() {
+        let pml4 = PhysAddr(0x1234_5000);
+
+        assert_eq!(ept_pointer(pml4), 0x1234_501e);
+        assert_eq!(ept_root(ept_pointer(pml4)), pml4);
+    }
+
+    #[test]
+    fn test_decode() {
+        // A write to an unmapped page
+        let violation = EptViolation::decode(0x182, 0xdead_b000);
+
+        assert_eq!(violation.guest_phys_addr, PhysAddr(0xdead_b000));
+        assert_eq!(violation.qualification.write(), 1);
+        assert_eq!(violation.qualification.readable(), 0);
+        assert_eq!(
+            violation.to_string(),
+            "write at guest physical address 0xdeadb000 (not mapped), while translating the final address"
+        );
+
+        // An instruction fetch from a page that isn't executable, while walking the guest's page
+        // tables
+        let violation = EptViolation::decode(0x9c, 0x1000);
+        assert_eq!(
+            violation.to_string(),
+            "instruction fetch at guest physical address 0x1000 (protection violation), while walking the guest's page tables"
+        );
+    }
+}
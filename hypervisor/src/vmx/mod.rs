@@ -0,0 +1,868 @@
+use super::{VcpuBox, VcpuError, Vesselable, VirtTech};
+
+use kernel::{
+    arch::{
+        BASIC_PAGE_SIZE, MAX_CPUS,
+        x86_64::{
+            X86_64,
+            apic::lapic::LocalApic,
+            cpu::{
+                Cr0, Cr3, Cr4, Register,
+                features::cpu_features,
+                fpu::FpuState,
+                msr::{AmdMsr, IntelMsr, MsrData, rdmsr, wrmsr},
+                read_rsp,
+            },
+            gdt::{
+                Cs, Ds, Es, Fs, FullSegmentSelector, Gdt, Gs, SegmentSelector, Ss, Tss, read_tss,
+            },
+            interrupts::Idt,
+        },
+    },
+    mem::paging::PagingManager,
+};
+use pmm::PmmAllocator;
+use slab::{SlabAllocatable, SlabAllocated, SlabAllocator};
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use cpu::{VmFail, vmread, vmwrite};
+use ept::EptViolation;
+use utils::{
+    mem::{PhysAddr, memset},
+    sanity_assert,
+};
+use vmcs::{Exit, ExitReason, VmcsField};
+
+mod cpu;
+mod ept;
+mod vmcs;
+
+static VMCS_ALLOCATOR: SlabAllocator<Vmcs> = SlabAllocator::new();
+
+/// The physical address of the VMXON region of every CPU (by APIC ID), or 0 if the CPU isn't in
+/// VMX operation
+static VMXON_REGIONS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// The amount of vCPUs whose VMCS is active on every CPU (by APIC ID)
+static LIVE_VCPUS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// The size of the VMCS and VMXON regions
+const VMCS_SIZE: usize = 0x1000;
+
+/// A ZST to implement the `VirtTech` trait on
+pub struct Vmx;
+
+/// The bits of `IA32_FEATURE_CONTROL` that allow VMX operation
+struct FeatureControl;
+
+impl FeatureControl {
+    /// Once set, the MSR can't be written until the next reset
+    const LOCK: u32 = 1 << 0;
+    /// Allows VMXON outside of SMX operation
+    const VMX_OUTSIDE_SMX: u32 = 1 << 2;
+}
+
+/// The pin-based VM-execution controls
+struct PinBasedControls;
+
+impl PinBasedControls {
+    /// NMIs cause a VM exit, so they are handled by the host
+    const NMI_EXITING: u32 = 1 << 3;
+}
+
+/// The primary processor-based VM-execution controls
+struct PrimaryProcBasedControls;
+
+impl PrimaryProcBasedControls {
+    const HLT_EXITING: u32 = 1 << 7;
+    /// The secondary processor-based VM-execution controls are used
+    const ACTIVATE_SECONDARY_CONTROLS: u32 = 1 << 31;
+}
+
+/// The secondary processor-based VM-execution controls
+struct SecondaryProcBasedControls;
+
+impl SecondaryProcBasedControls {
+    const ENABLE_EPT: u32 = 1 << 1;
+}
+
+/// The VM-exit controls
+struct ExitControls;
+
+impl ExitControls {
+    /// The host is in 64 bit mode after a VM exit
+    const HOST_ADDRESS_SPACE_SIZE: u32 = 1 << 9;
+    const SAVE_IA32_EFER: u32 = 1 << 20;
+    const LOAD_IA32_EFER: u32 = 1 << 21;
+}
+
+/// The VM-entry controls
+struct EntryControls;
+
+impl EntryControls {
+    /// The guest is in IA-32e mode after VM entry
+    const IA32E_MODE_GUEST: u32 = 1 << 9;
+    const LOAD_IA32_EFER: u32 = 1 << 15;
+}
+
+/// The type of an event in the VM-exit interruption information field
+const INTERRUPTION_TYPE_NMI: u32 = 2;
+
+/// The access rights of an unusable segment (e.g. a null one)
+const ACCESS_RIGHTS_UNUSABLE: u32 = 1 << 16;
+
+/// The access rights of the TSS: Present, busy 64 bit TSS
+const ACCESS_RIGHTS_BUSY_TSS: u32 = 0x8b;
+
+/// Bit 1 of RFLAGS is reserved, and must be set
+const RFLAGS_RESERVED: u64 = 1 << 1;
+
+/// DR7's value after reset
+const DR7_INIT: u64 = 0x400;
+
+/// The VMCS of a vCPU, followed by state that isn't part of it
+#[repr(C, align(0x1000))]
+pub struct Vmcs {
+    /// The VMCS revision identifier, which has to match the one `IA32_VMX_BASIC` reports
+    revision_id: u32,
+    /// Set by the processor if a VMX abort happens
+    abort_indicator: u32,
+    /// The rest of the VMCS, whose format is implementation specific
+    _data: [u8; VMCS_SIZE - 8],
+    /// The guest's x87/SSE state, which isn't part of the VMCS
+    guest_fpu: FpuState,
+    /// The host's x87/SSE state, saved while the guest runs
+    host_fpu: FpuState,
+    /// The APIC ID of the CPU the VMCS is active on, which has to be the one that runs it
+    cpu: u32,
+    /// Whether the VMCS was launched, in which case it's entered with VMRESUME
+    launched: bool,
+}
+
+/// Adjust `desired` VMX controls to the allowed settings `allowed` reported by their capability
+/// MSR. The low half has the bits that must be set, and the high half the bits that may be set.
+///
+/// Returns `None` if one of the `desired` controls isn't supported
+const fn adjust_controls(desired: u32, allowed: MsrData) -> Option<u32> {
+    let controls = (desired | allowed.low) & allowed.high;
+
+    if desired & !controls == 0 {
+        Some(controls)
+    } else {
+        None
+    }
+}
+
+/// Get the VMX access rights of the segment `descriptor` describes. Besides the access byte, they
+/// hold the descriptor's flags (the granularity, size and long mode bits)
+const fn access_rights(access: u8, flags: u8) -> u32 {
+    access as u32 | ((flags as u32) << 12)
+}
+
+/// Read `field` of the current VMCS
+///
+/// # Panics
+/// Panics if there is no current VMCS
+fn read(field: VmcsField) -> u64 {
+    unsafe { vmread(field) }.unwrap_or_else(|fail| panic!("Failed to read {field:?}: {fail:?}"))
+}
+
+/// Write `value` to `field` of the current VMCS
+///
+/// # Panics
+/// Panics if there is no current VMCS, or the field can't be written
+fn write(field: VmcsField, value: u64) {
+    if let Err(fail) = unsafe { vmwrite(field, value) } {
+        panic!(
+            "Failed to write {value:#x} to {field:?}: {fail:?} (error {})",
+            instruction_error()
+        );
+    }
+}
+
+/// Get the error number of the last VMX instruction that failed, or 0 if there is no current
+/// VMCS to hold it
+fn instruction_error() -> u64 {
+    unsafe { vmread(VmcsField::VmInstructionError) }.unwrap_or(0)
+}
+
+impl Vmx {
+    /// Get the revision identifier the VMXON region and the VMCSs have to start with
+    fn revision_id() -> u32 {
+        unsafe { rdmsr(IntelMsr::Ia32VmxBasic) }.low & 0x7fff_ffff
+    }
+
+    fn allocate_zeroed_page() -> PhysAddr {
+        let phys_addr = pmm::get().allocate(1, 1).expect("Failed to allocate page");
+
+        // Getting rid of stale data
+        unsafe {
+            memset(
+                ptr::without_provenance_mut(phys_addr.add_hhdm_offset().0),
+                0x0,
+                BASIC_PAGE_SIZE.size(),
+            );
+        };
+
+        phys_addr
+    }
+
+    /// Free a page allocated with `allocate_zeroed_page`
+    ///
+    /// # Safety
+    /// The page must not be used anymore
+    unsafe fn free_page(phys_addr: PhysAddr) {
+        unsafe { pmm::get().free(phys_addr, 1).expect("Failed to free page") };
+    }
+
+    /// Make sure the processor supports VMX
+    fn check_support() {
+        assert!(cpu_features().vmx, "VMX isn't supported on this processor");
+    }
+
+    /// Make sure the firmware didn't disable VMX, enabling it if the firmware left it up to us
+    fn check_firmware_disabled() {
+        let mut feature_control = unsafe { rdmsr(IntelMsr::Ia32FeatureControl) };
+
+        if feature_control.low & FeatureControl::LOCK == 0 {
+            feature_control.low |= FeatureControl::LOCK | FeatureControl::VMX_OUTSIDE_SMX;
+            unsafe { wrmsr(IntelMsr::Ia32FeatureControl, feature_control) };
+        } else {
+            assert!(
+                feature_control.low & FeatureControl::VMX_OUTSIDE_SMX != 0,
+                "VMX is disabled by firmware. Change your BIOS/UEFI settings to enable it."
+            );
+        }
+    }
+
+    /// Set the bits of CR0 and CR4 VMX operation requires, including `CR4.VMXE`
+    fn enable() {
+        Self::check_support();
+        Self::check_firmware_disabled();
+
+        let fixed = |fixed0: IntelMsr, fixed1: IntelMsr, value: u64| {
+            let (fixed0, fixed1): (u64, u64) =
+                unsafe { (rdmsr(fixed0).into(), rdmsr(fixed1).into()) };
+            (value | fixed0) & fixed1
+        };
+
+        unsafe {
+            let cr0: u64 = Cr0::read().into();
+            Cr0::from(fixed(
+                IntelMsr::Ia32VmxCr0Fixed0,
+                IntelMsr::Ia32VmxCr0Fixed1,
+                cr0,
+            ))
+            .write();
+
+            let cr4: u64 = Cr4::read().with_vmxe(1).into();
+            Cr4::from(fixed(
+                IntelMsr::Ia32VmxCr4Fixed0,
+                IntelMsr::Ia32VmxCr4Fixed1,
+                cr4,
+            ))
+            .write();
+        };
+
+        logger::info!("Enabled VMX sucessfully");
+    }
+
+    /// Clear `CR4.VMXE`. The calling core must not be in VMX operation
+    fn disable() {
+        unsafe { Cr4::read().with_vmxe(0).write() };
+
+        logger::info!("Disabled VMX sucessfully");
+    }
+
+    /// Enter VMX operation on the calling core, with a new VMXON region
+    fn vmxon() {
+        let region = Self::allocate_zeroed_page();
+        unsafe {
+            ptr::with_exposed_provenance_mut::<u32>(region.add_hhdm_offset().0)
+                .write(Self::revision_id());
+            cpu::vmxon(region).expect("VMXON failed");
+        };
+
+        VMXON_REGIONS[LocalApic::get_this_apic_id() as usize].store(region.0, Ordering::Relaxed);
+    }
+
+    /// Make sure the calling core is in VMX operation, entering it if it isn't
+    fn ensure_vmx_operation() {
+        let cpu = LocalApic::get_this_apic_id() as usize;
+        if VMXON_REGIONS[cpu].load(Ordering::Relaxed) == 0 {
+            Self::enable();
+            Self::vmxon();
+        }
+    }
+
+    /// Leave VMX operation on the calling core, freeing its VMXON region
+    fn vmxoff() {
+        let cpu = LocalApic::get_this_apic_id() as usize;
+        let region = VMXON_REGIONS[cpu].swap(0, Ordering::Relaxed);
+        if region == 0 {
+            return;
+        }
+
+        unsafe {
+            cpu::vmxoff().expect("VMXOFF failed");
+            Self::free_page(PhysAddr(region));
+        };
+    }
+}
+
+impl Vmcs {
+    /// Create a VMCS.
+    ///
+    /// NOTE: It isn't usable until it's loaded with `activate`
+    #[inline]
+    const fn uninit() -> Self {
+        let mut vmcs: Self = unsafe { core::mem::zeroed() };
+        // That isn't part of the VMCS, and zeroes aren't a valid state for it
+        vmcs.guest_fpu = FpuState::new();
+
+        vmcs
+    }
+
+    /// Get the physical address of the VMCS, which the VMX instructions take
+    fn phys_addr(&mut self) -> PhysAddr {
+        let ptr = ptr::from_mut(self);
+
+        X86_64::translate(ptr.into()).unwrap()
+    }
+
+    /// Initialize the VMCS and make it the current one of the calling core
+    fn activate(&mut self) {
+        self.revision_id = Vmx::revision_id();
+        self.cpu = LocalApic::get_this_apic_id();
+
+        let phys_addr = self.phys_addr();
+        unsafe {
+            cpu::vmclear(phys_addr).expect("VMCLEAR failed");
+            cpu::vmptrld(phys_addr).expect("VMPTRLD failed");
+        };
+    }
+
+    /// Switch the guest's x87/SSE state in, saving the host's
+    #[inline]
+    fn load_guest_fpu(&mut self) {
+        self.host_fpu.save();
+        self.guest_fpu.restore();
+    }
+
+    /// Switch the host's x87/SSE state back in, saving the guest's
+    #[inline]
+    fn load_host_fpu(&mut self) {
+        self.guest_fpu.save();
+        self.host_fpu.restore();
+    }
+
+    /// Makes sure the processor supports EPT before we try to set it up.
+    #[inline]
+    fn check_nested_paging_support() {
+        let allowed = unsafe { rdmsr(IntelMsr::Ia32VmxProcbasedCtls2) };
+        assert!(
+            adjust_controls(SecondaryProcBasedControls::ENABLE_EPT, allowed).is_some(),
+            "EPT is not supported on this processor"
+        );
+    }
+
+    /// Set the EPT the vCPU uses to translate guest physical addresses.
+    ///
+    /// All of the vCPUs of a guest share the same one.
+    #[inline]
+    fn set_nested_page_table(nested_page_table: PhysAddr) {
+        write(VmcsField::EptPointer, ept::ept_pointer(nested_page_table));
+    }
+
+    /// Get the EPT the vCPU uses
+    #[inline]
+    fn nested_page_table() -> PhysAddr {
+        ept::ept_root(read(VmcsField::EptPointer))
+    }
+
+    /// Translate guest physical addresses through the EPT.
+    ///
+    /// NOTE: Guest memory isn't mapped into the EPT yet (see `handle_ept_violation`), so like on
+    /// SVM this isn't done yet
+    fn enable_nested_paging() {
+        Self::check_nested_paging_support();
+
+        let controls = read(VmcsField::SecondaryProcBasedControls) as u32;
+        write(
+            VmcsField::SecondaryProcBasedControls,
+            u64::from(controls | SecondaryProcBasedControls::ENABLE_EPT),
+        );
+    }
+
+    /// Set the VM-execution, VM-exit and VM-entry controls
+    fn init_controls() {
+        let controls = |msr: IntelMsr, desired: u32| {
+            let allowed = unsafe { rdmsr(msr) };
+            let controls = adjust_controls(desired, allowed)
+                .unwrap_or_else(|| panic!("Unsupported VMX controls {desired:#x} for {msr:?}"));
+            u64::from(controls)
+        };
+
+        write(
+            VmcsField::PinBasedControls,
+            controls(IntelMsr::Ia32VmxPinbasedCtls, PinBasedControls::NMI_EXITING),
+        );
+        write(
+            VmcsField::PrimaryProcBasedControls,
+            controls(
+                IntelMsr::Ia32VmxProcbasedCtls,
+                PrimaryProcBasedControls::HLT_EXITING
+                    | PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS,
+            ),
+        );
+        write(
+            VmcsField::SecondaryProcBasedControls,
+            controls(IntelMsr::Ia32VmxProcbasedCtls2, 0),
+        );
+        write(
+            VmcsField::ExitControls,
+            controls(
+                IntelMsr::Ia32VmxExitCtls,
+                ExitControls::HOST_ADDRESS_SPACE_SIZE
+                    | ExitControls::SAVE_IA32_EFER
+                    | ExitControls::LOAD_IA32_EFER,
+            ),
+        );
+        write(
+            VmcsField::EntryControls,
+            controls(
+                IntelMsr::Ia32VmxEntryCtls,
+                EntryControls::IA32E_MODE_GUEST | EntryControls::LOAD_IA32_EFER,
+            ),
+        );
+
+        // Like on SVM, all exceptions are intercepted
+        write(VmcsField::ExceptionBitmap, u64::from(u32::MAX));
+    }
+
+    /// Set the host state the processor loads on VM exit. RSP and RIP are set by `vmenter` right
+    /// before entering.
+    ///
+    /// NOTE: The host selectors must have an RPL and TI of 0
+    fn init_host_state() {
+        let selector = |selector: SegmentSelector| u64::from(u16::from(selector) & !0b111);
+        let (tr, tss_base) = read_tss();
+
+        unsafe {
+            write(VmcsField::HostCr0, Cr0::read().into());
+            write(VmcsField::HostCr3, Cr3::read().into());
+            write(VmcsField::HostCr4, Cr4::read().into());
+
+            write(VmcsField::HostEsSelector, selector(Es::read().0));
+            write(VmcsField::HostCsSelector, selector(Cs::read().0));
+            write(VmcsField::HostSsSelector, selector(Ss::read().0));
+            write(VmcsField::HostDsSelector, selector(Ds::read().0));
+            write(VmcsField::HostFsSelector, selector(Fs::read().0));
+            write(VmcsField::HostGsSelector, selector(Gs::read().0));
+            write(VmcsField::HostTrSelector, selector(tr));
+
+            write(VmcsField::HostFsBase, rdmsr(IntelMsr::Ia32FsBase).into());
+            write(VmcsField::HostGsBase, rdmsr(IntelMsr::Ia32GsBase).into());
+            write(VmcsField::HostTrBase, tss_base);
+            write(
+                VmcsField::HostGdtrBase,
+                FullSegmentSelector::from(Gdt::read_gdtr()).base,
+            );
+            write(
+                VmcsField::HostIdtrBase,
+                FullSegmentSelector::from(Idt::read_idtr()).base,
+            );
+
+            write(VmcsField::HostSysenterCs, 0);
+            write(VmcsField::HostSysenterEsp, 0);
+            write(VmcsField::HostSysenterEip, 0);
+
+            write(VmcsField::HostIa32Efer, rdmsr(AmdMsr::Efer).into());
+        };
+    }
+
+    /// Initializes the guest state of the VMCS, which the processor loads on VM entry. Like on
+    /// SVM, the guest starts out with the host's segments and control registers.
+    ///
+    /// NOTE: Not every combination of fields is valid. See the Intel SDM Vol 3, `Checks on the
+    /// Guest State Area`
+    fn init_guest_state(rip: usize) {
+        Self::init_guest_segments();
+
+        unsafe {
+            write(VmcsField::GuestCr0, Cr0::read().into());
+            write(VmcsField::GuestCr3, Cr3::read().into());
+            write(VmcsField::GuestCr4, Cr4::read().into());
+            write(VmcsField::GuestIa32Efer, rdmsr(AmdMsr::Efer).into());
+            write(VmcsField::GuestDr7, DR7_INIT);
+
+            write(VmcsField::GuestRip, rip as u64);
+            write(VmcsField::GuestRsp, read_rsp() as u64);
+            write(VmcsField::GuestRflags, RFLAGS_RESERVED);
+
+            write(VmcsField::GuestSysenterCs, 0);
+            write(VmcsField::GuestSysenterEsp, 0);
+            write(VmcsField::GuestSysenterEip, 0);
+
+            // Active, with nothing blocking interrupts
+            write(VmcsField::GuestActivityState, 0);
+            write(VmcsField::GuestInterruptibility, 0);
+            write(VmcsField::VmcsLinkPointer, u64::MAX);
+        };
+    }
+
+    /// Set the guest's segments and descriptor tables to the host's
+    fn init_guest_segments() {
+        let gdt = {
+            let ptr: *const Gdt = Gdt::read_gdtr().into();
+            unsafe { ptr.as_ref().unwrap() }
+        };
+        let segment =
+            |selector: SegmentSelector, fields: [VmcsField; 4]| -> [(VmcsField, u64); 4] {
+                let [selector_field, base, limit, rights] = fields;
+                let full = gdt.read_full_selector(selector);
+                let descriptor = gdt[selector];
+
+                [
+                    (selector_field, u64::from(u16::from(selector))),
+                    (base, full.base),
+                    (limit, u64::from(full.limit)),
+                    (
+                        rights,
+                        u64::from(access_rights(descriptor.access(), descriptor.flags())),
+                    ),
+                ]
+            };
+
+        let (tr, tss_base) = read_tss();
+        let gdtr = FullSegmentSelector::from(Gdt::read_gdtr());
+        let idtr = FullSegmentSelector::from(Idt::read_idtr());
+
+        unsafe {
+            for (field, value) in [
+                segment(
+                    Cs::read().0,
+                    [
+                        VmcsField::GuestCsSelector,
+                        VmcsField::GuestCsBase,
+                        VmcsField::GuestCsLimit,
+                        VmcsField::GuestCsAccessRights,
+                    ],
+                ),
+                segment(
+                    Ss::read().0,
+                    [
+                        VmcsField::GuestSsSelector,
+                        VmcsField::GuestSsBase,
+                        VmcsField::GuestSsLimit,
+                        VmcsField::GuestSsAccessRights,
+                    ],
+                ),
+                segment(
+                    Ds::read().0,
+                    [
+                        VmcsField::GuestDsSelector,
+                        VmcsField::GuestDsBase,
+                        VmcsField::GuestDsLimit,
+                        VmcsField::GuestDsAccessRights,
+                    ],
+                ),
+                segment(
+                    Es::read().0,
+                    [
+                        VmcsField::GuestEsSelector,
+                        VmcsField::GuestEsBase,
+                        VmcsField::GuestEsLimit,
+                        VmcsField::GuestEsAccessRights,
+                    ],
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                write(field, value);
+            }
+
+            // FS and GS aren't used by the guest, and there is no LDT
+            for (selector, access_rights) in [
+                (VmcsField::GuestFsSelector, VmcsField::GuestFsAccessRights),
+                (VmcsField::GuestGsSelector, VmcsField::GuestGsAccessRights),
+                (
+                    VmcsField::GuestLdtrSelector,
+                    VmcsField::GuestLdtrAccessRights,
+                ),
+            ] {
+                write(selector, 0);
+                write(access_rights, u64::from(ACCESS_RIGHTS_UNUSABLE));
+            }
+
+            // The guest must have a TSS, so it shares the host's
+            write(VmcsField::GuestTrSelector, u64::from(u16::from(tr)));
+            write(VmcsField::GuestTrBase, tss_base);
+            write(VmcsField::GuestTrLimit, (size_of::<Tss>() - 1) as u64);
+            write(
+                VmcsField::GuestTrAccessRights,
+                u64::from(ACCESS_RIGHTS_BUSY_TSS),
+            );
+
+            write(VmcsField::GuestGdtrBase, gdtr.base);
+            write(VmcsField::GuestGdtrLimit, u64::from(gdtr.limit));
+            write(VmcsField::GuestIdtrBase, idtr.base);
+            write(VmcsField::GuestIdtrLimit, u64::from(idtr.limit));
+        };
+    }
+
+    /// Skip the instruction that caused the VM exit
+    fn skip_instruction() {
+        let rip = read(VmcsField::GuestRip);
+        write(
+            VmcsField::GuestRip,
+            rip + read(VmcsField::ExitInstructionLength),
+        );
+    }
+
+    /// Log every guest state field, to find out what's wrong with it after a failed VM entry
+    fn dump_guest_state() {
+        for &field in VmcsField::ALL {
+            if field.kind() == vmcs::FieldKind::GuestState
+                && let Ok(value) = unsafe { vmread(field) }
+            {
+                logger::err!("{field:?}: {value:#x}");
+            }
+        }
+    }
+
+    /// Handles an exception or NMI VM exit
+    fn handle_exception_or_nmi() {
+        let info = read(VmcsField::ExitInterruptionInfo) as u32;
+        let vector = info & 0xff;
+
+        if (info >> 8) & 0b111 == INTERRUPTION_TYPE_NMI {
+            // The NMI was meant for the host, but caused a VM exit instead of being delivered
+            unsafe { cpu::deliver_host_nmi() };
+            return;
+        }
+
+        let rip = read(VmcsField::GuestRip);
+        panic!("Unhandled guest exception {vector} at guest RIP {rip:#x}");
+    }
+
+    /// Handles an EPT violation.
+    ///
+    /// NOTE: Guest memory isn't mapped into the EPT yet, so there is nothing to map the page
+    /// from, and the fault is fatal
+    fn handle_ept_violation() {
+        let violation = EptViolation::decode(
+            read(VmcsField::ExitQualification),
+            read(VmcsField::GuestPhysicalAddress),
+        );
+        let rip = read(VmcsField::GuestRip);
+
+        panic!("Unhandled EPT violation at guest RIP {rip:#x}: {violation}");
+    }
+
+    /// Handles the VM exit.
+    ///
+    /// This if the very first function that is called when a VM exit happens.
+    fn handle_vmexit(&mut self) {
+        let exit = Exit::decode(read(VmcsField::ExitReason) as u32);
+        logger::info!("VMEXIT with reason: {}", exit);
+
+        if exit.entry_failure {
+            Self::dump_guest_state();
+            panic!(
+                "VM entry failed: {exit}, qualification {:#x}",
+                read(VmcsField::ExitQualification)
+            );
+        }
+        self.launched = true;
+
+        match exit.reason {
+            Ok(ExitReason::Cpuid) => {
+                logger::info!("CPUID intercept triggered");
+                Self::skip_instruction();
+            }
+            Ok(ExitReason::Hlt) => {
+                logger::info!("HLT intercept triggered");
+                Self::skip_instruction();
+            }
+            Ok(ExitReason::ExceptionOrNmi) => Self::handle_exception_or_nmi(),
+            Ok(
+                ExitReason::Vmcall
+                | ExitReason::Vmclear
+                | ExitReason::Vmlaunch
+                | ExitReason::Vmptrld
+                | ExitReason::Vmptrst
+                | ExitReason::Vmread
+                | ExitReason::Vmresume
+                | ExitReason::Vmwrite
+                | ExitReason::Vmxoff
+                | ExitReason::Vmxon
+                | ExitReason::Invept
+                | ExitReason::Invvpid,
+            ) => {
+                logger::err!("Nested virtualization is not supported yet.");
+                Self::skip_instruction();
+            }
+            Ok(ExitReason::EptViolation) => Self::handle_ept_violation(),
+            Ok(ExitReason::TripleFault) => panic!("The guest triple faulted"),
+            _ => panic!("Unhandled VMEXIT"),
+        }
+    }
+}
+
+impl VirtTech for Vmx {
+    type VesselControlBlock = Vmcs;
+
+    fn start() {
+        Self::ensure_vmx_operation();
+
+        logger::info!("Started VMX operation successfully");
+    }
+
+    fn stop() {
+        // VMX operation is per core, so this only affects the calling core. If a vCPU runs on it
+        // again, `ensure_vmx_operation` enters it again
+        let live_vcpus = LIVE_VCPUS[LocalApic::get_this_apic_id() as usize].load(Ordering::Relaxed);
+        if live_vcpus == 0 {
+            Self::vmxoff();
+            Self::disable();
+        } else {
+            // Leaving VMX operation would invalidate the VMCSs active on this core
+            logger::warn!(
+                "Not leaving VMX operation, since {live_vcpus} vCPUs are still active on this core"
+            );
+        }
+    }
+
+    fn new_nested_page_table() -> PhysAddr {
+        Self::allocate_zeroed_page()
+    }
+
+    unsafe fn free_nested_page_table(nested_page_table: PhysAddr) {
+        // TODO: Free the lower levels too once guest memory is actually mapped in
+        unsafe {
+            Self::free_page(nested_page_table);
+            // A new EPT might be allocated at the same address, so the old one's cached
+            // translations must go.
+            // NOTE: This only invalidates the calling core's translations
+            cpu::invept_all();
+        };
+    }
+}
+
+impl Vesselable for Vmcs {
+    fn new(rip: usize, nested_page_table: PhysAddr) -> VcpuBox<Self> {
+        Vmx::ensure_vmx_operation();
+
+        let mut vmcs = VcpuBox::new(Self::uninit());
+        vmcs.activate();
+        LIVE_VCPUS[vmcs.cpu as usize].fetch_add(1, Ordering::Relaxed);
+
+        Self::init_controls();
+        Self::init_host_state();
+        Self::init_guest_state(rip);
+        Self::set_nested_page_table(nested_page_table);
+
+        vmcs
+    }
+
+    fn run(&mut self) -> Result<(), VcpuError> {
+        // A VMCS has to be cleared on the core it's active on before another core can load it,
+        // and there is no way to get that core to do it yet, so vCPUs stay on their core
+        if self.cpu != LocalApic::get_this_apic_id() {
+            return Err(VcpuError::WrongCore);
+        }
+
+        let phys_addr = self.phys_addr();
+        unsafe { cpu::vmptrld(phys_addr) }.expect("VMPTRLD failed");
+
+        self.load_guest_fpu();
+        let result = unsafe { cpu::vmenter(self.launched) };
+        self.load_host_fpu();
+
+        match result {
+            Ok(()) => self.handle_vmexit(),
+            Err(VmFail::Valid) => panic!("VM entry failed with error {}", instruction_error()),
+            Err(VmFail::Invalid) => panic!("VM entry failed, with no current VMCS"),
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Vmcs {
+    fn drop(&mut self) {
+        if self.revision_id == 0 {
+            // Never activated, so the processor doesn't know about it
+            return;
+        }
+
+        // The processor might have parts of the VMCS cached, so it must be written back before
+        // the memory is reused
+        sanity_assert!(self.cpu == LocalApic::get_this_apic_id());
+        let phys_addr = self.phys_addr();
+        unsafe { cpu::vmclear(phys_addr) }.expect("VMCLEAR failed");
+
+        LIVE_VCPUS[self.cpu as usize].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl SlabAllocatable for Vmcs {}
+
+impl SlabAllocated for Vmcs {
+    fn allocator() -> &'static SlabAllocator<Self> {
+        &VMCS_ALLOCATOR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::offset_of;
+
+    #[test]
+    fn test_vmcs_layout() {
+        assert_eq!(offset_of!(Vmcs, revision_id), 0);
+        assert_eq!(offset_of!(Vmcs, abort_indicator), 4);
+
+        // The state that isn't part of the VMCS is past its page
+        assert_eq!(offset_of!(Vmcs, guest_fpu), VMCS_SIZE);
+        assert_eq!(align_of::<Vmcs>(), VMCS_SIZE);
+    }
+
+    #[test]
+    fn test_adjust_controls() {
+        // Bit 1 must be set, and bits 0-7 may be
+        let allowed = MsrData {
+            low: 0b10,
+            high: 0xff,
+        };
+
+        assert_eq!(adjust_controls(0, allowed), Some(0b10));
+        assert_eq!(adjust_controls(0b1000_0001, allowed), Some(0b1000_0011));
+        assert_eq!(adjust_controls(1 << 8, allowed), None);
+
+        // The controls the hypervisor uses, on a processor that supports all of them
+        let allowed = MsrData {
+            low: 0x16,
+            high: u32::MAX,
+        };
+        assert_eq!(
+            adjust_controls(PinBasedControls::NMI_EXITING, allowed),
+            Some(0x1e)
+        );
+    }
+
+    #[test]
+    fn test_access_rights() {
+        // A 64 bit code segment: Present, ring 0, code, with the long mode and granularity flags
+        assert_eq!(access_rights(0x9a, 0b1010), 0xa09a);
+
+        // A flat 32 bit data segment
+        assert_eq!(access_rights(0x92, 0b1100), 0xc092);
+    }
+}
@@ -0,0 +1,462 @@
+//! The fields of the VMCS, and the reasons for VM exits.
+//!
+//! Unlike the VMCB, the layout of the VMCS is implementation specific, so its fields can only be
+//! accessed with `VMREAD`/`VMWRITE`, by their encoding. Each encoding describes the field:
+//!
+//! - Bit 0: Access type. Set to access the high 32 bits of a 64 bit field on its own
+//! - Bits 9:1: The index of the field within its kind and width
+//! - Bits 11:10: The kind of the field (see `FieldKind`)
+//! - Bits 14:13: The width of the field (see `FieldWidth`)
+
+use core::fmt;
+
+/// The width of a VMCS field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FieldWidth {
+    Word,
+    Qword,
+    Dword,
+    /// 64 bits wide on processors that support Intel 64
+    Natural,
+}
+
+/// The kind of a VMCS field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FieldKind {
+    /// Controls the operation of the guest
+    Control,
+    /// Information about the last VM exit (or the last failed VMX instruction). Read only
+    ExitInformation,
+    /// Loaded on VM entry and saved on VM exit
+    GuestState,
+    /// Loaded on VM exit
+    HostState,
+}
+
+/// Define the `VmcsField` enum, along with a table of all of its variants
+macro_rules! vmcs_fields {
+    ($($(#[$doc:meta])* $name:ident = $encoding:literal,)*) => {
+        /// The VMCS fields the hypervisor uses
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u32)]
+        pub(super) enum VmcsField {
+            $($(#[$doc])* $name = $encoding,)*
+        }
+
+        impl VmcsField {
+            /// All of the fields, in encoding order
+            pub(super) const ALL: &[Self] = &[$(Self::$name,)*];
+        }
+    };
+}
+
+vmcs_fields! {
+    // 16 bit guest state
+    GuestEsSelector = 0x0800,
+    GuestCsSelector = 0x0802,
+    GuestSsSelector = 0x0804,
+    GuestDsSelector = 0x0806,
+    GuestFsSelector = 0x0808,
+    GuestGsSelector = 0x080a,
+    GuestLdtrSelector = 0x080c,
+    GuestTrSelector = 0x080e,
+
+    // 16 bit host state
+    HostEsSelector = 0x0c00,
+    HostCsSelector = 0x0c02,
+    HostSsSelector = 0x0c04,
+    HostDsSelector = 0x0c06,
+    HostFsSelector = 0x0c08,
+    HostGsSelector = 0x0c0a,
+    HostTrSelector = 0x0c0c,
+
+    // 64 bit controls
+    /// The root of the EPT, along with its memory type and page walk length
+    EptPointer = 0x201a,
+
+    // 64 bit exit information
+    /// The guest physical address that caused an EPT violation or misconfiguration
+    GuestPhysicalAddress = 0x2400,
+
+    // 64 bit guest state
+    /// Must be all ones, since VMCS shadowing isn't used
+    VmcsLinkPointer = 0x2800,
+    GuestIa32Efer = 0x2806,
+
+    // 64 bit host state
+    HostIa32Efer = 0x2c02,
+
+    // 32 bit controls
+    PinBasedControls = 0x4000,
+    PrimaryProcBasedControls = 0x4002,
+    /// A bit for every exception vector, set if the exception causes a VM exit
+    ExceptionBitmap = 0x4004,
+    ExitControls = 0x400c,
+    EntryControls = 0x4012,
+    /// The event injected into the guest on VM entry
+    EntryInterruptionInfo = 0x4016,
+    SecondaryProcBasedControls = 0x401e,
+
+    // 32 bit exit information
+    /// The error number of the last failed VMX instruction
+    VmInstructionError = 0x4400,
+    ExitReason = 0x4402,
+    ExitInterruptionInfo = 0x4404,
+    ExitInterruptionErrorCode = 0x4406,
+    /// The event that was being delivered when the VM exit happened
+    IdtVectoringInfo = 0x4408,
+    ExitInstructionLength = 0x440c,
+
+    // 32 bit guest state
+    GuestEsLimit = 0x4800,
+    GuestCsLimit = 0x4802,
+    GuestSsLimit = 0x4804,
+    GuestDsLimit = 0x4806,
+    GuestFsLimit = 0x4808,
+    GuestGsLimit = 0x480a,
+    GuestLdtrLimit = 0x480c,
+    GuestTrLimit = 0x480e,
+    GuestGdtrLimit = 0x4810,
+    GuestIdtrLimit = 0x4812,
+    GuestEsAccessRights = 0x4814,
+    GuestCsAccessRights = 0x4816,
+    GuestSsAccessRights = 0x4818,
+    GuestDsAccessRights = 0x481a,
+    GuestFsAccessRights = 0x481c,
+    GuestGsAccessRights = 0x481e,
+    GuestLdtrAccessRights = 0x4820,
+    GuestTrAccessRights = 0x4822,
+    GuestInterruptibility = 0x4824,
+    GuestActivityState = 0x4826,
+    GuestSysenterCs = 0x482a,
+
+    // 32 bit host state
+    HostSysenterCs = 0x4c00,
+
+    // Natural width exit information
+    /// Extra information about the VM exit, depending on its reason
+    ExitQualification = 0x6400,
+    GuestLinearAddress = 0x640a,
+
+    // Natural width guest state
+    GuestCr0 = 0x6800,
+    GuestCr3 = 0x6802,
+    GuestCr4 = 0x6804,
+    GuestEsBase = 0x6806,
+    GuestCsBase = 0x6808,
+    GuestSsBase = 0x680a,
+    GuestDsBase = 0x680c,
+    GuestFsBase = 0x680e,
+    GuestGsBase = 0x6810,
+    GuestLdtrBase = 0x6812,
+    GuestTrBase = 0x6814,
+    GuestGdtrBase = 0x6816,
+    GuestIdtrBase = 0x6818,
+    GuestDr7 = 0x681a,
+    GuestRsp = 0x681c,
+    GuestRip = 0x681e,
+    GuestRflags = 0x6820,
+    GuestSysenterEsp = 0x6824,
+    GuestSysenterEip = 0x6826,
+
+    // Natural width host state
+    HostCr0 = 0x6c00,
+    HostCr3 = 0x6c02,
+    HostCr4 = 0x6c04,
+    HostFsBase = 0x6c06,
+    HostGsBase = 0x6c08,
+    HostTrBase = 0x6c0a,
+    HostGdtrBase = 0x6c0c,
+    HostIdtrBase = 0x6c0e,
+    HostSysenterEsp = 0x6c10,
+    HostSysenterEip = 0x6c12,
+    HostRsp = 0x6c14,
+    HostRip = 0x6c16,
+}
+
+impl VmcsField {
+    /// Get the encoding `VMREAD`/`VMWRITE` take
+    #[inline]
+    pub(super) const fn encoding(self) -> u32 {
+        self as u32
+    }
+
+    /// Get the width of the field
+    pub(super) const fn width(self) -> FieldWidth {
+        match (self.encoding() >> 13) & 0b11 {
+            0 => FieldWidth::Word,
+            1 => FieldWidth::Qword,
+            2 => FieldWidth::Dword,
+            _ => FieldWidth::Natural,
+        }
+    }
+
+    /// Get the kind of the field
+    pub(super) const fn kind(self) -> FieldKind {
+        match (self.encoding() >> 10) & 0b11 {
+            0 => FieldKind::Control,
+            1 => FieldKind::ExitInformation,
+            2 => FieldKind::GuestState,
+            _ => FieldKind::HostState,
+        }
+    }
+
+    /// Get the field `encoding` stands for, or `None` if it isn't one the hypervisor uses
+    pub(super) fn from_encoding(encoding: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|field| field.encoding() == encoding)
+    }
+}
+
+/// The basic reason of a VM exit, found in the low 16 bits of the exit reason field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub(super) enum ExitReason {
+    /// An exception set in the exception bitmap, or an NMI
+    ExceptionOrNmi = 0,
+    ExternalInterrupt = 1,
+    TripleFault = 2,
+    Init = 3,
+    Sipi = 4,
+    InterruptWindow = 7,
+    NmiWindow = 8,
+    TaskSwitch = 9,
+    Cpuid = 10,
+    Getsec = 11,
+    Hlt = 12,
+    Invd = 13,
+    Invlpg = 14,
+    Rdpmc = 15,
+    Rdtsc = 16,
+    Rsm = 17,
+    Vmcall = 18,
+    Vmclear = 19,
+    Vmlaunch = 20,
+    Vmptrld = 21,
+    Vmptrst = 22,
+    Vmread = 23,
+    Vmresume = 24,
+    Vmwrite = 25,
+    Vmxoff = 26,
+    Vmxon = 27,
+    CrAccess = 28,
+    DrAccess = 29,
+    IoInstruction = 30,
+    Rdmsr = 31,
+    Wrmsr = 32,
+    /// VM entry failed because the guest state is invalid
+    InvalidGuestState = 33,
+    /// VM entry failed while loading MSRs
+    MsrLoading = 34,
+    Mwait = 36,
+    MonitorTrapFlag = 37,
+    Monitor = 39,
+    Pause = 40,
+    /// VM entry failed because of a machine check
+    MachineCheck = 41,
+    TprBelowThreshold = 43,
+    ApicAccess = 44,
+    GdtrIdtrAccess = 46,
+    LdtrTrAccess = 47,
+    EptViolation = 48,
+    EptMisconfig = 49,
+    Invept = 50,
+    Rdtscp = 51,
+    PreemptionTimer = 52,
+    Invvpid = 53,
+    Wbinvd = 54,
+    Xsetbv = 55,
+}
+
+impl ExitReason {
+    /// Get the reason whose number is `basic`, or `None` if the hypervisor doesn't know it
+    const fn from_basic(basic: u16) -> Option<Self> {
+        Some(match basic {
+            0 => Self::ExceptionOrNmi,
+            1 => Self::ExternalInterrupt,
+            2 => Self::TripleFault,
+            3 => Self::Init,
+            4 => Self::Sipi,
+            7 => Self::InterruptWindow,
+            8 => Self::NmiWindow,
+            9 => Self::TaskSwitch,
+            10 => Self::Cpuid,
+            11 => Self::Getsec,
+            12 => Self::Hlt,
+            13 => Self::Invd,
+            14 => Self::Invlpg,
+            15 => Self::Rdpmc,
+            16 => Self::Rdtsc,
+            17 => Self::Rsm,
+            18 => Self::Vmcall,
+            19 => Self::Vmclear,
+            20 => Self::Vmlaunch,
+            21 => Self::Vmptrld,
+            22 => Self::Vmptrst,
+            23 => Self::Vmread,
+            24 => Self::Vmresume,
+            25 => Self::Vmwrite,
+            26 => Self::Vmxoff,
+            27 => Self::Vmxon,
+            28 => Self::CrAccess,
+            29 => Self::DrAccess,
+            30 => Self::IoInstruction,
+            31 => Self::Rdmsr,
+            32 => Self::Wrmsr,
+            33 => Self::InvalidGuestState,
+            34 => Self::MsrLoading,
+            36 => Self::Mwait,
+            37 => Self::MonitorTrapFlag,
+            39 => Self::Monitor,
+            40 => Self::Pause,
+            41 => Self::MachineCheck,
+            43 => Self::TprBelowThreshold,
+            44 => Self::ApicAccess,
+            46 => Self::GdtrIdtrAccess,
+            47 => Self::LdtrTrAccess,
+            48 => Self::EptViolation,
+            49 => Self::EptMisconfig,
+            50 => Self::Invept,
+            51 => Self::Rdtscp,
+            52 => Self::PreemptionTimer,
+            53 => Self::Invvpid,
+            54 => Self::Wbinvd,
+            55 => Self::Xsetbv,
+            _ => return None,
+        })
+    }
+}
+
+/// A decoded exit reason field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Exit {
+    /// The basic reason, or `Err` with its number if the hypervisor doesn't know it
+    pub reason: Result<ExitReason, u16>,
+    /// Set if the exit is actually a failed VM entry
+    pub entry_failure: bool,
+}
+
+impl Exit {
+    /// Set in the exit reason field if VM entry failed
+    const ENTRY_FAILURE: u32 = 1 << 31;
+
+    /// Decode the value of the exit reason field
+    pub(super) const fn decode(raw: u32) -> Self {
+        let basic = raw as u16;
+
+        Self {
+            reason: match ExitReason::from_basic(basic) {
+                Some(reason) => Ok(reason),
+                None => Err(basic),
+            },
+            entry_failure: raw & Self::ENTRY_FAILURE != 0,
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            Ok(reason) => write!(f, "{reason:?}")?,
+            Err(basic) => write!(f, "unknown reason {basic}")?,
+        }
+        if self.entry_failure {
+            write!(f, " (VM entry failure)")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_field_encoding() {
+        let field = |field: VmcsField| (field.width(), field.kind());
+
+        assert_eq!(
+            field(VmcsField::GuestCsSelector),
+            (FieldWidth::Word, FieldKind::GuestState)
+        );
+        assert_eq!(
+            field(VmcsField::HostTrSelector),
+            (FieldWidth::Word, FieldKind::HostState)
+        );
+        assert_eq!(
+            field(VmcsField::EptPointer),
+            (FieldWidth::Qword, FieldKind::Control)
+        );
+        assert_eq!(
+            field(VmcsField::GuestPhysicalAddress),
+            (FieldWidth::Qword, FieldKind::ExitInformation)
+        );
+        assert_eq!(
+            field(VmcsField::PinBasedControls),
+            (FieldWidth::Dword, FieldKind::Control)
+        );
+        assert_eq!(
+            field(VmcsField::ExitReason),
+            (FieldWidth::Dword, FieldKind::ExitInformation)
+        );
+        assert_eq!(
+            field(VmcsField::GuestRip),
+            (FieldWidth::Natural, FieldKind::GuestState)
+        );
+        assert_eq!(
+            field(VmcsField::HostRsp),
+            (FieldWidth::Natural, FieldKind::HostState)
+        );
+    }
+
+    #[test]
+    fn test_field_table() {
+        // Every field is a full access one (bit 0 clear), and the fields are listed in encoding
+        // order, so each encoding maps back to exactly one field
+        assert!(VmcsField::ALL.iter().all(|field| field.encoding() & 1 == 0));
+        assert!(
+            VmcsField::ALL
+                .windows(2)
+                .all(|pair| pair[0].encoding() < pair[1].encoding())
+        );
+        for &field in VmcsField::ALL {
+            assert_eq!(VmcsField::from_encoding(field.encoding()), Some(field));
+        }
+
+        // The field names agree with their encodings
+        for &field in VmcsField::ALL {
+            let name = alloc::format!("{field:?}");
+            if name.starts_with("Host") {
+                assert_eq!(field.kind(), FieldKind::HostState, "{name}");
+            } else if name.starts_with("Guest")
+                && field != VmcsField::GuestPhysicalAddress
+                && field != VmcsField::GuestLinearAddress
+            {
+                assert_eq!(field.kind(), FieldKind::GuestState, "{name}");
+            }
+        }
+
+        assert_eq!(VmcsField::from_encoding(0x681f), None);
+        assert_eq!(VmcsField::from_encoding(0xffff), None);
+    }
+
+    #[test]
+    fn test_exit_decode() {
+        let exit = Exit::decode(10);
+        assert_eq!(exit.reason, Ok(ExitReason::Cpuid));
+        assert!(!exit.entry_failure);
+
+        let exit = Exit::decode((1 << 31) | 0x21);
+        assert_eq!(exit.reason, Ok(ExitReason::InvalidGuestState));
+        assert!(exit.entry_failure);
+        assert_eq!(exit.to_string(), "InvalidGuestState (VM entry failure)");
+
+        // Reasons the hypervisor doesn't know are kept by their number
+        assert_eq!(Exit::decode(0x1_0005).reason, Err(5));
+        assert_eq!(Exit::decode(5).to_string(), "unknown reason 5");
+    }
+}
@@ -0,0 +1,135 @@
+//! Everything specific to the `aarch64` architecture.
+
+use core::arch::asm;
+
+use utils::boot_info::{BootInfo, MemoryRegion};
+use utils::mem::{PhysAddr, VirtAddr};
+
+use crate::mem::paging::{Flags, PageSize, PagingError, PagingManager};
+
+use super::Arch;
+
+pub mod paging;
+
+/// a ZST to implement the Arch trait on
+pub struct Aarch64;
+
+impl Arch for Aarch64 {
+    #[inline]
+    unsafe fn early_boot_init() {
+        utils::once_per_boot!();
+
+        // Interrupts stay masked until there is a vector table to handle them
+        unsafe {
+            asm!(
+                "msr daifset, #0xf",
+                options(nomem, nostack, preserves_flags)
+            );
+        };
+
+        // The compiler freely uses the FP/SIMD registers, so accessing them must not trap
+        // (`CPACR_EL1.FPEN`)
+        unsafe {
+            asm!(
+                "mrs {0}, cpacr_el1",
+                "orr {0}, {0}, #(0b11 << 20)",
+                "msr cpacr_el1, {0}",
+                "isb",
+                out(reg) _,
+                options(nostack, preserves_flags),
+            );
+        };
+    }
+
+    #[inline]
+    fn wait_for_interrupt() {
+        unsafe {
+            asm!("msr daifclr, #0b0010", "wfi", options(nomem, nostack));
+        };
+    }
+}
+
+impl PagingManager for Aarch64 {
+    const BASIC_PAGE_SIZE: PageSize<Self> = PageSize::<Self>::size_4kb(); // 4KB granule
+
+    #[inline]
+    unsafe fn init_paging(boot_info: &BootInfo, used_by_pmm: &MemoryRegion) {
+        unsafe { paging::init(boot_info, used_by_pmm) };
+    }
+
+    unsafe fn map_pages_to(
+        phys_addr: PhysAddr,
+        virt_addr: VirtAddr,
+        count: usize,
+        flags: Flags<Self>,
+        page_size: PageSize<Self>,
+    ) -> Result<(), PagingError> {
+        let root = paging::get_root_table(virt_addr)?;
+        unsafe { root.map_pages(virt_addr, phys_addr, count, page_size, flags) }
+    }
+
+    unsafe fn unmap_pages(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Self>,
+    ) -> Result<(), PagingError> {
+        let root = paging::get_root_table(virt_addr)?;
+        unsafe { root.unmap_pages(virt_addr, page_count, page_size)? };
+
+        for i in 0..page_count {
+            Self::flush_address(virt_addr + i * page_size.size());
+        }
+
+        Ok(())
+    }
+
+    unsafe fn unmap_pages_keep_frames(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Self>,
+    ) -> Result<PhysAddr, PagingError> {
+        let root = paging::get_root_table(virt_addr)?;
+        let phys_addr = unsafe { root.unmap_keep_frame(virt_addr, page_count, page_size)? };
+
+        for i in 0..page_count {
+            Self::flush_address(virt_addr + i * page_size.size());
+        }
+
+        Ok(phys_addr)
+    }
+
+    unsafe fn change_flags(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        flags: Flags<Self>,
+        page_size: PageSize<Self>,
+    ) -> Result<(), PagingError> {
+        let root = paging::get_root_table(virt_addr)?;
+        unsafe { root.change_flags(virt_addr, page_count, page_size, flags)? };
+
+        for i in 0..page_count {
+            Self::flush_address(virt_addr + i * page_size.size());
+        }
+
+        Ok(())
+    }
+
+    fn translate(virt_addr: VirtAddr) -> Option<PhysAddr> {
+        paging::get_root_table(virt_addr).ok()?.translate(virt_addr)
+    }
+
+    #[inline]
+    fn flush_address(virt_addr: VirtAddr) {
+        paging::tlbi_va(virt_addr);
+    }
+
+    #[inline]
+    fn flush_all() {
+        paging::tlbi_all();
+    }
+
+    #[inline]
+    fn resident_pages() -> usize {
+        paging::resident_pages()
+    }
+}
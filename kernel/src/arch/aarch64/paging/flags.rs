@@ -0,0 +1,160 @@
+use crate::{arch::aarch64::Aarch64, mem::paging::Flags};
+
+/// The memory types the kernel programs into `MAIR_EL1`, in the order of their indices there.
+/// Descriptors pick one with their `AttrIndx` bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Normal memory, inner and outer write-back cacheable
+    Normal = 0,
+    /// Device-nGnRnE memory, for MMIO
+    Device = 1,
+    /// Normal memory that isn't cached, which allows gathering writes (e.g. for framebuffers)
+    NormalNonCacheable = 2,
+}
+
+impl MemoryType {
+    /// The value of `MAIR_EL1`, with the attributes of every type at its index
+    pub(super) const MAIR: u64 = u64::from_le_bytes([
+        0xff, // Normal: inner and outer write-back, read and write allocate
+        0x00, // Device-nGnRnE
+        0x44, // Normal: inner and outer non-cacheable
+        0, 0, 0, 0, 0,
+    ]);
+}
+
+impl Flags<Aarch64> {
+    /// Keep all flags as off
+    pub const FLAGS_NONE: usize = 0;
+    /// The shift of the `AttrIndx` bits (`2-4`), the index of the entry's memory type in
+    /// `MAIR_EL1` (see `MemoryType`)
+    pub(super) const ATTR_INDEX_SHIFT: usize = 2;
+    /// The `AttrIndx` bits
+    pub(super) const ATTR_INDEX_MASK: usize = 0b111 << Self::ATTR_INDEX_SHIFT;
+    /// `AP[1]` bit (`6`):
+    /// - If `1` EL0 can access the page as well
+    /// - If `0` only EL1 can access the page
+    pub(super) const FLAG_AP_EL0: usize = 1 << 6;
+    /// `AP[2]` bit (`7`):
+    /// - If `1` the page is read-only
+    /// - If `0` the page is writeable and readable
+    pub(super) const FLAG_AP_RO: usize = 1 << 7;
+    /// Inner shareable (`SH` bits `8-9` set to `0b11`), so the mapping is coherent across all the
+    /// cores
+    pub(super) const FLAG_SH_INNER: usize = 0b11 << 8;
+    /// Access flag (`10`). Accessing a page without it faults, so it's always set since we don't
+    /// track accesses
+    pub(super) const FLAG_AF: usize = 1 << 10;
+    /// Not global bit (`11`):
+    /// - If `1` the TLB entries of the page are tagged with the current ASID
+    /// - If `0` the TLB entries of the page apply to all ASIDs
+    pub(super) const FLAG_NG: usize = 1 << 11;
+    /// Privileged execute never bit (`53`). If `1` EL1 can't execute from the page
+    pub(super) const FLAG_PXN: usize = 1 << 53;
+    /// Unprivileged execute never bit (`54`). If `1` EL0 can't execute from the page
+    pub(super) const FLAG_UXN: usize = 1 << 54;
+
+    /// Custom flag to mark a page as an allocated one, so we should free it when the time comes.
+    ///
+    /// NOTE: Bits `55-58` are reserved for software use
+    pub(super) const FLAG_ALLOCATED: usize = 1 << 55;
+
+    /// All of the bits of a descriptor that are flags (as opposed to the address, or the valid and
+    /// type bits)
+    pub(super) const MASK: usize = (0xfff & !0b11) | (0x1fff << 51);
+
+    /// Create a new `Flags` instance for read-only, kernel only, normal memory.
+    ///
+    /// NOTE: The defaults are the same as on `x86_64`, so the architecture independent code gets
+    /// the same mappings on both
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        unsafe {
+            Self::from_raw(
+                Self::FLAGS_NONE | Self::FLAG_AF | Self::FLAG_SH_INNER | Self::FLAG_AP_RO,
+            )
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn set_read_write(self, status: bool) -> Self {
+        self.set(Self::FLAG_AP_RO, !status)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn set_user_supervisor(self, status: bool) -> Self {
+        self.set(Self::FLAG_AP_EL0, status)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn set_global(self, status: bool) -> Self {
+        self.set(Self::FLAG_NG, !status)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn set_execute_disable(self, status: bool) -> Self {
+        self.set(Self::FLAG_PXN | Self::FLAG_UXN, status)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn set_memory_type(self, memory_type: MemoryType) -> Self {
+        unsafe {
+            Self::from_raw(
+                (self.data() & !Self::ATTR_INDEX_MASK)
+                    | ((memory_type as usize) << Self::ATTR_INDEX_SHIFT),
+            )
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub(super) const fn set_allocated(self, status: bool) -> Self {
+        self.set(Self::FLAG_ALLOCATED, status)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_read_write(self) -> bool {
+        !self.get(Self::FLAG_AP_RO)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_user_supervisor(self) -> bool {
+        self.get(Self::FLAG_AP_EL0)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_global(self) -> bool {
+        !self.get(Self::FLAG_NG)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_execute_disable(self) -> bool {
+        self.get(Self::FLAG_PXN)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_memory_type(self) -> Option<MemoryType> {
+        match (self.data() & Self::ATTR_INDEX_MASK) >> Self::ATTR_INDEX_SHIFT {
+            0 => Some(MemoryType::Normal),
+            1 => Some(MemoryType::Device),
+            2 => Some(MemoryType::NormalNonCacheable),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_allocated(self) -> bool {
+        self.get(Self::FLAG_ALLOCATED)
+    }
+}
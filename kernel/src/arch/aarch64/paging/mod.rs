@@ -0,0 +1,769 @@
+//! Stage 1 translation of EL1 addresses, with a 4KB granule and 48 bit virtual addresses.
+//!
+//! The lower half of the address space is translated by the table in `TTBR0_EL1`, and the higher
+//! (kernel) half by the one in `TTBR1_EL1`.
+
+use core::arch::asm;
+use core::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use page_size::{LAST_LEVEL, ROOT_LEVEL};
+use pmm::PmmAllocator;
+use utils::{
+    boot_info::{BootInfo, MemoryRegion, MemoryRegionKind},
+    mem::{PhysAddr, VirtAddr, memset},
+};
+
+use crate::mem::paging::{Flags, PageSize, PagingError, RESIDENT_PAGES};
+
+use super::Aarch64;
+
+pub mod flags;
+pub mod page_size;
+
+pub use flags::MemoryType;
+
+/// The number of entries per translation table
+pub const ENTRIES_PER_TABLE: usize = 512;
+
+/// The amount of bits of a virtual address that are translated. The bits above them select the
+/// half (and so the root table), and must be either all `0` or all `1`
+const VIRT_ADDR_BITS: usize = 48;
+
+/// The physical address of the root table of the lower half (`TTBR0_EL1`), or 0 before paging is
+/// initialized. Kept here so it doesn't have to be read back from the register
+static LOWER_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+/// The physical address of the root table of the higher half (`TTBR1_EL1`), or 0 before paging is
+/// initialized
+static HIGHER_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+/// A descriptor in a translation table
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Descriptor(usize);
+
+/// A translation table
+#[repr(C, align(4096))]
+#[derive(Debug)]
+pub(super) struct PageTable([Descriptor; ENTRIES_PER_TABLE]);
+
+impl Descriptor {
+    /// Valid bit (`0`). Translating through an invalid descriptor faults
+    const VALID: usize = 1 << 0;
+    /// Type bit (`1`):
+    /// - On levels 0-2, `1` for a table descriptor and `0` for a block descriptor
+    /// - On level 3, must be `1` (a page descriptor)
+    const TABLE_OR_PAGE: usize = 1 << 1;
+    /// The output address bits (`12-47`)
+    const ADDR_MASK: usize = 0x0000_ffff_ffff_f000;
+
+    /// A descriptor that translates nothing
+    const INVALID: Self = Self(0);
+
+    /// A descriptor pointing to the next level table at `table_addr`
+    #[inline]
+    #[must_use]
+    const fn table(table_addr: PhysAddr) -> Self {
+        Self((table_addr.0 & Self::ADDR_MASK) | Self::TABLE_OR_PAGE | Self::VALID)
+    }
+
+    /// A descriptor of a level `level` table, mapping a page (or block) at `phys_addr` with
+    /// `flags`
+    #[inline]
+    #[must_use]
+    const fn leaf(phys_addr: PhysAddr, flags: Flags<Aarch64>, level: usize) -> Self {
+        let kind = if level == LAST_LEVEL {
+            Self::TABLE_OR_PAGE
+        } else {
+            0
+        };
+
+        Self(
+            (phys_addr.0 & Self::ADDR_MASK)
+                | (flags.data() & Flags::<Aarch64>::MASK)
+                | kind
+                | Self::VALID,
+        )
+    }
+
+    #[inline]
+    #[must_use]
+    const fn is_valid(self) -> bool {
+        self.0 & Self::VALID != 0
+    }
+
+    /// Returns true if the descriptor (of a level `level` table) points to a next level table
+    #[inline]
+    #[must_use]
+    const fn is_table(self, level: usize) -> bool {
+        self.is_valid() && level != LAST_LEVEL && self.0 & Self::TABLE_OR_PAGE != 0
+    }
+
+    /// Returns true if the descriptor (of a level `level` table) maps a page or a block
+    #[inline]
+    #[must_use]
+    const fn is_leaf(self, level: usize) -> bool {
+        self.is_valid() && !self.is_table(level)
+    }
+
+    /// Get the address of the page, block, or table the descriptor points to
+    #[inline]
+    #[must_use]
+    const fn addr(self) -> PhysAddr {
+        PhysAddr(self.0 & Self::ADDR_MASK)
+    }
+
+    #[inline]
+    #[must_use]
+    const fn flags(self) -> Flags<Aarch64> {
+        unsafe { Flags::from_raw(self.0 & Flags::<Aarch64>::MASK) }
+    }
+}
+
+impl PageTable {
+    /// Allocates a new translation table
+    ///
+    /// # Panics
+    /// Panics if there is no physical memory left for the table
+    pub fn new() -> (&'static mut Self, PhysAddr) {
+        Self::try_new().expect("Failed to allocate translation table")
+    }
+
+    /// Tries to allocate a new translation table
+    ///
+    /// # Errors
+    /// Fails with `PagingError::OutOfMemory` if there is no physical memory left for the table
+    pub fn try_new() -> Result<(&'static mut Self, PhysAddr), PagingError> {
+        let phys_addr = pmm::get()
+            .allocate(PageSize::<Aarch64>::size_4kb().page_alignment(), 1)
+            .map_err(|_| PagingError::OutOfMemory)?;
+
+        // Same as on `x86_64`, all the tables are accessed through the HHDM
+        let ptr: *mut u8 = core::ptr::without_provenance_mut(phys_addr.add_hhdm_offset().0);
+        // An all zeroes table is all invalid descriptors
+        unsafe {
+            memset(ptr, 0, size_of::<PageTable>());
+        };
+
+        Ok((
+            unsafe { ptr.cast::<PageTable>().as_mut().unwrap() },
+            phys_addr,
+        ))
+    }
+
+    /// Get the table the `i`th descriptor points to
+    fn table_at(&mut self, i: usize) -> &mut PageTable {
+        let ptr: *mut PageTable =
+            core::ptr::without_provenance_mut(self[i].addr().add_hhdm_offset().0);
+
+        unsafe { ptr.as_mut().expect("Failed to get next level table") }
+    }
+
+    /// Get the level `level` table translating `virt_addr`, creating the tables missing on the way.
+    ///
+    /// NOTE: If a table can't be allocated, the tables created before it are left in, empty
+    fn get_create_table(
+        &mut self,
+        virt_addr: VirtAddr,
+        level: usize,
+    ) -> Result<&mut PageTable, PagingError> {
+        let mut table = self;
+        for current in ROOT_LEVEL..level {
+            let i = table_index(virt_addr, current);
+            if !table[i].is_valid() {
+                let (_, table_addr) = PageTable::try_new()?;
+                table[i] = Descriptor::table(table_addr);
+            } else if !table[i].is_table(current) {
+                // A bigger block already maps the address
+                return Err(PagingError::PageAlreadyPresent(virt_addr));
+            }
+
+            table = table.table_at(i);
+        }
+
+        Ok(table)
+    }
+
+    /// Get the level `level` table translating `virt_addr`, or `None` if one of the tables on the
+    /// way is missing
+    fn get_table(&mut self, virt_addr: VirtAddr, level: usize) -> Option<&mut PageTable> {
+        let mut table = self;
+        for current in ROOT_LEVEL..level {
+            let i = table_index(virt_addr, current);
+            if !table[i].is_table(current) {
+                return None;
+            }
+
+            table = table.table_at(i);
+        }
+
+        Some(table)
+    }
+
+    /// Maps `page_count` pages of `page_size` starting at `base_addr` to the physical pages
+    /// starting at `phys_addr`
+    pub(super) unsafe fn map_pages(
+        &mut self,
+        base_addr: VirtAddr,
+        phys_addr: PhysAddr,
+        page_count: usize,
+        page_size: PageSize<Aarch64>,
+        flags: Flags<Aarch64>,
+    ) -> Result<(), PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        } else if !phys_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedPhysicalAddress(phys_addr));
+        }
+
+        let root = core::ptr::from_ref(self).addr();
+        let level = page_size.level();
+        let table = self.get_create_table(base_addr, level)?;
+
+        let to_skip = table_index(base_addr, level);
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        // Check the entire range first, so the table is left untouched on error
+        let descriptors = &mut table[to_skip..to_skip + page_count];
+        if let Some(taken) = descriptors
+            .iter()
+            .position(|descriptor| descriptor.is_valid())
+        {
+            return Err(PagingError::PageAlreadyPresent(
+                base_addr + taken * page_size.size(),
+            ));
+        }
+
+        let flags = with_global_for(base_addr, flags);
+        for (i, descriptor) in descriptors.iter_mut().enumerate() {
+            *descriptor = Descriptor::leaf(phys_addr + i * page_size.size(), flags, level);
+        }
+
+        if flags.get_allocated() {
+            RESIDENT_PAGES.add(root, page_count * page_size.to_default_page_count());
+        }
+
+        // The descriptors weren't valid before, so nothing is cached in the TLB, but the table
+        // walker still has to see the writes
+        publish_tables();
+
+        Ok(())
+    }
+
+    /// Unmaps `page_count` pages of `page_size` starting at `base_addr`, freeing the ones that were
+    /// allocated.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it
+    pub(super) unsafe fn unmap_pages(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Aarch64>,
+    ) -> Result<(), PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        let root = core::ptr::from_ref(self).addr();
+        let level = page_size.level();
+        let table = self
+            .get_table(base_addr, level)
+            .ok_or(PagingError::PageNotPresent(base_addr))?;
+
+        let to_skip = table_index(base_addr, level);
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        for (i, descriptor) in table.iter_mut().skip(to_skip).take(page_count).enumerate() {
+            if !descriptor.is_leaf(level) {
+                return Err(PagingError::PageNotPresent(
+                    base_addr + i * page_size.size(),
+                ));
+            }
+
+            if descriptor.flags().get_allocated() {
+                unsafe {
+                    pmm::get()
+                        .free(descriptor.addr(), page_size.to_default_page_count())
+                        .expect("Failed to free page");
+                };
+                RESIDENT_PAGES.sub(root, page_size.to_default_page_count());
+            }
+
+            *descriptor = Descriptor::INVALID;
+        }
+
+        Ok(())
+    }
+
+    /// Unmaps the given range like `unmap_pages`, but leaves the physical pages allocated, handing
+    /// them over to the caller. Returns the physical address the first page was mapped to, which
+    /// covers all of them, as the pages must be mapped to contiguous frames.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it
+    pub(super) unsafe fn unmap_keep_frame(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Aarch64>,
+    ) -> Result<PhysAddr, PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        let root = core::ptr::from_ref(self).addr();
+        let level = page_size.level();
+        let table = self
+            .get_table(base_addr, level)
+            .ok_or(PagingError::PageNotPresent(base_addr))?;
+
+        let to_skip = table_index(base_addr, level);
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        // Check the entire range first, so the table is left untouched on error
+        let descriptors = &mut table[to_skip..to_skip + page_count];
+        if let Some(missing) = descriptors
+            .iter()
+            .position(|descriptor| !descriptor.is_leaf(level))
+        {
+            return Err(PagingError::PageNotPresent(
+                base_addr + missing * page_size.size(),
+            ));
+        }
+        let phys_addr = descriptors
+            .first()
+            .ok_or(PagingError::PageNotPresent(base_addr))?
+            .addr();
+        // Only the first frame is handed back, so the rest have to follow it
+        if let Some(scattered) = descriptors
+            .iter()
+            .enumerate()
+            .position(|(i, descriptor)| descriptor.addr() != phys_addr + i * page_size.size())
+        {
+            return Err(PagingError::NonContiguousFrames(
+                base_addr + scattered * page_size.size(),
+            ));
+        }
+
+        for descriptor in descriptors {
+            // The caller owns the frames from now on, so they aren't freed
+            if descriptor.flags().get_allocated() {
+                RESIDENT_PAGES.sub(root, page_size.to_default_page_count());
+            }
+
+            *descriptor = Descriptor::INVALID;
+        }
+
+        Ok(phys_addr)
+    }
+
+    /// Replaces the flags of the given mapped range, keeping the physical pages they're mapped to.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it. Changing the memory type of
+    /// a live mapping requires a break-before-make sequence, which is also up to the caller
+    pub(super) unsafe fn change_flags(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Aarch64>,
+        flags: Flags<Aarch64>,
+    ) -> Result<(), PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        let level = page_size.level();
+        let table = self
+            .get_table(base_addr, level)
+            .ok_or(PagingError::PageNotPresent(base_addr))?;
+
+        let to_skip = table_index(base_addr, level);
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        let descriptors = &mut table[to_skip..to_skip + page_count];
+        if let Some(missing) = descriptors
+            .iter()
+            .position(|descriptor| !descriptor.is_leaf(level))
+        {
+            return Err(PagingError::PageNotPresent(
+                base_addr + missing * page_size.size(),
+            ));
+        }
+
+        let flags = with_global_for(base_addr, flags);
+        for descriptor in descriptors {
+            // Whether the frame is ours to free doesn't change with the permissions
+            let allocated = descriptor.flags().get_allocated();
+            *descriptor =
+                Descriptor::leaf(descriptor.addr(), flags.set_allocated(allocated), level);
+        }
+
+        Ok(())
+    }
+
+    /// Get the physical address `virt_addr` is mapped to, or `None` if it isn't mapped
+    pub(super) fn translate(&mut self, virt_addr: VirtAddr) -> Option<PhysAddr> {
+        let mut table = self;
+        for level in ROOT_LEVEL..=LAST_LEVEL {
+            let descriptor = table[table_index(virt_addr, level)];
+            if descriptor.is_leaf(level) {
+                let page_size = PageSize::<Aarch64>::from_level(level)?;
+                return Some(descriptor.addr() + (virt_addr.0 & (page_size.size() - 1)));
+            } else if !descriptor.is_valid() {
+                return None;
+            }
+
+            table = table.table_at(table_index(virt_addr, level));
+        }
+
+        None
+    }
+}
+
+impl Deref for PageTable {
+    type Target = [Descriptor; ENTRIES_PER_TABLE];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for PageTable {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Get the index of the descriptor translating `addr` in a level `level` table
+#[inline]
+#[must_use]
+pub(super) const fn table_index(addr: VirtAddr, level: usize) -> usize {
+    (addr.0 >> (12 + (LAST_LEVEL - level) * 9)) & 0b1_1111_1111
+}
+
+/// Returns true if `virt_addr` is in the higher (kernel) half of the address space, which is
+/// translated through `TTBR1_EL1`
+#[inline]
+#[must_use]
+const fn is_higher_half(virt_addr: VirtAddr) -> bool {
+    virt_addr.0 & (1 << 63) != 0
+}
+
+/// Returns true if the bits of `virt_addr` above the translated ones are either all `0` or all
+/// `1`. Other addresses fault without being translated.
+///
+/// NOTE: Unlike on `x86_64`, the top translated bit doesn't have to match them
+#[inline]
+#[must_use]
+const fn is_canonical(virt_addr: VirtAddr) -> bool {
+    let top = virt_addr.0 >> VIRT_ADDR_BITS;
+
+    top == 0 || top == usize::MAX >> VIRT_ADDR_BITS
+}
+
+/// Set the global bit of `flags` if `virt_addr` is in the kernel's (higher) half of the address
+/// space, and clear it otherwise, so only the lower half's TLB entries are tagged with the ASID
+#[inline]
+#[must_use]
+fn with_global_for(virt_addr: VirtAddr, flags: Flags<Aarch64>) -> Flags<Aarch64> {
+    flags.set_global(is_higher_half(virt_addr))
+}
+
+/// Get the root table translating `virt_addr`
+///
+/// # Panics
+/// Panics if paging wasn't initialized yet
+pub(super) fn get_root_table(virt_addr: VirtAddr) -> Result<&'static mut PageTable, PagingError> {
+    if !is_canonical(virt_addr) {
+        return Err(PagingError::InvalidVirtualAddress);
+    }
+
+    let root = if is_higher_half(virt_addr) {
+        &HIGHER_ROOT
+    } else {
+        &LOWER_ROOT
+    };
+    let phys_addr = PhysAddr(root.load(Ordering::Relaxed));
+    assert!(phys_addr.0 != 0, "Paging wasn't initialized yet");
+
+    let ptr: *mut PageTable = core::ptr::without_provenance_mut(phys_addr.add_hhdm_offset().0);
+
+    Ok(unsafe { ptr.as_mut().expect("Failed to get root table") })
+}
+
+/// Get the amount of 4KB frames the address space owns in both halves, which are freed when
+/// they're unmapped.
+#[must_use]
+pub fn resident_pages() -> usize {
+    [&LOWER_ROOT, &HIGHER_ROOT]
+        .into_iter()
+        .map(|root| {
+            let phys_addr = PhysAddr(root.load(Ordering::Relaxed));
+            RESIDENT_PAGES.get(phys_addr.add_hhdm_offset().0)
+        })
+        .sum()
+}
+
+/// Make the descriptor writes made so far visible to the table walker
+#[inline]
+fn publish_tables() {
+    unsafe {
+        asm!("dsb ishst", "isb", options(nostack, preserves_flags));
+    };
+}
+
+/// Flush the translation of the page containing `addr` from the TLBs of all the cores
+#[inline]
+pub(super) fn tlbi_va(addr: VirtAddr) {
+    unsafe {
+        // The operand holds the page number in its bottom 44 bits, and the ASID (which is ignored
+        // by the "all ASIDs" variant) above them
+        asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) (addr.0 >> 12) & ((1 << 44) - 1),
+            options(nostack, preserves_flags),
+        );
+    };
+}
+
+/// Flush all the EL1 translations from the TLBs of all the cores
+#[inline]
+pub(super) fn tlbi_all() {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1is",
+            "dsb ish",
+            "isb",
+            options(nostack, preserves_flags),
+        );
+    };
+}
+
+/// Get the value of `TCR_EL1` for 48 bit virtual addresses with a 4KB granule in both halves, on
+/// a CPU whose `ID_AA64MMFR0_EL1.PARange` is `pa_range`.
+///
+/// The tables are walked as inner shareable, write-back cacheable memory.
+#[must_use]
+const fn tcr_value(pa_range: u64) -> u64 {
+    /// The size of each half is `2^(64 - TxSZ)`
+    const TXSZ: u64 = 64 - VIRT_ADDR_BITS as u64;
+    /// Inner and outer write-back write-allocate cacheable, inner shareable
+    const WALK_ATTRIBUTES: u64 = 0b01 | (0b01 << 2) | (0b11 << 4);
+    /// `TG0` is 4KB for `0b00`, and `TG1` for `0b10`
+    const TG1_4KB: u64 = 0b10;
+
+    TXSZ | (WALK_ATTRIBUTES << 8)
+        | (TXSZ << 16)
+        | (WALK_ATTRIBUTES << 24)
+        | (TG1_4KB << 30)
+        // The intermediate physical address size is the one the CPU supports
+        | ((pa_range & 0b111) << 32)
+}
+
+/// Helper function to avoid code duplication.
+///
+/// Maps in the given virtual address range to the given physical one, with the biggest blocks
+/// possible
+fn map_in_entry(
+    mut base_virt_addr: VirtAddr,
+    mut base_phys_addr: PhysAddr,
+    mut total_size: usize,
+    root: &mut PageTable,
+    flags: Flags<Aarch64>,
+) {
+    while total_size != 0 {
+        let page_size = PageSize::<Aarch64>::all()
+            .into_iter()
+            .rev()
+            .find(|page_size| {
+                total_size >= page_size.size()
+                    && base_phys_addr.is_aligned(page_size.size())
+                    && base_virt_addr.is_aligned(page_size.size())
+            })
+            .expect("Region isn't page aligned");
+
+        unsafe {
+            root.map_pages(base_virt_addr, base_phys_addr, 1, page_size, flags)
+                .unwrap();
+        };
+
+        total_size -= page_size.size();
+        base_phys_addr.0 += page_size.size();
+        base_virt_addr.0 += page_size.size();
+    }
+}
+
+pub(super) unsafe fn init(boot_info: &BootInfo, used_by_pmm: &MemoryRegion) {
+    let (lower_root, lower_root_addr) = PageTable::new();
+    let (higher_root, higher_root_addr) = PageTable::new();
+    // Nothing lives in the lower half yet
+    let _ = lower_root;
+
+    map_in_entry(
+        used_by_pmm.base.add_hhdm_offset(),
+        used_by_pmm.base,
+        used_by_pmm.length,
+        higher_root,
+        Flags::<Aarch64>::new().set_read_write(true),
+    );
+
+    for region in boot_info
+        .memory_map()
+        .iter()
+        .filter(|region| region.base != used_by_pmm.base)
+    {
+        match region.kind {
+            MemoryRegionKind::ExecutableAndModules => map_in_entry(
+                boot_info.kernel_virt(),
+                boot_info.kernel_phys(),
+                region.length,
+                higher_root,
+                Flags::<Aarch64>::new().set_read_write(true),
+            ),
+            MemoryRegionKind::AcpiReclaimable
+            | MemoryRegionKind::BootloaderReclaimable
+            | MemoryRegionKind::Usable => map_in_entry(
+                region.base.add_hhdm_offset(),
+                region.base,
+                region.length,
+                higher_root,
+                Flags::<Aarch64>::new().set_read_write(true),
+            ),
+            #[cfg(feature = "framebuffer")]
+            MemoryRegionKind::Framebuffer => map_in_entry(
+                region.base.add_hhdm_offset(),
+                region.base,
+                region.length,
+                higher_root,
+                Flags::<Aarch64>::new()
+                    .set_read_write(true)
+                    .set_memory_type(MemoryType::NormalNonCacheable),
+            ),
+            _ => (),
+        }
+    }
+
+    unsafe { finalize_init(lower_root_addr, higher_root_addr) };
+
+    logger::info!("Paging system initialized successfully");
+}
+
+/// Finalize the initialization of the paging system by programming the memory types and the
+/// translation controls, and moving over to the newly setup tables.
+///
+/// NOTE: The bootloader already runs us with the same granule and a higher half mapping of the
+/// kernel, so the new tables map everything we use at the same addresses
+unsafe fn finalize_init(lower_root_addr: PhysAddr, higher_root_addr: PhysAddr) {
+    LOWER_ROOT.store(lower_root_addr.0, Ordering::Relaxed);
+    HIGHER_ROOT.store(higher_root_addr.0, Ordering::Relaxed);
+
+    unsafe {
+        let mmfr0: u64;
+        asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0, options(nomem, nostack, preserves_flags));
+
+        asm!(
+            "msr mair_el1, {mair}",
+            "msr tcr_el1, {tcr}",
+            "isb",
+            "msr ttbr0_el1, {ttbr0}",
+            "msr ttbr1_el1, {ttbr1}",
+            "isb",
+            "tlbi vmalle1",
+            "dsb ish",
+            "isb",
+            mair = in(reg) MemoryType::MAIR,
+            tcr = in(reg) tcr_value(mmfr0 & 0xf),
+            ttbr0 = in(reg) lower_root_addr.0,
+            ttbr1 = in(reg) higher_root_addr.0,
+            options(nostack, preserves_flags),
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_index() {
+        // Level 0 index 5, level 1 index 3, level 2 index 7, level 3 index 9, in the higher half
+        let addr =
+            VirtAddr(0xffff_0000_0000_0000 | (5 << 39) | (3 << 30) | (7 << 21) | (9 << 12) | 0x123);
+        assert_eq!(
+            [0, 1, 2, 3].map(|level| table_index(addr, level)),
+            [5, 3, 7, 9]
+        );
+        assert!(is_higher_half(addr));
+        assert!(is_canonical(addr));
+
+        // The top index of each level
+        let addr = VirtAddr(0x0000_ffff_ffff_f000);
+        assert_eq!([0, 1, 2, 3].map(|level| table_index(addr, level)), [511; 4]);
+        assert!(!is_higher_half(addr));
+        assert!(is_canonical(addr));
+
+        // The bits above the translated ones have to all be the same
+        assert!(!is_canonical(VirtAddr(0x0001_0000_0000_0000)));
+        assert!(!is_canonical(VirtAddr(0x8000_0000_0000_0000)));
+    }
+
+    #[test]
+    fn test_descriptor_encoding() {
+        let rw = Flags::<Aarch64>::new().set_read_write(true);
+
+        // A 4KB page: valid, page, AF, inner shareable, normal memory
+        let page = Descriptor::leaf(PhysAddr(0x4000_0000), rw, LAST_LEVEL);
+        assert_eq!(page.0, 0x4000_0703);
+        assert!(page.is_leaf(LAST_LEVEL) && !page.is_table(LAST_LEVEL));
+
+        // A read-only 2MB block has the type bit clear, and AP[2] set
+        let block = Descriptor::leaf(PhysAddr(0x4000_0000), Flags::<Aarch64>::new(), 2);
+        assert_eq!(block.0, 0x4000_0781);
+        assert!(block.is_leaf(2) && !block.is_table(2));
+
+        // A table descriptor is just the address of the table
+        let table = Descriptor::table(PhysAddr(0x1234_5000));
+        assert_eq!(table.0, 0x1234_5003);
+        assert!(table.is_table(1) && !table.is_leaf(1));
+        assert_eq!(table.addr(), PhysAddr(0x1234_5000));
+
+        assert!(!Descriptor::INVALID.is_leaf(2) && !Descriptor::INVALID.is_table(2));
+
+        // Flags survive the round trip, and don't leak into the address
+        let flags = rw
+            .set_user_supervisor(true)
+            .set_execute_disable(true)
+            .set_global(false)
+            .set_memory_type(MemoryType::Device)
+            .set_allocated(true);
+        let page = Descriptor::leaf(PhysAddr(0x8_0000_1000), flags, LAST_LEVEL);
+        assert_eq!(page.addr(), PhysAddr(0x8_0000_1000));
+        assert_eq!(page.flags().data(), flags.data());
+        assert_eq!(page.flags().get_memory_type(), Some(MemoryType::Device));
+        assert!(page.flags().get_read_write() && page.flags().get_user_supervisor());
+        assert!(!page.flags().get_global() && page.flags().get_allocated());
+        assert_eq!(page.0 & (0b111 << 2), 1 << 2);
+    }
+
+    #[test]
+    fn test_tcr_value() {
+        // 48 bit physical addresses
+        assert_eq!(tcr_value(0b101), 0x5_b510_3510);
+        // Only 32 bit ones, with a bogus top bit that doesn't fit the field
+        assert_eq!(tcr_value(0b1000), 0xb510_3510);
+    }
+}
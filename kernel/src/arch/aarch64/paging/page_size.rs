@@ -0,0 +1,84 @@
+use core::fmt::Debug;
+
+use crate::{arch::aarch64::Aarch64, mem::paging::PageSize};
+
+/// The level of the root translation table. With a 4KB granule and 48 bit virtual addresses the
+/// walk goes through levels 0 to 3
+pub(super) const ROOT_LEVEL: usize = 0;
+
+/// The level of the last translation table, whose entries map 4KB pages
+pub(super) const LAST_LEVEL: usize = 3;
+
+impl PageSize<Aarch64> {
+    const SIZE_4KB: usize = 0x1000; // 4KB page
+    const SIZE_2MB: usize = 0x0020_0000; // 2MB block
+    const SIZE_1GB: usize = 0x4000_0000; // 1GB block
+
+    #[inline]
+    #[must_use]
+    pub const fn size_4kb() -> Self {
+        unsafe { Self::from_raw(Self::SIZE_4KB) }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn size_2mb() -> Self {
+        unsafe { Self::from_raw(Self::SIZE_2MB) }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn size_1gb() -> Self {
+        unsafe { Self::from_raw(Self::SIZE_1GB) }
+    }
+
+    /// Returns all the page sizes supported with a 4KB granule, smallest first. Unlike on
+    /// `x86_64`, all of them are always supported
+    #[inline]
+    #[must_use]
+    pub const fn all() -> [Self; 3] {
+        [Self::size_4kb(), Self::size_2mb(), Self::size_1gb()]
+    }
+
+    /// Get the level of the translation table whose entries map pages of this size
+    #[inline]
+    #[must_use]
+    pub(super) const fn level(self) -> usize {
+        match self.size() {
+            Self::SIZE_4KB => LAST_LEVEL,
+            Self::SIZE_2MB => 2,
+            Self::SIZE_1GB => 1,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get the size of the pages the entries of a level `level` table map, or `None` if they can't
+    /// map pages (i.e. for the root table)
+    #[inline]
+    #[must_use]
+    pub(super) const fn from_level(level: usize) -> Option<Self> {
+        match level {
+            LAST_LEVEL => Some(Self::size_4kb()),
+            2 => Some(Self::size_2mb()),
+            1 => Some(Self::size_1gb()),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for PageSize<Aarch64> {
+    fn eq(&self, other: &Self) -> bool {
+        self.size() == other.size()
+    }
+}
+
+impl Debug for PageSize<Aarch64> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.size() {
+            Self::SIZE_4KB => write!(f, "PageSize::Size4KB"),
+            Self::SIZE_2MB => write!(f, "PageSize::Size2MB"),
+            Self::SIZE_1GB => write!(f, "PageSize::Size1GB"),
+            _ => write!(f, "PageSize::Unknown({})", self.size()),
+        }
+    }
+}
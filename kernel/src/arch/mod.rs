@@ -1,13 +1,32 @@
 //! (Will be) safe, general arch abstractions so kernel doesn't need to deal with all the nitty gritty
 
+#[cfg(target_arch = "x86_64")]
+use utils::mem::{PhysAddr, VirtAddr};
+
+#[cfg(target_arch = "x86_64")]
+use crate::mem::paging::{self, Flags, PagingError};
 use crate::mem::paging::{PageSize, PagingManager};
 
 #[cfg(target_arch = "x86_64")]
 pub mod x86_64;
 
+#[cfg(target_arch = "aarch64")]
+pub mod aarch64;
+
+/// Compiler and hardware memory barriers. They live in `utils` so the locks and MMIO wrappers can
+/// use them too
+pub use utils::sync::barrier;
+
+/// The maximum amount of CPUs, which per-CPU state is kept for (indexed by the CPU's ID, e.g. its
+/// APIC ID). xAPIC IDs are 8 bits wide
+pub const MAX_CPUS: usize = 256;
+
 #[cfg(target_arch = "x86_64")]
 pub const BASIC_PAGE_SIZE: PageSize<x86_64::X86_64> = x86_64::X86_64::BASIC_PAGE_SIZE;
 
+#[cfg(target_arch = "aarch64")]
+pub const BASIC_PAGE_SIZE: PageSize<aarch64::Aarch64> = aarch64::Aarch64::BASIC_PAGE_SIZE;
+
 /// A trait that every arch should implement
 // TODO: Make this internal
 pub trait Arch: PagingManager + Sized {
@@ -15,4 +34,40 @@ pub trait Arch: PagingManager + Sized {
     ///
     /// SHOULD ONLY BE CALLED ONCE DURING BOOT!
     unsafe fn early_boot_init();
+
+    /// Enable interrupts and put the CPU to sleep until the next one arrives
+    fn wait_for_interrupt();
+}
+
+/// Put the CPU to sleep until the next interrupt arrives, enabling interrupts if they aren't
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub fn wait_for_interrupt() {
+    x86_64::X86_64::wait_for_interrupt();
+}
+
+/// Put the CPU to sleep until the next interrupt arrives, enabling interrupts if they aren't
+#[cfg(target_arch = "aarch64")]
+#[inline]
+pub fn wait_for_interrupt() {
+    aarch64::Aarch64::wait_for_interrupt();
+}
+
+/// Temporarily map `page_count` pages starting at `phys_addr`, run `f` with the virtual address
+/// `phys_addr` is mapped to, and unmap them once `f` returns (or panics).
+///
+/// # Safety
+/// Same as `paging::with_temp_mapping`
+///
+/// # Errors
+/// Fails if the pages couldn't be mapped
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub unsafe fn with_temp_mapping<R>(
+    phys_addr: PhysAddr,
+    page_count: usize,
+    flags: Flags<x86_64::X86_64>,
+    f: impl FnOnce(VirtAddr) -> R,
+) -> Result<R, PagingError> {
+    unsafe { paging::with_temp_mapping(phys_addr, page_count, flags, f) }
 }
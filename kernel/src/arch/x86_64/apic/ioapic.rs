@@ -1,11 +1,11 @@
 //! Interface and driver for the IO APIC
 
 use crate::{
-    arch::x86_64::{X86_64, cpu::outb_8},
+    arch::x86_64::X86_64,
     mem::paging::{Flags, PageSize, PagingManager},
 };
 
-use super::{DeliveryMode, Destination};
+use super::{DeliveryMode, Destination, inti_pin_polarity, inti_trigger_mode};
 use alloc::vec::Vec;
 use core::cell::SyncUnsafeCell;
 use modular_bitfield::prelude::*;
@@ -36,6 +36,9 @@ struct IrqOverride {
     gsi: u32,
 }
 
+/// The amount of legacy ISA IRQs, which are hardwired to devices
+const LEGACY_IRQ_COUNT: u8 = 16;
+
 /// An IRQ allocator, to keep track of used/unused IRQs efficiently
 pub static IRQ_ALLOCATOR: SpinLock<IdTracker> = SpinLock::new(IdTracker::uninit());
 
@@ -114,15 +117,6 @@ impl IoApic {
     /// address
     const OFFSET_FROM_SEL_TO_WIN: usize = 0x10;
 
-    /// Mask all of the PICs interrupt, effectively disabling it.
-    /// This is required so the PICs don't interfere with the APIC stack
-    pub unsafe fn mask_off_pic() {
-        unsafe {
-            outb_8(0x21, 0xff); // ICW1
-            outb_8(0xa1, 0xff); // ICW2
-        }
-    }
-
     /// Creates a new IO APIC
     fn new(base: *mut u32, gsi_base: u32, apic_id: u8) -> Self {
         let io_sel = MmioCell::new(base);
@@ -240,6 +234,8 @@ pub unsafe fn add(phys_addr: PhysAddr, gsi_base: u32, apic_id: u8) {
 ///
 /// NOTE: THIS SHOULD BE CALLED ONLY ONCE DURING BOOT!
 pub fn init_irq_allocator() {
+    utils::once_per_boot!();
+
     let mut irq_allocator = IRQ_ALLOCATOR.lock();
     let gsi_count = get_ioapics()
         .iter()
@@ -262,6 +258,40 @@ pub unsafe fn allocate_irq_at(irq: u8) -> Result<(), IdTrackerError> {
     irq_allocator.allocate_at(Id(irq as usize))
 }
 
+/// Allocate a free IRQ line, for devices whose line can be routed anywhere (e.g. the HPET's
+/// timers). The legacy ISA IRQs are never handed out, since they are hardwired to devices.
+///
+/// # Errors
+/// Fails if all of the IRQ lines above the legacy ones are taken
+#[must_use = "Not freeing the IRQ will cause leaking"]
+pub fn allocate_irq() -> Result<u8, IdTrackerError> {
+    let mut irq_allocator = IRQ_ALLOCATOR.lock();
+
+    for irq in LEGACY_IRQ_COUNT..=u8::MAX {
+        match irq_allocator.allocate_at(Id(irq as usize)) {
+            Err(IdTrackerError::IdAlreadyTaken) => {}
+            // Ran past the last IRQ line
+            Err(IdTrackerError::InvalidId) => break,
+            res => return res.map(|()| irq),
+        }
+    }
+
+    Err(IdTrackerError::OutOfIds)
+}
+
+/// Free the given IRQ, so it can be allocated again.
+///
+/// # Safety
+/// The IRQ must be masked off, and its device must not use it anymore
+///
+/// # Errors
+/// Fails if the IRQ doesn't exist, or isn't allocated
+pub unsafe fn free_irq(irq: u8) -> Result<(), IdTrackerError> {
+    let mut irq_allocator = IRQ_ALLOCATOR.lock();
+
+    unsafe { irq_allocator.free(Id(irq as usize)) }
+}
+
 /// Map the given IRQ to the given vector.
 ///
 /// NOTE: Make sure you pass in the IRQ, and NOT the GSI.
@@ -311,8 +341,8 @@ pub unsafe fn override_irq(irq: u8, gsi: u32, flags: u16, delivery_mode: Option<
             // Read the redirection entry
             let mut entry: RedirectionEntry = unsafe { io_apic.read_redirection_entry(offset) };
 
-            entry.set_pin_polarity(((flags & 2) >> 1) as u8);
-            entry.set_trigger_mode(((flags & 8) >> 3) as u8);
+            entry.set_pin_polarity(inti_pin_polarity(flags).expect("Invalid INTI flags").into());
+            entry.set_trigger_mode(inti_trigger_mode(flags).expect("Invalid INTI flags").into());
             // XXX: FIX THESE! Make this be all LOCAL APICS and not just the one setting this
             // up
             entry.set_destination_mode(Destination::PHYSICAL_MODE);
@@ -320,7 +350,7 @@ pub unsafe fn override_irq(irq: u8, gsi: u32, flags: u16, delivery_mode: Option<
 
             // XXX: I think I should change the delivery mode?
             if let Some(delivery_mode) = delivery_mode {
-                entry.set_delivery_mode(delivery_mode as u8);
+                entry.set_delivery_mode(delivery_mode.into());
             }
 
             // Write the entry back
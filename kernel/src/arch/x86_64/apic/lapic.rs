@@ -10,7 +10,10 @@ use crate::{
     mem::paging::{Flags, PageSize, PagingManager},
 };
 
-use super::{DeliveryMode, Destination, DestinationShorthand, Level, PinPolarity, TriggerMode};
+use super::{
+    DeliveryMode, Destination, DestinationShorthand, Level, PinPolarity, TriggerMode,
+    inti_pin_polarity, inti_trigger_mode,
+};
 use utils::mem::{
     PhysAddr,
     mmio::{MmioArea, Offsetable},
@@ -18,8 +21,15 @@ use utils::mem::{
 use utils::sync::spinlock::{SpinLock, SpinLockGuard, SpinLockable};
 
 use alloc::vec::Vec;
+use macros::register_field;
 use modular_bitfield::prelude::*;
 
+/// The vector the local APIC raises spurious interrupts on
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// The bit in the spurious interrupt vector register that software enables the local APIC
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
 /// The local APICs' MMIO registers that can be written to
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
@@ -132,6 +142,7 @@ pub enum TimerMode {
 }
 
 /// Represents the flags of the local APIC
+#[register_field(bits = 1)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum ApicFlags {
@@ -184,26 +195,11 @@ pub struct LocalApic {
 }
 
 impl LocalApic {
-    /// Verifies that the CPU actually supports APIC
-    #[inline]
-    fn check_support() {
-        const CPUID_APIC_BIT: u32 = 0x1 << 9;
-
-        let cpuid = unsafe { core::arch::x86_64::__cpuid(1) };
-
-        assert!(
-            cpuid.edx & CPUID_APIC_BIT != 0,
-            "Local APIC not supported on this CPU"
-        );
-    }
-
     /// Enables the local APIC in the `IA32_APIC_BASE` MSR, in case firmware didn't do it already
     #[inline]
     fn hardware_enable() {
         const APIC_ENABLE: u32 = 1 << 11;
 
-        Self::check_support();
-
         let mut value = unsafe { rdmsr(IntelMsr::Ia32ApicBase) };
         value.low |= APIC_ENABLE;
 
@@ -225,8 +221,10 @@ impl LocalApic {
             Self::hardware_enable();
 
             // Configure the SIV and software enable the APIC
-            apic.area
-                .write(WriteableRegs::SpuriousInterruptVector, 0x1ff);
+            apic.area.write(
+                WriteableRegs::SpuriousInterruptVector,
+                APIC_SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR),
+            );
             apic.area.write(WriteableRegs::TaskPriority, 0x0);
         }
 
@@ -256,16 +254,16 @@ impl LocalApic {
         };
 
         // Configure the NMI LINT
-        nmi_lint_entry.set_delivery_mode(DeliveryMode::Nmi as u8);
-        nmi_lint_entry.set_trigger_mode(nmi_lint_trigger_mode as u8);
-        nmi_lint_entry.set_pin_polarity(nmi_lint_pin_polarity as u8);
+        nmi_lint_entry.set_delivery_mode(DeliveryMode::Nmi.into());
+        nmi_lint_entry.set_trigger_mode(nmi_lint_trigger_mode.into());
+        nmi_lint_entry.set_pin_polarity(nmi_lint_pin_polarity.into());
         nmi_lint_entry.set_mask(false.into());
 
         // Configure the other LINT to be for external interrupts
-        ext_int_lint_entry.set_delivery_mode(DeliveryMode::ExtInt as u8);
+        ext_int_lint_entry.set_delivery_mode(DeliveryMode::ExtInt.into());
         // XXX: IIRC Some newer devices need this to be level, so this might cause trouble
-        ext_int_lint_entry.set_trigger_mode(TriggerMode::EdgeTriggered as u8);
-        // ext_int_lint_entry.set_pin_polarity(PinPolarity::ActiveHigh as u8);
+        ext_int_lint_entry.set_trigger_mode(TriggerMode::EdgeTriggered.into());
+        // ext_int_lint_entry.set_pin_polarity(PinPolarity::ActiveHigh.into());
         ext_int_lint_entry.set_mask(false.into());
 
         // Enable the error LVT
@@ -282,6 +280,14 @@ impl LocalApic {
         }
     }
 
+    /// Deliver the performance counter overflow interrupt as an NMI, and unmask it
+    pub fn set_performance_nmi(&self) {
+        let mut lvtpc = LvtReg::new();
+        lvtpc.set_delivery_mode(DeliveryMode::Nmi.into());
+
+        unsafe { self.area.write(WriteableRegs::LvtPerformance, lvtpc.into()) };
+    }
+
     /// Read the error status register
     pub fn read_errors(&self) -> u32 {
         unsafe {
@@ -349,6 +355,16 @@ impl LocalApic {
         unsafe { self.area.read(ReadableRegs::TimerInitialCount) }
     }
 
+    /// Write the timer's initial count, which restarts the countdown from it without changing
+    /// the timer's mode
+    #[inline]
+    pub fn set_initial_timer_count(&self, cycle_count: u32) {
+        unsafe {
+            self.area
+                .write(WriteableRegs::TimerInitialCount, cycle_count);
+        }
+    }
+
     /// Configure and send an inter-processor interrupt.
     ///
     /// This function is unsafe for 2 reasons:
@@ -376,11 +392,11 @@ impl LocalApic {
         };
 
         let lower_part_data = (vector as u32)
-            | ((delivery_mode as u32) << 8)
+            | (u32::from(delivery_mode) << 8)
             | ((destination.0 as u32) << 11)
-            | ((level as u32) << 14)
-            | ((trigger_mode as u32) << 15)
-            | ((destination_shorthand as u32) << 18);
+            | (u32::from(level) << 14)
+            | (u32::from(trigger_mode) << 15)
+            | (u32::from(destination_shorthand) << 18);
 
         unsafe {
             self.area
@@ -425,6 +441,15 @@ impl LocalApic {
     }
 }
 
+/// Lock the local APIC matching the given APIC ID, or return `None` if it's already locked (or
+/// there is no such APIC). Never spins, so it's safe to use in the NMI handler
+pub fn try_get_apic(apic_id: u32) -> Option<SpinLockGuard<'static, LocalApic>> {
+    get_lapics()
+        .iter()
+        .filter_map(SpinLock::try_lock)
+        .find(|lapic| lapic.apic_id == apic_id)
+}
+
 fn get_lapics() -> &'static Vec<SpinLock<LocalApic>> {
     unsafe { LOCAL_APICS.get().as_ref().unwrap() }
 }
@@ -474,8 +499,8 @@ pub unsafe fn config_lints(acpi_processor_id: u32, lint: u8, flags: u16) {
             unsafe {
                 apic.setup_lvts(
                     lint,
-                    (flags & 0b11).try_into().unwrap(),
-                    ((flags >> 2) & 0b11).try_into().unwrap(),
+                    inti_pin_polarity(flags).expect("Invalid INTI flags"),
+                    inti_trigger_mode(flags).expect("Invalid INTI flags"),
                 );
             }
         }
@@ -486,8 +511,8 @@ pub unsafe fn config_lints(acpi_processor_id: u32, lint: u8, flags: u16) {
                 unsafe {
                     apic.setup_lvts(
                         lint,
-                        (flags & 0b11).try_into().unwrap(),
-                        ((flags >> 2) & 0b11).try_into().unwrap(),
+                        inti_pin_polarity(flags).expect("Invalid INTI flags"),
+                        inti_trigger_mode(flags).expect("Invalid INTI flags"),
                     );
                 }
             }
@@ -505,18 +530,6 @@ pub unsafe fn override_base(base: *mut u32) {
     });
 }
 
-impl TryFrom<u32> for ApicFlags {
-    type Error = ();
-
-    fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            0x0 => Ok(ApicFlags::Enabled),
-            0x1 => Ok(ApicFlags::OnlineCapable),
-            _ => Err(()),
-        }
-    }
-}
-
 impl Offsetable for ReadableRegs {
     fn offset(self) -> usize {
         self as usize
@@ -1,9 +1,12 @@
 //! APIC implementation
 
+use macros::register_field;
+
 pub mod ioapic;
 pub mod lapic;
 
 #[allow(dead_code)]
+#[register_field(bits = 3)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum DeliveryMode {
@@ -18,6 +21,7 @@ pub enum DeliveryMode {
 
 // TODO
 #[allow(dead_code)]
+#[register_field(bits = 1)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum RemoteIrr {
@@ -26,6 +30,7 @@ pub enum RemoteIrr {
 }
 
 #[allow(dead_code)]
+#[register_field(bits = 1)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum Mask {
@@ -33,20 +38,23 @@ pub enum Mask {
     Masked = 0b1,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[register_field(bits = 1)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum PinPolarity {
     ActiveHigh = 0b0,
     ActiveLow = 0b1,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[register_field(bits = 1)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum TriggerMode {
     EdgeTriggered = 0b0,
     LevelTriggered = 0b1,
 }
 
+#[register_field(bits = 1)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum Level {
@@ -61,6 +69,7 @@ pub enum Destination {
     Logical(u8),
 }
 
+#[register_field(bits = 2)]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum DestinationShorthand {
@@ -70,13 +79,63 @@ pub enum DestinationShorthand {
     AllExcludingSelf = 0b11,
 }
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum SharedFlags {
-    HighEdge = 0,
-    HighLevel = 2,
-    LowEdge = 8,
-    LowLevel = 2 | 8,
+/// The polarity field (bits `0-1`) of the MPS INTI flags the MADT entries carry
+#[register_field(bits = 2)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntiPolarity {
+    /// Conforms to the specifications of the bus
+    BusDefault = 0b00,
+    ActiveHigh = 0b01,
+    ActiveLow = 0b11,
+}
+
+/// The trigger mode field (bits `2-3`) of the MPS INTI flags the MADT entries carry
+#[register_field(bits = 2)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntiTriggerMode {
+    /// Conforms to the specifications of the bus
+    BusDefault = 0b00,
+    EdgeTriggered = 0b01,
+    LevelTriggered = 0b11,
+}
+
+impl IntiPolarity {
+    /// The shift of the field in the flags
+    pub const SHIFT: u32 = 0;
+}
+
+impl IntiTriggerMode {
+    /// The shift of the field in the flags
+    pub const SHIFT: u32 = 2;
+}
+
+/// Decode the polarity out of the MPS INTI flags of a MADT entry, or `None` if they're malformed.
+///
+/// NOTE: The entries describe ISA interrupts, and the ISA bus default is active high
+#[inline]
+#[must_use]
+pub fn inti_pin_polarity(flags: u16) -> Option<PinPolarity> {
+    match IntiPolarity::unpack(u64::from(flags), IntiPolarity::SHIFT) {
+        Some(IntiPolarity::BusDefault | IntiPolarity::ActiveHigh) => Some(PinPolarity::ActiveHigh),
+        Some(IntiPolarity::ActiveLow) => Some(PinPolarity::ActiveLow),
+        None => None,
+    }
+}
+
+/// Decode the trigger mode out of the MPS INTI flags of a MADT entry, or `None` if they're
+/// malformed.
+///
+/// NOTE: The entries describe ISA interrupts, and the ISA bus default is edge triggered
+#[inline]
+#[must_use]
+pub fn inti_trigger_mode(flags: u16) -> Option<TriggerMode> {
+    match IntiTriggerMode::unpack(u64::from(flags), IntiTriggerMode::SHIFT) {
+        Some(IntiTriggerMode::BusDefault | IntiTriggerMode::EdgeTriggered) => {
+            Some(TriggerMode::EdgeTriggered)
+        }
+        Some(IntiTriggerMode::LevelTriggered) => Some(TriggerMode::LevelTriggered),
+        None => None,
+    }
 }
 
 impl Destination {
@@ -108,42 +167,66 @@ impl Destination {
     }
 }
 
-impl TryFrom<u16> for TriggerMode {
-    type Error = ();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            // 0 is the bus default: on ISA, this is edge triggered
-            0b1 | 0b0 => Ok(TriggerMode::EdgeTriggered),
-            0b11 => Ok(TriggerMode::LevelTriggered),
-            _ => Err(()),
-        }
+    #[register_field(bits = 3)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Sample {
+        Zero = 0b000,
+        Two = 0b010,
+        Seven = 0b111,
     }
-}
 
-impl TryFrom<u16> for PinPolarity {
-    type Error = ();
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            // 0 is the bus default: on EISA, this is active low
-            // XXX: Is this the default only for Level or for Edge as well?
-            0b1 => Ok(PinPolarity::ActiveHigh),
-            0b11 | 0b0 => Ok(PinPolarity::ActiveLow),
-            _ => Err(()),
-        }
+    #[test]
+    fn test_register_field_conversions() {
+        assert_eq!(Sample::BITS, 3);
+        assert_eq!(Sample::MASK, 0b111);
+
+        assert_eq!(Sample::try_from(0b010_u8), Ok(Sample::Two));
+        assert_eq!(Sample::try_from(0b111_u16), Ok(Sample::Seven));
+        assert_eq!(Sample::try_from(0_u32), Ok(Sample::Zero));
+        // Not a variant, or doesn't fit in the field
+        assert_eq!(Sample::try_from(0b001_u64), Err(()));
+        assert_eq!(Sample::try_from(0b1010_u8), Err(()));
+
+        assert_eq!(u8::from(Sample::Seven), 0b111);
+        assert_eq!(u32::from(Sample::Two), 0b010);
+        assert_eq!(Sample::Two.into_bits(), 0b010);
     }
-}
 
-impl TryFrom<u16> for SharedFlags {
-    type Error = ();
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        const FOO: u16 = 8 | 2;
-        match value {
-            0 => Ok(SharedFlags::HighEdge),
-            2 => Ok(SharedFlags::HighLevel),
-            8 => Ok(SharedFlags::LowEdge),
-            FOO => Ok(SharedFlags::LowLevel),
-            _ => Err(()),
-        }
+    #[test]
+    fn test_register_field_packing() {
+        // Replaces only the field's bits
+        assert_eq!(Sample::Two.pack(u64::MAX, 8), !(0b101 << 8));
+        assert_eq!(Sample::Seven.pack(0, 61), 0b111 << 61);
+
+        assert_eq!(Sample::unpack(0xf0f2 << 4, 4), Some(Sample::Two));
+        assert_eq!(Sample::unpack(0b0110, 1), None);
+
+        // The real fields land where the LAPIC expects them
+        assert_eq!(DeliveryMode::Nmi.pack(0xff, 8), 0x4ff);
+        assert_eq!(
+            DestinationShorthand::AllExcludingSelf.pack(0, 18),
+            0b11 << 18
+        );
+    }
+
+    #[test]
+    fn test_inti_flags() {
+        // The bus defaults agree for the polarity and trigger mode: ISA is active high, edge
+        // triggered
+        assert_eq!(inti_pin_polarity(0b0000), Some(PinPolarity::ActiveHigh));
+        assert_eq!(inti_trigger_mode(0b0000), Some(TriggerMode::EdgeTriggered));
+
+        assert_eq!(inti_pin_polarity(0b1101), Some(PinPolarity::ActiveHigh));
+        assert_eq!(inti_trigger_mode(0b1101), Some(TriggerMode::LevelTriggered));
+        assert_eq!(inti_pin_polarity(0b0111), Some(PinPolarity::ActiveLow));
+        assert_eq!(inti_trigger_mode(0b0111), Some(TriggerMode::EdgeTriggered));
+
+        // `0b10` is reserved in both fields
+        assert_eq!(inti_pin_polarity(0b0010), None);
+        assert_eq!(inti_trigger_mode(0b1000), None);
     }
 }
@@ -0,0 +1,554 @@
+//! Validation of the CPU features the kernel assumes are present, and enabling of the optional
+//! ones worth having
+
+use core::{
+    arch::x86_64::{__cpuid, CpuidResult},
+    hint,
+};
+
+use utils::sync::once::Once;
+
+use super::{Cr4, Register};
+
+/// The register of a CPUID leaf a feature bit is reported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CpuidRegister {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// A CPU feature, reported by a single CPUID bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFeature {
+    /// Human readable name of the feature
+    pub name: &'static str,
+    /// The CPUID leaf reporting the feature
+    leaf: u32,
+    /// The register of the leaf the feature bit is in
+    register: CpuidRegister,
+    /// The bit reporting the feature
+    bit: u32,
+}
+
+impl CpuFeature {
+    /// Returns true if `result` (the result of CPUID leaf `self.leaf`) reports the feature
+    #[inline]
+    const fn is_set_in(&self, result: &CpuidResult) -> bool {
+        let value = match self.register {
+            CpuidRegister::Ebx => result.ebx,
+            CpuidRegister::Ecx => result.ecx,
+            CpuidRegister::Edx => result.edx,
+        };
+
+        value & (1 << self.bit) != 0
+    }
+
+    /// Run CPUID to check whether the CPU reports the feature right now.
+    ///
+    /// Unlike `cpu_features`, nothing is cached, so this is what bits reflecting the state of the
+    /// CPU (e.g. `OSXSAVE`) should be checked with.
+    #[must_use]
+    pub fn is_reported(&self) -> bool {
+        self.is_set_in(&cpuid(self.leaf))
+    }
+}
+
+/// Long mode (64 bit mode)
+pub const LONG_MODE: CpuFeature = CpuFeature {
+    name: "Long mode",
+    leaf: 0x8000_0001,
+    register: CpuidRegister::Edx,
+    bit: 29,
+};
+
+/// Physical Address Extension
+pub const PAE: CpuFeature = CpuFeature {
+    name: "PAE",
+    leaf: 1,
+    register: CpuidRegister::Edx,
+    bit: 6,
+};
+
+/// Global pages, which aren't flushed from the TLB on CR3 writes
+pub const PGE: CpuFeature = CpuFeature {
+    name: "PGE",
+    leaf: 1,
+    register: CpuidRegister::Edx,
+    bit: 13,
+};
+
+/// The No-Execute page table bit
+pub const NX: CpuFeature = CpuFeature {
+    name: "NX",
+    leaf: 0x8000_0001,
+    register: CpuidRegister::Edx,
+    bit: 20,
+};
+
+/// SSE2 instructions
+pub const SSE2: CpuFeature = CpuFeature {
+    name: "SSE2",
+    leaf: 1,
+    register: CpuidRegister::Edx,
+    bit: 26,
+};
+
+/// An on chip local APIC
+pub const APIC: CpuFeature = CpuFeature {
+    name: "APIC",
+    leaf: 1,
+    register: CpuidRegister::Edx,
+    bit: 9,
+};
+
+/// The Page Attribute Table
+pub const PAT: CpuFeature = CpuFeature {
+    name: "PAT",
+    leaf: 1,
+    register: CpuidRegister::Edx,
+    bit: 16,
+};
+
+/// The features the kernel can't run without.
+///
+/// APIC and PAT are included since the kernel doesn't support the legacy PIC or running without
+/// the PAT.
+pub const REQUIRED_FEATURES: &[CpuFeature] = &[LONG_MODE, PAE, PGE, NX, SSE2, APIC, PAT];
+
+/// The TSC ticks at a constant rate, no matter the P-state and C-state of the core, so it can be
+/// used as a clock
+pub const INVARIANT_TSC: CpuFeature = CpuFeature {
+    name: "Invariant TSC",
+    leaf: 0x8000_0007,
+    register: CpuidRegister::Edx,
+    bit: 8,
+};
+
+/// The `rdtscp` instruction, and the `IA32_TSC_AUX` MSR
+pub const RDTSCP: CpuFeature = CpuFeature {
+    name: "RDTSCP",
+    leaf: 0x8000_0001,
+    register: CpuidRegister::Edx,
+    bit: 27,
+};
+
+/// The TSC-deadline mode of the local APIC timer, which fires once the TSC reaches a deadline
+pub const TSC_DEADLINE: CpuFeature = CpuFeature {
+    name: "TSC-deadline",
+    leaf: 1,
+    register: CpuidRegister::Ecx,
+    bit: 24,
+};
+
+/// The XSAVE family of instructions, and XCR0
+pub const XSAVE: CpuFeature = CpuFeature {
+    name: "XSAVE",
+    leaf: 1,
+    register: CpuidRegister::Ecx,
+    bit: 26,
+};
+
+/// Mirrors CR4.OSXSAVE, which unlike CR4 can be read in any ring. It's the state of the CPU
+/// rather than something it supports, so it's not in `CpuFeatures` (see `CpuFeature::is_reported`)
+pub const OSXSAVE: CpuFeature = CpuFeature {
+    name: "OSXSAVE",
+    leaf: 1,
+    register: CpuidRegister::Ecx,
+    bit: 27,
+};
+
+/// 1GB pages
+pub const PAGE_1GB: CpuFeature = CpuFeature {
+    name: "1GB pages",
+    leaf: 0x8000_0001,
+    register: CpuidRegister::Edx,
+    bit: 26,
+};
+
+/// Process context identifiers, which tag TLB entries with the address space they belong to
+pub const PCID: CpuFeature = CpuFeature {
+    name: "PCID",
+    leaf: 1,
+    register: CpuidRegister::Ecx,
+    bit: 17,
+};
+
+/// The x2APIC mode of the local APIC, accessed through MSRs
+pub const X2APIC: CpuFeature = CpuFeature {
+    name: "x2APIC",
+    leaf: 1,
+    register: CpuidRegister::Ecx,
+    bit: 21,
+};
+
+/// Intel's Virtual Machine Extensions
+pub const VMX: CpuFeature = CpuFeature {
+    name: "VMX",
+    leaf: 1,
+    register: CpuidRegister::Ecx,
+    bit: 5,
+};
+
+/// AMD's Secure Virtual Machine extensions
+pub const SVM: CpuFeature = CpuFeature {
+    name: "SVM",
+    leaf: 0x8000_0001,
+    register: CpuidRegister::Ecx,
+    bit: 2,
+};
+
+/// Nested paging, for translating guest physical addresses in hardware
+pub const NESTED_PAGING: CpuFeature = CpuFeature {
+    name: "Nested paging",
+    leaf: 0x8000_000a,
+    register: CpuidRegister::Edx,
+    bit: 0,
+};
+
+/// SVM lock, which allows SVM to be unlocked with a key after firmware disabled it
+pub const SVM_LOCK: CpuFeature = CpuFeature {
+    name: "SVM lock",
+    leaf: 0x8000_000a,
+    register: CpuidRegister::Edx,
+    bit: 2,
+};
+
+/// The CPU features detected at boot, which the rest of the kernel checks instead of running
+/// CPUID itself
+static CPU_FEATURES: Once<CpuFeatures> = Once::new();
+
+/// The features the kernel (and the hypervisor) care about, as reported by CPUID.
+///
+/// NOTE: Bits that reflect the state of the CPU rather than what it supports (e.g. OSXSAVE, which
+/// follows `CR4.OSXSAVE`) aren't here, since they'd go stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CpuFeatures {
+    pub long_mode: bool,
+    pub pae: bool,
+    pub pge: bool,
+    pub nx: bool,
+    pub sse2: bool,
+    pub apic: bool,
+    pub pat: bool,
+    pub invariant_tsc: bool,
+    pub rdtscp: bool,
+    pub tsc_deadline: bool,
+    pub xsave: bool,
+    pub page_1gb: bool,
+    pub pcid: bool,
+    pub x2apic: bool,
+    pub vmx: bool,
+    pub svm: bool,
+    pub nested_paging: bool,
+    pub svm_lock: bool,
+}
+
+impl CpuFeatures {
+    /// Decode the features reported by `cpuid`
+    fn from_cpuid(cpuid: impl Fn(u32) -> CpuidResult) -> Self {
+        let has = |feature: CpuFeature| feature.is_set_in(&cpuid(feature.leaf));
+
+        Self {
+            long_mode: has(LONG_MODE),
+            pae: has(PAE),
+            pge: has(PGE),
+            nx: has(NX),
+            sse2: has(SSE2),
+            apic: has(APIC),
+            pat: has(PAT),
+            invariant_tsc: has(INVARIANT_TSC),
+            rdtscp: has(RDTSCP),
+            tsc_deadline: has(TSC_DEADLINE),
+            xsave: has(XSAVE),
+            page_1gb: has(PAGE_1GB),
+            pcid: has(PCID),
+            x2apic: has(X2APIC),
+            vmx: has(VMX),
+            svm: has(SVM),
+            nested_paging: has(NESTED_PAGING),
+            svm_lock: has(SVM_LOCK),
+        }
+    }
+}
+
+/// Get the features of the CPU. They are detected on the first call (which is during early boot),
+/// and the same ones are returned from then on.
+///
+/// NOTE: All CPUs are assumed to report the same features
+pub fn cpu_features() -> &'static CpuFeatures {
+    if let Some(features) = CPU_FEATURES.get() {
+        return features;
+    }
+
+    match CPU_FEATURES.set(CpuFeatures::from_cpuid(cpuid)) {
+        Ok(features) => features,
+        // Another CPU raced us to it, and is storing the same features
+        Err(_) => loop {
+            if let Some(features) = CPU_FEATURES.get() {
+                break features;
+            }
+            hint::spin_loop();
+        },
+    }
+}
+
+/// Get the amount of address space IDs SVM tags the TLB entries of guests with (ASID 0 being the
+/// host's), or 0 if SVM isn't supported
+#[must_use]
+pub fn svm_asid_count() -> u32 {
+    cpuid(NESTED_PAGING.leaf).ebx
+}
+
+/// An optional feature, which is enabled by setting a CR4 bit if the CPU supports it
+pub struct Cr4Feature {
+    pub feature: CpuFeature,
+    /// Set the feature's bit in CR4
+    enable: fn(Cr4) -> Cr4,
+}
+
+/// The CR4 features that harden the kernel (or make it faster) at no cost, so they are enabled
+/// whenever they are supported
+pub const HARDENING_FEATURES: &[Cr4Feature] = &[
+    // Makes SGDT, SIDT, SLDT, SMSW and STR fault in user mode, so they can't leak kernel addresses
+    Cr4Feature {
+        feature: CpuFeature {
+            name: "UMIP",
+            leaf: 7,
+            register: CpuidRegister::Ecx,
+            bit: 2,
+        },
+        enable: |cr4| cr4.with_umip(1),
+    },
+    // Allows RDGSBASE/WRGSBASE, which are much faster than going through the MSRs for per CPU
+    // data
+    Cr4Feature {
+        feature: CpuFeature {
+            name: "FSGSBASE",
+            leaf: 7,
+            register: CpuidRegister::Ebx,
+            bit: 0,
+        },
+        enable: |cr4| cr4.with_fsgsbase(1),
+    },
+];
+
+/// Get the features in `features` that are reported by `cpuid`
+fn supported_cr4_features(
+    features: &'static [Cr4Feature],
+    cpuid: impl Fn(u32) -> CpuidResult,
+) -> impl Iterator<Item = &'static Cr4Feature> {
+    features.iter().filter(move |cr4_feature| {
+        cr4_feature
+            .feature
+            .is_set_in(&cpuid(cr4_feature.feature.leaf))
+    })
+}
+
+/// Find the first feature in `features` that isn't reported by `cpuid`
+fn find_missing_feature(
+    features: &'static [CpuFeature],
+    cpuid: impl Fn(u32) -> CpuidResult,
+) -> Option<&'static CpuFeature> {
+    features
+        .iter()
+        .find(|feature| !feature.is_set_in(&cpuid(feature.leaf)))
+}
+
+/// Execute CPUID leaf `leaf`, or return all zeroes if the CPU doesn't support that leaf
+pub(crate) fn cpuid(leaf: u32) -> CpuidResult {
+    // The highest supported leaf of the range (basic or extended) `leaf` is in
+    let max_leaf = __cpuid(leaf & 0x8000_0000).eax;
+
+    if leaf > max_leaf {
+        CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        }
+    } else {
+        __cpuid(leaf)
+    }
+}
+
+/// Make sure the CPU supports all of `REQUIRED_FEATURES`.
+///
+/// This should run as early as possible, so a missing feature is reported by name instead of
+/// causing a fault somewhere deep in the initialization.
+///
+/// # Panics
+/// Panics if a required feature isn't supported
+pub fn check_required_features() {
+    if let Some(feature) = find_missing_feature(REQUIRED_FEATURES, cpuid) {
+        panic!(
+            "Required CPU feature `{}` is not supported by this CPU",
+            feature.name
+        );
+    }
+
+    logger::info!("All required CPU features are supported");
+}
+
+/// Enable each of `HARDENING_FEATURES` the CPU supports, logging the ones that were enabled
+///
+/// # Safety
+/// Must run in ring 0, before anything relies on the affected instructions behaving otherwise
+pub unsafe fn enable_hardening_features() {
+    let mut cr4 = unsafe { Cr4::read() };
+    for cr4_feature in supported_cr4_features(HARDENING_FEATURES, cpuid) {
+        cr4 = (cr4_feature.enable)(cr4);
+        logger::info!("Enabled {}", cr4_feature.feature.name);
+    }
+
+    unsafe { cr4.write() };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mocked CPUID, reporting all of `REQUIRED_FEATURES` except for `missing`
+    fn mock_cpuid(missing: Option<&'static str>) -> impl Fn(u32) -> CpuidResult {
+        move |leaf| {
+            let mut result = CpuidResult {
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            };
+
+            for feature in REQUIRED_FEATURES
+                .iter()
+                .filter(|feature| feature.leaf == leaf && Some(feature.name) != missing)
+            {
+                match feature.register {
+                    CpuidRegister::Ebx => result.ebx |= 1 << feature.bit,
+                    CpuidRegister::Ecx => result.ecx |= 1 << feature.bit,
+                    CpuidRegister::Edx => result.edx |= 1 << feature.bit,
+                }
+            }
+
+            result
+        }
+    }
+
+    #[test]
+    fn test_all_features_present() {
+        assert_eq!(
+            find_missing_feature(REQUIRED_FEATURES, mock_cpuid(None)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_missing_feature_reported_by_name() {
+        for name in ["Long mode", "NX", "SSE2", "PAT"] {
+            let missing = find_missing_feature(REQUIRED_FEATURES, mock_cpuid(Some(name)));
+            assert_eq!(missing.map(|feature| feature.name), Some(name));
+        }
+    }
+
+    #[test]
+    fn test_unsupported_leaf() {
+        // A CPU that doesn't support the extended leaves at all
+        let cpuid = |leaf| {
+            if leaf >= 0x8000_0000 {
+                CpuidResult {
+                    eax: 0,
+                    ebx: 0,
+                    ecx: 0,
+                    edx: 0,
+                }
+            } else {
+                mock_cpuid(None)(leaf)
+            }
+        };
+
+        let missing = find_missing_feature(REQUIRED_FEATURES, cpuid);
+        assert_eq!(missing.map(|feature| feature.name), Some("Long mode"));
+    }
+
+    #[test]
+    fn test_decode_cpu_features() {
+        // An AMD CPU with SVM and nested paging, but without 1GB pages, PCID or x2APIC
+        let cpuid = |leaf| {
+            let mut result = mock_cpuid(None)(leaf);
+            match leaf {
+                0x8000_0001 => result.ecx |= 1 << 2,
+                0x8000_0007 => result.edx |= 1 << 8,
+                0x8000_000a => result.edx |= 1,
+                _ => {}
+            }
+
+            result
+        };
+
+        assert_eq!(
+            CpuFeatures::from_cpuid(cpuid),
+            CpuFeatures {
+                long_mode: true,
+                pae: true,
+                pge: true,
+                nx: true,
+                sse2: true,
+                apic: true,
+                pat: true,
+                invariant_tsc: true,
+                rdtscp: false,
+                tsc_deadline: false,
+                xsave: false,
+                page_1gb: false,
+                pcid: false,
+                x2apic: false,
+                vmx: false,
+                svm: true,
+                nested_paging: true,
+                svm_lock: false,
+            }
+        );
+
+        // Leaf 1 reports a lot of features in ECX, make sure each lands in the right field
+        let cpuid = |leaf| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: if leaf == 1 {
+                (1 << 17) | (1 << 21) | (1 << 24) | (1 << 26)
+            } else {
+                0
+            },
+            edx: if leaf == 0x8000_0001 { 1 << 26 } else { 0 },
+        };
+        let features = CpuFeatures::from_cpuid(cpuid);
+        assert!(features.pcid && features.x2apic && features.page_1gb);
+        assert!(features.tsc_deadline && features.xsave);
+        assert!(!features.long_mode && !features.svm);
+    }
+
+    #[test]
+    fn test_only_supported_cr4_features_are_enabled() {
+        // Only FSGSBASE is reported
+        let cpuid = |leaf| CpuidResult {
+            eax: 0,
+            ebx: u32::from(leaf == 7),
+            ecx: 0,
+            edx: 0,
+        };
+
+        let cr4 = supported_cr4_features(HARDENING_FEATURES, cpuid)
+            .fold(Cr4::new(), |cr4, cr4_feature| (cr4_feature.enable)(cr4));
+        assert_eq!(cr4.fsgsbase(), 1);
+        assert_eq!(cr4.umip(), 0);
+        assert_eq!(u64::from(cr4), 1 << 16);
+
+        // And nothing is enabled on a CPU without leaf 7
+        let none = |_| CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        assert_eq!(supported_cr4_features(HARDENING_FEATURES, none).count(), 0);
+    }
+}
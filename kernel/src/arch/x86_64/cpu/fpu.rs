@@ -0,0 +1,295 @@
+//! Saving and restoring the x87 FPU, SSE and AVX registers (the "extended state").
+//!
+//! The kernel doesn't use any of them itself, but what runs on top of it (e.g. guests) does, so
+//! the state has to be swapped when switching between them.
+//!
+//! XSAVE is used if it was enabled (CR4.OSXSAVE), and FXSAVE otherwise, which only covers the x87
+//! and SSE state. With XSAVE, XCR0 is part of the state as well, since it decides which of the
+//! components are enabled (and e.g. a guest might enable different ones than the host).
+
+use core::arch::{asm, x86_64::__cpuid_count};
+
+use utils::sync::once::Once;
+
+use super::features::OSXSAVE;
+
+/// The XSAVE state components that are saved: x87, SSE, AVX, and the AVX-512 ones. The others
+/// (e.g. AMX) don't fit in `FpuState`, so they aren't saved
+const SAVED_COMPONENTS: u64 = 0b1110_0111;
+
+/// The size of the legacy (FXSAVE) region of the XSAVE area and the XSAVE header, which the x87
+/// and SSE state are in
+const LEGACY_AREA_SIZE: u32 = 576;
+
+/// The value of the x87 control word after reset
+const INIT_FCW: u16 = 0x037f;
+
+/// The value of MXCSR after reset, with all exceptions masked
+pub const INIT_MXCSR: u32 = 0x1f80;
+
+/// The XSAVE components to save and restore (or `None` if XSAVE isn't enabled), worked out on
+/// first use
+static XSAVE_MASK: Once<Option<u64>> = Once::new();
+
+/// A saved copy of the extended state
+#[repr(C, align(64))]
+pub struct FpuState {
+    /// The XSAVE (or FXSAVE) area
+    area: [u8; FpuState::SIZE],
+    /// XCR0 when the state was saved, which is switched back in along with the rest of the state.
+    ///
+    /// 0 (which isn't a valid XCR0) until the state is first saved, in which case XCR0 is left as
+    /// it is. A guest can't use any of the components XCR0 enables until it enables XSAVE in its
+    /// CR4 and sets up its own XCR0 anyway
+    xcr0: u64,
+}
+
+impl FpuState {
+    /// The size of the XSAVE area holding all of `SAVED_COMPONENTS`, which is more than FXSAVE
+    /// needs
+    pub const SIZE: usize = 2688;
+
+    /// Create the state the CPU is in after reset
+    #[must_use]
+    pub const fn new() -> Self {
+        let mut data = [0; Self::SIZE];
+
+        // Everything else, including the XSAVE header, is zero. A zeroed XSTATE_BV makes XRSTOR
+        // put all the components in their init state
+        let fcw = INIT_FCW.to_le_bytes();
+        data[0] = fcw[0];
+        data[1] = fcw[1];
+        let mxcsr = INIT_MXCSR.to_le_bytes();
+        let mut i = 0;
+        while i < mxcsr.len() {
+            data[24 + i] = mxcsr[i];
+            i += 1;
+        }
+
+        Self {
+            area: data,
+            xcr0: 0,
+        }
+    }
+
+    /// Save the CPU's extended state
+    #[inline]
+    pub fn save(&mut self) {
+        let ptr = self.area.as_mut_ptr();
+
+        unsafe {
+            if let Some(mask) = xsave_mask() {
+                self.xcr0 = read_xcr0();
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) ptr,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!("fxsave64 [{}]", in(reg) ptr, options(nostack, preserves_flags));
+            }
+        };
+    }
+
+    /// Load the saved state into the CPU, replacing its current extended state (and XCR0)
+    #[inline]
+    pub fn restore(&self) {
+        let ptr = self.area.as_ptr();
+
+        // The state is only ever written by `new` and `save`, so it's valid to load. XCR0 is
+        // switched first, since it decides which of the components XRSTOR loads
+        unsafe {
+            if let Some(mask) = xsave_mask() {
+                if self.xcr0 != 0 && self.xcr0 != read_xcr0() {
+                    write_xcr0(self.xcr0);
+                }
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) ptr,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    clobber_abi("C"),
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!(
+                    "fxrstor64 [{}]",
+                    in(reg) ptr,
+                    clobber_abi("C"),
+                    options(nostack, preserves_flags),
+                );
+            }
+        };
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the XSAVE components to save and restore, or `None` if XSAVE isn't enabled.
+///
+/// They're the ones the host enabled in XCR0 (out of `SAVED_COMPONENTS`), worked out on the first
+/// call and cached, since CPUID is slow (and traps when running under a hypervisor). That has to be
+/// after CR4.OSXSAVE was set, which happens during early boot
+///
+/// # Panics
+/// Panics if the XSAVE area of the enabled components doesn't fit in `FpuState`
+fn xsave_mask() -> Option<u64> {
+    if let Some(&mask) = XSAVE_MASK.get() {
+        return mask;
+    }
+
+    let mask = OSXSAVE.is_reported().then(|| {
+        let xcr0 = read_xcr0();
+        let mask = xcr0 & SAVED_COMPONENTS;
+
+        // EBX is the size of the XSAVE area of the components enabled in XCR0. If some of them
+        // aren't saved (e.g. AMX), the area only has to reach the end of the last one that is,
+        // whose size and offset are reported by the component's own subleaf
+        let size = if mask == xcr0 {
+            __cpuid_count(0xd, 0).ebx
+        } else {
+            (2..u64::BITS)
+                .filter(|&component| mask & (1 << component) != 0)
+                .map(|component| {
+                    let result = __cpuid_count(0xd, component);
+                    result.ebx + result.eax
+                })
+                .max()
+                .unwrap_or(LEGACY_AREA_SIZE)
+        };
+        assert!(
+            size as usize <= FpuState::SIZE,
+            "The XSAVE area ({size} bytes) doesn't fit in FpuState"
+        );
+
+        mask
+    });
+
+    // Every CPU works out the same mask, so it doesn't matter whose is stored
+    let _ = XSAVE_MASK.set(mask);
+
+    mask
+}
+
+/// Read XCR0, the XSAVE components that are enabled
+#[inline]
+fn read_xcr0() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!(
+            "xgetbv",
+            in("ecx") 0,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags),
+        );
+    };
+
+    (u64::from(high) << 32) | u64::from(low)
+}
+
+/// The last value written to XCR0 in tests, since they can't actually `xsetbv`
+#[cfg(test)]
+static TEST_XCR0: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+/// Write XCR0.
+///
+/// # Safety
+/// `xcr0` must be a valid combination of components the CPU supports
+#[inline]
+unsafe fn write_xcr0(xcr0: u64) {
+    #[cfg(not(test))]
+    unsafe {
+        asm!(
+            "xsetbv",
+            in("ecx") 0,
+            in("eax") xcr0 as u32,
+            in("edx") (xcr0 >> 32) as u32,
+            options(nomem, nostack, preserves_flags),
+        );
+    };
+    #[cfg(test)]
+    TEST_XCR0.store(xcr0, core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Read the MXCSR register, which controls and reports the SSE floating point operations
+#[inline]
+#[must_use]
+pub fn read_mxcsr() -> u32 {
+    let mut mxcsr = 0_u32;
+    unsafe {
+        asm!("stmxcsr [{}]", in(reg) &raw mut mxcsr, options(nostack, preserves_flags));
+    };
+
+    mxcsr
+}
+
+/// Write the MXCSR register.
+///
+/// # Safety
+/// Reserved bits must not be set. Anything relying on the SSE rounding mode or exception masks
+/// (e.g. compiled floating point code) is affected
+#[inline]
+pub unsafe fn write_mxcsr(mxcsr: u32) {
+    unsafe {
+        asm!("ldmxcsr [{}]", in(reg) &raw const mxcsr, options(nostack, preserves_flags));
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MXCSR with round towards zero, instead of to nearest
+    const ROUND_TOWARDS_ZERO: u32 = 0x6000;
+
+    #[test]
+    fn test_save_and_restore() {
+        let original = read_mxcsr();
+        let mut saved = FpuState::new();
+        let init = FpuState::new();
+
+        unsafe { write_mxcsr(original | ROUND_TOWARDS_ZERO) };
+        saved.save();
+
+        // Loading the init state resets the registers
+        init.restore();
+        assert_eq!(read_mxcsr(), INIT_MXCSR);
+
+        saved.restore();
+        assert_eq!(read_mxcsr(), original | ROUND_TOWARDS_ZERO);
+
+        unsafe { write_mxcsr(original) };
+    }
+
+    #[test]
+    fn test_xcr0_is_switched() {
+        if xsave_mask().is_none() {
+            return;
+        }
+
+        // Saving records the current XCR0
+        let mut saved = FpuState::new();
+        saved.save();
+        assert_eq!(saved.xcr0, read_xcr0());
+
+        // A state that was never saved leaves XCR0 alone
+        FpuState::new().restore();
+        assert_eq!(TEST_XCR0.load(core::sync::atomic::Ordering::Relaxed), 0);
+
+        // Otherwise, its XCR0 is switched in before it's loaded. Only the x87 state is enabled in
+        // the reset XCR0
+        let mut guest = FpuState::new();
+        guest.xcr0 = 0b1;
+        guest.restore();
+        assert_eq!(TEST_XCR0.load(core::sync::atomic::Ordering::Relaxed), 0b1);
+
+        saved.restore();
+    }
+}
@@ -8,6 +8,10 @@ use core::{arch::asm, mem::transmute};
 use modular_bitfield::prelude::*;
 use utils::mem::VirtAddr;
 
+use super::gdt::{Cs, SegmentSelector};
+
+pub mod features;
+pub mod fpu;
 pub mod msr;
 
 pub trait Register {
@@ -290,10 +294,34 @@ pub struct AmdDr7 {
     pub reserved_mbz_2: B32,
 }
 
+/// Get the privilege level a segment selector requests (its RPL, the selector's low 2 bits)
+#[inline]
+#[must_use]
+pub fn selector_privilege_level(selector: u16) -> u8 {
+    SegmentSelector::from(selector).rpl()
+}
+
+/// Get the current privilege level (CPL), which is the RPL of the CS selector
+#[inline]
+#[must_use]
+pub fn current_cpl() -> u8 {
+    // SAFETY: Reading CS has no side effects, and is allowed at any privilege level
+    unsafe { Cs::read() }.0.rpl()
+}
+
+/// Assert (in debug builds) that we're running in ring 0 before doing the privileged `operation`,
+/// so running one from user mode is caught here rather than as a #GP somewhere down the line
+#[inline]
+#[track_caller]
+pub(super) fn debug_assert_cpl0(operation: &str) {
+    debug_assert_eq!(current_cpl(), 0, "{operation} requires CPL 0");
+}
+
 /// Wrapper for the 'outb' instruction, accessing a `u32` port
 #[allow(unused)]
 #[inline]
 pub unsafe fn outb_32(port: u16, value: u32) {
+    debug_assert_cpl0("Port I/O");
     unsafe {
         asm! (
             "out dx, eax",
@@ -307,6 +335,7 @@ pub unsafe fn outb_32(port: u16, value: u32) {
 /// Wrapper for the 'out' instruction, accessing a `u8` port
 #[inline]
 pub unsafe fn outb_8(port: u16, value: u8) {
+    debug_assert_cpl0("Port I/O");
     unsafe {
         asm! (
             "out dx, al",
@@ -317,10 +346,25 @@ pub unsafe fn outb_8(port: u16, value: u8) {
     };
 }
 
+/// Wrapper for the 'out' instruction, accessing a `u16` port
+#[inline]
+pub unsafe fn outb_16(port: u16, value: u16) {
+    debug_assert_cpl0("Port I/O");
+    unsafe {
+        asm! (
+            "out dx, ax",
+            in("dx") port,
+            in("ax") value,
+            options(nomem, nostack),
+        );
+    };
+}
+
 #[allow(unused)]
 /// Wrapper for the 'in' instruction, accessing a `u32` port
 #[inline]
 pub unsafe fn inb_32(port: u16) -> u32 {
+    debug_assert_cpl0("Port I/O");
     let res: u32;
     unsafe {
         asm! (
@@ -334,9 +378,27 @@ pub unsafe fn inb_32(port: u16) -> u32 {
     res
 }
 
+/// Wrapper for the 'in' instruction, accessing a `u16` port
+#[inline]
+pub unsafe fn inb_16(port: u16) -> u16 {
+    debug_assert_cpl0("Port I/O");
+    let res: u16;
+    unsafe {
+        asm! (
+            "in ax, dx",
+            out("ax") res,
+            in("dx") port,
+            options(nomem, nostack),
+        );
+    };
+
+    res
+}
+
 /// Wrapper for the 'in' instruction, accessing a `u8` port
 #[inline]
 pub unsafe fn inb_8(port: u16) -> u8 {
+    debug_assert_cpl0("Port I/O");
     let res: u8;
     unsafe {
         asm! (
@@ -350,6 +412,22 @@ pub unsafe fn inb_8(port: u16) -> u8 {
     res
 }
 
+/// Wait a short while (around 1 microsecond) by doing a dummy write to port `0x80`.
+///
+/// Legacy devices (PIC, PIT, CMOS) might miss back-to-back port writes on real hardware, so
+/// this should be called between them. Does nothing in tests, which can't access ports.
+#[inline]
+pub fn io_wait() {
+    /// The POST code port. Nothing listens on it after boot, so writing to it is harmless
+    #[cfg(not(test))]
+    const IO_WAIT_PORT: u16 = 0x80;
+
+    #[cfg(not(test))]
+    unsafe {
+        outb_8(IO_WAIT_PORT, 0);
+    }
+}
+
 /// Clear `RFLAGS` interrupt flag to mask all maskable external interrupts
 #[inline]
 pub fn cli() {
@@ -366,6 +444,60 @@ pub fn sti() {
     };
 }
 
+/// Enable handling of external interrupts, and halt until the next one arrives.
+///
+/// `sti` only takes effect after the instruction following it, so an interrupt can't sneak in
+/// between the two and leave us halted with nothing to wake us up.
+#[inline]
+pub fn sti_hlt() {
+    unsafe {
+        asm!("sti", "hlt", options(nostack, nomem));
+    };
+}
+
+/// Read the time stamp counter with `rdtsc`.
+///
+/// `rdtsc` isn't serializing, so the CPU might execute it before the instructions preceding it
+/// finish, or start the following instructions before it. That's fine for timestamps, but when
+/// measuring how long some code takes use `read_tsc_ordered` at the start of the measurement and
+/// `read_tscp` at its end.
+///
+/// NOTE: The TSC only ticks at a constant rate on CPUs with an invariant TSC (see
+/// `features::cpu_features`), so it shouldn't be used as a clock otherwise
+#[inline]
+#[must_use]
+pub fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Same as `read_tsc`, but executes `lfence` first, so the counter is only read once all the
+/// preceding instructions finished
+#[inline]
+#[must_use]
+pub fn read_tsc_ordered() -> u64 {
+    unsafe {
+        core::arch::x86_64::_mm_lfence();
+        core::arch::x86_64::_rdtsc()
+    }
+}
+
+/// Read the time stamp counter along with `IA32_TSC_AUX`, which holds the ID of the CPU it was
+/// read on (if the kernel wrote it there), with `rdtscp`.
+///
+/// `rdtscp` waits for all the preceding instructions to finish, so it can end a measurement
+/// started with `read_tsc_ordered`. The following instructions might still start before it, so
+/// put an `lfence` after it if that matters.
+///
+/// NOTE: `rdtscp` must be supported (see `features::cpu_features`), or this faults
+#[inline]
+#[must_use]
+pub fn read_tscp() -> (u64, u32) {
+    let mut aux = 0;
+    let tsc = unsafe { core::arch::x86_64::__rdtscp(&raw mut aux) };
+
+    (tsc, aux)
+}
+
 /// Read the current stack pointer (RSP) register
 pub fn read_rsp() -> usize {
     let rsp: u64;
@@ -394,6 +526,7 @@ impl Register for Dr0 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing DR0");
         unsafe {
             asm!("mov dr0, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -411,6 +544,7 @@ impl Register for Dr1 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing DR1");
         unsafe {
             asm!("mov dr1, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -428,6 +562,7 @@ impl Register for Dr2 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing DR2");
         unsafe {
             asm!("mov dr2, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -445,6 +580,7 @@ impl Register for Dr3 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing DR3");
         unsafe {
             asm!("mov dr3, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -478,6 +614,7 @@ impl Register for AmdDr7 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing DR7");
         unsafe {
             asm!("mov dr7, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -512,6 +649,7 @@ impl Register for Cr0 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing CR0");
         unsafe {
             asm!("mov cr0, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -545,6 +683,7 @@ impl Register for Cr3 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing CR3");
         unsafe {
             asm!("mov cr3, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -562,6 +701,7 @@ impl Register for Cr4 {
     }
 
     unsafe fn write(self) {
+        debug_assert_cpl0("Writing CR4");
         unsafe {
             asm!("mov cr4, {:r}", in(reg) transmute::<Self, u64>(self));
         }
@@ -573,3 +713,38 @@ impl Default for Cr3 {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_privilege_level() {
+        // The kernel's code selector, and a user code selector
+        assert_eq!(selector_privilege_level(0x08), 0);
+        assert_eq!(selector_privilege_level(0x2b), 3);
+        assert_eq!(selector_privilege_level(0x11), 1);
+
+        // Tests run as a regular user mode process
+        assert_eq!(current_cpl(), 3);
+    }
+
+    #[test]
+    fn test_tsc_is_monotonic() {
+        if !features::cpu_features().rdtscp {
+            return;
+        }
+
+        let mut last = read_tsc_ordered();
+        for _ in 0..10_000 {
+            let tsc = read_tsc_ordered();
+            let (tscp, _) = read_tscp();
+
+            assert!(last <= tsc);
+            assert!(tsc <= tscp);
+            last = tscp;
+        }
+
+        assert!(read_tsc() >= last);
+    }
+}
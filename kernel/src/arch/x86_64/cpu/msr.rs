@@ -17,8 +17,36 @@ pub enum IntelMsr {
     Ia32FeatureControl = 0x3A,
     /// Address of the `IA32_VMX_BASIC` MSR
     Ia32VmxBasic = 0x480,
+    /// The allowed settings of the pin-based VM-execution controls
+    Ia32VmxPinbasedCtls = 0x481,
+    /// The allowed settings of the primary processor-based VM-execution controls
+    Ia32VmxProcbasedCtls = 0x482,
+    /// The allowed settings of the VM-exit controls
+    Ia32VmxExitCtls = 0x483,
+    /// The allowed settings of the VM-entry controls
+    Ia32VmxEntryCtls = 0x484,
+    /// The bits of CR0 that must be set in VMX operation
+    Ia32VmxCr0Fixed0 = 0x486,
+    /// The bits of CR0 that may be set in VMX operation
+    Ia32VmxCr0Fixed1 = 0x487,
+    /// The bits of CR4 that must be set in VMX operation
+    Ia32VmxCr4Fixed0 = 0x488,
+    /// The bits of CR4 that may be set in VMX operation
+    Ia32VmxCr4Fixed1 = 0x489,
+    /// The allowed settings of the secondary processor-based VM-execution controls
+    Ia32VmxProcbasedCtls2 = 0x48B,
+    /// The EPT and VPID capabilities
+    Ia32VmxEptVpidCap = 0x48C,
+    /// Address of the `IA32_FS_BASE` MSR
+    Ia32FsBase = 0xC000_0100,
+    /// Address of the `IA32_GS_BASE` MSR
+    Ia32GsBase = 0xC000_0101,
     /// Address of the `IA32_PAT` MSR
     Ia32Pat = 0x277,
+    /// Address of the `IA32_PMC0` MSR, the first general purpose performance counter
+    Ia32Pmc0 = 0xC1,
+    /// Address of the `IA32_PERFEVTSEL0` MSR, which selects what `IA32_PMC0` counts
+    Ia32PerfEvtSel0 = 0x186,
 }
 
 /// AMD CPUs specific MSRs
@@ -31,6 +59,10 @@ pub enum AmdMsr {
     VmCr = 0xC001_0114,
     /// Physical address of the host state save area
     VmHsavePa = 0xC001_0117,
+    /// Selects what `PerfCtr0` counts
+    PerfEvtSel0 = 0xC001_0000,
+    /// The first performance counter
+    PerfCtr0 = 0xC001_0004,
 }
 
 /// Data structure to hold the low and high parts of a model specific register (MSR)
@@ -100,6 +132,7 @@ impl Efer {
 pub unsafe fn rdmsr(msr: impl Msr) -> MsrData {
     let low: u32;
     let high: u32;
+    super::debug_assert_cpl0("Reading an MSR");
     unsafe {
         asm!(
             "rdmsr",
@@ -117,6 +150,7 @@ pub unsafe fn rdmsr(msr: impl Msr) -> MsrData {
 #[allow(private_bounds)]
 #[inline]
 pub unsafe fn wrmsr(msr: impl Msr, data: MsrData) {
+    super::debug_assert_cpl0("Writing an MSR");
     unsafe {
         asm!(
             "wrmsr",
@@ -1,13 +1,256 @@
 //! Various `x86_64` specific events handling
 
 use macros::isr;
+use utils::sync::spinlock::{SpinLock, SpinLockable};
+
+use utils::mem::VirtAddr;
 
 use crate::arch::x86_64::{
-    apic::lapic::LocalApic,
+    apic::lapic::{LocalApic, SPURIOUS_VECTOR},
     cpu::{Cr2, Register},
+    interrupts::{self, IsrStub},
+    nmi::InterruptFrame,
+    paging, pic, vectors,
 };
 
-pub const GENERIC_ISR_VECTOR: u8 = 255;
+/// The first vector IRQs are dispatched on. The vectors below it are taken by exceptions
+pub const FIRST_IRQ_VECTOR: u8 = 32;
+
+/// The amount of vectors (starting from `FIRST_IRQ_VECTOR`) IRQs are dispatched on
+pub const IRQ_VECTOR_COUNT: usize = 32;
+
+/// The last vector IRQs are dispatched on
+pub const LAST_IRQ_VECTOR: u8 = FIRST_IRQ_VECTOR + (IRQ_VECTOR_COUNT - 1) as u8;
+
+/// The max amount of deferred work that can be pending at once
+const MAX_DEFERRED_WORK: usize = 16;
+
+/// The global IRQ dispatch table
+static IRQ_DISPATCH: SpinLock<IrqDispatch> = SpinLock::new(IrqDispatch::new());
+
+/// A handler for an IRQ. Gets the vector the IRQ was received on
+pub type IrqHandler = fn(u8);
+
+/// Work deferred by an IRQ handler, to run after the IRQ is acknowledged
+pub type DeferredWork = fn();
+
+/// Errors the IRQ dispatch table might encounter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventError {
+    /// The vector isn't one of the vectors IRQs are dispatched on
+    InvalidVector,
+    /// A handler is already registered on the vector
+    VectorTaken,
+    /// All of the vectors IRQs are dispatched on are taken
+    NoFreeVector,
+    /// Too much deferred work is already pending
+    DeferredWorkFull,
+}
+
+/// A table mapping each IRQ vector to the handler that should be called for it
+pub struct IrqDispatch {
+    /// The handlers, indexed by `vector - FIRST_IRQ_VECTOR`
+    handlers: [Option<IrqHandler>; IRQ_VECTOR_COUNT],
+    /// Pending deferred work, in the order it was deferred
+    deferred: [Option<DeferredWork>; MAX_DEFERRED_WORK],
+    /// The amount of pending deferred work
+    deferred_count: usize,
+}
+
+impl IrqDispatch {
+    /// Create an empty dispatch table
+    const fn new() -> Self {
+        Self {
+            handlers: [None; IRQ_VECTOR_COUNT],
+            deferred: [None; MAX_DEFERRED_WORK],
+            deferred_count: 0,
+        }
+    }
+
+    /// Get the index of `vector` in the handlers table
+    const fn index(vector: u8) -> Result<usize, EventError> {
+        match vector.checked_sub(FIRST_IRQ_VECTOR) {
+            Some(index) if (index as usize) < IRQ_VECTOR_COUNT => Ok(index as usize),
+            _ => Err(EventError::InvalidVector),
+        }
+    }
+
+    /// Register `handler` to be called on `vector`
+    fn register(&mut self, vector: u8, handler: IrqHandler) -> Result<(), EventError> {
+        let entry = &mut self.handlers[Self::index(vector)?];
+        if entry.is_some() {
+            return Err(EventError::VectorTaken);
+        }
+        *entry = Some(handler);
+
+        Ok(())
+    }
+
+    /// Register `handler` on the first free vector `claim` succeeds on, and return that vector.
+    ///
+    /// `claim` is called on free vectors until it returns true, so vectors that are used for
+    /// something other than dispatching IRQs can be skipped
+    fn register_any(
+        &mut self,
+        handler: IrqHandler,
+        mut claim: impl FnMut(u8) -> bool,
+    ) -> Result<u8, EventError> {
+        let (index, entry) = self
+            .handlers
+            .iter_mut()
+            .enumerate()
+            .find(|(index, entry)| entry.is_none() && claim(FIRST_IRQ_VECTOR + *index as u8))
+            .ok_or(EventError::NoFreeVector)?;
+        *entry = Some(handler);
+
+        Ok(FIRST_IRQ_VECTOR + index as u8)
+    }
+
+    /// Remove the handler registered on `vector`
+    fn unregister(&mut self, vector: u8) -> Result<(), EventError> {
+        self.handlers[Self::index(vector)?] = None;
+
+        Ok(())
+    }
+
+    /// Get the handler registered on `vector`, if there is one
+    fn handler(&self, vector: u8) -> Option<IrqHandler> {
+        Self::index(vector)
+            .ok()
+            .and_then(|index| self.handlers[index])
+    }
+
+    /// Queue `work` to run after the current IRQ is acknowledged
+    fn defer(&mut self, work: DeferredWork) -> Result<(), EventError> {
+        if self.deferred_count == MAX_DEFERRED_WORK {
+            return Err(EventError::DeferredWorkFull);
+        }
+        self.deferred[self.deferred_count] = Some(work);
+        self.deferred_count += 1;
+
+        Ok(())
+    }
+
+    /// Take the oldest pending deferred work
+    fn take_deferred(&mut self) -> Option<DeferredWork> {
+        if self.deferred_count == 0 {
+            return None;
+        }
+
+        let work = self.deferred[0].take();
+        self.deferred.copy_within(1..self.deferred_count, 0);
+        self.deferred_count -= 1;
+        self.deferred[self.deferred_count] = None;
+
+        work
+    }
+}
+
+impl SpinLockable for IrqDispatch {}
+
+/// Register `handler` to be called whenever an IRQ is received on `vector`, allocating the
+/// vector
+///
+/// # Errors
+/// Fails if `vector` isn't an IRQ vector, or if it's already in use
+pub fn register(vector: u8, handler: IrqHandler) -> Result<(), EventError> {
+    // The dispatch table is also locked by the ISRs, which would spin forever if they interrupted
+    // us while we hold it
+    interrupts::without_interrupts(|| {
+        let mut dispatch = IRQ_DISPATCH.lock();
+        dispatch.register(vector, handler)?;
+
+        if vectors::allocate_vector_at(vector).is_err() {
+            dispatch.unregister(vector)?;
+            return Err(EventError::VectorTaken);
+        }
+
+        Ok(())
+    })
+}
+
+/// Register `handler` on the first free IRQ vector, and return that vector
+///
+/// # Errors
+/// Fails if all of the IRQ vectors are in use
+pub fn register_any(handler: IrqHandler) -> Result<u8, EventError> {
+    interrupts::without_interrupts(|| {
+        IRQ_DISPATCH.lock().register_any(handler, |vector| {
+            vectors::allocate_vector_at(vector).is_ok()
+        })
+    })
+}
+
+/// Remove the handler registered on `vector`, freeing the vector
+///
+/// # Errors
+/// Fails if `vector` isn't an IRQ vector
+///
+/// # Panics
+/// Panics if the vector had a handler but wasn't allocated, which means the allocator is corrupt
+pub fn unregister(vector: u8) -> Result<(), EventError> {
+    interrupts::without_interrupts(|| {
+        let mut dispatch = IRQ_DISPATCH.lock();
+        if dispatch.handler(vector).is_some() {
+            dispatch.unregister(vector)?;
+            // SAFETY: There is no handler for the vector anymore, so IRQs received on it are
+            // dropped
+            unsafe { vectors::free_vector(vector) }
+                .expect("Registered IRQ vector wasn't allocated");
+        }
+
+        Ok(())
+    })
+}
+
+/// Queue `work` to run after the IRQ currently being handled is acknowledged.
+///
+/// Meant for IRQ handlers that have work which doesn't have to be done with the IRQ pending.
+///
+/// # Errors
+/// Fails if too much deferred work is already pending
+pub fn defer(work: DeferredWork) -> Result<(), EventError> {
+    // Usually called from an IRQ handler, where interrupts are already disabled, but not always
+    interrupts::without_interrupts(|| IRQ_DISPATCH.lock().defer(work))
+}
+
+/// Call the handler registered on `vector` in `dispatch`, returning false if there is none.
+///
+/// The lock is released before the handler is called, so it can register handlers and defer work
+fn call_handler(dispatch: &SpinLock<IrqDispatch>, vector: u8) -> bool {
+    let handler = dispatch.lock().handler(vector);
+
+    handler.map(|handler| handler(vector)).is_some()
+}
+
+/// Run all of the deferred work pending in `dispatch`, including work deferred while running it
+fn run_deferred_work(dispatch: &SpinLock<IrqDispatch>) {
+    loop {
+        let work = dispatch.lock().take_deferred();
+        let Some(work) = work else {
+            break;
+        };
+
+        work();
+    }
+}
+
+/// The common entry of all of the dispatched IRQ vectors
+fn dispatch(vector: u8) {
+    interrupts::count_interrupt(vector);
+
+    if !call_handler(&IRQ_DISPATCH, vector) {
+        logger::warn!("Received IRQ on vector {vector}, but no handler is registered for it");
+    }
+
+    let this_lapic_id = LocalApic::get_this_apic_id();
+    LocalApic::get_apic(this_lapic_id).signal_eoi();
+
+    run_deferred_work(&IRQ_DISPATCH);
+
+    // Write out whatever was logged while handling the IRQ (a no-op unless logging is buffered)
+    logger::flush();
+}
 
 /// List of error messages for each exception
 static EXCEPTION_MESSAGES: &[&str] = &[
@@ -57,13 +300,42 @@ macro_rules! generic_exception_isr {
 
 generic_exception_isr!(exception_0, 0);
 generic_exception_isr!(exception_1, 1);
-generic_exception_isr!(exception_2, 2);
 generic_exception_isr!(exception_3, 3);
 generic_exception_isr!(exception_4, 4);
 generic_exception_isr!(exception_5, 5);
 generic_exception_isr!(exception_6, 6);
 generic_exception_isr!(exception_7, 7);
-generic_exception_isr!(exception_8, 8);
+
+/// Double fault handler.
+///
+/// A double fault is raised when an exception can't be delivered, most often because the stack
+/// overflowed and pushing the frame of the page fault faulted again. Without a handler that'd be
+/// a triple fault, which resets the machine without a word, so the handler runs on its own IST
+/// stack and only reports the fault.
+extern "C" fn exception_8(frame: &InterruptFrame) -> ! {
+    panic!(
+        "Exception: {} (likely a stack overflow) at {:#x} (cs {:#x}, rflags {:#x}, rsp {:#x}, ss {:#x})",
+        EXCEPTION_MESSAGES[8], frame.rip, frame.cs, frame.rflags, frame.rsp, frame.ss
+    );
+}
+
+/// The ISR stub of the double fault handler.
+///
+/// The handler never returns (a double fault can't be recovered from), so no registers are
+/// preserved, and it's passed the interrupt frame, which is right above the error code
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn __isr_stub_exception_8() {
+    core::arch::naked_asm!(
+        // The CPU pushed 6 quad words (including the error code) on the aligned IST stack, so
+        // the stack is already aligned to 16
+        "lea rdi, [rsp + 8]",
+        "call {}",
+        "ud2",
+        sym exception_8,
+    );
+}
+
 generic_exception_isr!(exception_9, 9);
 generic_exception_isr!(exception_10, 10);
 generic_exception_isr!(exception_11, 11);
@@ -71,13 +343,56 @@ generic_exception_isr!(exception_12, 12);
 generic_exception_isr!(exception_13, 13);
 
 // TODO: Take care of recursive calls
-/// Page fault handler
-#[isr]
-fn exception_14() {
+/// Page fault handler.
+///
+/// Faults on demand-zero pages are resolved, and the faulting instruction is retried. Any other
+/// fault is fatal.
+extern "C" fn exception_14() {
+    let addr = unsafe { Cr2::read().0 };
+    if paging::demand_zero::handle_page_fault(VirtAddr(addr as usize)).is_ok() {
+        return;
+    }
+
     panic!(
         "Exception {} at address: {:#x}",
-        EXCEPTION_MESSAGES[14],
-        unsafe { Cr2::read().0 },
+        EXCEPTION_MESSAGES[14], addr
+    );
+}
+
+/// The ISR stub of the page fault handler.
+///
+/// Unlike the `#[isr]` stubs, the page fault handler might return to the faulting instruction,
+/// so the scratch registers are preserved and the error code is popped before returning.
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn __isr_stub_exception_14() {
+    core::arch::naked_asm!(
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        // The CPU pushed 6 quad words (including the error code), so align the stack back to 16
+        "sub rsp, 8",
+        "call {}",
+        "add rsp, 8",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        // Pop the error code
+        "add rsp, 8",
+        "iretq",
+        sym exception_14,
     );
 }
 
@@ -99,10 +414,172 @@ generic_exception_isr!(exception_29, 29);
 generic_exception_isr!(exception_30, 30);
 generic_exception_isr!(exception_31, 31);
 
+/// The local APIC's spurious interrupt handler.
+///
+/// The local APIC raises a spurious interrupt when the interrupt it was about to deliver goes away
+/// (e.g. it got masked). It isn't put in service, so it mustn't be acknowledged with an EOI
 #[isr]
-pub fn generic_irq_isr() {
-    // TODO: Possibly rewrite this
-    let this_lapic_id = LocalApic::get_this_apic_id();
-    let lapic = LocalApic::get_apic(this_lapic_id);
-    lapic.signal_eoi();
+pub fn spurious_isr() {
+    interrupts::count_interrupt(SPURIOUS_VECTOR);
+    interrupts::count_spurious();
+}
+
+/// The handler of the vector the master PIC raises its spurious IRQs on
+#[isr]
+pub fn pic_master_spurious_isr() {
+    pic_spurious(pic::MASTER_SPURIOUS_VECTOR);
+}
+
+/// The handler of the vector the slave PIC raises its spurious IRQs on
+#[isr]
+pub fn pic_slave_spurious_isr() {
+    pic_spurious(pic::SLAVE_SPURIOUS_VECTOR);
+}
+
+/// Handle an IRQ from the (masked) PICs on `vector`, which should be spurious
+fn pic_spurious(vector: u8) {
+    interrupts::count_interrupt(vector);
+
+    if pic::handle_spurious(vector) {
+        interrupts::count_spurious();
+    } else {
+        logger::warn!(
+            "Received a real IRQ from the PICs on vector {vector}, even though they're masked"
+        );
+    }
+}
+
+/// Utility macro to define the ISRs of the dispatched IRQ vectors, which just pass the vector on
+/// to `dispatch`
+macro_rules! irq_dispatch_isrs {
+    ($($isr_name:ident => $vec:expr),* $(,)?) => {
+        $(
+            #[isr]
+            fn $isr_name() {
+                dispatch($vec);
+            }
+        )*
+    };
+}
+
+irq_dispatch_isrs!(
+    irq_32 => 32, irq_33 => 33, irq_34 => 34, irq_35 => 35,
+    irq_36 => 36, irq_37 => 37, irq_38 => 38, irq_39 => 39,
+    irq_40 => 40, irq_41 => 41, irq_42 => 42, irq_43 => 43,
+    irq_44 => 44, irq_45 => 45, irq_46 => 46, irq_47 => 47,
+    irq_48 => 48, irq_49 => 49, irq_50 => 50, irq_51 => 51,
+    irq_52 => 52, irq_53 => 53, irq_54 => 54, irq_55 => 55,
+    irq_56 => 56, irq_57 => 57, irq_58 => 58, irq_59 => 59,
+    irq_60 => 60, irq_61 => 61, irq_62 => 62, irq_63 => 63,
+);
+
+/// The ISR stubs of the dispatched IRQ vectors, indexed by `vector - FIRST_IRQ_VECTOR`
+#[rustfmt::skip]
+pub(super) static IRQ_ISR_STUBS: [IsrStub; IRQ_VECTOR_COUNT] = [
+    __isr_stub_irq_32, __isr_stub_irq_33, __isr_stub_irq_34, __isr_stub_irq_35,
+    __isr_stub_irq_36, __isr_stub_irq_37, __isr_stub_irq_38, __isr_stub_irq_39,
+    __isr_stub_irq_40, __isr_stub_irq_41, __isr_stub_irq_42, __isr_stub_irq_43,
+    __isr_stub_irq_44, __isr_stub_irq_45, __isr_stub_irq_46, __isr_stub_irq_47,
+    __isr_stub_irq_48, __isr_stub_irq_49, __isr_stub_irq_50, __isr_stub_irq_51,
+    __isr_stub_irq_52, __isr_stub_irq_53, __isr_stub_irq_54, __isr_stub_irq_55,
+    __isr_stub_irq_56, __isr_stub_irq_57, __isr_stub_irq_58, __isr_stub_irq_59,
+    __isr_stub_irq_60, __isr_stub_irq_61, __isr_stub_irq_62, __isr_stub_irq_63,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+    static LAST_VECTOR: AtomicU8 = AtomicU8::new(0);
+    static DEFERRED_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn record_vector(vector: u8) {
+        LAST_VECTOR.store(vector, Ordering::Relaxed);
+    }
+
+    fn other_handler(_vector: u8) {
+        panic!("The wrong handler was called");
+    }
+
+    fn deferred_work() {
+        DEFERRED_RUNS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_register_and_dispatch() {
+        let dispatch = SpinLock::new(IrqDispatch::new());
+
+        dispatch.lock().register(40, record_vector).unwrap();
+        dispatch.lock().register(41, other_handler).unwrap();
+
+        assert!(call_handler(&dispatch, 40));
+        assert_eq!(LAST_VECTOR.load(Ordering::Relaxed), 40);
+
+        // Nothing is registered on these
+        assert!(!call_handler(&dispatch, 42));
+        assert!(!call_handler(&dispatch, 3));
+
+        dispatch.lock().unregister(41).unwrap();
+        assert!(!call_handler(&dispatch, 41));
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let mut dispatch = IrqDispatch::new();
+
+        assert_eq!(
+            dispatch.register(14, record_vector),
+            Err(EventError::InvalidVector)
+        );
+        assert_eq!(
+            dispatch.register(FIRST_IRQ_VECTOR + IRQ_VECTOR_COUNT as u8, record_vector),
+            Err(EventError::InvalidVector)
+        );
+
+        dispatch.register(FIRST_IRQ_VECTOR, record_vector).unwrap();
+        assert_eq!(
+            dispatch.register(FIRST_IRQ_VECTOR, record_vector),
+            Err(EventError::VectorTaken)
+        );
+
+        // The first vector is taken, so the next one should be handed out
+        assert_eq!(
+            dispatch.register_any(record_vector, |_| true),
+            Ok(FIRST_IRQ_VECTOR + 1)
+        );
+        // Vectors that can't be claimed are skipped
+        assert_eq!(
+            dispatch.register_any(record_vector, |vector| vector != FIRST_IRQ_VECTOR + 2),
+            Ok(FIRST_IRQ_VECTOR + 3)
+        );
+        for _ in 3..IRQ_VECTOR_COUNT {
+            dispatch.register_any(record_vector, |_| true).unwrap();
+        }
+        assert_eq!(
+            dispatch.register_any(record_vector, |_| true),
+            Err(EventError::NoFreeVector)
+        );
+    }
+
+    #[test]
+    fn test_deferred_work() {
+        let dispatch = SpinLock::new(IrqDispatch::new());
+
+        for _ in 0..MAX_DEFERRED_WORK {
+            dispatch.lock().defer(deferred_work).unwrap();
+        }
+        assert_eq!(
+            dispatch.lock().defer(deferred_work),
+            Err(EventError::DeferredWorkFull)
+        );
+
+        let before = DEFERRED_RUNS.load(Ordering::Relaxed);
+        run_deferred_work(&dispatch);
+        assert_eq!(
+            DEFERRED_RUNS.load(Ordering::Relaxed) - before,
+            MAX_DEFERRED_WORK
+        );
+        assert!(dispatch.lock().take_deferred().is_none());
+    }
 }
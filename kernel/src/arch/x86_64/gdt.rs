@@ -2,7 +2,13 @@
 //!
 //! NOTE: Setting up the GDT only happens when we're booting on raw UEFI without any bootloaders.
 
-use core::{arch::asm, mem::transmute, ops::Index, ptr};
+use core::{
+    arch::asm,
+    cell::SyncUnsafeCell,
+    mem::{size_of, transmute},
+    ops::Index,
+    ptr,
+};
 
 use modular_bitfield::prelude::*;
 
@@ -31,7 +37,6 @@ pub struct SegmentDescriptor {
     pub base_1: B8,
 }
 
-// TODO: Add TSS here as well
 /// The GDT
 ///
 #[repr(C, packed)]
@@ -39,6 +44,55 @@ pub struct Gdt {
     segments: [SegmentDescriptor; 6],
 }
 
+/// The IST entry (1 based, as used in the IDT) the NMI handler runs on
+pub const NMI_IST_INDEX: u8 = 1;
+
+/// The IST entry (1 based, as used in the IDT) the double fault handler runs on. It isn't shared
+/// with the NMI handler, since an NMI can arrive while the double fault handler is running
+pub const DOUBLE_FAULT_IST_INDEX: u8 = 2;
+
+/// The size of each of the IST stacks
+const IST_STACK_SIZE: usize = 4 * 0x1000;
+
+/// The max amount of entries the GDT we load (the bootloader's GDT + the TSS) can have
+const MAX_GDT_ENTRIES: usize = 16;
+
+/// The GDT we load, which is a copy of the bootloader's GDT with the TSS descriptor appended
+static GDT: SyncUnsafeCell<[u64; MAX_GDT_ENTRIES]> = SyncUnsafeCell::new([0; MAX_GDT_ENTRIES]);
+
+/// The TSS
+static TSS: SyncUnsafeCell<Tss> = SyncUnsafeCell::new(Tss::new());
+
+/// The stack the NMI handler runs on
+static NMI_STACK: SyncUnsafeCell<IstStack> = SyncUnsafeCell::new(IstStack([0; IST_STACK_SIZE]));
+
+/// The stack the double fault handler runs on, so it works even if the fault was caused by
+/// overflowing the kernel stack
+static DOUBLE_FAULT_STACK: SyncUnsafeCell<IstStack> =
+    SyncUnsafeCell::new(IstStack([0; IST_STACK_SIZE]));
+
+/// A stack that an IST entry points to
+#[repr(C, align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+/// The 64 bit TSS.
+///
+/// In long mode there is no hardware task switching, so it's only used to hold the stacks the CPU
+/// switches to on privilege level changes and on interrupts with an IST entry set.
+#[repr(C, packed)]
+pub struct Tss {
+    reserved_0: u32,
+    /// The stacks to load when switching to privilege levels 0-2
+    rsp: [u64; 3],
+    reserved_1: u64,
+    /// The Interrupt Stack Table. `ist[n]` is used by IDT entries with an IST index of `n + 1`
+    ist: [u64; 7],
+    reserved_2: u64,
+    reserved_3: u16,
+    /// The offset of the IO permission bitmap from the start of the TSS
+    iomap_base: u16,
+}
+
 /// The basic, visible part of a segment selector.
 #[bitfield(bits = 16)]
 #[derive(Debug, Clone, Copy, Default)]
@@ -117,6 +171,99 @@ impl Gdt {
     }
 }
 
+impl Tss {
+    /// Create an empty TSS, with no IO permission bitmap
+    const fn new() -> Self {
+        Self {
+            reserved_0: 0,
+            rsp: [0; 3],
+            reserved_1: 0,
+            ist: [0; 7],
+            reserved_2: 0,
+            reserved_3: 0,
+            iomap_base: size_of::<Self>() as u16,
+        }
+    }
+}
+
+/// Create the (16 byte long) system segment descriptor of an available 64 bit TSS
+fn tss_descriptor(base: u64, limit: u32) -> [u64; 2] {
+    /// Present, available 64 bit TSS
+    const ACCESS: u8 = SegmentDescriptor::ACCESS_P | 0b1001;
+
+    let low = (u64::from(limit) & 0xffff)
+        | ((base & 0xff_ffff) << 16)
+        | (u64::from(ACCESS) << 40)
+        | (((u64::from(limit) >> 16) & 0xf) << 48)
+        | (((base >> 24) & 0xff) << 56);
+
+    [low, base >> 32]
+}
+
+/// Load a GDT with a TSS, so interrupts can switch to their IST stacks.
+///
+/// The bootloader's descriptors are copied over as they are, so the currently loaded segment
+/// selectors stay valid.
+///
+/// NOTE: THIS FUNCTION SHOULD BE CALLED ONLY ONCE DURING BOOT, before the IDT is loaded
+pub(super) unsafe fn load_tss() {
+    utils::once_per_boot!();
+
+    let gdtr = Gdt::read_gdtr();
+    let entries = (usize::from(gdtr.limit) + 1) / size_of::<u64>();
+    assert!(
+        entries + 2 <= MAX_GDT_ENTRIES,
+        "Bootloader GDT is too big to append the TSS to"
+    );
+
+    let tss = TSS.get();
+    let gdt = unsafe { &mut *GDT.get() };
+    unsafe {
+        (*tss).ist[usize::from(NMI_IST_INDEX) - 1] =
+            (NMI_STACK.get().addr() + IST_STACK_SIZE) as u64;
+        (*tss).ist[usize::from(DOUBLE_FAULT_IST_INDEX) - 1] =
+            (DOUBLE_FAULT_STACK.get().addr() + IST_STACK_SIZE) as u64;
+
+        ptr::copy_nonoverlapping(
+            ptr::with_exposed_provenance::<u64>(gdtr.base as usize),
+            gdt.as_mut_ptr(),
+            entries,
+        );
+    };
+    gdt[entries..entries + 2].copy_from_slice(&tss_descriptor(
+        tss.addr() as u64,
+        (size_of::<Tss>() - 1) as u32,
+    ));
+
+    let new_gdtr = DescriptorTablePtr {
+        base: gdt.as_ptr().addr() as u64,
+        limit: ((entries + 2) * size_of::<u64>() - 1) as u16,
+    };
+    let tss_selector = (entries * size_of::<u64>()) as u16;
+
+    unsafe {
+        asm!(
+            "lgdt [{}]",
+            "ltr {:x}",
+            in(reg) ptr::from_ref(&new_gdtr),
+            in(reg) tss_selector,
+            options(nostack),
+        );
+    };
+
+    logger::info!("Loaded TSS successfully");
+}
+
+/// Get the selector (read from `TR`) and the address of the TSS `load_tss` loaded
+pub fn read_tss() -> (SegmentSelector, u64) {
+    let selector: u16;
+    unsafe {
+        asm!("str {:x}", out(reg) selector, options(nomem, nostack));
+    };
+
+    (SegmentSelector::from(selector), TSS.get().addr() as u64)
+}
+
 impl SegmentDescriptor {
     /// Accessed bit. Set to 1 by the CPU when accessed (unless set manually in advance)
     const ACCESS_A: u8 = 1 << 0;
@@ -344,7 +491,7 @@ impl From<DescriptorTablePtr> for FullSegmentSelector {
 mod tests {
     use core::mem::offset_of;
 
-    use crate::arch::x86_64::gdt::FullSegmentSelector;
+    use crate::arch::x86_64::gdt::{FullSegmentSelector, Tss, tss_descriptor};
 
     #[test]
     fn test_full_segment_selector_layout() {
@@ -353,4 +500,24 @@ mod tests {
         assert_eq!(offset_of!(FullSegmentSelector, limit), 4);
         assert_eq!(offset_of!(FullSegmentSelector, base), 8);
     }
+
+    #[test]
+    fn test_tss_layout() {
+        assert_eq!(size_of::<Tss>(), 104);
+        assert_eq!(offset_of!(Tss, rsp), 0x4);
+        assert_eq!(offset_of!(Tss, ist), 0x24);
+        assert_eq!(offset_of!(Tss, iomap_base), 0x66);
+    }
+
+    #[test]
+    fn test_tss_descriptor() {
+        let [low, high] = tss_descriptor(0xffff_8000_1234_5678, 103);
+
+        assert_eq!(low & 0xffff, 103);
+        assert_eq!((low >> 16) & 0xff_ffff, 0x34_5678);
+        assert_eq!((low >> 40) & 0xff, 0x89);
+        assert_eq!((low >> 48) & 0xf, 0);
+        assert_eq!(low >> 56, 0x12);
+        assert_eq!(high, 0xffff_8000);
+    }
 }
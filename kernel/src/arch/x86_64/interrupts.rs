@@ -2,21 +2,26 @@
 
 use crate::arch::x86_64::{
     cpu::{self, Register},
-    event::GENERIC_ISR_VECTOR,
-    gdt::Cs,
+    event::{self, EventError, FIRST_IRQ_VECTOR, IRQ_ISR_STUBS, IrqHandler, LAST_IRQ_VECTOR},
+    gdt::{Cs, DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX},
 };
 use core::{
     arch::asm,
     mem::{size_of, transmute},
     ptr::{self, from_ref},
+    sync::atomic::{AtomicU64, Ordering},
 };
 use modular_bitfield::prelude::*;
 use utils::sync::spinlock::{SpinLock, SpinLockable};
 
 use super::{
     DescriptorTablePtr,
-    apic::ioapic::{self, map_irq_to_vector, set_disabled},
+    apic::{
+        ioapic::{self, map_irq_to_vector, set_disabled},
+        lapic::SPURIOUS_VECTOR,
+    },
     gdt::SegmentSelector,
+    pic, vectors,
 };
 
 /// The number of entries in the IDT
@@ -26,6 +31,13 @@ const IDT_ENTRIES_NUM: usize = 256;
 /// The IDT
 static IDT: SpinLock<Idt> = SpinLock::new(Idt([GateDescriptor::DEFAULT; IDT_ENTRIES_NUM]));
 
+/// The amount of interrupts received on each vector
+static INTERRUPT_COUNTS: [AtomicU64; IDT_ENTRIES_NUM] =
+    [const { AtomicU64::new(0) }; IDT_ENTRIES_NUM];
+
+/// The amount of spurious interrupts received, from the local APIC and the PICs
+static SPURIOUS_COUNT: AtomicU64 = AtomicU64::new(0);
+
 /// The IDT
 pub struct Idt([GateDescriptor; IDT_ENTRIES_NUM]);
 
@@ -54,7 +66,7 @@ struct GateDescriptor {
 
 /// The type of the gate
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GateType {
     /// This IDT entry is for an interrupt
     Interrupt = 0b1110,
@@ -83,28 +95,138 @@ pub enum Present {
     Present = 1,
 }
 
+/// Errors building or installing an IDT gate might encounter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdtError {
+    /// The IST index doesn't fit in the gate (it must be 0 for no IST, or 1 to 7)
+    InvalidIst(u8),
+    /// The gate uses an IST, but is a trap gate. Trap gates leave interrupts enabled, so the
+    /// handler could be re-entered, which resets the stack pointer to the top of the same IST
+    /// stack and overwrites the frames still on it
+    IstOnTrapGate,
+    /// The gate uses an IST, but can be triggered from user mode with `int`, which lets user code
+    /// re-enter the handler on the same IST stack
+    IstOnUserGate,
+    /// The selector isn't a ring 0 selector of a GDT segment
+    InvalidSelector,
+    /// The gate isn't present, so its handler would never be called
+    NotPresent,
+    /// There is no free entry left in the IDT
+    NoFreeEntry,
+}
+
 /// Represents an entry in the IDT
 impl GateDescriptor {
     const DEFAULT: Self = unsafe { transmute(0_u128) };
+}
+
+/// Builds an IDT gate, making sure its type, selector, IST index and DPL make sense together.
+///
+/// Starts off as a present, ring 0 interrupt gate with no IST.
+#[derive(Debug, Clone, Copy)]
+pub struct IdtEntryBuilder {
+    offset: u64,
+    segment_selector: SegmentSelector,
+    ist: u8,
+    gate_type: GateType,
+    dpl: Dpl,
+    present: Present,
+}
+
+impl IdtEntryBuilder {
+    /// Start building a gate that calls `isr_stub`, running it on the code segment
+    /// `segment_selector`
+    #[inline]
+    #[must_use]
+    pub fn new(isr_stub: IsrStub, segment_selector: SegmentSelector) -> Self {
+        Self::from_offset(isr_stub as usize as u64, segment_selector)
+    }
+
+    /// Start building a gate whose handler is at `offset`
+    #[inline]
+    const fn from_offset(offset: u64, segment_selector: SegmentSelector) -> Self {
+        Self {
+            offset,
+            segment_selector,
+            ist: 0,
+            gate_type: GateType::Interrupt,
+            dpl: Dpl::Kernel,
+            present: Present::Present,
+        }
+    }
+
+    /// Set the type of the gate
+    #[inline]
+    #[must_use]
+    pub const fn gate_type(mut self, gate_type: GateType) -> Self {
+        self.gate_type = gate_type;
+        self
+    }
+
+    /// Set the lowest privilege level that can trigger the gate with `int`
+    #[inline]
+    #[must_use]
+    pub const fn dpl(mut self, dpl: Dpl) -> Self {
+        self.dpl = dpl;
+        self
+    }
+
+    /// Set the IST entry (1 based) the handler runs on, or 0 to keep using the current stack
+    #[inline]
+    #[must_use]
+    pub const fn ist(mut self, ist: u8) -> Self {
+        self.ist = ist;
+        self
+    }
 
-    /// Registers the entry in the IDT with the given parameters.
-    fn install(
-        &mut self,
-        offset: u64,
-        segment_selector: SegmentSelector,
-        ist: u8,
-        gate_type: GateType,
-        dpl: Dpl,
-        present: Present,
-    ) {
-        self.set_offset_0(offset as u16);
-        self.set_segment_selector(segment_selector.into());
-        self.set_ist(ist);
-        self.set_gate_type(gate_type as u8);
-        self.set_dpl(dpl as u8);
-        self.set_present(present as u8);
-        self.set_offset_1((offset >> 16) as u16);
-        self.set_offset_2((offset >> 32) as u32);
+    /// Set whether the gate is present
+    #[inline]
+    #[must_use]
+    pub const fn present(mut self, present: Present) -> Self {
+        self.present = present;
+        self
+    }
+
+    /// Check that the gate is valid
+    ///
+    /// # Errors
+    /// Returns the first problem found with the gate
+    pub fn validate(&self) -> Result<(), IdtError> {
+        let selector = self.segment_selector;
+        // A null selector, an LDT selector or a non ring 0 selector can't be used to run a handler
+        if selector.index() == 0 || selector.gdt() != 0 || selector.rpl() != 0 {
+            return Err(IdtError::InvalidSelector);
+        }
+
+        if self.ist > 0b111 {
+            return Err(IdtError::InvalidIst(self.ist));
+        }
+
+        if self.ist != 0 {
+            if self.gate_type == GateType::Trap {
+                return Err(IdtError::IstOnTrapGate);
+            }
+            if self.dpl != Dpl::Kernel {
+                return Err(IdtError::IstOnUserGate);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the gate, and encode it into a descriptor
+    fn build(&self) -> Result<GateDescriptor, IdtError> {
+        self.validate()?;
+
+        Ok(GateDescriptor::new()
+            .with_offset_0(self.offset as u16)
+            .with_segment_selector(self.segment_selector.into())
+            .with_ist(self.ist)
+            .with_gate_type(self.gate_type as u8)
+            .with_dpl(self.dpl as u8)
+            .with_present(self.present as u8)
+            .with_offset_1((self.offset >> 16) as u16)
+            .with_offset_2((self.offset >> 32) as u32))
     }
 }
 
@@ -113,6 +235,8 @@ impl Idt {
     ///
     /// NOTE: THIS FUNCTION SHOULD BE CALLED ONLY ONCE DURING BOOT
     pub(super) fn init() {
+        utils::once_per_boot!();
+
         cpu::cli();
         let mut idt = IDT.lock();
 
@@ -127,44 +251,34 @@ impl Idt {
 
     /// Install all the exception ISR handlers
     #[inline]
-    #[rustfmt::skip]
     fn install_exception_isrs(&mut self) {
         let cs = unsafe { Cs::read().0 };
 
-        self.0[0].install(__isr_stub_exception_0 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[1].install(__isr_stub_exception_1 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[2].install(__isr_stub_exception_2 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[3].install(__isr_stub_exception_3 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[4].install(__isr_stub_exception_4 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[5].install(__isr_stub_exception_5 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[6].install(__isr_stub_exception_6 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[7].install(__isr_stub_exception_7 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[8].install(__isr_stub_exception_8 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[9].install(__isr_stub_exception_9 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[10].install(__isr_stub_exception_10 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[11].install(__isr_stub_exception_11 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[12].install(__isr_stub_exception_12 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[13].install(__isr_stub_exception_13 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[14].install(__isr_stub_exception_14 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[15].install(__isr_stub_exception_15 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[16].install(__isr_stub_exception_16 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[17].install(__isr_stub_exception_17 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[18].install(__isr_stub_exception_18 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[19].install(__isr_stub_exception_19 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[20].install(__isr_stub_exception_20 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[21].install(__isr_stub_exception_21 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[22].install(__isr_stub_exception_22 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[23].install(__isr_stub_exception_23 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[24].install(__isr_stub_exception_24 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[25].install(__isr_stub_exception_25 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[26].install(__isr_stub_exception_26 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[27].install(__isr_stub_exception_27 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[28].install(__isr_stub_exception_28 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[29].install(__isr_stub_exception_29 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[30].install(__isr_stub_exception_30 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-        self.0[31].install(__isr_stub_exception_31 as usize as u64, cs, 0, GateType::Trap, Dpl::Kernel, Present::Present);
-
-        self.0[GENERIC_ISR_VECTOR as usize].install(__isr_stub_generic_irq_isr as usize as u64, cs, 0, GateType::Interrupt, Dpl::Kernel, Present::Present);
+        for (vector, isr_stub) in EXCEPTION_ISR_STUBS.into_iter().enumerate() {
+            self.0[vector] = exception_gate(vector, IdtEntryBuilder::new(isr_stub, cs))
+                .build()
+                .expect("Exception gates should be valid");
+        }
+
+        for (i, isr_stub) in IRQ_ISR_STUBS.iter().enumerate() {
+            self.0[FIRST_IRQ_VECTOR as usize + i] = IdtEntryBuilder::new(*isr_stub, cs)
+                .build()
+                .expect("IRQ gates should be valid");
+        }
+
+        self.0[SPURIOUS_VECTOR as usize] = IdtEntryBuilder::new(__isr_stub_spurious_isr, cs)
+            .build()
+            .expect("The spurious interrupt gate should be valid");
+
+        // The PICs are masked, but can still raise spurious IRQs
+        self.0[pic::MASTER_SPURIOUS_VECTOR as usize] =
+            IdtEntryBuilder::new(__isr_stub_pic_master_spurious_isr, cs)
+                .build()
+                .expect("The PIC spurious interrupt gate should be valid");
+        self.0[pic::SLAVE_SPURIOUS_VECTOR as usize] =
+            IdtEntryBuilder::new(__isr_stub_pic_slave_spurious_isr, cs)
+                .build()
+                .expect("The PIC spurious interrupt gate should be valid");
 
         logger::info!("Installed ISRs successfully");
     }
@@ -205,55 +319,43 @@ impl Idt {
     }
 }
 
-/// Find an available entry in the IDT and install an ISR entry there
+/// Allocate a vector and install the gate built by `entry` in its IDT entry. Returns the vector.
 ///
-/// NOTE: Make sure to call with the *ISR stub* and *not the actual handler!!* (ie. `__isr_stub_..`)
-pub unsafe fn install_isr(
-    isr_stub: IsrStub,
-    segment_selector: SegmentSelector,
-    ist_field: u8,
-    gate_type: GateType,
-    dpl: Dpl,
-    present: Present,
-) -> u8 {
-    let mut idt = IDT.lock();
-
-    let (entry_number, entry) = idt
-        .0
-        .iter_mut()
-        .enumerate()
-        .find(|entry| entry.1.present() == Present::NotPresent as u8)
-        .unwrap();
-
-    entry.install(
-        isr_stub as usize as u64,
-        segment_selector,
-        ist_field,
-        gate_type,
-        dpl,
-        present,
-    );
-
-    entry_number as u8
+/// The vector is taken from above the dispatched IRQ vectors, whose entries are already in use.
+///
+/// NOTE: Make sure to build the entry with the *ISR stub* and *not the actual handler!!* (ie.
+/// `__isr_stub_..`)
+///
+/// # Errors
+/// Fails if the gate is invalid or isn't present, or if there is no free entry left
+pub unsafe fn install_isr(entry: IdtEntryBuilder) -> Result<u8, IdtError> {
+    if entry.present == Present::NotPresent {
+        return Err(IdtError::NotPresent);
+    }
+    let descriptor = entry.build()?;
+
+    let vector = vectors::allocate_vector_in(LAST_IRQ_VECTOR + 1..=u8::MAX)
+        .map_err(|_| IdtError::NoFreeEntry)?;
+    IDT.lock().0[vector as usize] = descriptor;
+
+    Ok(vector)
 }
 
-// TODO: Return an error instead of panicking here
-/// A wrapper for easier installing of IRQ ISRs
-pub unsafe fn register_irq(irq: u8, isr_stub: IsrStub) {
+// TODO: Return an error instead of panicking on IO APIC errors here
+/// A wrapper for easier registering of IRQ handlers.
+///
+/// `handler` is registered on a free IRQ vector, which `irq` is then routed to. Returns the
+/// vector that was used.
+///
+/// # Errors
+/// Fails if there is no free IRQ vector left
+pub unsafe fn register_irq(irq: u8, handler: IrqHandler) -> Result<u8, EventError> {
     unsafe {
         // Make sure the interrupt is masked off before we do any fiddiling with the
-        // IO APIC and IDT
+        // IO APIC and the dispatch table
         ioapic::set_disabled(irq, true).unwrap();
 
-        // Install the new ISR
-        let vector = install_isr(
-            isr_stub,
-            Cs::read().0,
-            0,
-            GateType::Interrupt,
-            Dpl::Kernel,
-            Present::Present,
-        );
+        let vector = event::register_any(handler)?;
 
         // Tell the IO APIC to map `irq` to the given `vector`
         // XXX: Change the flags here!
@@ -264,15 +366,150 @@ pub unsafe fn register_irq(irq: u8, isr_stub: IsrStub) {
         // NOTE: No interrupt should be triggered yet, since the timer is still
         // disabled internally.
         set_disabled(irq, false).unwrap();
-    };
+
+        Ok(vector)
+    }
+}
+
+/// The interrupt flag in tests, since they can't actually `cli`/`sti`
+#[cfg(test)]
+static TEST_INTERRUPT_FLAG: core::sync::atomic::AtomicBool =
+    core::sync::atomic::AtomicBool::new(true);
+
+/// Returns true if maskable interrupts are enabled (i.e. `RFLAGS.IF` is set)
+#[inline]
+#[must_use]
+pub fn interrupts_enabled() -> bool {
+    #[cfg(not(test))]
+    return unsafe { cpu::Rflags::read() }.if_enable() != 0;
+    #[cfg(test)]
+    return TEST_INTERRUPT_FLAG.load(core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Set the interrupt flag to `enabled`
+#[inline]
+fn set_interrupts_enabled(enabled: bool) {
+    #[cfg(not(test))]
+    if enabled {
+        cpu::sti();
+    } else {
+        cpu::cli();
+    }
+    #[cfg(test)]
+    TEST_INTERRUPT_FLAG.store(enabled, core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Run `f` with maskable interrupts disabled, then restore the interrupt flag to what it was.
+///
+/// Interrupts are only re-enabled if they were enabled before, so this can be nested, and used
+/// from code that already runs with interrupts disabled (e.g. ISRs).
+#[inline]
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let were_enabled = interrupts_enabled();
+    if were_enabled {
+        set_interrupts_enabled(false);
+    }
+
+    let ret = f();
+
+    if were_enabled {
+        set_interrupts_enabled(true);
+    }
+
+    ret
 }
 
 // TODO: unregister_isr
 
+/// A snapshot of the interrupt counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptStats {
+    /// The amount of interrupts received on each vector, spurious ones included
+    pub per_vector: [u64; IDT_ENTRIES_NUM],
+    /// The amount of spurious interrupts received
+    pub spurious: u64,
+}
+
+/// Count an interrupt received on `vector`
+#[inline]
+pub(super) fn count_interrupt(vector: u8) {
+    INTERRUPT_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a spurious interrupt. It should also be counted on its vector with `count_interrupt`
+#[inline]
+pub(super) fn count_spurious() {
+    SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Get the amount of interrupts received so far.
+///
+/// The counters keep going up while they are read, so the snapshot isn't atomic as a whole
+#[must_use]
+pub fn stats() -> InterruptStats {
+    InterruptStats {
+        per_vector: core::array::from_fn(|i| INTERRUPT_COUNTS[i].load(Ordering::Relaxed)),
+        spurious: SPURIOUS_COUNT.load(Ordering::Relaxed),
+    }
+}
+
+/// The vector of the NMI
+const NMI_VECTOR: usize = 2;
+
+/// The vector of the double fault exception
+const DOUBLE_FAULT_VECTOR: usize = 8;
+
+/// Set up `entry` as the gate of exception `vector`
+const fn exception_gate(vector: usize, entry: IdtEntryBuilder) -> IdtEntryBuilder {
+    match vector {
+        // The NMI can arrive while any other handler is running, even on a bad stack
+        NMI_VECTOR => entry.ist(NMI_IST_INDEX),
+        // A double fault is often caused by a bad stack, so it can't be handled on it
+        DOUBLE_FAULT_VECTOR => entry.ist(DOUBLE_FAULT_IST_INDEX),
+        _ => entry.gate_type(GateType::Trap),
+    }
+}
+
+/// The ISR stubs of the exceptions, indexed by vector
+const EXCEPTION_ISR_STUBS: [IsrStub; 32] = [
+    __isr_stub_exception_0,
+    __isr_stub_exception_1,
+    __isr_stub_nmi,
+    __isr_stub_exception_3,
+    __isr_stub_exception_4,
+    __isr_stub_exception_5,
+    __isr_stub_exception_6,
+    __isr_stub_exception_7,
+    __isr_stub_exception_8,
+    __isr_stub_exception_9,
+    __isr_stub_exception_10,
+    __isr_stub_exception_11,
+    __isr_stub_exception_12,
+    __isr_stub_exception_13,
+    __isr_stub_exception_14,
+    __isr_stub_exception_15,
+    __isr_stub_exception_16,
+    __isr_stub_exception_17,
+    __isr_stub_exception_18,
+    __isr_stub_exception_19,
+    __isr_stub_exception_20,
+    __isr_stub_exception_21,
+    __isr_stub_exception_22,
+    __isr_stub_exception_23,
+    __isr_stub_exception_24,
+    __isr_stub_exception_25,
+    __isr_stub_exception_26,
+    __isr_stub_exception_27,
+    __isr_stub_exception_28,
+    __isr_stub_exception_29,
+    __isr_stub_exception_30,
+    __isr_stub_exception_31,
+];
+
 unsafe extern "C" {
     fn __isr_stub_exception_0();
     fn __isr_stub_exception_1();
-    fn __isr_stub_exception_2();
+    fn __isr_stub_nmi();
     fn __isr_stub_exception_3();
     fn __isr_stub_exception_4();
     fn __isr_stub_exception_5();
@@ -303,7 +540,129 @@ unsafe extern "C" {
     fn __isr_stub_exception_30();
     fn __isr_stub_exception_31();
 
-    fn __isr_stub_generic_irq_isr();
+    fn __isr_stub_spurious_isr();
+    fn __isr_stub_pic_master_spurious_isr();
+    fn __isr_stub_pic_slave_spurious_isr();
 }
 
 impl SpinLockable for Idt {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The selector of the kernel code segment (GDT entry 5, ring 0)
+    fn kernel_cs() -> SegmentSelector {
+        SegmentSelector::new().with_index(5)
+    }
+
+    #[test]
+    fn test_gate_encoding() {
+        let gate = IdtEntryBuilder::from_offset(0xffff_ffff_8012_3456, kernel_cs())
+            .ist(NMI_IST_INDEX)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            gate.into_bytes(),
+            [
+                0x56, 0x34, // Offset 15:0
+                0x28, 0x00, // Selector
+                0x01, // IST
+                0x8e, // Present, DPL 0, 64 bit interrupt gate
+                0x12, 0x80, // Offset 31:16
+                0xff, 0xff, 0xff, 0xff, // Offset 63:32
+                0x00, 0x00, 0x00, 0x00, // Reserved
+            ]
+        );
+
+        let gate = IdtEntryBuilder::from_offset(0x1000, kernel_cs())
+            .gate_type(GateType::Trap)
+            .dpl(Dpl::User)
+            .build()
+            .unwrap();
+        assert_eq!(gate.into_bytes()[5], 0xef);
+    }
+
+    #[test]
+    fn test_double_fault_gate() {
+        let gate = exception_gate(
+            DOUBLE_FAULT_VECTOR,
+            IdtEntryBuilder::from_offset(0x1000, kernel_cs()),
+        )
+        .build()
+        .unwrap()
+        .into_bytes();
+
+        // An interrupt gate running on its own IST stack
+        assert_eq!(gate[4], DOUBLE_FAULT_IST_INDEX);
+        assert_eq!(gate[5], 0x8e);
+        assert_ne!(DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX);
+
+        // The rest of the exceptions (besides the NMI) keep using the current stack
+        let gate = exception_gate(13, IdtEntryBuilder::from_offset(0x1000, kernel_cs()))
+            .build()
+            .unwrap()
+            .into_bytes();
+        assert_eq!(gate[4], 0);
+        assert_eq!(gate[5], 0x8f);
+    }
+
+    #[test]
+    fn test_invalid_gates_are_rejected() {
+        let entry = IdtEntryBuilder::from_offset(0x1000, kernel_cs());
+        assert_eq!(entry.validate(), Ok(()));
+
+        assert_eq!(
+            entry.gate_type(GateType::Trap).ist(1).validate(),
+            Err(IdtError::IstOnTrapGate)
+        );
+        assert_eq!(
+            entry.dpl(Dpl::User).ist(1).validate(),
+            Err(IdtError::IstOnUserGate)
+        );
+        assert_eq!(entry.ist(8).validate(), Err(IdtError::InvalidIst(8)));
+
+        for selector in [
+            SegmentSelector::new(),
+            kernel_cs().with_rpl(3),
+            kernel_cs().with_gdt(1),
+        ] {
+            assert_eq!(
+                IdtEntryBuilder::from_offset(0x1000, selector).validate(),
+                Err(IdtError::InvalidSelector)
+            );
+        }
+
+        // A gate that isn't present is fine, but there is no point in installing a handler in it
+        let not_present = entry.present(Present::NotPresent);
+        assert_eq!(not_present.validate(), Ok(()));
+        assert_eq!(
+            unsafe { install_isr(not_present) },
+            Err(IdtError::NotPresent)
+        );
+    }
+
+    #[test]
+    fn test_without_interrupts_nested() {
+        assert!(interrupts_enabled());
+
+        let ret = without_interrupts(|| {
+            assert!(!interrupts_enabled());
+
+            // The inner call finds interrupts disabled, so it leaves them that way
+            without_interrupts(|| assert!(!interrupts_enabled()));
+            assert!(!interrupts_enabled());
+
+            7
+        });
+        assert_eq!(ret, 7);
+        assert!(interrupts_enabled());
+
+        // Called with interrupts disabled, it doesn't enable them behind our back
+        set_interrupts_enabled(false);
+        without_interrupts(|| assert!(!interrupts_enabled()));
+        assert!(!interrupts_enabled());
+        set_interrupts_enabled(true);
+    }
+}
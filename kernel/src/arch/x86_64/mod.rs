@@ -3,6 +3,7 @@
 use core::arch::x86_64::__cpuid_count;
 
 use paging::get_pml;
+use utils::boot_info::{BootInfo, MemoryRegion};
 use utils::mem::PhysAddr;
 use utils::mem::VirtAddr;
 
@@ -22,7 +23,11 @@ pub mod apic;
 pub mod event;
 pub mod gdt;
 pub mod interrupts;
+pub mod nmi;
 pub mod paging;
+pub mod pic;
+pub mod vectors;
+pub mod watchdog;
 
 /// A static variable to store the CPU vendor we are running on
 pub static CPU_VENDOR: FastLazyStatic<CpuVendor> = FastLazyStatic::new(CpuVendor::Invalid);
@@ -52,30 +57,33 @@ pub struct DescriptorTablePtr {
 impl Arch for X86_64 {
     #[inline]
     unsafe fn early_boot_init() {
+        utils::once_per_boot!();
+
+        // The IDT refers to the IST stacks, so the TSS must be loaded first
+        unsafe { gdt::load_tss() };
+
         // Make sure no pesky interrupt interrupt us
         Idt::init();
 
+        cpu::features::check_required_features();
+        unsafe { cpu::features::enable_hardening_features() };
         find_cpu_vendor();
     }
+
+    #[inline]
+    fn wait_for_interrupt() {
+        cpu::sti_hlt();
+    }
 }
 
 impl PagingManager for X86_64 {
     const BASIC_PAGE_SIZE: PageSize<Self> = PageSize::<Self>::size_4kb(); // 4KB page size
 
     #[inline]
-    #[cfg(feature = "limine")]
-    unsafe fn init_paging_from_limine(
-        mem_map: &[&limine::memory_map::Entry],
-        kernel_virt: VirtAddr,
-        kernel_phys: PhysAddr,
-        used_by_pmm: &limine::memory_map::Entry,
-    ) {
-        use paging::init_from_limine;
-
-        unsafe {
-            init_from_limine(mem_map, kernel_virt, kernel_phys, used_by_pmm);
-        }
+    unsafe fn init_paging(boot_info: &BootInfo, used_by_pmm: &MemoryRegion) {
+        unsafe { paging::init(boot_info, used_by_pmm) };
     }
+
     unsafe fn map_pages_to(
         phys_addr: PhysAddr,
         virt_addr: VirtAddr,
@@ -94,7 +102,44 @@ impl PagingManager for X86_64 {
     ) -> Result<(), PagingError> {
         let pml = get_pml();
         // TODO: Change this to unmap_page
-        unsafe { pml.unmap_pages(virt_addr, page_count, page_size) }
+        unsafe { pml.unmap_pages(virt_addr, page_count, page_size)? };
+
+        for i in 0..page_count {
+            Self::flush_address(virt_addr + i * page_size.size());
+        }
+
+        Ok(())
+    }
+
+    unsafe fn unmap_pages_keep_frames(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Self>,
+    ) -> Result<PhysAddr, PagingError> {
+        let pml = get_pml();
+        let phys_addr = unsafe { pml.unmap_keep_frame(virt_addr, page_count, page_size)? };
+
+        for i in 0..page_count {
+            Self::flush_address(virt_addr + i * page_size.size());
+        }
+
+        Ok(phys_addr)
+    }
+
+    unsafe fn change_flags(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        flags: Flags<Self>,
+        page_size: PageSize<Self>,
+    ) -> Result<(), PagingError> {
+        let pml = get_pml();
+        unsafe { pml.change_flags(virt_addr, page_count, page_size, flags)? };
+
+        for i in 0..page_count {
+            Self::flush_address(virt_addr + i * page_size.size());
+        }
+
+        Ok(())
     }
 
     fn translate(virt_addr: VirtAddr) -> Option<PhysAddr> {
@@ -102,6 +147,21 @@ impl PagingManager for X86_64 {
 
         pml.translate(virt_addr)
     }
+
+    #[inline]
+    fn flush_address(virt_addr: VirtAddr) {
+        paging::invlpg(virt_addr);
+    }
+
+    #[inline]
+    fn flush_all() {
+        paging::flush_tlb();
+    }
+
+    #[inline]
+    fn resident_pages() -> usize {
+        paging::resident_pages()
+    }
 }
 
 // TODO: Possibly remove these asserts here? Could slow things down
@@ -125,10 +185,7 @@ fn find_cpu_vendor() {
     );
 
     // Making sure we're not executing this for nothing
-    assert!(
-        CPU_VENDOR.get() == CpuVendor::Invalid,
-        "CPU vendor is already set. Did you forget you called `find_cpu_vendor`?",
-    );
+    utils::once_per_boot!();
 
     let string = unsafe {
         let res = __cpuid_count(0, 0);
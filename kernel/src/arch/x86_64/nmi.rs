@@ -0,0 +1,247 @@
+//! Handling of non-maskable interrupts
+//!
+//! An NMI can arrive at any point, including while a lock is held or while the stack is in a bad
+//! state. The handler runs on its own IST stack, and never waits for a lock: the NMI is only
+//! recorded if the lock protecting the record can be taken right away.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use utils::sync::{
+    cache_padded::CachePadded,
+    spinlock::{SpinLock, SpinLockable},
+};
+
+use super::apic::lapic::LocalApic;
+use crate::arch::MAX_CPUS;
+
+/// What is known about the NMIs received
+static NMI_RECORD: SpinLock<NmiRecord> = SpinLock::new(NmiRecord::new());
+
+/// The amount of NMI handlers currently running on every CPU (by APIC ID). Kept per CPU, so NMIs
+/// on different cores aren't mistaken for nested ones, and padded so they don't share cache lines
+static NMI_DEPTH: [CachePadded<AtomicUsize>; MAX_CPUS] =
+    [const { CachePadded::new(AtomicUsize::new(0)) }; MAX_CPUS];
+
+/// The amount of NMIs that were received, but couldn't be recorded
+static DROPPED_NMIS: AtomicUsize = AtomicUsize::new(0);
+
+/// The interrupt stack frame the CPU pushes before calling an ISR
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// A record of the NMIs received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NmiRecord {
+    /// The amount of NMIs recorded
+    pub count: usize,
+    /// The state the CPU was in when the last recorded NMI arrived
+    pub last_frame: Option<InterruptFrame>,
+}
+
+/// How an NMI was handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NmiOutcome {
+    /// The NMI was recorded
+    Recorded,
+    /// The NMI arrived while another NMI was being handled
+    Nested,
+    /// The record was locked by whatever the NMI interrupted
+    RecordBusy,
+}
+
+impl NmiRecord {
+    /// Create an empty record
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            last_frame: None,
+        }
+    }
+}
+
+impl SpinLockable for NmiRecord {}
+
+/// Handle an NMI that interrupted `frame`, recording it in `record` and passing a copy of the
+/// updated record to `report`, once the record is unlocked again.
+///
+/// `depth` tracks the handlers currently running on the calling CPU. An NMI handler can be
+/// interrupted by another NMI once it executes an `iretq` (e.g. returning from an exception), in
+/// which case the nested one just bails out, since the outer one is using the IST stack and the
+/// record.
+fn handle(
+    record: &SpinLock<NmiRecord>,
+    depth: &AtomicUsize,
+    frame: &InterruptFrame,
+    report: impl FnOnce(&NmiRecord),
+) -> NmiOutcome {
+    if depth.fetch_add(1, Ordering::Acquire) != 0 {
+        depth.fetch_sub(1, Ordering::Release);
+        return NmiOutcome::Nested;
+    }
+
+    // Never spin here: the lock might be held by the very code we interrupted
+    let updated = record.try_lock().map(|mut record| {
+        record.count += 1;
+        record.last_frame = Some(*frame);
+
+        *record
+    });
+
+    // Reported without holding the lock, so NMIs on other cores aren't dropped while it's logged
+    let outcome = if let Some(updated) = updated {
+        report(&updated);
+
+        NmiOutcome::Recorded
+    } else {
+        NmiOutcome::RecordBusy
+    };
+
+    depth.fetch_sub(1, Ordering::Release);
+
+    outcome
+}
+
+/// The NMI handler
+extern "C" fn nmi(frame: &InterruptFrame) {
+    let depth = &NMI_DEPTH[LocalApic::get_this_apic_id() as usize];
+    let outcome = handle(&NMI_RECORD, depth, frame, |record| {
+        logger::warn!(
+            "Received NMI #{} at {:#x} (rsp {:#x}, rflags {:#x})",
+            record.count,
+            frame.rip,
+            frame.rsp,
+            frame.rflags
+        );
+    });
+
+    if outcome != NmiOutcome::Recorded {
+        DROPPED_NMIS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "watchdog")]
+    super::watchdog::on_nmi(frame);
+}
+
+/// The ISR stub of the NMI handler.
+///
+/// Unlike the `#[isr]` stubs, the NMI handler returns to whatever it interrupted, so the scratch
+/// registers are preserved, and the handler is passed the interrupt frame.
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn __isr_stub_nmi() {
+    core::arch::naked_asm!(
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        // The interrupt frame is right above the registers we pushed. The CPU pushed 5 quad
+        // words, so with the 9 registers the stack is aligned to 16
+        "lea rdi, [rsp + 72]",
+        "call {}",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        "iretq",
+        sym nmi,
+    );
+}
+
+/// Get the record of the NMIs received so far.
+///
+/// NOTE: This must not be called from the NMI handler
+pub fn record() -> NmiRecord {
+    *NMI_RECORD.lock()
+}
+
+/// Get the amount of NMIs that were received, but couldn't be recorded (either because they
+/// nested, or because the record was locked)
+pub fn dropped_count() -> usize {
+    DROPPED_NMIS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: InterruptFrame = InterruptFrame {
+        rip: 0xffff_ffff_8000_1234,
+        cs: 0x28,
+        rflags: 0x202,
+        rsp: 0xffff_ffff_8010_0000,
+        ss: 0x30,
+    };
+
+    #[test]
+    fn test_nmi_recorded() {
+        let record = SpinLock::new(NmiRecord::new());
+        let depth = AtomicUsize::new(0);
+
+        assert_eq!(
+            handle(&record, &depth, &FRAME, |_| {}),
+            NmiOutcome::Recorded
+        );
+
+        // The report gets a copy, and the record is free to be updated while it runs
+        let mut reported = None;
+        assert_eq!(
+            handle(&record, &depth, &FRAME, |copy| {
+                assert!(record.try_lock().is_some());
+                reported = Some(*copy);
+            }),
+            NmiOutcome::Recorded
+        );
+        assert_eq!(reported.map(|copy| copy.count), Some(2));
+
+        let record = *record.lock();
+        assert_eq!(record.count, 2);
+        assert_eq!(record.last_frame, Some(FRAME));
+        assert_eq!(depth.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_nmi_uses_try_lock() {
+        let record = SpinLock::new(NmiRecord::new());
+        let depth = AtomicUsize::new(0);
+
+        // The NMI interrupted code holding the lock. With a blocking `lock` this would never
+        // return
+        let guard = record.lock();
+        assert_eq!(
+            handle(&record, &depth, &FRAME, |_| {}),
+            NmiOutcome::RecordBusy
+        );
+        assert_eq!(depth.load(Ordering::Relaxed), 0);
+        drop(guard);
+
+        assert_eq!(record.lock().count, 0);
+    }
+
+    #[test]
+    fn test_nested_nmi() {
+        let record = SpinLock::new(NmiRecord::new());
+
+        // Another NMI handler is already running
+        let depth = AtomicUsize::new(1);
+        assert_eq!(handle(&record, &depth, &FRAME, |_| {}), NmiOutcome::Nested);
+        assert_eq!(depth.load(Ordering::Relaxed), 1);
+        assert_eq!(*record.lock(), NmiRecord::new());
+    }
+}
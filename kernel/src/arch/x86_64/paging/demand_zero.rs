@@ -0,0 +1,370 @@
+//! Demand-zero (lazily allocated) anonymous memory.
+//!
+//! Demand-zero pages are mapped as not present, and only get a zeroed frame allocated and mapped
+//! in when they are first accessed. This is useful for big, sparsely used allocations.
+//!
+//! The fault might be raised while the PMM is locked (e.g. by code touching a demand-zero page
+//! while allocating), so the fault handler never waits for the PMM: if it's locked, the frame is
+//! taken from a small reserve the faulting CPU keeps, which is refilled whenever pages are mapped
+//! as demand-zero.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use pmm::PmmAllocator;
+use utils::mem::{PhysAddr, VirtAddr, memset};
+
+use crate::{
+    arch::{
+        MAX_CPUS,
+        x86_64::{X86_64, apic::lapic::LocalApic},
+    },
+    mem::paging::{Flags, PageSize, PagingError, RESIDENT_PAGES},
+};
+
+use super::{ENTRIES_PER_TABLE, PageTable, get_pml, next_level_index};
+
+/// The amount of demand-zero pages that weren't accessed yet, so have no frame backing them
+static RESERVED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// The amount of demand-zero pages that were accessed, so have a frame backing them
+static COMMITTED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// The amount of frames every CPU keeps for faults raised while the PMM is locked
+const RESERVE_FRAMES: usize = 4;
+
+/// The frame reserve of every CPU (by APIC ID)
+static RESERVES: [FrameReserve; MAX_CPUS] = [const { FrameReserve::new() }; MAX_CPUS];
+
+/// Frames put aside for demand-zero faults that can't wait for the PMM
+struct FrameReserve {
+    /// The physical addresses of the frames, or 0 for empty slots
+    frames: [AtomicUsize; RESERVE_FRAMES],
+}
+
+impl FrameReserve {
+    const fn new() -> Self {
+        Self {
+            frames: [const { AtomicUsize::new(0) }; RESERVE_FRAMES],
+        }
+    }
+
+    /// Take a frame out of the reserve, if there is one
+    fn take(&self) -> Option<PhysAddr> {
+        self.frames.iter().find_map(|slot| {
+            let frame = slot.swap(0, Ordering::Relaxed);
+            (frame != 0).then_some(PhysAddr(frame))
+        })
+    }
+
+    /// Put a frame allocated by `allocate_frame` into every empty slot
+    fn refill(&self, mut allocate_frame: impl FnMut() -> Option<PhysAddr>) {
+        for slot in &self.frames {
+            if slot.load(Ordering::Relaxed) != 0 {
+                continue;
+            }
+
+            let Some(frame) = allocate_frame() else {
+                return;
+            };
+            slot.store(frame.0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Usage statistics of the demand-zero pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DemandZeroStats {
+    /// The amount of pages that are mapped, but have no frame backing them yet
+    pub reserved: usize,
+    /// The amount of pages that have a frame backing them
+    pub committed: usize,
+}
+
+impl PageTable {
+    /// Map `page_count` 4KB pages starting at `base_addr` as demand-zero pages with the given
+    /// flags
+    pub(super) unsafe fn map_demand_zero(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        flags: Flags<X86_64>,
+    ) -> Result<(), PagingError> {
+        let page_size = PageSize::size_4kb();
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        let table = self.get_create_table_range(base_addr, page_size)?;
+
+        let to_skip = next_level_index(base_addr, page_size.bottom_paging_level());
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        // Check the entire range first, so the table is left untouched on error
+        let entries = &mut table[to_skip..to_skip + page_count];
+        if let Some(taken) = entries.iter().position(|entry| {
+            let flags = entry.get_flags();
+            flags.get_present() || flags.get_demand_zero()
+        }) {
+            return Err(PagingError::PageAlreadyPresent(
+                base_addr + taken * page_size.size(),
+            ));
+        }
+
+        // The entry is marked as a last entry so it could be found while it's not present
+        let flags = flags
+            .set_present(false)
+            .set_last_entry(true)
+            .set_demand_zero(true);
+        for entry in entries {
+            entry.clear();
+            entry.set_flags(flags);
+        }
+
+        RESERVED_PAGES.fetch_add(page_count, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Handle a page fault at `addr` if it was caused by accessing a demand-zero page, by
+    /// allocating a frame using `allocate_frame`, zeroing it and mapping it in.
+    fn handle_demand_zero_fault(
+        &mut self,
+        addr: VirtAddr,
+        allocate_frame: impl FnOnce() -> Result<PhysAddr, PagingError>,
+    ) -> Result<(), PagingError> {
+        let root = core::ptr::from_ref(self).addr();
+        let (entry, page_size) = self
+            .get_entry(addr)
+            .ok_or(PagingError::PageNotPresent(addr))?;
+
+        let flags = entry.get_flags();
+        if flags.get_present() {
+            return Err(PagingError::PageAlreadyPresent(addr));
+        } else if !flags.get_demand_zero() {
+            return Err(PagingError::PageNotPresent(addr));
+        }
+
+        let frame = allocate_frame()?;
+        unsafe {
+            memset(
+                core::ptr::without_provenance_mut(frame.add_hhdm_offset().0),
+                0,
+                page_size.size(),
+            );
+        };
+
+        // The frame is ours, so it should be freed when the page is unmapped
+        entry.clear();
+        unsafe {
+            entry.map(
+                addr,
+                frame,
+                flags.set_last_entry(false).set_allocated(true),
+                page_size,
+            )?;
+        };
+        RESIDENT_PAGES.add(root, page_size.to_default_page_count());
+
+        RESERVED_PAGES.fetch_sub(1, Ordering::Relaxed);
+        COMMITTED_PAGES.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+/// Note that a demand-zero page that wasn't accessed was unmapped
+pub(super) fn release_reserved() {
+    RESERVED_PAGES.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Note that a demand-zero page that was accessed was unmapped
+pub(super) fn release_committed() {
+    COMMITTED_PAGES.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Get the usage statistics of the demand-zero pages
+#[must_use]
+pub fn stats() -> DemandZeroStats {
+    DemandZeroStats {
+        reserved: RESERVED_PAGES.load(Ordering::Relaxed),
+        committed: COMMITTED_PAGES.load(Ordering::Relaxed),
+    }
+}
+
+/// Map `page_count` 4KB pages starting at `virt_addr` as demand-zero pages, in the current
+/// address space.
+///
+/// No memory is allocated until a page is accessed, at which point a zeroed frame is mapped in.
+///
+/// This also tops up the calling CPU's frame reserve, so it must not be called with the PMM locked.
+///
+/// # Safety
+/// The range shouldn't be used for anything else
+///
+/// # Errors
+/// Fails if `virt_addr` isn't page aligned, the range crosses a page table, one of the pages is
+/// already mapped, or a page table couldn't be allocated
+pub unsafe fn map_demand_zero(
+    virt_addr: VirtAddr,
+    page_count: usize,
+    flags: Flags<X86_64>,
+) -> Result<(), PagingError> {
+    RESERVES[LocalApic::get_this_apic_id() as usize].refill(|| {
+        pmm::get()
+            .allocate(PageSize::size_4kb().page_alignment(), 1)
+            .ok()
+    });
+
+    unsafe { get_pml().map_demand_zero(virt_addr, page_count, flags) }
+}
+
+/// Try to resolve a page fault at `addr`, which succeeds only if it was caused by accessing a
+/// demand-zero page for the first time.
+///
+/// The PMM is only used if it isn't locked, otherwise the frame comes from the calling CPU's
+/// reserve.
+///
+/// # Errors
+/// Fails if the fault wasn't caused by a demand-zero page, or if no frame could be allocated
+pub fn handle_page_fault(addr: VirtAddr) -> Result<(), PagingError> {
+    get_pml().handle_demand_zero_fault(addr, || {
+        let frame = pmm::try_get()
+            .and_then(|mut pmm| pmm.allocate(PageSize::size_4kb().page_alignment(), 1).ok());
+
+        frame
+            .or_else(|| RESERVES[LocalApic::get_this_apic_id() as usize].take())
+            .ok_or(PagingError::OutOfMemory)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Entry;
+    use super::*;
+    use alloc::boxed::Box;
+    use core::{cell::Cell, ptr::from_mut};
+
+    /// A fake physical frame. The HHDM offset is 0 in tests, so its address is its "physical" one
+    #[repr(C, align(4096))]
+    struct Frame([u8; 4096]);
+
+    fn empty_table() -> Box<PageTable> {
+        Box::new(PageTable(core::array::from_fn(|_| Entry(0))))
+    }
+
+    /// Point entry 0 of `table` to `next`
+    fn link(table: &mut PageTable, next: &mut PageTable) {
+        table[0].set_addr(PhysAddr(from_mut(next).addr()), PageSize::size_4kb());
+        table[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+    }
+
+    #[test]
+    fn test_demand_zero_fault() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+        let mut pt = empty_table();
+        link(&mut pml4, &mut pdpt);
+        link(&mut pdpt, &mut pd);
+        link(&mut pd, &mut pt);
+
+        let before = stats();
+        let resident = pml4.resident_pages();
+        unsafe {
+            pml4.map_demand_zero(VirtAddr(0x3000), 2, Flags::new().set_read_write(true))
+                .unwrap();
+        };
+        assert_eq!(stats().reserved, before.reserved + 2);
+
+        // Nothing is backing the pages yet
+        assert_eq!(pml4.translate(VirtAddr(0x3000)), None);
+        assert_eq!(
+            unsafe {
+                pml4.map_pages(
+                    VirtAddr(0x3000),
+                    PhysAddr(0),
+                    1,
+                    PageSize::size_4kb(),
+                    Flags::new(),
+                )
+            },
+            Err(PagingError::PageAlreadyPresent(VirtAddr(0x3000)))
+        );
+
+        let mut frame = Box::new(Frame([0xaa; 4096]));
+        let frame_addr = PhysAddr(from_mut(&mut *frame).expose_provenance());
+        let mut allocations = 0;
+
+        pml4.handle_demand_zero_fault(VirtAddr(0x3010), || {
+            allocations += 1;
+            Ok(frame_addr)
+        })
+        .unwrap();
+
+        assert_eq!(allocations, 1);
+        assert_eq!(pml4.translate(VirtAddr(0x3000)), Some(frame_addr));
+        assert!(frame.0.iter().all(|&byte| byte == 0));
+        assert_eq!(stats().reserved, before.reserved + 1);
+        assert_eq!(stats().committed, before.committed + 1);
+        // The frame was allocated for the page, so it's ours
+        assert_eq!(pml4.resident_pages(), resident + 1);
+
+        // Another fault on the same page shouldn't allocate another frame
+        let res = pml4.handle_demand_zero_fault(VirtAddr(0x3ff8), || {
+            allocations += 1;
+            Ok(frame_addr)
+        });
+        assert_eq!(res, Err(PagingError::PageAlreadyPresent(VirtAddr(0x3ff8))));
+        assert_eq!(allocations, 1);
+
+        // Faults on pages that aren't demand-zero ones aren't handled
+        let res = pml4.handle_demand_zero_fault(VirtAddr(0x5000), || Ok(frame_addr));
+        assert_eq!(res, Err(PagingError::PageNotPresent(VirtAddr(0x5000))));
+
+        // Unmapping the untouched page doesn't free anything
+        unsafe {
+            pml4.unmap_pages(VirtAddr(0x4000), 1, PageSize::size_4kb())
+                .unwrap();
+        };
+        assert_eq!(stats().reserved, before.reserved);
+        assert_eq!(pt[4].0, 0);
+    }
+
+    #[test]
+    fn test_frame_reserve() {
+        let reserve = FrameReserve::new();
+        assert_eq!(reserve.take(), None);
+
+        let next = Cell::new(0x1000);
+        let allocate = || {
+            next.set(next.get() + 0x1000);
+            Some(PhysAddr(next.get()))
+        };
+        reserve.refill(allocate);
+        assert_eq!(next.get(), 0x1000 * (RESERVE_FRAMES + 1));
+
+        // Only the slots that were emptied are refilled
+        assert_eq!(reserve.take(), Some(PhysAddr(0x2000)));
+        reserve.refill(allocate);
+        assert_eq!(next.get(), 0x1000 * (RESERVE_FRAMES + 2));
+
+        let mut taken = 0;
+        while reserve.take().is_some() {
+            taken += 1;
+        }
+        assert_eq!(taken, RESERVE_FRAMES);
+
+        // Running out of memory while refilling leaves the reserve partially filled
+        let mut left = 1;
+        reserve.refill(|| {
+            (left > 0).then(|| {
+                left -= 1;
+                PhysAddr(0x5000)
+            })
+        });
+        assert_eq!(reserve.take(), Some(PhysAddr(0x5000)));
+        assert_eq!(reserve.take(), None);
+    }
+}
@@ -69,6 +69,12 @@ impl Flags<X86_64> {
     /// that we know the last level we can combine that with the `PS` flag to determine the size
     pub(super) const FLAG_LAST_ENTRY: usize = 1 << 10;
 
+    /// Custom flag to mark a page as a demand-zero one, which gets a zeroed frame allocated and
+    /// mapped on the first access to it.
+    ///
+    /// NOTE: This shares a bit with `HLAT`, which we never enable
+    pub(super) const FLAG_DEMAND_ZERO: usize = 1 << 11;
+
     /// Create a new, empty `Flags` instance
     #[inline]
     #[must_use]
@@ -180,6 +186,12 @@ impl Flags<X86_64> {
         self.set(Self::FLAG_LAST_ENTRY, status)
     }
 
+    #[inline]
+    #[must_use]
+    pub(super) const fn set_demand_zero(self, status: bool) -> Self {
+        self.set(Self::FLAG_DEMAND_ZERO, status)
+    }
+
     #[inline]
     #[must_use]
     pub const fn get_present(self) -> bool {
@@ -257,4 +269,10 @@ impl Flags<X86_64> {
     pub const fn get_last_entry(self) -> bool {
         self.get(Self::FLAG_LAST_ENTRY)
     }
+
+    #[inline]
+    #[must_use]
+    pub const fn get_demand_zero(self) -> bool {
+        self.get(Self::FLAG_DEMAND_ZERO)
+    }
 }
@@ -0,0 +1,419 @@
+//! Splitting huge (2MB/1GB) pages into smaller ones, and promoting them back.
+//!
+//! Splitting is needed to change part of a huge page (e.g. its permissions), and promoting lets
+//! 512 contiguous, identically flagged pages share a single TLB entry again.
+
+use pmm::PmmAllocator;
+use utils::mem::{PhysAddr, VirtAddr};
+
+use crate::{
+    arch::x86_64::X86_64,
+    mem::paging::{Flags, PageSize, PagingError},
+};
+
+use super::{ENTRIES_PER_TABLE, Entry, MAX_BOTTOM_PAGING_LEVEL, PageTable, get_pml, invlpg};
+
+/// The bits of an entry holding the physical address, for all page sizes
+const ADDR_MASK: usize = 0x000f_ffff_ffff_f000;
+
+/// The bits that may differ between pages that are promoted together. They are merged instead
+const MERGED_BITS: usize = Flags::<X86_64>::FLAG_A | Flags::<X86_64>::FLAG_D;
+
+/// Get the bits of `raw` (an entry mapping a `page_size` page) that hold the frame's address
+#[inline]
+const fn frame_bits(raw: usize, page_size: PageSize<X86_64>) -> usize {
+    raw & ADDR_MASK & !page_size.get_offset_mask()
+}
+
+/// Convert the non address bits of a huge page entry to those of the entries mapping its parts
+#[inline]
+const fn split_attributes(attributes: usize, sub_size: PageSize<X86_64>) -> usize {
+    if sub_size.size() != PageSize::size_4kb().size() {
+        return attributes;
+    }
+
+    // A 4KB entry has no PS bit, and its PAT bit sits where the huge entry's PS bit is
+    let pat = attributes & Flags::<X86_64>::FLAG_BIG_PAGES_PAT != 0;
+    let attributes = attributes & !(Flags::<X86_64>::FLAG_PS | Flags::<X86_64>::FLAG_BIG_PAGES_PAT);
+    if pat {
+        attributes | Flags::<X86_64>::FLAG_4KB_PAT
+    } else {
+        attributes
+    }
+}
+
+/// Convert the non address bits of the entries mapping the parts of a huge page to those of the
+/// huge page's entry. The reverse of `split_attributes`
+#[inline]
+const fn promote_attributes(attributes: usize, sub_size: PageSize<X86_64>) -> usize {
+    if sub_size.size() != PageSize::size_4kb().size() {
+        return attributes;
+    }
+
+    let pat = attributes & Flags::<X86_64>::FLAG_4KB_PAT != 0;
+    let attributes = (attributes & !Flags::<X86_64>::FLAG_4KB_PAT) | Flags::<X86_64>::FLAG_PS;
+    if pat {
+        attributes | Flags::<X86_64>::FLAG_BIG_PAGES_PAT
+    } else {
+        attributes
+    }
+}
+
+impl Entry {
+    /// Point the (non last) entry to the table at `table_addr`, allowing user access if `user`
+    #[inline]
+    fn link_table(&mut self, table_addr: PhysAddr, user: bool) {
+        self.clear();
+        self.set_addr(table_addr, PageSize::size_4kb());
+        // The permissions are enforced by the last entries, so this level allows everything
+        self.set_flags(
+            Flags::new()
+                .set_present(true)
+                .set_read_write(true)
+                .set_user_supervisor(user),
+        );
+    }
+}
+
+impl PageTable {
+    /// Replace the 2MB/1GB page containing `virt_addr` with a table of 512 4KB/2MB pages mapping
+    /// the same frames with the same flags. The new table is allocated using `allocate_table`.
+    ///
+    /// NOTE: The translations don't change, so there is nothing to flush from the TLB
+    pub(super) fn split_huge_with(
+        &mut self,
+        virt_addr: VirtAddr,
+        allocate_table: impl FnOnce() -> Result<PhysAddr, PagingError>,
+    ) -> Result<(), PagingError> {
+        let (entry, page_size) = self
+            .get_entry(virt_addr)
+            .ok_or(PagingError::PageNotPresent(virt_addr))?;
+
+        if !entry.get_flags().get_present() {
+            return Err(PagingError::PageNotPresent(virt_addr));
+        } else if page_size == PageSize::size_4kb() {
+            return Err(PagingError::InvalidPageSize);
+        }
+        let sub_size = PageSize::from_bottom_paging_level(page_size.bottom_paging_level() - 1)
+            .ok_or(PagingError::InvalidPageSize)?;
+
+        let raw = entry.0;
+        let frame = frame_bits(raw, page_size);
+        let attributes = split_attributes(raw & !frame, sub_size);
+
+        let table_addr = allocate_table()?;
+        entry.link_table(table_addr, entry.get_flags().get_user_supervisor());
+
+        let table = entry.next_level_table();
+        for (i, sub_entry) in table.iter_mut().enumerate() {
+            sub_entry.0 = (frame + i * sub_size.size()) | attributes;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the table the page containing `virt_addr` is in with a single 2MB/1GB page, if
+    /// all of the table's pages are present, map contiguous frames and have the same flags. The
+    /// table is then freed using `free_table`.
+    ///
+    /// Returns whether the pages were promoted.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it
+    pub(super) fn try_promote_with(
+        &mut self,
+        virt_addr: VirtAddr,
+        free_table: impl FnOnce(PhysAddr),
+    ) -> Result<bool, PagingError> {
+        let (entry, page_size) = self
+            .get_parent_entry(virt_addr)
+            .ok_or(PagingError::PageNotPresent(virt_addr))?;
+
+        if page_size == PageSize::size_1gb()
+            && !PageSize::supported().any(|supported| supported == page_size)
+        {
+            return Ok(false);
+        }
+        let sub_size = PageSize::from_bottom_paging_level(page_size.bottom_paging_level() - 1)
+            .ok_or(PagingError::InvalidPageSize)?;
+
+        let table = entry.next_level_table();
+        let first = table[0].0;
+        let frame = frame_bits(first, sub_size);
+        let attributes = first & !frame & !MERGED_BITS;
+
+        // The huge page needs a frame aligned to its size
+        if !frame.is_multiple_of(page_size.size()) {
+            return Ok(false);
+        }
+
+        let mut merged = 0;
+        for (i, sub_entry) in table.iter().enumerate() {
+            let flags = sub_entry.get_flags();
+            if !flags.get_present()
+                || !flags.get_last_entry()
+                || frame_bits(sub_entry.0, sub_size) != frame + i * sub_size.size()
+                || sub_entry.0 & !frame_bits(sub_entry.0, sub_size) & !MERGED_BITS != attributes
+            {
+                return Ok(false);
+            }
+
+            merged |= sub_entry.0 & MERGED_BITS;
+        }
+
+        let table_addr = entry.get_addr(PageSize::size_4kb());
+        entry.0 = frame | promote_attributes(attributes | merged, sub_size);
+        free_table(table_addr);
+
+        Ok(true)
+    }
+
+    /// Get the (non last) entry pointing to the table the page containing `virt_addr` is in,
+    /// along with the size of the page the entry would map if it were a last entry.
+    ///
+    /// If the page isn't mapped, or it's mapped by an entry in the top level table, `None` is
+    /// returned.
+    #[must_use]
+    fn get_parent_entry(&mut self, virt_addr: VirtAddr) -> Option<(&mut Entry, PageSize<X86_64>)> {
+        let mut table = self;
+
+        for level in (1..=MAX_BOTTOM_PAGING_LEVEL).rev() {
+            let i = super::next_level_index(virt_addr, level);
+
+            let flags = table[i].get_flags();
+            if flags.get_last_entry() || !flags.get_present() {
+                return None;
+            }
+
+            let child = super::next_level_index(virt_addr, level - 1);
+            if table[i].next_level_table()[child]
+                .get_flags()
+                .get_last_entry()
+            {
+                return Some((&mut table[i], PageSize::from_bottom_paging_level(level)?));
+            }
+
+            table = table[i].next_level_table();
+        }
+
+        None
+    }
+
+    /// Split the 2MB/1GB page containing `virt_addr` into 512 4KB/2MB pages mapping the same
+    /// frames with the same flags
+    pub(super) fn split_huge(&mut self, virt_addr: VirtAddr) -> Result<(), PagingError> {
+        self.split_huge_with(virt_addr, || {
+            pmm::get()
+                .allocate(PageSize::size_4kb().page_alignment(), 1)
+                .map_err(|_| PagingError::OutOfMemory)
+        })
+    }
+
+    /// Promote the table the page containing `virt_addr` is in to a single 2MB/1GB page, if its
+    /// pages allow it. Returns whether the pages were promoted.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it
+    pub(super) fn try_promote(&mut self, virt_addr: VirtAddr) -> Result<bool, PagingError> {
+        self.try_promote_with(virt_addr, |table_addr| unsafe {
+            pmm::get()
+                .free(table_addr, 1)
+                .expect("Failed to free page table");
+        })
+    }
+}
+
+/// Split the 2MB/1GB page containing `virt_addr` into 512 4KB/2MB pages, in the current address
+/// space.
+///
+/// # Errors
+/// Fails if the page isn't mapped, is already a 4KB page, or if no page table could be allocated
+pub fn split_huge(virt_addr: VirtAddr) -> Result<(), PagingError> {
+    get_pml().split_huge(virt_addr)
+}
+
+/// Promote the 512 4KB/2MB pages around `virt_addr` to a single 2MB/1GB page, in the current
+/// address space, if they are contiguous and have the same flags.
+///
+/// Returns whether the pages were promoted.
+///
+/// # Safety
+/// The caller must make sure no one relies on the pages being mapped separately (e.g. by unmapping
+/// only some of them)
+///
+/// # Errors
+/// Fails if the page isn't mapped
+pub unsafe fn try_promote(virt_addr: VirtAddr) -> Result<bool, PagingError> {
+    let pml = get_pml();
+    // Find the size of the page before it's promoted, to know what to flush
+    let (_, sub_size) = pml
+        .get_entry(virt_addr)
+        .ok_or(PagingError::PageNotPresent(virt_addr))?;
+
+    let promoted = pml.try_promote(virt_addr)?;
+    if promoted {
+        let huge_size = sub_size.size() * ENTRIES_PER_TABLE;
+        let base = virt_addr.align_down(huge_size);
+        for i in 0..ENTRIES_PER_TABLE {
+            invlpg(base + i * sub_size.size());
+        }
+    }
+
+    Ok(promoted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::paging::pat::PatType;
+    use alloc::{boxed::Box, vec::Vec};
+    use core::ptr::from_mut;
+
+    const SIZE_2MB: usize = 0x0020_0000;
+
+    fn empty_table() -> Box<PageTable> {
+        Box::new(PageTable(core::array::from_fn(|_| Entry(0))))
+    }
+
+    /// Point entry 0 of `table` to `next`
+    fn link(table: &mut PageTable, next: &mut PageTable) {
+        table[0].set_addr(PhysAddr(from_mut(next).addr()), PageSize::size_4kb());
+        table[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+    }
+
+    /// Hand out `table` to be linked in by a split. The HHDM offset is 0 in tests, so its address
+    /// is its "physical" one
+    fn allocate(table: &mut PageTable) -> impl FnOnce() -> Result<PhysAddr, PagingError> {
+        let addr = PhysAddr(from_mut(table).addr());
+        move || Ok(addr)
+    }
+
+    #[test]
+    fn test_split_preserves_translations() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+        link(&mut pml4, &mut pdpt);
+        link(&mut pdpt, &mut pd);
+
+        let flags = Flags::new()
+            .set_read_write(true)
+            .set_pat(PatType::WriteCombining, PageSize::size_2mb());
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_2MB),
+                PhysAddr(4 * SIZE_2MB),
+                1,
+                PageSize::size_2mb(),
+                flags,
+            )
+            .unwrap();
+        };
+
+        let mut pt = empty_table();
+        pml4.split_huge_with(VirtAddr(SIZE_2MB + 0x5000), allocate(&mut pt))
+            .unwrap();
+
+        // 4KB pages can't be split any further
+        let mut unused = empty_table();
+        assert_eq!(
+            pml4.split_huge_with(VirtAddr(SIZE_2MB + 0x5000), allocate(&mut unused)),
+            Err(PagingError::InvalidPageSize)
+        );
+        assert_eq!(
+            pml4.split_huge_with(VirtAddr(0), allocate(&mut unused)),
+            Err(PagingError::PageNotPresent(VirtAddr(0)))
+        );
+
+        for offset in [0, 0x1000, 0x5000, SIZE_2MB - 0x1000] {
+            let (entry, page_size) = pml4.get_entry(VirtAddr(SIZE_2MB + offset)).unwrap();
+            assert_eq!(page_size, PageSize::size_4kb());
+            assert_eq!(entry.get_addr(page_size), PhysAddr(4 * SIZE_2MB + offset));
+
+            let sub_flags = entry.get_flags();
+            assert!(sub_flags.get_present() && sub_flags.get_read_write());
+            // The PAT type moved to the 4KB PAT bit (which is where the PS bit is in huge pages)
+            let expected = flags.set_pat(PatType::WriteCombining, PageSize::size_4kb());
+            assert_eq!(
+                sub_flags.data() & 0b1001_1000,
+                expected.data() & 0b1001_1000
+            );
+        }
+    }
+
+    #[test]
+    fn test_promote_round_trip() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+        link(&mut pml4, &mut pdpt);
+        link(&mut pdpt, &mut pd);
+
+        let flags = Flags::new().set_read_write(true).set_global(true);
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_2MB),
+                PhysAddr(6 * SIZE_2MB),
+                1,
+                PageSize::size_2mb(),
+                flags,
+            )
+            .unwrap();
+        };
+        let original = pd[1].0;
+
+        let mut pt = empty_table();
+        pml4.split_huge_with(VirtAddr(SIZE_2MB), allocate(&mut pt))
+            .unwrap();
+        let table_addr = pd[1].get_addr(PageSize::size_4kb());
+
+        // One page with different flags blocks the promotion
+        unsafe {
+            pml4.change_flags(
+                VirtAddr(SIZE_2MB + 0x3000),
+                1,
+                PageSize::size_4kb(),
+                flags.set_read_write(false),
+            )
+            .unwrap();
+        };
+        let mut freed = Vec::new();
+        assert_eq!(
+            pml4.try_promote_with(VirtAddr(SIZE_2MB), |addr| freed.push(addr)),
+            Ok(false)
+        );
+        assert_eq!(freed, []);
+
+        // Accessed/dirty bits don't block it, and are kept
+        unsafe {
+            pml4.change_flags(VirtAddr(SIZE_2MB + 0x3000), 1, PageSize::size_4kb(), flags)
+                .unwrap();
+        };
+        let dirty = pml4.get_entry(VirtAddr(SIZE_2MB + 0x7000)).unwrap().0;
+        dirty.set_flags(dirty.get_flags().set_accessed(true).set_dirty(true));
+
+        assert_eq!(
+            pml4.try_promote_with(VirtAddr(SIZE_2MB + 0x7000), |addr| freed.push(addr)),
+            Ok(true)
+        );
+        assert_eq!(freed, [table_addr]);
+
+        let (entry, page_size) = pml4.get_entry(VirtAddr(SIZE_2MB + 0x7000)).unwrap();
+        assert_eq!(page_size, PageSize::size_2mb());
+        let merged = Flags::<X86_64>::new().set_accessed(true).set_dirty(true);
+        assert_eq!(entry.0, original | merged.data());
+        assert_eq!(
+            pml4.translate(VirtAddr(SIZE_2MB)),
+            Some(PhysAddr(6 * SIZE_2MB))
+        );
+
+        // Promoting again would make a 1GB page out of the whole PD, which is mostly unmapped
+        assert_eq!(
+            pml4.try_promote_with(VirtAddr(SIZE_2MB), |addr| freed.push(addr)),
+            Ok(false)
+        );
+        assert_eq!(
+            pml4.try_promote_with(VirtAddr(0x4000_0000), |addr| freed.push(addr)),
+            Err(PagingError::PageNotPresent(VirtAddr(0x4000_0000)))
+        );
+        assert_eq!(freed.len(), 1);
+    }
+}
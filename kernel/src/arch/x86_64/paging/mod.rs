@@ -1,23 +1,22 @@
 use core::{
-    arch::asm,
-    arch::x86_64::__cpuid,
     fmt::Debug,
     ops::{Deref, DerefMut},
+    ptr,
 };
 
 use page_size::MAX_BOTTOM_PAGING_LEVEL;
 use pat::{PatType, setup_pat};
 use pmm::PmmAllocator;
 use utils::{
+    boot_info::{BootInfo, MemoryRegion, MemoryRegionKind},
+    collections::arrayvec::ArrayVec,
     mem::{PhysAddr, VirtAddr},
     sanity_assert,
 };
 
-#[cfg(feature = "limine")]
-use limine::memory_map::{self, EntryType};
 use utils::mem::memset;
 
-use crate::mem::paging::{Flags, PageSize, PagingError};
+use crate::mem::paging::{Flags, PageSize, PagingError, RESIDENT_PAGES};
 
 use super::{
     X86_64,
@@ -27,13 +26,21 @@ use super::{
     },
 };
 
+pub mod demand_zero;
 pub mod flags;
+pub mod huge;
 pub mod page_size;
 pub mod pat;
+mod phys_check;
+pub mod recursive;
 
 /// The number of entries per page table
 pub const ENTRIES_PER_TABLE: usize = 512;
 
+/// The most page tables a single `map_scatter` call can create, since it keeps track of them to
+/// free them if it fails
+pub const MAX_SCATTER_TABLES: usize = 64;
+
 /// An entry in a page table
 #[repr(C)]
 #[derive(Debug)]
@@ -103,57 +110,110 @@ impl Entry {
         }
     }
 
-    /// Immediately maps the entry to the given physical address with the given flags.
+    /// Immediately maps the entry, which is used for `virt_addr`, to the given physical address
+    /// with the given flags.
     unsafe fn map(
         &mut self,
+        virt_addr: VirtAddr,
         phys_addr: PhysAddr,
         flags: Flags<X86_64>,
         page_size: PageSize<X86_64>,
     ) -> Result<(), PagingError> {
-        if self.get_flags().get_present() {
-            return Err(PagingError::PageAlreadyPresent);
+        // A demand-zero entry is already taken, even if it isn't present yet
+        let old_flags = self.get_flags();
+        if old_flags.get_present() || old_flags.get_demand_zero() {
+            return Err(PagingError::PageAlreadyPresent(virt_addr));
         }
 
-        self.set_addr(phys_addr, page_size);
-        self.set_flags(unsafe {
+        // Making sure the flags are valid before touching the entry, so it's left untouched on error
+        let flags = unsafe {
             flags
                 .set_present(true)
                 .set_last_entry(true)
                 .join(page_size.into())
                 .ok_or(PagingError::InvalidFlags)?
-        });
+        };
+
+        self.set_addr(phys_addr, page_size);
+        self.set_flags(flags);
 
         Ok(())
     }
 
-    /// Marks the entry as not present and frees the physical page if the entry was activated not
-    /// manually (ie. activated using a call to `activate`).
-    fn release(&mut self, page_size: PageSize<X86_64>) -> Result<(), PagingError> {
+    /// Clears the entry entirely, without freeing the physical page it maps.
+    ///
+    /// Used to undo a `map` of a page that is still owned by someone else.
+    #[inline]
+    const fn clear(&mut self) {
+        self.0 = 0;
+    }
+
+    /// Marks the entry (used for `virt_addr`) as not present and frees the physical page if the
+    /// entry was activated not manually (ie. activated using a call to `activate`).
+    ///
+    /// Returns the amount of 4KB frames the address space no longer owns, which is 0 unless a
+    /// present page with the allocated bit was released
+    fn release(
+        &mut self,
+        virt_addr: VirtAddr,
+        page_size: PageSize<X86_64>,
+    ) -> Result<usize, PagingError> {
+        self.release_with(virt_addr, page_size, |phys_addr| unsafe {
+            pmm::get().free(phys_addr, 1).expect("Failed to free page");
+        })
+    }
+
+    /// Like `release`, but frees the physical page using `free_frame`
+    fn release_with(
+        &mut self,
+        virt_addr: VirtAddr,
+        page_size: PageSize<X86_64>,
+        free_frame: impl FnOnce(PhysAddr),
+    ) -> Result<usize, PagingError> {
         // XXX: need to determine page size here for freeing
         let flags = self.get_flags();
-        if !self.get_flags().get_present() {
-            return Err(PagingError::PageNotPresent);
+        if flags.get_demand_zero() && !flags.get_present() {
+            // The page was never accessed, so there is no frame to free
+            self.clear();
+            demand_zero::release_reserved();
+            return Ok(0);
+        } else if !flags.get_present() {
+            return Err(PagingError::PageNotPresent(virt_addr));
         }
 
+        let mut released = 0;
         if flags.get_allocated() {
-            let phys_addr = self.get_addr(page_size);
-            unsafe {
-                pmm::get().free(phys_addr, 1).expect("Failed to free page");
-            }
+            free_frame(self.get_addr(page_size));
+            released = page_size.to_default_page_count();
         }
 
-        self.set_flags(flags.set_present(false));
+        if flags.get_demand_zero() {
+            demand_zero::release_committed();
+        }
 
-        Ok(())
+        self.set_flags(flags.set_present(false).set_demand_zero(false));
+
+        Ok(released)
     }
 }
 
 impl PageTable {
     /// Allocates a new page table
+    ///
+    /// # Panics
+    /// Panics if there is no physical memory left for the table
     pub fn new() -> (&'static mut Self, PhysAddr) {
+        Self::try_new().expect("Failed to allocate page table")
+    }
+
+    /// Tries to allocate a new page table
+    ///
+    /// # Errors
+    /// Fails with `PagingError::OutOfMemory` if there is no physical memory left for the table
+    pub fn try_new() -> Result<(&'static mut Self, PhysAddr), PagingError> {
         let phys_addr = pmm::get()
             .allocate(PageSize::size_4kb().page_alignment(), 1)
-            .expect("Failed to allocate page table");
+            .map_err(|_| PagingError::OutOfMemory)?;
 
         // For easier bootstrapping, we are HHDM mapping all page tables
         let ptr: *mut u8 = core::ptr::without_provenance_mut(phys_addr.add_hhdm_offset().0);
@@ -162,10 +222,10 @@ impl PageTable {
             memset(ptr, 0, size_of::<PageTable>());
         };
 
-        (
+        Ok((
             unsafe { ptr.cast::<PageTable>().as_mut().unwrap() },
             phys_addr,
-        )
+        ))
     }
 
     /// Tries to get a reference to the `Entry` associated with the given virtual address.
@@ -196,18 +256,47 @@ impl PageTable {
 
     /// Gets the parent page table of the given `base_addr`.
     ///
-    /// If one of the page tables are missing during translation, a new page table is created.
-    #[must_use]
+    /// If one of the page tables are missing during translation, a new page table is created. If
+    /// a table can't be allocated, the tables created so far are freed and `PagingError::OutOfMemory`
+    /// is returned
     fn get_create_table_range(
         &mut self,
         base_addr: VirtAddr,
         page_size: PageSize<X86_64>,
-    ) -> &mut PageTable {
+    ) -> Result<&mut PageTable, PagingError> {
+        self.get_create_table_range_with(
+            base_addr,
+            page_size,
+            || PageTable::try_new().map(|(_, phys_addr)| phys_addr),
+            |phys_addr| unsafe {
+                pmm::get()
+                    .free(phys_addr, 1)
+                    .expect("Failed to free page table");
+            },
+        )
+    }
+
+    /// Same as `get_create_table_range`, but the missing tables are allocated using
+    /// `allocate_table` (which has to hand out zeroed tables), and freed on failure using
+    /// `free_table`
+    fn get_create_table_range_with(
+        &mut self,
+        base_addr: VirtAddr,
+        page_size: PageSize<X86_64>,
+        mut allocate_table: impl FnMut() -> Result<PhysAddr, PagingError>,
+        mut free_table: impl FnMut(PhysAddr),
+    ) -> Result<&mut PageTable, PagingError> {
         sanity_assert!(
-            base_addr.0 % page_size.size() == 0,
+            base_addr.is_aligned(page_size.size()),
             "Address is not aligned"
         );
 
+        // The first entry we linked a new table in, and the tables we created. Every table after
+        // the first one is linked in the one before it, so clearing that entry unlinks them all
+        let mut first_linked: Option<*mut Entry> = None;
+        let mut created = [PhysAddr(0); MAX_BOTTOM_PAGING_LEVEL];
+        let mut created_count = 0;
+
         // A page table entry addresses are stored in descending order: (|PML5|PML4|PDPT|PDE|PTE|offset|)
         //
         // We start from the highest level, and go down. In our case we want to return the last
@@ -217,15 +306,32 @@ impl PageTable {
             let i = next_level_index(base_addr, level);
             let flags = table[i].get_flags();
             if !flags.get_present() {
+                let phys_addr = match allocate_table() {
+                    Ok(phys_addr) => phys_addr,
+                    Err(err) => {
+                        if let Some(entry) = first_linked {
+                            unsafe { (*entry).clear() };
+                        }
+                        for &phys_addr in &created[..created_count] {
+                            free_table(phys_addr);
+                        }
+
+                        return Err(err);
+                    }
+                };
+
                 table[i].set_flags(flags.set_present(true).set_read_write(true));
-                let (_, phys_addr) = PageTable::new();
                 table[i].set_addr(phys_addr, PageSize::size_4kb());
+
+                first_linked.get_or_insert(&raw mut table[i]);
+                created[created_count] = phys_addr;
+                created_count += 1;
             }
 
             table = table[i].next_level_table();
         }
 
-        table
+        Ok(table)
     }
 
     /// Tries to get the parent table of the given `base_addr`.
@@ -238,7 +344,7 @@ impl PageTable {
         page_size: PageSize<X86_64>,
     ) -> Option<&mut PageTable> {
         sanity_assert!(
-            base_addr.0 % page_size.size() == 0,
+            base_addr.is_aligned(page_size.size()),
             "Address is not aligned"
         );
 
@@ -259,7 +365,10 @@ impl PageTable {
         Some(table)
     }
 
-    /// Maps the given virtual address to the given physical address
+    /// Maps the given virtual address to the given physical address.
+    ///
+    /// If a missing page table can't be allocated, `PagingError::OutOfMemory` is returned and the
+    /// tables that were created for the mapping are freed
     pub unsafe fn map_pages(
         &mut self,
         base_addr: VirtAddr,
@@ -268,14 +377,17 @@ impl PageTable {
         page_size: PageSize<X86_64>,
         flags: Flags<X86_64>,
     ) -> Result<(), PagingError> {
-        if base_addr.0 % page_size.size() != 0 {
-            return Err(PagingError::InvalidVirtualAddress);
-        } else if phys_addr.0 % page_size.size() != 0 {
-            return Err(PagingError::InvalidPhysicalAddress);
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        } else if !phys_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedPhysicalAddress(phys_addr));
         }
 
+        #[cfg(debug_assertions)]
+        phys_check::validate(phys_addr, page_count * page_size.size());
+
         // Get the parent page table
-        let table = self.get_create_table_range(base_addr, page_size);
+        let table = self.get_create_table_range(base_addr, page_size)?;
 
         // Extract the index to the entry
         let to_skip = next_level_index(base_addr, page_size.bottom_paging_level());
@@ -283,15 +395,179 @@ impl PageTable {
             return Err(PagingError::BadPageCountAndAddressCombination);
         }
 
-        for (i, entry) in table.iter_mut().skip(to_skip).take(page_count).enumerate() {
-            unsafe {
-                entry.map(phys_addr + (i * page_size.size()), flags, page_size)?;
+        for i in 0..page_count {
+            let res = unsafe {
+                table[to_skip + i].map(
+                    base_addr + (i * page_size.size()),
+                    phys_addr + (i * page_size.size()),
+                    flags,
+                    page_size,
+                )
             };
+
+            if let Err(err) = res {
+                // Undo the mappings we've made so far, so the address space is left unchanged.
+                //
+                // NOTE: We only clear the entries and don't free the physical pages, since the
+                // caller still owns them. The entries weren't present before, so there is nothing
+                // to flush from the TLB either.
+                for entry in &mut table[to_skip..to_skip + i] {
+                    entry.clear();
+                }
+
+                return Err(err);
+            }
+        }
+
+        if flags.get_allocated() {
+            RESIDENT_PAGES.add(
+                ptr::from_ref(self).addr(),
+                page_count * page_size.to_default_page_count(),
+            );
         }
 
         Ok(())
     }
 
+    /// Maps each of `frames` to the next page of a contiguous virtual range starting at
+    /// `base_addr` (e.g. for a DMA buffer made of scattered physical pages).
+    ///
+    /// Unlike `map_pages`, the range may cross page tables. If any of the frames can't be mapped,
+    /// the ones mapped so far are unmapped again (still owned by the caller) and the tables
+    /// created for them are freed, so the address space is left unchanged. For that, at most
+    /// `MAX_SCATTER_TABLES` tables can be created, and `PagingError::OutOfMemory` is returned
+    /// past that.
+    ///
+    /// Returns the amount of pages mapped
+    pub unsafe fn map_scatter(
+        &mut self,
+        base_addr: VirtAddr,
+        frames: impl IntoIterator<Item = PhysAddr>,
+        page_size: PageSize<X86_64>,
+        flags: Flags<X86_64>,
+    ) -> Result<usize, PagingError> {
+        unsafe {
+            self.map_scatter_with(
+                base_addr,
+                frames,
+                page_size,
+                flags,
+                || PageTable::try_new().map(|(_, phys_addr)| phys_addr),
+                |phys_addr| {
+                    pmm::get()
+                        .free(phys_addr, 1)
+                        .expect("Failed to free page table");
+                },
+            )
+        }
+    }
+
+    /// Same as `map_scatter`, but the missing tables are allocated using `allocate_table` (which
+    /// has to hand out zeroed tables), and freed on failure using `free_table`
+    unsafe fn map_scatter_with(
+        &mut self,
+        base_addr: VirtAddr,
+        frames: impl IntoIterator<Item = PhysAddr>,
+        page_size: PageSize<X86_64>,
+        flags: Flags<X86_64>,
+        mut allocate_table: impl FnMut() -> Result<PhysAddr, PagingError>,
+        mut free_table: impl FnMut(PhysAddr),
+    ) -> Result<usize, PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        // The tables created for the mapping, so they can be freed if it fails
+        let mut created = ArrayVec::<PhysAddr, MAX_SCATTER_TABLES>::new();
+        let mut mapped = 0;
+        for frame in frames {
+            let virt_addr = base_addr + (mapped * page_size.size());
+            let res = if frame.is_aligned(page_size.size()) {
+                #[cfg(debug_assertions)]
+                phys_check::validate(frame, page_size.size());
+
+                self.get_create_table_range_with(
+                    virt_addr,
+                    page_size,
+                    || {
+                        if created.is_full() {
+                            return Err(PagingError::OutOfMemory);
+                        }
+                        let phys_addr = allocate_table()?;
+                        let _ = created.push(phys_addr);
+
+                        Ok(phys_addr)
+                    },
+                    // Freed along with the rest of the created tables below
+                    |_| {},
+                )
+                .and_then(|table| unsafe {
+                    table[next_level_index(virt_addr, page_size.bottom_paging_level())]
+                        .map(virt_addr, frame, flags, page_size)
+                })
+            } else {
+                Err(PagingError::UnalignedPhysicalAddress(frame))
+            };
+
+            if let Err(err) = res {
+                for i in 0..mapped {
+                    // NOTE: Like in `map_pages`, the frames stay with the caller and there is
+                    // nothing to flush from the TLB
+                    unsafe {
+                        self.unmap_keep_frame(base_addr + (i * page_size.size()), 1, page_size)
+                    }
+                    .expect("Failed to undo a mapping we just made");
+                }
+
+                // Every created table is either linked in another created table, or in an entry
+                // that wasn't present before, which is cleared
+                if !created.is_empty() {
+                    for i in 0..=mapped {
+                        self.unlink_tables(base_addr + (i * page_size.size()), page_size, &created);
+                    }
+                }
+                for &phys_addr in &created {
+                    free_table(phys_addr);
+                }
+
+                return Err(err);
+            }
+
+            // Counted right away, since undoing the mapping on failure uncounts it
+            if flags.get_allocated() {
+                RESIDENT_PAGES.add(
+                    ptr::from_ref(self).addr(),
+                    page_size.to_default_page_count(),
+                );
+            }
+            mapped += 1;
+        }
+
+        Ok(mapped)
+    }
+
+    /// Clears the first entry on the translation of `virt_addr` that links one of `tables`, which
+    /// unlinks all the tables below it as well
+    fn unlink_tables(
+        &mut self,
+        virt_addr: VirtAddr,
+        page_size: PageSize<X86_64>,
+        tables: &[PhysAddr],
+    ) {
+        let mut table = self;
+        for level in (page_size.bottom_paging_level() + 1..=MAX_BOTTOM_PAGING_LEVEL).rev() {
+            let i = next_level_index(virt_addr, level);
+            if !table[i].get_flags().get_present() {
+                return;
+            } else if tables.contains(&table[i].get_addr(PageSize::size_4kb())) {
+                table[i].clear();
+                return;
+            }
+
+            table = table[i].next_level_table();
+        }
+    }
+
     /// Unmaps the given virtual address range
     pub(super) unsafe fn unmap_pages(
         &mut self,
@@ -299,42 +575,223 @@ impl PageTable {
         page_count: usize,
         page_size: PageSize<X86_64>,
     ) -> Result<(), PagingError> {
-        if base_addr.0 % page_size.size() != 0 {
-            return Err(PagingError::InvalidVirtualAddress);
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
         }
 
+        let root = ptr::from_ref(self).addr();
         let table = self
             .get_table_range(base_addr, page_size)
-            .ok_or(PagingError::PageNotPresent)?;
+            .ok_or(PagingError::PageNotPresent(base_addr))?;
 
         let to_skip = next_level_index(base_addr, page_size.bottom_paging_level());
         if to_skip + page_count > ENTRIES_PER_TABLE {
             return Err(PagingError::BadPageCountAndAddressCombination);
         }
 
-        for entry in table.iter_mut().skip(to_skip).take(page_count) {
-            entry.release(page_size)?;
+        for (i, entry) in table.iter_mut().skip(to_skip).take(page_count).enumerate() {
+            let released = entry.release(base_addr + (i * page_size.size()), page_size)?;
+            RESIDENT_PAGES.sub(root, released);
         }
 
         Ok(())
     }
 
-    /// Get the physical address associated with the given virtual address.
+    /// Unmaps the given virtual address range like `unmap_pages`, but leaves the physical pages
+    /// allocated, handing them over to the caller (e.g. to map them somewhere else).
+    ///
+    /// Returns the physical address the first page was mapped to. The pages must be mapped to
+    /// contiguous frames, so that address covers all of them.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it
+    pub(super) unsafe fn unmap_keep_frame(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<X86_64>,
+    ) -> Result<PhysAddr, PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        let root = ptr::from_ref(self).addr();
+        let table = self
+            .get_table_range(base_addr, page_size)
+            .ok_or(PagingError::PageNotPresent(base_addr))?;
+
+        let to_skip = next_level_index(base_addr, page_size.bottom_paging_level());
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        // Check the entire range first, so the table is left untouched on error. A demand-zero page
+        // that was never accessed has no frame to hand over either
+        let entries = &mut table[to_skip..to_skip + page_count];
+        if let Some(missing) = entries
+            .iter()
+            .position(|entry| !entry.get_flags().get_present())
+        {
+            return Err(PagingError::PageNotPresent(
+                base_addr + missing * page_size.size(),
+            ));
+        }
+        let phys_addr = entries
+            .first()
+            .ok_or(PagingError::PageNotPresent(base_addr))?
+            .get_addr(page_size);
+        // Only the first frame is handed back, so the rest have to follow it
+        if let Some(scattered) = entries
+            .iter()
+            .enumerate()
+            .position(|(i, entry)| entry.get_addr(page_size) != phys_addr + i * page_size.size())
+        {
+            return Err(PagingError::NonContiguousFrames(
+                base_addr + scattered * page_size.size(),
+            ));
+        }
+
+        for (i, entry) in entries.iter_mut().enumerate() {
+            // The caller owns the frames from now on, so they aren't freed
+            let released =
+                entry.release_with(base_addr + (i * page_size.size()), page_size, |_| {})?;
+            RESIDENT_PAGES.sub(root, released);
+        }
+
+        Ok(phys_addr)
+    }
+
+    /// Replaces the flags of the given mapped virtual address range, keeping the physical pages
+    /// they're mapped to.
+    ///
+    /// NOTE: This doesn't flush the TLB, so the caller has to do it
+    pub(super) unsafe fn change_flags(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<X86_64>,
+        flags: Flags<X86_64>,
+    ) -> Result<(), PagingError> {
+        unsafe {
+            self.change_flags_with(
+                base_addr,
+                page_count,
+                page_size,
+                flags,
+                || {
+                    pmm::get()
+                        .allocate(PageSize::size_4kb().page_alignment(), 1)
+                        .map_err(|_| PagingError::OutOfMemory)
+                },
+                |table_addr| {
+                    pmm::get()
+                        .free(table_addr, 1)
+                        .expect("Failed to free page table");
+                },
+            )
+        }
+    }
+
+    /// Same as `change_flags`, but splitting huge pages with tables allocated using
+    /// `allocate_table`, and freeing them using `free_table` if the range can't be changed.
+    unsafe fn change_flags_with(
+        &mut self,
+        base_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<X86_64>,
+        flags: Flags<X86_64>,
+        mut allocate_table: impl FnMut() -> Result<PhysAddr, PagingError>,
+        mut free_table: impl FnMut(PhysAddr),
+    ) -> Result<(), PagingError> {
+        if !base_addr.is_aligned(page_size.size()) {
+            return Err(PagingError::UnalignedVirtualAddress(base_addr));
+        }
+
+        let to_skip = next_level_index(base_addr, page_size.bottom_paging_level());
+        if to_skip + page_count > ENTRIES_PER_TABLE {
+            return Err(PagingError::BadPageCountAndAddressCombination);
+        }
+
+        let flags = unsafe {
+            flags
+                .set_present(true)
+                .set_last_entry(true)
+                .join(page_size.into())
+                .ok_or(PagingError::InvalidFlags)?
+        };
+
+        // The range is part of a bigger page, which has to be split first. The range doesn't cross
+        // a table, so it's all in that one page (and so it's all present)
+        let mut splits = 0;
+        while let Some((_, mapped_size)) = self.get_entry(base_addr)
+            && mapped_size.size() > page_size.size()
+        {
+            if let Err(err) = self.split_huge_with(base_addr, &mut allocate_table) {
+                // Merge the pages split so far back, so the table is left untouched on error
+                for _ in 0..splits {
+                    self.try_promote_with(base_addr, &mut free_table)?;
+                }
+                return Err(err);
+            }
+            splits += 1;
+        }
+
+        let table = self
+            .get_table_range(base_addr, page_size)
+            .ok_or(PagingError::PageNotPresent(base_addr))?;
+
+        // Check the entire range first, so the table is left untouched on error
+        let entries = &mut table[to_skip..to_skip + page_count];
+        if let Some(missing) = entries
+            .iter()
+            .position(|entry| !entry.get_flags().get_present())
+        {
+            return Err(PagingError::PageNotPresent(
+                base_addr + missing * page_size.size(),
+            ));
+        }
+
+        for entry in entries {
+            // Whether we own the page doesn't change
+            let allocated = entry.get_flags().get_allocated();
+            entry.set_flags(flags.set_allocated(allocated));
+        }
+
+        Ok(())
+    }
+
+    /// Get the amount of 4KB frames the address space this is the top level table of owns, which
+    /// are freed when they're unmapped.
+    ///
+    /// Frames that are only mapped (e.g. MMIO, or frames owned by someone else) aren't counted.
+    #[must_use]
+    pub(super) fn resident_pages(&self) -> usize {
+        RESIDENT_PAGES.get(ptr::from_ref(self).addr())
+    }
+
+    /// Get the physical address associated with the given virtual address, keeping its offset
+    /// within the page it's in (which might be a 2MB or 1GB page).
     ///
     /// If the virtual address is not mapped, `None` is returned.
     #[must_use]
-    pub(super) fn translate(&mut self, base_addr: VirtAddr) -> Option<PhysAddr> {
-        let (entry, page_size) = self.get_entry(base_addr)?;
+    pub(super) fn translate(&mut self, virt_addr: VirtAddr) -> Option<PhysAddr> {
+        let (entry, page_size) = self.get_entry(virt_addr)?;
 
         let flags = entry.get_flags();
         if !flags.get_present() {
             return None;
         }
 
-        Some(entry.get_addr(page_size))
+        Some(entry.get_addr(page_size) + (virt_addr.0 & page_size.get_offset_mask()))
     }
 }
 
+/// Get the amount of 4KB frames the current address space owns, which are freed when they're
+/// unmapped
+#[must_use]
+pub fn resident_pages() -> usize {
+    get_pml().resident_pages()
+}
+
 /// Get the top level paging table PML4/PML5 (depending on the paging level)
 pub(super) fn get_pml() -> &'static mut PageTable {
     let phys_addr = unsafe { PhysAddr((Cr3::read().top_pml() << 12) as usize) };
@@ -350,6 +807,19 @@ const fn next_level_index(addr: VirtAddr, level: usize) -> usize {
     (addr.0 >> (PageSize::size_4kb().offset_bit_count() + (level * 9))) & 0b1_1111_1111
 }
 
+/// Set the global bit of `flags` if `virt_addr` is in the kernel's (higher) half of the address
+/// space, and clear it otherwise.
+///
+/// The kernel's half is mapped the same in every address space, so its TLB entries can survive
+/// CR3 reloads. The lower half changes with the address space, so its entries must not.
+#[inline]
+#[must_use]
+fn with_global_for(virt_addr: VirtAddr, flags: Flags<X86_64>) -> Flags<X86_64> {
+    // Canonical higher half addresses have all the bits above the used ones set, so the top bit
+    // tells the halves apart with both 4 and 5 level paging
+    flags.set_global(virt_addr.0 & (1 << 63) != 0)
+}
+
 /// Helper function to avoid code duplication.
 ///
 /// Maps in the given virtual address to the given
@@ -361,21 +831,28 @@ fn map_in_entry(
     mut flags: Flags<X86_64>,
     pat: Option<PatType>,
 ) {
+    // The kernel's mappings are shared by every address space
+    flags = with_global_for(base_virt_addr, flags);
+
+    // Not every CPU supports 1GB pages
+    let has_1gb = PageSize::supported().any(|page_size| page_size == PageSize::size_1gb());
+
     // Breaking into pages and allocating
     while total_size != 0 {
-        let page_size = if total_size >= PageSize::size_1gb().size()
-            && base_phys_addr.0 % PageSize::size_1gb().size() == 0
-            && base_virt_addr.0 % PageSize::size_1gb().size() == 0
+        let page_size = if has_1gb
+            && total_size >= PageSize::size_1gb().size()
+            && base_phys_addr.is_aligned(PageSize::size_1gb().size())
+            && base_virt_addr.is_aligned(PageSize::size_1gb().size())
         {
             PageSize::size_1gb()
         } else if total_size >= PageSize::size_2mb().size()
-            && base_phys_addr.0 % PageSize::size_2mb().size() == 0
-            && base_virt_addr.0 % PageSize::size_2mb().size() == 0
+            && base_phys_addr.is_aligned(PageSize::size_2mb().size())
+            && base_virt_addr.is_aligned(PageSize::size_2mb().size())
         {
             PageSize::size_2mb()
         } else if total_size >= PageSize::size_4kb().size()
-            && base_phys_addr.0 % PageSize::size_4kb().size() == 0
-            && base_virt_addr.0 % PageSize::size_4kb().size() == 0
+            && base_phys_addr.is_aligned(PageSize::size_4kb().size())
+            && base_virt_addr.is_aligned(PageSize::size_4kb().size())
         {
             PageSize::size_4kb()
         } else {
@@ -399,13 +876,7 @@ fn map_in_entry(
     }
 }
 
-#[cfg(feature = "limine")]
-pub(super) unsafe fn init_from_limine(
-    mem_map: &[&memory_map::Entry],
-    kernel_virt: VirtAddr,
-    kernel_phys: PhysAddr,
-    used_by_pmm: &memory_map::Entry,
-) {
+pub(super) unsafe fn init(boot_info: &BootInfo, used_by_pmm: &MemoryRegion) {
     // TODO: CPUID check as well
     // #[cfg(feature = "paging_5")]
     // if read_cr!(cr4) & (1 << 12) != 0 {
@@ -414,46 +885,63 @@ pub(super) unsafe fn init_from_limine(
 
     let (new_pml, new_pml_addr) = PageTable::new();
 
+    #[cfg(feature = "recursive_paging")]
+    new_pml.install_recursive(new_pml_addr);
+
     map_in_entry(
-        PhysAddr(used_by_pmm.base as usize).add_hhdm_offset(),
-        PhysAddr(used_by_pmm.base as usize),
-        used_by_pmm.length as usize,
+        used_by_pmm.base.add_hhdm_offset(),
+        used_by_pmm.base,
+        used_by_pmm.length,
         new_pml,
         Flags::new().set_read_write(true),
         None,
     );
 
-    // NOTE: We are doing the `EXECUTABLE_AND_MODULES` mapping independently of the other sections,
-    // since the kernel's view of this section is different than HHDM (even though this memory is
-    // also HHDM mapped IIRC)
-    for entry in mem_map
+    for region in boot_info
+        .memory_map()
         .iter()
-        .filter(|entry| entry.base != used_by_pmm.base)
+        .filter(|region| region.base != used_by_pmm.base)
     {
-        match entry.entry_type {
-            EntryType::EXECUTABLE_AND_MODULES => map_in_entry(
-                kernel_virt,
-                kernel_phys,
-                entry.length as usize,
-                new_pml,
-                Flags::new().set_read_write(true),
-                None,
-            ),
-            EntryType::ACPI_RECLAIMABLE | EntryType::BOOTLOADER_RECLAIMABLE | EntryType::USABLE => {
+        match region.kind {
+            MemoryRegionKind::ExecutableAndModules => {
+                // NOTE: The kernel runs from its own virtual addresses rather than the HHDM, so the
+                // region holding it is mapped there as well
+                if region.base == boot_info.kernel_phys() {
+                    map_in_entry(
+                        boot_info.kernel_virt(),
+                        boot_info.kernel_phys(),
+                        region.length,
+                        new_pml,
+                        Flags::new().set_read_write(true),
+                        None,
+                    );
+                }
+
+                // Boot modules are accessed through the HHDM (see `BootModule::data`)
                 map_in_entry(
-                    PhysAddr(entry.base as usize).add_hhdm_offset(),
-                    PhysAddr(entry.base as usize),
-                    entry.length as usize,
+                    region.base.add_hhdm_offset(),
+                    region.base,
+                    region.length,
                     new_pml,
                     Flags::new().set_read_write(true),
                     None,
-                )
+                );
             }
+            MemoryRegionKind::AcpiReclaimable
+            | MemoryRegionKind::BootloaderReclaimable
+            | MemoryRegionKind::Usable => map_in_entry(
+                region.base.add_hhdm_offset(),
+                region.base,
+                region.length,
+                new_pml,
+                Flags::new().set_read_write(true),
+                None,
+            ),
             #[cfg(feature = "framebuffer")]
-            EntryType::FRAMEBUFFER => map_in_entry(
-                PhysAddr(entry.base as usize).add_hhdm_offset(),
-                PhysAddr(entry.base as usize),
-                entry.length as usize,
+            MemoryRegionKind::Framebuffer => map_in_entry(
+                region.base.add_hhdm_offset(),
+                region.base,
+                region.length,
                 new_pml,
                 Flags::new().set_read_write(true),
                 Some(PatType::WriteCombining),
@@ -467,62 +955,41 @@ pub(super) unsafe fn init_from_limine(
     logger::info!("Paging system initialized successfully");
 }
 
-/// Check if the CPU supports Paging Global Enable (PGE).
-#[inline]
-fn check_pge_support() {
-    const PGE_BIT: u32 = 1 << 12;
-
-    unsafe {
-        // TODO: possibly handle the case this isn't supported?
-        assert!(
-            __cpuid(1).edx & PGE_BIT != 0,
-            "Paging Global Enable (PGE) is not supported by the CPU"
-        );
-    }
-}
-
-/// Check if the CPU supports NX (No-Execute) bit.
+/// Flush the translation of the page containing `addr` from the TLB
 #[inline]
-fn check_nx_support() {
-    const NX_BIT: u32 = 1 << 20;
-
+pub(super) fn invlpg(addr: VirtAddr) {
+    #[cfg(not(test))]
     unsafe {
-        assert!(
-            __cpuid(0x8000_0001).edx & NX_BIT != 0,
-            "No-Execute (NX) bit is not supported by the CPU"
-        );
-    }
-}
-
-#[inline]
-fn invlpg(addr: VirtAddr) {
-    unsafe {
-        asm!(
+        core::arch::asm!(
             "invlpg [{}]",
             in(reg) addr.0,
             options(nostack, nomem, preserves_flags),
         );
     }
+    #[cfg(test)]
+    tests::record_invlpg(addr);
 }
 
 // TODO: Take care of PCIDs
+/// Flush all the non global translations from the TLB, by reloading CR3
 #[inline]
-fn flush_tlb() {
+pub(super) fn flush_tlb() {
+    #[cfg(not(test))]
     unsafe {
-        asm!(
+        core::arch::asm!(
             "mov {0}, cr3",
             "mov cr3, {0}",
             out(reg) _,
-                  options(nostack, preserves_flags),
+            options(nostack, preserves_flags),
         )
     }
+    #[cfg(test)]
+    tests::record_flush_tlb();
 }
 
 /// Enable the No-Execute (NX) bit in the EFER MSR.
 #[inline]
 fn enable_nx() {
-    check_nx_support();
-
     let mut efer: u64 = unsafe { rdmsr(AmdMsr::Efer).into() };
     efer |= Efer::NX;
     unsafe { wrmsr(AmdMsr::Efer, efer.into()) };
@@ -533,8 +1000,7 @@ fn enable_nx() {
 unsafe fn finalize_init(pml_phys_addr: PhysAddr) {
     // TODO: Make sure CRs flags are OK
     unsafe {
-        // Check to make sure the features we want to enable are supported
-        check_pge_support();
+        // NOTE: PGE, PAT and NX are required features, so they were already checked for
         setup_pat();
         enable_nx();
 
@@ -567,3 +1033,673 @@ impl DerefMut for PageTable {
 // possibly TODO:
 // PCIDs
 // SMEP/SMAP
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::paging::PagingManager;
+    use alloc::vec::Vec;
+    use core::{cell::Cell, ptr::from_mut};
+
+    const SIZE_2MB: usize = 0x0020_0000;
+    const SIZE_1GB: usize = 0x4000_0000;
+
+    extern crate std;
+
+    std::thread_local! {
+        /// The privileged TLB instructions can't run in tests, so their wrappers record the calls
+        /// here instead. The records are per thread, so tests that flush in parallel don't see
+        /// each other's calls
+        static INVLPG_CALLS: Cell<usize> = const { Cell::new(0) };
+        static LAST_INVLPG: Cell<usize> = const { Cell::new(0) };
+        static FLUSH_TLB_CALLS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub(super) fn record_invlpg(addr: VirtAddr) {
+        LAST_INVLPG.set(addr.0);
+        INVLPG_CALLS.set(INVLPG_CALLS.get() + 1);
+    }
+
+    pub(super) fn record_flush_tlb() {
+        FLUSH_TLB_CALLS.set(FLUSH_TLB_CALLS.get() + 1);
+    }
+
+    #[test]
+    fn test_global_bit_only_for_kernel_half() {
+        let flags = Flags::new().set_read_write(true);
+
+        for kernel_addr in [0xffff_8000_0000_0000, 0xffff_ffff_8000_0000] {
+            let kernel_flags = with_global_for(VirtAddr(kernel_addr), flags);
+            assert!(kernel_flags.get_global());
+            assert!(kernel_flags.get_read_write());
+        }
+
+        // Even if the flags asked for it
+        for user_addr in [0x1000, 0x0000_7fff_ffff_f000] {
+            assert!(!with_global_for(VirtAddr(user_addr), flags).get_global());
+            assert!(!with_global_for(VirtAddr(user_addr), flags.set_global(true)).get_global());
+        }
+    }
+
+    #[test]
+    fn test_flush_forwards_to_instructions() {
+        let invlpg_calls = INVLPG_CALLS.get();
+        X86_64::flush_address(VirtAddr(0xffff_8000_1234_5000));
+        assert_eq!(INVLPG_CALLS.get(), invlpg_calls + 1);
+        assert_eq!(LAST_INVLPG.get(), 0xffff_8000_1234_5000);
+
+        let flush_tlb_calls = FLUSH_TLB_CALLS.get();
+        X86_64::flush_all();
+        assert_eq!(FLUSH_TLB_CALLS.get(), flush_tlb_calls + 1);
+    }
+
+    #[test]
+    fn test_change_flags() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let pdpt_ptr = from_mut(&mut pdpt);
+
+        pml4[0].set_addr(PhysAddr(pdpt_ptr.addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_1GB),
+                PhysAddr(SIZE_1GB),
+                2,
+                PageSize::size_1gb(),
+                Flags::new().set_read_write(true),
+            )
+            .unwrap();
+        };
+
+        // The range reaches an unmapped page, so nothing should change
+        let res =
+            unsafe { pml4.change_flags(VirtAddr(SIZE_1GB), 3, PageSize::size_1gb(), Flags::new()) };
+        assert_eq!(
+            res,
+            Err(PagingError::PageNotPresent(VirtAddr(3 * SIZE_1GB)))
+        );
+        let pdpt = unsafe { &mut *pdpt_ptr };
+        assert!(pdpt[1].get_flags().get_read_write());
+
+        unsafe {
+            pml4.change_flags(VirtAddr(SIZE_1GB), 2, PageSize::size_1gb(), Flags::new())
+                .unwrap();
+        };
+        let pdpt = unsafe { &*pdpt_ptr };
+        for i in 1..3 {
+            let flags = pdpt[i].get_flags();
+            assert!(flags.get_present());
+            assert!(!flags.get_read_write());
+            assert_eq!(
+                pdpt[i].get_addr(PageSize::size_1gb()),
+                PhysAddr(i * SIZE_1GB)
+            );
+        }
+    }
+
+    #[test]
+    fn test_change_flags_merges_splits_on_error() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let pdpt_ptr = from_mut(&mut pdpt);
+
+        pml4[0].set_addr(PhysAddr(pdpt_ptr.addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_1GB),
+                PhysAddr(SIZE_1GB),
+                1,
+                PageSize::size_1gb(),
+                Flags::new().set_read_write(true),
+            )
+            .unwrap();
+        };
+        let original = unsafe { (&*pdpt_ptr)[1].0 };
+
+        // Splitting the 1GB page works, but there's no table left to split the 2MB page with
+        let mut pd = empty_table();
+        let mut tables = Some(PhysAddr(from_mut(&mut pd).addr()));
+        let mut freed = Vec::new();
+        let res = unsafe {
+            pml4.change_flags_with(
+                VirtAddr(SIZE_1GB + 0x5000),
+                1,
+                PageSize::size_4kb(),
+                Flags::new(),
+                || tables.take().ok_or(PagingError::OutOfMemory),
+                |addr| freed.push(addr),
+            )
+        };
+        assert_eq!(res, Err(PagingError::OutOfMemory));
+
+        // The 1GB page should be back as it was
+        assert_eq!(unsafe { (&*pdpt_ptr)[1].0 }, original);
+        assert_eq!(freed, [PhysAddr(from_mut(&mut pd).addr())]);
+    }
+
+    fn empty_table() -> PageTable {
+        PageTable(core::array::from_fn(|_| Entry(0)))
+    }
+
+    #[test]
+    fn test_map_pages_rolls_back_on_conflict() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let pdpt_ptr = from_mut(&mut pdpt);
+
+        // NOTE: The HHDM offset is 0 in tests, so the PDPT's address is its own "physical" address
+        pml4[0].set_addr(PhysAddr(pdpt_ptr.addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        // Something is already mapped in the middle of the range
+        unsafe {
+            (&mut *pdpt_ptr)[5]
+                .map(
+                    VirtAddr(5 * SIZE_1GB),
+                    PhysAddr(5 * SIZE_1GB),
+                    Flags::new(),
+                    PageSize::size_1gb(),
+                )
+                .unwrap();
+        };
+
+        let res = unsafe {
+            pml4.map_pages(
+                VirtAddr(0),
+                PhysAddr(0),
+                10,
+                PageSize::size_1gb(),
+                Flags::new().set_read_write(true),
+            )
+        };
+        assert_eq!(
+            res,
+            Err(PagingError::PageAlreadyPresent(VirtAddr(5 * SIZE_1GB)))
+        );
+
+        // No new mappings should be left behind, and the old mapping should be untouched
+        let pdpt = unsafe { &*pdpt_ptr };
+        for (i, entry) in pdpt.iter().enumerate().take(10) {
+            assert_eq!(entry.get_flags().get_present(), i == 5, "Entry {i}");
+        }
+        assert_eq!(
+            pdpt[5].get_addr(PageSize::size_1gb()),
+            PhysAddr(5 * SIZE_1GB)
+        );
+    }
+
+    #[test]
+    fn test_map_pages_no_conflict() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let pdpt_ptr = from_mut(&mut pdpt);
+
+        pml4[0].set_addr(PhysAddr(pdpt_ptr.addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_1GB),
+                PhysAddr(0),
+                3,
+                PageSize::size_1gb(),
+                Flags::new().set_read_write(true),
+            )
+            .unwrap();
+        };
+
+        let pdpt = unsafe { &*pdpt_ptr };
+        for (i, entry) in pdpt.iter().enumerate().take(5) {
+            assert_eq!(entry.get_flags().get_present(), (1..4).contains(&i));
+        }
+    }
+
+    #[test]
+    fn test_create_table_range_out_of_tables() {
+        let mut pml4 = empty_table();
+        let mut tables: [PageTable; 3] = core::array::from_fn(|_| empty_table());
+        let ptrs = tables.each_mut().map(from_mut);
+        let addrs = ptrs.map(|ptr| PhysAddr(ptr.addr()));
+
+        // Hands out `limit` of the tables (zeroed, like the PMM ones are), then runs out
+        let allocate = |limit: usize| {
+            let mut next = 0;
+            move || {
+                let ptr = *ptrs[..limit].get(next).ok_or(PagingError::OutOfMemory)?;
+                unsafe { ptr.write(empty_table()) };
+                next += 1;
+                Ok(PhysAddr(ptr.addr()))
+            }
+        };
+
+        // Running out at any point of the walk leaves no trace of it
+        for limit in 0..3 {
+            let mut freed = [PhysAddr(0); 3];
+            let mut freed_count = 0;
+            let res = pml4.get_create_table_range_with(
+                VirtAddr(0x1234_5000),
+                PageSize::size_4kb(),
+                allocate(limit),
+                |addr| {
+                    freed[freed_count] = addr;
+                    freed_count += 1;
+                },
+            );
+
+            assert_eq!(res.err(), Some(PagingError::OutOfMemory));
+            assert_eq!(&freed[..freed_count], &addrs[..limit]);
+            assert!(pml4.iter().all(|entry| entry.0 == 0));
+        }
+
+        // With enough tables, the walk links all of them in
+        let pt = pml4
+            .get_create_table_range_with(
+                VirtAddr(0x1234_5000),
+                PageSize::size_4kb(),
+                allocate(3),
+                |_| panic!("Nothing should be freed"),
+            )
+            .unwrap();
+        assert_eq!(PhysAddr(from_mut(pt).addr()), addrs[2]);
+    }
+
+    #[test]
+    fn test_resident_pages() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+        let pd_ptr = from_mut(&mut pd);
+
+        pml4[0].set_addr(PhysAddr(from_mut(&mut pdpt).addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+        pdpt[0].set_addr(PhysAddr(pd_ptr.addr()), PageSize::size_4kb());
+        pdpt[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        let before = pml4.resident_pages();
+        let owned = Flags::new().set_read_write(true).set_allocated(true);
+
+        // Frames that aren't ours (e.g. MMIO) aren't counted
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(5 * SIZE_2MB),
+                PhysAddr(0xfe00_0000),
+                1,
+                PageSize::size_2mb(),
+                Flags::new().set_read_write(true),
+            )
+            .unwrap();
+        };
+        assert_eq!(pml4.resident_pages(), before);
+
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_2MB),
+                PhysAddr(SIZE_1GB),
+                2,
+                PageSize::size_2mb(),
+                owned,
+            )
+            .unwrap();
+        };
+        assert_eq!(pml4.resident_pages(), before + 2 * ENTRIES_PER_TABLE);
+
+        // A mapping that was rolled back doesn't count
+        let res = unsafe {
+            pml4.map_pages(
+                VirtAddr(3 * SIZE_2MB),
+                PhysAddr(2 * SIZE_1GB),
+                3,
+                PageSize::size_2mb(),
+                owned,
+            )
+        };
+        assert_eq!(
+            res,
+            Err(PagingError::PageAlreadyPresent(VirtAddr(5 * SIZE_2MB)))
+        );
+        assert_eq!(pml4.resident_pages(), before + 2 * ENTRIES_PER_TABLE);
+
+        // The same tables linked into another address space count towards that one
+        let mut other = empty_table();
+        other[0].set_addr(PhysAddr(from_mut(&mut pdpt).addr()), PageSize::size_4kb());
+        other[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+        let other_before = other.resident_pages();
+        unsafe {
+            other
+                .map_pages(
+                    VirtAddr(7 * SIZE_2MB),
+                    PhysAddr(3 * SIZE_1GB),
+                    1,
+                    PageSize::size_2mb(),
+                    owned,
+                )
+                .unwrap();
+        };
+        assert_eq!(other.resident_pages(), other_before + ENTRIES_PER_TABLE);
+        assert_eq!(pml4.resident_pages(), before + 2 * ENTRIES_PER_TABLE);
+
+        // Releasing a frame we own frees it and reports it as no longer resident, while releasing
+        // one we don't leaves it alone
+        let pd = unsafe { &mut *pd_ptr };
+        let mut freed = None;
+        let released = pd[5]
+            .release_with(VirtAddr(5 * SIZE_2MB), PageSize::size_2mb(), |frame| {
+                freed = Some(frame);
+            })
+            .unwrap();
+        assert_eq!((released, freed), (0, None));
+
+        let released = pd[1]
+            .release_with(VirtAddr(SIZE_2MB), PageSize::size_2mb(), |frame| {
+                freed = Some(frame);
+            })
+            .unwrap();
+        assert_eq!(
+            (released, freed),
+            (ENTRIES_PER_TABLE, Some(PhysAddr(SIZE_1GB)))
+        );
+
+        // A page that isn't present anymore has nothing left to release
+        assert_eq!(
+            pd[1].release_with(VirtAddr(SIZE_2MB), PageSize::size_2mb(), |_| {
+                panic!("The frame was already freed");
+            }),
+            Err(PagingError::PageNotPresent(VirtAddr(SIZE_2MB)))
+        );
+    }
+
+    #[test]
+    fn test_translate_huge_pages() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+
+        pml4[0].set_addr(PhysAddr(from_mut(&mut pdpt).addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+        pdpt[0].set_addr(PhysAddr(from_mut(&mut pd).addr()), PageSize::size_4kb());
+        pdpt[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        let flags = Flags::new().set_read_write(true);
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_2MB),
+                PhysAddr(3 * SIZE_2MB),
+                1,
+                PageSize::size_2mb(),
+                flags,
+            )
+            .unwrap();
+            pml4.map_pages(
+                VirtAddr(SIZE_1GB),
+                PhysAddr(2 * SIZE_1GB),
+                1,
+                PageSize::size_1gb(),
+                flags,
+            )
+            .unwrap();
+        };
+
+        // The walk stops at the huge entries, instead of reading the pages as tables
+        assert_eq!(
+            pml4.get_entry(VirtAddr(SIZE_2MB + 0x1234)).unwrap().1,
+            PageSize::size_2mb()
+        );
+        assert_eq!(
+            pml4.get_entry(VirtAddr(SIZE_1GB + SIZE_2MB)).unwrap().1,
+            PageSize::size_1gb()
+        );
+
+        // The offset within the page is kept, all the way to the end of the page
+        assert_eq!(
+            pml4.translate(VirtAddr(SIZE_2MB + 0x1234)),
+            Some(PhysAddr(3 * SIZE_2MB + 0x1234))
+        );
+        assert_eq!(
+            pml4.translate(VirtAddr(2 * SIZE_2MB - 1)),
+            Some(PhysAddr(4 * SIZE_2MB - 1))
+        );
+        assert_eq!(
+            pml4.translate(VirtAddr(SIZE_1GB + 0x0012_3456)),
+            Some(PhysAddr(2 * SIZE_1GB + 0x0012_3456))
+        );
+        assert_eq!(pml4.translate(VirtAddr(2 * SIZE_2MB)), None);
+    }
+
+    #[test]
+    fn test_map_scatter() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+
+        pml4[0].set_addr(PhysAddr(from_mut(&mut pdpt).addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+        pdpt[0].set_addr(PhysAddr(from_mut(&mut pd).addr()), PageSize::size_4kb());
+        pdpt[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        let before = pml4.resident_pages();
+        let owned = Flags::new().set_read_write(true).set_allocated(true);
+        let frames = [
+            PhysAddr(7 * SIZE_2MB),
+            PhysAddr(2 * SIZE_2MB),
+            PhysAddr(SIZE_1GB),
+        ];
+
+        // Something is already mapped where the last frame would go, so nothing should be left
+        // mapped
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(3 * SIZE_2MB),
+                PhysAddr(0),
+                1,
+                PageSize::size_2mb(),
+                Flags::new(),
+            )
+            .unwrap();
+        };
+        let res =
+            unsafe { pml4.map_scatter(VirtAddr(SIZE_2MB), frames, PageSize::size_2mb(), owned) };
+        assert_eq!(
+            res,
+            Err(PagingError::PageAlreadyPresent(VirtAddr(3 * SIZE_2MB)))
+        );
+        assert_eq!(pml4.translate(VirtAddr(SIZE_2MB)), None);
+        assert_eq!(pml4.translate(VirtAddr(2 * SIZE_2MB)), None);
+        assert_eq!(pml4.resident_pages(), before);
+
+        let res = unsafe {
+            pml4.map_scatter(VirtAddr(4 * SIZE_2MB), frames, PageSize::size_2mb(), owned)
+        };
+        assert_eq!(res, Ok(3));
+        for (i, frame) in frames.into_iter().enumerate() {
+            assert_eq!(
+                pml4.translate(VirtAddr((4 + i) * SIZE_2MB + 0x1234)),
+                Some(frame + 0x1234)
+            );
+        }
+        assert_eq!(pml4.translate(VirtAddr(7 * SIZE_2MB)), None);
+        assert_eq!(pml4.resident_pages(), before + 3 * ENTRIES_PER_TABLE);
+    }
+
+    #[test]
+    fn test_map_scatter_frees_created_tables() {
+        let mut pml4 = empty_table();
+        let mut tables: [PageTable; 4] = core::array::from_fn(|_| empty_table());
+        let ptrs = tables.each_mut().map(from_mut);
+        let addrs = ptrs.map(|ptr| PhysAddr(ptr.addr()));
+
+        // Hands out `limit` of the tables (zeroed, like the PMM ones are), then runs out
+        let allocate = |limit: usize| {
+            let mut next = 0;
+            move || {
+                let ptr = *ptrs[..limit].get(next).ok_or(PagingError::OutOfMemory)?;
+                unsafe { ptr.write(empty_table()) };
+                next += 1;
+                Ok(PhysAddr(ptr.addr()))
+            }
+        };
+
+        let before = pml4.resident_pages();
+        let owned = Flags::new().set_read_write(true).set_allocated(true);
+        // The first two pages are on both sides of a 2MB boundary, so they need a PDPT, a PD and
+        // two PTs
+        let base_addr = VirtAddr(SIZE_2MB - 0x1000);
+        let frames = [PhysAddr(0x5000), PhysAddr(0x1000)];
+
+        // The last frame can't be mapped after all the tables were created
+        let mut freed = ArrayVec::<PhysAddr, 4>::new();
+        let res = unsafe {
+            pml4.map_scatter_with(
+                base_addr,
+                frames.into_iter().chain([PhysAddr(0x3800)]),
+                PageSize::size_4kb(),
+                owned,
+                allocate(4),
+                |addr| freed.push(addr).unwrap(),
+            )
+        };
+        assert_eq!(
+            res,
+            Err(PagingError::UnalignedPhysicalAddress(PhysAddr(0x3800)))
+        );
+        assert_eq!(freed.as_slice(), &addrs);
+        assert!(pml4.iter().all(|entry| entry.0 == 0));
+        assert_eq!(pml4.resident_pages(), before);
+
+        // Running out of tables midway is undone the same way
+        freed.clear();
+        let res = unsafe {
+            pml4.map_scatter_with(
+                base_addr,
+                frames,
+                PageSize::size_4kb(),
+                owned,
+                allocate(3),
+                |addr| freed.push(addr).unwrap(),
+            )
+        };
+        assert_eq!(res, Err(PagingError::OutOfMemory));
+        assert_eq!(freed.as_slice(), &addrs[..3]);
+        assert!(pml4.iter().all(|entry| entry.0 == 0));
+        assert_eq!(pml4.resident_pages(), before);
+
+        let res = unsafe {
+            pml4.map_scatter_with(
+                base_addr,
+                frames,
+                PageSize::size_4kb(),
+                owned,
+                allocate(4),
+                |_| panic!("Nothing should be freed"),
+            )
+        };
+        assert_eq!(res, Ok(2));
+        assert_eq!(pml4.translate(base_addr + 0x123), Some(PhysAddr(0x5123)));
+        assert_eq!(
+            pml4.translate(VirtAddr(SIZE_2MB + 0x123)),
+            Some(PhysAddr(0x1123))
+        );
+        assert_eq!(pml4.resident_pages(), before + 2);
+
+        unsafe {
+            pml4.unmap_keep_frame(base_addr, 1, PageSize::size_4kb())
+                .unwrap();
+            pml4.unmap_keep_frame(VirtAddr(SIZE_2MB), 1, PageSize::size_4kb())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unmap_keep_frame() {
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+
+        pml4[0].set_addr(PhysAddr(from_mut(&mut pdpt).addr()), PageSize::size_4kb());
+        pml4[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+        pdpt[0].set_addr(PhysAddr(from_mut(&mut pd).addr()), PageSize::size_4kb());
+        pdpt[0].set_flags(Flags::new().set_present(true).set_read_write(true));
+
+        let before = pml4.resident_pages();
+        let owned = Flags::new().set_read_write(true).set_allocated(true);
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(SIZE_2MB),
+                PhysAddr(SIZE_1GB),
+                2,
+                PageSize::size_2mb(),
+                owned,
+            )
+            .unwrap();
+        };
+
+        // The range reaches an unmapped page, so nothing should change
+        let res = unsafe { pml4.unmap_keep_frame(VirtAddr(SIZE_2MB), 3, PageSize::size_2mb()) };
+        assert_eq!(
+            res,
+            Err(PagingError::PageNotPresent(VirtAddr(3 * SIZE_2MB)))
+        );
+        assert_eq!(pml4.translate(VirtAddr(SIZE_2MB)), Some(PhysAddr(SIZE_1GB)));
+
+        // NOTE: The PMM isn't initialized in tests, so this would crash if it tried to free the
+        // frames
+        let phys_addr =
+            unsafe { pml4.unmap_keep_frame(VirtAddr(SIZE_2MB), 2, PageSize::size_2mb()) }.unwrap();
+        assert_eq!(phys_addr, PhysAddr(SIZE_1GB));
+        assert_eq!(pml4.translate(VirtAddr(SIZE_2MB)), None);
+        assert_eq!(pml4.translate(VirtAddr(2 * SIZE_2MB)), None);
+        assert_eq!(pml4.resident_pages(), before);
+
+        // The frames are still ours, so they can be mapped somewhere else
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(6 * SIZE_2MB),
+                phys_addr,
+                2,
+                PageSize::size_2mb(),
+                owned,
+            )
+            .unwrap();
+        };
+        assert_eq!(
+            pml4.translate(VirtAddr(7 * SIZE_2MB)),
+            Some(PhysAddr(SIZE_1GB + SIZE_2MB))
+        );
+        assert_eq!(pml4.resident_pages(), before + 2 * ENTRIES_PER_TABLE);
+
+        // Only the first frame would be handed back, so frames that aren't contiguous are refused
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(9 * SIZE_2MB),
+                PhysAddr(SIZE_1GB + 4 * SIZE_2MB),
+                1,
+                PageSize::size_2mb(),
+                owned,
+            )
+            .unwrap();
+        };
+        unsafe {
+            pml4.map_pages(
+                VirtAddr(8 * SIZE_2MB),
+                PhysAddr(SIZE_1GB + 2 * SIZE_2MB),
+                1,
+                PageSize::size_2mb(),
+                owned,
+            )
+            .unwrap();
+        };
+        let res = unsafe { pml4.unmap_keep_frame(VirtAddr(7 * SIZE_2MB), 3, PageSize::size_2mb()) };
+        assert_eq!(
+            res,
+            Err(PagingError::NonContiguousFrames(VirtAddr(9 * SIZE_2MB)))
+        );
+        assert_eq!(
+            pml4.translate(VirtAddr(9 * SIZE_2MB)),
+            Some(PhysAddr(SIZE_1GB + 4 * SIZE_2MB))
+        );
+        assert_eq!(pml4.resident_pages(), before + 4 * ENTRIES_PER_TABLE);
+    }
+}
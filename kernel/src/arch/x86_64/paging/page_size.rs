@@ -1,7 +1,10 @@
 use core::fmt::Debug;
 
 use crate::{
-    arch::x86_64::X86_64,
+    arch::x86_64::{
+        X86_64,
+        cpu::features::{CpuFeatures, cpu_features},
+    },
     mem::paging::{Flags, PageSize},
 };
 
@@ -30,6 +33,37 @@ impl PageSize<X86_64> {
         unsafe { Self::from_raw(Self::SIZE_1GB) }
     }
 
+    /// Returns all the page sizes the architecture defines, smallest first. Not all of them are
+    /// necessarily supported by the CPU, see `supported`
+    #[inline]
+    #[must_use]
+    pub const fn all() -> [Self; 3] {
+        [Self::size_4kb(), Self::size_2mb(), Self::size_1gb()]
+    }
+
+    /// Returns the page sizes this CPU supports, smallest first
+    pub fn supported() -> impl Iterator<Item = Self> {
+        Self::supported_by(cpu_features())
+    }
+
+    /// Returns the biggest page size this CPU supports
+    #[must_use]
+    pub fn largest_supported() -> Self {
+        // 4KB pages are always supported, so this never falls back
+        Self::supported().last().unwrap_or(Self::size_4kb())
+    }
+
+    /// Returns the page sizes supported by a CPU with `features`, smallest first.
+    ///
+    /// 4KB and 2MB pages are always supported in long mode, only 1GB pages need to be checked.
+    fn supported_by(features: &CpuFeatures) -> impl Iterator<Item = Self> {
+        let has_1gb = features.page_1gb;
+
+        Self::all()
+            .into_iter()
+            .filter(move |page_size| *page_size != Self::size_1gb() || has_1gb)
+    }
+
     #[inline]
     #[must_use]
     pub(super) const fn from_bottom_paging_level(level: usize) -> Option<Self> {
@@ -105,3 +139,30 @@ impl Debug for PageSize<X86_64> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// The features of a CPU supporting 1GB pages only if `has_1gb` is set
+    fn mock_features(has_1gb: bool) -> CpuFeatures {
+        CpuFeatures {
+            page_1gb: has_1gb,
+            ..CpuFeatures::default()
+        }
+    }
+
+    #[test]
+    fn test_supported_page_sizes() {
+        let supported: Vec<_> = PageSize::supported_by(&mock_features(true)).collect();
+        assert_eq!(supported, PageSize::all());
+
+        let supported: Vec<_> = PageSize::supported_by(&mock_features(false)).collect();
+        assert_eq!(supported, [PageSize::size_4kb(), PageSize::size_2mb()]);
+        assert_eq!(
+            PageSize::supported_by(&mock_features(false)).last(),
+            Some(PageSize::size_2mb())
+        );
+    }
+}
@@ -1,7 +1,5 @@
 //! Pat support for `x86_64` paging
 
-use core::arch::x86_64::__cpuid;
-
 use crate::arch::x86_64::cpu::msr::{IntelMsr, rdmsr, wrmsr};
 
 /// The amount of bits between each PAT entry in the `IA32_PAT` MSR. This is the amount of bits we
@@ -88,8 +86,6 @@ impl PatEntry {
 
 /// Setup the PAT entries as we want them to be.
 pub(super) unsafe fn setup_pat() {
-    check_pat_support();
-
     unsafe {
         PatEntry::set(PatEntry::Pat0, PatType::WriteBack);
         PatEntry::set(PatEntry::Pat1, PatType::WriteThrough);
@@ -100,18 +96,6 @@ pub(super) unsafe fn setup_pat() {
     }
 }
 
-/// Check if PAT is supported by this CPU.
-fn check_pat_support() {
-    const PAT_BIT: u32 = 1 << 16;
-
-    unsafe {
-        assert!(
-            __cpuid(1).edx & PAT_BIT != 0,
-            "PAT is not supported by this CPU"
-        );
-    }
-}
-
 impl From<PatType> for PatEntry {
     fn from(pat_type: PatType) -> Self {
         match pat_type {
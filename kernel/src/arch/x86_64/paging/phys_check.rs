@@ -0,0 +1,175 @@
+//! Checking the physical addresses that are mapped against the memory map.
+//!
+//! A bogus physical address (e.g. a length passed as the base) maps just fine, and only shows up
+//! later as corrupted memory somewhere else, so debug builds check every mapping against the
+//! memory map the bootloader handed over, and warn about the ones that can't be right.
+
+use core::arch::x86_64::CpuidResult;
+
+use utils::{
+    boot_info::{self, MemoryRegion, MemoryRegionKind},
+    mem::PhysAddr,
+};
+
+use crate::{arch::x86_64::cpu::features::cpuid, mem::paging::PagingError};
+
+/// The physical address width CPUs without CPUID leaf `0x8000_0008` have
+const DEFAULT_PHYS_ADDR_BITS: u32 = 36;
+
+/// Whether the region is RAM (i.e. memory, as opposed to holes, MMIO or firmware reserved areas)
+const fn is_ram(kind: MemoryRegionKind) -> bool {
+    matches!(
+        kind,
+        MemoryRegionKind::Usable
+            | MemoryRegionKind::AcpiReclaimable
+            | MemoryRegionKind::AcpiNvs
+            | MemoryRegionKind::BootloaderReclaimable
+            | MemoryRegionKind::ExecutableAndModules
+    )
+}
+
+/// Get the end of the physical address space according to `cpuid`
+fn phys_addr_limit(cpuid: impl Fn(u32) -> CpuidResult) -> usize {
+    let bits = match cpuid(0x8000_0008).eax & 0xff {
+        0 => DEFAULT_PHYS_ADDR_BITS,
+        bits => bits,
+    };
+
+    1 << bits
+}
+
+/// Check that `len` bytes starting at `phys_addr` can be mapped, according to `memory_map`.
+///
+/// The range is rejected if it's beyond the physical address space, touches bad memory, or is
+/// only partly RAM. Ranges that aren't RAM at all are allowed, since they might be MMIO, which
+/// the memory map doesn't necessarily describe.
+///
+/// # Errors
+/// Returns `PagingError::InvalidPhysicalAddress` if the range is rejected
+pub(super) fn check_phys_range(
+    memory_map: &[MemoryRegion],
+    phys_addr_limit: usize,
+    phys_addr: PhysAddr,
+    len: usize,
+) -> Result<(), PagingError> {
+    let end = phys_addr
+        .0
+        .checked_add(len)
+        .ok_or(PagingError::InvalidPhysicalAddress)?;
+    if end > phys_addr_limit {
+        return Err(PagingError::InvalidPhysicalAddress);
+    }
+
+    let mut ram_bytes = 0;
+    for region in memory_map {
+        let overlap_start = phys_addr.0.max(region.base.0);
+        let overlap_end = end.min(region.end().0);
+        if overlap_start >= overlap_end {
+            continue;
+        }
+
+        if region.kind == MemoryRegionKind::BadMemory {
+            return Err(PagingError::InvalidPhysicalAddress);
+        } else if is_ram(region.kind) {
+            ram_bytes += overlap_end - overlap_start;
+        }
+    }
+
+    if ram_bytes == 0 || ram_bytes == len {
+        Ok(())
+    } else {
+        Err(PagingError::InvalidPhysicalAddress)
+    }
+}
+
+/// Warn if `len` bytes starting at `phys_addr` shouldn't be mapped.
+///
+/// Does nothing before the boot info is available (i.e. while the initial page tables are built)
+pub(super) fn validate(phys_addr: PhysAddr, len: usize) {
+    let Some(boot_info) = boot_info::get() else {
+        return;
+    };
+
+    if check_phys_range(
+        boot_info.memory_map(),
+        phys_addr_limit(cpuid),
+        phys_addr,
+        len,
+    )
+    .is_err()
+    {
+        logger::warn!(
+            "Mapping {len:#x} bytes at physical address {:#x}, which isn't valid according to the memory map",
+            phys_addr.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: usize = 1 << 40;
+
+    fn region(base: usize, length: usize, kind: MemoryRegionKind) -> MemoryRegion {
+        MemoryRegion {
+            base: PhysAddr(base),
+            length,
+            kind,
+        }
+    }
+
+    fn memory_map() -> [MemoryRegion; 4] {
+        [
+            region(0x0, 0x9_f000, MemoryRegionKind::Usable),
+            region(0x10_0000, 0x7ff0_0000, MemoryRegionKind::Usable),
+            region(0x8000_0000, 0x1000, MemoryRegionKind::BadMemory),
+            region(0xfee0_0000, 0x1000, MemoryRegionKind::Reserved),
+        ]
+    }
+
+    #[test]
+    fn test_check_phys_range() {
+        let map = memory_map();
+        let check = |phys_addr, len| check_phys_range(&map, LIMIT, PhysAddr(phys_addr), len);
+
+        // RAM, and MMIO both in a hole and in a reserved region
+        assert_eq!(check(0x20_0000, 0x4000), Ok(()));
+        assert_eq!(check(0xfec0_0000, 0x1000), Ok(()));
+        assert_eq!(check(0xfee0_0000, 0x1000), Ok(()));
+
+        // Running off the end of RAM
+        assert_eq!(
+            check(0x9_e000, 0x2000),
+            Err(PagingError::InvalidPhysicalAddress)
+        );
+        // Bad memory, and beyond the physical address space
+        assert_eq!(
+            check(0x8000_0000, 0x1000),
+            Err(PagingError::InvalidPhysicalAddress)
+        );
+        assert_eq!(
+            check(LIMIT, 0x1000),
+            Err(PagingError::InvalidPhysicalAddress)
+        );
+        assert_eq!(
+            check(usize::MAX & !0xfff, 0x2000),
+            Err(PagingError::InvalidPhysicalAddress)
+        );
+    }
+
+    #[test]
+    fn test_phys_addr_limit() {
+        let cpuid_with = |eax| {
+            move |_| CpuidResult {
+                eax,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            }
+        };
+
+        assert_eq!(phys_addr_limit(cpuid_with(0x3027)), 1 << 39);
+        assert_eq!(phys_addr_limit(cpuid_with(0)), 1 << DEFAULT_PHYS_ADDR_BITS);
+    }
+}
@@ -0,0 +1,186 @@
+//! Access to the page tables through a recursive mapping, for address spaces that don't map all of
+//! physical memory in the HHDM.
+//!
+//! One PML entry (`RECURSIVE_INDEX`) points back to the PML itself. Translating through it one
+//! extra time lands one level higher in the hierarchy, so every page table of the address space
+//! shows up as a page at a fixed virtual address, which `recursive_table_addr` computes.
+//!
+//! NOTE: Only 4 level paging is supported for now
+
+use utils::mem::{PhysAddr, VirtAddr};
+
+use crate::{
+    arch::x86_64::X86_64,
+    mem::paging::{Flags, PageSize},
+};
+
+use super::{PageTable, next_level_index, page_size::MAX_BOTTOM_PAGING_LEVEL};
+
+/// The PML entry pointing to the PML itself. The entry right below the kernel's one, so it doesn't
+/// collide with the HHDM or the kernel's image
+pub const RECURSIVE_INDEX: usize = 510;
+
+/// The amount of bits of a virtual address that are translated with 4 level paging
+const VIRT_ADDR_BITS: usize = 48;
+
+/// Get the virtual address the level `level` table translating `virt_addr` is mapped at through
+/// the recursive entry. Level `MAX_BOTTOM_PAGING_LEVEL` is the PML itself, and level 0 the page
+/// table mapping `virt_addr`'s 4KB page.
+///
+/// # Panics
+/// Panics if `level` is above `MAX_BOTTOM_PAGING_LEVEL`
+#[must_use]
+pub const fn recursive_table_addr(virt_addr: VirtAddr, level: usize) -> VirtAddr {
+    assert!(level <= MAX_BOTTOM_PAGING_LEVEL, "Invalid paging level");
+
+    let offset_bits = PageSize::<X86_64>::size_4kb().offset_bit_count();
+    // Every pass through the recursive entry drops one level of the walk, so the indices of
+    // `virt_addr` are shifted down by the amount of passes
+    let passes = level + 1;
+    let kept_index_bits = VIRT_ADDR_BITS - offset_bits - passes * 9;
+    let shifted = (virt_addr.0 >> (offset_bits + passes * 9)) & ((1 << kept_index_bits) - 1);
+
+    let mut addr = shifted << offset_bits;
+    let mut pass = 0;
+    while pass < passes {
+        addr |= RECURSIVE_INDEX << (VIRT_ADDR_BITS - 9 * (pass + 1));
+        pass += 1;
+    }
+
+    VirtAddr(sign_extend(addr))
+}
+
+/// Copy the top translated bit of `addr` to all the bits above it, making it canonical
+#[inline]
+#[must_use]
+const fn sign_extend(addr: usize) -> usize {
+    let shift = usize::BITS as usize - VIRT_ADDR_BITS;
+
+    ((addr << shift).cast_signed() >> shift).cast_unsigned()
+}
+
+impl PageTable {
+    /// Get the level `level` table translating `virt_addr`, through the recursive mapping.
+    ///
+    /// # Safety
+    /// The recursive entry must be installed in the active PML (see `install_recursive`), and all
+    /// the tables above `level` translating `virt_addr` must be present. The caller must also make
+    /// sure the table isn't aliased mutably, same as with the HHDM
+    #[must_use]
+    pub(super) unsafe fn from_recursive(virt_addr: VirtAddr, level: usize) -> &'static mut Self {
+        let ptr: *mut PageTable =
+            core::ptr::without_provenance_mut(recursive_table_addr(virt_addr, level).0);
+
+        unsafe {
+            ptr.as_mut()
+                .expect("Failed to get recursively mapped table")
+        }
+    }
+
+    /// Point the `RECURSIVE_INDEX`th entry of this PML, which is at `phys_addr`, back to itself.
+    ///
+    /// NOTE: The entry is left non global, since each address space maps its own PML there
+    pub(super) fn install_recursive(&mut self, phys_addr: PhysAddr) {
+        self[RECURSIVE_INDEX].set_addr(phys_addr, PageSize::size_4kb());
+        self[RECURSIVE_INDEX].set_flags(Flags::new().set_present(true).set_read_write(true));
+    }
+}
+
+/// Returns true if `virt_addr` is in the part of the address space the recursive entry occupies
+#[inline]
+#[must_use]
+pub const fn is_recursive(virt_addr: VirtAddr) -> bool {
+    next_level_index(virt_addr, MAX_BOTTOM_PAGING_LEVEL) == RECURSIVE_INDEX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Entry;
+    use super::*;
+    use alloc::boxed::Box;
+    use core::ptr::from_mut;
+
+    fn empty_table() -> Box<PageTable> {
+        Box::new(PageTable(core::array::from_fn(|_| Entry(0))))
+    }
+
+    /// Walk `virt_addr` through all the levels starting at `pml` like the MMU does, and return the
+    /// address of the 4KB page it lands on
+    fn walk(pml: &mut PageTable, virt_addr: VirtAddr) -> PhysAddr {
+        let mut table = pml;
+        for level in (1..=MAX_BOTTOM_PAGING_LEVEL).rev() {
+            let entry = &mut table[next_level_index(virt_addr, level)];
+            assert!(entry.get_flags().get_present());
+            table = entry.next_level_table();
+        }
+
+        table[next_level_index(virt_addr, 0)].get_addr(PageSize::size_4kb())
+    }
+
+    #[test]
+    fn test_recursive_table_addr() {
+        let virt_addr = VirtAddr(0xffff_8000_1234_5678);
+
+        // The PML is reached by going through the recursive entry on every level
+        assert_eq!(
+            recursive_table_addr(virt_addr, MAX_BOTTOM_PAGING_LEVEL),
+            VirtAddr(0xffff_ff7f_bfdf_e000)
+        );
+        assert_eq!(
+            recursive_table_addr(VirtAddr(0), MAX_BOTTOM_PAGING_LEVEL),
+            VirtAddr(0xffff_ff7f_bfdf_e000)
+        );
+
+        // Every level one below replaces one recursive index with one of `virt_addr`'s, from the
+        // top
+        for level in 0..=MAX_BOTTOM_PAGING_LEVEL {
+            let addr = recursive_table_addr(virt_addr, level);
+            let passes = level + 1;
+
+            for i in 0..=MAX_BOTTOM_PAGING_LEVEL {
+                let expected = if i + passes > MAX_BOTTOM_PAGING_LEVEL {
+                    RECURSIVE_INDEX
+                } else {
+                    next_level_index(virt_addr, i + passes)
+                };
+                assert_eq!(
+                    next_level_index(addr, i),
+                    expected,
+                    "level {level} index {i}"
+                );
+            }
+            assert!(is_recursive(addr));
+            assert!(addr.is_aligned(0x1000));
+        }
+        assert!(!is_recursive(virt_addr));
+    }
+
+    #[test]
+    fn test_recursive_walk_reaches_tables() {
+        // The HHDM offset is 0 in tests, so the tables' addresses are their "physical" ones
+        let mut pml4 = empty_table();
+        let mut pdpt = empty_table();
+        let mut pd = empty_table();
+        let mut pt = empty_table();
+        let virt_addr = VirtAddr(0xffff_8000_4060_3000);
+
+        let addrs = [&mut pt, &mut pd, &mut pdpt, &mut pml4]
+            .map(|table| PhysAddr(from_mut(&mut **table).addr()));
+
+        // Link each table to the one below it, on `virt_addr`'s path
+        for (level, table) in [(1, &mut pd), (2, &mut pdpt), (3, &mut pml4)] {
+            let entry = &mut table[next_level_index(virt_addr, level)];
+            entry.set_addr(addrs[level - 1], PageSize::size_4kb());
+            entry.set_flags(Flags::new().set_present(true).set_read_write(true));
+        }
+        pml4.install_recursive(addrs[MAX_BOTTOM_PAGING_LEVEL]);
+
+        for (level, expected) in addrs.into_iter().enumerate() {
+            assert_eq!(
+                walk(&mut pml4, recursive_table_addr(virt_addr, level)),
+                expected,
+                "level {level}"
+            );
+        }
+    }
+}
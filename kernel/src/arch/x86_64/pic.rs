@@ -0,0 +1,216 @@
+//! Driver for the legacy 8259 PICs
+//!
+//! We only use the APIC stack, but the PICs still need to be remapped away from the exception
+//! vectors (0x00 - 0x1f) and masked off, otherwise stray IRQs land on exception handlers. They are
+//! remapped to 0xe0 - 0xef, out of the way of the vectors IRQs are dispatched on, and those vectors
+//! are never handed out to drivers.
+//!
+//! Even while masked, a PIC can raise a spurious IRQ on its lowest priority line (IRQ 7 on the
+//! master, IRQ 15 on the slave) when an IRQ goes away before the CPU acknowledges it. These are
+//! told apart from real IRQs by looking at the PIC's In-Service Register.
+
+use super::cpu::{inb_8, io_wait, outb_8};
+
+/// The vector the master PIC's IRQs are remapped to
+pub const MASTER_VECTOR_BASE: u8 = 0xe0;
+/// The vector the slave PIC's IRQs are remapped to
+pub const SLAVE_VECTOR_BASE: u8 = MASTER_VECTOR_BASE + 8;
+/// The last vector the PICs' IRQs are remapped to
+pub const LAST_VECTOR: u8 = SLAVE_VECTOR_BASE + 7;
+
+/// The vector the master PIC raises its spurious IRQs on
+pub const MASTER_SPURIOUS_VECTOR: u8 = MASTER_VECTOR_BASE + MASTER_SPURIOUS_IRQ;
+/// The vector the slave PIC raises its spurious IRQs on
+pub const SLAVE_SPURIOUS_VECTOR: u8 = MASTER_VECTOR_BASE + SLAVE_SPURIOUS_IRQ;
+
+/// The ports of the PICs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+enum PicPort {
+    MasterCommand = 0x20,
+    MasterData = 0x21,
+    SlaveCommand = 0xa0,
+    SlaveData = 0xa1,
+}
+
+/// ICW1: Start initialization, ICW4 will be present
+const ICW1_INIT: u8 = 0x11;
+/// ICW3 (master): A slave PIC is connected to IRQ 2
+const ICW3_MASTER: u8 = 0b100;
+/// ICW3 (slave): The cascade identity of the slave PIC
+const ICW3_SLAVE: u8 = 2;
+/// ICW4: 8086/88 mode
+const ICW4_8086: u8 = 0x1;
+/// OCW1: Mask all IRQ lines
+const MASK_ALL: u8 = 0xff;
+/// OCW2: Non-specific EOI
+const OCW2_EOI: u8 = 0x20;
+/// OCW3: Read the In-Service Register on the next read of the command port
+const OCW3_READ_ISR: u8 = 0x0b;
+
+/// The IRQ the master PIC raises spurious IRQs on
+const MASTER_SPURIOUS_IRQ: u8 = 7;
+/// The IRQ the slave PIC raises spurious IRQs on
+const SLAVE_SPURIOUS_IRQ: u8 = 15;
+
+/// What an IRQ coming from the PICs turned out to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IrqKind {
+    /// A real IRQ, which should be handled and acknowledged as usual
+    Real,
+    /// A spurious IRQ from the master, which mustn't be acknowledged
+    MasterSpurious,
+    /// A spurious IRQ from the slave. The master did see a real IRQ on its cascade line, so it
+    /// still has to be acknowledged
+    SlaveSpurious,
+}
+
+/// The port writes needed to remap the PICs to `master_base` and `master_base + 8` and mask all
+/// of their IRQ lines
+const fn init_sequence(master_base: u8) -> [(PicPort, u8); 10] {
+    [
+        (PicPort::MasterCommand, ICW1_INIT),
+        (PicPort::SlaveCommand, ICW1_INIT),
+        (PicPort::MasterData, master_base),
+        (PicPort::SlaveData, master_base + 8),
+        (PicPort::MasterData, ICW3_MASTER),
+        (PicPort::SlaveData, ICW3_SLAVE),
+        (PicPort::MasterData, ICW4_8086),
+        (PicPort::SlaveData, ICW4_8086),
+        (PicPort::MasterData, MASK_ALL),
+        (PicPort::SlaveData, MASK_ALL),
+    ]
+}
+
+/// Remap the PICs to `MASTER_VECTOR_BASE` and `SLAVE_VECTOR_BASE`, and mask off all of their
+/// IRQs, effectively disabling them.
+///
+/// # Safety
+/// This should be called once during interrupt initialization, before the APIC is enabled
+pub unsafe fn remap_and_disable() {
+    for (port, value) in init_sequence(MASTER_VECTOR_BASE) {
+        unsafe { outb_8(port as u16, value) };
+        io_wait();
+    }
+}
+
+/// Tell what the IRQ `irq` is, given the combined In-Service Register `isr` of both PICs (the
+/// master's in the low byte and the slave's in the high one).
+///
+/// A spurious IRQ is raised on the PIC's lowest priority line without that line being in service
+const fn classify(irq: u8, isr: u16) -> IrqKind {
+    let in_service = isr & (1 << irq) != 0;
+    match irq {
+        MASTER_SPURIOUS_IRQ if !in_service => IrqKind::MasterSpurious,
+        SLAVE_SPURIOUS_IRQ if !in_service => IrqKind::SlaveSpurious,
+        _ => IrqKind::Real,
+    }
+}
+
+/// Read the In-Service Registers of both PICs, the master's in the low byte and the slave's in the
+/// high one
+fn read_isr() -> u16 {
+    unsafe {
+        outb_8(PicPort::MasterCommand as u16, OCW3_READ_ISR);
+        outb_8(PicPort::SlaveCommand as u16, OCW3_READ_ISR);
+
+        u16::from_le_bytes([
+            inb_8(PicPort::MasterCommand as u16),
+            inb_8(PicPort::SlaveCommand as u16),
+        ])
+    }
+}
+
+/// Check whether the IRQ received on `vector` (either `MASTER_SPURIOUS_VECTOR` or
+/// `SLAVE_SPURIOUS_VECTOR`) is a spurious IRQ from the PICs, and acknowledge it as needed.
+///
+/// Returns true if it's spurious. The PICs are masked, so it should always be, but if it isn't
+/// it's acknowledged all the same, so it doesn't hold back the PICs' other IRQs
+///
+/// # Panics
+/// Panics if `vector` isn't one of the PICs' spurious vectors
+#[must_use]
+pub fn handle_spurious(vector: u8) -> bool {
+    assert!(
+        vector == MASTER_SPURIOUS_VECTOR || vector == SLAVE_SPURIOUS_VECTOR,
+        "Vector {vector} isn't a PIC spurious vector"
+    );
+
+    let kind = classify(vector - MASTER_VECTOR_BASE, read_isr());
+    unsafe {
+        if kind == IrqKind::Real && vector == SLAVE_SPURIOUS_VECTOR {
+            outb_8(PicPort::SlaveCommand as u16, OCW2_EOI);
+        }
+        // A spurious IRQ from the slave still went through the master's cascade line
+        if kind != IrqKind::MasterSpurious {
+            outb_8(PicPort::MasterCommand as u16, OCW2_EOI);
+        }
+    }
+
+    kind != IrqKind::Real
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_sequence() {
+        let sequence = init_sequence(0x20);
+
+        // ICW1 goes to the command ports, and the rest to the data ports
+        assert_eq!(sequence[0], (PicPort::MasterCommand, 0x11));
+        assert_eq!(sequence[1], (PicPort::SlaveCommand, 0x11));
+        assert_eq!(
+            &sequence[2..],
+            &[
+                (PicPort::MasterData, 0x20),
+                (PicPort::SlaveData, 0x28),
+                (PicPort::MasterData, 0x4),
+                (PicPort::SlaveData, 0x2),
+                (PicPort::MasterData, 0x1),
+                (PicPort::SlaveData, 0x1),
+                (PicPort::MasterData, 0xff),
+                (PicPort::SlaveData, 0xff),
+            ]
+        );
+
+        // The vector bases should move with the requested base
+        let sequence = init_sequence(0x70);
+        assert_eq!(sequence[2], (PicPort::MasterData, 0x70));
+        assert_eq!(sequence[3], (PicPort::SlaveData, 0x78));
+    }
+
+    #[test]
+    fn test_vectors_out_of_dispatch_range() {
+        use crate::arch::x86_64::{
+            apic::lapic::SPURIOUS_VECTOR,
+            event::{FIRST_IRQ_VECTOR, LAST_IRQ_VECTOR},
+        };
+
+        let dispatched = FIRST_IRQ_VECTOR..=LAST_IRQ_VECTOR;
+        for vector in MASTER_VECTOR_BASE..=LAST_VECTOR {
+            assert!(!dispatched.contains(&vector));
+            assert_ne!(vector, SPURIOUS_VECTOR);
+        }
+        assert_eq!(MASTER_SPURIOUS_VECTOR, 0xe7);
+        assert_eq!(SLAVE_SPURIOUS_VECTOR, 0xef);
+    }
+
+    #[test]
+    fn test_spurious_detection() {
+        // IRQ 7 and IRQ 15 are only real if they are in service
+        assert_eq!(classify(7, 0), IrqKind::MasterSpurious);
+        assert_eq!(classify(7, 1 << 7), IrqKind::Real);
+        assert_eq!(classify(15, 1 << 2), IrqKind::SlaveSpurious);
+        assert_eq!(classify(15, (1 << 15) | (1 << 2)), IrqKind::Real);
+
+        // Another line being in service doesn't make them real
+        assert_eq!(classify(7, 1 << 15), IrqKind::MasterSpurious);
+        assert_eq!(classify(15, 1 << 7), IrqKind::SlaveSpurious);
+
+        // The other lines are never spurious
+        assert_eq!(classify(1, 0), IrqKind::Real);
+        assert_eq!(classify(14, 0), IrqKind::Real);
+    }
+}
@@ -0,0 +1,233 @@
+//! Tracking of the interrupt vectors in use, so drivers request a vector instead of hard-coding
+//! one
+
+use core::ops::RangeInclusive;
+
+use utils::{
+    collections::id::tracker::IdTrackerError,
+    sync::spinlock::{SpinLock, SpinLockable},
+};
+
+use super::{apic::lapic::SPURIOUS_VECTOR, pic};
+
+/// The first vector that can be allocated. The vectors below it are taken by exceptions
+pub const FIRST_FREE_VECTOR: u8 = 0x20;
+
+/// The global vector allocator
+static VECTOR_ALLOCATOR: SpinLock<VectorAllocator> = SpinLock::new(VectorAllocator::new());
+
+/// Tracks which of the 256 interrupt vectors are in use
+pub struct VectorAllocator {
+    /// A bit for every vector, set if the vector is in use
+    used: [u64; 4],
+}
+
+impl VectorAllocator {
+    /// Create an allocator with only the reserved vectors in use
+    const fn new() -> Self {
+        let mut allocator = Self { used: [0; 4] };
+        let mut vector = 0;
+        while vector < FIRST_FREE_VECTOR {
+            allocator.set_used(vector, true);
+            vector += 1;
+        }
+        allocator.set_used(SPURIOUS_VECTOR, true);
+        let mut vector = pic::MASTER_VECTOR_BASE;
+        while vector <= pic::LAST_VECTOR {
+            allocator.set_used(vector, true);
+            vector += 1;
+        }
+
+        allocator
+    }
+
+    /// Returns true if `vector` can never be allocated (i.e. it's an exception, the spurious
+    /// vector or one the PICs are remapped to)
+    const fn is_reserved(vector: u8) -> bool {
+        vector < FIRST_FREE_VECTOR
+            || vector == SPURIOUS_VECTOR
+            || (vector >= pic::MASTER_VECTOR_BASE && vector <= pic::LAST_VECTOR)
+    }
+
+    /// Returns true if `vector` is in use
+    const fn is_used(&self, vector: u8) -> bool {
+        self.used[vector as usize / 64] & (1 << (vector % 64)) != 0
+    }
+
+    /// Mark `vector` as used or free
+    const fn set_used(&mut self, vector: u8, used: bool) {
+        let bit = 1 << (vector % 64);
+        if used {
+            self.used[vector as usize / 64] |= bit;
+        } else {
+            self.used[vector as usize / 64] &= !bit;
+        }
+    }
+
+    /// Allocate the first free vector in `range`
+    fn allocate_in(&mut self, range: RangeInclusive<u8>) -> Result<u8, IdTrackerError> {
+        let vector = range
+            .into_iter()
+            .find(|&vector| !self.is_used(vector))
+            .ok_or(IdTrackerError::OutOfIds)?;
+        self.set_used(vector, true);
+
+        Ok(vector)
+    }
+
+    /// Allocate `vector`
+    fn allocate_at(&mut self, vector: u8) -> Result<(), IdTrackerError> {
+        if Self::is_reserved(vector) {
+            return Err(IdTrackerError::InvalidId);
+        }
+        if self.is_used(vector) {
+            return Err(IdTrackerError::IdAlreadyTaken);
+        }
+        self.set_used(vector, true);
+
+        Ok(())
+    }
+
+    /// Free `vector`, so it can be allocated again
+    fn free(&mut self, vector: u8) -> Result<(), IdTrackerError> {
+        if Self::is_reserved(vector) {
+            return Err(IdTrackerError::InvalidId);
+        }
+        if !self.is_used(vector) {
+            return Err(IdTrackerError::IdAlreadyFree);
+        }
+        self.set_used(vector, false);
+
+        Ok(())
+    }
+}
+
+impl SpinLockable for VectorAllocator {}
+
+/// Allocate the first free vector
+///
+/// # Errors
+/// Fails if all of the vectors are in use
+#[must_use = "Not freeing the vector will cause leaking"]
+pub fn allocate_vector() -> Result<u8, IdTrackerError> {
+    VECTOR_ALLOCATOR
+        .lock()
+        .allocate_in(FIRST_FREE_VECTOR..=u8::MAX)
+}
+
+/// Allocate the first free vector in `range`
+///
+/// # Errors
+/// Fails if all of the vectors in `range` are in use
+#[must_use = "Not freeing the vector will cause leaking"]
+pub fn allocate_vector_in(range: RangeInclusive<u8>) -> Result<u8, IdTrackerError> {
+    VECTOR_ALLOCATOR.lock().allocate_in(range)
+}
+
+/// Allocate `vector`, for the few devices whose vector is fixed
+///
+/// # Errors
+/// Fails if `vector` is reserved, or is already in use
+pub fn allocate_vector_at(vector: u8) -> Result<(), IdTrackerError> {
+    VECTOR_ALLOCATOR.lock().allocate_at(vector)
+}
+
+/// Free `vector`, so it can be allocated again
+///
+/// # Safety
+/// Nothing may be delivered on `vector` anymore, since it might be handed out to another device
+///
+/// # Errors
+/// Fails if `vector` is reserved, or isn't in use
+pub unsafe fn free_vector(vector: u8) -> Result<(), IdTrackerError> {
+    VECTOR_ALLOCATOR.lock().free(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_and_free() {
+        let mut allocator = VectorAllocator::new();
+
+        let vector = allocator.allocate_in(FIRST_FREE_VECTOR..=u8::MAX).unwrap();
+        assert_eq!(vector, FIRST_FREE_VECTOR);
+        assert_eq!(
+            allocator.allocate_at(vector),
+            Err(IdTrackerError::IdAlreadyTaken)
+        );
+
+        allocator.allocate_at(0x40).unwrap();
+        assert_eq!(
+            allocator.allocate_at(0x40),
+            Err(IdTrackerError::IdAlreadyTaken)
+        );
+
+        // Freeing makes the vector available again, but only once
+        allocator.free(vector).unwrap();
+        assert_eq!(allocator.free(vector), Err(IdTrackerError::IdAlreadyFree));
+        assert_eq!(
+            allocator.allocate_in(FIRST_FREE_VECTOR..=u8::MAX),
+            Ok(vector)
+        );
+    }
+
+    #[test]
+    fn test_reserved_vectors() {
+        let mut allocator = VectorAllocator::new();
+
+        for vector in [
+            0,
+            14,
+            FIRST_FREE_VECTOR - 1,
+            SPURIOUS_VECTOR,
+            pic::MASTER_VECTOR_BASE,
+            pic::MASTER_SPURIOUS_VECTOR,
+            pic::SLAVE_SPURIOUS_VECTOR,
+        ] {
+            assert_eq!(
+                allocator.allocate_at(vector),
+                Err(IdTrackerError::InvalidId)
+            );
+            assert_eq!(allocator.free(vector), Err(IdTrackerError::InvalidId));
+        }
+
+        // The spurious vector is never handed out, even when it's the only one left in the range
+        assert_eq!(allocator.allocate_in(0xfe..=0xff), Ok(0xfe));
+        assert_eq!(
+            allocator.allocate_in(0xfe..=0xff),
+            Err(IdTrackerError::OutOfIds)
+        );
+
+        // Neither are the PICs' vectors
+        assert_eq!(
+            allocator.allocate_in(pic::MASTER_VECTOR_BASE - 1..=pic::LAST_VECTOR + 1),
+            Ok(pic::MASTER_VECTOR_BASE - 1)
+        );
+        assert_eq!(
+            allocator.allocate_in(pic::MASTER_VECTOR_BASE - 1..=pic::LAST_VECTOR + 1),
+            Ok(pic::LAST_VECTOR + 1)
+        );
+        assert_eq!(
+            allocator.allocate_in(pic::MASTER_VECTOR_BASE..=pic::LAST_VECTOR),
+            Err(IdTrackerError::OutOfIds)
+        );
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let mut allocator = VectorAllocator::new();
+
+        let free_count = (FIRST_FREE_VECTOR..=u8::MAX)
+            .filter(|&vector| !VectorAllocator::is_reserved(vector))
+            .count();
+        for _ in 0..free_count {
+            allocator.allocate_in(FIRST_FREE_VECTOR..=u8::MAX).unwrap();
+        }
+        assert_eq!(
+            allocator.allocate_in(FIRST_FREE_VECTOR..=u8::MAX),
+            Err(IdTrackerError::OutOfIds)
+        );
+    }
+}
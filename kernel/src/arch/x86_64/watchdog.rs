@@ -0,0 +1,271 @@
+//! A watchdog catching hung CPUs (deadlocks, infinite loops...) using the NMI.
+//!
+//! Every CPU bumps its heartbeat whenever it makes progress (i.e. every time the scheduler runs
+//! something). With the `watchdog` feature, a performance counter counting unhalted cycles raises
+//! an NMI every `PERIOD_CYCLES`, and the NMI handler checks that the CPU's heartbeat moved since
+//! the last one. If it didn't move for `STALE_THRESHOLD` NMIs in a row, the CPU is considered hung,
+//! so the state it was interrupted in is dumped and the kernel panics.
+//!
+//! Halted cycles aren't counted, so CPUs sitting idle in `hlt` don't get NMIs at all.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use utils::sync::cache_padded::CachePadded;
+
+use crate::arch::MAX_CPUS;
+
+#[cfg(feature = "watchdog")]
+use super::{
+    CPU_VENDOR, CpuVendor,
+    apic::lapic::{self, LocalApic},
+    cpu::msr::{AmdMsr, IntelMsr, wrmsr},
+    nmi::InterruptFrame,
+};
+
+/// The amount of NMIs in a row a CPU's heartbeat has to not move in for it to be considered hung
+const STALE_THRESHOLD: u32 = 10;
+
+/// The amount of unhalted cycles between NMIs (~0.7s at 3GHz). Writes to the counter are sign
+/// extended from 32 bits on some CPUs, so it can't be more than `2^31`
+#[cfg(feature = "watchdog")]
+const PERIOD_CYCLES: u64 = 1 << 31;
+
+/// `PerfEvtSel` bits: count in CPL 3 and CPL 0, raise an interrupt on overflow, and enable
+#[cfg(feature = "watchdog")]
+const EVTSEL_FLAGS: u64 = (1 << 16) | (1 << 17) | (1 << 20) | (1 << 22);
+
+/// The architectural "unhalted core cycles" event on Intel
+#[cfg(feature = "watchdog")]
+const INTEL_UNHALTED_CYCLES_EVENT: u64 = 0x3c;
+
+/// The "CPU clocks not halted" event on AMD
+#[cfg(feature = "watchdog")]
+const AMD_UNHALTED_CYCLES_EVENT: u64 = 0x76;
+
+/// The amount of times unmasking the NMI is attempted before it's left to the next heartbeat
+const UNMASK_ATTEMPTS: usize = 16;
+
+/// The heartbeats of the CPUs, indexed by APIC ID. Every CPU beats its own all the time, so they
+/// each get a cache line of their own
+static HEARTBEATS: [CachePadded<Heartbeat>; MAX_CPUS] =
+    [const { CachePadded::new(Heartbeat::new()) }; MAX_CPUS];
+
+/// Set for the CPUs whose NMI is still masked, because their local APIC was locked when the last
+/// NMI tried to unmask it. Indexed by APIC ID
+#[cfg(feature = "watchdog")]
+static UNMASK_PENDING: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// A counter a CPU bumps as it makes progress, and what the watchdog last saw of it
+struct Heartbeat {
+    /// The amount of times the CPU made progress
+    beats: AtomicU64,
+    /// `beats` as it was on the last check
+    last_seen: AtomicU64,
+    /// The amount of checks in a row `beats` didn't move in
+    stale_checks: AtomicU32,
+}
+
+impl Heartbeat {
+    const fn new() -> Self {
+        Self {
+            beats: AtomicU64::new(0),
+            last_seen: AtomicU64::new(0),
+            stale_checks: AtomicU32::new(0),
+        }
+    }
+
+    /// Record that the CPU made progress
+    #[inline]
+    fn beat(&self) {
+        self.beats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Check whether the heartbeat moved since the last check. Returns true if it didn't move for
+    /// `threshold` checks in a row
+    fn check(&self, threshold: u32) -> bool {
+        let beats = self.beats.load(Ordering::Relaxed);
+
+        if self.last_seen.swap(beats, Ordering::Relaxed) == beats {
+            self.stale_checks.fetch_add(1, Ordering::Relaxed) + 1 >= threshold
+        } else {
+            self.stale_checks.store(0, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// Call `try_unmask` until it succeeds, up to `UNMASK_ATTEMPTS` times, and record in `pending`
+/// whether the NMI is still masked, so it's retried later on.
+///
+/// Never waits for anything, since the lock `try_unmask` takes might be held by the very code the
+/// NMI interrupted
+fn unmask_or_defer(pending: &AtomicBool, mut try_unmask: impl FnMut() -> bool) {
+    let unmasked = (0..UNMASK_ATTEMPTS).any(|_| {
+        let unmasked = try_unmask();
+        if !unmasked {
+            core::hint::spin_loop();
+        }
+
+        unmasked
+    });
+
+    pending.store(!unmasked, Ordering::Relaxed);
+}
+
+/// Get the index of the calling CPU in `HEARTBEATS`
+#[cfg(feature = "watchdog")]
+fn this_cpu() -> usize {
+    LocalApic::get_this_apic_id() as usize
+}
+
+/// Unmask the NMI the local APIC of `cpu` masks whenever it delivers it, if its lock can be taken
+/// right away. Returns whether it was unmasked
+#[cfg(feature = "watchdog")]
+fn try_unmask_nmi(cpu: usize) -> bool {
+    lapic::try_get_apic(cpu as u32)
+        .map(|lapic| lapic.set_performance_nmi())
+        .is_some()
+}
+
+/// Record that the calling CPU made progress, and unmask its NMI if the last one couldn't. Does
+/// nothing without the `watchdog` feature
+#[inline]
+pub fn heartbeat() {
+    #[cfg(feature = "watchdog")]
+    {
+        let cpu = this_cpu();
+        HEARTBEATS[cpu].beat();
+
+        // The counter already overflowed, so no NMI can come in and race with us while it's masked
+        if UNMASK_PENDING[cpu].load(Ordering::Relaxed) {
+            unmask_or_defer(&UNMASK_PENDING[cpu], || try_unmask_nmi(cpu));
+        }
+    }
+}
+
+/// (Re)load the performance counter so it overflows after `PERIOD_CYCLES`, and unmask the NMI
+/// the local APIC masks whenever it delivers it.
+///
+/// Never panics or waits for a lock, since this runs in the NMI handler. If the local APIC stays
+/// locked, the NMI is unmasked by the next heartbeat instead
+#[cfg(feature = "watchdog")]
+unsafe fn arm() {
+    let cpu = this_cpu();
+    let counter = 0_u64.wrapping_sub(PERIOD_CYCLES);
+    unsafe {
+        match CPU_VENDOR.get() {
+            CpuVendor::Intel => wrmsr(IntelMsr::Ia32Pmc0, counter.into()),
+            CpuVendor::Amd => wrmsr(AmdMsr::PerfCtr0, counter.into()),
+            // `start` checked the vendor before the counter was ever enabled
+            CpuVendor::Invalid => return,
+        }
+    };
+
+    unmask_or_defer(&UNMASK_PENDING[cpu], || try_unmask_nmi(cpu));
+}
+
+/// Start the watchdog on the calling CPU.
+///
+/// # Safety
+/// The performance counter the watchdog uses (counter 0) must not be used by anything else, and
+/// the calling CPU's local APIC must be set up
+///
+/// # Panics
+/// Panics if the CPU vendor wasn't found yet
+#[cfg(feature = "watchdog")]
+pub unsafe fn start() {
+    let cpu = this_cpu();
+    HEARTBEATS[cpu].last_seen.store(
+        HEARTBEATS[cpu].beats.load(Ordering::Relaxed),
+        Ordering::Relaxed,
+    );
+
+    unsafe {
+        match CPU_VENDOR.get() {
+            CpuVendor::Intel => wrmsr(
+                IntelMsr::Ia32PerfEvtSel0,
+                (EVTSEL_FLAGS | INTEL_UNHALTED_CYCLES_EVENT).into(),
+            ),
+            CpuVendor::Amd => wrmsr(
+                AmdMsr::PerfEvtSel0,
+                (EVTSEL_FLAGS | AMD_UNHALTED_CYCLES_EVENT).into(),
+            ),
+            CpuVendor::Invalid => panic!("The CPU vendor wasn't found yet"),
+        }
+
+        arm();
+    };
+}
+
+/// Called on every NMI. Panics if the CPU the NMI interrupted is hung, and sets up the next NMI
+/// otherwise
+#[cfg(feature = "watchdog")]
+pub(super) fn on_nmi(frame: &InterruptFrame) {
+    let cpu = this_cpu();
+
+    assert!(
+        !HEARTBEATS[cpu].check(STALE_THRESHOLD),
+        "Watchdog: CPU {cpu} is hung at {:#x} (rsp {:#x}, rflags {:#x})",
+        frame.rip,
+        frame.rsp,
+        frame.rflags
+    );
+
+    unsafe { arm() };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_heartbeat() {
+        let heartbeat = Heartbeat::new();
+
+        // Making progress between every check
+        for _ in 0..5 {
+            heartbeat.beat();
+            assert!(!heartbeat.check(3));
+        }
+
+        // Stuck: Only the third check in a row without progress reports it
+        assert!(!heartbeat.check(3));
+        assert!(!heartbeat.check(3));
+        assert!(heartbeat.check(3));
+        assert!(heartbeat.check(3));
+
+        // A single beat resets the count
+        heartbeat.beat();
+        assert!(!heartbeat.check(3));
+        assert!(!heartbeat.check(3));
+        assert!(!heartbeat.check(3));
+        assert!(heartbeat.check(3));
+    }
+
+    #[test]
+    fn test_unmask_or_defer() {
+        let pending = AtomicBool::new(false);
+
+        // The lock is taken on the third attempt
+        let mut attempts = 0;
+        unmask_or_defer(&pending, || {
+            attempts += 1;
+            attempts == 3
+        });
+        assert_eq!(attempts, 3);
+        assert!(!pending.load(Ordering::Relaxed));
+
+        // The lock stays taken, so it's left for later without waiting any longer
+        attempts = 0;
+        unmask_or_defer(&pending, || {
+            attempts += 1;
+            false
+        });
+        assert_eq!(attempts, UNMASK_ATTEMPTS);
+        assert!(pending.load(Ordering::Relaxed));
+
+        // And the later attempt clears it
+        unmask_or_defer(&pending, || true);
+        assert!(!pending.load(Ordering::Relaxed));
+    }
+}
@@ -1,9 +1,24 @@
-use core::{marker::PhantomData, num::NonZero, ptr::NonNull};
+use core::{
+    fmt,
+    marker::PhantomData,
+    num::NonZero,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use pmm::PmmAllocator;
-use utils::mem::{PhysAddr, VirtAddr};
+use utils::{
+    boot_info::{BootInfo, MemoryRegion},
+    mem::{PhysAddr, VirtAddr},
+};
 
 use super::vaa::VAA;
 
+/// The most address spaces whose resident pages can be counted
+const MAX_ADDRESS_SPACES: usize = 64;
+
+/// The amount of basic sized frames each address space owns (see `PagingManager::resident_pages`)
+pub(crate) static RESIDENT_PAGES: ResidentPages<MAX_ADDRESS_SPACES> = ResidentPages::new();
+
 pub struct Flags<P>
 where
     P: PagingManager,
@@ -20,17 +35,64 @@ where
     _arch: PhantomData<P>,
 }
 
+/// Errors the paging code might encounter
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PagingError {
-    PageAlreadyPresent,
-    PageNotPresent,
+    /// A page is already mapped at the address
+    PageAlreadyPresent(VirtAddr),
+    /// No page is mapped at the address
+    PageNotPresent(VirtAddr),
     PageFault,
     InvalidPageSize,
+    /// The virtual address isn't aligned to the page size
+    UnalignedVirtualAddress(VirtAddr),
+    /// The physical address isn't aligned to the page size
+    UnalignedPhysicalAddress(PhysAddr),
+    /// The physical address can't be mapped
     InvalidPhysicalAddress,
+    /// The virtual address can't be mapped
     InvalidVirtualAddress,
     InvalidFlags,
     OutOfMemory,
     BadPageCountAndAddressCombination,
+    /// The page at the address isn't mapped right after the previous page's frame, so the frames
+    /// can't be handed over as one range
+    NonContiguousFrames(VirtAddr),
+}
+
+impl fmt::Display for PagingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PageAlreadyPresent(addr) => {
+                write!(f, "a page is already mapped at {:#x}", addr.0)
+            }
+            Self::PageNotPresent(addr) => write!(f, "no page is mapped at {:#x}", addr.0),
+            Self::PageFault => write!(f, "page fault"),
+            Self::InvalidPageSize => write!(f, "invalid page size"),
+            Self::UnalignedVirtualAddress(addr) => write!(
+                f,
+                "virtual address {:#x} is not aligned to the page size",
+                addr.0
+            ),
+            Self::UnalignedPhysicalAddress(addr) => write!(
+                f,
+                "physical address {:#x} is not aligned to the page size",
+                addr.0
+            ),
+            Self::InvalidPhysicalAddress => write!(f, "invalid physical address"),
+            Self::InvalidVirtualAddress => write!(f, "invalid virtual address"),
+            Self::InvalidFlags => write!(f, "the flags can't be used together"),
+            Self::OutOfMemory => write!(f, "out of physical memory"),
+            Self::BadPageCountAndAddressCombination => {
+                write!(f, "the page range crosses a page table boundary")
+            }
+            Self::NonContiguousFrames(addr) => write!(
+                f,
+                "the page at {:#x} isn't mapped right after the previous one",
+                addr.0
+            ),
+        }
+    }
 }
 
 pub trait PagingManager: Sized {
@@ -50,8 +112,46 @@ pub trait PagingManager: Sized {
         age_size: PageSize<Self>,
     ) -> Result<(), PagingError>;
 
+    /// Unmap `page_count` pages starting at `virt_addr` like `unmap_pages`, but leave the physical
+    /// pages they're mapped to allocated, handing them over to the caller (e.g. to remap them, or
+    /// share them copy-on-write). The stale translations are flushed from the TLB.
+    ///
+    /// Returns the physical address the first page was mapped to. The pages must be mapped to
+    /// contiguous frames, so that address covers all of them.
+    unsafe fn unmap_pages_keep_frames(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        page_size: PageSize<Self>,
+    ) -> Result<PhysAddr, PagingError>;
+
+    /// Replace the flags of `page_count` mapped pages starting at `virt_addr`, keeping them mapped
+    /// to the same physical pages. The stale translations are flushed from the TLB.
+    unsafe fn change_flags(
+        virt_addr: VirtAddr,
+        page_count: usize,
+        flags: Flags<Self>,
+        page_size: PageSize<Self>,
+    ) -> Result<(), PagingError>;
+
+    /// Get the physical address `virt_addr` is mapped to (including its offset within the page),
+    /// or `None` if it isn't mapped
     fn translate(virt_addr: VirtAddr) -> Option<PhysAddr>;
 
+    /// Flush the translation of the page containing `virt_addr` from the TLB.
+    ///
+    /// Must be called after a mapping is modified in place (unmapped, remapped, or had its flags
+    /// changed), otherwise the stale translation might still be used.
+    fn flush_address(virt_addr: VirtAddr);
+
+    /// Flush all the (non global) translations from the TLB
+    fn flush_all();
+
+    /// Get the amount of basic sized frames the current address space owns, which are freed when
+    /// they're unmapped.
+    ///
+    /// Pages merely mapped to memory someone else owns (e.g. MMIO) aren't counted.
+    fn resident_pages() -> usize;
+
     fn allocate_pages(
         page_count: usize,
         flags: Flags<Self>,
@@ -86,14 +186,15 @@ pub trait PagingManager: Sized {
         let virt_addr = ptr.into();
         for i in 0..count {
             let addr = virt_addr + (i * page_size.size());
-            let phys_addr = Self::translate(virt_addr).ok_or(PagingError::PageNotPresent)?;
+            let phys_addr =
+                Self::translate(virt_addr).ok_or(PagingError::PageNotPresent(virt_addr))?;
             // XXX: need to make sure we uunmap and then free
             unsafe {
                 Self::unmap_pages(addr, count, page_size)?;
 
                 pmm::get()
                     .free(phys_addr, page_size.to_default_page_count())
-                    .map_err(|_| PagingError::PageNotPresent)?;
+                    .map_err(|_| PagingError::PageNotPresent(addr))?;
             };
         }
 
@@ -108,7 +209,10 @@ pub trait PagingManager: Sized {
     ) -> Result<*mut (), PagingError> {
         let virt_addr = {
             let mut vaa = VAA.lock();
-            vaa.handout(1, page_size.page_alignment())
+            vaa.handout(
+                page_count * page_size.to_default_page_count(),
+                page_size.page_alignment(),
+            )
         };
 
         unsafe {
@@ -118,13 +222,71 @@ pub trait PagingManager: Sized {
         Ok(core::ptr::without_provenance_mut(virt_addr.0))
     }
 
-    #[cfg(feature = "limine")]
-    unsafe fn init_paging_from_limine(
-        mem_map: &[&limine::memory_map::Entry],
-        kernel_virt: VirtAddr,
-        kernel_phys: PhysAddr,
-        used_by_pmm: &limine::memory_map::Entry,
-    );
+    /// Build the kernel's page tables from the boot info's memory map, and switch to them.
+    ///
+    /// `used_by_pmm` is the region the PMM took for its metadata, which must be mapped as well
+    unsafe fn init_paging(boot_info: &BootInfo, used_by_pmm: &MemoryRegion);
+}
+
+/// The amount of frames each address space owns, by the address of its root table.
+///
+/// An address space takes a slot the first time frames are counted for it, and keeps it. Once all
+/// the slots are taken, the frames of other address spaces aren't counted
+pub(crate) struct ResidentPages<const N: usize> {
+    /// The address of the root table each slot counts the frames of, or 0 for free slots
+    roots: [AtomicUsize; N],
+    /// The amount of frames counted in each slot
+    pages: [AtomicUsize; N],
+}
+
+impl<const N: usize> ResidentPages<N> {
+    const fn new() -> Self {
+        Self {
+            roots: [const { AtomicUsize::new(0) }; N],
+            pages: [const { AtomicUsize::new(0) }; N],
+        }
+    }
+
+    /// Get the slot of `root`, or `None` if it has none. A free slot is taken for it if `claim` is
+    /// set
+    fn slot(&self, root: usize, claim: bool) -> Option<&AtomicUsize> {
+        // Slots are never freed, so the first slot that isn't taken by another root is the one
+        for (slot_root, pages) in self.roots.iter().zip(&self.pages) {
+            let taken_by = match slot_root.load(Ordering::Acquire) {
+                0 if claim => slot_root
+                    .compare_exchange(0, root, Ordering::AcqRel, Ordering::Acquire)
+                    .map_or_else(|taken_by| taken_by, |_| root),
+                0 => return None,
+                taken_by => taken_by,
+            };
+
+            if taken_by == root {
+                return Some(pages);
+            }
+        }
+
+        None
+    }
+
+    /// Count `pages` more frames as owned by the address space of `root`
+    pub(crate) fn add(&self, root: usize, pages: usize) {
+        if let Some(slot) = self.slot(root, true) {
+            slot.fetch_add(pages, Ordering::Relaxed);
+        }
+    }
+
+    /// Count `pages` frames as no longer owned by the address space of `root`
+    pub(crate) fn sub(&self, root: usize, pages: usize) {
+        if let Some(slot) = self.slot(root, false) {
+            slot.fetch_sub(pages, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the amount of frames the address space of `root` owns
+    pub(crate) fn get(&self, root: usize) -> usize {
+        self.slot(root, false)
+            .map_or(0, |slot| slot.load(Ordering::Relaxed))
+    }
 }
 
 #[inline]
@@ -151,6 +313,190 @@ where
     unsafe { P::free_pages(ptr, count, page_size) }
 }
 
+/// Unmaps a temporary mapping and hands its virtual addresses back when dropped, so they are
+/// released even if the code using the mapping panics
+struct TempMapping<P>
+where
+    P: PagingManager,
+{
+    virt_addr: VirtAddr,
+    page_count: usize,
+    _arch: PhantomData<P>,
+}
+
+impl<P> Drop for TempMapping<P>
+where
+    P: PagingManager,
+{
+    fn drop(&mut self) {
+        // The pages were mapped by `with_temp_mapping`, so unmapping them can't fail
+        let _ = unsafe { P::unmap_pages(self.virt_addr, self.page_count, P::BASIC_PAGE_SIZE) };
+        VAA.lock().give_back(self.virt_addr, self.page_count);
+    }
+}
+
+/// Map `page_count` pages starting at `phys_addr` to temporary virtual addresses, and run `f` with
+/// the virtual address `phys_addr` is mapped to.
+///
+/// The pages are unmapped and their virtual addresses handed back once `f` returns (or panics), so
+/// short lived mappings don't leak virtual address space.
+///
+/// # Safety
+/// The physical pages must be safe to map with `flags`. `f` must not keep any reference into the
+/// mapping around after it returns
+///
+/// # Errors
+/// Fails if the pages couldn't be mapped, in which case `f` isn't called
+pub unsafe fn with_temp_mapping<P, R>(
+    phys_addr: PhysAddr,
+    page_count: usize,
+    flags: Flags<P>,
+    f: impl FnOnce(VirtAddr) -> R,
+) -> Result<R, PagingError>
+where
+    P: PagingManager,
+{
+    let page_size = P::BASIC_PAGE_SIZE.size();
+    let base = phys_addr.align_down(page_size);
+    let offset = phys_addr - base;
+
+    let virt_addr = VAA.lock().handout(page_count, 1);
+    if let Err(err) =
+        unsafe { P::map_pages_to(base, virt_addr, page_count, flags, P::BASIC_PAGE_SIZE) }
+    {
+        VAA.lock().give_back(virt_addr, page_count);
+        return Err(err);
+    }
+
+    let _mapping = TempMapping::<P> {
+        virt_addr,
+        page_count,
+        _arch: PhantomData,
+    };
+
+    Ok(f(virt_addr + offset))
+}
+
+/// A stack with an unmapped guard page right below it, so overflowing the stack faults instead of
+/// silently overwriting whatever is mapped below it.
+///
+/// The stack's frames are freed and its virtual addresses (the guard page's included) are handed
+/// back when it's dropped
+pub struct GuardedStack<P>
+where
+    P: PagingManager,
+{
+    /// The address of the guard page, right below the stack
+    guard_page: VirtAddr,
+    /// The amount of pages the stack spans, not including the guard page
+    page_count: usize,
+    /// The amount of the stack's pages that are mapped, from the bottom up
+    mapped: usize,
+    _arch: PhantomData<P>,
+}
+
+impl<P> GuardedStack<P>
+where
+    P: PagingManager,
+{
+    /// Allocate a stack of `page_count` basic sized pages, mapped with `flags`
+    ///
+    /// # Errors
+    /// Fails with `PagingError::OutOfMemory` if there are no frames left for the stack, or with
+    /// the error of mapping one of its pages. Whatever was allocated by then is freed
+    pub fn new(page_count: usize, flags: Flags<P>) -> Result<Self, PagingError> {
+        Self::new_with(
+            page_count,
+            flags,
+            || {
+                pmm::get()
+                    .allocate(1, 1)
+                    .map_err(|_| PagingError::OutOfMemory)
+            },
+            free_frame,
+        )
+    }
+
+    /// Same as `new`, but the frames are allocated using `allocate_frame`, and freed on failure
+    /// using `free_frame`
+    fn new_with(
+        page_count: usize,
+        flags: Flags<P>,
+        mut allocate_frame: impl FnMut() -> Result<PhysAddr, PagingError>,
+        mut free_frame: impl FnMut(PhysAddr),
+    ) -> Result<Self, PagingError> {
+        let mut stack = Self {
+            guard_page: VAA.lock().handout(page_count + 1, 1),
+            page_count,
+            mapped: 0,
+            _arch: PhantomData,
+        };
+
+        while stack.mapped < page_count {
+            let virt_addr = stack.bottom() + stack.mapped * P::BASIC_PAGE_SIZE.size();
+            let res = allocate_frame().and_then(|frame| {
+                unsafe { P::map_pages_to(frame, virt_addr, 1, flags, P::BASIC_PAGE_SIZE) }
+                    .inspect_err(|_| free_frame(frame))
+            });
+
+            if let Err(err) = res {
+                // The virtual addresses are handed back once the stack is dropped
+                stack.unmap_with(free_frame);
+                return Err(err);
+            }
+            stack.mapped += 1;
+        }
+
+        Ok(stack)
+    }
+
+    /// Get the address right above the stack, which is where it starts since it grows down
+    #[must_use]
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.page_count * P::BASIC_PAGE_SIZE.size()
+    }
+
+    /// Get the address of the guard page below the stack
+    #[must_use]
+    pub const fn guard_page(&self) -> VirtAddr {
+        self.guard_page
+    }
+
+    /// Get the lowest address of the stack, right above the guard page
+    fn bottom(&self) -> VirtAddr {
+        self.guard_page + P::BASIC_PAGE_SIZE.size()
+    }
+
+    /// Unmap the stack's pages, freeing their frames using `free_frame`
+    fn unmap_with(&mut self, mut free_frame: impl FnMut(PhysAddr)) {
+        for i in 0..self.mapped {
+            let virt_addr = self.bottom() + i * P::BASIC_PAGE_SIZE.size();
+            // The pages were mapped by `new`, so unmapping them can't fail
+            if let Ok(frame) =
+                unsafe { P::unmap_pages_keep_frames(virt_addr, 1, P::BASIC_PAGE_SIZE) }
+            {
+                free_frame(frame);
+            }
+        }
+        self.mapped = 0;
+    }
+}
+
+impl<P> Drop for GuardedStack<P>
+where
+    P: PagingManager,
+{
+    fn drop(&mut self) {
+        self.unmap_with(free_frame);
+        VAA.lock().give_back(self.guard_page, self.page_count + 1);
+    }
+}
+
+/// Give a frame of a `GuardedStack` back to the PMM
+fn free_frame(frame: PhysAddr) {
+    unsafe { pmm::get().free(frame, 1) }.expect("Failed to free stack frame");
+}
+
 impl<P> PageSize<P>
 where
     P: PagingManager,
@@ -252,3 +598,219 @@ where
 }
 
 impl<P> Copy for PageSize<P> where P: PagingManager {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, string::String, vec::Vec};
+
+    #[test]
+    fn test_resident_pages_per_root() {
+        let counts: ResidentPages<2> = ResidentPages::new();
+
+        counts.add(0x1000, 3);
+        counts.add(0x2000, 5);
+        counts.sub(0x1000, 1);
+        assert_eq!(counts.get(0x1000), 2);
+        assert_eq!(counts.get(0x2000), 5);
+
+        // Once the slots are taken, other roots aren't counted
+        counts.add(0x3000, 7);
+        counts.sub(0x3000, 1);
+        assert_eq!(counts.get(0x3000), 0);
+        assert_eq!(counts.get(0x1000), 2);
+    }
+
+    #[test]
+    fn test_paging_error_messages() {
+        let errors = [
+            PagingError::PageAlreadyPresent(VirtAddr(0x1000)),
+            PagingError::PageNotPresent(VirtAddr(0x1000)),
+            PagingError::PageFault,
+            PagingError::InvalidPageSize,
+            PagingError::UnalignedVirtualAddress(VirtAddr(0x1001)),
+            PagingError::UnalignedPhysicalAddress(PhysAddr(0x1001)),
+            PagingError::InvalidPhysicalAddress,
+            PagingError::InvalidVirtualAddress,
+            PagingError::InvalidFlags,
+            PagingError::OutOfMemory,
+            PagingError::BadPageCountAndAddressCombination,
+        ];
+
+        let messages: Vec<String> = errors.iter().map(|err| format!("{err}")).collect();
+        for (i, message) in messages.iter().enumerate() {
+            assert_ne!(message, "");
+            assert!(
+                !messages[..i].contains(message),
+                "Duplicate message: {message}"
+            );
+        }
+
+        // The offending address should be part of the message
+        assert!(messages[0].contains("0x1000"));
+        assert!(messages[5].contains("0x1001"));
+    }
+
+    mod fake {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use utils::sync::spinlock::SpinLock;
+
+        use super::*;
+
+        /// The virtual address and page count of the only mapping the fake arch keeps
+        pub static MAPPED_AT: AtomicUsize = AtomicUsize::new(0);
+        pub static MAPPED_COUNT: AtomicUsize = AtomicUsize::new(0);
+        pub static MAPPED_PHYS: AtomicUsize = AtomicUsize::new(0);
+
+        /// Held by the tests using the fake arch, since its mapping is global
+        pub static LOCK: SpinLock<()> = SpinLock::new(());
+
+        /// An arch that only keeps track of a single mapping
+        pub struct FakeArch;
+
+        impl PagingManager for FakeArch {
+            const BASIC_PAGE_SIZE: PageSize<Self> = PageSize {
+                size: 0x1000,
+                _arch: PhantomData,
+            };
+
+            unsafe fn map_pages_to(
+                phys_addr: PhysAddr,
+                virt_addr: VirtAddr,
+                count: usize,
+                _flags: Flags<Self>,
+                _page_size: PageSize<Self>,
+            ) -> Result<(), PagingError> {
+                if MAPPED_COUNT.load(Ordering::Relaxed) != 0 {
+                    return Err(PagingError::PageAlreadyPresent(virt_addr));
+                }
+                MAPPED_AT.store(virt_addr.0, Ordering::Relaxed);
+                MAPPED_PHYS.store(phys_addr.0, Ordering::Relaxed);
+                MAPPED_COUNT.store(count, Ordering::Relaxed);
+
+                Ok(())
+            }
+
+            unsafe fn unmap_pages(
+                virt_addr: VirtAddr,
+                page_count: usize,
+                _page_size: PageSize<Self>,
+            ) -> Result<(), PagingError> {
+                assert_eq!(MAPPED_AT.load(Ordering::Relaxed), virt_addr.0);
+                assert_eq!(MAPPED_COUNT.load(Ordering::Relaxed), page_count);
+                MAPPED_COUNT.store(0, Ordering::Relaxed);
+
+                Ok(())
+            }
+
+            unsafe fn unmap_pages_keep_frames(
+                virt_addr: VirtAddr,
+                page_count: usize,
+                page_size: PageSize<Self>,
+            ) -> Result<PhysAddr, PagingError> {
+                unsafe { Self::unmap_pages(virt_addr, page_count, page_size)? };
+
+                Ok(PhysAddr(MAPPED_PHYS.load(Ordering::Relaxed)))
+            }
+
+            unsafe fn change_flags(
+                _virt_addr: VirtAddr,
+                _page_count: usize,
+                _flags: Flags<Self>,
+                _page_size: PageSize<Self>,
+            ) -> Result<(), PagingError> {
+                Ok(())
+            }
+
+            fn translate(virt_addr: VirtAddr) -> Option<PhysAddr> {
+                let start = MAPPED_AT.load(Ordering::Relaxed);
+                let size = MAPPED_COUNT.load(Ordering::Relaxed) * 0x1000;
+                (start..start + size)
+                    .contains(&virt_addr.0)
+                    .then(|| PhysAddr(MAPPED_PHYS.load(Ordering::Relaxed) + (virt_addr.0 - start)))
+            }
+
+            fn flush_address(_virt_addr: VirtAddr) {}
+
+            fn flush_all() {}
+
+            fn resident_pages() -> usize {
+                0
+            }
+
+            unsafe fn init_paging(_boot_info: &BootInfo, _used_by_pmm: &MemoryRegion) {}
+        }
+    }
+
+    #[test]
+    fn test_temp_mapping_is_gone_after_use() {
+        use fake::FakeArch;
+
+        let _lock = fake::LOCK.lock();
+
+        let flags = unsafe { Flags::<FakeArch>::from_raw(0) };
+        let phys = PhysAddr(0x5_0123);
+
+        let virt = unsafe {
+            with_temp_mapping(phys, 2, flags, |virt| {
+                // The offset into the page is kept
+                assert_eq!(virt.0 % 0x1000, 0x123);
+                assert_eq!(FakeArch::translate(virt), Some(phys));
+                assert_eq!(FakeArch::translate(virt + 0x1000), Some(PhysAddr(0x5_1123)));
+                virt
+            })
+        }
+        .unwrap();
+        assert_eq!(FakeArch::translate(virt), None);
+
+        // The virtual addresses are handed out again rather than leaked
+        let again = unsafe { with_temp_mapping(phys, 2, flags, |virt| virt) }.unwrap();
+        assert_eq!(again, virt);
+        assert_eq!(FakeArch::translate(again), None);
+    }
+
+    #[test]
+    fn test_guarded_stack() {
+        use fake::FakeArch;
+
+        let _lock = fake::LOCK.lock();
+        let flags = unsafe { Flags::<FakeArch>::from_raw(0) };
+        let mut freed = Vec::new();
+
+        let mut stack =
+            GuardedStack::<FakeArch>::new_with(1, flags, || Ok(PhysAddr(0x7000)), |_| {}).unwrap();
+        let guard_page = stack.guard_page();
+        assert_eq!(stack.top(), guard_page + 0x2000);
+        assert_eq!(
+            FakeArch::translate(guard_page + 0x1ff8),
+            Some(PhysAddr(0x7ff8))
+        );
+        assert_eq!(FakeArch::translate(guard_page), None);
+
+        // The frame is freed, and the virtual addresses (the guard page's included) handed back
+        stack.unmap_with(|frame| freed.push(frame));
+        drop(stack);
+        assert_eq!(freed, [PhysAddr(0x7000)]);
+        assert_eq!(FakeArch::translate(guard_page + 0x1000), None);
+
+        let mut again =
+            GuardedStack::<FakeArch>::new_with(1, flags, || Ok(PhysAddr(0x7000)), |_| {}).unwrap();
+        assert_eq!(again.guard_page(), guard_page);
+        again.unmap_with(|_| {});
+        drop(again);
+
+        // The fake arch can't map the second page, so the first one is unmapped again and both
+        // frames are freed
+        freed.clear();
+        let mut frames = [PhysAddr(0x8000), PhysAddr(0x9000)].into_iter();
+        let res = GuardedStack::<FakeArch>::new_with(
+            2,
+            flags,
+            || frames.next().ok_or(PagingError::OutOfMemory),
+            |frame| freed.push(frame),
+        );
+        assert!(matches!(res, Err(PagingError::PageAlreadyPresent(_))));
+        assert_eq!(freed, [PhysAddr(0x9000), PhysAddr(0x8000)]);
+        assert_eq!(fake::MAPPED_COUNT.load(Ordering::Relaxed), 0);
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(not(test))]
+use utils::boot_info::BootInfo;
 use utils::{
     collections::id::{Id, hander::IdHander},
     mem::{HHDM_OFFSET, VirtAddr},
@@ -10,8 +12,55 @@ use crate::arch::BASIC_PAGE_SIZE;
 pub(crate) static VAA: SpinLock<VirtualAddressAllocator> =
     SpinLock::new(VirtualAddressAllocator::uninit());
 
+/// The maximum amount of ranges that can be reserved ahead of the handed out ones at once
+const MAX_RESERVED_RANGES: usize = 16;
+
+/// The maximum amount of handed back ranges kept around to be handed out again
+const MAX_FREED_RANGES: usize = 16;
+
+/// Errors the VAA might encounter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VaaError {
+    /// The passed address isn't page aligned
+    UnalignedAddress,
+    /// The requested range is empty
+    EmptyRange,
+    /// Part of the requested range was already handed out or reserved
+    RangeTaken,
+    /// There is no room left to keep track of another reserved range
+    TooManyReservations,
+}
+
+/// A range of pages that was either reserved (so it shouldn't be handed out), or handed back (so it
+/// can be handed out again)
+#[derive(Debug, Clone, Copy)]
+struct ReservedRange {
+    /// The ID of the first page in the range
+    start: Id,
+    /// The amount of pages in the range
+    count: usize,
+}
+
 pub struct VirtualAddressAllocator {
     hander: IdHander,
+    /// Ranges that were reserved ahead of the handed out ones, and so `handout` should skip
+    reserved: [Option<ReservedRange>; MAX_RESERVED_RANGES],
+    /// Ranges that were handed back, and so `handout` can hand out again
+    freed: [Option<ReservedRange>; MAX_FREED_RANGES],
+}
+
+impl ReservedRange {
+    /// The ID right after the last page in the range
+    #[inline]
+    const fn end(self) -> usize {
+        self.start.0 + self.count
+    }
+
+    /// Returns true if the range overlaps with the `count` pages starting at `start`
+    #[inline]
+    const fn overlaps(self, start: usize, count: usize) -> bool {
+        start < self.end() && self.start.0 < start + count
+    }
 }
 
 impl VirtualAddressAllocator {
@@ -20,7 +69,7 @@ impl VirtualAddressAllocator {
         const MIN_MEM_SPAN: usize = 8 * 0x1000 * 0x1000 * 0x1000 * 0x1000; // 8TB
 
         // Making sure address is page aligned
-        sanity_assert!(start_addr.0 % BASIC_PAGE_SIZE.size() == 0);
+        sanity_assert!(start_addr.is_aligned(BASIC_PAGE_SIZE.size()));
 
         // Make sure we have enough virtual memory space
         assert!(
@@ -33,6 +82,8 @@ impl VirtualAddressAllocator {
         let start_id = Id(start_addr.0 / BASIC_PAGE_SIZE.size());
         Self {
             hander: IdHander::new_starting_from(start_id, Id::MAX_ID),
+            reserved: [None; MAX_RESERVED_RANGES],
+            freed: [None; MAX_FREED_RANGES],
         }
     }
 
@@ -40,36 +91,278 @@ impl VirtualAddressAllocator {
     const fn uninit() -> Self {
         Self {
             hander: IdHander::new(Id(1000)),
+            reserved: [None; MAX_RESERVED_RANGES],
+            freed: [None; MAX_FREED_RANGES],
         }
     }
 
     #[inline]
     pub(super) fn handout(&mut self, count: usize, page_alignment: usize) -> VirtAddr {
+        if let Some(start) = self.handout_freed(count, page_alignment) {
+            return VirtAddr(start * BASIC_PAGE_SIZE.size());
+        }
+
         let next = self.hander.peek_next().0;
-        let skip = (next as *const ()).align_offset(page_alignment);
+        let mut start = next.next_multiple_of(page_alignment);
+
+        // Skip over every reserved range the handed out range would collide with
+        while let Some(range) = self
+            .reserved
+            .iter()
+            .flatten()
+            .find(|range| range.overlaps(start, count))
+        {
+            start = range.end().next_multiple_of(page_alignment);
+        }
 
-        let page_id = self
-            .hander
-            .handout_and_skip(skip + count)
+        self.hander
+            .handout_and_skip(start - next + count)
             .expect("Virtual address allocator ran out of IDs");
 
-        VirtAddr(page_id.0 * BASIC_PAGE_SIZE.size())
+        VirtAddr(start * BASIC_PAGE_SIZE.size())
+    }
+
+    /// Hand out `count` pages from the start of a handed back range, if one fits and is aligned
+    /// to `page_alignment`
+    fn handout_freed(&mut self, count: usize, page_alignment: usize) -> Option<usize> {
+        let slot = self.freed.iter_mut().find(|slot| {
+            slot.is_some_and(|range| {
+                range.count >= count && range.start.0.is_multiple_of(page_alignment)
+            })
+        })?;
+
+        // The check above made sure the slot isn't empty
+        let range = slot.as_mut()?;
+        let start = range.start.0;
+        range.start.0 += count;
+        range.count -= count;
+        if range.count == 0 {
+            *slot = None;
+        }
+
+        Some(start)
+    }
+
+    /// Hands back the `count` pages starting at `base`, so they can be handed out again.
+    ///
+    /// NOTE: If there is no room left to keep track of the range, it's never handed out again
+    pub(super) fn give_back(&mut self, base: VirtAddr, count: usize) {
+        sanity_assert!(base.is_aligned(BASIC_PAGE_SIZE.size()));
+        if count == 0 {
+            return;
+        }
+
+        if let Some(slot) = self.freed.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(ReservedRange {
+                start: Id(base.0 / BASIC_PAGE_SIZE.size()),
+                count,
+            });
+        }
+    }
+
+    /// Reserves the `count` pages starting at `base`, so they are never handed out.
+    ///
+    /// Useful for placing things (e.g. MMIO) at a known, fixed virtual address.
+    ///
+    /// # Errors
+    /// If any part of the range was already handed out or reserved, `VaaError::RangeTaken` is
+    /// returned.
+    pub fn reserve(&mut self, base: VirtAddr, count: usize) -> Result<(), VaaError> {
+        if !base.is_aligned(BASIC_PAGE_SIZE.size()) {
+            return Err(VaaError::UnalignedAddress);
+        } else if count == 0 {
+            return Err(VaaError::EmptyRange);
+        }
+
+        let start = base.0 / BASIC_PAGE_SIZE.size();
+        let next = self.hander.peek_next().0;
+
+        // Everything below the next ID was already handed out
+        if start < next
+            || self
+                .reserved
+                .iter()
+                .flatten()
+                .any(|range| range.overlaps(start, count))
+        {
+            return Err(VaaError::RangeTaken);
+        }
+
+        // Forget about the reserved ranges we already handed out past, to make room for new ones
+        for slot in &mut self.reserved {
+            if slot.is_some_and(|range| range.end() <= next) {
+                *slot = None;
+            }
+        }
+
+        let slot = self
+            .reserved
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(VaaError::TooManyReservations)?;
+        *slot = Some(ReservedRange {
+            start: Id(start),
+            count,
+        });
+
+        Ok(())
     }
 }
 
-#[cfg(feature = "limine")]
-pub unsafe fn init_vaa_from_limine(mem_map: &[&limine::memory_map::Entry]) {
-    assert!(!cfg!(test), "Cannot initialize VAA in test environment");
-    // Get the last entry in the memory map
+/// Reserves the `count` pages starting at `base` in the global VAA, so they are never handed out.
+///
+/// # Errors
+/// See `VirtualAddressAllocator::reserve`
+#[inline]
+pub fn reserve(base: VirtAddr, count: usize) -> Result<(), VaaError> {
+    VAA.lock().reserve(base, count)
+}
 
-    use crate::mem::vaa::{VAA, VirtualAddressAllocator};
-    use utils::mem::VirtAddr;
+/// Initialize the global VAA, handing out addresses from right after the end of the boot info's
+/// memory map
+///
+/// # Safety
+/// Should only be called once during boot, before anything is handed out
+///
+/// # Panics
+/// Panics if the memory map is empty
+// The VAA can't be initialized in the test environment
+#[cfg(not(test))]
+pub unsafe fn init_vaa(boot_info: &BootInfo) {
+    utils::once_per_boot!();
 
-    let last_entry = mem_map.last().unwrap();
-    let addr = VirtAddr(last_entry.base as usize + last_entry.length as usize);
+    // Get the last entry in the memory map
+    let last_entry = boot_info.memory_map().last().unwrap();
+    let addr = VirtAddr(last_entry.end().0);
 
     let mut vaa = VAA.lock();
     *vaa = VirtualAddressAllocator::new(addr);
 }
 
 impl SpinLockable for VirtualAddressAllocator {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START_ID: usize = 0x100;
+
+    fn new_vaa() -> VirtualAddressAllocator {
+        VirtualAddressAllocator {
+            hander: IdHander::new_starting_from(Id(START_ID), Id::MAX_ID),
+            reserved: [None; MAX_RESERVED_RANGES],
+            freed: [None; MAX_FREED_RANGES],
+        }
+    }
+
+    fn page(id: usize) -> VirtAddr {
+        VirtAddr(id * BASIC_PAGE_SIZE.size())
+    }
+
+    #[test]
+    fn test_handout_skips_reserved_range() {
+        let mut vaa = new_vaa();
+
+        vaa.reserve(page(START_ID + 2), 4).unwrap();
+
+        // Fits right before the reserved range
+        assert_eq!(vaa.handout(2, 1), page(START_ID));
+        // Would overlap the reserved range, so it's handed out right after it
+        assert_eq!(vaa.handout(1, 1), page(START_ID + 6));
+    }
+
+    #[test]
+    fn test_handout_skips_reserved_range_aligned() {
+        let mut vaa = new_vaa();
+
+        vaa.reserve(page(0x200), 1).unwrap();
+
+        // The first aligned address after the reserved range
+        assert_eq!(vaa.handout(1, 0x200), page(0x400));
+    }
+
+    #[test]
+    fn test_reserve_handed_out_range() {
+        let mut vaa = new_vaa();
+
+        let _ = vaa.handout(4, 1);
+
+        assert_eq!(vaa.reserve(page(START_ID), 1), Err(VaaError::RangeTaken));
+        assert_eq!(
+            vaa.reserve(page(START_ID + 3), 2),
+            Err(VaaError::RangeTaken)
+        );
+        assert!(vaa.reserve(page(START_ID + 4), 2).is_ok());
+    }
+
+    #[test]
+    fn test_reserve_overlapping_ranges() {
+        let mut vaa = new_vaa();
+
+        vaa.reserve(page(START_ID + 10), 10).unwrap();
+
+        assert_eq!(
+            vaa.reserve(page(START_ID + 5), 6),
+            Err(VaaError::RangeTaken)
+        );
+        assert_eq!(
+            vaa.reserve(page(START_ID + 19), 1),
+            Err(VaaError::RangeTaken)
+        );
+        assert!(vaa.reserve(page(START_ID + 20), 1).is_ok());
+        assert!(vaa.reserve(page(START_ID + 5), 5).is_ok());
+    }
+
+    #[test]
+    fn test_reserve_invalid_arguments() {
+        let mut vaa = new_vaa();
+
+        assert_eq!(
+            vaa.reserve(VirtAddr(page(START_ID).0 + 1), 1),
+            Err(VaaError::UnalignedAddress)
+        );
+        assert_eq!(vaa.reserve(page(START_ID), 0), Err(VaaError::EmptyRange));
+    }
+
+    #[test]
+    fn test_reserve_too_many_ranges() {
+        let mut vaa = new_vaa();
+
+        for i in 0..MAX_RESERVED_RANGES {
+            vaa.reserve(page(START_ID + 2 * i), 1).unwrap();
+        }
+
+        assert_eq!(
+            vaa.reserve(page(START_ID + 2 * MAX_RESERVED_RANGES), 1),
+            Err(VaaError::TooManyReservations)
+        );
+
+        // Handing out past the reserved ranges frees up their slots
+        let _ = vaa.handout(1, 1);
+        let _ = vaa.handout(1, 1);
+        assert!(
+            vaa.reserve(page(START_ID + 2 * MAX_RESERVED_RANGES), 1)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_given_back_ranges_are_reused() {
+        let mut vaa = new_vaa();
+
+        let first = vaa.handout(3, 1);
+        let second = vaa.handout(1, 1);
+        vaa.give_back(first, 3);
+
+        // Smaller handouts are carved out of the handed back range, until it's used up
+        assert_eq!(vaa.handout(2, 1), first);
+        assert_eq!(vaa.handout(2, 1), page(START_ID + 4));
+        assert_eq!(vaa.handout(1, 1), page(START_ID + 2));
+        assert_eq!(vaa.handout(1, 1), page(START_ID + 6));
+
+        // A misaligned range isn't used for aligned handouts
+        vaa.give_back(second, 1);
+        assert_eq!(vaa.handout(1, 2), page(START_ID + 8));
+        assert_eq!(vaa.handout(1, 1), second);
+    }
+}
@@ -0,0 +1,207 @@
+//! Buffered logging, where log lines are queued and only written out to the sinks on `flush()`.
+//!
+//! Writing to the sinks is slow, and isn't safe from an interrupt handler that might've
+//! interrupted another write. Queueing a line never blocks, so it's safe from anywhere.
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use utils::collections::ring_buffer::RingBuffer;
+
+use super::{Level, sink::SINKS};
+
+/// The max length of a log line, including its newline. Longer lines are truncated
+const LINE_CAPACITY: usize = 128;
+
+/// The amount of log lines that can be buffered at once
+const BUFFERED_LINES: usize = 64;
+
+/// The log lines waiting to be written out
+static LOG_BUFFER: RingBuffer<LogLine, BUFFERED_LINES> = RingBuffer::new();
+
+/// Set while a flush is running, so concurrent flushes don't interleave lines
+static FLUSHING: AtomicBool = AtomicBool::new(false);
+
+/// The amount of log lines dropped because the buffer was full
+static DROPPED_LINES: AtomicUsize = AtomicUsize::new(0);
+
+/// A formatted log line
+#[derive(Clone, Copy)]
+struct LogLine {
+    /// The line's bytes. Only the first `len` are used
+    bytes: [u8; LINE_CAPACITY],
+    /// The length of the line
+    len: usize,
+    /// The level of the message, if it has one
+    level: Option<Level>,
+}
+
+impl LogLine {
+    /// Format `args` into a line, truncating it if it's too long
+    fn format(level: Option<Level>, args: fmt::Arguments) -> Self {
+        let mut line = Self {
+            bytes: [0; LINE_CAPACITY],
+            len: 0,
+            level,
+        };
+
+        // A truncated line still gets its newline
+        if line.write_fmt(args).is_err() {
+            line.bytes[line.len] = b'\n';
+            line.len += 1;
+        }
+
+        line
+    }
+
+    /// Get the line's bytes
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for LogLine {
+    /// Append `s` to the line, failing if it doesn't fit. One byte is always left for the newline
+    /// of a truncated line
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = LINE_CAPACITY - 1 - self.len;
+        let mut len = s.len().min(room);
+        // Don't cut a character in half
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        if len == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// Format `args` into a line of `level` and queue it in `buffer`, returning false if it was
+/// dropped since the buffer is full
+fn enqueue<const N: usize>(
+    buffer: &RingBuffer<LogLine, N>,
+    level: Option<Level>,
+    args: fmt::Arguments,
+) -> bool {
+    if buffer.push(LogLine::format(level, args)).is_err() {
+        DROPPED_LINES.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+
+    true
+}
+
+/// Pass the lines queued in `buffer` (along with their levels) to `sink` in order, until the
+/// buffer is empty
+fn drain<const N: usize>(
+    buffer: &RingBuffer<LogLine, N>,
+    mut sink: impl FnMut(Option<Level>, &[u8]),
+) {
+    while let Some(line) = buffer.pop() {
+        sink(line.level, line.as_bytes());
+    }
+}
+
+/// Queue a log line of `level`
+pub(super) fn push(level: Option<Level>, args: fmt::Arguments) {
+    enqueue(&LOG_BUFFER, level, args);
+}
+
+/// Write out the queued log lines, unless another flush is already doing so
+pub(super) fn flush() {
+    if FLUSHING
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        return;
+    }
+
+    drain(&LOG_BUFFER, |level, line| SINKS.write(level, line));
+
+    FLUSHING.store(false, Ordering::Release);
+}
+
+/// Write out the queued log lines even if another flush is running, e.g. one a panic interrupted
+/// (which will never finish). The lines are still written out whole, but might be out of order
+/// with the ones the other flush is writing
+pub(super) fn force_flush() {
+    drain(&LOG_BUFFER, |level, line| SINKS.write(level, line));
+}
+
+/// Get the amount of log lines dropped because the buffer was full
+pub(super) fn dropped_lines() -> usize {
+    DROPPED_LINES.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_isr_logging_is_deferred() {
+        let buffer: RingBuffer<LogLine, 4> = RingBuffer::new();
+        let mut output = Vec::new();
+
+        // Log from "regular" code, then from an "ISR" that interrupted it. Nothing is written out
+        // until the flush, so the sink isn't touched
+        assert!(enqueue(&buffer, None, format_args!("first {}\n", 1)));
+        assert!(enqueue(&buffer, None, format_args!("from isr {}\n", 2)));
+        assert_eq!(output, b"");
+
+        drain(&buffer, |_, line| output.extend_from_slice(line));
+        assert_eq!(output, b"first 1\nfrom isr 2\n");
+
+        // Everything was written out
+        drain(&buffer, |_, _| panic!("Buffer should be empty"));
+    }
+
+    #[test]
+    fn test_full_buffer_and_long_lines() {
+        let buffer: RingBuffer<LogLine, 2> = RingBuffer::new();
+        let dropped = dropped_lines();
+
+        let long = "é".repeat(LINE_CAPACITY);
+        assert!(enqueue(&buffer, None, format_args!("{long}\n")));
+        assert!(enqueue(&buffer, None, format_args!("short\n")));
+        assert!(!enqueue(&buffer, None, format_args!("dropped\n")));
+        assert_eq!(dropped_lines(), dropped + 1);
+
+        let mut lines = Vec::new();
+        drain(&buffer, |_, line| lines.push(Vec::from(line)));
+
+        // The long line was cut on a character boundary, and still ends with a newline
+        let truncated = core::str::from_utf8(&lines[0]).unwrap();
+        assert!(truncated.len() <= LINE_CAPACITY);
+        assert!(truncated.ends_with("é\n"));
+        assert_eq!(lines[1], b"short\n");
+    }
+
+    #[test]
+    fn test_force_flush_while_flushing() {
+        let _guard = crate::sink::tests::lock_global_sinks();
+
+        // A flush that's stuck (e.g. the one that panicked) keeps regular flushes out
+        FLUSHING.store(true, Ordering::Relaxed);
+        push(None, format_args!("first\n"));
+        push(None, format_args!("panicked\n"));
+        flush();
+        assert!(LOG_BUFFER.pop().is_some());
+
+        // But not a forced one
+        force_flush();
+        assert!(LOG_BUFFER.pop().is_none());
+
+        FLUSHING.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,97 @@
+//! Dumping bytes in `xxd` style, for inspecting tables and device structures
+
+use core::fmt::{self, Write};
+
+/// The amount of bytes dumped in each row
+const BYTES_PER_ROW: usize = 16;
+
+/// A row of the dump: the offset of the row, its bytes in hex, and its bytes as ASCII (with `.`
+/// for bytes that aren't printable)
+struct Row<'a> {
+    offset: usize,
+    bytes: &'a [u8],
+}
+
+impl fmt::Display for Row<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}:", self.offset)?;
+        for byte in self.bytes {
+            write!(f, " {byte:02x}")?;
+        }
+        // Pad a short last row, so its ASCII column lines up with the ones above it
+        for _ in self.bytes.len()..BYTES_PER_ROW {
+            f.write_str("   ")?;
+        }
+
+        f.write_str("  ")?;
+        for &byte in self.bytes {
+            let character = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            f.write_char(character)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Split `bytes` into the rows of the dump
+fn rows(bytes: &[u8]) -> impl Iterator<Item = Row<'_>> {
+    bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(index, bytes)| Row {
+            offset: index * BYTES_PER_ROW,
+            bytes,
+        })
+}
+
+/// Print `bytes` in `xxd` style, after a line with `label`: 16 bytes a row, each row starting
+/// with its offset and ending with its bytes as ASCII.
+///
+/// Every row is its own log line, so long dumps aren't truncated when logging is buffered.
+///
+/// ```text
+/// RSDP (20 bytes):
+/// 00000000: 52 53 44 20 50 54 52 20 a5 42 4f 43 48 53 20 00  RSD PTR .BOCHS .
+/// 00000010: 20 3f fe 07                                       ?..
+/// ```
+pub fn hexdump(label: &str, bytes: &[u8]) {
+    crate::println!("{label} ({} bytes):", bytes.len());
+    for row in rows(bytes) {
+        crate::println!("{row}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::{
+        self, Level,
+        tests::{CaptureSink, lock_global_sinks},
+    };
+
+    #[test]
+    fn test_hexdump_format() {
+        static CAPTURE: CaptureSink = CaptureSink::new();
+
+        let bytes: [u8; 20] = *b"RSD PTR \xa5BOCHS \0 ?\xfe\x07";
+
+        let _guard = lock_global_sinks();
+        sink::register(&CAPTURE, Level::Error).unwrap();
+        hexdump("RSDP", &bytes);
+        crate::flush();
+        assert_eq!(
+            CAPTURE.take(),
+            b"RSDP (20 bytes):\n\
+              00000000: 52 53 44 20 50 54 52 20 a5 42 4f 43 48 53 20 00  RSD PTR .BOCHS .\n\
+              00000010: 20 3f fe 07                                       ?..\n"
+        );
+
+        hexdump("Empty", &[]);
+        crate::flush();
+        assert_eq!(CAPTURE.take(), b"Empty (0 bytes):\n");
+    }
+}
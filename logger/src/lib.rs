@@ -8,19 +8,29 @@
 // compile_error!("At least one of the 'framebuffer' or 'serial' features must be enabled for the logger module.");
 
 use core::fmt::{self, Write};
+#[cfg(any(feature = "buffered", test))]
+#[cfg_attr(not(feature = "buffered"), allow(dead_code))]
+mod buffered;
+#[cfg(feature = "framebuffer")]
+pub mod font;
 #[cfg(feature = "framebuffer")]
 pub mod framebuffer;
+mod hexdump;
 #[cfg(feature = "serial")]
 pub mod serial;
+pub mod sink;
+
+pub use hexdump::hexdump;
+pub use sink::Level;
 
-/// Empty struct to implement 'Write' on
+/// Empty struct to implement 'Write' on. Writes to all the sinks, regardless of their minimum level
 pub struct Writer;
 
 /// A macro to print to the serial port or framebuffer with a newline
 #[macro_export]
 macro_rules! println {
     ($($arg:tt)*) => {{
-        let _ = core::fmt::Write::write_fmt(&mut $crate::Writer, format_args!("{}\n", format_args!($($arg)*)));
+        $crate::_print(format_args!("{}\n", format_args!($($arg)*)));
     }}
 }
 
@@ -28,7 +38,7 @@ macro_rules! println {
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {
-        $crate::println!("-> INFO: {}", format_args!($($arg)*));
+        $crate::_log($crate::Level::Info, format_args!("-> INFO: {}\n", format_args!($($arg)*)));
     }
 }
 
@@ -36,7 +46,7 @@ macro_rules! info {
 #[macro_export]
 macro_rules! err {
     ($($arg:tt)*) => {
-        $crate::println!("-> ERROR: {}", format_args!($($arg)*));
+        $crate::_log($crate::Level::Error, format_args!("-> ERROR: {}\n", format_args!($($arg)*)));
     }
 }
 
@@ -44,7 +54,7 @@ macro_rules! err {
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)*) => {
-        $crate::println!("-> WARNING: {}", format_args!($($arg)*));
+        $crate::_log($crate::Level::Warning, format_args!("-> WARNING: {}\n", format_args!($($arg)*)));
     }
 }
 
@@ -53,25 +63,61 @@ macro_rules! warn {
 macro_rules! debug {
     ($($arg:tt)*) => {
         #[cfg(debug_assertions)]
-        $crate::println!("-> DEBUG: {}", format_args!($($arg)*));
+        $crate::_log($crate::Level::Debug, format_args!("-> DEBUG: {}\n", format_args!($($arg)*)));
     }
 }
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            #[cfg(feature = "serial")]
-            #[allow(static_mut_refs)]
-            unsafe {
-                serial::SERIAL_WRITER.write_byte_all(byte);
-            };
-            #[cfg(feature = "framebuffer")]
-            #[allow(static_mut_refs)]
-            unsafe {
-                framebuffer::FRAMEBUFFER_WRITER.draw_char(byte).unwrap();
-            };
-        }
+        sink::SINKS.write(None, s.as_bytes());
 
         Ok(())
     }
 }
+
+/// Write a formatted message to the sinks accepting its level (all of them if it has none), or
+/// buffer it in buffered mode
+fn log(level: Option<Level>, args: fmt::Arguments) {
+    #[cfg(feature = "buffered")]
+    buffered::push(level, args);
+    #[cfg(not(feature = "buffered"))]
+    sink::SINKS.log(level, args);
+}
+
+/// Write a formatted message without a level. Used by `println!`
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    log(None, args);
+}
+
+/// Write a formatted message of `level`. Used by the logging macros
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    log(Some(level), args);
+}
+
+/// Write out all the buffered log lines.
+///
+/// Does nothing if the `buffered` feature is disabled, since then everything is written out right
+/// away. Never waits: if another flush is already running, it will write out our lines too.
+pub fn flush() {
+    #[cfg(feature = "buffered")]
+    buffered::flush();
+}
+
+/// Write out all the buffered log lines, even if another flush is running (e.g. one the panic
+/// interrupted, or that panicked itself).
+///
+/// Meant for the panic handler, since `flush` would leave the lines to a flush that will never
+/// finish. Does nothing if the `buffered` feature is disabled.
+pub fn flush_on_panic() {
+    #[cfg(feature = "buffered")]
+    buffered::force_flush();
+}
+
+/// Get the amount of log lines that were dropped because the buffer was full
+#[cfg(feature = "buffered")]
+#[must_use]
+pub fn dropped_lines() -> usize {
+    buffered::dropped_lines()
+}
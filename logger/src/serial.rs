@@ -1,19 +1,74 @@
 //! Simple serial driver for logging purposes
 
+#[cfg(target_arch = "x86_64")]
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-pub(super) static mut SERIAL_WRITER: SerialWriter = SerialWriter {
-    ports: [
-        Some(SerialPort::Comm1),
-        Some(SerialPort::Comm2),
-        Some(SerialPort::Comm3),
-        Some(SerialPort::Comm4),
-        Some(SerialPort::Comm5),
-        Some(SerialPort::Comm6),
-        Some(SerialPort::Comm7),
-        Some(SerialPort::Comm8),
-    ],
-};
+use crate::sink::LogSink;
+
+/// The receiver buffer register (read) and the transmitter holding register (write)
+const DATA_REG: u16 = 0;
+/// The interrupt enable register
+const INTERRUPT_ENABLE_REG: u16 = 1;
+/// The FIFO control register (write only)
+const FIFO_CONTROL_REG: u16 = 2;
+/// The line control register
+const LINE_CONTROL_REG: u16 = 3;
+/// The modem control register
+const MODEM_CONTROL_REG: u16 = 4;
+/// The line status register
+const LINE_STATUS_REG: u16 = 5;
+
+/// FCR bit enabling the FIFOs
+const FCR_ENABLE: u8 = 1 << 0;
+/// FCR bit clearing the receive FIFO
+const FCR_CLEAR_RX: u8 = 1 << 1;
+/// FCR bit clearing the transmit FIFO
+const FCR_CLEAR_TX: u8 = 1 << 2;
+/// The shift of the receive FIFO trigger level in the FCR
+const FCR_TRIGGER_SHIFT: u8 = 6;
+
+/// LSR bit set when there is a received byte to read
+const LSR_DATA_READY: u8 = 1 << 0;
+/// LSR bit set when a received byte was lost since the receive FIFO was full
+const LSR_OVERRUN: u8 = 1 << 1;
+/// LSR bit set when the received byte has a bad parity
+const LSR_PARITY: u8 = 1 << 2;
+/// LSR bit set when the received byte has no valid stop bit
+const LSR_FRAMING: u8 = 1 << 3;
+/// LSR bit set when the transmit FIFO is empty
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+/// The amount of bytes the transmit FIFO of a 16550 holds
+const TX_FIFO_DEPTH: usize = 16;
+
+/// The amount of overrun errors encountered on all ports
+static OVERRUN_ERRORS: AtomicUsize = AtomicUsize::new(0);
+/// The amount of parity errors encountered on all ports
+static PARITY_ERRORS: AtomicUsize = AtomicUsize::new(0);
+/// The amount of framing errors encountered on all ports
+static FRAMING_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) static mut SERIAL_WRITER: SerialWriter = SerialWriter { ports: PORTS };
+
+/// The serial ports that are written to, until `SerialWriter::init` finds they don't work
+#[cfg(all(target_arch = "x86_64", target_os = "none"))]
+const PORTS: [Option<SerialPort>; 8] = [
+    Some(SerialPort::Comm1),
+    Some(SerialPort::Comm2),
+    Some(SerialPort::Comm3),
+    Some(SerialPort::Comm4),
+    Some(SerialPort::Comm5),
+    Some(SerialPort::Comm6),
+    Some(SerialPort::Comm7),
+    Some(SerialPort::Comm8),
+];
+
+// TODO: Support the PL011 UART on aarch64. Until then there are no ports, since the ones above are
+// accessed through x86 I/O ports. Hosted builds (i.e. tests) can't access I/O ports either, so
+// whatever they log isn't written anywhere
+#[cfg(not(all(target_arch = "x86_64", target_os = "none")))]
+const PORTS: [Option<SerialPort>; 8] = [None; 8];
 
 /// Possible errors serial driver could encounter
 #[derive(Debug, Clone, Copy)]
@@ -37,6 +92,31 @@ enum SerialPort {
     Comm8 = 0x4e8,
 }
 
+/// The amount of bytes the receive FIFO should hold before raising an interrupt
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FifoTriggerLevel {
+    One = 0,
+    Four = 1,
+    Eight = 2,
+    Fourteen = 3,
+}
+
+/// The errors encountered while receiving bytes, counted across all ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SerialErrorCounts {
+    /// Bytes lost since they arrived while the receive FIFO was full
+    pub overrun: usize,
+    /// Bytes received with a bad parity
+    pub parity: usize,
+    /// Bytes received without a valid stop bit
+    pub framing: usize,
+}
+
+/// The log sink writing to all available serial ports (see `sink::SERIAL_SINK`)
+pub(super) struct SerialSink;
+
 /// A serial writer that writes to all available serial ports
 pub struct SerialWriter {
     ports: [Option<SerialPort>; 8],
@@ -45,34 +125,70 @@ pub struct SerialWriter {
 impl SerialPort {
     /// Initilize serial port. MUST call this before using any serial port
     unsafe fn init(self) -> Result<(), SerialError> {
+        let base = self as u16;
         unsafe {
-            outb_8(self as u16 + 1, 0x00); // Disable all interrupts
-            outb_8(self as u16 + 3, 0x80); // Enable DLAB (set baud rate divisor)
-            outb_8(self as u16, 0x03); // Set divisor to 3 (lo byte) 38400 baud
-            outb_8(self as u16 + 1, 0x00); //                  (hi byte)
-            outb_8(self as u16 + 3, 0x03); // 8 bits, no parity, one stop bit
-            outb_8(self as u16 + 2, 0xC7); // Enable FIFO, clear them, with 14-byte threshold
-            outb_8(self as u16 + 4, 0x0B); // IRQs enabled, RTS/DSR set
-            outb_8(self as u16 + 4, 0x1E); // Set in loopback mode, test the serial chip
-            outb_8(self as u16, 0xAE); // Test serial chip (send byte 0xAE and check if serial returns same byte)
+            outb_8(base + INTERRUPT_ENABLE_REG, 0x00); // Disable all interrupts
+            outb_8(base + LINE_CONTROL_REG, 0x80); // Enable DLAB (set baud rate divisor)
+            outb_8(base + DATA_REG, 0x03); // Set divisor to 3 (lo byte) 38400 baud
+            outb_8(base + INTERRUPT_ENABLE_REG, 0x00); //                  (hi byte)
+            outb_8(base + LINE_CONTROL_REG, 0x03); // 8 bits, no parity, one stop bit
+            // Enable the FIFOs and clear them, so bytes aren't dropped under load
+            outb_8(
+                base + FIFO_CONTROL_REG,
+                fifo_control_value(FifoTriggerLevel::Fourteen),
+            );
+            outb_8(base + MODEM_CONTROL_REG, 0x0B); // IRQs enabled, RTS/DSR set
+            outb_8(base + MODEM_CONTROL_REG, 0x1E); // Set in loopback mode, test the serial chip
+            outb_8(base + DATA_REG, 0xAE); // Test serial chip (send byte 0xAE and check if serial returns same byte)
         }
 
-        if unsafe { inb_8(self as u16) } != 0xae {
+        if unsafe { inb_8(base + DATA_REG) } != 0xae {
             return Err(SerialError::FaultySerialPort);
         }
 
         // If serial port is fine, set it to normal operation mode
-        unsafe { outb_8(self as u16 + 4, 0xf) };
+        unsafe { outb_8(base + MODEM_CONTROL_REG, 0xf) };
 
         Ok(())
     }
 
-    /// Write a byte to serial
-    fn write_byte(self, byte: u8) {
-        if byte == b'\n' {
-            unsafe { outb_8(self as u16, b'\r') };
+    /// Write `bytes` to serial, filling the transmit FIFO whenever it empties
+    fn write_bytes(self, bytes: &[u8]) {
+        feed_tx_fifo(
+            bytes,
+            || self.wait_tx_empty(),
+            |byte| unsafe { outb_8(self as u16 + DATA_REG, byte) },
+        );
+    }
+
+    /// Spin until the transmit FIFO is empty.
+    ///
+    /// Reading the line status register clears the receive errors it reports, so they are counted
+    /// here as well (see `error_counts`)
+    fn wait_tx_empty(self) {
+        loop {
+            let status = unsafe { inb_8(self as u16 + LINE_STATUS_REG) };
+            record_line_errors(status);
+
+            if status & LSR_TX_EMPTY != 0 {
+                break;
+            }
+            core::hint::spin_loop();
         }
-        unsafe { outb_8(self as u16, byte) };
+    }
+
+    /// Read a received byte, if there is one.
+    ///
+    /// Receive errors reported by the line status register are counted (see `error_counts`)
+    fn read_byte(self) -> Option<u8> {
+        let status = unsafe { inb_8(self as u16 + LINE_STATUS_REG) };
+        record_line_errors(status);
+
+        if status & LSR_DATA_READY == 0 {
+            return None;
+        }
+
+        Some(unsafe { inb_8(self as u16 + DATA_REG) })
     }
 }
 
@@ -80,7 +196,7 @@ impl SerialWriter {
     /// Initilize each of the enabled serial ports. If an error occured, mark them as unwriteable
     #[inline]
     pub fn init(&mut self) {
-        for ref mut port_wrapper in self.ports {
+        for port_wrapper in &mut self.ports {
             if let Some(port) = port_wrapper
                 && unsafe { port.init().is_err() }
             {
@@ -89,17 +205,104 @@ impl SerialWriter {
         }
     }
 
-    /// Write a byte to all available serial ports
-    pub(super) fn write_byte_all(&self, byte: u8) {
+    /// Write `bytes` to all available serial ports
+    pub(super) fn write_all(&self, bytes: &[u8]) {
         self.ports.iter().filter_map(|port| *port).for_each(|port| {
-            port.write_byte(byte);
+            port.write_bytes(bytes);
         });
     }
+
+    /// Read a received byte from the first available serial port that has one
+    pub fn read_byte(&self) -> Option<u8> {
+        self.ports
+            .iter()
+            .filter_map(|port| *port)
+            .find_map(SerialPort::read_byte)
+    }
+}
+
+impl LogSink for SerialSink {
+    fn write_bytes(&self, bytes: &[u8]) {
+        #[allow(static_mut_refs)]
+        unsafe {
+            SERIAL_WRITER.write_all(bytes);
+        };
+    }
+}
+
+/// Initialize the serial ports, enabling their FIFOs. Ports that don't work aren't written to from
+/// then on.
+///
+/// # Safety
+/// Must be called once, before anything else is running, since the ports aren't locked
+pub unsafe fn init() {
+    #[allow(static_mut_refs)]
+    unsafe {
+        SERIAL_WRITER.init();
+    };
+}
+
+/// Feed `bytes` to a transmit FIFO through `put`, with newlines turned into `\r\n`.
+///
+/// The FIFO is filled with up to `TX_FIFO_DEPTH` bytes at a time, calling `wait_empty` to wait for
+/// it to empty before each batch, instead of waiting for every single byte
+fn feed_tx_fifo(bytes: &[u8], mut wait_empty: impl FnMut(), mut put: impl FnMut(u8)) {
+    // The amount of bytes the FIFO still has room for
+    let mut room = 0;
+    let mut feed = |byte| {
+        if room == 0 {
+            wait_empty();
+            room = TX_FIFO_DEPTH;
+        }
+        put(byte);
+        room -= 1;
+    };
+
+    for &byte in bytes {
+        if byte == b'\n' {
+            feed(b'\r');
+        }
+        feed(byte);
+    }
+}
+
+/// Get the value of the FIFO control register enabling and clearing the FIFOs, with the receive
+/// FIFO raising an interrupt once it holds `trigger_level` bytes
+#[inline]
+const fn fifo_control_value(trigger_level: FifoTriggerLevel) -> u8 {
+    FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX | ((trigger_level as u8) << FCR_TRIGGER_SHIFT)
+}
+
+/// Count the receive errors reported by the line status register value `status`
+#[inline]
+fn record_line_errors(status: u8) {
+    let errors = [
+        (LSR_OVERRUN, &OVERRUN_ERRORS),
+        (LSR_PARITY, &PARITY_ERRORS),
+        (LSR_FRAMING, &FRAMING_ERRORS),
+    ];
+
+    for (bit, counter) in errors {
+        if status & bit != 0 {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Get the amount of receive errors encountered so far on all ports
+#[must_use]
+pub fn error_counts() -> SerialErrorCounts {
+    SerialErrorCounts {
+        overrun: OVERRUN_ERRORS.load(Ordering::Relaxed),
+        parity: PARITY_ERRORS.load(Ordering::Relaxed),
+        framing: FRAMING_ERRORS.load(Ordering::Relaxed),
+    }
 }
 
 // TODO: Remove these and use a arch lib crate
 
 /// Wrapper for the 'out' instruction, accessing a `u8` port
+#[cfg(target_arch = "x86_64")]
 #[inline]
 unsafe fn outb_8(port: u16, value: u8) {
     unsafe {
@@ -113,6 +316,7 @@ unsafe fn outb_8(port: u16, value: u8) {
 }
 
 /// Wrapper for the 'in' instruction, accessing a `u8` port
+#[cfg(target_arch = "x86_64")]
 #[inline]
 unsafe fn inb_8(port: u16) -> u8 {
     let res: u8;
@@ -127,3 +331,71 @@ unsafe fn inb_8(port: u16) -> u8 {
 
     res
 }
+
+/// There are no I/O ports on other architectures, and no ports in `PORTS` to access either
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn outb_8(_port: u16, _value: u8) {
+    unreachable!("I/O ports are only accessed on x86");
+}
+
+/// There are no I/O ports on other architectures, and no ports in `PORTS` to access either
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn inb_8(_port: u16) -> u8 {
+    unreachable!("I/O ports are only accessed on x86");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_control_value() {
+        assert_eq!(fifo_control_value(FifoTriggerLevel::One), 0x07);
+        assert_eq!(fifo_control_value(FifoTriggerLevel::Four), 0x47);
+        assert_eq!(fifo_control_value(FifoTriggerLevel::Eight), 0x87);
+        assert_eq!(fifo_control_value(FifoTriggerLevel::Fourteen), 0xc7);
+    }
+
+    #[test]
+    fn test_line_error_detection() {
+        let before = error_counts();
+
+        // Data ready and transmitter empty, no errors
+        record_line_errors(LSR_DATA_READY | LSR_TX_EMPTY);
+        assert_eq!(error_counts(), before);
+
+        record_line_errors(LSR_DATA_READY | LSR_OVERRUN);
+        record_line_errors(LSR_OVERRUN | LSR_FRAMING);
+        let after = error_counts();
+        assert_eq!(after.overrun, before.overrun + 2);
+        assert_eq!(after.framing, before.framing + 1);
+        assert_eq!(after.parity, before.parity);
+    }
+
+    #[test]
+    fn test_tx_fifo_batches() {
+        // 39 bytes, one of which is a newline that takes up 2 bytes in the FIFO
+        let line = [b'a'; 38];
+        let mut bytes = [0; 39];
+        bytes[..38].copy_from_slice(&line);
+        bytes[38] = b'\n';
+
+        let mut waits = 0;
+        let mut written = [0; 40];
+        let mut len = 0;
+        feed_tx_fifo(
+            &bytes,
+            || waits += 1,
+            |byte| {
+                written[len] = byte;
+                len += 1;
+            },
+        );
+
+        // The FIFO is only waited on once for every 16 bytes
+        assert_eq!(waits, 3);
+        assert_eq!(len, 40);
+        assert_eq!(written[..38], line);
+        assert_eq!(&written[38..], b"\r\n");
+    }
+}
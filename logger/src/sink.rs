@@ -0,0 +1,333 @@
+//! The registry of sinks log messages are written to, each of which only accepts messages of a
+//! minimum level.
+//!
+//! The serial ports and the framebuffer (when enabled) are registered from the start, and accept
+//! every message until told otherwise (see `set_min_level`)
+
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+
+/// The max amount of sinks that can be registered
+const MAX_SINKS: usize = 8;
+
+/// The sinks messages are written to
+pub(super) static SINKS: Registry<MAX_SINKS> = Registry::with_builtin_sinks();
+
+/// The severity of a log message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Debug = 0,
+    Info = 1,
+    Warning = 2,
+    Error = 3,
+}
+
+impl Level {
+    /// Get the level called `name` (e.g. as passed with `loglevel=`), or `None` if there's no such
+    /// level
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warning" | "warn" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Somewhere log messages can be written to
+pub trait LogSink: Sync {
+    /// Write out `bytes`, which are part of a log message
+    fn write_bytes(&self, bytes: &[u8]);
+}
+
+/// The ID of a registered sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkError {
+    /// There is no room to register another sink
+    RegistryFull,
+    /// No sink with the given ID was registered
+    InvalidSink,
+}
+
+/// Sinks along with the minimum level of the messages each of them accepts
+pub(super) struct Registry<const N: usize> {
+    /// The registered sinks. Entries are only written once, before being counted in `len`
+    sinks: UnsafeCell<[Option<&'static dyn LogSink>; N]>,
+    /// The minimum level of each sink
+    min_levels: [AtomicU8; N],
+    /// The amount of registered sinks
+    len: AtomicUsize,
+    /// Set while a sink is being registered, so concurrent registrations don't get the same entry
+    registering: AtomicBool,
+}
+
+// SAFETY: Entries of `sinks` are never written once they are visible through `len`, and only one
+// entry is written at a time
+unsafe impl<const N: usize> Sync for Registry<N> {}
+
+impl<const N: usize> Registry<N> {
+    /// Create a registry holding the `builtin` sinks, which accept every message
+    const fn new(builtin: &[&'static dyn LogSink]) -> Self {
+        let mut sinks = [None; N];
+        let mut i = 0;
+        while i < builtin.len() {
+            sinks[i] = Some(builtin[i]);
+            i += 1;
+        }
+
+        Self {
+            sinks: UnsafeCell::new(sinks),
+            min_levels: [const { AtomicU8::new(Level::Debug as u8) }; N],
+            len: AtomicUsize::new(builtin.len()),
+            registering: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a registry holding the sinks of the enabled logging methods
+    const fn with_builtin_sinks() -> Self {
+        Self::new(&[
+            #[cfg(feature = "serial")]
+            &super::serial::SerialSink,
+            #[cfg(feature = "framebuffer")]
+            &super::framebuffer::FramebufferSink,
+        ])
+    }
+
+    /// Add `sink`, which will only be passed messages of at least `min_level`
+    fn register(&self, sink: &'static dyn LogSink, min_level: Level) -> Result<SinkId, SinkError> {
+        while self
+            .registering
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        let len = self.len.load(Ordering::Relaxed);
+        let result = if len < N {
+            // SAFETY: The entry isn't visible to anyone else until `len` is bumped, and we're the
+            // only ones registering
+            unsafe { (*self.sinks.get())[len] = Some(sink) };
+            self.min_levels[len].store(min_level as u8, Ordering::Relaxed);
+            self.len.store(len + 1, Ordering::Release);
+
+            Ok(SinkId(len))
+        } else {
+            Err(SinkError::RegistryFull)
+        };
+
+        self.registering.store(false, Ordering::Release);
+
+        result
+    }
+
+    /// Change the minimum level of the messages `id` accepts
+    fn set_min_level(&self, id: SinkId, min_level: Level) -> Result<(), SinkError> {
+        if id.0 >= self.len.load(Ordering::Acquire) {
+            return Err(SinkError::InvalidSink);
+        }
+
+        self.min_levels[id.0].store(min_level as u8, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Get the sinks that accept messages of `level`. Messages without a level go to all of them
+    fn sinks_for(&self, level: Option<Level>) -> impl Iterator<Item = &'static dyn LogSink> + '_ {
+        let len = self.len.load(Ordering::Acquire);
+        // SAFETY: The first `len` entries are never written again
+        let sinks = unsafe { &(&*self.sinks.get())[..len] };
+
+        sinks
+            .iter()
+            .zip(&self.min_levels)
+            .filter(move |(_, min_level)| {
+                level.is_none_or(|level| level as u8 >= min_level.load(Ordering::Relaxed))
+            })
+            .filter_map(|(sink, _)| *sink)
+    }
+
+    /// Write `bytes` to the sinks that accept messages of `level`
+    pub(super) fn write(&self, level: Option<Level>, bytes: &[u8]) {
+        for sink in self.sinks_for(level) {
+            sink.write_bytes(bytes);
+        }
+    }
+
+    /// Format a message of `level` and write it to the sinks that accept it
+    #[cfg_attr(feature = "buffered", allow(dead_code))]
+    pub(super) fn log(&self, level: Option<Level>, args: fmt::Arguments) {
+        let _ = LevelWriter {
+            registry: self,
+            level,
+        }
+        .write_fmt(args);
+    }
+}
+
+/// Writes formatted messages of a single level to a registry's sinks
+#[cfg_attr(feature = "buffered", allow(dead_code))]
+struct LevelWriter<'a, const N: usize> {
+    registry: &'a Registry<N>,
+    level: Option<Level>,
+}
+
+impl<const N: usize> Write for LevelWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.registry.write(self.level, s.as_bytes());
+
+        Ok(())
+    }
+}
+
+/// Register `sink`, which will be passed every message of at least `min_level`.
+///
+/// # Errors
+/// Returns `SinkError::RegistryFull` if there is no room for another sink
+pub fn register(sink: &'static dyn LogSink, min_level: Level) -> Result<SinkId, SinkError> {
+    SINKS.register(sink, min_level)
+}
+
+/// Only pass messages of at least `min_level` to the sink `id` from now on
+///
+/// # Errors
+/// Returns `SinkError::InvalidSink` if no sink with that ID was registered
+pub fn set_min_level(id: SinkId, min_level: Level) -> Result<(), SinkError> {
+    SINKS.set_min_level(id, min_level)
+}
+
+/// The ID of the serial ports' sink
+#[cfg(feature = "serial")]
+pub const SERIAL_SINK: SinkId = SinkId(0);
+
+/// The ID of the framebuffer's sink
+#[cfg(feature = "framebuffer")]
+pub const FRAMEBUFFER_SINK: SinkId = SinkId(if cfg!(feature = "serial") { 1 } else { 0 });
+
+/// Only pass messages of at least `min_level` to the built-in sinks (the serial ports and the
+/// framebuffer, whichever are enabled) from now on
+pub fn set_builtin_min_level(min_level: Level) {
+    let builtin: &[SinkId] = &[
+        #[cfg(feature = "serial")]
+        SERIAL_SINK,
+        #[cfg(feature = "framebuffer")]
+        FRAMEBUFFER_SINK,
+    ];
+
+    for &id in builtin {
+        // The built-in sinks are registered from the start, so they can't be missing
+        let _ = SINKS.set_min_level(id, min_level);
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{
+        sync::{Mutex, MutexGuard, PoisonError},
+        vec::Vec,
+    };
+
+    /// Held by the tests that log through the global sinks, which would see each other's messages
+    /// otherwise
+    static GLOBAL_SINKS: Mutex<()> = Mutex::new(());
+
+    /// Keep the other tests from logging through the global sinks until the guard is dropped
+    pub(crate) fn lock_global_sinks() -> MutexGuard<'static, ()> {
+        GLOBAL_SINKS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A sink that keeps everything written to it
+    pub(crate) struct CaptureSink(Mutex<Vec<u8>>);
+
+    impl CaptureSink {
+        pub(crate) const fn new() -> Self {
+            Self(Mutex::new(Vec::new()))
+        }
+
+        /// Take everything written so far
+        pub(crate) fn take(&self) -> Vec<u8> {
+            core::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl LogSink for CaptureSink {
+        fn write_bytes(&self, bytes: &[u8]) {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+        }
+    }
+
+    #[test]
+    fn test_severity_routing() {
+        static SCREEN: CaptureSink = CaptureSink::new();
+        static SERIAL: CaptureSink = CaptureSink::new();
+
+        let _guard = lock_global_sinks();
+        register(&SCREEN, Level::Debug).unwrap();
+        let serial = register(&SERIAL, Level::Error).unwrap();
+
+        // A warning only goes to the sink that accepts everything
+        crate::warn!("{}", 1);
+        crate::flush();
+        assert_eq!(SCREEN.take(), b"-> WARNING: 1\n");
+        assert_eq!(SERIAL.take(), b"");
+
+        // An error goes to both
+        crate::err!("{}", 2);
+        crate::flush();
+        assert_eq!(SCREEN.take(), b"-> ERROR: 2\n");
+        assert_eq!(SERIAL.take(), b"-> ERROR: 2\n");
+
+        // Once the threshold is lowered, warnings go to both as well
+        set_min_level(serial, Level::Warning).unwrap();
+        crate::warn!("{}", 3);
+        crate::flush();
+        assert_eq!(SERIAL.take(), b"-> WARNING: 3\n");
+
+        // Messages without a level aren't filtered
+        crate::println!("plain");
+        crate::flush();
+        assert_eq!(SCREEN.take(), b"-> WARNING: 3\nplain\n");
+        assert_eq!(SERIAL.take(), b"plain\n");
+
+        assert_eq!(
+            set_min_level(SinkId(MAX_SINKS), Level::Info),
+            Err(SinkError::InvalidSink)
+        );
+    }
+
+    #[test]
+    fn test_registry_full() {
+        static SINK: CaptureSink = CaptureSink::new();
+
+        let registry: Registry<1> = Registry::new(&[]);
+        registry.register(&SINK, Level::Debug).unwrap();
+        assert_eq!(
+            registry.register(&SINK, Level::Info),
+            Err(SinkError::RegistryFull)
+        );
+    }
+
+    #[test]
+    fn test_level_from_name() {
+        assert_eq!(Level::from_name("debug"), Some(Level::Debug));
+        assert_eq!(Level::from_name("info"), Some(Level::Info));
+        assert_eq!(Level::from_name("warn"), Some(Level::Warning));
+        assert_eq!(Level::from_name("warning"), Some(Level::Warning));
+        assert_eq!(Level::from_name("error"), Some(Level::Error));
+        assert_eq!(Level::from_name("Error"), None);
+        assert_eq!(Level::from_name(""), None);
+    }
+}
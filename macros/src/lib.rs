@@ -2,10 +2,10 @@
 
 extern crate alloc;
 
-use alloc::{format, string::ToString};
+use alloc::{format, string::ToString, vec::Vec};
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{ItemFn, parse_macro_input};
+use syn::{Expr, ExprLit, Fields, ItemEnum, ItemFn, Lit, LitInt, Token, parse_macro_input};
 
 /// A macro to make a function an mock/integration testing function
 #[proc_macro_attribute]
@@ -94,3 +94,165 @@ pub fn isr(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+/// Generate the conversions of an enum describing a field of a hardware register (or table entry)
+/// from and to its raw bits. The field's width is passed as `bits = N`, and every variant must
+/// have an explicit discriminant, which is its encoding in the field.
+///
+/// Generates:
+/// - `BITS` and `MASK` constants
+/// - `from_bits`/`into_bits`, and `unpack`/`pack` to get/set the field at a shift in a register
+/// - `TryFrom<u8/u16/u32/u64>` (failing with `()` for values that aren't a variant's encoding) and
+///   `From<Self>` for the same types
+///
+/// ```ignore
+/// #[register_field(bits = 1)]
+/// #[derive(Debug, Clone, Copy)]
+/// pub enum Mask {
+///     Unmasked = 0b0,
+///     Masked = 0b1,
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn register_field(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemEnum);
+
+    let bits = match parse_bits(attr) {
+        Ok(bits) => bits,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let mut variants = Vec::new();
+    let mut encodings = Vec::new();
+    for variant in &input.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return syn::Error::new_spanned(variant, "register fields can't hold data")
+                .to_compile_error()
+                .into();
+        }
+
+        let encoding = match &variant.discriminant {
+            Some((
+                _,
+                Expr::Lit(ExprLit {
+                    lit: Lit::Int(encoding),
+                    ..
+                }),
+            )) => encoding.base10_parse::<u64>(),
+            _ => Err(syn::Error::new_spanned(
+                variant,
+                "register field variants must have an integer literal discriminant",
+            )),
+        };
+        let encoding = match encoding {
+            Ok(encoding) if encoding >> bits != 0 => {
+                return syn::Error::new_spanned(
+                    variant,
+                    format!("encoding doesn't fit in a {bits} bit field"),
+                )
+                .to_compile_error()
+                .into();
+            }
+            Ok(encoding) => encoding,
+            Err(err) => return err.to_compile_error().into(),
+        };
+
+        variants.push(&variant.ident);
+        encodings.push(encoding);
+    }
+
+    let name = &input.ident;
+    let mask = (1_u64 << bits) - 1;
+    let int_types = [quote!(u8), quote!(u16), quote!(u32), quote!(u64)];
+
+    let output = quote! {
+        #input
+
+        impl #name {
+            /// The width of the field in bits
+            pub const BITS: u32 = #bits;
+            /// The bits of the field, before it's shifted into place
+            pub const MASK: u64 = #mask;
+
+            /// Decode the field from its raw bits, or `None` if they don't encode any variant
+            #[inline]
+            #[must_use]
+            pub const fn from_bits(bits: u64) -> Option<Self> {
+                match bits {
+                    #(#encodings => Some(Self::#variants),)*
+                    _ => None,
+                }
+            }
+
+            /// Encode the field into its raw bits
+            #[inline]
+            #[must_use]
+            pub const fn into_bits(self) -> u64 {
+                match self {
+                    #(Self::#variants => #encodings,)*
+                }
+            }
+
+            /// Decode the field at `shift` in `reg`
+            #[inline]
+            #[must_use]
+            pub const fn unpack(reg: u64, shift: u32) -> Option<Self> {
+                Self::from_bits((reg >> shift) & Self::MASK)
+            }
+
+            /// Encode the field at `shift` in `reg`, replacing the bits that were there
+            #[inline]
+            #[must_use]
+            pub const fn pack(self, reg: u64, shift: u32) -> u64 {
+                (reg & !(Self::MASK << shift)) | (self.into_bits() << shift)
+            }
+        }
+
+        #(
+            impl TryFrom<#int_types> for #name {
+                type Error = ();
+
+                #[inline]
+                fn try_from(value: #int_types) -> Result<Self, Self::Error> {
+                    Self::from_bits(u64::from(value)).ok_or(())
+                }
+            }
+
+            impl From<#name> for #int_types {
+                #[inline]
+                fn from(value: #name) -> Self {
+                    // The encodings were checked to fit in the field, which fits in a `u8`
+                    #[allow(clippy::cast_possible_truncation, clippy::unnecessary_cast)]
+                    {
+                        value.into_bits() as #int_types
+                    }
+                }
+            }
+        )*
+    };
+
+    output.into()
+}
+
+/// Parse the `bits = N` argument of `register_field`
+fn parse_bits(attr: TokenStream) -> syn::Result<u32> {
+    let parser = |input: syn::parse::ParseStream| {
+        let key: syn::Ident = input.parse()?;
+        if key != "bits" {
+            return Err(syn::Error::new_spanned(key, "expected `bits = N`"));
+        }
+        input.parse::<Token![=]>()?;
+        let bits: LitInt = input.parse()?;
+        let bits = bits.base10_parse::<u32>()?;
+        if !(1..=8).contains(&bits) {
+            return Err(syn::Error::new(
+                input.span(),
+                "register fields must be 1 to 8 bits wide",
+            ));
+        }
+
+        Ok(bits)
+    };
+
+    syn::parse::Parser::parse(parser, attr)
+}
@@ -1,30 +1,67 @@
 //! A buddy allocator for the PMM
-
-use core::{cmp::min, ptr::NonNull, slice::from_raw_parts_mut};
+//!
+//! The free blocks are tracked with one bitmap per zone, where each bit stands for one block of
+//! the zone's size. This costs 2 bits per page of physical memory in total (one for the first
+//! zone, half a bit for the second, and so on), up to the end of the usable memory. So the metadata
+//! grows with the amount of memory rather than being a fixed size pool:
+//!
+//! | Memory | Metadata |
+//! |--------|----------|
+//! | 1GB    | 64KB     |
+//! | 4GB    | 256KB    |
+//! | 16GB   | 1MB      |
+//! | 64GB   | 4MB      |
+//!
+//! Before this the free blocks were kept in linked lists, with the nodes taken out of a 2MB pool
+//! that was reserved no matter how much memory there was, and that ran out once there were more
+//! than ~87K free blocks.
+
+use core::{cmp::min, iter, ptr::NonNull, slice::from_raw_parts_mut};
 
 use crate::BASIC_PAGE_SIZE;
-#[cfg(feature = "limine")]
-use limine::memory_map::EntryType;
 use utils::{
-    collections::linkedlist::{LinkedList, Node},
+    boot_info::{MemoryRegion, MemoryRegionKind},
+    collections::bitmap::Bitmap,
     mem::PhysAddr,
     sync::spinlock::{SpinLock, SpinLockable},
 };
 
-use super::{PmmAllocator, PmmError};
+use super::{AllocationPolicy, NumaRange, PmmAllocator, PmmError};
+
+/// The maximum amount of NUMA ranges the allocator keeps track of
+const MAX_NUMA_RANGES: usize = 32;
+
+/// The amount of recently freed blocks remembered for `AllocationPolicy::RecentlyFreed`
+const RECENTLY_FREED_COUNT: usize = 16;
 
-#[allow(unused)]
-const FREELIST_BUCKETS_SIZE: usize = 0x0020_0000; // 2MB freelist bucket size
+/// The physical page that's never handed out, so a null physical address (and the virtual
+/// addresses it's mapped to) can always stand for "no page"
+const NULL_PAGE: PhysAddr = PhysAddr(0);
+
+/// The free blocks of a zone. Bit `n` is set if the `n`th block of the zone's size (counting from
+/// address 0) is free as a whole
+type ZoneBitmap<'a> = Bitmap<&'a mut [u8]>;
 
 pub(super) static PMM: SpinLock<BuddyAllocator<'static>> = SpinLock::new(BuddyAllocator::uninit());
 
 /// A buddy allocator for the PMM
 #[derive(Debug)]
 pub(super) struct BuddyAllocator<'a> {
-    /// Array of the zones of the buddy allocator
-    zones: &'a mut [LinkedList<PhysAddr>],
-    /// The freelist of the buddy allocator
-    freelist: LinkedList<PhysAddr>,
+    /// The free blocks of each zone of the buddy allocator
+    zones: &'a mut [ZoneBitmap<'a>],
+    /// The amount of pages that are currently free
+    free_pages: usize,
+    /// The amount of free pages under which `low_memory_callback` is called
+    low_watermark: usize,
+    /// Called when an allocation makes the amount of free pages drop below `low_watermark`
+    low_memory_callback: Option<fn(usize)>,
+    /// How the block to allocate from is picked
+    policy: AllocationPolicy,
+    /// The NUMA node each range of memory belongs to. Free blocks never span more than one node
+    numa_ranges: [Option<NumaRange>; MAX_NUMA_RANGES],
+    /// The address and zone index of the last blocks that were freed, the most recent one last.
+    /// They might have been allocated or merged since
+    recently_freed: [Option<(PhysAddr, usize)>; RECENTLY_FREED_COUNT],
 }
 
 impl PmmAllocator for BuddyAllocator<'_> {
@@ -46,30 +83,135 @@ impl PmmAllocator for BuddyAllocator<'_> {
         let used_index = self.find_bucket_at(addr, start_index)?;
 
         self.disband(addr, start_index, used_index);
+        self.take_free_pages(page_count);
 
         Ok(())
     }
 
-    fn allocate(&mut self, alignment: usize, mut page_count: usize) -> Result<PhysAddr, PmmError> {
-        if page_count == 0 {
+    fn allocate(&mut self, alignment: usize, page_count: usize) -> Result<PhysAddr, PmmError> {
+        self.allocate_from(None, alignment, page_count)
+    }
+
+    fn grow(
+        &mut self,
+        base: PhysAddr,
+        old_pages: usize,
+        new_pages: usize,
+    ) -> Result<PhysAddr, PmmError> {
+        if old_pages == 0 || new_pages == 0 {
             return Err(PmmError::EmptyAllocation);
-        } else if page_count > 2_usize.pow(self.zones.len() as u32) {
-            return Err(PmmError::TooBigAllocation);
-        } else if !alignment.is_power_of_two() {
-            return Err(PmmError::InvalidAlignment);
         }
 
-        page_count = page_count
+        // Blocks are rounded up like allocations are
+        let old_pages = old_pages
             .checked_next_power_of_two()
-            .ok_or(PmmError::NoAvailableBlock)?;
+            .ok_or(PmmError::TooBigAllocation)?;
+        let new_pages = new_pages
+            .checked_next_power_of_two()
+            .ok_or(PmmError::TooBigAllocation)?;
+        if !base.0.is_multiple_of(old_pages * BASIC_PAGE_SIZE) {
+            return Err(PmmError::InvalidAlignment);
+        } else if self.is_page_free(base, old_pages)? {
+            return Err(PmmError::InvalidAddress);
+        }
 
-        let start_index = page_count.ilog2() as usize;
+        // The rounded up block is already big enough
+        if new_pages <= old_pages {
+            return Ok(base);
+        }
 
-        let (used_addr, used_index) = self.find_bucket(alignment, start_index)?;
+        if self.can_grow_in_place(base, old_pages, new_pages) {
+            for (addr, page_count) in Self::growth_blocks(base, old_pages, new_pages) {
+                self.allocate_at(addr, page_count)
+                    .expect("Failed to take a free block while growing in place");
+            }
 
-        self.disband(used_addr, start_index, used_index);
+            return Ok(base);
+        }
 
-        Ok(used_addr)
+        self.allocate(1, new_pages)
+    }
+
+    fn allocate_on_node(
+        &mut self,
+        node: u32,
+        alignment: usize,
+        page_count: usize,
+    ) -> Result<PhysAddr, PmmError> {
+        match self.allocate_from(Some(node), alignment, page_count) {
+            Err(PmmError::NoAvailableBlock) => self.allocate_from(None, alignment, page_count),
+            ret => ret,
+        }
+    }
+
+    fn set_numa_ranges(&mut self, ranges: &[NumaRange]) {
+        if ranges.len() > MAX_NUMA_RANGES {
+            logger::warn!("PMM: More than {MAX_NUMA_RANGES} NUMA ranges, ignoring the rest");
+        }
+
+        self.numa_ranges = [None; MAX_NUMA_RANGES];
+        for (slot, &range) in self.numa_ranges.iter_mut().zip(ranges) {
+            *slot = Some(range);
+        }
+
+        // Free blocks that span more than one node are taken out and freed again, piece by piece
+        for i in 0..self.zones.len() {
+            let block_size = 2_usize.pow(i as u32) * BASIC_PAGE_SIZE;
+            loop {
+                let Some(addr) = self
+                    .zone_blocks(i)
+                    .find(|&addr| self.crosses_boundary(addr, block_size))
+                else {
+                    break;
+                };
+
+                self.take_from_zone(addr, i);
+                self.free_pages -= 2_usize.pow(i as u32);
+                self.free_partitioned(addr, block_size);
+            }
+        }
+    }
+
+    fn set_low_watermark(&mut self, page_count: usize, callback: fn(usize)) {
+        self.low_watermark = page_count;
+        self.low_memory_callback = Some(callback);
+    }
+
+    fn set_allocation_policy(&mut self, policy: AllocationPolicy) {
+        self.policy = policy;
+    }
+
+    fn free_page_count(&self) -> usize {
+        self.free_pages
+    }
+
+    fn free_regions(&self) -> impl Iterator<Item = (PhysAddr, usize)> {
+        // Look for the lowest block from where the last run ended, then keep extending the run
+        // with the block that starts right where it ends.
+        // NOTE: Free blocks never overlap, and the page at the end of the last run isn't free, so
+        // no free block contains the end of the last run without starting after it
+        let mut next = PhysAddr(0);
+        iter::from_fn(move || {
+            let (start, mut page_count) = (0..self.zones.len())
+                .filter_map(|i| {
+                    let index = self.zones[i].first_set_from(Self::block_index(next, i))?;
+                    Some((Self::block_addr(index, i), 2_usize.pow(i as u32)))
+                })
+                .min_by_key(|&(addr, _)| addr)?;
+
+            let run_end = |page_count: usize| PhysAddr(start.0 + page_count * BASIC_PAGE_SIZE);
+            while let Some(i) = (0..self.zones.len()).find(|&i| {
+                let end = run_end(page_count);
+                end.0
+                    .is_multiple_of(2_usize.pow(i as u32) * BASIC_PAGE_SIZE)
+                    && self.is_free_in_zone(end, i)
+            }) {
+                page_count += 2_usize.pow(i as u32);
+            }
+
+            next = run_end(page_count);
+            Some((start, page_count))
+        })
     }
 
     fn is_page_free(&self, addr: PhysAddr, mut page_count: usize) -> Result<bool, PmmError> {
@@ -83,22 +225,17 @@ impl PmmAllocator for BuddyAllocator<'_> {
             .checked_next_power_of_two()
             .ok_or(PmmError::NoAvailableBlock)?;
 
+        // The pages are free if any zone has a free block containing them
         let start_index = page_count.ilog2() as usize;
-        for i in start_index..self.zones.len() {
-            let bucket_size = 2_usize.pow(i as u32) * BASIC_PAGE_SIZE;
-            for &bucket in self.zones[i].iter() {
-                if bucket <= addr && addr < PhysAddr(bucket.0 + bucket_size) {
-                    return Ok(true);
-                }
-            }
-        }
-
-        Ok(false)
+        Ok((start_index..self.zones.len()).any(|i| self.is_free_in_zone(addr, i)))
     }
 
     unsafe fn free(&mut self, addr: PhysAddr, mut page_count: usize) -> Result<(), PmmError> {
         if page_count == 0 {
             return Err(PmmError::EmptyFree);
+        } else if addr == NULL_PAGE {
+            // The null page was never handed out, so it can't be freed either
+            return Err(PmmError::InvalidAddress);
         } else if self.is_page_free(addr, page_count)? {
             return Err(PmmError::FreeOfAlreadyFree);
         }
@@ -110,30 +247,27 @@ impl PmmAllocator for BuddyAllocator<'_> {
         let zone_index = page_count.ilog2() as usize;
 
         self.coalesce(addr, zone_index);
+        self.free_pages += page_count;
 
         Ok(())
     }
 
-    #[cfg(feature = "limine")]
-    unsafe fn init_from_limine<'a>(
-        mem_map: &'a [&'a limine::memory_map::Entry],
-    ) -> &'a limine::memory_map::Entry {
-        let (new_pmm, entry, page_count) = BuddyAllocator::new_from_limine(mem_map);
+    unsafe fn init(memory_map: &[MemoryRegion]) -> MemoryRegion {
+        let (new_pmm, entry, page_count) = BuddyAllocator::new_from_memory_map(memory_map);
         let mut pmm = PMM.lock();
         *pmm = new_pmm;
 
         // Mark all free memory as free
-        for entry in mem_map {
-            if entry.entry_type == EntryType::USABLE {
-                let page_count = entry.length as usize / BASIC_PAGE_SIZE;
-                let addr = PhysAddr(entry.base as usize);
+        for region in memory_map {
+            if region.kind == MemoryRegionKind::Usable {
+                let page_count = region.length / BASIC_PAGE_SIZE;
 
-                pmm.break_into_buckets_n_free(addr, page_count);
+                pmm.break_into_buckets_n_free(region.base, page_count);
             }
         }
 
         for i in 0..page_count {
-            let addr = PhysAddr(entry.base as usize + i * BASIC_PAGE_SIZE);
+            let addr = PhysAddr(entry.base.0 + i * BASIC_PAGE_SIZE);
 
             pmm.allocate_at(addr, 1)
                 .expect("Failed to allocate a page for the buddy allocator");
@@ -143,43 +277,187 @@ impl PmmAllocator for BuddyAllocator<'_> {
     }
 }
 
-impl BuddyAllocator<'_> {
+impl<'a> BuddyAllocator<'a> {
     /// The lowest possible zone level (the zone level of `BASIC_PAGE_SIZE`)
     const MIN_ZONE_LEVEL: usize = BASIC_PAGE_SIZE.ilog2() as usize;
 
     pub(super) const fn uninit() -> Self {
         Self {
             zones: &mut [],
-            freelist: LinkedList::new(),
+            free_pages: 0,
+            low_watermark: 0,
+            low_memory_callback: None,
+            policy: AllocationPolicy::BestFit,
+            numa_ranges: [None; MAX_NUMA_RANGES],
+            recently_freed: [None; RECENTLY_FREED_COUNT],
         }
     }
 
-    /// Tries to find a zone bucket that satisfies the passed `alignment` page alignment, starting
-    /// from the `min_zone_index` zone index.
+    /// Get the index of the block of zone `zone_index` that contains `addr`
+    #[inline]
+    const fn block_index(addr: PhysAddr, zone_index: usize) -> usize {
+        (addr.0 / BASIC_PAGE_SIZE) >> zone_index
+    }
+
+    /// Get the address of the block with index `index` in zone `zone_index`
+    #[inline]
+    const fn block_addr(index: usize, zone_index: usize) -> PhysAddr {
+        PhysAddr((index << zone_index) * BASIC_PAGE_SIZE)
+    }
+
+    /// Returns true if the block of zone `zone_index` that contains `addr` is free
+    #[inline]
+    fn is_free_in_zone(&self, addr: PhysAddr, zone_index: usize) -> bool {
+        // Blocks past the end of memory are never free
+        self.zones[zone_index]
+            .is_set(Self::block_index(addr, zone_index))
+            .unwrap_or(false)
+    }
+
+    /// Get the addresses of the free blocks of zone `zone_index`, from the lowest address up
+    fn zone_blocks(&self, zone_index: usize) -> impl Iterator<Item = PhysAddr> {
+        let zone = &self.zones[zone_index];
+        let mut next = 0;
+        iter::from_fn(move || {
+            let index = zone.first_set_from(next)?;
+            next = index + 1;
+
+            Some(Self::block_addr(index, zone_index))
+        })
+    }
+
+    /// Get the NUMA node `addr` belongs to, or `None` if it doesn't belong to any
+    fn node_of(&self, addr: PhysAddr) -> Option<u32> {
+        self.numa_ranges
+            .iter()
+            .flatten()
+            .find(|range| range.start <= addr && addr < range.end)
+            .map(|range| range.node)
+    }
+
+    /// Returns true if a NUMA range starts or ends inside the `size` bytes block at `addr`
+    fn crosses_boundary(&self, addr: PhysAddr, size: usize) -> bool {
+        let inside = |boundary: PhysAddr| addr < boundary && boundary.0 < addr.0 + size;
+
+        self.numa_ranges
+            .iter()
+            .flatten()
+            .any(|range| inside(range.start) || inside(range.end))
+    }
+
+    /// Free the `size` bytes at `addr`, in pieces that don't cross any NUMA range's boundary
+    fn free_partitioned(&mut self, addr: PhysAddr, size: usize) {
+        let end = addr.0 + size;
+        let mut start = addr.0;
+        while start < end {
+            // The closest page aligned boundary after `start`, or the end of the block
+            let piece_end = self
+                .numa_ranges
+                .iter()
+                .flatten()
+                .flat_map(|range| [range.start.0, range.end.0])
+                .map(|boundary| boundary.next_multiple_of(BASIC_PAGE_SIZE))
+                .filter(|&boundary| start < boundary && boundary < end)
+                .min()
+                .unwrap_or(end);
+
+            self.break_into_buckets_n_free(PhysAddr(start), (piece_end - start) / BASIC_PAGE_SIZE);
+            start = piece_end;
+        }
+    }
+
+    /// Allocate `page_count` pages aligned to `alignment` pages, out of the blocks of NUMA node
+    /// `node`, or out of any block if `node` is `None`
+    fn allocate_from(
+        &mut self,
+        node: Option<u32>,
+        alignment: usize,
+        mut page_count: usize,
+    ) -> Result<PhysAddr, PmmError> {
+        if page_count == 0 {
+            return Err(PmmError::EmptyAllocation);
+        } else if page_count > 2_usize.pow(self.zones.len() as u32) {
+            return Err(PmmError::TooBigAllocation);
+        } else if !alignment.is_power_of_two() {
+            return Err(PmmError::InvalidAlignment);
+        }
+
+        page_count = page_count
+            .checked_next_power_of_two()
+            .ok_or(PmmError::NoAvailableBlock)?;
+
+        let start_index = page_count.ilog2() as usize;
+
+        let (used_addr, used_index) = self.find_bucket(node, alignment, start_index)?;
+        assert_ne!(used_addr, NULL_PAGE, "PMM: The null page was free");
+
+        self.disband(used_addr, start_index, used_index);
+        self.take_free_pages(page_count);
+
+        Ok(used_addr)
+    }
+
+    /// Accounts for `page_count` pages that were just allocated, calling the low memory callback
+    /// if this made the amount of free pages drop below the watermark
+    fn take_free_pages(&mut self, page_count: usize) {
+        let old_free_pages = self.free_pages;
+        self.free_pages = self.free_pages.saturating_sub(page_count);
+
+        // Only calling once when crossing the watermark, and not on every allocation below it
+        if let Some(callback) = self.low_memory_callback
+            && old_free_pages >= self.low_watermark
+            && self.free_pages < self.low_watermark
+        {
+            callback(self.free_pages);
+        }
+    }
+
+    /// Tries to find a zone bucket that satisfies the passed `alignment` page alignment (and
+    /// belongs to NUMA node `node`, if passed), starting from the `min_zone_index` zone index.
+    /// Out of the fitting buckets, the one the allocation policy prefers is taken.
     ///
     /// Returns the physical address of the found zone bucket
     /// and the index of the zone
     fn find_bucket(
         &mut self,
+        node: Option<u32>,
         alignment: usize,
         start_index: usize,
     ) -> Result<(PhysAddr, usize), PmmError> {
-        for i in start_index..self.zones.len() {
-            // Try finding a node that satisfies the page wise alignment
-            if let Some(node) = self.zones[i]
-                .iter_nodes()
-                .enumerate()
-                .find(|&node| node.1.data.0 % (BASIC_PAGE_SIZE * alignment) == 0)
-            {
-                // Save the nodes address so we can return it
-                let ret = node.1.data;
-                self.pop_from_zone(i, node.0);
+        let fits = |addr: &PhysAddr| {
+            addr.0.is_multiple_of(BASIC_PAGE_SIZE * alignment)
+                && (node.is_none() || self.node_of(*addr) == node)
+        };
+        let zones = start_index.min(self.zones.len())..self.zones.len();
+        // The lowest fitting bucket of each zone
+        let lowest = |i: usize| self.zone_blocks(i).find(fits).map(|addr| (i, addr));
+
+        // The zone index and address of the bucket
+        let found = match self.policy {
+            AllocationPolicy::BestFit => zones.clone().find_map(lowest),
+            AllocationPolicy::RecentlyFreed => zones.clone().find_map(|i| {
+                // The buckets that were freed too long ago to be remembered are taken the same
+                // way `BestFit` takes them
+                self.recently_freed
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .find(|&&(addr, zone_index)| {
+                        zone_index == i && self.is_free_in_zone(addr, i) && fits(&addr)
+                    })
+                    .map(|&(addr, _)| (i, addr))
+                    .or_else(|| lowest(i))
+            }),
+            AllocationPolicy::LowestAddress => zones
+                .clone()
+                .filter_map(lowest)
+                .min_by_key(|&(_, addr)| addr),
+        };
 
-                return Ok((ret, i));
-            }
-        }
+        let (i, addr) = found.ok_or(PmmError::NoAvailableBlock)?;
+        self.take_from_zone(addr, i);
 
-        Err(PmmError::NoAvailableBlock)
+        Ok((addr, i))
     }
 
     /// Tries to find a zone bucket that contains the passed `addr`, starting from the
@@ -187,27 +465,20 @@ impl BuddyAllocator<'_> {
     ///
     /// Returns the index of the zone where the bucket was found
     fn find_bucket_at(&mut self, addr: PhysAddr, start_index: usize) -> Result<usize, PmmError> {
-        // Try finding a node that contains the passed `addr`
-        for i in start_index..self.zones.len() {
-            let bucket_size = 2_usize.pow(i as u32) * BASIC_PAGE_SIZE;
-            if let Some(node) = self.zones[i]
-                .iter_nodes()
-                .enumerate()
-                .find(|&node| node.1.data <= addr && addr < PhysAddr(node.1.data.0 + bucket_size))
-            {
-                self.pop_from_zone(i, node.0);
+        let i = (start_index..self.zones.len())
+            .find(|&i| self.is_free_in_zone(addr, i))
+            .ok_or(PmmError::NoAvailableBlock)?;
 
-                return Ok(i);
-            }
-        }
+        let bucket = Self::block_addr(Self::block_index(addr, i), i);
+        self.take_from_zone(bucket, i);
 
-        Err(PmmError::NoAvailableBlock)
+        Ok(i)
     }
 
-    /// Splits the passed `addr` into the freelist, starting from the `bucket_index` and going
+    /// Splits the passed `addr` into the zones, starting from the `bucket_index` and going
     /// down (i.e. The opposite of coalescing)
     fn disband(&mut self, mut addr: PhysAddr, start_index: usize, used_index: usize) {
-        // For each zone under the used index, we push a new node
+        // For each zone under the used index, we push a new block
         for i in start_index..used_index {
             let buddy_addr = Self::get_buddy_addr(addr, i);
             self.push_to_zone(buddy_addr, i);
@@ -215,25 +486,23 @@ impl BuddyAllocator<'_> {
         }
     }
 
-    /// Coalesces the passed `addr` into the freelist, starting from the `min_zone_index` and going
+    /// Coalesces the passed `addr` into the zones, starting from the `min_zone_index` and going
     /// up
     fn coalesce(&mut self, mut addr: PhysAddr, start_index: usize) {
         for i in start_index..self.zones.len() {
             let buddy_addr = Self::get_buddy_addr(addr, i);
-            if let Some(buddy_node) = self.zones[i]
-                .iter_nodes()
-                .enumerate()
-                .find(|&node| node.1.data == buddy_addr)
+            // Blocks of different NUMA nodes are never merged
+            if self.is_free_in_zone(buddy_addr, i) && self.node_of(addr) == self.node_of(buddy_addr)
             {
-                // If the buddy is here, then we can coalesce. Logically this means combining the
-                // two to a node in the next zone level.
-                // What we do is just remove the buddy from the zone, and then after we finished
-                // coalescing with each level, we just push a node to the final level
-                self.pop_from_zone(i, buddy_node.0);
+                // If the buddy is free, then we can coalesce. Logically this means combining the
+                // two to a block in the next zone level.
+                // What we do is just take the buddy out of the zone, and then after we finished
+                // coalescing with each level, we just push a block to the final level
+                self.take_from_zone(buddy_addr, i);
                 addr = Self::determine_next_bucket_addr(addr, buddy_addr);
             } else {
-                // If the buddy isn't here then we can't coalesce anymore, so we're done popping
-                // nodes and we can push the node
+                // If the buddy isn't free then we can't coalesce anymore, so we're done taking
+                // blocks out and we can push the block
                 self.push_to_zone(addr, i);
                 return;
             }
@@ -242,6 +511,28 @@ impl BuddyAllocator<'_> {
         unreachable!();
     }
 
+    /// Get the blocks that make the block of `old_pages` at `base` into a block of `new_pages`
+    /// (both powers of two), as their address and page count: the buddy of the block, then the
+    /// buddy of the block merged with it, and so on
+    fn growth_blocks(
+        base: PhysAddr,
+        old_pages: usize,
+        new_pages: usize,
+    ) -> impl Iterator<Item = (PhysAddr, usize)> {
+        iter::successors(Some(old_pages), |&page_count| Some(page_count * 2))
+            .take_while(move |&page_count| page_count < new_pages)
+            .map(move |page_count| (base + page_count * BASIC_PAGE_SIZE, page_count))
+    }
+
+    /// Returns true if the block of `old_pages` at `base` can grow into a block of `new_pages`
+    /// without moving: it has to be aligned like the bigger block, and the rest of the bigger block
+    /// has to be free
+    fn can_grow_in_place(&self, base: PhysAddr, old_pages: usize, new_pages: usize) -> bool {
+        base.0.is_multiple_of(new_pages * BASIC_PAGE_SIZE)
+            && Self::growth_blocks(base, old_pages, new_pages)
+                .all(|(addr, page_count)| self.is_page_free(addr, page_count) == Ok(true))
+    }
+
     /// Returns the buddy address of the passed `addr` in the passed `zone_index`
     ///
     /// NOTE: This method assumes that the passed `addr` belongs to the passed `zone_index`. An
@@ -267,33 +558,34 @@ impl BuddyAllocator<'_> {
         min(addr, buddy_addr)
     }
 
-    /// Pops a node from the freelist and pushes it to the zone at the passed `zone_index` and
-    /// `buddy_index`
+    /// Marks the free block at `addr` in the zone at the passed `zone_index` as taken
     #[inline]
-    fn pop_from_zone(&mut self, zone_index: usize, node_index: usize) {
-        let node = self.zones[zone_index].remove_at_node(node_index).unwrap();
-
-        unsafe {
-            self.freelist.push_node_back(node);
-        }
+    fn take_from_zone(&mut self, addr: PhysAddr, zone_index: usize) {
+        self.zones[zone_index]
+            .unset(Self::block_index(addr, zone_index))
+            .unwrap();
     }
 
-    /// Pushes the passed `buddy_addr` to the zone at the passed `zone_index`
+    /// Marks the block at `buddy_addr` in the zone at the passed `zone_index` as free
     fn push_to_zone(&mut self, buddy_addr: PhysAddr, zone_index: usize) {
-        // Move the node from the freelist to `zones[zone_index]`
-        let mut buddy = self.freelist.pop_node_back().unwrap();
-        unsafe {
-            buddy.as_mut().data = buddy_addr;
-        }
+        self.zones[zone_index]
+            .set(Self::block_index(buddy_addr, zone_index))
+            .expect("PMM: Freed a block past the end of memory");
 
-        unsafe {
-            self.zones[zone_index].push_node_back(buddy);
-        }
+        self.recently_freed.rotate_left(1);
+        self.recently_freed[RECENTLY_FREED_COUNT - 1] = Some((buddy_addr, zone_index));
     }
 
+    /// Frees the `total_page_count` pages at `addr`, as the biggest blocks that fit them.
+    ///
+    /// The null page is skipped, so it stays taken no matter what the memory map says
     #[allow(unused)]
     fn break_into_buckets_n_free(&mut self, addr: PhysAddr, mut total_page_count: usize) {
         let mut addr_id = addr.0 / BASIC_PAGE_SIZE;
+        if addr == NULL_PAGE && total_page_count != 0 {
+            addr_id += 1;
+            total_page_count -= 1;
+        }
         'outer: loop {
             if total_page_count == 0 {
                 break;
@@ -316,81 +608,134 @@ impl BuddyAllocator<'_> {
         }
     }
 
-    /// Creates a new instance of the `BuddyAllocator`
-    /// TODO: Use the leftover memory as well
-    #[cfg(feature = "limine")]
-    pub fn new_from_limine<'a>(
-        mem_map: &'a [&'a limine::memory_map::Entry],
-    ) -> (Self, &'a limine::memory_map::Entry, usize) {
-        use core::num::NonZero;
+    /// Get the amount of bytes the bitmap of zone `zone_index` takes, for `page_count` pages of
+    /// memory
+    #[inline]
+    const fn zone_bitmap_size(page_count: usize, zone_index: usize) -> usize {
+        page_count.div_ceil(1 << zone_index).div_ceil(8)
+    }
 
-        let zones_count = {
-            let total_page_count = super::get_page_count_from_mem_map(mem_map);
+    /// Get the amount of bytes the allocator's metadata takes, for `page_count` pages of memory
+    /// split into `zones_count` zones
+    const fn metadata_size(page_count: usize, zones_count: usize) -> usize {
+        let mut size = zones_count * size_of::<ZoneBitmap>();
+        let mut i = 0;
+        while i < zones_count {
+            size += Self::zone_bitmap_size(page_count, i);
+            i += 1;
+        }
 
-            total_page_count.ilog2() as usize + 1
-        };
+        size
+    }
 
-        let total_buffer_size = {
-            let zones_size = zones_count * size_of::<LinkedList<PhysAddr>>();
-            let align_to_add = zones_size % align_of::<Node<PhysAddr>>();
+    /// Creates a new instance of the `BuddyAllocator`
+    /// TODO: Use the leftover memory as well
+    pub fn new_from_memory_map(memory_map: &[MemoryRegion]) -> (Self, MemoryRegion, usize) {
+        use core::num::NonZero;
 
-            zones_size + align_to_add + FREELIST_BUCKETS_SIZE
+        let page_count = super::get_page_count_from_mem_map(memory_map);
+        let zones_count = page_count.ilog2() as usize + 1;
+        let total_buffer_size = Self::metadata_size(page_count, zones_count);
+
+        // Find a matching entry in Limine's memory map. The null page stays taken, so it can't
+        // hold the metadata either
+        let entry = memory_map
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+            .map(|&region| {
+                if region.base == NULL_PAGE && region.length != 0 {
+                    MemoryRegion {
+                        base: PhysAddr(BASIC_PAGE_SIZE),
+                        length: region.length.saturating_sub(BASIC_PAGE_SIZE),
+                        ..region
+                    }
+                } else {
+                    region
+                }
+            })
+            .find(|region| region.length >= total_buffer_size)
+            .unwrap();
+
+        // Create a pointer to it. The zones come first, and their bitmaps right after them
+        let zones_ptr =
+            NonNull::without_provenance(NonZero::new(entry.base.add_hhdm_offset().0).unwrap());
+        let bitmaps_size = total_buffer_size - zones_count * size_of::<ZoneBitmap>();
+        // SAFETY: The entry is usable memory that nothing uses yet, and it's big enough
+        let bitmaps = unsafe {
+            from_raw_parts_mut(
+                zones_ptr.add(zones_count).cast::<u8>().as_ptr(),
+                bitmaps_size,
+            )
         };
 
-        // Find a matching entry in Limine's memory map
-        let entry = *mem_map.iter().find(|&&entry| matches!(entry.entry_type, EntryType::USABLE if entry.length as usize >= total_buffer_size)).unwrap();
-
-        // Create a pointer to it
-        let zones_ptr = NonNull::without_provenance(
-            NonZero::new(PhysAddr(entry.base as usize).add_hhdm_offset().0).unwrap(),
-        );
-
         let ret = Self {
-            zones: Self::create_zones(zones_ptr, zones_count),
-            freelist: Self::create_freelist(zones_ptr, zones_count),
+            zones: unsafe { Self::create_zones(zones_ptr, zones_count, bitmaps, page_count) },
+            ..Self::uninit()
         };
 
         // Mark the memory we used as taken
         (ret, entry, total_buffer_size.div_ceil(BASIC_PAGE_SIZE))
     }
 
-    #[allow(unused)]
-    fn create_zones<'a>(
-        zones_ptr: NonNull<LinkedList<PhysAddr>>,
+    /// Creates the `zones_count` zones at `zones_ptr`, with their bitmaps in `bitmaps`, for
+    /// `page_count` pages of memory. All the memory starts out taken.
+    ///
+    /// # Safety
+    /// `zones_ptr` must be valid for writing `zones_count` zones, for as long as the zones live
+    unsafe fn create_zones(
+        zones_ptr: NonNull<ZoneBitmap<'a>>,
         zones_count: usize,
-    ) -> &'a mut [LinkedList<PhysAddr>] {
-        let zones = unsafe { from_raw_parts_mut(zones_ptr.as_ptr(), zones_count) };
-
-        for zone in zones.iter_mut() {
-            *zone = LinkedList::new();
+        mut bitmaps: &'a mut [u8],
+        page_count: usize,
+    ) -> &'a mut [ZoneBitmap<'a>] {
+        bitmaps.fill(0);
+
+        for i in 0..zones_count {
+            let (bitmap, rest) =
+                core::mem::take(&mut bitmaps).split_at_mut(Self::zone_bitmap_size(page_count, i));
+            let zone = Bitmap::from_storage(bitmap, page_count.div_ceil(1 << i));
+
+            unsafe { zones_ptr.add(i).write(zone) };
+            bitmaps = rest;
         }
 
-        zones
+        unsafe { from_raw_parts_mut(zones_ptr.as_ptr(), zones_count) }
     }
+}
 
-    #[allow(unused)]
-    fn create_freelist(
-        zones_ptr: NonNull<LinkedList<PhysAddr>>,
-        max_zone_level: usize,
-    ) -> LinkedList<PhysAddr> {
-        let mut freelist = LinkedList::new();
-        // Push the pointer all the way to the end of the zones array, since this is the start
-        // of the buffer for the freelist nodes
-        let ptr = unsafe { zones_ptr.add(max_zone_level) };
-
-        // Make sure the pointer is aligned to the size of `Node<PhysAddr>`
-        let align_offset = ptr.align_offset(align_of::<Node<PhysAddr>>());
-        let ptr = unsafe { ptr.byte_add(align_offset).cast::<Node<PhysAddr>>() };
-
-        // Push the nodes to the freelist
-        let buckets_count = FREELIST_BUCKETS_SIZE / size_of::<Node<PhysAddr>>();
-        for i in 0..buckets_count {
-            unsafe {
-                freelist.push_node_back(ptr.add(i));
-            }
-        }
+#[cfg(test)]
+impl BuddyAllocator<'static> {
+    /// Creates an allocator managing the `page_count` pages starting from `base`, all free, with
+    /// its metadata on the heap instead of in memory described by a memory map.
+    ///
+    /// The metadata is leaked, so the allocator can be used for as long as the test needs
+    pub(super) fn new_for_test(base: PhysAddr, page_count: usize) -> Self {
+        use alloc::{boxed::Box, vec};
+
+        // The bitmaps count blocks from address 0, so they have to reach the end of the memory.
+        // They cover some memory past the free pages as well, for the tests that look there
+        let covered_pages = base.0 / BASIC_PAGE_SIZE + page_count.max(64);
+        let zones_count = covered_pages.max(1).ilog2() as usize + 1;
+        let bitmaps_size =
+            Self::metadata_size(covered_pages, zones_count) - zones_count * size_of::<ZoneBitmap>();
+
+        let zones = Box::<[ZoneBitmap]>::new_uninit_slice(zones_count);
+        let bitmaps = Box::leak(vec![0_u8; bitmaps_size].into_boxed_slice());
+
+        let mut ret = Self {
+            zones: unsafe {
+                Self::create_zones(
+                    NonNull::new(Box::into_raw(zones).cast()).unwrap(),
+                    zones_count,
+                    bitmaps,
+                    covered_pages,
+                )
+            },
+            ..Self::uninit()
+        };
+        ret.break_into_buckets_n_free(base, page_count);
 
-        freelist
+        ret
     }
 }
 
@@ -402,118 +747,72 @@ impl SpinLockable for BuddyAllocator<'_> {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use alloc::{boxed::Box, vec, vec::Vec};
-    use core::ops::{Deref, DerefMut};
+    use alloc::{vec, vec::Vec};
 
     const BASE_ADDR: PhysAddr = PhysAddr(0x1000000); // 16MB base address for testing
 
-    struct MockAllocator<'a>(BuddyAllocator<'a>);
-
-    impl<'a> Deref for MockAllocator<'a> {
-        type Target = BuddyAllocator<'a>;
-
-        fn deref(&self) -> &Self::Target {
-            &self.0
-        }
-    }
-
-    impl DerefMut for MockAllocator<'_> {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.0
-        }
-    }
-
-    impl Drop for MockAllocator<'_> {
-        fn drop(&mut self) {
-            unsafe {
-                let _ = Box::from_raw(self.0.zones as *mut [LinkedList<PhysAddr>]);
-            };
-        }
-    }
-
-    impl<'a> MockAllocator<'a> {
-        fn new(zones_count: usize, page_count: usize) -> Self {
-            let zones: Box<[LinkedList<PhysAddr>]> = (0..zones_count)
-                .map(|_| LinkedList::new())
-                .collect::<Vec<_>>()
-                .into_boxed_slice();
-            let mut freelist = LinkedList::new();
-
-            // Initialize freelist with dummy nodes
-            for i in 0..400 {
-                let addr = PhysAddr(i * BASIC_PAGE_SIZE);
-                let node = Node::new(addr);
-                unsafe {
-                    freelist.push_node_back(Box::into_non_null(Box::new(node)));
-                };
-            }
-
-            let mut ret = BuddyAllocator {
-                zones: Box::leak(zones),
-                freelist,
-            };
-
-            ret.break_into_buckets_n_free(BASE_ADDR, page_count);
-
-            Self(ret)
+    impl BuddyAllocator<'_> {
+        /// Get the amount of free blocks in the zone at `zone_index`
+        fn zone_len(&self, zone_index: usize) -> usize {
+            self.zone_blocks(zone_index).count()
         }
     }
 
     #[test]
     fn break_into_buckets_n_free_one_page() {
-        let allocator = MockAllocator::new(33, 1);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 1);
 
-        assert_eq!(allocator.zones[0].len(), 1);
+        assert_eq!(allocator.zone_len(0), 1);
 
         // All other zones should be empty
-        for (i, zone) in allocator.zones.iter().enumerate() {
+        for i in 0..allocator.zones.len() {
             if i != 0 {
-                assert_eq!(zone.len(), 0);
+                assert_eq!(allocator.zone_len(i), 0);
             }
         }
     }
 
     #[test]
     fn break_into_buckets_n_free_two_pages() {
-        let allocator = MockAllocator::new(33, 2);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 2);
 
         // 2 pages should create one 2-page bucket in zone 1
-        assert_eq!(allocator.zones[1].len(), 1);
+        assert_eq!(allocator.zone_len(1), 1);
 
         // All other zones should be empty
-        for (i, zone) in allocator.zones.iter().enumerate() {
+        for i in 0..allocator.zones.len() {
             if i != 1 {
-                assert_eq!(zone.len(), 0);
+                assert_eq!(allocator.zone_len(i), 0);
             }
         }
     }
 
     #[test]
     fn break_into_buckets_n_free_three_pages() {
-        let allocator = MockAllocator::new(33, 3);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 3);
 
         // 3 pages = 2 + 1, should have one 2-page bucket and one 1-page bucket
-        assert_eq!(allocator.zones[0].len(), 1);
-        assert_eq!(allocator.zones[1].len(), 1);
+        assert_eq!(allocator.zone_len(0), 1);
+        assert_eq!(allocator.zone_len(1), 1);
     }
 
     #[test]
     fn break_into_buckets_n_free_ten_pages() {
-        let allocator = MockAllocator::new(33, 10);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 10);
 
-        assert_eq!(allocator.zones[1].len(), 1);
-        assert_eq!(allocator.zones[3].len(), 1);
+        assert_eq!(allocator.zone_len(1), 1);
+        assert_eq!(allocator.zone_len(3), 1);
     }
 
     #[test]
     fn break_into_buckets_n_free_no_overlaps() {
-        let allocator = MockAllocator::new(33, 63);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 63);
 
         // Collect all allocated ranges
         let mut ranges = Vec::new();
-        for (i, zone) in allocator.zones.iter().enumerate() {
+        for i in 0..allocator.zones.len() {
             let bucket_size = 2_usize.pow((i + BuddyAllocator::MIN_ZONE_LEVEL) as u32);
-            for addr in zone.iter() {
+            for addr in allocator.zone_blocks(i) {
                 ranges.push((addr.0, addr.0 + bucket_size));
             }
         }
@@ -540,11 +839,11 @@ mod tests {
         ];
 
         for (page_count, expected_buckets) in test_cases {
-            let allocator = MockAllocator::new(33, page_count);
+            let allocator = BuddyAllocator::new_for_test(BASE_ADDR, page_count);
 
             for (bucket_pages, expected_count) in expected_buckets {
                 let zone_index = (bucket_pages as usize).ilog2() as usize;
-                assert_eq!(allocator.zones[zone_index].len(), expected_count);
+                assert_eq!(allocator.zone_len(zone_index), expected_count);
             }
         }
     }
@@ -552,13 +851,13 @@ mod tests {
     #[test]
     fn break_into_buckets_n_free_stress_test() {
         // Test with a very large region
-        let mut allocator = MockAllocator::new(33, 2047);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 2047);
 
         // Verify total page count
         let mut total_pages = 0;
-        for (i, zone) in allocator.zones.iter().enumerate() {
+        for i in 0..allocator.zones.len() {
             let bucket_pages = 2_usize.pow(i as u32);
-            total_pages += zone.len() * bucket_pages;
+            total_pages += allocator.zone_len(i) * bucket_pages;
         }
 
         assert_eq!(total_pages, 2047);
@@ -573,7 +872,7 @@ mod tests {
 
     #[test]
     fn allocate_and_free() {
-        let mut allocator = MockAllocator::new(33, 1000);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 1000);
 
         for _ in 0..200 {
             let addr = allocator.allocate(1, 4).unwrap();
@@ -593,7 +892,7 @@ mod tests {
 
     #[test]
     fn test_allocate_no_available_blocks() {
-        let mut allocator = MockAllocator::new(33, 0);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 0);
 
         // Don't add any free blocks
 
@@ -604,7 +903,7 @@ mod tests {
 
     #[test]
     fn test_free_coalescing() {
-        let mut allocator = MockAllocator::new(33, 1000);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 1000);
 
         // Allocate a few pages
         let addr1 = allocator.allocate(1, 4).unwrap();
@@ -631,7 +930,7 @@ mod tests {
     // Stress coalescing tests
     #[test]
     fn test_stress_coalescing() {
-        let mut allocator = MockAllocator::new(33, 2048);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 2048);
 
         // Allocate many pages of different sizes
         let mut allocations = Vec::new();
@@ -669,7 +968,7 @@ mod tests {
 
     #[test]
     fn test_coalescing_multiple_sizes() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         // Allocate 4 pages of size 4 each (uses up 16 pages total)
         let addr1 = allocator.allocate(1, 4).unwrap();
@@ -678,8 +977,8 @@ mod tests {
         let addr4 = allocator.allocate(1, 4).unwrap();
 
         // All zones should be empty now
-        for zone in allocator.zones.iter() {
-            assert_eq!(zone.len(), 0);
+        for i in 0..allocator.zones.len() {
+            assert_eq!(allocator.zone_len(i), 0);
         }
 
         // Free adjacent pairs to trigger coalescing
@@ -689,7 +988,7 @@ mod tests {
         }
 
         // Should have coalesced into one 8-page block
-        assert_eq!(allocator.zones[3].len(), 1); // 2^3 = 8 pages
+        assert_eq!(allocator.zone_len(3), 1); // 2^3 = 8 pages
 
         unsafe {
             allocator.free(addr3, 4).unwrap();
@@ -697,21 +996,21 @@ mod tests {
         }
 
         // Should have another 8-page block, but they are adjecent so they should coalesce
-        assert_eq!(allocator.zones[3].len(), 0); // 2^3 = 8 pages
-        assert_eq!(allocator.zones[4].len(), 1);
+        assert_eq!(allocator.zone_len(3), 0); // 2^3 = 8 pages
+        assert_eq!(allocator.zone_len(4), 1);
 
         // Now allocate a 16-page block
         allocator.allocate(1, 16).unwrap();
 
-        for zone in allocator.zones.iter() {
-            assert_eq!(zone.len(), 0); // All zones should be empty after allocation
+        for i in 0..allocator.zones.len() {
+            assert_eq!(allocator.zone_len(i), 0); // All zones should be empty after allocation
         }
     }
 
     // Disband tests
     #[test]
     fn test_disband_creates_correct_buddies() {
-        let mut allocator = MockAllocator::new(33, 32);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 32);
 
         // Allocate a 16-page block (zone index 4)
         let addr = allocator.allocate(1, 16).unwrap();
@@ -732,16 +1031,16 @@ mod tests {
 
     #[test]
     fn test_disband_complex_splitting() {
-        let mut allocator = MockAllocator::new(33, 64);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 64);
 
         // Allocate from the largest available block
         let addr1 = allocator.allocate(1, 1).unwrap(); // This will split a large block
 
         // Verify that buddies were created in appropriate zones
         let mut total_free_pages = 0;
-        for (i, zone) in allocator.zones.iter().enumerate() {
+        for i in 0..allocator.zones.len() {
             let bucket_pages = 2_usize.pow(i as u32);
-            total_free_pages += zone.len() * bucket_pages;
+            total_free_pages += allocator.zone_len(i) * bucket_pages;
         }
 
         assert_eq!(total_free_pages, 63); // 64 - 1 allocated
@@ -758,7 +1057,7 @@ mod tests {
     // allocate_at tests
     #[test]
     fn test_allocate_at_success() {
-        let mut allocator = MockAllocator::new(33, 32);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 32);
 
         // Try to allocate at a specific address within the available range
         let target_addr = BASE_ADDR + (8 * BASIC_PAGE_SIZE); // Aligned address
@@ -770,7 +1069,7 @@ mod tests {
 
     #[test]
     fn test_allocate_at_alignment_error() {
-        let mut allocator = MockAllocator::new(33, 32);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 32);
 
         // Try to allocate at a misaligned address
         let misaligned_addr = BASE_ADDR + BASIC_PAGE_SIZE; // Not aligned for 4-page allocation
@@ -782,7 +1081,7 @@ mod tests {
 
     #[test]
     fn test_allocate_at_no_containing_block() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         // Try to allocate at an address outside the available range
         let outside_addr = PhysAddr(BASE_ADDR.0 + 32 * BASIC_PAGE_SIZE);
@@ -794,7 +1093,7 @@ mod tests {
 
     #[test]
     fn test_allocate_at_already_allocated() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         // First allocation should succeed
         assert!(allocator.allocate_at(BASE_ADDR, 4).is_ok());
@@ -805,10 +1104,77 @@ mod tests {
         assert_eq!(result.unwrap_err(), PmmError::NoAvailableBlock);
     }
 
+    #[test]
+    fn test_grow_in_place() {
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 64);
+        let page = |index: usize| BASE_ADDR + index * BASIC_PAGE_SIZE;
+
+        let base = allocator.allocate(1, 2).unwrap();
+        assert_eq!(base, BASE_ADDR);
+
+        // The pages after the block are free, so it grows without moving
+        assert_eq!(allocator.grow(base, 2, 8), Ok(base));
+        assert_eq!(allocator.free_page_count(), 64 - 8);
+        assert!(!allocator.is_page_free(page(2), 2).unwrap());
+        assert!(!allocator.is_page_free(page(4), 4).unwrap());
+        assert!(allocator.is_page_free(page(8), 8).unwrap());
+
+        // Growing within the rounded up block changes nothing
+        assert_eq!(allocator.grow(base, 7, 8), Ok(base));
+        assert_eq!(allocator.free_page_count(), 64 - 8);
+
+        // The grown block is freed as a whole
+        unsafe { allocator.free(base, 8).unwrap() };
+        assert_eq!(allocator.free_page_count(), 64);
+        assert!(allocator.allocate(1, 64).is_ok());
+    }
+
+    #[test]
+    fn test_grow_relocates() {
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 64);
+        let page = |index: usize| BASE_ADDR + index * BASIC_PAGE_SIZE;
+
+        let base = allocator.allocate(1, 2).unwrap();
+        allocator.allocate_at(page(2), 1).unwrap();
+
+        // The page right after the block is taken, so a new block is allocated, and the old one is
+        // left for the caller to copy from and free
+        let moved = allocator.grow(base, 2, 4).unwrap();
+        assert_ne!(moved, base);
+        assert!(moved.0.is_multiple_of(4 * BASIC_PAGE_SIZE));
+        assert!(!allocator.is_page_free(base, 2).unwrap());
+        assert!(!allocator.is_page_free(moved, 4).unwrap());
+        assert_eq!(allocator.free_page_count(), 64 - 2 - 1 - 4);
+
+        // A block that isn't aligned like the bigger block can't grow in place either, even if
+        // the pages after it are free
+        allocator.allocate_at(page(12), 4).unwrap();
+        assert!(allocator.is_page_free(page(16), 4).unwrap());
+        assert_ne!(allocator.grow(page(12), 4, 8), Ok(page(12)));
+    }
+
+    #[test]
+    fn test_grow_errors() {
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 64);
+        let base = allocator.allocate(1, 2).unwrap();
+
+        assert_eq!(allocator.grow(base, 0, 4), Err(PmmError::EmptyAllocation));
+        assert_eq!(allocator.grow(base, 2, 0), Err(PmmError::EmptyAllocation));
+        assert_eq!(
+            allocator.grow(base + BASIC_PAGE_SIZE, 2, 4),
+            Err(PmmError::InvalidAlignment)
+        );
+        // Only allocated blocks can grow
+        assert_eq!(
+            allocator.grow(base + 8 * BASIC_PAGE_SIZE, 2, 4),
+            Err(PmmError::InvalidAddress)
+        );
+    }
+
     // Edge cases for allocate and free
     #[test]
     fn test_allocate_zero_pages() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         let result = allocator.allocate(1, 0);
         assert!(result.is_err());
@@ -817,7 +1183,7 @@ mod tests {
 
     #[test]
     fn test_allocate_at_zero_pages() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         let result = allocator.allocate_at(BASE_ADDR, 0);
         assert!(result.is_err());
@@ -826,7 +1192,7 @@ mod tests {
 
     #[test]
     fn test_free_zero_pages() {
-        let mut allocator = MockAllocator::new(33, 0);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 0);
 
         let result = unsafe { allocator.free(BASE_ADDR, 0) };
         assert!(result.is_err());
@@ -835,7 +1201,7 @@ mod tests {
 
     #[test]
     fn test_free_already_free_page() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         // First free should fail because the page is already free
         let result = unsafe { allocator.free(BASE_ADDR, 1) };
@@ -845,7 +1211,7 @@ mod tests {
 
     #[test]
     fn test_is_page_free_zero_pages() {
-        let allocator = MockAllocator::new(33, 16);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         let result = allocator.is_page_free(BASE_ADDR, 0);
         assert!(result.is_err());
@@ -854,7 +1220,7 @@ mod tests {
 
     #[test]
     fn test_is_page_free_misaligned() {
-        let allocator = MockAllocator::new(33, 60);
+        let allocator = BuddyAllocator::new_for_test(BASE_ADDR, 60);
 
         let misaligned_addr = PhysAddr(0x1000001); // Not page-aligned
         let result = allocator.is_page_free(misaligned_addr, 1);
@@ -864,7 +1230,7 @@ mod tests {
 
     #[test]
     fn test_allocate_overflow() {
-        let mut allocator = MockAllocator::new(33, 16);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
 
         // Try to allocate more pages than can fit in usize
         let result = allocator.allocate(1, usize::MAX);
@@ -874,7 +1240,7 @@ mod tests {
 
     #[test]
     fn test_alignment_requirements() {
-        let mut allocator = MockAllocator::new(33, 64);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 64);
 
         // Test various alignment requirements
         let addr1 = allocator.allocate(2, 4).unwrap(); // 2-page alignment
@@ -889,7 +1255,7 @@ mod tests {
 
     #[test]
     fn test_fragmentation_and_coalescing_recovery() {
-        let mut allocator = MockAllocator::new(33, 128);
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 128);
 
         // Create fragmentation by allocating alternating blocks
         let mut odd_allocations = Vec::new();
@@ -925,4 +1291,335 @@ mod tests {
         // Should now be able to allocate large blocks after coalescing
         assert!(allocator.allocate(1, 128).is_ok());
     }
+
+    #[test]
+    fn test_allocate_aligned_2mb() {
+        const SIZE_2MB: usize = 0x20_0000;
+
+        // 4MB worth of pages
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 1024);
+
+        // Take the first page so the start of the free memory isn't 2MB aligned anymore
+        let first = allocator.allocate(1, 1).unwrap();
+        assert_eq!(first, BASE_ADDR);
+
+        let addr = allocator.allocate_aligned(SIZE_2MB, 1).unwrap();
+        assert_eq!(addr.0 % SIZE_2MB, 0);
+        assert_eq!(addr, PhysAddr(BASE_ADDR.0 + SIZE_2MB));
+
+        // The whole 2MB block should be handed out aligned as well
+        unsafe { allocator.free(addr, 1).unwrap() };
+        let addr = allocator.allocate_aligned(SIZE_2MB, 512).unwrap();
+        assert_eq!(addr.0 % SIZE_2MB, 0);
+    }
+
+    #[test]
+    fn test_allocate_aligned_1gb() {
+        const SIZE_1GB: usize = 0x4000_0000;
+
+        // 2GB worth of pages, starting at the (not 1GB aligned) base address
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 2 * SIZE_1GB / BASIC_PAGE_SIZE);
+
+        // The range ends just past 2GB, so the smallest 1GB aligned block is the leftover at 2GB,
+        // and the next one is the 1GB block itself
+        for expected in [PhysAddr(2 * SIZE_1GB), PhysAddr(SIZE_1GB)] {
+            let addr = allocator.allocate_aligned(SIZE_1GB, 1).unwrap();
+            assert_eq!(addr.0 % SIZE_1GB, 0);
+            assert_eq!(addr, expected);
+        }
+    }
+
+    #[test]
+    fn test_allocate_aligned_invalid_alignment() {
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
+
+        // Not a power of two
+        assert_eq!(
+            allocator.allocate_aligned(3 * BASIC_PAGE_SIZE, 1),
+            Err(PmmError::InvalidAlignment)
+        );
+        // Smaller than a page
+        assert_eq!(
+            allocator.allocate_aligned(BASIC_PAGE_SIZE / 2, 1),
+            Err(PmmError::InvalidAlignment)
+        );
+        assert_eq!(
+            allocator.allocate_aligned(0, 1),
+            Err(PmmError::InvalidAlignment)
+        );
+    }
+
+    #[test]
+    fn test_low_watermark_callback() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAST_FREE_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+        fn callback(free_pages: usize) {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            LAST_FREE_PAGES.store(free_pages, Ordering::SeqCst);
+        }
+
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
+        assert_eq!(allocator.free_page_count(), 16);
+
+        allocator.set_low_watermark(8, callback);
+
+        // Going down to exactly the watermark shouldn't trigger the callback
+        let mut addrs: Vec<_> = (0..8).map(|_| allocator.allocate(1, 1).unwrap()).collect();
+        assert_eq!(allocator.free_page_count(), 8);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+
+        // Crossing it should
+        addrs.push(allocator.allocate(1, 1).unwrap());
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(LAST_FREE_PAGES.load(Ordering::SeqCst), 7);
+
+        // But staying under it shouldn't trigger it again
+        addrs.push(allocator.allocate(1, 1).unwrap());
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        // Going back above the watermark and crossing it again should trigger it again
+        for addr in addrs.drain(..) {
+            unsafe { allocator.free(addr, 1).unwrap() };
+        }
+        assert_eq!(allocator.free_page_count(), 16);
+
+        let _ = allocator.allocate(1, 16).unwrap();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+        assert_eq!(LAST_FREE_PAGES.load(Ordering::SeqCst), 0);
+    }
+
+    /// Create an allocator with no free pages, then free the given `(address, zone index)` buckets
+    /// in order
+    fn crafted_allocator(
+        policy: AllocationPolicy,
+        buckets: &[(PhysAddr, usize)],
+    ) -> BuddyAllocator<'static> {
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 0);
+        allocator.set_allocation_policy(policy);
+        for &(addr, zone_index) in buckets {
+            allocator.push_to_zone(addr, zone_index);
+        }
+
+        allocator
+    }
+
+    #[test]
+    fn test_allocation_policies() {
+        let low = BASE_ADDR;
+        let oldest = PhysAddr(BASE_ADDR.0 + 0x9000);
+        let newest = PhysAddr(BASE_ADDR.0 + 0x7000);
+        // A 4 page block at the lowest address, and 2 single (not 8KB aligned) pages freed after it
+        let buckets = [(low, 2), (oldest, 0), (newest, 0)];
+
+        // The free blocks aren't kept in the order they were freed, so the lower one goes first
+        let mut best_fit = crafted_allocator(AllocationPolicy::BestFit, &buckets);
+        assert_eq!(best_fit.allocate(1, 1), Ok(newest));
+        assert_eq!(best_fit.allocate(1, 1), Ok(oldest));
+
+        let mut recently_freed = crafted_allocator(AllocationPolicy::RecentlyFreed, &buckets);
+        assert_eq!(recently_freed.allocate(1, 1), Ok(newest));
+        assert_eq!(recently_freed.allocate(1, 1), Ok(oldest));
+
+        // The 4 page block is split, even though single pages are available
+        let mut lowest_address = crafted_allocator(AllocationPolicy::LowestAddress, &buckets);
+        assert_eq!(lowest_address.allocate(1, 1), Ok(low));
+        assert_eq!(lowest_address.zone_len(2), 0);
+        assert_eq!(lowest_address.zone_len(1), 1);
+        assert_eq!(lowest_address.zone_len(0), 3);
+        assert_eq!(
+            lowest_address.allocate(1, 1),
+            Ok(PhysAddr(low.0 + BASIC_PAGE_SIZE))
+        );
+
+        // All policies respect the alignment, and fall back to bigger blocks
+        for policy in [
+            AllocationPolicy::BestFit,
+            AllocationPolicy::RecentlyFreed,
+            AllocationPolicy::LowestAddress,
+        ] {
+            let mut allocator = crafted_allocator(policy, &buckets);
+            assert_eq!(allocator.allocate(2, 1), Ok(low), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn test_numa_local_first_then_fallback() {
+        const NODE_PAGES: usize = 32;
+        let node_1_start = PhysAddr(BASE_ADDR.0 + NODE_PAGES * BASIC_PAGE_SIZE);
+        let ranges = [
+            NumaRange {
+                start: BASE_ADDR,
+                end: node_1_start,
+                node: 0,
+            },
+            NumaRange {
+                start: node_1_start,
+                end: PhysAddr(node_1_start.0 + NODE_PAGES * BASIC_PAGE_SIZE),
+                node: 1,
+            },
+        ];
+
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 2 * NODE_PAGES);
+        assert_eq!(allocator.zone_len(6), 1);
+
+        // The single block spanning both nodes is split between them
+        allocator.set_numa_ranges(&ranges);
+        assert_eq!(allocator.zone_len(6), 0);
+        assert_eq!(allocator.zone_len(5), 2);
+        assert_eq!(allocator.free_page_count(), 2 * NODE_PAGES);
+
+        // Node 1's pages are handed out first, even though node 0's pages come first
+        let local: Vec<_> = (0..2)
+            .map(|_| allocator.allocate_on_node(1, 1, NODE_PAGES / 2).unwrap())
+            .collect();
+        for &addr in &local {
+            assert!(ranges[1].start <= addr && addr < ranges[1].end);
+        }
+
+        // Once node 1 is exhausted, node 0's pages are handed out instead
+        let fallback = allocator.allocate_on_node(1, 1, NODE_PAGES / 2).unwrap();
+        assert!(ranges[0].start <= fallback && fallback < ranges[0].end);
+        assert_eq!(
+            allocator.allocate_on_node(1, 1, NODE_PAGES),
+            Err(PmmError::NoAvailableBlock)
+        );
+
+        // Freeing everything doesn't merge the nodes' blocks back together
+        for addr in local.into_iter().chain([fallback]) {
+            unsafe { allocator.free(addr, NODE_PAGES / 2).unwrap() };
+        }
+        assert_eq!(allocator.zone_len(6), 0);
+        assert_eq!(allocator.zone_len(5), 2);
+        assert_eq!(
+            allocator.allocate(1, 2 * NODE_PAGES),
+            Err(PmmError::NoAvailableBlock)
+        );
+    }
+
+    #[test]
+    fn test_free_regions() {
+        let page = |index: usize| PhysAddr(BASE_ADDR.0 + index * BASIC_PAGE_SIZE);
+
+        // Adjacent blocks that aren't buddies still make up a single run
+        let allocator = crafted_allocator(
+            AllocationPolicy::BestFit,
+            &[
+                (page(16), 2),
+                (page(1), 0),
+                (page(6), 1),
+                (page(2), 1),
+                (page(4), 1),
+            ],
+        );
+        let regions: Vec<_> = allocator.free_regions().collect();
+        assert_eq!(regions, [(page(1), 7), (page(16), 4)]);
+
+        // Allocating from the middle of a run splits it
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 16);
+        allocator.allocate_at(page(4), 2).unwrap();
+        allocator.allocate_at(page(15), 1).unwrap();
+        let regions: Vec<_> = allocator.free_regions().collect();
+        assert_eq!(regions, [(page(0), 4), (page(6), 9)]);
+
+        let empty = BuddyAllocator::new_for_test(BASE_ADDR, 0);
+        assert_eq!(empty.free_regions().count(), 0);
+    }
+
+    #[test]
+    fn test_metadata_size() {
+        const PAGES_PER_GB: usize = 0x4000_0000 / BASIC_PAGE_SIZE;
+
+        // About 2 bits per page, on top of the zones themselves
+        for gigabytes in [1, 4, 16, 64] {
+            let page_count = gigabytes * PAGES_PER_GB;
+            let zones_count = page_count.ilog2() as usize + 1;
+            let size = BuddyAllocator::metadata_size(page_count, zones_count);
+
+            assert!(size >= page_count / 4);
+            assert!(
+                size < page_count / 4 + BASIC_PAGE_SIZE,
+                "{gigabytes}GB: {size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_recently_freed_outlives_memory() {
+        // More blocks are freed than the allocator remembers, so the oldest ones are taken the
+        // `BestFit` way
+        let page = |index: usize| PhysAddr(BASE_ADDR.0 + index * BASIC_PAGE_SIZE);
+        let buckets: Vec<_> = (0..2 * RECENTLY_FREED_COUNT)
+            .map(|i| (page(2 * i), 0))
+            .collect();
+        let mut allocator = crafted_allocator(AllocationPolicy::RecentlyFreed, &buckets);
+
+        for i in (0..2 * RECENTLY_FREED_COUNT)
+            .rev()
+            .take(RECENTLY_FREED_COUNT)
+        {
+            assert_eq!(allocator.allocate(1, 1), Ok(page(2 * i)));
+        }
+        for i in 0..RECENTLY_FREED_COUNT {
+            assert_eq!(allocator.allocate(1, 1), Ok(page(2 * i)));
+        }
+        assert_eq!(allocator.allocate(1, 1), Err(PmmError::NoAvailableBlock));
+    }
+
+    #[test]
+    fn test_new_for_test() {
+        let mut allocator = BuddyAllocator::new_for_test(BASE_ADDR, 100);
+        assert_eq!(allocator.free_pages, 100);
+
+        let mut taken = Vec::new();
+        while let Ok(addr) = allocator.allocate(1, 1) {
+            assert!(addr.0 >= BASE_ADDR.0 && addr.0 < BASE_ADDR.0 + 100 * BASIC_PAGE_SIZE);
+            taken.push(addr);
+        }
+        assert_eq!(taken.len(), 100);
+
+        for addr in taken {
+            unsafe { allocator.free(addr, 1).unwrap() };
+        }
+        assert_eq!(allocator.free_pages, 100);
+        assert!(allocator.allocate(1, 64).is_ok());
+    }
+
+    #[test]
+    fn test_null_page_never_allocated() {
+        // Low memory, starting right at the null page
+        let mut allocator = BuddyAllocator::new_for_test(NULL_PAGE, 64);
+        assert_eq!(allocator.free_pages, 63);
+        assert_eq!(allocator.is_page_free(NULL_PAGE, 1), Ok(false));
+
+        // The buddy of the null page can never merge with it
+        assert_eq!(allocator.allocate(1, 64), Err(PmmError::NoAvailableBlock));
+
+        let mut taken = Vec::new();
+        while let Ok(addr) = allocator.allocate(1, 1) {
+            assert_ne!(addr, NULL_PAGE);
+            taken.push(addr);
+        }
+        assert_eq!(taken.len(), 63);
+        assert_eq!(
+            allocator.allocate_at(NULL_PAGE, 1),
+            Err(PmmError::NoAvailableBlock)
+        );
+        assert_eq!(
+            unsafe { allocator.free(NULL_PAGE, 1) },
+            Err(PmmError::InvalidAddress)
+        );
+
+        for addr in taken {
+            unsafe { allocator.free(addr, 1).unwrap() };
+        }
+        assert_eq!(allocator.free_pages, 63);
+        assert_eq!(allocator.is_page_free(NULL_PAGE, 1), Ok(false));
+        assert_eq!(
+            allocator.allocate(1, 32),
+            Ok(PhysAddr(32 * BASIC_PAGE_SIZE))
+        );
+    }
 }
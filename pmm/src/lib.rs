@@ -5,9 +5,7 @@
 // TODO: Remove this once you fix the `as` conversion warnings
 #![allow(clippy::cast_possible_truncation)]
 
-#[cfg(feature = "limine")]
-use limine::memory_map;
-
+use utils::boot_info::{BootInfo, MemoryRegion, MemoryRegionKind};
 use utils::mem::PhysAddr;
 use utils::sync::spinlock::{SpinLockGuard, SpinLockable};
 
@@ -40,17 +38,62 @@ pub enum PmmError {
     TooBigAllocation,
 }
 
+/// How the PMM picks the free block to allocate from, out of the blocks big enough for an
+/// allocation
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum AllocationPolicy {
+    /// Split the smallest block that's big enough, taking the one with the lowest address.
+    ///
+    /// Keeps the bigger blocks intact for bigger allocations.
+    #[default]
+    BestFit,
+    /// Split the smallest block that's big enough, taking the one that was freed last. Only the
+    /// last few freed blocks are remembered, once none of them fit this is the same as `BestFit`.
+    ///
+    /// Fragments as much as `BestFit`, but a recently freed block is more likely to still be
+    /// cached (and mapped in the TLB), which helps workloads that free and allocate in bursts.
+    RecentlyFreed,
+    /// Split the block with the lowest address out of all the blocks that are big enough, even
+    /// if a smaller one is available.
+    ///
+    /// Packs allocations towards the bottom of memory, so the top stays in big contiguous blocks
+    /// under mixed workloads, at the cost of splitting big blocks earlier.
+    LowestAddress,
+}
+
+/// A range of physical memory that belongs to a NUMA node (a proximity domain, as the SRAT calls
+/// it)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct NumaRange {
+    /// The first address of the range
+    pub start: PhysAddr,
+    /// The address right after the end of the range
+    pub end: PhysAddr,
+    /// The proximity domain the range belongs to
+    pub node: u32,
+}
+
 /// Get the used PMM
 pub fn get<'a>() -> SpinLockGuard<'a, impl PmmAllocator> {
     buddy::PMM.lock()
 }
 
-/// Initilizes the used PMM from limine
-#[cfg(feature = "limine")]
-pub unsafe fn init_from_limine<'a>(
-    mem_map: &'a [&'a memory_map::Entry],
-) -> &'a limine::memory_map::Entry {
-    let ret = unsafe { buddy::BuddyAllocator::init_from_limine(mem_map) };
+/// Get the used PMM if it isn't locked, without waiting for it.
+///
+/// This is what code that might have interrupted the lock holder (e.g. a fault handler) should use
+pub fn try_get<'a>() -> Option<SpinLockGuard<'a, impl PmmAllocator>> {
+    buddy::PMM.try_lock()
+}
+
+/// Initilizes the used PMM from the boot info's memory map.
+///
+/// Returns the memory region the PMM took for its own metadata.
+///
+/// # Safety
+/// Should only be called once during boot, with the HHDM offset already set
+#[must_use = "The region the PMM took must not be handed out again"]
+pub unsafe fn init(boot_info: &BootInfo) -> MemoryRegion {
+    let ret = unsafe { buddy::BuddyAllocator::init(boot_info.memory_map()) };
 
     logger::info!("PMM initialized successfully");
 
@@ -62,47 +105,115 @@ pub trait PmmAllocator: SpinLockable {
     /// which satisfy the passed `alignment` page alignment.
     /// If allocation if successfull, the physical address of the start of the block is returned.
     ///
+    /// Physical page 0 is never handed out, so the returned address is never null.
+    ///
     /// NOTE: `alignment should be passed as page granularity. (e.g. 1 for 4KB, 2 for 8KB, etc.)`
     #[must_use = "Not freeing allocated memory will leak it"]
     fn allocate(&mut self, alignment: usize, page_count: usize) -> Result<PhysAddr, PmmError>;
 
+    /// Same as `allocate`, but the alignment is passed in bytes instead of page granularity.
+    ///
+    /// Useful when the block should be aligned to a bigger page size (e.g. 2MB for a huge page
+    /// mapping).
+    ///
+    /// NOTE: `byte_alignment` should be a power of two, and at least `BASIC_PAGE_SIZE`. Otherwise
+    /// `PmmError::InvalidAlignment` is returned.
+    #[must_use = "Not freeing allocated memory will leak it"]
+    fn allocate_aligned(
+        &mut self,
+        byte_alignment: usize,
+        page_count: usize,
+    ) -> Result<PhysAddr, PmmError> {
+        if !byte_alignment.is_power_of_two() || byte_alignment < BASIC_PAGE_SIZE {
+            return Err(PmmError::InvalidAlignment);
+        }
+
+        self.allocate(byte_alignment / BASIC_PAGE_SIZE, page_count)
+    }
+
+    /// Same as `allocate`, but prefers pages that belong to NUMA node `node`, falling back to any
+    /// node if it has no fitting free block.
+    ///
+    /// NOTE: Without NUMA ranges (see `set_numa_ranges`) this is the same as `allocate`
+    #[must_use = "Not freeing allocated memory will leak it"]
+    fn allocate_on_node(
+        &mut self,
+        node: u32,
+        alignment: usize,
+        page_count: usize,
+    ) -> Result<PhysAddr, PmmError>;
+
+    /// Sets the NUMA node each range of physical memory belongs to, splitting the free blocks so
+    /// that none of them spans more than one node. Memory outside of the ranges belongs to no
+    /// node.
+    fn set_numa_ranges(&mut self, ranges: &[NumaRange]);
+
     /// Tries to allocate a **physically** contiguous block of memory at a specific address
     #[allow(dead_code)]
     fn allocate_at(&mut self, addr: PhysAddr, page_count: usize) -> Result<(), PmmError>;
 
+    /// Grows the block of `old_pages` pages at `base` to `new_pages` pages, keeping it
+    /// **physically** contiguous (e.g. when resizing a device's DMA queue).
+    ///
+    /// The block is extended in place if the pages right after it are free, and `base` is
+    /// returned. Otherwise, a new block of `new_pages` pages is allocated and returned, and the
+    /// old block is left allocated, so the caller can copy the contents over before freeing it.
+    #[must_use = "The block might have moved, in which case the old block has to be freed"]
+    fn grow(
+        &mut self,
+        base: PhysAddr,
+        old_pages: usize,
+        new_pages: usize,
+    ) -> Result<PhysAddr, PmmError>;
+
     /// Tries to free a contiguous block of pages.
     #[allow(dead_code)]
     unsafe fn free(&mut self, addr: PhysAddr, page_count: usize) -> Result<(), PmmError>;
 
+    /// Sets the low memory watermark: once an allocation makes the amount of free pages drop
+    /// below `page_count`, `callback` is called with the new amount of free pages before the
+    /// allocation is returned.
+    ///
+    /// NOTE: The callback is called while the PMM is locked, so it must not use the PMM itself.
+    fn set_low_watermark(&mut self, page_count: usize, callback: fn(usize));
+
+    /// Sets the policy used to pick the free block allocations are made from
+    fn set_allocation_policy(&mut self, policy: AllocationPolicy);
+
+    /// Returns the amount of pages that are currently free
+    #[must_use]
+    fn free_page_count(&self) -> usize;
+
+    /// Returns each maximal run of free memory, as its address and page count, from the lowest
+    /// address up. Useful for dumping the physical memory map, and seeing how fragmented it is.
+    fn free_regions(&self) -> impl Iterator<Item = (PhysAddr, usize)>;
+
     /// Returns true if a page if free, false if it's not. If an error is encountered, an error is
     /// returned instead.
     #[allow(dead_code)]
     fn is_page_free(&self, addr: PhysAddr, page_count: usize) -> Result<bool, PmmError>;
 
-    /// Initilizes the PMM when using Limine using limine's memory map.
-    #[cfg(feature = "limine")]
-    unsafe fn init_from_limine<'a>(
-        mem_map: &'a [&'a memory_map::Entry],
-    ) -> &'a limine::memory_map::Entry;
+    /// Initilizes the PMM from the memory map, returning the region the PMM took for its own
+    /// metadata
+    unsafe fn init(memory_map: &[MemoryRegion]) -> MemoryRegion;
 }
 
 /// Get the maximum addressable page count from the memory map.
 /// This is done by finding the last memory map entry that is usable and calculating the page count
-#[cfg(feature = "limine")]
-fn get_page_count_from_mem_map(mem_map: &[&memory_map::Entry]) -> usize {
-    let last_descr = mem_map
+fn get_page_count_from_mem_map(memory_map: &[MemoryRegion]) -> usize {
+    let last_descr = memory_map
         .iter()
         .rev()
-        .find(|&entry| {
+        .find(|&region| {
             matches!(
-                entry.entry_type,
-                memory_map::EntryType::USABLE
-                    | memory_map::EntryType::BOOTLOADER_RECLAIMABLE
-                    | memory_map::EntryType::ACPI_RECLAIMABLE
-                    | memory_map::EntryType::EXECUTABLE_AND_MODULES
+                region.kind,
+                MemoryRegionKind::Usable
+                    | MemoryRegionKind::BootloaderReclaimable
+                    | MemoryRegionKind::AcpiReclaimable
+                    | MemoryRegionKind::ExecutableAndModules
             )
         })
         .unwrap();
 
-    (last_descr.base + last_descr.length) as usize / BASIC_PAGE_SIZE
+    last_descr.end().0 / BASIC_PAGE_SIZE
 }
@@ -1,8 +1,10 @@
 //! Simple scheduler which runs a single constant vessel
 
-use super::{Schedulable, Scheduler};
+use super::{
+    ExitCode, Next, Schedulable, Scheduler, TaskHandle, current, idle::IdleTask, task::ExitNotifier,
+};
 use alloc::boxed::Box;
-use utils::{sanity_assert, sync::spinlock::SpinLockable};
+use utils::{collections::id::Id, sanity_assert, sync::spinlock::SpinLockable};
 
 /// The parameters required to create a new `Constant` scheduler.
 pub type ParametersForNew<T> = Option<Box<T>>;
@@ -15,6 +17,10 @@ where
     T: Schedulable,
 {
     scheduable: Option<Box<T>>,
+    /// Publishes the exit code of `scheduable` to its handle
+    notifier: Option<ExitNotifier>,
+    /// Run while there is no schedulable
+    idle: IdleTask,
 }
 
 impl<T> Constant<T>
@@ -24,7 +30,11 @@ where
     // TODO: Remove this `new_const` when we get const fn in trait support, and use `new` instead
     #[must_use]
     pub const fn new_const() -> Self {
-        Self { scheduable: None }
+        Self {
+            scheduable: None,
+            notifier: None,
+            idle: IdleTask::new(),
+        }
     }
 }
 
@@ -35,28 +45,60 @@ where
     type ParametersForNew = ParametersForNew<T>;
 
     fn new(params: Self::ParametersForNew) -> Self {
-        Self { scheduable: params }
+        let mut scheduler = Self::new_const();
+        if let Some(vessel) = params {
+            // Nothing can wait on the initial vessel, so its handle isn't needed
+            let _ = scheduler.add(vessel);
+        }
+
+        scheduler
     }
 
-    fn add(&mut self, vessel: Box<T>) {
+    fn add(&mut self, vessel: Box<T>) -> TaskHandle {
         // sanity_assert!("Tried to add an additional schedulable but this is the 'const' scheduler");
         sanity_assert!(self.scheduable.is_none());
+        let (handle, notifier) = TaskHandle::new(vessel.id());
         self.scheduable = Some(vessel);
+        self.notifier = Some(notifier);
+
+        handle
     }
 
     fn remove(&mut self) -> Box<T> {
-        self.scheduable
+        let vessel = self
+            .scheduable
             .take()
-            .expect("Tried to expel an additional schedulable but this is the 'const' scheduler")
+            .expect("Tried to expel an additional schedulable but this is the 'const' scheduler");
+        current::forget(vessel.id());
+        self.notifier = None;
+
+        vessel
+    }
+
+    fn reap(&mut self, id: Id, code: ExitCode) {
+        sanity_assert!(
+            self.scheduable
+                .as_ref()
+                .is_some_and(|vessel| vessel.id() == id)
+        );
+        let notifier = self.notifier.take();
+        drop(self.remove());
+
+        if let Some(notifier) = notifier {
+            notifier.notify(code);
+        }
+    }
+
+    fn pick_next(&mut self) -> Next<'_, T> {
+        match self.scheduable {
+            Some(ref mut vessel) => Next::Vessel(vessel),
+            None => Next::Idle(&mut self.idle),
+        }
     }
 
     fn operation_loop(&mut self) -> ! {
-        if let Some(ref mut vessel) = self.scheduable {
-            loop {
-                vessel.run();
-            }
-        } else {
-            panic!("No schedulable found in the constant scheduler");
+        loop {
+            self.run_next();
         }
     }
 }
@@ -65,3 +107,180 @@ unsafe impl<T> Sync for Constant<T> where T: Schedulable {}
 unsafe impl<T> Send for Constant<T> where T: Schedulable {}
 
 impl<T> SpinLockable for Constant<T> where T: Schedulable {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JoinError;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use utils::sync::spinlock::SpinLock;
+
+    /// Held by the tests that switch tasks, since the current task is global
+    static CURRENT_LOCK: SpinLock<()> = SpinLock::new(());
+
+    struct TestVessel(usize);
+
+    impl Schedulable for TestVessel {
+        fn id(&self) -> Id {
+            Id(self.0)
+        }
+
+        fn run(&mut self) {}
+    }
+
+    /// A vessel that records what `current` reports while it runs
+    struct RecordingVessel {
+        id: usize,
+        seen: Option<Id>,
+    }
+
+    impl Schedulable for RecordingVessel {
+        fn id(&self) -> Id {
+            Id(self.id)
+        }
+
+        fn run(&mut self) {
+            self.seen = current::current();
+            current::set_task_local(self.id * 10).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_idle_only_when_empty() {
+        let mut scheduler = Constant::<TestVessel>::new_const();
+
+        // Nothing to run, so the CPU idles
+        assert!(matches!(scheduler.pick_next(), Next::Idle(_)));
+
+        // A vessel that became ready (e.g. from an IRQ that woke the idle task) runs instead
+        scheduler.add(Box::new(TestVessel(7)));
+        match scheduler.pick_next() {
+            Next::Vessel(vessel) => assert_eq!(vessel.id(), Id(7)),
+            Next::Idle(_) => panic!("Idle task picked while a vessel is ready"),
+        }
+
+        assert_eq!(scheduler.remove().id(), Id(7));
+        assert!(matches!(scheduler.pick_next(), Next::Idle(_)));
+    }
+
+    #[test]
+    fn test_current_follows_context_switches() {
+        let _lock = CURRENT_LOCK.lock();
+        let mut scheduler = Constant::<RecordingVessel>::new_const();
+
+        for id in [3, 4] {
+            scheduler.add(Box::new(RecordingVessel { id, seen: None }));
+            scheduler.run_next();
+            assert_eq!(current::current(), Some(Id(id)));
+            assert_eq!(current::task_local(), Some(id * 10));
+
+            let vessel = scheduler.remove();
+            assert_eq!(vessel.seen, Some(Id(id)));
+        }
+
+        // The removed vessels' task-local values are gone along with them
+        assert_eq!(current::current(), None);
+        assert_eq!(current::task_local(), None);
+        assert_eq!(
+            current::set_task_local(1),
+            Err(current::TaskLocalError::NoCurrentTask)
+        );
+    }
+
+    #[test]
+    fn test_current_is_per_cpu() {
+        let _lock = CURRENT_LOCK.lock();
+        let mut scheduler = Constant::<RecordingVessel>::new_const();
+
+        // Another CPU running a task doesn't make it this CPU's current task
+        current::set_test_cpu(1);
+        scheduler.add(Box::new(RecordingVessel { id: 8, seen: None }));
+        scheduler.run_next();
+        assert_eq!(current::current(), Some(Id(8)));
+
+        current::set_test_cpu(0);
+        assert_eq!(current::current(), None);
+
+        current::set_test_cpu(1);
+        assert_eq!(scheduler.remove().seen, Some(Id(8)));
+        assert_eq!(current::current(), None);
+        current::set_test_cpu(0);
+    }
+
+    /// The amount of `ExitingVessel`s that were dropped
+    static DROPPED_VESSELS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A vessel that exits with `code` once it ran `runs` times
+    struct ExitingVessel {
+        id: usize,
+        runs: usize,
+        code: ExitCode,
+    }
+
+    impl Drop for ExitingVessel {
+        fn drop(&mut self) {
+            // Stands in for the vessel's stack being freed
+            DROPPED_VESSELS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    impl Schedulable for ExitingVessel {
+        fn id(&self) -> Id {
+            Id(self.id)
+        }
+
+        fn run(&mut self) {
+            self.runs -= 1;
+        }
+
+        fn exit_code(&self) -> Option<ExitCode> {
+            (self.runs == 0).then_some(self.code)
+        }
+    }
+
+    #[test]
+    fn test_join_exited_task() {
+        let _lock = CURRENT_LOCK.lock();
+        let mut scheduler = Constant::<ExitingVessel>::new_const();
+
+        let handle = scheduler.add(Box::new(ExitingVessel {
+            id: 12,
+            runs: 2,
+            code: -3,
+        }));
+        assert_eq!(handle.id(), Id(12));
+
+        scheduler.run_next();
+        assert_eq!(handle.try_join(), Ok(None));
+
+        // The vessel exits, so it's reaped (and freed right away) and the CPU goes back to idling
+        let dropped = DROPPED_VESSELS.load(Ordering::Relaxed);
+        scheduler.run_next();
+        assert_eq!(handle.try_join(), Ok(Some(-3)));
+        assert_eq!(DROPPED_VESSELS.load(Ordering::Relaxed), dropped + 1);
+        assert!(matches!(scheduler.pick_next(), Next::Idle(_)));
+        assert_eq!(current::current(), None);
+
+        // Joining a task that already exited returns right away
+        assert_eq!(handle.join(), Ok(-3));
+    }
+
+    #[test]
+    fn test_join_removed_task() {
+        let _lock = CURRENT_LOCK.lock();
+        let mut scheduler = Constant::<ExitingVessel>::new_const();
+
+        let handle = scheduler.add(Box::new(ExitingVessel {
+            id: 13,
+            runs: 2,
+            code: 0,
+        }));
+        scheduler.run_next();
+        assert_eq!(handle.try_join(), Ok(None));
+
+        // The task will never exit, so joining it fails instead of spinning forever
+        drop(scheduler.remove());
+        assert_eq!(handle.try_join(), Err(JoinError::Removed));
+        assert_eq!(handle.join(), Err(JoinError::Removed));
+    }
+}
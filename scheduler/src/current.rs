@@ -0,0 +1,143 @@
+//! Keeping track of the task that's currently running on every CPU, and task-local storage
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel::arch::MAX_CPUS;
+use utils::{
+    collections::id::Id,
+    sanity_assert,
+    sync::{
+        cache_padded::CachePadded,
+        spinlock::{SpinLock, SpinLockable},
+    },
+};
+
+/// The maximum amount of tasks that can have a task-local value at once
+const MAX_TASK_LOCALS: usize = 64;
+
+/// Stored in `CURRENT` while the CPU is idling
+const NO_TASK: usize = usize::MAX;
+
+/// The ID of the task running on every CPU (by APIC ID), or `NO_TASK` while it idles.
+///
+/// Only the CPU itself writes its entry, and interrupt handlers might read it at any point, so it's
+/// an atomic rather than something behind a lock. Each is padded to a cache line, since it's
+/// written on every context switch
+static CURRENT: [CachePadded<AtomicUsize>; MAX_CPUS] =
+    [const { CachePadded::new(AtomicUsize::new(NO_TASK)) }; MAX_CPUS];
+
+/// The task-local value of each task that set one
+static LOCALS: SpinLock<TaskLocals> = SpinLock::new(TaskLocals([None; MAX_TASK_LOCALS]));
+
+/// The task-local values of all the tasks, along with the task each belongs to
+struct TaskLocals([Option<(Id, usize)>; MAX_TASK_LOCALS]);
+
+impl SpinLockable for TaskLocals {}
+
+/// Errors task-local storage might encounter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskLocalError {
+    /// No task is running, so there is no task to store the value for
+    NoCurrentTask,
+    /// All the task-local slots are taken by other tasks
+    NoFreeSlot,
+}
+
+#[cfg(test)]
+extern crate std;
+
+#[cfg(test)]
+std::thread_local! {
+    /// The CPU the test thread pretends to be
+    static TEST_CPU: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+/// Make the calling test thread pretend it runs on `cpu`
+#[cfg(test)]
+pub(crate) fn set_test_cpu(cpu: usize) {
+    TEST_CPU.with(|test_cpu| test_cpu.set(cpu));
+}
+
+/// Get the index of the calling CPU in `CURRENT`
+fn this_cpu() -> usize {
+    #[cfg(test)]
+    return TEST_CPU.with(core::cell::Cell::get);
+
+    #[cfg(all(not(test), target_arch = "x86_64"))]
+    return kernel::arch::x86_64::apic::lapic::LocalApic::get_this_apic_id() as usize;
+
+    // TODO: Use MPIDR_EL1 once other cores are brought up
+    #[cfg(all(not(test), not(target_arch = "x86_64")))]
+    return 0;
+}
+
+/// Get the ID of the task that's currently running on the calling CPU, or `None` if it's idling.
+///
+/// Interrupt handlers don't switch tasks, so when called from one this is the interrupted task.
+/// Never takes a lock, so it's safe to call from anywhere.
+#[must_use]
+pub fn current() -> Option<Id> {
+    let id = CURRENT[this_cpu()].load(Ordering::Relaxed);
+
+    (id != NO_TASK).then_some(Id(id))
+}
+
+/// Record that the task `id` is about to run on the calling CPU (or that it's about to idle, if
+/// `None`).
+///
+/// Called by the scheduler on every context switch.
+pub(crate) fn switch_to(id: Option<Id>) {
+    sanity_assert!(
+        id.is_none_or(|id| id.0 != NO_TASK),
+        "Task ID collides with NO_TASK"
+    );
+
+    CURRENT[this_cpu()].store(id.map_or(NO_TASK, |id| id.0), Ordering::Relaxed);
+}
+
+/// Get the task-local value of the current task, or `None` if it didn't set one
+#[must_use]
+pub fn task_local() -> Option<usize> {
+    let id = current()?;
+
+    LOCALS
+        .lock()
+        .0
+        .iter()
+        .flatten()
+        .find(|(owner, _)| *owner == id)
+        .map(|&(_, value)| value)
+}
+
+/// Set the task-local value of the current task to `value`
+///
+/// # Errors
+/// Fails if no task is running, or if there is no free slot for the value
+pub fn set_task_local(value: usize) -> Result<(), TaskLocalError> {
+    let id = current().ok_or(TaskLocalError::NoCurrentTask)?;
+    let mut locals = LOCALS.lock();
+
+    // Replace the task's value if it already has one, or take a free slot otherwise
+    let index = locals
+        .0
+        .iter()
+        .position(|slot| slot.is_some_and(|(owner, _)| owner == id))
+        .or_else(|| locals.0.iter().position(Option::is_none))
+        .ok_or(TaskLocalError::NoFreeSlot)?;
+    locals.0[index] = Some((id, value));
+
+    Ok(())
+}
+
+/// Drop the task-local value of task `id`, once it's no longer scheduled. If it's the calling CPU's
+/// current task, the CPU is recorded as idling
+pub(crate) fn forget(id: Id) {
+    for slot in &mut LOCALS.lock().0 {
+        if slot.is_some_and(|(owner, _)| owner == id) {
+            *slot = None;
+        }
+    }
+
+    let _ =
+        CURRENT[this_cpu()].compare_exchange(id.0, NO_TASK, Ordering::Relaxed, Ordering::Relaxed);
+}
@@ -0,0 +1,61 @@
+//! The idle task, which runs when no schedulable is ready
+
+use crate::run_queue::RunQueues;
+
+/// The task a scheduler runs when it has nothing else to run. Each CPU's scheduler has its own.
+///
+/// Instead of spinning, it puts the CPU to sleep until the next interrupt. Timer ticks and device
+/// interrupts are what make schedulables ready in the first place, so once one arrives the
+/// scheduler checks its queue again.
+#[derive(Debug, Default)]
+pub struct IdleTask {
+    /// The amount of times the CPU was woken up from idling
+    wakeups: usize,
+    /// The amount of times work was stolen from another CPU instead of idling
+    steals: usize,
+}
+
+impl IdleTask {
+    /// Create a new idle task
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            wakeups: 0,
+            steals: 0,
+        }
+    }
+
+    /// Sleep until the next interrupt, then return so the scheduler can check its queue again
+    pub fn run(&mut self) {
+        kernel::arch::wait_for_interrupt();
+        self.wakeups += 1;
+    }
+
+    /// Try to steal a ready item for `cpu` from the tail of another CPU's run queue. Schedulers
+    /// that share their queues call this before falling back to `run`, so a CPU only goes to
+    /// sleep once there is no work left anywhere
+    pub fn try_steal<T, const CPUS: usize>(
+        &mut self,
+        queues: &RunQueues<T, CPUS>,
+        cpu: usize,
+    ) -> Option<T> {
+        let item = queues.steal_for(cpu);
+        if item.is_some() {
+            self.steals += 1;
+        }
+
+        item
+    }
+
+    /// Get the amount of times the CPU was woken up from idling
+    #[must_use]
+    pub const fn wakeups(&self) -> usize {
+        self.wakeups
+    }
+
+    /// Get the amount of times work was stolen from another CPU instead of idling
+    #[must_use]
+    pub const fn steals(&self) -> usize {
+        self.steals
+    }
+}
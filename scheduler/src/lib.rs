@@ -5,12 +5,22 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use idle::IdleTask;
 use utils::collections::id::Id;
 
 // TODO: make sure only one scheduler type is enabled
 
 #[cfg(feature = "constant")]
 pub mod constant;
+pub mod current;
+pub mod idle;
+pub mod run_queue;
+pub mod task;
+#[cfg(feature = "work_stealing")]
+pub mod work_stealing;
+
+pub use current::{current, set_task_local, task_local};
+pub use task::{ExitCode, JoinError, TaskHandle};
 
 /// A trait for types that can be scheduled by one of the available schedulers.
 pub trait Schedulable {
@@ -20,6 +30,27 @@ pub trait Schedulable {
     /// Run the schedulable
     fn run(&mut self);
     // TODO: Add `Context` struct to store info and shit
+
+    /// Get the code the schedulable exited with, or `None` if it should keep being scheduled.
+    ///
+    /// Checked every time `run` returns. Once this returns a code, the scheduler drops the
+    /// schedulable right away (freeing whatever it owns, e.g. its
+    /// `kernel::mem::paging::GuardedStack` along with the guard page)
+    /// and hands the code to its `TaskHandle`
+    fn exit_code(&self) -> Option<ExitCode> {
+        None
+    }
+}
+
+/// What a scheduler picked to run next
+pub enum Next<'a, T>
+where
+    T: Schedulable,
+{
+    /// A vessel that is ready to run
+    Vessel(&'a mut T),
+    /// Nothing is ready, so the CPU should idle
+    Idle(&'a mut IdleTask),
 }
 
 pub trait Scheduler<T>
@@ -32,12 +63,45 @@ where
     /// Create a new scheduler with the given parameters.
     fn new(params: Self::ParametersForNew) -> Self;
 
-    /// Add a new vessel to the scheduling queue.
-    fn add(&mut self, vessel: Box<T>);
+    /// Add a new vessel to the scheduling queue, returning a handle to wait for it to exit with.
+    fn add(&mut self, vessel: Box<T>) -> TaskHandle;
 
-    /// Remove a vessel from the scheduling queue.
+    /// Remove a vessel from the scheduling queue. Joining its `TaskHandle` fails from then on.
     fn remove(&mut self) -> Box<T>;
 
+    /// Remove the vessel `id`, which exited with `code`, from the scheduling queue, drop it, and
+    /// hand `code` to its `TaskHandle`.
+    fn reap(&mut self, id: Id, code: ExitCode);
+
+    /// Pick what to run next. The idle task is picked only if no vessel is ready.
+    fn pick_next(&mut self) -> Next<'_, T>;
+
+    /// Run whatever `pick_next` picks, until it yields back to the scheduler.
+    ///
+    /// This is the context switch, so `current` reports the picked vessel while it runs.
+    /// Vessels that exit are reaped as soon as they yield.
+    fn run_next(&mut self) {
+        #[cfg(target_arch = "x86_64")]
+        kernel::arch::x86_64::watchdog::heartbeat();
+
+        let exited = match self.pick_next() {
+            Next::Vessel(vessel) => {
+                current::switch_to(Some(vessel.id()));
+                vessel.run();
+                vessel.exit_code().map(|code| (vessel.id(), code))
+            }
+            Next::Idle(idle) => {
+                current::switch_to(None);
+                idle.run();
+                None
+            }
+        };
+
+        if let Some((id, code)) = exited {
+            self.reap(id, code);
+        }
+    }
+
     /// Enter the operation loop of the scheduler.
     fn operation_loop(&mut self) -> !;
 }
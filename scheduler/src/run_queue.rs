@@ -0,0 +1,155 @@
+//! Per CPU run queues, which idle CPUs steal work from
+//!
+//! Every CPU's scheduler owns one queue. It pushes the vessels it schedules to the back of its
+//! queue and takes them from the front, while other CPUs that ran out of work steal from the back
+//! (the tail), so the owner and the thieves mostly stay out of each other's way.
+
+use alloc::collections::vec_deque::VecDeque;
+use utils::sync::spinlock::{SpinLock, SpinLockable};
+
+/// The items of a run queue, behind its lock
+struct Deque<T>(VecDeque<T>);
+
+impl<T> SpinLockable for Deque<T> {}
+
+/// The run queue of a single CPU
+pub struct RunQueue<T> {
+    deque: SpinLock<Deque<T>>,
+}
+
+impl<T> RunQueue<T> {
+    /// Create an empty run queue
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            deque: SpinLock::new(Deque(VecDeque::new())),
+        }
+    }
+
+    /// Add `item` to the back of the queue. Only the owner of the queue should push to it
+    pub fn push(&self, item: T) {
+        self.deque.lock().0.push_back(item);
+    }
+
+    /// Take the item at the front of the queue. Only the owner of the queue should pop from it
+    pub fn pop(&self) -> Option<T> {
+        self.deque.lock().0.pop_front()
+    }
+
+    /// Take the item at the back (tail) of the queue, on behalf of another CPU.
+    ///
+    /// NOTE: This doesn't wait for the lock. If the queue is locked its owner is using it, and the
+    /// thief is better off trying another queue
+    pub fn steal(&self) -> Option<T> {
+        self.deque.try_lock()?.0.pop_back()
+    }
+
+    /// Get the amount of items in the queue
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.deque.lock().0.len()
+    }
+
+    /// Returns true if the queue has no items
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for RunQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The run queues of all the CPUs, `CPUS` of them
+pub struct RunQueues<T, const CPUS: usize> {
+    queues: [RunQueue<T>; CPUS],
+}
+
+impl<T, const CPUS: usize> RunQueues<T, CPUS> {
+    /// Create the run queues, all of them empty
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            queues: [const { RunQueue::new() }; CPUS],
+        }
+    }
+
+    /// Get the run queue of `cpu`
+    ///
+    /// # Panics
+    /// Panics if `cpu` isn't below `CPUS`
+    #[must_use]
+    pub fn get(&self, cpu: usize) -> &RunQueue<T> {
+        &self.queues[cpu]
+    }
+
+    /// Steal an item for `cpu` from the tail of another CPU's queue, or `None` if all of them are
+    /// empty (or busy).
+    ///
+    /// The queues are tried in order starting from the CPU after `cpu`, so thieves on different
+    /// CPUs don't all go for the same victim first
+    pub fn steal_for(&self, cpu: usize) -> Option<T> {
+        (1..CPUS)
+            .map(|offset| &self.queues[(cpu + offset) % CPUS])
+            .find_map(RunQueue::steal)
+    }
+}
+
+impl<T, const CPUS: usize> Default for RunQueues<T, CPUS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_fifo_thief_from_tail() {
+        let queue = RunQueue::new();
+        for item in 1..=3 {
+            queue.push(item);
+        }
+        assert_eq!(queue.len(), 3);
+
+        // The owner takes the oldest item, thieves the newest one
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.steal(), Some(3));
+        assert_eq!(queue.pop(), Some(2));
+        assert!(queue.is_empty());
+        assert_eq!(queue.steal(), None);
+    }
+
+    #[test]
+    fn test_steal_from_other_queue() {
+        let queues = RunQueues::<usize, 4>::new();
+        queues.get(2).push(7);
+        queues.get(2).push(8);
+
+        // CPU 0 ran out of work, so it takes from CPU 2's tail
+        assert!(queues.get(0).is_empty());
+        assert_eq!(queues.steal_for(0), Some(8));
+        assert_eq!(queues.get(2).len(), 1);
+
+        // A CPU never steals from itself
+        assert_eq!(queues.steal_for(2), None);
+        assert_eq!(queues.steal_for(3), Some(7));
+        assert_eq!(queues.steal_for(0), None);
+    }
+
+    #[test]
+    fn test_steal_skips_locked_queue() {
+        let queues = RunQueues::<usize, 3>::new();
+        queues.get(1).push(1);
+        queues.get(2).push(2);
+
+        // CPU 1's owner holds its queue, so the thief moves on to CPU 2
+        let _guard = queues.get(1).deque.lock();
+        assert_eq!(queues.steal_for(0), Some(2));
+        assert_eq!(queues.steal_for(0), None);
+    }
+}
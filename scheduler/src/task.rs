@@ -0,0 +1,87 @@
+//! Handles to scheduled tasks, for waiting on them to exit
+
+use alloc::sync::Arc;
+use utils::{collections::id::Id, sync::once::Once};
+
+/// The code a task exits with
+pub type ExitCode = i32;
+
+/// Errors joining a task might encounter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was removed from its scheduler without exiting, so it never will
+    Removed,
+}
+
+/// A handle to a task that was added to a scheduler, which can be used to wait for it to exit
+#[derive(Debug)]
+pub struct TaskHandle {
+    /// The ID of the task
+    id: Id,
+    /// Set once the task exits, or once it's removed from its scheduler
+    exit_code: Arc<Once<Result<ExitCode, JoinError>>>,
+}
+
+/// The scheduler's side of a `TaskHandle`, which publishes the task's exit code to it.
+///
+/// Dropping it without publishing a code (e.g. when the task is removed from its scheduler) fails
+/// the handle's joins
+#[derive(Debug)]
+pub(crate) struct ExitNotifier(Arc<Once<Result<ExitCode, JoinError>>>);
+
+impl TaskHandle {
+    /// Create the handle of the task `id`, along with the notifier the scheduler publishes its
+    /// exit code through
+    pub(crate) fn new(id: Id) -> (Self, ExitNotifier) {
+        let exit_code = Arc::new(Once::new());
+        let notifier = ExitNotifier(exit_code.clone());
+
+        (Self { id, exit_code }, notifier)
+    }
+
+    /// Get the ID of the task
+    #[must_use]
+    pub const fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Get the code the task exited with, or `None` if it's still running
+    ///
+    /// # Errors
+    /// Returns `JoinError::Removed` if the task was removed from its scheduler without exiting
+    pub fn try_join(&self) -> Result<Option<ExitCode>, JoinError> {
+        self.exit_code.get().copied().transpose()
+    }
+
+    /// Wait for the task to exit, and get the code it exited with. Returns right away if it
+    /// already exited.
+    ///
+    /// NOTE: This spins, so the task has to be run by a scheduler on another CPU (or from an
+    /// interrupt handler) for this to ever return.
+    ///
+    /// # Errors
+    /// Returns `JoinError::Removed` if the task was removed from its scheduler without exiting
+    pub fn join(self) -> Result<ExitCode, JoinError> {
+        loop {
+            if let Some(code) = self.try_join()? {
+                return Ok(code);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl ExitNotifier {
+    /// Publish the exit code of the task, waking up whoever joins it
+    pub(crate) fn notify(self, code: ExitCode) {
+        // Only the scheduler holds the notifier, and it's consumed here, so the code is set once
+        let _ = self.0.set(Ok(code));
+    }
+}
+
+impl Drop for ExitNotifier {
+    fn drop(&mut self) {
+        // Does nothing if the exit code was published
+        let _ = self.0.set(Err(JoinError::Removed));
+    }
+}
@@ -0,0 +1,240 @@
+//! Round robin scheduler with a run queue per CPU, where CPUs that run out of work steal from the
+//! others
+//!
+//! Every CPU runs its own scheduler, so this relies on `current` being tracked per CPU: a vessel
+//! stolen by another CPU becomes that CPU's current task, and reaping it there doesn't touch what
+//! the CPU it came from is running.
+
+use super::{
+    ExitCode, Next, Schedulable, Scheduler, TaskHandle, current,
+    idle::IdleTask,
+    run_queue::{RunQueue, RunQueues},
+    task::ExitNotifier,
+};
+use alloc::boxed::Box;
+use utils::{collections::id::Id, sanity_assert, sync::spinlock::SpinLockable};
+
+/// A vessel waiting in a run queue, along with the notifier of its handle, which moves between CPUs
+/// with it
+pub struct Ready<T>
+where
+    T: Schedulable,
+{
+    vessel: Box<T>,
+    notifier: ExitNotifier,
+}
+
+// Vessels are only ever run by one CPU at a time, and stealing them moves them between CPUs
+unsafe impl<T> Sync for Ready<T> where T: Schedulable {}
+unsafe impl<T> Send for Ready<T> where T: Schedulable {}
+
+/// The parameters required to create a new `WorkStealing` scheduler: the run queues shared by all
+/// the CPUs, and the CPU the scheduler runs on
+pub type ParametersForNew<T, const CPUS: usize> = (&'static RunQueues<Ready<T>, CPUS>, usize);
+
+/// A round robin scheduler for a single CPU out of `CPUS`.
+///
+/// Vessels are added to this CPU's run queue. Once it's empty, vessels are stolen from the other
+/// CPUs' queues, and the CPU only idles if there is nothing to steal either.
+pub struct WorkStealing<T, const CPUS: usize>
+where
+    T: Schedulable + 'static,
+{
+    /// The run queues of all the CPUs
+    queues: &'static RunQueues<Ready<T>, CPUS>,
+    /// The CPU this scheduler runs on
+    cpu: usize,
+    /// The vessel that was picked last, which is out of the queue while it runs
+    running: Option<Ready<T>>,
+    /// Run while there is no schedulable on any CPU
+    idle: IdleTask,
+}
+
+impl<T, const CPUS: usize> WorkStealing<T, CPUS>
+where
+    T: Schedulable + 'static,
+{
+    /// Get this CPU's run queue
+    fn queue(&self) -> &'static RunQueue<Ready<T>> {
+        self.queues.get(self.cpu)
+    }
+}
+
+impl<T, const CPUS: usize> Scheduler<T> for WorkStealing<T, CPUS>
+where
+    T: Schedulable + 'static,
+{
+    type ParametersForNew = ParametersForNew<T, CPUS>;
+
+    fn new((queues, cpu): Self::ParametersForNew) -> Self {
+        assert!(cpu < CPUS, "Invalid CPU for the scheduler");
+
+        Self {
+            queues,
+            cpu,
+            running: None,
+            idle: IdleTask::new(),
+        }
+    }
+
+    fn add(&mut self, vessel: Box<T>) -> TaskHandle {
+        let (handle, notifier) = TaskHandle::new(vessel.id());
+        self.queue().push(Ready { vessel, notifier });
+
+        handle
+    }
+
+    fn remove(&mut self) -> Box<T> {
+        let ready = self
+            .running
+            .take()
+            .or_else(|| self.queue().pop())
+            .expect("Tried to remove a schedulable, but there are none on this CPU");
+        current::forget(ready.vessel.id());
+
+        ready.vessel
+    }
+
+    fn reap(&mut self, id: Id, code: ExitCode) {
+        sanity_assert!(
+            self.running
+                .as_ref()
+                .is_some_and(|ready| ready.vessel.id() == id)
+        );
+        let Some(ready) = self.running.take() else {
+            return;
+        };
+        current::forget(id);
+
+        drop(ready.vessel);
+        ready.notifier.notify(code);
+    }
+
+    fn pick_next(&mut self) -> Next<'_, T> {
+        // The vessel that ran last waits behind the rest, so every vessel gets its turn
+        if let Some(ready) = self.running.take() {
+            self.queue().push(ready);
+        }
+
+        self.running = self
+            .queue()
+            .pop()
+            .or_else(|| self.idle.try_steal(self.queues, self.cpu));
+
+        match self.running {
+            Some(ref mut ready) => Next::Vessel(&mut ready.vessel),
+            None => Next::Idle(&mut self.idle),
+        }
+    }
+
+    fn operation_loop(&mut self) -> ! {
+        loop {
+            self.run_next();
+        }
+    }
+}
+
+unsafe impl<T, const CPUS: usize> Sync for WorkStealing<T, CPUS> where T: Schedulable + 'static {}
+unsafe impl<T, const CPUS: usize> Send for WorkStealing<T, CPUS> where T: Schedulable + 'static {}
+
+impl<T, const CPUS: usize> SpinLockable for WorkStealing<T, CPUS> where T: Schedulable + 'static {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestVessel(usize);
+
+    impl Schedulable for TestVessel {
+        fn id(&self) -> Id {
+            Id(self.0)
+        }
+
+        fn run(&mut self) {}
+    }
+
+    /// Create the schedulers of `CPUS` CPUs sharing the same run queues
+    fn schedulers<const CPUS: usize>() -> [WorkStealing<TestVessel, CPUS>; CPUS] {
+        let queues = Box::leak(Box::new(RunQueues::new()));
+
+        core::array::from_fn(|cpu| WorkStealing::new((&*queues, cpu)))
+    }
+
+    /// Get the ID of the vessel `scheduler` picks next, or `None` if it idles
+    fn pick_id<const CPUS: usize>(scheduler: &mut WorkStealing<TestVessel, CPUS>) -> Option<Id> {
+        match scheduler.pick_next() {
+            Next::Vessel(vessel) => Some(vessel.id()),
+            Next::Idle(_) => None,
+        }
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let [mut scheduler] = schedulers::<1>();
+        for id in 20..23 {
+            let _ = scheduler.add(Box::new(TestVessel(id)));
+        }
+
+        for id in [20, 21, 22, 20, 21] {
+            assert_eq!(pick_id(&mut scheduler), Some(Id(id)));
+        }
+        assert_eq!(scheduler.idle.steals(), 0);
+    }
+
+    #[test]
+    fn test_idle_cpu_steals() {
+        let [mut idle_cpu, mut busy_cpu] = schedulers::<2>();
+        let _ = busy_cpu.add(Box::new(TestVessel(30)));
+        let handle = busy_cpu.add(Box::new(TestVessel(31)));
+
+        // CPU 0 has nothing queued, so it takes the vessel at the tail of CPU 1's queue instead of
+        // idling
+        assert_eq!(pick_id(&mut idle_cpu), Some(Id(31)));
+        assert_eq!(idle_cpu.idle.steals(), 1);
+        assert_eq!(pick_id(&mut busy_cpu), Some(Id(30)));
+
+        // The stolen vessel's handle moved along with it
+        idle_cpu.reap(Id(31), 5);
+        assert_eq!(handle.try_join(), Ok(Some(5)));
+
+        // Nothing is left to steal, so CPU 0 idles, and CPU 1 keeps running its own vessel
+        assert_eq!(pick_id(&mut idle_cpu), None);
+        assert_eq!(idle_cpu.idle.steals(), 1);
+        assert_eq!(pick_id(&mut busy_cpu), Some(Id(30)));
+        assert_eq!(busy_cpu.remove().id(), Id(30));
+        assert_eq!(pick_id(&mut busy_cpu), None);
+    }
+
+    #[test]
+    fn test_current_per_cpu() {
+        // Pretend to be CPUs no other test uses, so the current tasks aren't shared with them
+        const IDLE_CPU: usize = 2;
+        const BUSY_CPU: usize = 3;
+
+        let [mut idle_cpu, mut busy_cpu] = schedulers::<2>();
+
+        current::set_test_cpu(BUSY_CPU);
+        let _ = busy_cpu.add(Box::new(TestVessel(40)));
+        let _ = busy_cpu.add(Box::new(TestVessel(41)));
+        busy_cpu.run_next();
+        assert_eq!(current::current(), Some(Id(40)));
+
+        // The stolen vessel is the current task of the CPU that stole it, and only of that one
+        current::set_test_cpu(IDLE_CPU);
+        idle_cpu.run_next();
+        assert_eq!(current::current(), Some(Id(41)));
+        current::set_test_cpu(BUSY_CPU);
+        assert_eq!(current::current(), Some(Id(40)));
+
+        // Removing it leaves the other CPU's current task alone
+        current::set_test_cpu(IDLE_CPU);
+        assert_eq!(idle_cpu.remove().id(), Id(41));
+        assert_eq!(current::current(), None);
+        current::set_test_cpu(BUSY_CPU);
+        assert_eq!(current::current(), Some(Id(40)));
+
+        assert_eq!(busy_cpu.remove().id(), Id(40));
+        assert_eq!(current::current(), None);
+        current::set_test_cpu(0);
+    }
+}
@@ -0,0 +1,107 @@
+//! A `Box` for objects of types that have a slab allocator of their own
+
+use core::{
+    fmt::{self, Debug},
+    ops::{Deref, DerefMut},
+};
+
+use alloc::boxed::Box;
+
+use crate::{SlabAllocatable, SlabAllocator};
+
+/// A type whose objects are allocated from a single, static slab allocator
+pub trait SlabAllocated: SlabAllocatable + Sized + 'static {
+    /// Get the slab allocator the objects of this type are allocated from
+    fn allocator() -> &'static SlabAllocator<Self>;
+}
+
+/// An owned object allocated from its type's slab allocator, which is freed back to it on drop
+pub struct SlabBox<T>(Box<T, &'static SlabAllocator<T>>)
+where
+    T: SlabAllocated;
+
+impl<T> SlabBox<T>
+where
+    T: SlabAllocated,
+{
+    /// Allocate an object from `T`'s slab allocator, and move `value` into it.
+    ///
+    /// # Panics
+    /// Panics if the allocation fails
+    #[inline]
+    #[must_use]
+    pub fn new(value: T) -> Self {
+        Self(Box::new_in(value, T::allocator()))
+    }
+}
+
+impl<T> Deref for SlabBox<T>
+where
+    T: SlabAllocated,
+{
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for SlabBox<T>
+where
+    T: SlabAllocated,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> Debug for SlabBox<T>
+where
+    T: SlabAllocated + Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::SlabError;
+    use core::ptr::NonNull;
+
+    struct TestObject {
+        a: u64,
+        b: u64,
+    }
+
+    impl SlabAllocatable for TestObject {}
+
+    static TEST_OBJECT_ALLOCATOR: SlabAllocator<TestObject> = SlabAllocator::new();
+
+    impl SlabAllocated for TestObject {
+        fn allocator() -> &'static SlabAllocator<Self> {
+            &TEST_OBJECT_ALLOCATOR
+        }
+    }
+
+    #[test]
+    fn test_slab_box_uses_type_allocator() {
+        let mut object = SlabBox::new(TestObject { a: 1, b: 2 });
+        object.b += 1;
+        assert_eq!(object.a + object.b, 4);
+
+        let ptr = NonNull::from_mut(&mut *object).cast::<()>();
+        assert!(TEST_OBJECT_ALLOCATOR.allocator.lock().owns(ptr));
+
+        drop(object);
+
+        // The object is already back on the slab's free list
+        assert_eq!(
+            unsafe { TEST_OBJECT_ALLOCATOR.allocator.lock().free(ptr) },
+            Err(SlabError::DoubleFree)
+        );
+    }
+}
@@ -1,14 +1,80 @@
 //! A global heap allocator for the kernel. Structured as a bunch of uninitable object slab allocators
+//!
+//! Every size class is aligned to its size, so small allocations with big alignments just go to a
+//! bigger class. Allocations no class can hold (bigger than a page, or aligned to more than one)
+//! get pages of their own.
 
-use utils::sync::spinlock::{SpinLock, SpinLockGuard};
+use kernel::arch::BASIC_PAGE_SIZE;
+use utils::{
+    sanity_assert,
+    sync::spinlock::{SpinLock, SpinLockGuard, SpinLockable},
+};
 
-use super::internal::InternalSlabAllocator;
+use super::internal::{InternalSlabAllocator, allocate_pages, free_pages};
 
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::{NonNull, null_mut},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+/// The size of the emergency reserve, in bytes. Enough for formatting a few panic messages
+const RESERVE_SIZE: usize = 4 * 4096;
+
+/// The amount of live allocations the leak tracker can keep track of at once
+#[cfg(debug_assertions)]
+const LEAK_TRACKER_CAPACITY: usize = 256;
+
+/// Usage statistics of the heap
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The total amount of bytes ever allocated
+    pub total_allocated: usize,
+    /// The amount of bytes that are currently allocated and weren't freed yet
+    pub live: usize,
+    /// The total amount of allocations ever made
+    pub allocation_count: usize,
+    /// The highest amount of live bytes ever reached
+    pub peak: usize,
+}
+
+/// A single allocation that wasn't freed yet, as recorded by the leak tracker
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveAllocation {
+    /// The address of the allocation
+    pub addr: usize,
+    /// The size of the allocation in bytes
+    pub size: usize,
+    /// The address the allocation was requested from
+    pub caller: usize,
+}
+
+/// Records the live allocations of the heap, so leaks can be found during development
+#[cfg(debug_assertions)]
+#[derive(Debug)]
+struct LeakTracker {
+    /// Whether allocations should be recorded at all
+    enabled: bool,
+    /// The recorded live allocations
+    entries: [Option<LiveAllocation>; LEAK_TRACKER_CAPACITY],
+    /// The amount of allocations that couldn't be recorded since the table was full
+    missed: usize,
+}
+
+/// A small pool of memory only allocated from once the heap is in panic mode, so the panic path
+/// can still allocate when the rest of the heap is exhausted.
+///
+/// It's a bump allocator: Memory is only reclaimed when the last allocation is freed, which is
+/// good enough since not much runs after a panic
+#[derive(Debug)]
+#[repr(C, align(4096))]
+struct EmergencyReserve {
+    pool: [u8; RESERVE_SIZE],
+    /// The offset of the first free byte in the pool
+    next: usize,
+}
+
 /// A global heap allocator for the kernel. Structured as a bunch of uninitable object slab
 /// allocators
 #[derive(Debug)]
@@ -21,6 +87,15 @@ pub struct Heap {
     slab_1024: SpinLock<InternalSlabAllocator>,
     slab_2048: SpinLock<InternalSlabAllocator>,
     slab_4096: SpinLock<InternalSlabAllocator>,
+    stats: SpinLock<HeapStats>,
+    reserve: SpinLock<EmergencyReserve>,
+    /// Whether allocations the heap can't satisfy should go to `reserve`
+    panic_mode: AtomicBool,
+    #[cfg(debug_assertions)]
+    leak_tracker: SpinLock<LeakTracker>,
+    /// Makes all the allocations that don't go to `reserve` fail, as if the heap was exhausted
+    #[cfg(test)]
+    exhausted: AtomicBool,
 }
 
 impl Default for Heap {
@@ -59,36 +134,114 @@ impl Heap {
                 slab_4096: SpinLock::new(InternalSlabAllocator::new(
                     Layout::from_size_align_unchecked(4096, 4096),
                 )),
+                stats: SpinLock::new(HeapStats {
+                    total_allocated: 0,
+                    live: 0,
+                    allocation_count: 0,
+                    peak: 0,
+                }),
+                reserve: SpinLock::new(EmergencyReserve::new()),
+                panic_mode: AtomicBool::new(false),
+                #[cfg(debug_assertions)]
+                leak_tracker: SpinLock::new(LeakTracker::new()),
+                #[cfg(test)]
+                exhausted: AtomicBool::new(false),
             }
         }
     }
 
+    /// Get the size class `layout` goes to, or `None` if it's too big or too aligned for all of
+    /// them
     #[must_use]
-    fn layout_to_allocator(&self, mut layout: Layout) -> SpinLockGuard<'_, InternalSlabAllocator> {
+    fn layout_to_allocator(
+        &self,
+        mut layout: Layout,
+    ) -> Option<SpinLockGuard<'_, InternalSlabAllocator>> {
         // Padding since we're storing the slabs sequentially in memory
         layout = layout.pad_to_align();
-        if layout.size() <= 32 && layout.align() <= 32 {
-            self.slab_32.lock()
+        let allocator = if layout.size() <= 32 && layout.align() <= 32 {
+            &self.slab_32
         } else if layout.size() <= 64 && layout.align() <= 64 {
-            self.slab_64.lock()
+            &self.slab_64
         } else if layout.size() <= 128 && layout.align() <= 128 {
-            self.slab_128.lock()
+            &self.slab_128
         } else if layout.size() <= 256 && layout.align() <= 256 {
-            self.slab_256.lock()
+            &self.slab_256
         } else if layout.size() <= 512 && layout.align() <= 512 {
-            self.slab_512.lock()
+            &self.slab_512
         } else if layout.size() <= 1024 && layout.align() <= 1024 {
-            self.slab_1024.lock()
+            &self.slab_1024
         } else if layout.size() <= 2048 && layout.align() <= 2048 {
-            self.slab_2048.lock()
+            &self.slab_2048
         } else if layout.size() <= 4096 && layout.align() <= 4096 {
-            self.slab_4096.lock()
+            &self.slab_4096
         } else {
-            panic!(
-                "No allocator for size {} and alignment {}",
-                layout.size(),
-                layout.align()
-            );
+            return None;
+        };
+
+        Some(allocator.lock())
+    }
+
+    /// Returns a snapshot of the heap's usage statistics
+    #[inline]
+    #[must_use]
+    pub fn stats(&self) -> HeapStats {
+        *self.stats.lock()
+    }
+
+    /// Enables or disables recording the live allocations of the heap.
+    ///
+    /// NOTE: Allocations made while the tracking was disabled are never recorded, so they won't be
+    /// reported as live.
+    #[cfg(debug_assertions)]
+    pub fn set_leak_tracking(&self, enable: bool) {
+        self.leak_tracker.lock().enabled = enable;
+    }
+
+    /// Calls `f` on every recorded allocation that wasn't freed yet.
+    ///
+    /// Returns the amount of allocations that weren't recorded because the table was full.
+    #[cfg(debug_assertions)]
+    pub fn dump_live_allocations(&self, mut f: impl FnMut(&LiveAllocation)) -> usize {
+        let tracker = self.leak_tracker.lock();
+
+        tracker.entries.iter().flatten().for_each(&mut f);
+
+        tracker.missed
+    }
+
+    /// Let allocations the heap can't satisfy be made from the emergency reserve from now on.
+    ///
+    /// Meant to be called first thing in the panic handler, so the panic message can still be
+    /// formatted and logged when the panic was caused by running out of memory. There is no way
+    /// back, since nothing is supposed to keep running after a panic
+    #[cold]
+    pub fn enter_panic_mode(&self) {
+        self.panic_mode.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if `ptr` was allocated from the emergency reserve.
+    ///
+    /// The pool never moves, so this doesn't lock the reserve, and frees of normal allocations
+    /// don't all contend on its lock
+    fn reserve_owns(&self, ptr: NonNull<u8>) -> bool {
+        // SAFETY: Only the address of the pool is taken, nothing is read through the pointer
+        let pool = unsafe { &raw const (*self.reserve.as_ptr()).pool };
+
+        (pool.addr()..pool.addr() + RESERVE_SIZE).contains(&ptr.addr().get())
+    }
+
+    /// Allocate `layout` from the size classes (or pages of its own), without touching the
+    /// emergency reserve
+    fn allocate_normal(&self, layout: Layout) -> Option<NonNull<u8>> {
+        #[cfg(test)]
+        if self.exhausted.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        match self.layout_to_allocator(layout) {
+            Some(mut allocator) => allocator.allocate().ok().map(NonNull::cast),
+            None => allocate_large(layout),
         }
     }
 
@@ -115,24 +268,365 @@ impl Heap {
 
 unsafe impl GlobalAlloc for Heap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.layout_to_allocator(layout);
+        let ptr = self.allocate_normal(layout).or_else(|| {
+            if self.panic_mode.load(Ordering::Relaxed) {
+                self.reserve.lock().allocate(layout)
+            } else {
+                None
+            }
+        });
+
+        if let Some(ptr) = ptr {
+            let mut stats = self.stats.lock();
+            stats.total_allocated += layout.size();
+            stats.live += layout.size();
+            stats.allocation_count += 1;
+            stats.peak = stats.peak.max(stats.live);
+            drop(stats);
 
-        if let Ok(ptr) = allocator.allocate() {
-            return ptr.as_ptr().cast::<u8>();
+            #[cfg(debug_assertions)]
+            self.leak_tracker.lock().record(LiveAllocation {
+                addr: ptr.addr().get(),
+                size: layout.size(),
+                // NOTE: This is the address `alloc` returns to, which is usually the allocation
+                // shim and not the code that actually requested the memory
+                caller: core::arch::return_address!().addr(),
+            });
+
+            return ptr.as_ptr();
         }
 
         null_mut()
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.layout_to_allocator(layout);
-
         let ptr = NonNull::new(ptr).expect("Tried to deallocate a null pointer");
-        // SAFETY: We are deallocating a pointer that was allocated by this allocator
-        unsafe {
-            allocator.free(ptr.cast()).unwrap();
-        };
+
+        if self.reserve_owns(ptr) {
+            self.reserve.lock().free(ptr, layout);
+        } else if let Some(mut allocator) = self.layout_to_allocator(layout) {
+            sanity_assert!(
+                allocator.owns(ptr.cast()),
+                "Tried to deallocate a pointer that wasn't allocated from the heap"
+            );
+            // SAFETY: We are deallocating a pointer that was allocated by this allocator
+            unsafe {
+                allocator.free(ptr.cast()).unwrap();
+            };
+        } else {
+            // SAFETY: Layouts no size class can hold are allocated with `allocate_large`
+            unsafe { free_large(ptr, layout) };
+        }
+
+        self.stats.lock().live -= layout.size();
+
+        #[cfg(debug_assertions)]
+        self.leak_tracker.lock().forget(ptr.addr().get());
+    }
+}
+
+/// Get the amount of pages `allocate_large` allocates for `layout`.
+///
+/// Pages are only page aligned, so for bigger alignments there is room for up to a whole `align`
+/// of padding before the allocation
+const fn large_page_count(layout: Layout) -> usize {
+    let padding = if layout.align() > BASIC_PAGE_SIZE.size() {
+        layout.align()
+    } else {
+        0
+    };
+
+    (layout.size() + padding).div_ceil(BASIC_PAGE_SIZE.size())
+}
+
+/// Allocate `layout` on pages of its own, for layouts that are too big or too aligned for the
+/// size classes.
+///
+/// If `layout` is aligned to more than a page, the returned pointer is somewhere inside the pages,
+/// and the address of the pages is kept in the word right before it
+fn allocate_large(layout: Layout) -> Option<NonNull<u8>> {
+    let pages = allocate_pages(large_page_count(layout)).ok()?.cast::<u8>();
+    if layout.align() <= BASIC_PAGE_SIZE.size() {
+        return Some(pages);
     }
+
+    // The pages are page aligned and the pointer is aligned to more than a page, so there is at
+    // least a page between them, plenty for the address of the pages
+    let base = pages.addr().get();
+    let ptr = pages.with_addr(
+        (base + 1)
+            .next_multiple_of(layout.align())
+            .try_into()
+            .unwrap(),
+    );
+    unsafe { ptr.cast::<usize>().sub(1).write(base) };
+
+    Some(ptr)
 }
 
+/// Free an allocation made by `allocate_large`.
+///
+/// # Safety
+/// `ptr` must have been returned by `allocate_large` for `layout`, and not freed yet
+unsafe fn free_large(ptr: NonNull<u8>, layout: Layout) {
+    let pages = if layout.align() <= BASIC_PAGE_SIZE.size() {
+        ptr
+    } else {
+        let base = unsafe { ptr.cast::<usize>().sub(1).read() };
+        ptr.with_addr(base.try_into().unwrap())
+    };
+
+    unsafe { free_pages(pages.cast(), large_page_count(layout)).unwrap() };
+}
+
+impl EmergencyReserve {
+    const fn new() -> Self {
+        Self {
+            pool: [0; RESERVE_SIZE],
+            next: 0,
+        }
+    }
+
+    /// Allocate `layout` from the free part of the pool, if it fits
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let base = self.pool.as_mut_ptr();
+        let start = (base.addr() + self.next).next_multiple_of(layout.align()) - base.addr();
+        let end = start.checked_add(layout.size())?;
+        if end > RESERVE_SIZE {
+            return None;
+        }
+
+        self.next = end;
+
+        NonNull::new(base.wrapping_add(start))
+    }
+
+    /// Free `ptr`, which was allocated from the pool for `layout`. Its memory is only reclaimed if
+    /// it's the last allocation made
+    fn free(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let start = ptr.addr().get() - self.pool.as_ptr().addr();
+        if start + layout.size() == self.next {
+            self.next = start;
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl LeakTracker {
+    /// Creates a new, disabled, leak tracker
+    const fn new() -> Self {
+        Self {
+            enabled: false,
+            entries: [None; LEAK_TRACKER_CAPACITY],
+            missed: 0,
+        }
+    }
+
+    /// Records a new live allocation
+    fn record(&mut self, allocation: LiveAllocation) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.is_none()) {
+            *entry = Some(allocation);
+        } else {
+            self.missed += 1;
+        }
+    }
+
+    /// Removes the allocation at `addr` from the live allocations, if it was recorded
+    fn forget(&mut self, addr: usize) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|allocation| allocation.addr == addr))
+        {
+            *entry = None;
+        }
+    }
+}
+
+impl SpinLockable for HeapStats {}
+
+impl SpinLockable for EmergencyReserve {}
+
+#[cfg(debug_assertions)]
+impl SpinLockable for LeakTracker {}
+
 unsafe impl Sync for Heap {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_stats_live_returns_to_baseline() {
+        let heap = Heap::new();
+        let baseline = heap.stats();
+
+        let layouts = [
+            Layout::from_size_align(8, 8).unwrap(),
+            Layout::from_size_align(100, 4).unwrap(),
+            Layout::from_size_align(1024, 64).unwrap(),
+        ];
+
+        let ptrs: Vec<_> = layouts
+            .iter()
+            .map(|&layout| unsafe { heap.alloc(layout) })
+            .collect();
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+
+        let total: usize = layouts.iter().map(Layout::size).sum();
+        let stats = heap.stats();
+        assert_eq!(stats.live, baseline.live + total);
+        assert_eq!(stats.total_allocated, baseline.total_allocated + total);
+        assert_eq!(
+            stats.allocation_count,
+            baseline.allocation_count + layouts.len()
+        );
+
+        for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+
+        let stats = heap.stats();
+        assert_eq!(stats.live, baseline.live);
+        // The cumulative counters shouldn't go down on free
+        assert_eq!(stats.total_allocated, baseline.total_allocated + total);
+        assert_eq!(
+            stats.allocation_count,
+            baseline.allocation_count + layouts.len()
+        );
+    }
+
+    #[test]
+    fn test_stats_peak() {
+        let heap = Heap::new();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        let first = unsafe { heap.alloc(layout) };
+        let second = unsafe { heap.alloc(layout) };
+        unsafe {
+            heap.dealloc(first, layout);
+            heap.dealloc(second, layout);
+        }
+
+        let third = unsafe { heap.alloc(layout) };
+        unsafe { heap.dealloc(third, layout) };
+
+        let stats = heap.stats();
+        assert_eq!(stats.peak, 2 * layout.size());
+        assert_eq!(stats.live, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_leak_tracking() {
+        let heap = Heap::new();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        // Allocations made before enabling the tracking aren't recorded
+        let untracked = unsafe { heap.alloc(layout) };
+        heap.set_leak_tracking(true);
+
+        let freed = unsafe { heap.alloc(layout) };
+        let leaked = unsafe { heap.alloc(layout) };
+        unsafe { heap.dealloc(freed, layout) };
+
+        let mut live = Vec::new();
+        let missed = heap.dump_live_allocations(|allocation| live.push(*allocation));
+
+        assert_eq!(missed, 0);
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].addr, leaked.addr());
+        assert_eq!(live[0].size, layout.size());
+
+        unsafe {
+            heap.dealloc(leaked, layout);
+            heap.dealloc(untracked, layout);
+        }
+
+        assert_eq!(
+            heap.dump_live_allocations(|_| panic!("Nothing should be live")),
+            0
+        );
+    }
+
+    #[test]
+    fn test_emergency_reserve() {
+        let heap = Heap::new();
+        let layout = Layout::from_size_align(200, 8).unwrap();
+
+        // Allocations succeed normally until the heap runs out, and only panic mode unlocks the
+        // reserve
+        let normal = unsafe { heap.alloc(layout) };
+        assert!(!normal.is_null());
+        assert!(!heap.reserve_owns(NonNull::new(normal).unwrap()));
+
+        heap.exhausted.store(true, Ordering::Relaxed);
+        assert!(unsafe { heap.alloc(layout) }.is_null());
+
+        heap.enter_panic_mode();
+        let first = unsafe { heap.alloc(layout) };
+        let second = unsafe { heap.alloc(Layout::from_size_align(64, 64).unwrap()) };
+        for ptr in [first, second] {
+            assert!(!ptr.is_null());
+            assert!(heap.reserve_owns(NonNull::new(ptr).unwrap()));
+        }
+        assert!(second.is_aligned_to(64));
+        unsafe { first.write_bytes(0xcc, layout.size()) };
+
+        // The reserve is limited too
+        let too_big = Layout::from_size_align(RESERVE_SIZE, 8).unwrap();
+        assert!(unsafe { heap.alloc(too_big) }.is_null());
+
+        // Freeing the last allocation gives its memory back
+        unsafe { heap.dealloc(second, Layout::from_size_align(64, 64).unwrap()) };
+        let third = unsafe { heap.alloc(Layout::from_size_align(64, 64).unwrap()) };
+        assert_eq!(third, second);
+
+        unsafe {
+            heap.dealloc(third, Layout::from_size_align(64, 64).unwrap());
+            heap.dealloc(first, layout);
+            heap.dealloc(normal, layout);
+        };
+        assert_eq!(heap.stats().live, 0);
+    }
+
+    #[test]
+    fn test_over_aligned_allocations() {
+        let heap = Heap::new();
+
+        // Small objects aligned up to a page fit in a size class, and more than that get pages of
+        // their own
+        for align in [64, 512, 4096, 0x4000, 0x10_0000] {
+            let layout = Layout::from_size_align(64, align).unwrap();
+            let ptrs: Vec<_> = (0..4).map(|_| unsafe { heap.alloc(layout) }).collect();
+
+            for &ptr in &ptrs {
+                assert!(!ptr.is_null());
+                assert!(
+                    ptr.is_aligned_to(align),
+                    "{ptr:p} isn't aligned to {align:#x}"
+                );
+                unsafe { ptr.write_bytes(0xaa, layout.size()) };
+            }
+            for ptr in ptrs {
+                unsafe { heap.dealloc(ptr, layout) };
+            }
+        }
+
+        // Bigger than all of the size classes
+        let layout = Layout::from_size_align(3 * 4096 + 1, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe {
+            ptr.write_bytes(0xbb, layout.size());
+            heap.dealloc(ptr, layout);
+        };
+
+        assert_eq!(heap.stats().live, 0);
+    }
+}
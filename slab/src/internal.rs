@@ -63,7 +63,7 @@ impl InternalSlabAllocator {
     /// Creates a new slab allocator with const evaluation (unsafe version)
     pub(super) const fn new(layout: Layout) -> Self {
         let adjusted_layout = Self::adjust_layout(layout);
-        let page_count = 1; // TODO: Make this configurable
+        let page_count = Self::calculate_page_count(adjusted_layout);
 
         Self {
             slabs: LinkedList::new(),
@@ -73,15 +73,32 @@ impl InternalSlabAllocator {
         }
     }
 
-    /// Adjusts layout to meet minimum requirements (const version for unsafe const_new)
+    /// Adjusts layout to meet minimum requirements (const version for unsafe const_new).
+    ///
+    /// Objects are laid out back to back from the start of the slab, so the size is rounded up to
+    /// the alignment to keep every object aligned, not just the first one.
     const fn adjust_layout(layout: Layout) -> Layout {
-        let size = const_max!(layout.size(), size_of::<ObjectNode>());
         let align = const_max!(layout.align(), align_of::<ObjectNode>());
+        // Slabs are only guaranteed to be page aligned
+        assert!(
+            align <= BASIC_PAGE_SIZE.size(),
+            "Slab objects can't be aligned to more than a page"
+        );
+        let size = align_up(const_max!(layout.size(), size_of::<ObjectNode>()), align);
 
         // SAFETY: This is only used in const_new which is marked unsafe
         unsafe { Layout::from_size_align_unchecked(size, align) }
     }
 
+    /// Calculate how many pages a slab needs so at least one object fits in it, along with the
+    /// slab's metadata
+    // TODO: Make this configurable, so big objects don't waste most of their slab
+    const fn calculate_page_count(layout: Layout) -> usize {
+        let min_size = align_up(layout.size(), align_of::<SlabNode>()) + size_of::<SlabNode>();
+
+        min_size.div_ceil(BASIC_PAGE_SIZE.size())
+    }
+
     /// Allocates an object from the slab allocator
     pub(super) fn allocate(&mut self) -> Result<NonNull<()>, SlabError> {
         // Try to allocate from existing slabs (prefer partially filled ones)
@@ -104,6 +121,52 @@ impl InternalSlabAllocator {
         Err(SlabError::SlabFullInternalError)
     }
 
+    /// Allocates `count` objects at once, chained through their first word: each object points to
+    /// the next one, and the last one points to null. Returns the first object, or `None` if
+    /// `count` is 0.
+    ///
+    /// The objects are carved out of each slab's free list in one go, instead of looking for a
+    /// slab with free objects again for every object. Either all the objects are allocated, or
+    /// none of them are.
+    pub(super) fn allocate_chain(
+        &mut self,
+        count: usize,
+    ) -> Result<Option<NonNull<()>>, SlabError> {
+        let mut chain = None;
+        let mut allocated = 0;
+        for slab in self.slabs.iter_mut() {
+            allocated += slab.allocate_chain(count - allocated, &mut chain, self.layout);
+        }
+
+        while allocated < count {
+            if let Err(err) = self.grow() {
+                // Don't leave the objects we already took allocated
+                while let Some(object) = chain {
+                    chain = unsafe { object.cast::<Option<NonNull<()>>>().read() };
+                    unsafe { self.free(object).unwrap() };
+                }
+
+                return Err(err);
+            }
+
+            let slab = self.slabs.back_mut().unwrap();
+            allocated += slab.allocate_chain(count - allocated, &mut chain, self.layout);
+        }
+
+        Ok(chain)
+    }
+
+    /// Returns true if `ptr` points to an object in one of this allocator's slabs, i.e. it falls
+    /// within a slab's objects and on an object boundary. It doesn't tell whether the object is
+    /// currently allocated.
+    pub(super) fn owns(&self, ptr: NonNull<()>) -> bool {
+        ptr.as_ptr().is_aligned_to(self.layout.align())
+            && self
+                .slabs
+                .iter()
+                .any(|slab| slab.contains_object(ptr, self.layout))
+    }
+
     /// Frees the object pointed to by `ptr` from the slab allocator.
     ///
     /// SAFETY: This function is unsafe because the passed pointer needs to be a valid pointer to an
@@ -158,6 +221,12 @@ impl InternalSlabAllocator {
         unsafe {
             let buffer = pages.cast::<u8>();
 
+            // Every object's alignment relies on the buffer being aligned too
+            sanity_assert!(
+                buffer.as_ptr().is_aligned_to(self.layout.align()),
+                "Slab buffer isn't aligned to the object alignment"
+            );
+
             // Calculate where to place the slab metadata
             let objects_size = self.object_count * self.layout.size();
             let metadata_offset = align_up(objects_size, align_of::<SlabNode>());
@@ -234,6 +303,12 @@ impl Slab {
         ptr_addr >= buffer_start && ptr_addr < buffer_end
     }
 
+    /// Check if the given pointer points to the start of one of this slab's objects
+    fn contains_object(&self, ptr: NonNull<()>, layout: Layout) -> bool {
+        self.contains_ptr(ptr, layout)
+            && (ptr.as_ptr().addr() - self.buffer.as_ptr().addr()).is_multiple_of(layout.size())
+    }
+
     /// Check if this slab is completely empty
     fn is_empty(&self) -> bool {
         self.allocated_count == 0
@@ -256,6 +331,26 @@ impl Slab {
         }
     }
 
+    /// Allocate up to `count` objects from this slab, pushing each to the front of `chain` (see
+    /// `InternalSlabAllocator::allocate_chain`). Returns the amount of objects allocated
+    fn allocate_chain(
+        &mut self,
+        count: usize,
+        chain: &mut Option<NonNull<()>>,
+        layout: Layout,
+    ) -> usize {
+        let mut allocated = 0;
+        while allocated < count
+            && let Ok(object) = self.allocate(layout)
+        {
+            unsafe { object.cast::<Option<NonNull<()>>>().write(*chain) };
+            *chain = Some(object);
+            allocated += 1;
+        }
+
+        allocated
+    }
+
     /// Free an object back to this slab
     ///
     /// SAFETY: ptr must be a valid pointer that was allocated from this slab
@@ -293,7 +388,7 @@ const fn align_up(value: usize, align: usize) -> usize {
     (value + align - 1) & !(align - 1)
 }
 
-fn allocate_pages(pages_per_slab: usize) -> Result<NonNull<()>, PagingError> {
+pub(super) fn allocate_pages(pages_per_slab: usize) -> Result<NonNull<()>, PagingError> {
     #[cfg(test)]
     unsafe {
         use alloc::alloc::alloc_zeroed;
@@ -320,7 +415,10 @@ fn allocate_pages(pages_per_slab: usize) -> Result<NonNull<()>, PagingError> {
     }
 }
 
-unsafe fn free_pages(ptr: NonNull<()>, pages_per_slab: usize) -> Result<(), PagingError> {
+pub(super) unsafe fn free_pages(
+    ptr: NonNull<()>,
+    pages_per_slab: usize,
+) -> Result<(), PagingError> {
     #[cfg(test)]
     unsafe {
         use alloc::alloc::dealloc;
@@ -566,6 +664,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_owns_rejects_foreign_pointers() {
+        let mut allocator = InternalSlabAllocator::new(medium_layout());
+        let mut other = InternalSlabAllocator::new(medium_layout());
+
+        let ptr = allocator.allocate().unwrap();
+        let foreign = other.allocate().unwrap();
+        assert!(allocator.owns(ptr));
+        assert!(other.owns(foreign));
+
+        // Same layout, but a different cache's slab
+        assert!(!allocator.owns(foreign));
+        assert!(!other.owns(ptr));
+
+        // Inside the slab, but not on an object boundary
+        assert!(!allocator.owns(unsafe { ptr.byte_add(align_of::<ObjectNode>()) }));
+        assert!(!allocator.owns(NonNull::dangling()));
+
+        unsafe {
+            allocator.free(ptr).unwrap();
+            other.free(foreign).unwrap();
+        }
+    }
+
     #[test]
     fn test_slab_growth_multiple_times() {
         let mut allocator = InternalSlabAllocator::new(small_layout());
@@ -905,4 +1027,23 @@ mod tests {
 
         // Intentionally we don't drop, to make sure it panics if there are still slabs allocated
     }
+
+    #[test]
+    fn test_page_aligned_objects() {
+        #[repr(C, align(4096))]
+        struct PageAligned([u8; 4096]);
+
+        let mut allocator = InternalSlabAllocator::new(Layout::new::<PageAligned>());
+        assert!(allocator.object_count > 0);
+
+        let ptrs: Vec<_> = (0..8).map(|_| allocator.allocate().unwrap()).collect();
+        for ptr in &ptrs {
+            assert!(ptr.as_ptr().is_aligned_to(BASIC_PAGE_SIZE.size()));
+        }
+
+        for ptr in ptrs {
+            unsafe { allocator.free(ptr).unwrap() };
+        }
+        allocator.reap();
+    }
 }
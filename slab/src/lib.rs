@@ -1,34 +1,45 @@
 //! A simple slab allocator implementation
 
 #![cfg_attr(not(test), no_std)]
-#![feature(sync_unsafe_cell)]
 #![feature(allocator_api)]
 #![feature(pointer_is_aligned_to)]
 #![feature(box_vec_non_null)]
+#![cfg_attr(debug_assertions, feature(return_address))]
 // TODO: Remove this once you fix the `as` conversion warnings
 #![allow(clippy::cast_possible_truncation)]
 
-use core::{alloc::Layout, cell::SyncUnsafeCell, marker::PhantomData, ptr::NonNull};
+use core::{alloc::Layout, marker::PhantomData, ptr::NonNull};
 
 use alloc::alloc::{AllocError, Allocator};
 use internal::InternalSlabAllocator;
-use utils::sync::spinlock::SpinLockable;
+use utils::{
+    const_max, sanity_assert,
+    sync::spinlock::{SpinLock, SpinLockable},
+};
 
 extern crate alloc;
 
 // TODO: Actually call 'initializer' of the SlabAllocatable trait
 
+pub mod boxed;
 pub mod heap;
 mod internal;
 
+pub use boxed::{SlabAllocated, SlabBox};
+
 /// A trait for every type that can be allocated using a custom slab allocator.
-pub trait SlabAllocatable {}
+pub trait SlabAllocatable {
+    /// The minimum alignment of the objects in the slab, for types that need to be aligned past
+    /// their natural alignment (e.g. structures the hardware expects to be page aligned). The
+    /// stricter of this and the type's alignment is used, and it can't be more than a page.
+    const MIN_ALIGN: usize = 1;
+}
 
 pub struct SlabAllocator<T>
 where
     T: SlabAllocatable,
 {
-    allocator: SyncUnsafeCell<InternalSlabAllocator>,
+    allocator: SpinLock<InternalSlabAllocator>,
     phantom_data: PhantomData<T>,
 }
 
@@ -39,15 +50,102 @@ where
     #[inline]
     #[must_use]
     pub const fn new() -> Self {
-        let allocator = InternalSlabAllocator::new(Layout::new::<T>());
+        let allocator = InternalSlabAllocator::new(Self::slab_layout());
 
         Self {
-            allocator: SyncUnsafeCell::new(allocator),
+            allocator: SpinLock::new(allocator),
             phantom_data: PhantomData,
         }
     }
 }
 
+impl<T> SlabAllocator<T>
+where
+    T: SlabAllocatable,
+{
+    /// Allocate `count` objects at once, taking the lock only once instead of once per object.
+    ///
+    /// Either all the objects are allocated, or none of them are. The objects are uninitialized,
+    /// and each has to be freed (with `deallocate` or `deallocate_many`) once it's no longer used.
+    ///
+    /// # Errors
+    /// Fails if the slab allocator couldn't grow to fit all the objects
+    pub fn allocate_many(&self, count: usize) -> Result<BulkAllocation<T>, AllocError> {
+        let next = self
+            .allocator
+            .lock()
+            .allocate_chain(count)
+            .map_err(|_| AllocError)?;
+
+        Ok(BulkAllocation {
+            next,
+            remaining: count,
+            phantom_data: PhantomData,
+        })
+    }
+
+    /// Free all of `objects` at once, taking the lock only once instead of once per object.
+    ///
+    /// # Safety
+    /// Every object must have been allocated by this allocator, and must not be used afterwards
+    ///
+    /// # Panics
+    /// Panics if one of the objects isn't allocated by this allocator
+    pub unsafe fn deallocate_many(&self, objects: impl IntoIterator<Item = NonNull<T>>) {
+        let mut allocator = self.allocator.lock();
+        for object in objects {
+            sanity_assert!(
+                allocator.owns(object.cast()),
+                "Tried to deallocate a pointer from another slab allocator"
+            );
+            assert!(
+                unsafe { allocator.free(object.cast()).is_ok() },
+                "Tried to deallocate a pointer that was not allocated by this allocator"
+            );
+        }
+    }
+
+    /// The layout of the objects in the slab, which is `T`'s layout with `T::MIN_ALIGN` applied
+    const fn slab_layout() -> Layout {
+        let layout = Layout::new::<T>();
+        let align = const_max!(layout.align(), T::MIN_ALIGN);
+
+        match Layout::from_size_align(layout.size(), align) {
+            Ok(layout) => layout,
+            Err(_) => panic!("Invalid minimum slab alignment"),
+        }
+    }
+}
+
+/// The objects allocated by `SlabAllocator::allocate_many`.
+///
+/// NOTE: The objects that aren't taken out of the iterator are leaked
+pub struct BulkAllocation<T> {
+    /// The next object. Each object not taken yet holds a pointer to the one after it
+    next: Option<NonNull<()>>,
+    /// The amount of objects not taken yet
+    remaining: usize,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> Iterator for BulkAllocation<T> {
+    type Item = NonNull<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let object = self.next?;
+        self.next = unsafe { object.cast::<Option<NonNull<()>>>().read() };
+        self.remaining -= 1;
+
+        Some(object.cast())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for BulkAllocation<T> {}
+
 // XXX: We need to make sure only values T are allocated using this allocator. Checking the layout
 // isn't enough if we're gonna use the 'initalizer()' fn
 unsafe impl<T> Allocator for SlabAllocator<T>
@@ -60,18 +158,12 @@ where
             "Tried to allocate incompatible type 'A' with a slab allocator designated for type 'B'"
         );
 
-        // Try getting the allocator
-        // Then also try allocating an object
-        if let Some(allocator) = unsafe { self.allocator.get().as_mut() } {
-            let object = allocator.allocate().unwrap();
-            // if we were successfull, return the object
-            Ok(NonNull::slice_from_raw_parts(
-                object.cast::<u8>(),
-                layout.size(),
-            ))
-        } else {
-            Err(AllocError)
-        }
+        let object = self.allocator.lock().allocate().map_err(|_| AllocError)?;
+
+        Ok(NonNull::slice_from_raw_parts(
+            object.cast::<u8>(),
+            layout.size(),
+        ))
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
@@ -80,16 +172,144 @@ where
             "Tried to deallocate incompatible type 'A' with a slab allocator designated for type 'B'"
         );
 
-        // Try getting the allocator
-        if let Some(allocator) = unsafe { self.allocator.get().as_mut() } {
-            assert!(
-                !unsafe { allocator.free(ptr.cast()).is_err() },
-                "Tried to deallocate a pointer that was not allocated by this allocator"
-            );
-        }
+        let mut allocator = self.allocator.lock();
+        sanity_assert!(
+            allocator.owns(ptr.cast()),
+            "Tried to deallocate a pointer from another slab allocator"
+        );
+        assert!(
+            unsafe { allocator.free(ptr.cast()).is_ok() },
+            "Tried to deallocate a pointer that was not allocated by this allocator"
+        );
     }
 }
 
 unsafe impl<T> Sync for SlabAllocator<T> where T: SlabAllocatable + Send {}
 
 impl<T> SpinLockable for SlabAllocator<T> where T: SlabAllocatable + Send {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, vec::Vec};
+    use std::{collections::HashSet, thread};
+
+    #[repr(C)]
+    struct TestObject {
+        a: u64,
+        b: u64,
+    }
+
+    impl SlabAllocatable for TestObject {}
+
+    #[repr(C)]
+    struct PageAlignedObject {
+        a: u64,
+    }
+
+    impl SlabAllocatable for PageAlignedObject {
+        const MIN_ALIGN: usize = 0x1000;
+    }
+
+    #[test]
+    fn test_concurrent_allocations_are_unique() {
+        const THREAD_COUNT: usize = 8;
+        const ALLOCATIONS_PER_THREAD: usize = 256;
+
+        let allocator: SlabAllocator<TestObject> = SlabAllocator::new();
+
+        // NOTE: The spinlock might serialize the threads, but either way no object should ever be
+        // handed out twice
+        let addrs: Vec<usize> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..THREAD_COUNT)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..ALLOCATIONS_PER_THREAD)
+                            .map(|_| {
+                                allocator
+                                    .allocate(Layout::new::<TestObject>())
+                                    .unwrap()
+                                    .cast::<u8>()
+                                    .expose_provenance()
+                                    .get()
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let unique: HashSet<usize> = addrs.iter().copied().collect();
+        assert_eq!(unique.len(), THREAD_COUNT * ALLOCATIONS_PER_THREAD);
+
+        for addr in addrs {
+            unsafe {
+                allocator.deallocate(
+                    NonNull::with_exposed_provenance(addr.try_into().unwrap()),
+                    Layout::new::<TestObject>(),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bulk_allocation() {
+        let allocator: SlabAllocator<TestObject> = SlabAllocator::new();
+
+        // Enough objects to need more than one slab
+        let objects: Vec<_> = allocator.allocate_many(500).unwrap().collect();
+        assert_eq!(objects.len(), 500);
+        let unique: HashSet<_> = objects.iter().copied().collect();
+        assert_eq!(unique.len(), 500);
+
+        for (i, object) in objects.iter().enumerate() {
+            assert!(object.as_ptr().is_aligned());
+            unsafe { object.write(TestObject { a: i as u64, b: 0 }) };
+        }
+        for (i, object) in objects.iter().enumerate() {
+            assert_eq!(unsafe { object.as_ref().a }, i as u64);
+        }
+
+        // All of them go back to the free lists, so the same objects are handed out again
+        unsafe { allocator.deallocate_many(objects) };
+        let objects: HashSet<_> = allocator.allocate_many(500).unwrap().collect();
+        assert_eq!(objects, unique);
+
+        // Bulk allocations mix with single ones
+        let single = allocator
+            .allocate(Layout::new::<TestObject>())
+            .unwrap()
+            .cast::<TestObject>();
+        assert!(!objects.contains(&single));
+        unsafe { allocator.deallocate_many(objects.into_iter().chain([single])) };
+
+        assert_eq!(allocator.allocate_many(0).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_box_in_slab_allocator() {
+        let allocator: SlabAllocator<TestObject> = SlabAllocator::new();
+
+        let object = Box::new_in(TestObject { a: 1, b: 2 }, &allocator);
+        assert_eq!(object.a + object.b, 3);
+    }
+
+    #[test]
+    fn test_min_align() {
+        let allocator: SlabAllocator<PageAlignedObject> = SlabAllocator::new();
+
+        let objects: Vec<_> = (0..4)
+            .map(|i| Box::new_in(PageAlignedObject { a: i }, &allocator))
+            .collect();
+
+        for (i, object) in objects.iter().enumerate() {
+            assert!(core::ptr::from_ref(&**object).is_aligned_to(0x1000));
+            assert_eq!(object.a, i as u64);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Everything the bootloader tells the kernel, in a bootloader agnostic form.
+//!
+//! The boot code fills a `BootInfo` from whatever the bootloader hands it, and stores it with
+//! `init`. From then on, subsystems are initialized from the `BootInfo`, and never have to deal
+//! with the bootloader's own types.
+
+use crate::{
+    cmdline::KernelArgs,
+    collections::arrayvec::ArrayVec,
+    mem::{PhysAddr, VirtAddr},
+    sync::once::Once,
+};
+
+/// The maximum amount of memory map regions kept track of
+pub const MAX_MEMORY_REGIONS: usize = 128;
+
+/// The maximum amount of boot modules kept track of
+pub const MAX_MODULES: usize = 16;
+
+/// What a region of physical memory is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    /// Free for the kernel to use
+    Usable,
+    /// Used by the firmware or hardware, and shouldn't be touched
+    Reserved,
+    /// Holds the ACPI tables, and can be used once they are no longer needed
+    AcpiReclaimable,
+    /// Used by the firmware to keep its state across sleep states
+    AcpiNvs,
+    /// Faulty memory
+    BadMemory,
+    /// Used by the bootloader, and can be used once its data is no longer needed
+    BootloaderReclaimable,
+    /// Holds the kernel's image and the boot modules
+    ExecutableAndModules,
+    /// The framebuffer
+    Framebuffer,
+}
+
+/// A region of physical memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: PhysAddr,
+    /// The length of the region, in bytes
+    pub length: usize,
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    /// Get the address right after the end of the region
+    #[inline]
+    #[must_use]
+    pub const fn end(&self) -> PhysAddr {
+        PhysAddr(self.base.0 + self.length)
+    }
+}
+
+/// Where a color channel's bits are within a framebuffer pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorMask {
+    /// The amount of bits the channel takes
+    pub size: u8,
+    /// The index of the channel's lowest bit
+    pub shift: u8,
+}
+
+/// The framebuffer the bootloader set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    /// Where the framebuffer is mapped
+    pub addr: VirtAddr,
+    /// The width, in pixels
+    pub width: u64,
+    /// The height, in pixels
+    pub height: u64,
+    /// The amount of bytes between the start of each row
+    pub pitch: u64,
+    /// The amount of bits per pixel
+    pub bpp: u16,
+    pub red_mask: ColorMask,
+    pub green_mask: ColorMask,
+    pub blue_mask: ColorMask,
+}
+
+/// A file the bootloader loaded along with the kernel (e.g. a font)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootModule {
+    /// Where the module is mapped
+    pub addr: VirtAddr,
+    /// The size of the module, in bytes
+    pub size: usize,
+    /// The command line the module was given in the bootloader's config, which tells modules apart
+    pub cmdline: &'static str,
+}
+
+impl BootModule {
+    /// Get the contents of the module.
+    ///
+    /// # Safety
+    /// The module must still be mapped at `addr`, and its memory must not be reused for as long as
+    /// the contents are used
+    #[inline]
+    #[must_use]
+    pub const unsafe fn data(&self) -> &'static [u8] {
+        unsafe {
+            core::slice::from_raw_parts(core::ptr::without_provenance(self.addr.0), self.size)
+        }
+    }
+}
+
+/// Everything the bootloader tells the kernel
+#[derive(Debug)]
+pub struct BootInfo {
+    hhdm_offset: usize,
+    kernel_virt: VirtAddr,
+    kernel_phys: PhysAddr,
+    rsdp: Option<PhysAddr>,
+    memory_map: ArrayVec<MemoryRegion, MAX_MEMORY_REGIONS>,
+    framebuffer: Option<FramebufferInfo>,
+    modules: ArrayVec<BootModule, MAX_MODULES>,
+    args: KernelArgs,
+}
+
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+impl BootInfo {
+    /// Create a new `BootInfo`, for a kernel loaded at `kernel_phys` and mapped at `kernel_virt`,
+    /// with the HHDM at `hhdm_offset`. The rest is filled in using the setters.
+    #[inline]
+    #[must_use]
+    pub const fn new(hhdm_offset: usize, kernel_virt: VirtAddr, kernel_phys: PhysAddr) -> Self {
+        Self {
+            hhdm_offset,
+            kernel_virt,
+            kernel_phys,
+            rsdp: None,
+            memory_map: ArrayVec::new(),
+            framebuffer: None,
+            modules: ArrayVec::new(),
+            args: KernelArgs::EMPTY,
+        }
+    }
+
+    /// Add a region to the end of the memory map. Regions should be added in order of address.
+    ///
+    /// # Errors
+    /// Fails if the memory map is full, in which case the region is handed back
+    #[inline]
+    pub fn push_memory_region(&mut self, region: MemoryRegion) -> Result<(), MemoryRegion> {
+        self.memory_map.push(region)
+    }
+
+    /// Add a boot module.
+    ///
+    /// # Errors
+    /// Fails if there are too many modules, in which case the module is handed back
+    #[inline]
+    pub fn push_module(&mut self, module: BootModule) -> Result<(), BootModule> {
+        self.modules.push(module)
+    }
+
+    #[inline]
+    pub const fn set_rsdp(&mut self, rsdp: PhysAddr) {
+        self.rsdp = Some(rsdp);
+    }
+
+    #[inline]
+    pub const fn set_args(&mut self, args: KernelArgs) {
+        self.args = args;
+    }
+
+    #[inline]
+    pub const fn set_framebuffer(&mut self, framebuffer: FramebufferInfo) {
+        self.framebuffer = Some(framebuffer);
+    }
+
+    /// Get the offset of the HHDM, where all of physical memory is mapped
+    #[inline]
+    #[must_use]
+    pub const fn hhdm_offset(&self) -> usize {
+        self.hhdm_offset
+    }
+
+    /// Get the virtual address the kernel's image is mapped at
+    #[inline]
+    #[must_use]
+    pub const fn kernel_virt(&self) -> VirtAddr {
+        self.kernel_virt
+    }
+
+    /// Get the physical address the kernel's image is loaded at
+    #[inline]
+    #[must_use]
+    pub const fn kernel_phys(&self) -> PhysAddr {
+        self.kernel_phys
+    }
+
+    /// Get the physical address of the RSDP, or `None` if the bootloader didn't find it
+    #[inline]
+    #[must_use]
+    pub const fn rsdp(&self) -> Option<PhysAddr> {
+        self.rsdp
+    }
+
+    /// Get the memory map, sorted by address
+    #[inline]
+    #[must_use]
+    pub const fn memory_map(&self) -> &[MemoryRegion] {
+        self.memory_map.as_slice()
+    }
+
+    /// Get the framebuffer, or `None` if the bootloader didn't set one up
+    #[inline]
+    #[must_use]
+    pub const fn framebuffer(&self) -> Option<&FramebufferInfo> {
+        self.framebuffer.as_ref()
+    }
+
+    /// Get the boot modules, in the order the bootloader loaded them
+    #[inline]
+    #[must_use]
+    pub const fn modules(&self) -> &[BootModule] {
+        self.modules.as_slice()
+    }
+
+    /// Get the kernel's command line arguments. Empty if the bootloader didn't pass any
+    #[inline]
+    #[must_use]
+    pub const fn args(&self) -> &KernelArgs {
+        &self.args
+    }
+
+    /// Get the first boot module whose command line is `cmdline`
+    #[must_use]
+    pub fn module(&self, cmdline: &str) -> Option<&BootModule> {
+        self.modules()
+            .iter()
+            .find(|module| module.cmdline == cmdline)
+    }
+}
+
+/// Store the boot info, so it's available for the rest of the kernel's lifetime.
+///
+/// # Panics
+/// Panics if the boot info was already stored
+pub fn init(boot_info: BootInfo) -> &'static BootInfo {
+    BOOT_INFO
+        .set(boot_info)
+        .unwrap_or_else(|_| panic!("The boot info was already initialized"))
+}
+
+/// Get the boot info, or `None` if it wasn't stored yet
+#[inline]
+#[must_use]
+pub fn get() -> Option<&'static BootInfo> {
+    BOOT_INFO.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accessors_return_stored_values() {
+        let mut boot_info = BootInfo::new(
+            0xffff_8000_0000_0000,
+            VirtAddr(0xffff_ffff_8000_0000),
+            PhysAddr(0x20_0000),
+        );
+        assert_eq!(boot_info.rsdp(), None);
+        assert_eq!(boot_info.framebuffer(), None);
+        assert_eq!(boot_info.memory_map(), []);
+        assert_eq!(boot_info.modules(), []);
+        assert_eq!(boot_info.args().as_str(), "");
+
+        let regions = [
+            MemoryRegion {
+                base: PhysAddr(0),
+                length: 0x9_f000,
+                kind: MemoryRegionKind::Usable,
+            },
+            MemoryRegion {
+                base: PhysAddr(0x20_0000),
+                length: 0x10_0000,
+                kind: MemoryRegionKind::ExecutableAndModules,
+            },
+        ];
+        for region in regions {
+            boot_info.push_memory_region(region).unwrap();
+        }
+        let framebuffer = FramebufferInfo {
+            addr: VirtAddr(0xffff_8000_fd00_0000),
+            width: 1024,
+            height: 768,
+            pitch: 4096,
+            bpp: 32,
+            red_mask: ColorMask { size: 8, shift: 16 },
+            green_mask: ColorMask { size: 8, shift: 8 },
+            blue_mask: ColorMask { size: 8, shift: 0 },
+        };
+        let font = BootModule {
+            addr: VirtAddr(0xffff_8000_0030_0000),
+            size: 0x1000,
+            cmdline: "font",
+        };
+        boot_info.set_rsdp(PhysAddr(0xe_0000));
+        boot_info.set_framebuffer(framebuffer);
+        boot_info.push_module(font).unwrap();
+        boot_info.set_args(KernelArgs::new("loglevel=debug noapic").unwrap());
+
+        assert_eq!(boot_info.hhdm_offset(), 0xffff_8000_0000_0000);
+        assert_eq!(boot_info.kernel_virt(), VirtAddr(0xffff_ffff_8000_0000));
+        assert_eq!(boot_info.kernel_phys(), PhysAddr(0x20_0000));
+        assert_eq!(boot_info.rsdp(), Some(PhysAddr(0xe_0000)));
+        assert_eq!(boot_info.memory_map(), regions);
+        assert_eq!(boot_info.memory_map()[1].end(), PhysAddr(0x30_0000));
+        assert_eq!(boot_info.framebuffer(), Some(&framebuffer));
+        assert_eq!(boot_info.modules(), [font]);
+        assert_eq!(boot_info.module("font"), Some(&font));
+        assert_eq!(boot_info.module("initrd"), None);
+        assert_eq!(boot_info.args().get("loglevel"), Some("debug"));
+        assert!(boot_info.args().flag("noapic"));
+
+        // Once stored, it's the same boot info everywhere
+        let stored = init(boot_info);
+        assert!(core::ptr::eq(get().unwrap(), stored));
+        assert_eq!(stored.rsdp(), Some(PhysAddr(0xe_0000)));
+    }
+}
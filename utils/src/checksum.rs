@@ -0,0 +1,109 @@
+//! Checksums used by firmware tables and on-disk structures
+
+/// Get the 8 bit sum of `bytes`, which ACPI tables use as their checksum.
+///
+/// A table's checksum field is set so that all of its bytes (including the field itself) sum up
+/// to 0, so a valid table sums up to 0
+#[must_use]
+pub fn acpi_checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Returns true if `bytes` sum up to 0, i.e. if the ACPI checksum of the structure they make up
+/// is valid
+#[must_use]
+pub fn verify(bytes: &[u8]) -> bool {
+    acpi_checksum(bytes) == 0
+}
+
+/// The CRC-32 lookup table, one entry per byte value
+const CRC32_TABLE: [u32; 256] = {
+    /// The reversed CRC-32 (IEEE 802.3) polynomial
+    const POLYNOMIAL: u32 = 0xedb8_8320;
+
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < table.len() {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ POLYNOMIAL
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// Get the CRC-32 (IEEE 802.3, as used by GPT and zlib) of `bytes`
+#[must_use]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(u32::MAX, |crc, &byte| {
+        CRC32_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RSDP (revision 2) as QEMU builds it. The first 20 bytes and all 36 sum up to 0 separately
+    const RSDP: [u8; 36] = [
+        0x52, 0x53, 0x44, 0x20, 0x50, 0x54, 0x52, 0x20, 0x8a, 0x42, 0x4f, 0x43, 0x48, 0x53, 0x20,
+        0x02, 0xad, 0x14, 0xfe, 0x07, 0x24, 0x00, 0x00, 0x00, 0xa1, 0x15, 0xfe, 0x07, 0x00, 0x00,
+        0x00, 0x00, 0x21, 0x00, 0x00, 0x00,
+    ];
+
+    /// An HPET table as QEMU builds it
+    const HPET: [u8; 56] = [
+        0x48, 0x50, 0x45, 0x54, 0x38, 0x00, 0x00, 0x00, 0x01, 0x74, 0x42, 0x4f, 0x43, 0x48, 0x53,
+        0x20, 0x42, 0x58, 0x50, 0x43, 0x20, 0x20, 0x20, 0x20, 0x01, 0x00, 0x00, 0x00, 0x42, 0x58,
+        0x50, 0x43, 0x01, 0x00, 0x00, 0x00, 0x01, 0xa2, 0x86, 0x80, 0x00, 0x40, 0x00, 0x00, 0x00,
+        0x00, 0xd0, 0xfe, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_valid_acpi_tables() {
+        assert!(verify(&RSDP[..20]));
+        assert!(verify(&RSDP));
+        assert!(verify(&HPET));
+
+        // Nothing sums up to 0
+        assert!(verify(&[]));
+    }
+
+    #[test]
+    fn test_corrupted_acpi_tables() {
+        let mut hpet = HPET;
+        // The HPET's base address got a bit flipped
+        hpet[46] ^= 0x10;
+        assert!(!verify(&hpet));
+        assert_eq!(acpi_checksum(&hpet), 0xf0);
+
+        // Fixing up the checksum field makes it valid again
+        hpet[9] = hpet[9].wrapping_sub(acpi_checksum(&hpet));
+        assert!(verify(&hpet));
+
+        // Only the extended part of the RSDP is off, so only the full checksum catches it
+        let mut rsdp = RSDP;
+        rsdp[24] = 0;
+        assert!(verify(&rsdp[..20]));
+        assert!(!verify(&rsdp));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+}
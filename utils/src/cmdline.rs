@@ -0,0 +1,215 @@
+//! The kernel's command line, for configuring it at boot time instead of at build time.
+//!
+//! The command line is a list of whitespace separated arguments, each either a flag (`noapic`) or
+//! a `key=value` pair (`loglevel=debug`). Double quotes let an argument contain whitespace
+//! (`label="test machine"`), and are stripped from it.
+
+use core::fmt;
+
+/// The longest command line that can be stored
+pub const MAX_CMDLINE_LEN: usize = 1024;
+
+/// An argument on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arg<'a> {
+    pub key: &'a str,
+    /// The value after the `=`, or `None` if the argument is a flag
+    pub value: Option<&'a str>,
+}
+
+/// The kernel's command line, copied out of the bootloader's memory
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KernelArgs {
+    bytes: [u8; MAX_CMDLINE_LEN],
+    len: usize,
+}
+
+impl KernelArgs {
+    /// An empty command line, for when the bootloader didn't pass one
+    pub const EMPTY: Self = Self {
+        bytes: [0; MAX_CMDLINE_LEN],
+        len: 0,
+    };
+
+    /// Copy `cmdline`, or return `None` if it's longer than `MAX_CMDLINE_LEN`
+    #[must_use]
+    pub fn new(cmdline: &str) -> Option<Self> {
+        let mut args = Self::EMPTY;
+        args.bytes
+            .get_mut(..cmdline.len())?
+            .copy_from_slice(cmdline.as_bytes());
+        args.len = cmdline.len();
+
+        Some(args)
+    }
+
+    /// Get the command line, as the bootloader passed it
+    #[inline]
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        // Copied from a `&str` in `new`, so it's valid UTF-8
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+
+    /// Get the arguments, in the order they were passed
+    #[inline]
+    #[must_use]
+    pub fn iter(&self) -> Args<'_> {
+        Args {
+            rest: self.as_str(),
+        }
+    }
+
+    /// Get the value of `key`, or `None` if it wasn't passed with a value. If `key` was passed
+    /// more than once, the last one wins
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter()
+            .filter(|arg| arg.key == key)
+            .last()
+            .and_then(|arg| arg.value)
+    }
+
+    /// Returns true if `key` was passed, either as a flag or with a value
+    #[must_use]
+    pub fn flag(&self, key: &str) -> bool {
+        self.iter().any(|arg| arg.key == key)
+    }
+}
+
+impl<'a> IntoIterator for &'a KernelArgs {
+    type Item = Arg<'a>;
+    type IntoIter = Args<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for KernelArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("KernelArgs").field(&self.as_str()).finish()
+    }
+}
+
+/// An iterator over the arguments of a command line
+#[derive(Debug, Clone)]
+pub struct Args<'a> {
+    /// The part of the command line that wasn't parsed yet
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Arg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rest = self.rest.trim_start();
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        // Whitespace only ends the argument outside of quotes. An unterminated quote runs to the
+        // end of the command line
+        let mut in_quotes = false;
+        let end = self
+            .rest
+            .find(|c: char| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .unwrap_or(self.rest.len());
+        let (arg, rest) = self.rest.split_at(end);
+        self.rest = rest;
+
+        // The whole argument might be quoted (`"key=value"`), or just its value (`key="value"`)
+        let arg = unquote(arg);
+        Some(match arg.split_once('=') {
+            Some((key, value)) => Arg {
+                key,
+                value: Some(unquote(value)),
+            },
+            None => Arg {
+                key: arg,
+                value: None,
+            },
+        })
+    }
+}
+
+/// Strip the quotes around `s`, if it's quoted
+fn unquote(s: &str) -> &str {
+    match s.strip_prefix('"') {
+        Some(quoted) => quoted.strip_suffix('"').unwrap_or(quoted),
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Assert that `cmdline` is parsed into the `(key, value)` pairs `expected`
+    fn assert_args(cmdline: &str, expected: &[(&str, Option<&str>)]) {
+        let args = KernelArgs::new(cmdline).unwrap();
+        let parsed: Vec<_> = args.iter().map(|arg| (arg.key, arg.value)).collect();
+
+        assert_eq!(parsed, expected, "{cmdline}");
+    }
+
+    #[test]
+    fn test_flags_and_values() {
+        assert_args(
+            "loglevel=debug noapic  timer=hpet\tquiet",
+            &[
+                ("loglevel", Some("debug")),
+                ("noapic", None),
+                ("timer", Some("hpet")),
+                ("quiet", None),
+            ],
+        );
+
+        // An empty value isn't the same as no value
+        assert_args("root= ro", &[("root", Some("")), ("ro", None)]);
+        assert_args("", &[]);
+        assert_args("   ", &[]);
+    }
+
+    #[test]
+    fn test_quoted_values() {
+        assert_args(
+            r#"label="test machine" "title=a b" path="x=y" last"#,
+            &[
+                ("label", Some("test machine")),
+                ("title", Some("a b")),
+                ("path", Some("x=y")),
+                ("last", None),
+            ],
+        );
+
+        // An unterminated quote takes the rest of the line
+        assert_args(
+            r#"noapic name="never closed"#,
+            &[("noapic", None), ("name", Some("never closed"))],
+        );
+    }
+
+    #[test]
+    fn test_queries() {
+        let args = KernelArgs::new("loglevel=info noapic loglevel=debug pmm").unwrap();
+
+        assert_eq!(args.get("loglevel"), Some("debug"));
+        assert_eq!(args.get("noapic"), None);
+        assert_eq!(args.get("timer"), None);
+        assert!(args.flag("noapic"));
+        assert!(args.flag("loglevel"));
+        assert!(!args.flag("pm"));
+        assert_eq!(args.as_str(), "loglevel=info noapic loglevel=debug pmm");
+
+        assert_eq!(KernelArgs::EMPTY.iter().count(), 0);
+        assert!(KernelArgs::new(&"a".repeat(MAX_CMDLINE_LEN)).is_some());
+        assert!(KernelArgs::new(&"a".repeat(MAX_CMDLINE_LEN + 1)).is_none());
+    }
+}
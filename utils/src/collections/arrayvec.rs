@@ -0,0 +1,292 @@
+//! A fixed capacity, `Vec`-like collection that doesn't require an allocator
+
+use core::{
+    fmt::{self, Debug},
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    ptr, slice,
+};
+
+/// A `Vec`-like collection that can hold up to `N` elements, stored inline.
+///
+/// Useful for code that runs before the heap is initialized, or in contexts where allocating
+/// isn't possible (e.g. interrupt handlers).
+pub struct ArrayVec<T, const N: usize> {
+    /// The elements. Only the first `len` are initialized
+    data: [MaybeUninit<T>; N],
+    /// The amount of initialized elements
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    /// Creates a new, empty `ArrayVec`
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            data: [const { MaybeUninit::uninit() }; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the amount of elements in the `ArrayVec`
+    #[inline]
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the `ArrayVec` has no elements
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the maximum amount of elements the `ArrayVec` can hold
+    #[inline]
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns true if no more elements can be pushed
+    #[inline]
+    #[must_use]
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Pushes `value` to the end of the `ArrayVec`.
+    ///
+    /// # Errors
+    /// If the `ArrayVec` is full, `value` is handed back as the error.
+    #[inline]
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+
+        self.data[self.len].write(value);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the last element and returns it, or `None` if the `ArrayVec` is empty
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.len -= 1;
+        // SAFETY: The element at `len` was initialized, and since we decremented `len` it won't be
+        // read again
+        Some(unsafe { self.data[self.len].assume_init_read() })
+    }
+
+    /// Drops all the elements
+    #[inline]
+    pub fn clear(&mut self) {
+        let elements: *mut [T] = self.as_mut_slice();
+        // Setting the length first, so if a `drop` panics we don't drop things twice
+        self.len = 0;
+
+        unsafe { ptr::drop_in_place(elements) };
+    }
+
+    /// Returns the initialized elements as a slice
+    #[inline]
+    #[must_use]
+    pub const fn as_slice(&self) -> &[T] {
+        // SAFETY: The first `len` elements are initialized
+        unsafe { slice::from_raw_parts(self.data.as_ptr().cast::<T>(), self.len) }
+    }
+
+    /// Returns the initialized elements as a mutable slice
+    #[inline]
+    #[must_use]
+    pub const fn as_mut_slice(&mut self) -> &mut [T] {
+        // SAFETY: The first `len` elements are initialized
+        unsafe { slice::from_raw_parts_mut(self.data.as_mut_ptr().cast::<T>(), self.len) }
+    }
+}
+
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T, const N: usize> Deref for ArrayVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.as_mut_slice()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a ArrayVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut ArrayVec<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_mut_slice().iter_mut()
+    }
+}
+
+impl<T: Debug, const N: usize> Debug for ArrayVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{rc::Rc, vec::Vec};
+
+    #[test]
+    fn test_new_is_empty() {
+        let vec: ArrayVec<u32, 4> = ArrayVec::new();
+
+        assert!(vec.is_empty());
+        assert!(!vec.is_full());
+        assert_eq!(vec.len(), 0);
+        assert_eq!(vec.capacity(), 4);
+        assert_eq!(vec.as_slice(), &[]);
+    }
+
+    #[test]
+    fn test_fill_to_capacity() {
+        let mut vec: ArrayVec<u32, 3> = ArrayVec::new();
+
+        assert_eq!(vec.push(1), Ok(()));
+        assert_eq!(vec.push(2), Ok(()));
+        assert_eq!(vec.push(3), Ok(()));
+        assert!(vec.is_full());
+
+        // The value should be handed back when full
+        assert_eq!(vec.push(4), Err(4));
+        assert_eq!(vec.len(), 3);
+        assert_eq!(vec.as_slice(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let mut vec: ArrayVec<u32, 0> = ArrayVec::new();
+
+        assert!(vec.is_full());
+        assert_eq!(vec.push(1), Err(1));
+        assert_eq!(vec.pop(), None);
+    }
+
+    #[test]
+    fn test_pop() {
+        let mut vec: ArrayVec<u32, 3> = ArrayVec::new();
+        vec.push(1).unwrap();
+        vec.push(2).unwrap();
+
+        assert_eq!(vec.pop(), Some(2));
+        assert_eq!(vec.pop(), Some(1));
+        assert_eq!(vec.pop(), None);
+        assert!(vec.is_empty());
+
+        // Popping should free up room
+        vec.push(3).unwrap();
+        assert_eq!(vec.as_slice(), &[3]);
+    }
+
+    #[test]
+    fn test_iteration_order() {
+        let mut vec: ArrayVec<u32, 8> = ArrayVec::new();
+        for i in 0..5 {
+            vec.push(i).unwrap();
+        }
+
+        let items: Vec<_> = vec.iter().copied().collect();
+        assert_eq!(items, [0, 1, 2, 3, 4]);
+
+        for item in &mut vec {
+            *item *= 10;
+        }
+
+        let items: Vec<_> = (&vec).into_iter().copied().collect();
+        assert_eq!(items, [0, 10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn test_indexing() {
+        let mut vec: ArrayVec<u32, 4> = ArrayVec::new();
+        vec.push(7).unwrap();
+        vec.push(8).unwrap();
+
+        assert_eq!(vec[0], 7);
+        assert_eq!(vec[1], 8);
+        assert_eq!(vec.get(2), None);
+
+        vec[1] = 9;
+        assert_eq!(vec[1], 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds")]
+    fn test_index_past_len() {
+        let mut vec: ArrayVec<u32, 4> = ArrayVec::new();
+        vec.push(1).unwrap();
+
+        let _ = vec[1];
+    }
+
+    #[test]
+    fn test_drop_elements() {
+        let counter = Rc::new(());
+
+        {
+            let mut vec: ArrayVec<Rc<()>, 4> = ArrayVec::new();
+            vec.push(Rc::clone(&counter)).unwrap();
+            vec.push(Rc::clone(&counter)).unwrap();
+            assert_eq!(Rc::strong_count(&counter), 3);
+
+            drop(vec.pop());
+            assert_eq!(Rc::strong_count(&counter), 2);
+        }
+
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_clear() {
+        let counter = Rc::new(());
+        let mut vec: ArrayVec<Rc<()>, 4> = ArrayVec::new();
+        vec.push(Rc::clone(&counter)).unwrap();
+        vec.push(Rc::clone(&counter)).unwrap();
+
+        vec.clear();
+
+        assert!(vec.is_empty());
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+}
@@ -2,10 +2,14 @@ use alloc::vec;
 use alloc::vec::Vec;
 use core::iter::Iterator;
 
-/// A dynamic bitmap implementation with grow/shrink capabilities
+/// A dynamic bitmap implementation with grow/shrink capabilities.
+///
+/// The bits are stored in `S`, which is a `Vec` by default. Any other byte buffer (e.g. a borrowed
+/// slice of memory, for when there is no heap yet) can be used with `from_storage`, only without
+/// growing and shrinking.
 #[derive(Clone, Debug, PartialEq)]
-pub struct Bitmap {
-    entries: Vec<u8>,
+pub struct Bitmap<S = Vec<u8>> {
+    entries: S,
     used_bits_count: usize,
 }
 
@@ -35,49 +39,54 @@ impl Bitmap {
         }
     }
 
-    /// Sets the bit at the given index
+    /// Grows the bitmap to accommodate `new_size` bits
     ///
     /// # Errors
-    /// Returns an error if the index is out of bounds.
-    pub fn set(&mut self, index: usize) -> Result<(), BitmapError> {
-        if index >= self.used_bits_count {
-            return Err(BitmapError::IndexOutOfBounds {
-                index,
-                size: self.used_bits_count,
-            });
+    /// Returns an error if `new_size` is less than the current used bits count.
+    pub fn grow(&mut self, new_size: usize) -> Result<(), BitmapError> {
+        if new_size < self.used_bits_count {
+            return Err(BitmapError::InvalidSize { size: new_size });
         }
-        self.entries[index / 8] |= 1 << (index % 8);
+        let new_entries_count = new_size.div_ceil(8);
+        if new_entries_count > self.entries.len() {
+            self.entries.resize(new_entries_count, 0);
+        }
+        self.used_bits_count = new_size;
         Ok(())
     }
 
-    /// Unsets the bit at the given index
+    /// Shrinks the bitmap to `new_size` bits
     ///
     /// # Errors
-    /// Returns an error if the index is out of bounds.
-    pub fn unset(&mut self, index: usize) -> Result<(), BitmapError> {
-        if index >= self.used_bits_count {
-            return Err(BitmapError::IndexOutOfBounds {
-                index,
-                size: self.used_bits_count,
-            });
+    /// Returns an error if `new_size` is greater than the current used bits count.
+    pub fn shrink(&mut self, new_size: usize) -> Result<(), BitmapError> {
+        if new_size > self.used_bits_count {
+            return Err(BitmapError::InvalidSize { size: new_size });
         }
-        self.entries[index / 8] &= !(1 << (index % 8));
+        let new_entries_count = new_size.div_ceil(8);
+        self.entries.truncate(new_entries_count);
+        self.used_bits_count = new_size;
         Ok(())
     }
+}
 
-    /// Flips the bit at the given index
+impl<S: AsRef<[u8]>> Bitmap<S> {
+    /// Creates a bitmap of `used_bits_count` bits, stored in `entries`. The bits keep whatever
+    /// value `entries` already holds.
     ///
-    /// # Errors
-    /// Returns an error if the index is out of bounds.
-    pub fn flip(&mut self, index: usize) -> Result<(), BitmapError> {
-        if index >= self.used_bits_count {
-            return Err(BitmapError::IndexOutOfBounds {
-                index,
-                size: self.used_bits_count,
-            });
+    /// # Panics
+    /// Panics if `entries` is too small to hold `used_bits_count` bits
+    #[must_use]
+    pub fn from_storage(entries: S, used_bits_count: usize) -> Self {
+        assert!(
+            entries.as_ref().len() >= used_bits_count.div_ceil(8),
+            "Bitmap storage is too small"
+        );
+
+        Self {
+            entries,
+            used_bits_count,
         }
-        self.entries[index / 8] ^= 1 << (index % 8);
-        Ok(())
     }
 
     /// Checks if the bit at index is set
@@ -91,7 +100,30 @@ impl Bitmap {
                 size: self.used_bits_count,
             });
         }
-        Ok((self.entries[index / 8] & (1 << (index % 8))) != 0)
+        Ok((self.entries.as_ref()[index / 8] & (1 << (index % 8))) != 0)
+    }
+
+    /// Returns the index of the first set bit at or after `start`, or `None` if there is none.
+    ///
+    /// Whole bytes of unset bits are skipped at once, so this is a lot faster than checking the
+    /// bits one by one on sparse bitmaps.
+    #[must_use]
+    pub fn first_set_from(&self, start: usize) -> Option<usize> {
+        if start >= self.used_bits_count {
+            return None;
+        }
+
+        let entries = self.entries.as_ref();
+        // Mask out the bits before `start` in its own byte
+        let first = entries[start / 8] & (u8::MAX << (start % 8));
+        let index = core::iter::once(first)
+            .chain(entries[start / 8 + 1..].iter().copied())
+            .enumerate()
+            .find(|&(_, entry)| entry != 0)
+            .map(|(i, entry)| (start / 8 + i) * 8 + entry.trailing_zeros() as usize)?;
+
+        // Bits past the used ones are never set, but the storage might have had garbage in them
+        (index < self.used_bits_count).then_some(index)
     }
 
     /// Returns the number of used bits
@@ -100,58 +132,78 @@ impl Bitmap {
         self.used_bits_count
     }
 
-    /// Grows the bitmap to accommodate `new_size` bits
+    /// Creates an iterator over the bitmap bits
+    #[must_use]
+    pub fn iter(&self) -> BitmapIterator<'_, S> {
+        BitmapIterator {
+            bitmap: self,
+            current_index: 0,
+        }
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Bitmap<S> {
+    /// Sets the bit at the given index
     ///
     /// # Errors
-    /// Returns an error if `new_size` is less than the current used bits count.
-    pub fn grow(&mut self, new_size: usize) -> Result<(), BitmapError> {
-        if new_size < self.used_bits_count {
-            return Err(BitmapError::InvalidSize { size: new_size });
+    /// Returns an error if the index is out of bounds.
+    pub fn set(&mut self, index: usize) -> Result<(), BitmapError> {
+        if index >= self.used_bits_count {
+            return Err(BitmapError::IndexOutOfBounds {
+                index,
+                size: self.used_bits_count,
+            });
         }
-        let new_entries_count = new_size.div_ceil(8);
-        if new_entries_count > self.entries.len() {
-            self.entries.resize(new_entries_count, 0);
+        self.entries.as_mut()[index / 8] |= 1 << (index % 8);
+        Ok(())
+    }
+
+    /// Unsets the bit at the given index
+    ///
+    /// # Errors
+    /// Returns an error if the index is out of bounds.
+    pub fn unset(&mut self, index: usize) -> Result<(), BitmapError> {
+        if index >= self.used_bits_count {
+            return Err(BitmapError::IndexOutOfBounds {
+                index,
+                size: self.used_bits_count,
+            });
         }
-        self.used_bits_count = new_size;
+        self.entries.as_mut()[index / 8] &= !(1 << (index % 8));
         Ok(())
     }
 
-    /// Shrinks the bitmap to `new_size` bits
+    /// Flips the bit at the given index
     ///
     /// # Errors
-    /// Returns an error if `new_size` is greater than the current used bits count.
-    pub fn shrink(&mut self, new_size: usize) -> Result<(), BitmapError> {
-        if new_size > self.used_bits_count {
-            return Err(BitmapError::InvalidSize { size: new_size });
+    /// Returns an error if the index is out of bounds.
+    pub fn flip(&mut self, index: usize) -> Result<(), BitmapError> {
+        if index >= self.used_bits_count {
+            return Err(BitmapError::IndexOutOfBounds {
+                index,
+                size: self.used_bits_count,
+            });
         }
-        let new_entries_count = new_size.div_ceil(8);
-        self.entries.truncate(new_entries_count);
-        self.used_bits_count = new_size;
+        self.entries.as_mut()[index / 8] ^= 1 << (index % 8);
         Ok(())
     }
 
     /// Clears all bits in the bitmap
     pub fn clear(&mut self) {
-        self.entries.iter_mut().for_each(|entry| *entry = 0);
-    }
-
-    /// Creates an iterator over the bitmap bits
-    #[must_use]
-    pub fn iter(&self) -> BitmapIterator<'_> {
-        BitmapIterator {
-            bitmap: self,
-            current_index: 0,
-        }
+        self.entries
+            .as_mut()
+            .iter_mut()
+            .for_each(|entry| *entry = 0);
     }
 }
 
 /// Iterator over bitmap bits
-pub struct BitmapIterator<'a> {
-    bitmap: &'a Bitmap,
+pub struct BitmapIterator<'a, S = Vec<u8>> {
+    bitmap: &'a Bitmap<S>,
     current_index: usize,
 }
 
-impl Iterator for BitmapIterator<'_> {
+impl<S: AsRef<[u8]>> Iterator for BitmapIterator<'_, S> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -164,9 +216,18 @@ impl Iterator for BitmapIterator<'_> {
     }
 }
 
-impl IntoIterator for Bitmap {
+impl<'a, S: AsRef<[u8]>> IntoIterator for &'a Bitmap<S> {
     type Item = bool;
-    type IntoIter = BitmapIntoIterator;
+    type IntoIter = BitmapIterator<'a, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<S: AsRef<[u8]>> IntoIterator for Bitmap<S> {
+    type Item = bool;
+    type IntoIter = BitmapIntoIterator<S>;
 
     fn into_iter(self) -> Self::IntoIter {
         BitmapIntoIterator {
@@ -177,12 +238,12 @@ impl IntoIterator for Bitmap {
 }
 
 /// `IntoIterator` implementation for owned bitmap
-pub struct BitmapIntoIterator {
-    bitmap: Bitmap,
+pub struct BitmapIntoIterator<S = Vec<u8>> {
+    bitmap: Bitmap<S>,
     current_index: usize,
 }
 
-impl Iterator for BitmapIntoIterator {
+impl<S: AsRef<[u8]>> Iterator for BitmapIntoIterator<S> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -277,4 +338,44 @@ mod tests {
             Err(BitmapError::InvalidSize { size: 9 })
         ));
     }
+
+    #[test]
+    fn test_borrowed_storage() {
+        let mut storage = [0xff_u8, 0, 0];
+        let mut bitmap = Bitmap::from_storage(&mut storage[..], 20);
+
+        // The bits start out as whatever the storage held
+        assert!(bitmap.is_set(7).unwrap());
+        assert!(!bitmap.is_set(8).unwrap());
+        bitmap.clear();
+        bitmap.set(11).unwrap();
+        assert!(matches!(
+            bitmap.set(20),
+            Err(BitmapError::IndexOutOfBounds {
+                index: 20,
+                size: 20
+            })
+        ));
+
+        assert_eq!(storage, [0, 0b1000, 0]);
+    }
+
+    #[test]
+    fn test_first_set_from() {
+        let mut bitmap = Bitmap::new(40);
+        assert_eq!(bitmap.first_set_from(0), None);
+
+        bitmap.set(3).unwrap();
+        bitmap.set(33).unwrap();
+        assert_eq!(bitmap.first_set_from(0), Some(3));
+        assert_eq!(bitmap.first_set_from(3), Some(3));
+        assert_eq!(bitmap.first_set_from(4), Some(33));
+        assert_eq!(bitmap.first_set_from(34), None);
+        assert_eq!(bitmap.first_set_from(40), None);
+
+        // Garbage past the used bits isn't reported
+        let storage = [0, 0b1000_0000];
+        let bitmap = Bitmap::from_storage(&storage[..], 12);
+        assert_eq!(bitmap.first_set_from(0), None);
+    }
 }
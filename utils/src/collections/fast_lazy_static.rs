@@ -1,13 +1,19 @@
 /// A faster, simpler `OnceCell` alternative *when you know what you're doing* - that is when you
 /// can **100%** guarantee that the safety rules apply. If you can't, use the regular `OnceCell` instead.
 use core::cell::SyncUnsafeCell;
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicBool, Ordering};
 
-#[repr(transparent)]
+#[cfg_attr(not(debug_assertions), repr(transparent))]
 pub struct FastLazyStatic<T>
 where
     T: Copy + PartialEq,
 {
     data: SyncUnsafeCell<T>,
+    /// Whether the value was set, to catch reads of the placeholder value in debug builds. Release
+    /// builds don't keep track of it, so reads stay a plain load
+    #[cfg(debug_assertions)]
+    initialized: AtomicBool,
 }
 
 /// This simply creates two functions: a setter and a getter.
@@ -18,10 +24,25 @@ impl<T> FastLazyStatic<T>
 where
     T: Copy + PartialEq,
 {
+    /// Create a `FastLazyStatic` holding the placeholder value `uninit` until it's set.
+    ///
+    /// In debug builds, getting the value before it's set panics.
     #[inline]
     pub const fn new(uninit: T) -> Self {
         Self {
             data: SyncUnsafeCell::new(uninit),
+            #[cfg(debug_assertions)]
+            initialized: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a `FastLazyStatic` that is already set to `data`
+    #[inline]
+    pub const fn new_set(data: T) -> Self {
+        Self {
+            data: SyncUnsafeCell::new(data),
+            #[cfg(debug_assertions)]
+            initialized: AtomicBool::new(true),
         }
     }
 
@@ -35,16 +56,54 @@ where
             // sanity_assert!(*foo == T::UNINIT);
             *var = data;
         }
+
+        #[cfg(debug_assertions)]
+        self.initialized.store(true, Ordering::Release);
     }
 
+    /// Get the value.
+    ///
+    /// # Panics
+    /// In debug builds, panics if the value wasn't set yet
     #[inline]
     pub fn get(&self) -> T {
-        unsafe {
-            // TODO: Might have to remove this since sometimes the UNINT type can be valid
-            // Making sure the value has indeed been set
-            // sanity_assert!(foo != T::UNINIT);
+        #[cfg(debug_assertions)]
+        crate::sanity_assert!(
+            self.initialized.load(Ordering::Acquire),
+            "FastLazyStatic read before it was set"
+        );
 
-            *self.data.get()
-        }
+        self.peek()
+    }
+
+    /// Get the value, or the placeholder value if it wasn't set yet. Unlike `get`, never panics.
+    #[inline]
+    pub fn peek(&self) -> T {
+        unsafe { *self.data.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_then_get() {
+        let value = FastLazyStatic::new(0_usize);
+        assert_eq!(value.peek(), 0);
+
+        unsafe { value.set(0xffff_8000_0000_0000) };
+        assert_eq!(value.get(), 0xffff_8000_0000_0000);
+
+        let value = FastLazyStatic::new_set(7_u8);
+        assert_eq!(value.get(), 7);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "read before it was set")]
+    fn test_get_before_set() {
+        let value = FastLazyStatic::new(usize::MAX);
+        let _ = value.get();
     }
 }
@@ -0,0 +1,346 @@
+//! A fixed capacity map that evicts its least recently used entry when it's full
+
+use core::hash::{Hash, Hasher};
+
+/// Marks the end of a list, or a bucket without entries
+const NONE: usize = usize::MAX;
+
+/// The links of a slot in the intrusive lists the map keeps its slots in
+#[derive(Debug, Clone, Copy)]
+struct Links {
+    /// The previous (more recently used) slot in the recency list
+    prev: usize,
+    /// The next (less recently used) slot in the recency list, or the next free slot if this
+    /// slot is free
+    next: usize,
+    /// The next slot in the same hash bucket
+    chain: usize,
+}
+
+/// A 64 bit FNV-1a hasher. It's easy to come up with colliding keys, but the keys are never
+/// attacker controlled
+struct FnvHasher(u64);
+
+impl FnvHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+}
+
+/// A map holding up to `N` entries, which evicts the least recently used one to make room for new
+/// ones. Lookups, insertions and removals are O(1) on average.
+///
+/// The entries are kept in a hash table (with a bucket per entry, chained through the slots) and
+/// in a doubly linked list ordered by recency, both stored inline, so no allocator is needed.
+pub struct LruMap<K, V, const N: usize> {
+    slots: [Option<(K, V)>; N],
+    links: [Links; N],
+    /// The first slot of each hash bucket
+    buckets: [usize; N],
+    /// The most recently used slot
+    head: usize,
+    /// The least recently used slot
+    tail: usize,
+    /// The first free slot, with the rest linked through `Links::next`
+    free: usize,
+    len: usize,
+}
+
+impl<K, V, const N: usize> LruMap<K, V, N>
+where
+    K: Hash + Eq,
+{
+    /// Creates a new, empty `LruMap`
+    ///
+    /// # Panics
+    /// Panics if `N` is 0
+    #[must_use]
+    pub const fn new() -> Self {
+        assert!(N > 0, "An LRU map must have room for at least one entry");
+
+        let mut links = [Links {
+            prev: NONE,
+            next: NONE,
+            chain: NONE,
+        }; N];
+        let mut i = 0;
+        while i + 1 < N {
+            links[i].next = i + 1;
+            i += 1;
+        }
+
+        Self {
+            slots: [const { None }; N],
+            links,
+            buckets: [NONE; N],
+            head: NONE,
+            tail: NONE,
+            free: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the amount of entries in the map
+    #[inline]
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the map has no entries
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the maximum amount of entries the map can hold
+    #[inline]
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Get the value of `key`, marking it as the most recently used entry
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let slot = self.find(key)?;
+        self.touch(slot);
+
+        self.slots[slot].as_ref().map(|(_, value)| value)
+    }
+
+    /// Get the value of `key` mutably, marking it as the most recently used entry
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let slot = self.find(key)?;
+        self.touch(slot);
+
+        self.slots[slot].as_mut().map(|(_, value)| value)
+    }
+
+    /// Get the value of `key`, without affecting its recency
+    #[must_use]
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        let slot = self.find(key)?;
+
+        self.slots[slot].as_ref().map(|(_, value)| value)
+    }
+
+    /// Get the least recently used entry, which is the next one to be evicted
+    #[must_use]
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        self.slots
+            .get(self.tail)?
+            .as_ref()
+            .map(|(key, value)| (key, value))
+    }
+
+    /// Insert `value` under `key`, as the most recently used entry.
+    ///
+    /// Returns the entry that was pushed out of the map, so the caller can write it back if
+    /// needed: The previous entry of `key` if it was already in the map, or else the least
+    /// recently used entry if the map was full.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(slot) = self.find(&key) {
+            self.touch(slot);
+            return self.slots[slot].replace((key, value));
+        }
+
+        let evicted = if self.free == NONE {
+            self.remove_slot(self.tail)
+        } else {
+            None
+        };
+
+        let slot = self.free;
+        self.free = self.links[slot].next;
+
+        let bucket = Self::bucket_of(&key);
+        self.links[slot].chain = self.buckets[bucket];
+        self.buckets[bucket] = slot;
+        self.slots[slot] = Some((key, value));
+        self.push_front(slot);
+        self.len += 1;
+
+        evicted
+    }
+
+    /// Remove `key` from the map, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let slot = self.find(key)?;
+
+        self.remove_slot(slot).map(|(_, value)| value)
+    }
+
+    /// Get the hash bucket `key` belongs in
+    fn bucket_of(key: &K) -> usize {
+        let mut hasher = FnvHasher(FnvHasher::OFFSET_BASIS);
+        key.hash(&mut hasher);
+
+        (hasher.finish() % N as u64) as usize
+    }
+
+    /// Get the slot `key` is in
+    fn find(&self, key: &K) -> Option<usize> {
+        let mut slot = self.buckets[Self::bucket_of(key)];
+        while slot != NONE {
+            if self.slots[slot].as_ref().is_some_and(|(k, _)| k == key) {
+                return Some(slot);
+            }
+            slot = self.links[slot].chain;
+        }
+
+        None
+    }
+
+    /// Mark `slot` as the most recently used
+    fn touch(&mut self, slot: usize) {
+        if self.head != slot {
+            self.unlink(slot);
+            self.push_front(slot);
+        }
+    }
+
+    /// Put `slot` at the front of the recency list
+    fn push_front(&mut self, slot: usize) {
+        self.links[slot].prev = NONE;
+        self.links[slot].next = self.head;
+        if self.head == NONE {
+            self.tail = slot;
+        } else {
+            self.links[self.head].prev = slot;
+        }
+        self.head = slot;
+    }
+
+    /// Take `slot` out of the recency list
+    fn unlink(&mut self, slot: usize) {
+        let Links { prev, next, .. } = self.links[slot];
+
+        if prev == NONE {
+            self.head = next;
+        } else {
+            self.links[prev].next = next;
+        }
+        if next == NONE {
+            self.tail = prev;
+        } else {
+            self.links[next].prev = prev;
+        }
+    }
+
+    /// Take `slot` out of both lists and free it, returning its entry
+    fn remove_slot(&mut self, slot: usize) -> Option<(K, V)> {
+        let entry = self.slots[slot].take()?;
+
+        // Take it out of its bucket's chain
+        let bucket = Self::bucket_of(&entry.0);
+        if self.buckets[bucket] == slot {
+            self.buckets[bucket] = self.links[slot].chain;
+        } else {
+            let mut prev = self.buckets[bucket];
+            while self.links[prev].chain != slot {
+                prev = self.links[prev].chain;
+            }
+            self.links[prev].chain = self.links[slot].chain;
+        }
+
+        self.unlink(slot);
+        self.links[slot].next = self.free;
+        self.free = slot;
+        self.len -= 1;
+
+        Some(entry)
+    }
+}
+
+impl<K, V, const N: usize> Default for LruMap<K, V, N>
+where
+    K: Hash + Eq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eviction_order() {
+        let mut map: LruMap<u32, &str, 3> = LruMap::new();
+
+        assert_eq!(map.insert(1, "one"), None);
+        assert_eq!(map.insert(2, "two"), None);
+        assert_eq!(map.insert(3, "three"), None);
+        assert_eq!(map.len(), 3);
+
+        // Recency is now (most recent first) 1, 3, 2
+        assert_eq!(map.get(&3), Some(&"three"));
+        assert_eq!(map.get(&1), Some(&"one"));
+        assert_eq!(map.peek_lru(), Some((&2, &"two")));
+
+        assert_eq!(map.insert(4, "four"), Some((2, "two")));
+        assert_eq!(map.insert(5, "five"), Some((3, "three")));
+        assert_eq!(map.peek(&2), None);
+        assert_eq!(map.len(), 3);
+
+        // Replacing an entry hands back the old one, and makes it the most recent
+        assert_eq!(map.insert(1, "uno"), Some((1, "one")));
+        assert_eq!(map.insert(6, "six"), Some((4, "four")));
+        assert_eq!(map.insert(7, "seven"), Some((5, "five")));
+        assert_eq!(map.insert(8, "eight"), Some((1, "uno")));
+    }
+
+    #[test]
+    fn test_get_updates_recency() {
+        let mut map: LruMap<u32, u32, 2> = LruMap::new();
+        map.insert(1, 10);
+        map.insert(2, 20);
+
+        // Peeking doesn't count as a use, so 1 is still the least recently used
+        assert_eq!(map.peek(&1), Some(&10));
+        assert_eq!(map.peek_lru(), Some((&1, &10)));
+
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(map.insert(3, 30), Some((2, 20)));
+        assert_eq!(map.get(&1), Some(&11));
+    }
+
+    #[test]
+    fn test_remove_and_collisions() {
+        // Way more keys than buckets, so the chains are exercised as well
+        let mut map: LruMap<u32, u32, 4> = LruMap::new();
+        for key in 0..64 {
+            map.insert(key, key * 2);
+            if key % 3 == 0 {
+                assert_eq!(map.remove(&key), Some(key * 2));
+                assert_eq!(map.remove(&key), None);
+            }
+        }
+
+        // 63 pushed 58 out before it was removed, leaving a free slot
+        assert_eq!(map.len(), 3);
+        for key in [59, 61, 62] {
+            assert_eq!(map.peek(&key), Some(&(key * 2)));
+        }
+        assert!(map.peek(&58).is_none());
+
+        while let Some((&key, _)) = map.peek_lru() {
+            map.remove(&key);
+        }
+        assert!(map.is_empty());
+    }
+}
@@ -1,5 +1,9 @@
+pub mod arrayvec;
 pub mod bitmap;
 pub mod fast_lazy_static;
 pub mod id;
 pub mod linkedlist;
+pub mod lru_map;
+pub mod priority_queue;
+pub mod ring_buffer;
 pub mod stacklist;
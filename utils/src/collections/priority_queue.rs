@@ -0,0 +1,279 @@
+//! A binary min-heap, for picking the smallest of a changing set of elements (e.g. the nearest
+//! timer deadline, or the highest priority task)
+
+use alloc::vec::Vec;
+
+/// A priority queue handing out its smallest element first.
+///
+/// Elements can also be removed or updated while they're queued (e.g. when a timeout is cancelled
+/// or rearmed), though finding them is linear.
+#[derive(Debug, Clone)]
+pub struct PriorityQueue<T: Ord> {
+    /// The elements, laid out as an implicit binary tree: the children of `i` are `2i + 1` and
+    /// `2i + 2`, and each element is no bigger than its children
+    heap: Vec<T>,
+}
+
+impl<T: Ord> PriorityQueue<T> {
+    /// Creates a new, empty queue
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { heap: Vec::new() }
+    }
+
+    /// Creates a new, empty queue with room for at least `capacity` elements
+    #[inline]
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            heap: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the amount of queued elements
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Returns true if there are no queued elements
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Queues `value`
+    pub fn push(&mut self, value: T) {
+        self.heap.push(value);
+        self.sift_up(self.heap.len() - 1);
+    }
+
+    /// Returns the smallest element without removing it, or `None` if the queue is empty
+    #[inline]
+    #[must_use]
+    pub fn peek(&self) -> Option<&T> {
+        self.heap.first()
+    }
+
+    /// Removes the smallest element and returns it, or `None` if the queue is empty
+    pub fn pop_min(&mut self) -> Option<T> {
+        self.remove_at(0)
+    }
+
+    /// Removes the first element `pred` matches (in no particular order) and returns it, or `None`
+    /// if no element matches
+    pub fn remove(&mut self, pred: impl FnMut(&T) -> bool) -> Option<T> {
+        let index = self.heap.iter().position(pred)?;
+
+        self.remove_at(index)
+    }
+
+    /// Changes the key of the first element `pred` matches (in no particular order) using
+    /// `update`, and moves it to its new place in the queue.
+    ///
+    /// Returns false if no element matches.
+    pub fn update_key(
+        &mut self,
+        pred: impl FnMut(&T) -> bool,
+        update: impl FnOnce(&mut T),
+    ) -> bool {
+        let Some(index) = self.heap.iter().position(pred) else {
+            return false;
+        };
+
+        update(&mut self.heap[index]);
+        // The key might've gone either way, and only one of these will move it
+        let index = self.sift_up(index);
+        self.sift_down(index);
+
+        true
+    }
+
+    /// Removes all the elements
+    #[inline]
+    pub fn clear(&mut self) {
+        self.heap.clear();
+    }
+
+    /// Returns an iterator over the elements, in no particular order
+    #[inline]
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.heap.iter()
+    }
+
+    /// Removes the element at `index` in the heap and returns it
+    fn remove_at(&mut self, index: usize) -> Option<T> {
+        if index >= self.heap.len() {
+            return None;
+        }
+
+        let value = self.heap.swap_remove(index);
+        // The last element took the removed one's place, so it might be out of order
+        if index < self.heap.len() {
+            let index = self.sift_up(index);
+            self.sift_down(index);
+        }
+
+        Some(value)
+    }
+
+    /// Moves the element at `index` up until its parent isn't bigger than it, and returns its new
+    /// index
+    fn sift_up(&mut self, mut index: usize) -> usize {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.heap[parent] <= self.heap[index] {
+                break;
+            }
+
+            self.heap.swap(parent, index);
+            index = parent;
+        }
+
+        index
+    }
+
+    /// Moves the element at `index` down until none of its children are smaller than it
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let left = 2 * index + 1;
+            let right = left + 1;
+
+            let mut smallest = index;
+            if left < self.heap.len() && self.heap[left] < self.heap[smallest] {
+                smallest = left;
+            }
+            if right < self.heap.len() && self.heap[right] < self.heap[smallest] {
+                smallest = right;
+            }
+
+            if smallest == index {
+                break;
+            }
+
+            self.heap.swap(smallest, index);
+            index = smallest;
+        }
+    }
+}
+
+impl<T: Ord> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T: Ord> IntoIterator for &'a PriorityQueue<T> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Ord> Extend<T> for PriorityQueue<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T: Ord> FromIterator<T> for PriorityQueue<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut queue = Self::new();
+        queue.extend(iter);
+
+        queue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// A timeout, ordered by its deadline
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct Timeout {
+        deadline: u64,
+        id: usize,
+    }
+
+    /// Check each element in `queue` isn't bigger than its children
+    fn assert_heap_invariant<T: Ord + core::fmt::Debug>(queue: &PriorityQueue<T>) {
+        for (i, value) in queue.heap.iter().enumerate().skip(1) {
+            assert!(queue.heap[(i - 1) / 2] <= *value, "{:?}", queue.heap);
+        }
+    }
+
+    #[test]
+    fn test_interleaved_push_pop() {
+        let mut queue = PriorityQueue::new();
+        let mut popped = Vec::new();
+
+        // A fixed, scrambled sequence of values, popping every third push
+        for (i, value) in (0..200u64).map(|i| (i * 7919) % 211).enumerate() {
+            queue.push(value);
+            assert_heap_invariant(&queue);
+
+            if i % 3 == 2 {
+                let min = *queue.iter().min().unwrap();
+                assert_eq!(queue.peek(), Some(&min));
+                assert_eq!(queue.pop_min(), Some(min));
+                assert_heap_invariant(&queue);
+                popped.push(min);
+            }
+        }
+
+        // Whatever's left comes out sorted
+        let mut rest = Vec::new();
+        while let Some(value) = queue.pop_min() {
+            rest.push(value);
+        }
+        assert!(rest.is_sorted());
+        assert_eq!(popped.len() + rest.len(), 200);
+        assert!(queue.is_empty());
+        assert_eq!(queue.peek(), None);
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let mut queue: PriorityQueue<Timeout> = [50, 10, 40, 20, 30]
+            .into_iter()
+            .enumerate()
+            .map(|(id, deadline)| Timeout { deadline, id })
+            .collect();
+
+        // Cancel the nearest timeout, and one in the middle
+        assert_eq!(
+            queue.remove(|timeout| timeout.id == 1),
+            Some(Timeout {
+                deadline: 10,
+                id: 1
+            })
+        );
+        assert_eq!(
+            queue.remove(|timeout| timeout.id == 4).unwrap().deadline,
+            30
+        );
+        assert_eq!(queue.remove(|timeout| timeout.id == 4), None);
+        assert_heap_invariant(&queue);
+
+        // Rearm one timeout to be the nearest, and push another one back
+        assert!(queue.update_key(|timeout| timeout.id == 2, |timeout| timeout.deadline = 5));
+        assert!(queue.update_key(|timeout| timeout.id == 3, |timeout| timeout.deadline = 60));
+        assert!(!queue.update_key(|timeout| timeout.id == 1, |_| {}));
+        assert_heap_invariant(&queue);
+
+        let mut order = vec![];
+        while let Some(timeout) = queue.pop_min() {
+            order.push((timeout.id, timeout.deadline));
+        }
+        assert_eq!(order, [(2, 5), (0, 50), (3, 60)]);
+    }
+}
@@ -0,0 +1,202 @@
+//! A fixed capacity, lock-free FIFO queue
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::sync::cache_padded::CachePadded;
+
+/// A slot in the ring buffer
+struct Slot<T> {
+    /// The position the slot is ready for, minus the slot's index (so it starts at 0 for every
+    /// slot). The slot is ready to be pushed into at position `pos` when this equals `pos`, and
+    /// ready to be popped from at `pos` when this equals `pos + 1`.
+    sequence: AtomicUsize,
+    /// The value, which is only initialized between a push and a pop
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    const fn new() -> Self {
+        Self {
+            sequence: AtomicUsize::new(0),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+/// A bounded multi-producer, multi-consumer FIFO queue that never blocks.
+///
+/// Pushing and popping never wait on each other, so it's safe to use from interrupt handlers
+/// (and the code they interrupt): a push claims its own slot, and a pop skips nothing but also
+/// never waits for a push that is still in progress.
+pub struct RingBuffer<T, const N: usize> {
+    slots: [Slot<T>; N],
+    /// The position of the next push. Producers and consumers each hammer their own end, so they
+    /// are kept on separate cache lines
+    tail: CachePadded<AtomicUsize>,
+    /// The position of the next pop
+    head: CachePadded<AtomicUsize>,
+}
+
+// SAFETY: Each value is only accessed by the single thread that claimed its slot
+unsafe impl<T: Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    /// Creates a new, empty `RingBuffer`
+    ///
+    /// # Panics
+    /// Panics if `N` is 0
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        assert!(N > 0, "A ring buffer must have room for at least one value");
+
+        Self {
+            slots: [const { Slot::new() }; N],
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            head: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the maximum amount of values the `RingBuffer` can hold
+    #[inline]
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Pushes `value` to the back of the queue.
+    ///
+    /// # Errors
+    /// If the queue is full, `value` is handed back as the error.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let index = pos % N;
+            let slot = &self.slots[index];
+            let ready_for = slot.sequence.load(Ordering::Acquire).wrapping_add(index);
+
+            match ready_for.wrapping_sub(pos).cast_signed() {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: Claiming the position gave us exclusive access to the slot
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence
+                            .store(pos.wrapping_add(1).wrapping_sub(index), Ordering::Release);
+
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot still holds the value pushed a lap ago
+                diff if diff < 0 => return Err(value),
+                // Another push claimed the position first
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Removes the value at the front of the queue and returns it, or `None` if the queue is
+    /// empty (or the push at the front of the queue didn't finish yet)
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let index = pos % N;
+            let slot = &self.slots[index];
+            let ready_for = slot.sequence.load(Ordering::Acquire).wrapping_add(index);
+
+            match ready_for.wrapping_sub(pos.wrapping_add(1)).cast_signed() {
+                0 => match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: Claiming the position gave us exclusive access to the slot, and
+                        // its sequence tells the push into it finished
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        // Ready for the push a lap from now
+                        slot.sequence
+                            .store(pos.wrapping_add(N).wrapping_sub(index), Ordering::Release);
+
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                },
+                // Nothing was pushed to this position yet
+                diff if diff < 0 => return None,
+                // Another pop claimed the position first
+                _ => pos = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for RingBuffer<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+
+    #[test]
+    fn test_fifo_order_and_wrap_around() {
+        let buffer: RingBuffer<usize, 4> = RingBuffer::new();
+        assert_eq!(buffer.pop(), None);
+
+        // Go around the buffer a few times
+        for lap in 0..3 {
+            for i in 0..4 {
+                buffer.push(lap * 10 + i).unwrap();
+            }
+            assert_eq!(buffer.push(99), Err(99));
+
+            for i in 0..4 {
+                assert_eq!(buffer.pop(), Some(lap * 10 + i));
+            }
+            assert_eq!(buffer.pop(), None);
+        }
+
+        // Interleaved
+        buffer.push(1).unwrap();
+        buffer.push(2).unwrap();
+        assert_eq!(buffer.pop(), Some(1));
+        buffer.push(3).unwrap();
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), Some(3));
+    }
+
+    #[test]
+    fn test_drops_leftover_values() {
+        let value = Rc::new(());
+        {
+            let buffer: RingBuffer<Rc<()>, 4> = RingBuffer::new();
+            buffer.push(value.clone()).unwrap();
+            buffer.push(value.clone()).unwrap();
+            drop(buffer.pop());
+            assert_eq!(Rc::strong_count(&value), 2);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! Helpers for reading integers of a given endianness out of byte buffers.
+//!
+//! On-disk and on-wire structures (GPT headers, ACPI tables, USB descriptors, etc) are just byte
+//! blobs with fields at fixed offsets. Reading them through these helpers instead of casting to a
+//! `#[repr(C, packed)]` struct avoids taking references to unaligned fields.
+
+/// Get the `N` bytes at `offset` in `buf`, or `None` if they're out of bounds
+#[inline]
+fn bytes_at<const N: usize>(buf: &[u8], offset: usize) -> Option<[u8; N]> {
+    buf.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Define a pair of panicking little and big endian read functions for the given integer type
+macro_rules! read_fns {
+    ($int:ty, $le_name:ident, $be_name:ident) => {
+        #[doc = concat!("Read a little endian `", stringify!($int), "` at `offset` in `buf`.")]
+        ///
+        /// `offset` doesn't have to be aligned.
+        ///
+        /// # Panics
+        /// Panics if the value doesn't fit in `buf`
+        #[inline]
+        #[must_use]
+        pub fn $le_name(buf: &[u8], offset: usize) -> $int {
+            <$int>::from_le_bytes(bytes_at(buf, offset).expect("Read out of bounds"))
+        }
+
+        #[doc = concat!("Read a big endian `", stringify!($int), "` at `offset` in `buf`.")]
+        ///
+        /// `offset` doesn't have to be aligned.
+        ///
+        /// # Panics
+        /// Panics if the value doesn't fit in `buf`
+        #[inline]
+        #[must_use]
+        pub fn $be_name(buf: &[u8], offset: usize) -> $int {
+            <$int>::from_be_bytes(bytes_at(buf, offset).expect("Read out of bounds"))
+        }
+    };
+}
+
+read_fns!(u16, read_u16_le, read_u16_be);
+read_fns!(u32, read_u32_le, read_u32_be);
+read_fns!(u64, read_u64_le, read_u64_be);
+
+/// A cursor reading consecutive fields out of a byte buffer.
+///
+/// Every read advances the cursor past the value that was read. Reads past the end of the buffer
+/// return `None` and leave the cursor where it was.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    /// The buffer being read
+    buf: &'a [u8],
+    /// The offset of the next read
+    offset: usize,
+}
+
+/// Define a pair of little and big endian `Reader` methods for the given integer type
+macro_rules! reader_fns {
+    ($int:ty, $le_name:ident, $be_name:ident) => {
+        #[doc = concat!("Read a little endian `", stringify!($int), "`")]
+        #[inline]
+        pub fn $le_name(&mut self) -> Option<$int> {
+            self.take().map(<$int>::from_le_bytes)
+        }
+
+        #[doc = concat!("Read a big endian `", stringify!($int), "`")]
+        #[inline]
+        pub fn $be_name(&mut self) -> Option<$int> {
+            self.take().map(<$int>::from_be_bytes)
+        }
+    };
+}
+
+impl<'a> Reader<'a> {
+    /// Create a new reader, starting at the beginning of `buf`
+    #[inline]
+    #[must_use]
+    pub const fn new(buf: &'a [u8]) -> Self {
+        Self { buf, offset: 0 }
+    }
+
+    /// Get the offset of the next read
+    #[inline]
+    #[must_use]
+    pub const fn position(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the amount of bytes left to read
+    #[inline]
+    #[must_use]
+    pub const fn remaining(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    /// Take the next `N` bytes
+    #[inline]
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = bytes_at(self.buf, self.offset)?;
+        self.offset += N;
+
+        Some(bytes)
+    }
+
+    /// Read a single byte
+    #[inline]
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.take().map(u8::from_le_bytes)
+    }
+
+    reader_fns!(u16, read_u16_le, read_u16_be);
+    reader_fns!(u32, read_u32_le, read_u32_be);
+    reader_fns!(u64, read_u64_le, read_u64_be);
+
+    /// Read the next `len` bytes as a slice
+    #[inline]
+    pub fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+
+        Some(bytes)
+    }
+
+    /// Skip the next `len` bytes (e.g. reserved fields)
+    #[inline]
+    pub fn skip(&mut self, len: usize) -> Option<()> {
+        self.read_bytes(len).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUF: [u8; 11] = [
+        0xff, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a,
+    ];
+
+    #[test]
+    fn test_unaligned_reads() {
+        assert_eq!(read_u16_le(&BUF, 1), 0x0201);
+        assert_eq!(read_u16_be(&BUF, 1), 0x0102);
+        assert_eq!(read_u32_le(&BUF, 3), 0x0605_0403);
+        assert_eq!(read_u32_be(&BUF, 3), 0x0304_0506);
+        assert_eq!(read_u64_le(&BUF, 1), 0x0807_0605_0403_0201);
+        assert_eq!(read_u64_be(&BUF, 3), 0x0304_0506_0708_090a);
+    }
+
+    #[test]
+    #[should_panic(expected = "Read out of bounds")]
+    fn test_read_out_of_bounds() {
+        let _ = read_u32_le(&BUF, 8);
+    }
+
+    #[test]
+    fn test_reader() {
+        let mut reader = Reader::new(&BUF);
+
+        assert_eq!(reader.read_u8(), Some(0xff));
+        assert_eq!(reader.read_u16_le(), Some(0x0201));
+        assert_eq!(reader.read_u32_be(), Some(0x0304_0506));
+        assert_eq!(reader.position(), 7);
+        assert_eq!(reader.skip(1), Some(()));
+        assert_eq!(reader.read_bytes(2), Some(&BUF[8..10]));
+        assert_eq!(reader.remaining(), 1);
+
+        // Not enough bytes left, so the cursor shouldn't move
+        assert_eq!(reader.read_u16_le(), None);
+        assert_eq!(reader.read_bytes(usize::MAX), None);
+        assert_eq!(reader.position(), 10);
+        assert_eq!(reader.read_u8(), Some(0x0a));
+        assert_eq!(reader.read_u8(), None);
+    }
+}
@@ -5,9 +5,14 @@
 // TODO: Remove this once you fix the `as` conversion warnings
 #![allow(clippy::cast_possible_truncation)]
 
+pub mod boot_info;
+pub mod checksum;
+pub mod cmdline;
 pub mod collections;
+pub mod endian;
 pub mod mem;
 pub mod sync;
+pub mod time;
 
 extern crate alloc;
 
@@ -44,6 +49,25 @@ macro_rules! spin_until {
     };
 }
 
+/// Guards code that must only run once per boot (e.g. early initialization), catching the bug
+/// of running it twice.
+///
+/// On debug builds, a second run panics. On release builds, it runs `$on_repeat` instead (which
+/// should leave the enclosing function), or just returns if none is passed.
+#[macro_export]
+macro_rules! once_per_boot {
+    () => {
+        $crate::once_per_boot!(return)
+    };
+    ($on_repeat:expr) => {{
+        static CALLED: $crate::sync::once::OncePerBoot = $crate::sync::once::OncePerBoot::new();
+        if !CALLED.enter(concat!(module_path!(), ":", line!())) {
+            #[allow(unreachable_code)]
+            $on_repeat;
+        }
+    }};
+}
+
 /// For assertions that are so obvious, they should never fail in production code.
 /// These assertions are only checked in debug builds.
 #[macro_export]
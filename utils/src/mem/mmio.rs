@@ -1,10 +1,17 @@
 //! Wrappers for safer and easier handling of MMIO
+//!
+//! Volatile accesses only stop the compiler from eliding or merging the accesses themselves. They
+//! don't order them against regular memory accesses, and they don't stop the CPU from reordering
+//! weakly ordered stores (write-combining memory, non-temporal stores). When a device reads memory
+//! we wrote (i.e. DMA), use the barriers in `sync::barrier` to make sure it sees the right data.
 
 use core::{
     marker::PhantomData,
     ptr::{read_volatile, write_volatile},
 };
 
+use crate::sync::barrier::memory_barrier;
+
 /// A trait for types that can be used as MMIO register offsets
 ///
 /// NOTE: SHOULD NOT BE IMPLEMENTED FOR PRIMITIVE TYPES!
@@ -66,6 +73,20 @@ where
         unsafe { write_volatile(self.base.byte_add(reg.offset()), value) }
     }
 
+    /// Same as `write`, but also issues a full memory barrier right after the write, so the write
+    /// is globally visible before any memory access that comes after it.
+    ///
+    /// Useful for doorbell registers. NOTE: This doesn't order the stores that came **before** the
+    /// write, so call `dma_wmb` after writing the descriptors and before ringing the doorbell.
+    ///
+    /// # Safety
+    /// Same as `write`
+    #[inline]
+    pub unsafe fn write_then_fence(&self, reg: W, value: T) {
+        unsafe { self.write(reg, value) };
+        memory_barrier();
+    }
+
     /// Override the base address of the MMIO area
     #[inline]
     pub const unsafe fn change_base(&mut self, ptr: *mut T) {
@@ -104,6 +125,18 @@ where
     pub unsafe fn write(&self, value: T) {
         unsafe { write_volatile(self.base, value) }
     }
+
+    /// Same as `write`, but also issues a full memory barrier right after the write.
+    ///
+    /// See `MmioArea::write_then_fence`
+    ///
+    /// # Safety
+    /// Same as `write`
+    #[inline]
+    pub unsafe fn write_then_fence(&self, value: T) {
+        unsafe { self.write(value) };
+        memory_barrier();
+    }
 }
 
 impl Offsetable for usize {
@@ -111,3 +144,36 @@ impl Offsetable for usize {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::barrier::dma_wmb;
+    use core::ptr::from_mut;
+
+    #[test]
+    fn test_cell_write_then_fence() {
+        let mut reg: u32 = 0;
+        let cell = MmioCell::new(from_mut(&mut reg));
+
+        unsafe {
+            cell.write_then_fence(0xdead_beef);
+            assert_eq!(cell.read(), 0xdead_beef);
+        }
+    }
+
+    #[test]
+    fn test_area_write_then_fence() {
+        let mut regs: [u32; 4] = [0; 4];
+        let area: MmioArea<usize, usize, u32> = MmioArea::new(regs.as_mut_ptr());
+
+        unsafe {
+            area.write(0x0, 0x1);
+            dma_wmb();
+            area.write_then_fence(0xc, 0x2);
+
+            assert_eq!(area.read(0x0), 0x1);
+            assert_eq!(area.read(0xc), 0x2);
+        }
+    }
+}
@@ -8,10 +8,19 @@ use core::{
 };
 
 pub mod mmio;
+pub mod volatile;
 
-/// We set this to 0x0, since in testing we don't want to use HHDM offset
+pub use volatile::Volatile;
+
+/// The offset of the HHDM, set during boot
+#[cfg(target_os = "none")]
 pub static HHDM_OFFSET: FastLazyStatic<usize> = FastLazyStatic::new(0x0);
 
+/// We set this to 0x0, since in testing we don't want to use HHDM offset. Host tests never boot,
+/// so it's set from the start
+#[cfg(not(target_os = "none"))]
+pub static HHDM_OFFSET: FastLazyStatic<usize> = FastLazyStatic::new_set(0x0);
+
 /// A physical address
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Default)]
@@ -29,6 +38,39 @@ impl VirtAddr {
     pub fn subtract_hhdm_offset(self) -> PhysAddr {
         PhysAddr(self.0 - HHDM_OFFSET.get())
     }
+
+    /// Round the address down to a multiple of `align`
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two
+    #[inline]
+    #[must_use]
+    pub const fn align_down(self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        Self(self.0 & !(align - 1))
+    }
+
+    /// Round the address up to a multiple of `align`
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two, or if the rounded up address overflows
+    #[inline]
+    #[must_use]
+    pub const fn align_up(self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        Self(self.0.next_multiple_of(align))
+    }
+
+    /// Returns true if the address is a multiple of `align`
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two
+    #[inline]
+    #[must_use]
+    pub const fn is_aligned(self, align: usize) -> bool {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        self.0 & (align - 1) == 0
+    }
 }
 
 impl PhysAddr {
@@ -36,6 +78,39 @@ impl PhysAddr {
     pub fn add_hhdm_offset(self) -> VirtAddr {
         VirtAddr(self.0 + HHDM_OFFSET.get())
     }
+
+    /// Round the address down to a multiple of `align`
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two
+    #[inline]
+    #[must_use]
+    pub const fn align_down(self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        Self(self.0 & !(align - 1))
+    }
+
+    /// Round the address up to a multiple of `align`
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two, or if the rounded up address overflows
+    #[inline]
+    #[must_use]
+    pub const fn align_up(self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        Self(self.0.next_multiple_of(align))
+    }
+
+    /// Returns true if the address is a multiple of `align`
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two
+    #[inline]
+    #[must_use]
+    pub const fn is_aligned(self, align: usize) -> bool {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        self.0 & (align - 1) == 0
+    }
 }
 
 impl Debug for VirtAddr {
@@ -149,6 +224,39 @@ impl<T> From<NonNull<T>> for PhysAddr {
     }
 }
 
+/// Get a reference to the `T` at `phys`, through the HHDM.
+///
+/// # Safety
+/// `phys` must hold a valid `T` that's mapped by the HHDM, and that isn't written to for as long as
+/// the reference is used
+#[inline]
+#[must_use]
+pub unsafe fn struct_from_phys<T>(phys: PhysAddr) -> &'static T {
+    let ptr: *const T = core::ptr::with_exposed_provenance(phys.add_hhdm_offset().0);
+    crate::sanity_assert!(
+        ptr.is_aligned(),
+        "Physical address isn't aligned for the type"
+    );
+
+    unsafe { &*ptr }
+}
+
+/// Get a reference to the `len` `T`s starting at `phys`, through the HHDM.
+///
+/// # Safety
+/// Same as `struct_from_phys`, for all `len` of the `T`s
+#[inline]
+#[must_use]
+pub unsafe fn slice_from_phys<T>(phys: PhysAddr, len: usize) -> &'static [T] {
+    let ptr: *const T = core::ptr::with_exposed_provenance(phys.add_hhdm_offset().0);
+    crate::sanity_assert!(
+        ptr.is_aligned(),
+        "Physical address isn't aligned for the type"
+    );
+
+    unsafe { core::slice::from_raw_parts(ptr, len) }
+}
+
 /// Wrapper to memset some region of memory to some value
 pub unsafe fn memset(ptr: *mut u8, value: u8, len: usize) {
     unsafe {
@@ -166,3 +274,110 @@ pub unsafe fn memcpy(dst: *mut u8, src: *const u8, len: usize) {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alignment() {
+        // (address, alignment, aligned down, aligned up)
+        let cases = [
+            (0x0, 0x1000, 0x0, 0x0),
+            (0x1000, 0x1000, 0x1000, 0x1000),
+            (0x1001, 0x1000, 0x1000, 0x2000),
+            (0x1fff, 0x1000, 0x1000, 0x2000),
+            (0x20_0000, 0x20_0000, 0x20_0000, 0x20_0000),
+            (0x20_1000, 0x20_0000, 0x20_0000, 0x40_0000),
+            (0x4000_0001, 0x4000_0000, 0x4000_0000, 0x8000_0000),
+            // Alignments that aren't page sizes
+            (0x1234, 1, 0x1234, 0x1234),
+            (0x1234, 8, 0x1230, 0x1238),
+            (0x1238, 8, 0x1238, 0x1238),
+            (0x1234, 64, 0x1200, 0x1240),
+            (
+                0xffff_8000_0000_0123,
+                0x100,
+                0xffff_8000_0000_0100,
+                0xffff_8000_0000_0200,
+            ),
+        ];
+
+        for (addr, align, down, up) in cases {
+            let virt = VirtAddr(addr);
+            assert_eq!(
+                virt.align_down(align),
+                VirtAddr(down),
+                "{addr:#x} to {align:#x}"
+            );
+            assert_eq!(
+                virt.align_up(align),
+                VirtAddr(up),
+                "{addr:#x} to {align:#x}"
+            );
+            assert_eq!(
+                virt.is_aligned(align),
+                addr == down,
+                "{addr:#x} to {align:#x}"
+            );
+
+            let phys = PhysAddr(addr);
+            assert_eq!(
+                phys.align_down(align),
+                PhysAddr(down),
+                "{addr:#x} to {align:#x}"
+            );
+            assert_eq!(
+                phys.align_up(align),
+                PhysAddr(up),
+                "{addr:#x} to {align:#x}"
+            );
+            assert_eq!(
+                phys.is_aligned(align),
+                addr == down,
+                "{addr:#x} to {align:#x}"
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn test_alignment_must_be_a_power_of_two() {
+        let _ = VirtAddr(0x1000).is_aligned(0x3000);
+    }
+
+    #[test]
+    fn test_from_phys() {
+        #[derive(Debug, PartialEq)]
+        struct Header {
+            signature: [u8; 4],
+            length: u32,
+        }
+
+        // The HHDM offset is 0 in tests, so the "physical" address is the address itself
+        let header = Header {
+            signature: *b"APIC",
+            length: 0x2c,
+        };
+        let phys = PhysAddr(core::ptr::from_ref(&header).expose_provenance());
+        let read: &Header = unsafe { struct_from_phys(phys) };
+        assert_eq!(read, &header);
+
+        let entries: [u64; 3] = [0x1000, 0x2000, 0x3000];
+        let phys = PhysAddr(entries.as_ptr().expose_provenance());
+        assert_eq!(unsafe { slice_from_phys::<u64>(phys, 3) }, &entries);
+        assert_eq!(
+            unsafe { slice_from_phys::<u64>(phys + 8, 2) },
+            &entries[1..]
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "isn't aligned")]
+    fn test_from_phys_misaligned() {
+        let entries: [u64; 2] = [0; 2];
+        let phys = PhysAddr(entries.as_ptr().expose_provenance() + 4);
+        let _ = unsafe { struct_from_phys::<u64>(phys) };
+    }
+}
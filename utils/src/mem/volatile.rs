@@ -0,0 +1,105 @@
+//! A cell for memory the hardware reads or writes behind the compiler's back
+
+use core::{
+    fmt,
+    ptr::{from_mut, from_ref},
+};
+
+/// Wraps `T`, making every access to it volatile.
+///
+/// Meant for fields of structures shared with the hardware (e.g. DMA descriptors, or VMCB fields
+/// the CPU writes on `#VMEXIT`), where the compiler must not elide, merge or reorder the accesses.
+/// Since it's `#[repr(transparent)]`, it can be used as a field of a `#[repr(C)]` structure
+/// without changing its layout.
+///
+/// NOTE: Unlike `MmioCell`, this doesn't refer to a register by address. It holds the value itself
+#[derive(Default)]
+#[repr(transparent)]
+pub struct Volatile<T: Copy> {
+    value: T,
+}
+
+impl<T: Copy> Volatile<T> {
+    /// Wraps `value`
+    #[inline]
+    #[must_use]
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    /// Volatile read of the value
+    #[inline]
+    #[must_use]
+    pub fn read(&self) -> T {
+        unsafe { from_ref(&self.value).read_volatile() }
+    }
+
+    /// Volatile write of `value`
+    #[inline]
+    pub fn write(&mut self, value: T) {
+        unsafe { from_mut(&mut self.value).write_volatile(value) };
+    }
+
+    /// Read the value, pass it to `f`, and write back the value `f` returns
+    #[inline]
+    pub fn update(&mut self, f: impl FnOnce(T) -> T) {
+        let value = self.read();
+        self.write(f(value));
+    }
+}
+
+impl<T: Copy> Clone for Volatile<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(self.read())
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for Volatile<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Volatile").field(&self.read()).finish()
+    }
+}
+
+// Wrapping a value mustn't change the layout of the structure it's in
+const _: () = assert!(size_of::<Volatile<u16>>() == size_of::<u16>());
+const _: () = assert!(align_of::<Volatile<u64>>() == align_of::<u64>());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    struct Descriptor {
+        addr: Volatile<u64>,
+        len: Volatile<u32>,
+        status: Volatile<u32>,
+    }
+
+    #[test]
+    fn test_volatile_accesses_backing_memory() {
+        let mut backing = [0_u64; 2];
+        // SAFETY: `Descriptor` has the same size and alignment as the backing memory
+        let descriptor = unsafe { &mut *backing.as_mut_ptr().cast::<Descriptor>() };
+
+        descriptor.addr.write(0xdead_b000);
+        descriptor.len.write(0x200);
+        descriptor.status.update(|status| status | 1);
+        assert_eq!(descriptor.addr.read(), 0xdead_b000);
+        assert_eq!(descriptor.status.read(), 1);
+
+        // The writes landed in the backing memory, at the `#[repr(C)]` offsets
+        assert_eq!(backing[0], 0xdead_b000);
+        assert_eq!(backing[1], 0x200 | (1 << 32));
+
+        // And reads see what the "hardware" wrote behind our back
+        backing[1] = 0x2_0000_0010;
+        let descriptor = unsafe { &*backing.as_ptr().cast::<Descriptor>() };
+        assert_eq!(descriptor.len.read(), 0x10);
+        assert_eq!(descriptor.status.read(), 2);
+        assert_eq!(
+            alloc::format!("{:?}", descriptor.len.clone()),
+            "Volatile(16)"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Compiler and hardware memory barriers.
+//!
+//! Atomics order the accesses the compiler knows about, but devices don't take part in the memory
+//! model: MMIO and memory shared with a device (i.e. DMA) need an explicit barrier wherever their
+//! ordering matters, e.g.:
+//!
+//! - `dma_wmb` after writing descriptors/buffers and before ringing the doorbell, so the device
+//!   never sees the doorbell before the data it refers to.
+//! - `dma_rmb` after reading a completion entry/status and before reading the data it refers to.
+//! - `memory_barrier` when both loads and stores need to be ordered (e.g. writing a doorbell and
+//!   then polling a status the device writes in response).
+//!
+//! All of the hardware barriers are compiler barriers as well.
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+use core::arch::asm;
+use core::sync::atomic::{self, Ordering};
+
+/// Compiler barrier: The compiler doesn't move memory accesses across this. The CPU still might,
+/// so no instruction is emitted
+#[inline]
+pub fn compiler_fence() {
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// Full memory barrier: Every load and store before this is globally visible before any load or
+/// store after it
+#[inline]
+pub fn memory_barrier() {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        asm!("mfence", options(nostack, preserves_flags));
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        asm!("dmb sy", options(nostack, preserves_flags));
+    };
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        asm!("fence iorw, iorw", options(nostack, preserves_flags));
+    };
+}
+
+/// DMA write barrier: Every store to memory shared with a device before this is visible to the
+/// device before any store after it.
+///
+/// On `x86_64` regular stores are already ordered, but weakly ordered ones (write-combining memory,
+/// non-temporal stores) aren't, so this is an `sfence`
+#[inline]
+pub fn dma_wmb() {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        asm!("sfence", options(nostack, preserves_flags));
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        asm!("dmb oshst", options(nostack, preserves_flags));
+    };
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        asm!("fence ow, ow", options(nostack, preserves_flags));
+    };
+}
+
+/// DMA read barrier: Every load from memory shared with a device before this completes before any
+/// load after it starts
+#[inline]
+pub fn dma_rmb() {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        asm!("lfence", options(nostack, preserves_flags));
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        asm!("dmb oshld", options(nostack, preserves_flags));
+    };
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        asm!("fence ir, ir", options(nostack, preserves_flags));
+    };
+}
+
+#[cfg(test)]
+#[cfg(target_arch = "x86_64")]
+mod tests {
+    use super::*;
+
+    const MFENCE: [u8; 3] = [0x0f, 0xae, 0xf0];
+    const SFENCE: [u8; 3] = [0x0f, 0xae, 0xf8];
+    const LFENCE: [u8; 3] = [0x0f, 0xae, 0xe8];
+
+    /// Get the first fence instruction in the code of `f`
+    fn first_fence(f: fn()) -> Option<[u8; 3]> {
+        // None of the barriers take more than a few bytes of code
+        let code = unsafe { core::slice::from_raw_parts(f as *const u8, 32) };
+
+        code.windows(3)
+            .find(|window| [MFENCE, SFENCE, LFENCE].iter().any(|fence| fence == window))
+            .map(|window| window.try_into().unwrap())
+    }
+
+    #[test]
+    fn test_x86_64_fences() {
+        assert_eq!(first_fence(memory_barrier), Some(MFENCE));
+        assert_eq!(first_fence(dma_wmb), Some(SFENCE));
+        assert_eq!(first_fence(dma_rmb), Some(LFENCE));
+
+        // Making sure the instructions are valid as well
+        compiler_fence();
+        memory_barrier();
+        dma_wmb();
+        dma_rmb();
+    }
+}
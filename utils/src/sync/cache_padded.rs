@@ -0,0 +1,95 @@
+//! Padding values to a cache line of their own, to avoid false sharing
+
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+/// The size of a cache line, in bytes
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// Wraps `T`, aligning and padding it to a cache line of its own.
+///
+/// Values that are written to by different CPUs (e.g. per-CPU counters, or the head and tail of
+/// a queue) shouldn't share a cache line, or each write invalidates the line for the other CPUs
+/// even though they don't touch the written value (false sharing).
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C, align(64))]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+// The `align` attribute doesn't take a constant, so make sure the two don't drift apart
+const _: () = assert!(align_of::<CachePadded<u8>>() == CACHE_LINE_SIZE);
+
+impl<T> CachePadded<T> {
+    /// Wraps `value`, padding it to a cache line
+    #[inline]
+    #[must_use]
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    /// Returns the wrapped value
+    #[inline]
+    #[must_use]
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachePadded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CachePadded").field(&self.value).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // Checked at compile time, so a layout regression fails the build
+    const _: () = {
+        assert!(size_of::<CachePadded<u8>>() == CACHE_LINE_SIZE);
+        assert!(align_of::<CachePadded<AtomicUsize>>() == CACHE_LINE_SIZE);
+        // Values bigger than a cache line are padded to a multiple of it
+        assert!(size_of::<CachePadded<[u8; 65]>>() == 2 * CACHE_LINE_SIZE);
+    };
+
+    #[test]
+    fn test_adjacent_values_dont_share_a_line() {
+        let counters = [const { CachePadded::new(AtomicUsize::new(0)) }; 2];
+        counters[1].fetch_add(1, Ordering::Relaxed);
+
+        let first = core::ptr::from_ref(&counters[0]).addr();
+        let second = core::ptr::from_ref(&counters[1]).addr();
+        assert!(first.is_multiple_of(CACHE_LINE_SIZE));
+        assert_eq!(second - first, CACHE_LINE_SIZE);
+        assert_eq!(counters[1].load(Ordering::Relaxed), 1);
+        assert_eq!(counters[0].load(Ordering::Relaxed), 0);
+    }
+}
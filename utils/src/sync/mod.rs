@@ -1,3 +1,7 @@
 //! This module contains the implementation of various synchronization primitives.
 
+pub mod barrier;
+pub mod cache_padded;
+pub mod once;
+pub mod semaphore;
 pub mod spinlock;
@@ -0,0 +1,216 @@
+//! A cell that's written once, and only read from then on
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+/// The value wasn't set yet
+const UNINIT: u8 = 0;
+/// The value is being set
+const SETTING: u8 = 1;
+/// The value is set, and can be read
+const READY: u8 = 2;
+
+/// A cell that can be set only once, and can then be read from anywhere without locking.
+///
+/// Meant for values that are figured out once during boot and never change after that.
+pub struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    /// Create a new cell that wasn't set yet
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNINIT),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Set the value, and get a reference to it.
+    ///
+    /// # Errors
+    /// Fails if the value was already set, in which case `value` is handed back
+    pub fn set(&self, value: T) -> Result<&T, T> {
+        if self
+            .state
+            .compare_exchange(UNINIT, SETTING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(value);
+        }
+
+        // SAFETY: Only the one that moved the state to `SETTING` gets here, and no one reads the
+        // value until the state is `READY`
+        let value = unsafe { (*self.value.get()).write(value) };
+        self.state.store(READY, Ordering::Release);
+
+        Ok(value)
+    }
+
+    /// Get the value, or `None` if it wasn't set yet
+    #[inline]
+    #[must_use]
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) != READY {
+            return None;
+        }
+
+        // SAFETY: The value is set, and it's never written to again
+        Some(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Returns true if the value was set
+    #[inline]
+    #[must_use]
+    pub fn is_set(&self) -> bool {
+        self.state.load(Ordering::Acquire) == READY
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Once").field(&self.get()).finish()
+    }
+}
+
+/// A flag that remembers whether something already ran, for guarding code that must only run
+/// once per boot. Use it through `once_per_boot!`.
+pub struct OncePerBoot(AtomicBool);
+
+impl OncePerBoot {
+    /// Create a flag for something that didn't run yet
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Mark the guarded code as running. Returns true if this is the first time.
+    #[inline]
+    #[must_use]
+    pub fn first_call(&self) -> bool {
+        !self.0.swap(true, Ordering::AcqRel)
+    }
+
+    /// Same as `first_call`, but calling the guarded code again is reported as the bug it is.
+    ///
+    /// # Panics
+    /// On debug builds, panics if this isn't the first call. `name` says what was called twice
+    #[inline]
+    #[must_use]
+    pub fn enter(&self, name: &str) -> bool {
+        let first = self.first_call();
+        assert!(
+            first || !cfg!(debug_assertions),
+            "`{name}` should only run once per boot, but was called again"
+        );
+
+        first
+    }
+}
+
+impl Default for OncePerBoot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+
+    #[test]
+    fn test_set_only_once() {
+        let once = Once::new();
+        assert_eq!(once.get(), None);
+        assert!(!once.is_set());
+
+        assert_eq!(once.set(5), Ok(&5));
+        assert_eq!(once.set(6), Err(6));
+        assert_eq!(once.get(), Some(&5));
+        assert!(once.is_set());
+    }
+
+    #[test]
+    fn test_value_is_dropped() {
+        let value = Rc::new(());
+        let once = Once::new();
+        once.set(Rc::clone(&value)).unwrap();
+        assert_eq!(Rc::strong_count(&value), 2);
+
+        drop(once);
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        // An unset cell has nothing to drop
+        drop(Once::<Rc<()>>::new());
+    }
+
+    #[test]
+    fn test_once_per_boot() {
+        fn init(runs: &mut usize) -> Option<()> {
+            crate::once_per_boot!(return None);
+            *runs += 1;
+
+            Some(())
+        }
+
+        let guard = OncePerBoot::new();
+        assert!(guard.first_call());
+        assert!(!guard.first_call());
+
+        let mut runs = 0;
+        assert_eq!(init(&mut runs), Some(()));
+        assert_eq!(runs, 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "should only run once per boot")]
+    fn test_once_per_boot_called_twice() {
+        fn init() {
+            crate::once_per_boot!();
+        }
+
+        init();
+        init();
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn test_once_per_boot_called_twice() {
+        fn init(runs: &mut usize) {
+            crate::once_per_boot!();
+            *runs += 1;
+        }
+
+        let mut runs = 0;
+        init(&mut runs);
+        init(&mut runs);
+        assert_eq!(runs, 1);
+    }
+}
@@ -0,0 +1,129 @@
+//! A counting semaphore, for handing out a bounded amount of resources
+
+use core::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A counting semaphore: a pool of permits, each standing for one unit of some bounded resource
+/// (e.g. a hardware timer, or an IRQ line). Holding a permit guarantees a unit is free, so callers
+/// can wait for one instead of failing when they are all taken.
+///
+/// NOTE: Waiting spins, so permits should only be held for short periods, or acquired with
+/// `try_acquire` where waiting isn't an option (e.g. in interrupt handlers)
+pub struct Semaphore {
+    permits: AtomicUsize,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `permits` permits available
+    #[inline]
+    #[must_use]
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+        }
+    }
+
+    /// Take a permit if one is available. Returns true if a permit was taken
+    #[inline]
+    #[must_use = "The permit has to be released if it was taken"]
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits != 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+
+        false
+    }
+
+    /// Take a permit, spinning until one is available
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            // Only retry once there is something to take, so waiters don't fight over the cache
+            // line for nothing
+            while self.permits.load(Ordering::Relaxed) == 0 {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// Give back a permit taken with `acquire` or `try_acquire`
+    #[inline]
+    pub fn release(&self) {
+        self.add_permits(1);
+    }
+
+    /// Add `count` new permits, e.g. once the amount of resources is known
+    #[inline]
+    pub fn add_permits(&self, count: usize) {
+        self.permits.fetch_add(count, Ordering::Release);
+    }
+
+    /// Get the amount of permits currently available.
+    ///
+    /// NOTE: Other CPUs might take or release permits right after this is read
+    #[inline]
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::{sync::atomic::AtomicBool, time::Duration};
+    use std::thread;
+
+    #[test]
+    fn test_try_acquire() {
+        let semaphore = Semaphore::new(2);
+        assert!(semaphore.try_acquire());
+        assert!(semaphore.try_acquire());
+        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.available(), 0);
+
+        semaphore.release();
+        assert_eq!(semaphore.available(), 1);
+        assert!(semaphore.try_acquire());
+
+        semaphore.add_permits(3);
+        assert_eq!(semaphore.available(), 3);
+    }
+
+    #[test]
+    fn test_acquire_waits_for_release() {
+        let semaphore = Semaphore::new(2);
+        semaphore.acquire();
+        semaphore.acquire();
+
+        let acquired = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| {
+                semaphore.acquire();
+                acquired.store(true, Ordering::Release);
+            });
+
+            // All the permits are taken, so the waiter is stuck until one is released
+            thread::sleep(Duration::from_millis(50));
+            assert!(!acquired.load(Ordering::Acquire));
+
+            semaphore.release();
+            waiter.join().unwrap();
+        });
+
+        assert!(acquired.load(Ordering::Acquire));
+        assert_eq!(semaphore.available(), 0);
+    }
+}
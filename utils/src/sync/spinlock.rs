@@ -4,6 +4,7 @@ use core::cell::SyncUnsafeCell;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use super::barrier;
 use crate::spin_until;
 
 /// A trait for types that can be used with the spinlock
@@ -63,8 +64,38 @@ where
         }
     }
 
+    /// Try to lock the spinlock without spinning. Returns `None` if it's already locked.
+    ///
+    /// This is what code that can't afford to wait for the lock holder (e.g. an NMI handler, which
+    /// might have interrupted it) should use.
+    #[inline]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        if self.lock.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        Some(SpinLockGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        })
+    }
+
+    /// Get a pointer to the data, without locking the spinlock.
+    ///
+    /// Only meant for things that don't need the lock, like the address of the data. Accessing
+    /// the data through it races with the lock holder.
+    #[inline]
+    #[must_use]
+    pub const fn as_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
     /// Release the spinlock
     unsafe fn unlock(&self) {
+        // The release store orders the memory accesses made under the lock, but MMIO accesses
+        // are volatile ones, which the compiler only keeps in order with each other. Keep them
+        // before the release as well
+        barrier::compiler_fence();
         self.lock.store(false, Ordering::Release);
     }
 }
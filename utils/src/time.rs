@@ -0,0 +1,166 @@
+//! Helpers for converting between `Duration`s and clock ticks of a given frequency
+
+use core::time::Duration;
+
+/// The amount of nanoseconds in a single second
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Converts `time` into the amount of ticks a clock running at `hz` ticks for during it.
+///
+/// The result is rounded down, and saturates to `u64::MAX` if it doesn't fit in a `u64`.
+#[inline]
+#[must_use]
+pub const fn duration_to_ticks(time: Duration, hz: u64) -> u64 {
+    // NOTE: If the multiplication overflows a `u128`, then the result after dividing by
+    // `NANOS_PER_SEC` is way bigger than `u64::MAX` anyways, so we just saturate
+    let Some(scaled) = time.as_nanos().checked_mul(hz as u128) else {
+        return u64::MAX;
+    };
+
+    let ticks = scaled / NANOS_PER_SEC;
+    if ticks > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        ticks as u64
+    }
+}
+
+/// Converts `ticks` of a clock running at `hz` into the `Duration` they take.
+///
+/// The result is rounded down to the nearest nanosecond. If `hz` is 0 the clock never ticks, so
+/// `Duration::MAX` is returned.
+#[inline]
+#[must_use]
+pub const fn ticks_to_duration(ticks: u64, hz: u64) -> Duration {
+    if hz == 0 {
+        return Duration::MAX;
+    }
+
+    // NOTE: `u64::MAX * NANOS_PER_SEC` fits comfortably in a `u128`, and so does the result
+    // after dividing, so there is no overflow to worry about here
+    let nanos = (ticks as u128 * NANOS_PER_SEC) / hz as u128;
+
+    Duration::new(
+        (nanos / NANOS_PER_SEC) as u64,
+        (nanos % NANOS_PER_SEC) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_to_ticks_whole_seconds() {
+        assert_eq!(duration_to_ticks(Duration::from_secs(1), 1_000), 1_000);
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(3), 1_193_182),
+            3_579_546
+        );
+    }
+
+    #[test]
+    fn test_duration_to_ticks_rounds_down() {
+        // 1.193182 ticks per microsecond
+        assert_eq!(duration_to_ticks(Duration::from_micros(1), 1_193_182), 1);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(999), 1_000_000), 0);
+    }
+
+    #[test]
+    fn test_duration_to_ticks_sub_nanosecond_resolution() {
+        // A 10GHz clock ticks 10 times each nanosecond
+        assert_eq!(
+            duration_to_ticks(Duration::from_nanos(1), 10_000_000_000),
+            10
+        );
+        assert_eq!(
+            duration_to_ticks(Duration::from_nanos(7), 14_318_180_000),
+            100
+        );
+    }
+
+    #[test]
+    fn test_duration_to_ticks_zero() {
+        assert_eq!(duration_to_ticks(Duration::ZERO, 1_000_000), 0);
+        assert_eq!(duration_to_ticks(Duration::from_secs(5), 0), 0);
+    }
+
+    #[test]
+    fn test_duration_to_ticks_large_duration() {
+        // Roughly a year worth of seconds
+        let secs = 31_536_000;
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(secs), 3_000_000_000),
+            secs * 3_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_duration_to_ticks_saturates() {
+        assert_eq!(duration_to_ticks(Duration::MAX, u64::MAX), u64::MAX);
+        assert_eq!(duration_to_ticks(Duration::MAX, 2), u64::MAX);
+    }
+
+    #[test]
+    fn test_duration_to_ticks_overflow_boundary() {
+        // The biggest duration (in whole seconds) that still fits when counting at 1Hz
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(u64::MAX), 1),
+            u64::MAX
+        );
+        // One more tick per second doesn't fit anymore
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(u64::MAX / 2 + 1), 2),
+            u64::MAX
+        );
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(u64::MAX / 2), 2),
+            u64::MAX - 1
+        );
+    }
+
+    #[test]
+    fn test_ticks_to_duration() {
+        assert_eq!(ticks_to_duration(1_000, 1_000), Duration::from_secs(1));
+        assert_eq!(ticks_to_duration(1, 1_000_000), Duration::from_micros(1));
+        assert_eq!(ticks_to_duration(0, 1_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_ticks_to_duration_sub_nanosecond_resolution() {
+        // Less than a nanosecond worth of ticks rounds down to 0
+        assert_eq!(ticks_to_duration(9, 10_000_000_000), Duration::ZERO);
+        assert_eq!(
+            ticks_to_duration(25, 10_000_000_000),
+            Duration::from_nanos(2)
+        );
+    }
+
+    #[test]
+    fn test_ticks_to_duration_large() {
+        assert_eq!(
+            ticks_to_duration(u64::MAX, 1),
+            Duration::from_secs(u64::MAX)
+        );
+        assert_eq!(
+            ticks_to_duration(u64::MAX, u64::MAX),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_ticks_to_duration_zero_frequency() {
+        assert_eq!(ticks_to_duration(1, 0), Duration::MAX);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let hz = 14_318_180;
+        let time = Duration::from_millis(1234);
+        let ticks = duration_to_ticks(time, hz);
+
+        assert!(ticks_to_duration(ticks, hz) <= time);
+        // Rounding down twice can lose at most a single tick
+        assert!(ticks - duration_to_ticks(ticks_to_duration(ticks, hz), hz) <= 1);
+    }
+}